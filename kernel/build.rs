@@ -1,4 +1,7 @@
-use std::env;
+use std::path::Path;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{env, fs};
 
 fn main() {
     // Since we're in a workspace, the path we pass to the linker must be relative to the workspace,
@@ -8,13 +11,126 @@ fn main() {
     //     directory of the build script’s package.
     // (see https://doc.rust-lang.org/cargo/reference/build-scripts.html#inputs-to-the-build-script)
     // We therefore use our current directory to get a fully-qualified path to the linker script.
+    //
+    // A board can have a linker script of its own (see boards/), which `make build` passes in
+    // MICROPUPPY_LINKER_SCRIPT.
+    println!("cargo:rerun-if-env-changed=MICROPUPPY_LINKER_SCRIPT");
+    let linker_script =
+        env::var("MICROPUPPY_LINKER_SCRIPT").unwrap_or_else(|_| "src/linker.ld".to_owned());
     let linker_script = env::current_dir()
         .expect("build script to have a valid current working directory")
-        .join("src/linker.ld");
+        .join(linker_script);
     let linker_script = linker_script
         .to_str()
         .expect("linker script path to be valid");
 
     println!("cargo:rerun-if-changed={linker_script}");
     println!("cargo:rustc-link-arg=-T{linker_script}");
+
+    // the linker script aligns the kernel image to a page, which depends on the granule
+    let page_size = if env::var_os("CARGO_FEATURE_GRANULE_64K").is_some() {
+        0x10000
+    } else if env::var_os("CARGO_FEATURE_GRANULE_16K").is_some() {
+        0x4000
+    } else {
+        0x1000
+    };
+    println!("cargo:rustc-link-arg=--defsym=PAGE_SIZE={page_size:#x}");
+
+    build_info();
+    optional_drivers();
+}
+
+/// Writes `extern crate` items for the enabled optional drivers (see kernel/drivers) to
+/// `$OUT_DIR/drivers.rs`, so they're linked into the kernel, along with their initcalls.
+fn optional_drivers() {
+    let mut drivers = env::vars()
+        .filter_map(|(name, _)| {
+            let driver = name.strip_prefix("CARGO_FEATURE_DRIVER_")?;
+            Some(format!("extern crate {};\n", driver.to_lowercase()))
+        })
+        .collect::<Vec<_>>();
+    drivers.sort();
+
+    let out_dir = env::var("OUT_DIR").expect("cargo to set OUT_DIR");
+    fs::write(Path::new(&out_dir).join("drivers.rs"), drivers.concat())
+        .expect("drivers.rs to be writable");
+}
+
+/// Passes build metadata to the kernel (see src/build_info.rs) in `MICROPUPPY_BUILD_*` variables.
+///
+/// This only reruns when the git HEAD or index changes, so the commit may not be marked as dirty
+/// after editing files that haven't been staged.
+fn build_info() {
+    let commit = git(&["describe", "--always", "--dirty", "--abbrev=12"]);
+    let commit = commit.as_deref().unwrap_or("unknown");
+    if let Some(git_dir) = git(&["rev-parse", "--absolute-git-dir"]) {
+        println!("cargo:rerun-if-changed={git_dir}/HEAD");
+        println!("cargo:rerun-if-changed={git_dir}/index");
+    }
+
+    // honour SOURCE_DATE_EPOCH, for reproducible builds
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    let timestamp = match env::var("SOURCE_DATE_EPOCH") {
+        Ok(epoch) => epoch.parse().expect("SOURCE_DATE_EPOCH to be a number"),
+        Err(_) => SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("system clock to be after 1970")
+            .as_secs(),
+    };
+
+    let profile = env::var("PROFILE").expect("cargo to set PROFILE");
+
+    let mut features = env::vars()
+        .filter_map(|(name, _)| {
+            let feature = name.strip_prefix("CARGO_FEATURE_")?;
+            Some(feature.to_lowercase().replace('_', "-"))
+        })
+        .collect::<Vec<_>>();
+    features.sort();
+
+    println!("cargo:rustc-env=MICROPUPPY_BUILD_COMMIT={commit}");
+    println!(
+        "cargo:rustc-env=MICROPUPPY_BUILD_TIMESTAMP={}",
+        format_timestamp(timestamp)
+    );
+    println!("cargo:rustc-env=MICROPUPPY_BUILD_PROFILE={profile}");
+    println!(
+        "cargo:rustc-env=MICROPUPPY_BUILD_FEATURES={}",
+        features.join(",")
+    );
+}
+
+/// Runs git with the given arguments, returning its output if it succeeds.
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    Some(String::from_utf8(output.stdout).ok()?.trim().to_owned())
+}
+
+/// Formats seconds since the Unix epoch as an RFC 3339 timestamp in UTC.
+fn format_timestamp(timestamp: u64) -> String {
+    let (days, seconds) = (timestamp / 86400, timestamp % 86400);
+
+    // days to a proleptic Gregorian date, from Howard Hinnant’s `civil_from_days`
+    // (see https://howardhinnant.github.io/date_algorithms.html#civil_from_days)
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z % 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
 }
@@ -0,0 +1,438 @@
+//! Definitions shared by the kernel and the programs it runs, describing the system call ABI.
+//!
+//! A system call returns its result in `x0`. Values in `-4095..=-1` (as `i64`) are negated
+//! [`Errno`] codes, and all other values are successful results.
+#![cfg_attr(not(test), no_std)]
+
+/// Largest error code, chosen so that any error return value is distinguishable from a pointer or
+/// length returned on success.
+pub const MAX_ERRNO: u16 = 4095;
+
+/// Reason a system call failed.
+///
+/// The numeric codes are stable and match their Linux counterparts, so they must never change.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Errno {
+    /// `EPERM` (1): the caller isn't allowed to do that.
+    NotPermitted,
+    /// `ENOENT` (2): the named object does not exist.
+    NotFound,
+    /// `EINTR` (4): the operation was interrupted, such as by Ctrl+C on the console.
+    Interrupted,
+    /// `EBADF` (9): the handle is not open, or not open for the operation.
+    BadHandle,
+    /// `EAGAIN` (11): the operation would block, and the caller asked not to block.
+    WouldBlock,
+    /// `ENOMEM` (12): there's not enough memory to complete the operation.
+    NoMemory,
+    /// `EFAULT` (14): an address argument points outside the caller's address space.
+    BadAddress,
+    /// `EBUSY` (16): the resource is in use.
+    Busy,
+    /// `EEXIST` (17): the object already exists.
+    AlreadyExists,
+    /// `ENODEV` (19): the device does not exist or could not be probed.
+    NoDevice,
+    /// `EINVAL` (22): an argument is invalid.
+    InvalidArgument,
+    /// `EMFILE` (24): the task has too many open handles.
+    TooManyHandles,
+    /// `EPIPE` (32): the pipe being written to has no readers left.
+    BrokenPipe,
+    /// `ENOSYS` (38): there's no system call with the given number.
+    NoSys,
+    /// An error code in `1..=MAX_ERRNO` unknown to this version of the ABI.
+    Unknown(u16),
+}
+
+impl Errno {
+    /// Returns the error's numeric code.
+    pub const fn code(self) -> u16 {
+        match self {
+            Self::NotPermitted => 1,
+            Self::NotFound => 2,
+            Self::Interrupted => 4,
+            Self::BadHandle => 9,
+            Self::WouldBlock => 11,
+            Self::NoMemory => 12,
+            Self::BadAddress => 14,
+            Self::Busy => 16,
+            Self::AlreadyExists => 17,
+            Self::NoDevice => 19,
+            Self::InvalidArgument => 22,
+            Self::TooManyHandles => 24,
+            Self::BrokenPipe => 32,
+            Self::NoSys => 38,
+            Self::Unknown(code) => code,
+        }
+    }
+
+    /// Returns the error with the given numeric code, which must be in `1..=MAX_ERRNO`.
+    pub const fn from_code(code: u16) -> Self {
+        assert!(code >= 1 && code <= MAX_ERRNO);
+
+        match code {
+            1 => Self::NotPermitted,
+            2 => Self::NotFound,
+            4 => Self::Interrupted,
+            9 => Self::BadHandle,
+            11 => Self::WouldBlock,
+            12 => Self::NoMemory,
+            14 => Self::BadAddress,
+            16 => Self::Busy,
+            17 => Self::AlreadyExists,
+            19 => Self::NoDevice,
+            22 => Self::InvalidArgument,
+            24 => Self::TooManyHandles,
+            32 => Self::BrokenPipe,
+            38 => Self::NoSys,
+            code => Self::Unknown(code),
+        }
+    }
+}
+
+/// System call numbers, which are the `svc` immediates that tasks make system calls with. Like
+/// the [`Errno`] codes, they're stable, so they must never change.
+///
+/// Every system call must be in [`syscall::ALL`], which is checked when this crate is built: no
+/// number may be used twice, and no more than [`syscall::MAX_GAP`] numbers in a row may be unused,
+/// since a larger gap is more likely a typo than numbers kept free on purpose.
+pub mod syscall {
+    /// Gives up the rest of the caller's time slice.
+    pub const YIELD: u16 = 0;
+    /// Copies the kernel's build info to the caller.
+    pub const BUILD_INFO: u16 = 1;
+    /// Creates a task.
+    pub const SPAWN: u16 = 2;
+    /// Creates a pipe, and opens handles to both ends.
+    pub const PIPE: u16 = 3;
+    /// Reads from a handle.
+    pub const READ: u16 = 4;
+    /// Writes to a handle.
+    pub const WRITE: u16 = 5;
+    /// Closes a handle.
+    pub const CLOSE: u16 = 6;
+    /// Opens a handle to the console.
+    pub const OPEN_CONSOLE: u16 = 7;
+    /// Controls the object behind a handle (see [`ioctl`](super::ioctl)).
+    pub const IOCTL: u16 = 8;
+    /// Posts a signal to a task.
+    pub const KILL: u16 = 9;
+    /// Returns and clears the caller's pending signals.
+    pub const TAKE_SIGNALS: u16 = 10;
+    /// Sets or removes the caller's signal handler.
+    pub const SET_SIGNAL_HANDLER: u16 = 11;
+    /// Returns from a signal handler.
+    pub const SIGNAL_RETURN: u16 = 12;
+    /// Copies the kernel's metrics (see [`Metrics`](super::Metrics)) to the caller.
+    pub const METRICS: u16 = 13;
+    /// Makes the caller the leader of a task group of its own.
+    pub const NEW_GROUP: u16 = 14;
+    /// Posts a signal to every task in a task group.
+    pub const KILL_GROUP: u16 = 15;
+    /// Copies the trace or metrics (see [`snapshot`](super::snapshot)) to memory shared with the
+    /// caller, a chunk at a time. Each call carries on from where the last one left off, given
+    /// the value it returned (or 0 to start), until the copy is complete, when the address of the
+    /// shared memory is written for the caller and the length of the copy is returned.
+    pub const SNAPSHOT: u16 = 16;
+    /// Returns which sinks (see [`log_sink`](super::log_sink)) the kernel log is written to, and
+    /// optionally changes them.
+    pub const LOG_SINKS: u16 = 17;
+    /// Ends the caller, with an exit status for whoever waits for its task group.
+    pub const EXIT: u16 = 18;
+    /// Waits for every other task in a task group to exit, and returns their combined exit
+    /// status.
+    pub const WAIT_GROUP: u16 = 19;
+
+    /// Every system call number.
+    pub const ALL: [u16; 20] = [
+        YIELD,
+        BUILD_INFO,
+        SPAWN,
+        PIPE,
+        READ,
+        WRITE,
+        CLOSE,
+        OPEN_CONSOLE,
+        IOCTL,
+        KILL,
+        TAKE_SIGNALS,
+        SET_SIGNAL_HANDLER,
+        SIGNAL_RETURN,
+        METRICS,
+        NEW_GROUP,
+        KILL_GROUP,
+        SNAPSHOT,
+        LOG_SINKS,
+        EXIT,
+        WAIT_GROUP,
+    ];
+
+    /// Largest number of unused numbers allowed in a row, below the highest system call number.
+    pub const MAX_GAP: u16 = 3;
+
+    /// Highest system call number.
+    pub const HIGHEST: u16 = audit(&ALL);
+
+    /// Returns whether `number` is a system call number.
+    pub const fn is_known(number: u16) -> bool {
+        contains(&ALL, number)
+    }
+
+    /// Checks that no number in `numbers` is used twice, and that no more than [`MAX_GAP`] numbers
+    /// in a row are unused, from zero up to the highest number, which is returned.
+    ///
+    /// Panics if either check fails, which fails the build when called in a constant.
+    pub const fn audit(numbers: &[u16]) -> u16 {
+        let mut highest = 0;
+        let mut i = 0;
+        while i < numbers.len() {
+            let number = numbers[i];
+            let mut j = i + 1;
+            while j < numbers.len() {
+                if numbers[j] == number {
+                    panic!("a system call number is used twice");
+                }
+                j += 1;
+            }
+            if number > highest {
+                highest = number;
+            }
+            i += 1;
+        }
+
+        let mut unused = 0;
+        let mut number = 0;
+        while number <= highest {
+            if contains(numbers, number) {
+                unused = 0;
+            } else {
+                unused += 1;
+                if unused > MAX_GAP {
+                    panic!("too many system call numbers in a row are unused");
+                }
+            }
+            number += 1;
+        }
+
+        highest
+    }
+
+    const fn contains(numbers: &[u16], number: u16) -> bool {
+        let mut i = 0;
+        while i < numbers.len() {
+            if numbers[i] == number {
+                return true;
+            }
+            i += 1;
+        }
+
+        false
+    }
+}
+
+/// Requests for the `ioctl` system call, which controls the object behind a handle in ways that
+/// reading and writing can't.
+pub mod ioctl {
+    /// Puts a console handle's TTY into raw mode if the argument is nonzero, or canonical mode if
+    /// it's zero.
+    pub const TTY_SET_RAW: u64 = 1;
+}
+
+/// What the `snapshot` system call copies for the caller.
+pub mod snapshot {
+    /// The kernel's trace buffer, in the format defined by `trace_format`, as it would appear in a
+    /// dump.
+    pub const TRACE: u64 = 0;
+    /// The kernel's metrics, as a [`Metrics`](super::Metrics).
+    pub const METRICS: u64 = 1;
+}
+
+/// Bits for the sinks the kernel log can be written to, as passed to and returned by the
+/// `log_sinks` system call.
+pub mod log_sink {
+    /// The console UART.
+    pub const UART: u64 = 1 << 0;
+    /// The ring buffer in memory, which the console's `dmesg` command prints.
+    pub const RING: u64 = 1 << 1;
+    /// Passed instead of a set of sinks to leave them as they are.
+    pub const UNCHANGED: u64 = u64::MAX;
+}
+
+/// Signals, which notify a task of something asynchronously. A task can be sent any number from 1
+/// to [`signal::MAX`], but the kernel only sends these.
+pub mod signal {
+    /// Ctrl+C was pressed on the console, while the task was in the foreground.
+    pub const INTERRUPT: u32 = 2;
+    /// Sent by another task, for whatever reason the two agree on.
+    pub const USER: u32 = 10;
+    /// Highest signal number, so that a task's pending signals fit in a `u64`.
+    pub const MAX: u32 = 63;
+}
+
+/// Kernel metrics returned by the `metrics` system call, so programs can check how the kernel
+/// behaved while they ran, like how many context switches a sleep caused.
+///
+/// Every field is a counter that only ever goes up from boot, so programs should compare two
+/// snapshots rather than look at one. New fields are only ever added at the end, and the kernel
+/// writes as much of the struct as the caller's buffer has room for.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Metrics {
+    /// Number of times the scheduler switched from one task to another.
+    pub context_switches: u64,
+    /// Number of IRQs taken, including timer interrupts.
+    pub irqs: u64,
+    /// Number of timer interrupts taken.
+    pub timer_ticks: u64,
+    /// Number of system calls made, including the one that returned these metrics.
+    pub syscalls: u64,
+    /// Number of pages allocated by the kernel's page allocator, for task stacks and the like.
+    pub pages_allocated: u64,
+    /// Number of pages freed back to the kernel's page allocator.
+    pub pages_freed: u64,
+}
+
+impl Metrics {
+    /// Returns the change in every counter since `earlier`.
+    pub fn since(&self, earlier: &Self) -> Self {
+        Self {
+            context_switches: self.context_switches.wrapping_sub(earlier.context_switches),
+            irqs: self.irqs.wrapping_sub(earlier.irqs),
+            timer_ticks: self.timer_ticks.wrapping_sub(earlier.timer_ticks),
+            syscalls: self.syscalls.wrapping_sub(earlier.syscalls),
+            pages_allocated: self.pages_allocated.wrapping_sub(earlier.pages_allocated),
+            pages_freed: self.pages_freed.wrapping_sub(earlier.pages_freed),
+        }
+    }
+}
+
+/// Encodes the result of a system call as the value returned in `x0`.
+pub fn encode(result: Result<u64, Errno>) -> u64 {
+    match result {
+        Ok(value) => value,
+        Err(errno) => (-i64::from(errno.code())) as u64,
+    }
+}
+
+/// Decodes the value returned in `x0` by a system call.
+pub fn decode(x0: u64) -> Result<u64, Errno> {
+    let value = x0 as i64;
+
+    if (-i64::from(MAX_ERRNO)..=-1).contains(&value) {
+        Err(Errno::from_code(-value as u16))
+    } else {
+        Ok(x0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KNOWN: [Errno; 14] = [
+        Errno::NotPermitted,
+        Errno::NotFound,
+        Errno::Interrupted,
+        Errno::BadHandle,
+        Errno::WouldBlock,
+        Errno::NoMemory,
+        Errno::BadAddress,
+        Errno::Busy,
+        Errno::AlreadyExists,
+        Errno::NoDevice,
+        Errno::InvalidArgument,
+        Errno::TooManyHandles,
+        Errno::BrokenPipe,
+        Errno::NoSys,
+    ];
+
+    #[test]
+    fn syscall_audit() {
+        assert_eq!(syscall::audit(&[2, 0, 1]), 2);
+        assert_eq!(syscall::audit(&[0, 4, 8]), 8);
+        assert_eq!(syscall::HIGHEST, 19);
+        assert!(syscall::is_known(syscall::WAIT_GROUP));
+        assert!(!syscall::is_known(syscall::HIGHEST + 1));
+    }
+
+    #[test]
+    #[should_panic = "used twice"]
+    fn syscall_audit_duplicate() {
+        syscall::audit(&[0, 1, 2, 1]);
+    }
+
+    #[test]
+    #[should_panic = "unused"]
+    fn syscall_audit_gap() {
+        syscall::audit(&[0, 1, 6]);
+    }
+
+    #[test]
+    fn code_round_trip() {
+        for errno in KNOWN {
+            assert_eq!(Errno::from_code(errno.code()), errno);
+        }
+
+        for code in 1..=MAX_ERRNO {
+            assert_eq!(Errno::from_code(code).code(), code);
+        }
+    }
+
+    #[test]
+    fn known_codes_are_not_unknown() {
+        for errno in KNOWN {
+            assert!(!matches!(errno, Errno::Unknown(_)));
+        }
+        assert_eq!(Errno::from_code(22), Errno::InvalidArgument);
+        assert_eq!(Errno::from_code(3), Errno::Unknown(3));
+    }
+
+    #[test]
+    fn encode_decode_round_trip() {
+        for code in 1..=MAX_ERRNO {
+            let errno = Errno::from_code(code);
+            assert_eq!(decode(encode(Err(errno))), Err(errno));
+        }
+
+        for value in [0, 1, 0x4000_0000, u64::MAX - u64::from(MAX_ERRNO)] {
+            assert_eq!(decode(encode(Ok(value))), Ok(value));
+        }
+    }
+
+    #[test]
+    fn metrics_layout() {
+        // the layout is part of the ABI, so fields can only be added at the end
+        assert_eq!(core::mem::size_of::<Metrics>(), 6 * 8);
+        assert_eq!(core::mem::align_of::<Metrics>(), 8);
+
+        let earlier = Metrics {
+            context_switches: 10,
+            syscalls: 3,
+            ..Metrics::default()
+        };
+        let later = Metrics {
+            context_switches: 12,
+            syscalls: 5,
+            timer_ticks: 1,
+            ..earlier
+        };
+        assert_eq!(
+            later.since(&earlier),
+            Metrics {
+                context_switches: 2,
+                syscalls: 2,
+                timer_ticks: 1,
+                ..Metrics::default()
+            }
+        );
+    }
+
+    #[test]
+    fn encode_values() {
+        assert_eq!(encode(Err(Errno::InvalidArgument)), -22i64 as u64);
+        assert_eq!(encode(Err(Errno::NoSys)), -38i64 as u64);
+        assert_eq!(decode(u64::MAX), Err(Errno::NotPermitted));
+        assert_eq!(decode(-3i64 as u64), Err(Errno::Unknown(3)));
+    }
+}
@@ -1,10 +1,18 @@
+//! A page allocator for one or more regions of memory, with a [`Tree`] to track the pages in each.
+//!
+//! Addresses are only ever derived from the pointers the allocator was given, never from integers,
+//! so the tests can run under Miri with `-Zmiri-strict-provenance` (see `cargo xtask miri`).
 #![cfg_attr(not(test), no_std)]
+#![feature(strict_provenance)]
+#![deny(fuzzy_provenance_casts, lossy_provenance_casts)]
 
-use core::{fmt, slice};
+use core::ops::Range;
+use core::{fmt, mem, ptr, slice};
 
-use buddy_alloc::tree::{DoubleFreeError, OutOfMemoryError, Tree};
+use buddy_alloc::tree::{Ascii, FreeError, OutOfMemoryError, Placement, RegionState, Stats, Tree};
 
-pub const PAGE_SIZE: usize = 4096;
+/// Size of a page, which is the translation granule, so every allocation can be mapped on its own.
+pub use translation_tables::PAGE_SIZE;
 
 pub struct Allocator {
     tree: Tree<'static>,
@@ -29,13 +37,19 @@ impl Allocator {
         let align_offset = start.align_offset(PAGE_SIZE);
         let start_aligned = unsafe { start.add(align_offset) } as *const [u8; PAGE_SIZE];
 
-        // Create a tree for that many pages, even though in reality some of it
+        // Make the tree big enough for that many pages, even though in reality some of it
         // will be occupied by the tree itself.
         let tree_block_count = unsafe { end.offset_from(start_aligned) } as usize;
-        // Convert from bits to bytes, rounding up
-        let tree_len = (Tree::storage_bits_required(tree_block_count) + 7) / 8;
+        let tree_len = Tree::storage_bytes_required(tree_block_count);
 
-        let storage = unsafe { slice::from_raw_parts_mut(start as *mut _, tree_len) };
+        // The tree is made of words, so it needs to start on a word boundary, and the memory may
+        // be uninitialised, so it needs to be written before there can be a slice of it.
+        let start = unsafe { start.add(start.align_offset(mem::align_of::<usize>())) };
+        let words = tree_len / mem::size_of::<usize>();
+        let storage = unsafe {
+            ptr::write_bytes(start as *mut usize, 0, words);
+            slice::from_raw_parts_mut(start as *mut usize, words)
+        };
 
         let tree_end = unsafe { start.add(tree_len) };
         let padding = tree_end.align_offset(PAGE_SIZE);
@@ -43,25 +57,63 @@ impl Allocator {
         let heap_len_pages = unsafe { end.offset_from(heap) } as usize;
 
         Self {
-            tree: Tree::new(storage, tree_block_count),
+            tree: Tree::new(storage, heap_len_pages),
             heap,
             tree_len,
             heap_len_pages,
         }
     }
 
-    pub fn allocate(&mut self, block_count: usize) -> Result<Allocation, OutOfMemoryError> {
-        let allocation = self.tree.allocate(block_count)?;
+    /// Creates an allocator for the pages from `start` to `end`, keeping its tree in `tree`
+    /// rather than at the start of the pages, so the pages needn't be mapped until they're
+    /// allocated.
+    pub fn with_tree(tree: &'static mut [usize], start: *const u8, end: *const u8) -> Self {
+        assert_eq!(
+            start.align_offset(PAGE_SIZE),
+            0,
+            "start must be page-aligned"
+        );
+        assert_eq!(end.align_offset(PAGE_SIZE), 0, "end must be page-aligned");
+        let heap = start as *const [u8; PAGE_SIZE];
+        let heap_len_pages = unsafe { (end as *const [u8; PAGE_SIZE]).offset_from(heap) } as usize;
+
+        let tree_len = Self::tree_len(heap_len_pages);
+        let tree_words = tree_len / mem::size_of::<usize>();
+        assert!(
+            tree.len() >= tree_words,
+            "tree must be at least {tree_len} bytes long"
+        );
+
+        Self {
+            tree: Tree::new(&mut tree[..tree_words], heap_len_pages),
+            heap,
+            tree_len,
+            heap_len_pages,
+        }
+    }
 
-        if !self.is_within_heap(&allocation) {
-            // Free the allocation, so it can be used by future allocations that are smaller
-            // and/or have enough adjacent space.
-            self.tree
-                .free(allocation.offset)
-                .expect("Guaranteed by allocate");
+    /// Returns the number of bytes of tree needed by [`Allocator::with_tree`] for `pages` pages.
+    pub fn tree_len(pages: usize) -> usize {
+        Tree::storage_bytes_required(pages)
+    }
+
+    /// Sets the strategy for choosing which free pages satisfy an allocation.
+    pub fn with_placement(mut self, placement: Placement) -> Self {
+        self.tree = self.tree.with_placement(placement);
+        self
+    }
 
+    /// Returns the strategy for choosing which free pages satisfy an allocation.
+    pub fn placement(&self) -> Placement {
+        self.tree.placement()
+    }
+
+    pub fn allocate(&mut self, block_count: usize) -> Result<Allocation, OutOfMemoryError> {
+        // The tree can't represent allocations larger than itself, so don't ask it to.
+        if block_count > self.heap_len_pages {
             return Err(OutOfMemoryError);
         }
+        let allocation = self.tree.allocate(block_count)?;
 
         Ok(Allocation {
             ptr: unsafe { self.heap.add(allocation.offset) } as *mut _,
@@ -69,20 +121,62 @@ impl Allocator {
         })
     }
 
-    pub fn free(&mut self, allocation: Allocation) -> Result<(), DoubleFreeError> {
-        let offset = unsafe { allocation.ptr.offset_from(self.heap) };
-
-        if offset < 0 || offset as usize > self.heap_len_pages {
-            return Err(DoubleFreeError);
+    /// Frees an allocation, which fails if it's not from this allocator, or if it doesn't start at
+    /// the start of a page.
+    pub fn free(&mut self, allocation: Allocation) -> Result<(), FreeError> {
+        // the allocation may not be in this allocator's pages at all, which offset_from can't
+        // handle, so compare addresses, which wrap below the heap to an offset past its end
+        let offset = allocation.ptr.addr().wrapping_sub(self.heap.addr());
+        if offset / PAGE_SIZE > self.heap_len_pages {
+            return Err(FreeError::OutOfRange);
+        }
+        if offset % PAGE_SIZE != 0 {
+            return Err(FreeError::InsideAllocation);
         }
 
-        self.tree.free(offset as usize)
+        self.tree.free(offset / PAGE_SIZE)
+    }
+
+    /// Returns every allocation that hasn't been freed, with its size rounded up to what the tree
+    /// actually set aside for it.
+    pub fn allocations(&self) -> impl Iterator<Item = Allocation> + '_ {
+        self.tree.allocations().map(|allocation| Allocation {
+            ptr: unsafe { self.heap.add(allocation.offset) } as *mut _,
+            size: allocation.size * PAGE_SIZE,
+        })
+    }
+
+    /// Returns the free and allocated ranges of this allocator's pages, in order of address.
+    pub fn memory_map(&self) -> impl Iterator<Item = (Range<*const u8>, RegionState)> + '_ {
+        self.tree.regions().map(|region| {
+            let start = unsafe { self.heap.add(region.offset) } as *const u8;
+            let end = unsafe { self.heap.add(region.offset + region.size) } as *const u8;
+
+            (start..end, region.state)
+        })
+    }
+
+    /// Returns how many pages are allocated and free, and how fragmented the free pages are.
+    pub fn stats(&self) -> Stats {
+        self.tree.stats()
+    }
+
+    /// Returns a text rendering of the tree, with offsets in pages from the start of the heap.
+    pub fn ascii(&self) -> Ascii<'_, 'static> {
+        self.tree.ascii()
+    }
+
+    /// Returns the range of this allocator's pages.
+    pub fn heap(&self) -> Range<*const u8> {
+        let start = self.heap as *const u8;
+        let end = unsafe { self.heap.add(self.heap_len_pages) } as *const u8;
+
+        start..end
     }
 
-    /// Return false iff the given allocation overflows the actual end of the heap, which may be
-    /// less than the space representable by the tree.
-    fn is_within_heap(&self, allocation: &buddy_alloc::tree::Allocation) -> bool {
-        allocation.offset + allocation.size <= self.heap_len_pages
+    /// Returns whether `ptr` points into this allocator's pages.
+    pub fn contains(&self, ptr: *const u8) -> bool {
+        self.heap().contains(&ptr)
     }
 }
 
@@ -96,6 +190,90 @@ impl fmt::Debug for Allocator {
     }
 }
 
+/// Error returned when a [`RegionAllocator`] has no room for another region.
+#[derive(Debug, PartialEq, Eq)]
+pub struct TooManyRegions;
+
+/// Allocates pages from up to `N` disjoint regions of memory, such as RAM below and above 4GiB,
+/// with an [`Allocator`] for each region.
+pub struct RegionAllocator<const N: usize> {
+    regions: [Option<Allocator>; N],
+}
+
+impl<const N: usize> RegionAllocator<N> {
+    pub fn new() -> Self {
+        Self {
+            regions: core::array::from_fn(|_| None),
+        }
+    }
+
+    /// Adds a region, which will be allocated from after the regions added before it.
+    pub fn add(&mut self, allocator: Allocator) -> Result<(), TooManyRegions> {
+        let slot = self
+            .regions
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(TooManyRegions)?;
+        *slot = Some(allocator);
+
+        Ok(())
+    }
+
+    /// Returns the allocators for each region, in the order they were added.
+    pub fn regions(&self) -> impl Iterator<Item = &Allocator> {
+        self.regions.iter().flatten()
+    }
+
+    /// Returns every allocation that hasn't been freed, from each region in turn.
+    pub fn allocations(&self) -> impl Iterator<Item = Allocation> + '_ {
+        self.regions().flat_map(Allocator::allocations)
+    }
+
+    /// Allocates from the first region with enough contiguous free space.
+    pub fn allocate(&mut self, block_count: usize) -> Result<Allocation, OutOfMemoryError> {
+        self.regions
+            .iter_mut()
+            .flatten()
+            .find_map(|allocator| allocator.allocate(block_count).ok())
+            .ok_or(OutOfMemoryError)
+    }
+
+    /// Returns the index of the region containing `ptr`, and the offset of the page containing it
+    /// from the start of that region, in pages.
+    pub fn locate(&self, ptr: *const u8) -> Option<(usize, usize)> {
+        self.regions()
+            .enumerate()
+            .find(|(_, allocator)| allocator.contains(ptr))
+            .map(|(index, allocator)| {
+                let offset = ptr.addr() - allocator.heap().start.addr();
+
+                (index, offset / PAGE_SIZE)
+            })
+    }
+
+    /// Frees an allocation from whichever region it came from.
+    pub fn free(&mut self, allocation: Allocation) -> Result<(), FreeError> {
+        self.regions
+            .iter_mut()
+            .flatten()
+            .find(|allocator| allocator.contains(allocation.ptr as *const u8))
+            .ok_or(FreeError::OutOfRange)?
+            .free(allocation)
+    }
+}
+
+impl<const N: usize> Default for RegionAllocator<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> fmt::Debug for RegionAllocator<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.regions()).finish()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use core::alloc::Layout;
@@ -131,6 +309,23 @@ mod tests {
         assert_eq!(a4.size, 0x11000);
         assert_eq!(a5.size, 0x4000);
 
+        // pointers that aren't to the start of a page, or aren't in the heap at all, can't be freed
+        let inside = (a1.ptr as *mut u8).wrapping_add(1).cast();
+        assert_eq!(
+            allocator.free(Allocation {
+                ptr: inside,
+                size: 0
+            }),
+            Err(FreeError::InsideAllocation)
+        );
+        assert_eq!(
+            allocator.free(Allocation {
+                ptr: base.cast(),
+                size: 0
+            }),
+            Err(FreeError::OutOfRange)
+        );
+
         Ok(())
     }
 
@@ -145,16 +340,20 @@ mod tests {
         // but there are only 3 pages of usable heap space (0x2000..0x5000).
         let mut allocator = Allocator::new(start as *const _, end as *const _);
         eprintln!("{}", allocator.tree.dot());
-        assert_eq!(allocator.tree_len, 2);
+        assert_eq!(allocator.tree_len, 8);
         assert_eq!(allocator.heap_len_pages, 3);
+        assert_eq!(
+            allocator.heap(),
+            unsafe { base.add(0x2000) } as *const u8..end as *const u8
+        );
 
         // Allocate 2 blocks (offset 0, size 2).
         let a1 = allocator.allocate(2)?;
         assert_eq!(unsafe { (a1.ptr as *const u8).offset_from(base) }, 0x2000);
         assert_eq!(a1.size, 0x2000);
 
-        // The tree thinks it can allocate another 2 blocks (offset 2, size 2),
-        // but it would overflow our heap, so we still return OutOfMemoryError.
+        // The tree has room for another 2 blocks (offset 2, size 2), but it knows
+        // they would overflow our heap, so we get OutOfMemoryError.
         assert_eq!(allocator.allocate(2), Err(OutOfMemoryError));
 
         // Allocate 1 block (offset 2, size 1).
@@ -162,14 +361,117 @@ mod tests {
         assert_eq!(unsafe { (a2.ptr as *const u8).offset_from(base) }, 0x4000);
         assert_eq!(a2.size, 0x1000);
 
+        // The reserved block past the end of the heap isn't part of the memory map.
+        let page = |offset| unsafe { base.add(offset) } as *const u8;
+        assert_eq!(
+            allocator.memory_map().collect::<Vec<_>>(),
+            [
+                (page(0x2000)..page(0x4000), RegionState::Allocated),
+                (page(0x4000)..page(0x5000), RegionState::Allocated),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn regions() -> Result<(), Error> {
+        let layout = Layout::from_size_align(0x10000, PAGE_SIZE)?;
+        let low = unsafe { std::alloc::alloc(layout) };
+        let high = unsafe { std::alloc::alloc(layout) };
+        let words = Allocator::tree_len(16) / mem::size_of::<usize>();
+        let tree = Box::leak(vec![0; 2 * words].into_boxed_slice());
+        let (low_tree, high_tree) = tree.split_at_mut(words);
+
+        let mut allocator = RegionAllocator::<2>::new();
+        allocator.add(Allocator::with_tree(low_tree, low, unsafe {
+            low.add(0x4000)
+        }))?;
+        allocator.add(Allocator::with_tree(high_tree, high, unsafe {
+            high.add(0x10000)
+        }))?;
+        assert_eq!(
+            allocator.add(Allocator::new(low, unsafe { low.add(0x4000) })),
+            Err(TooManyRegions)
+        );
+
+        // the low region only has 4 pages, so larger allocations come from the high region
+        let a1 = allocator.allocate(2)?;
+        let a2 = allocator.allocate(8)?;
+        let a3 = allocator.allocate(2)?;
+        let a4 = allocator.allocate(1)?;
+        assert_eq!(a1.ptr as *const u8, low);
+        assert_eq!(a2.ptr as *const u8, high);
+        assert_eq!(unsafe { (a3.ptr as *const u8).offset_from(low) }, 0x2000);
+        assert_eq!(unsafe { (a4.ptr as *const u8).offset_from(high) }, 0x8000);
+        assert_eq!(allocator.allocate(16), Err(OutOfMemoryError));
+        assert_eq!(allocator.locate(a3.ptr as *const u8), Some((0, 2)));
+        assert_eq!(allocator.locate(a4.ptr as *const u8), Some((1, 8)));
+        assert_eq!(allocator.locate(unsafe { high.add(0x10000) }), None);
+
+        let ptr = a2.ptr;
+        allocator.free(a2)?;
+        assert_eq!(
+            allocator.free(Allocation { ptr, size: 0 }),
+            Err(FreeError::DoubleFree)
+        );
+        let outside = unsafe { high.add(0x10000) } as *mut _;
+        assert_eq!(
+            allocator.free(Allocation {
+                ptr: outside,
+                size: 0
+            }),
+            Err(FreeError::OutOfRange)
+        );
+        assert_eq!(allocator.regions().count(), 2);
+        assert_eq!(
+            allocator.allocations().collect::<Vec<_>>(),
+            [
+                Allocation {
+                    ptr: a1.ptr,
+                    size: 0x2000
+                },
+                Allocation {
+                    ptr: a3.ptr,
+                    size: 0x2000
+                },
+                Allocation {
+                    ptr: a4.ptr,
+                    size: 0x1000
+                },
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn placement() -> Result<(), Error> {
+        let layout = Layout::from_size_align(0x8000, PAGE_SIZE)?;
+        let base = unsafe { std::alloc::alloc(layout) };
+        let end = unsafe { base.add(0x6000) };
+        let words = Allocator::tree_len(6) / mem::size_of::<usize>();
+        let tree = Box::leak(vec![0; words].into_boxed_slice());
+
+        let mut allocator =
+            Allocator::with_tree(tree, base, end).with_placement(Placement::TopDown);
+        assert_eq!(allocator.placement(), Placement::TopDown);
+
+        // the tree has room for 8 pages, but the last 2 are past the end of the heap
+        let a1 = allocator.allocate(2)?;
+        let a2 = allocator.allocate(1)?;
+        assert_eq!(unsafe { (a1.ptr as *const u8).offset_from(base) }, 0x4000);
+        assert_eq!(unsafe { (a2.ptr as *const u8).offset_from(base) }, 0x3000);
+
         Ok(())
     }
 
     #[derive(Debug)]
     enum Error {
         LayoutError,
+        TooManyRegions,
         OutOfMemoryError,
-        DoubleFreeError,
+        FreeError,
     }
 
     impl From<core::alloc::LayoutError> for Error {
@@ -178,15 +480,21 @@ mod tests {
         }
     }
 
+    impl From<TooManyRegions> for Error {
+        fn from(_: TooManyRegions) -> Self {
+            Self::TooManyRegions
+        }
+    }
+
     impl From<OutOfMemoryError> for Error {
         fn from(_: OutOfMemoryError) -> Self {
             Self::OutOfMemoryError
         }
     }
 
-    impl From<DoubleFreeError> for Error {
-        fn from(_: DoubleFreeError) -> Self {
-            Self::DoubleFreeError
+    impl From<FreeError> for Error {
+        fn from(_: FreeError) -> Self {
+            Self::FreeError
         }
     }
 }
@@ -0,0 +1,479 @@
+//! What the kernel needs to know from the devicetree to boot: where RAM is, which interrupt
+//! controller and timer there are, where the UARTs are, and what's in /chosen.
+//!
+//! This is all found once, by [`BootInfo::new`], so the rest of the kernel doesn't have to
+//! interrogate the devicetree itself. Optional parts of the machine that the devicetree lacks are
+//! `None`, but parts that are there and malformed are errors.
+//!
+//! Devices are looked for at the root, and on buses at the root, like /soc on a Raspberry Pi, whose
+//! `ranges` translate the bus addresses in their `reg` to physical addresses.
+#![cfg_attr(not(test), no_std)]
+
+use core::{fmt, iter};
+
+use fdt::node::FdtNode;
+use fdt::Fdt;
+use memory_map::MemoryMap;
+
+/// Maximum number of UARTs we keep track of, which is plenty for QEMU's virt machine.
+pub const MAX_UARTS: usize = 4;
+
+const GICV2_COMPATIBLE: &[&str] = &["arm,cortex-a15-gic", "arm,gic-400"];
+const GICV3_COMPATIBLE: &[&str] = &["arm,gic-v3"];
+const TIMER_COMPATIBLE: &[&str] = &["arm,armv8-timer"];
+const UART_COMPATIBLE: &str = "arm,pl011";
+const FW_CFG_COMPATIBLE: &str = "qemu,fw-cfg-mmio";
+const PM_COMPATIBLE: &str = "brcm,bcm2835-pm-wdt";
+
+/// An error from a devicetree that describes the machine wrongly.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// RAM is split into more than [`memory_map::MAX_REGIONS`] regions.
+    TooManyMemoryRegions,
+    /// A node lacks a property needed to use it.
+    MissingProperty {
+        compatible: &'static str,
+        property: &'static str,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooManyMemoryRegions => {
+                write!(f, "more than {} regions of RAM", memory_map::MAX_REGIONS)
+            }
+            Self::MissingProperty {
+                compatible,
+                property,
+            } => write!(
+                f,
+                "device compatible with {compatible:?} has no {property:?}"
+            ),
+        }
+    }
+}
+
+impl From<memory_map::TooManyRegions> for Error {
+    fn from(_: memory_map::TooManyRegions) -> Self {
+        Self::TooManyMemoryRegions
+    }
+}
+
+/// Everything the kernel needs from the devicetree to boot.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BootInfo<'a> {
+    /// RAM, less the ranges in the memory reservation block.
+    pub memory: MemoryMap,
+    pub gic: Option<Gic>,
+    pub timer: Option<Timer>,
+    /// Every PL011 UART with a `reg`, in devicetree order.
+    pub uarts: [Option<Uart>; MAX_UARTS],
+    /// The kernel command line, from `bootargs` in /chosen.
+    pub bootargs: Option<&'a str>,
+    /// Base address of the UART named by `stdout-path` in /chosen.
+    pub stdout: Option<usize>,
+    /// Base address of QEMU's firmware configuration device, if there is one.
+    pub fw_cfg: Option<usize>,
+    /// Base address of the power management block of a Raspberry Pi, which has its watchdog.
+    pub pm: Option<usize>,
+}
+
+/// The interrupt controller, and the base addresses of its register frames.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Gic {
+    V2 {
+        distributor: usize,
+        cpu_interface: usize,
+    },
+    V3 {
+        distributor: usize,
+        redistributor: usize,
+    },
+}
+
+/// The interrupts of the architected timer, in the order of its devicetree binding.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Timer {
+    pub secure_phys: Interrupt,
+    pub phys: Interrupt,
+    pub virt: Interrupt,
+    pub hyp_phys: Interrupt,
+}
+
+/// A PL011 UART.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Uart {
+    pub base: usize,
+    /// The UART's interrupt, unless the devicetree doesn't say.
+    pub interrupt: Option<Interrupt>,
+}
+
+/// An interrupt specifier for the GIC, with three cells.
+///
+/// <https://github.com/torvalds/linux/blob/305230142ae0637213bf6e04f6d9f10bbcb74af8/Documentation/devicetree/bindings/interrupt-controller/arm%2Cgic.yaml#L71-L93>
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Interrupt {
+    /// 0 for an SPI, or 1 for a PPI.
+    pub interrupt_type: u32,
+    /// Zero-based SPI or PPI number.
+    pub interrupt_number: u32,
+    /// Trigger type and level flags, and for PPIs, the CPUs they go to.
+    pub flags: u32,
+}
+
+impl Interrupt {
+    /// Parses every specifier in an `interrupts` property.
+    pub fn parse_all(interrupts: &[u8]) -> impl Iterator<Item = Self> + '_ {
+        interrupts.chunks_exact(12).map(|cells| {
+            let cell = |i: usize| u32::from_be_bytes(cells[i * 4..][..4].try_into().unwrap());
+            Self {
+                interrupt_type: cell(0),
+                interrupt_number: cell(1),
+                flags: cell(2),
+            }
+        })
+    }
+}
+
+impl<'a> BootInfo<'a> {
+    pub fn new(fdt: &Fdt<'a>) -> Result<Self, Error> {
+        let mut uarts = devices(fdt)
+            .filter(|device| is_compatible(&device.node, &[UART_COMPATIBLE]))
+            .filter_map(|device| {
+                Some(Uart {
+                    base: device.reg(fdt, 0)?,
+                    interrupt: interrupts(&device.node).and_then(|mut i| i.next()),
+                })
+            });
+
+        Ok(Self {
+            memory: MemoryMap::from_fdt(fdt)?,
+            gic: gic(fdt)?,
+            timer: timer(fdt)?,
+            uarts: core::array::from_fn(|_| uarts.next()),
+            bootargs: fdt
+                .find_node("/chosen")
+                .and_then(|chosen| chosen.property("bootargs"))
+                .and_then(|bootargs| bootargs.as_str()),
+            stdout: stdout(fdt),
+            fw_cfg: find_compatible(fdt, &[FW_CFG_COMPATIBLE])
+                .and_then(|device| device.reg(fdt, 0)),
+            pm: find_compatible(fdt, &[PM_COMPATIBLE]).and_then(|device| device.reg(fdt, 0)),
+        })
+    }
+
+    /// Returns the UARTs, in devicetree order.
+    pub fn uarts(&self) -> impl Iterator<Item = &Uart> {
+        self.uarts.iter().flatten()
+    }
+}
+
+fn gic(fdt: &Fdt) -> Result<Option<Gic>, Error> {
+    if let Some(device) = find_compatible(fdt, GICV2_COMPATIBLE) {
+        let reg = |index| {
+            device
+                .reg(fdt, index)
+                .ok_or(missing(GICV2_COMPATIBLE, "reg"))
+        };
+        return Ok(Some(Gic::V2 {
+            distributor: reg(0)?,
+            cpu_interface: reg(1)?,
+        }));
+    }
+    if let Some(device) = find_compatible(fdt, GICV3_COMPATIBLE) {
+        let reg = |index| {
+            device
+                .reg(fdt, index)
+                .ok_or(missing(GICV3_COMPATIBLE, "reg"))
+        };
+        return Ok(Some(Gic::V3 {
+            distributor: reg(0)?,
+            redistributor: reg(1)?,
+        }));
+    }
+
+    Ok(None)
+}
+
+fn timer(fdt: &Fdt) -> Result<Option<Timer>, Error> {
+    let Some(device) = find_compatible(fdt, TIMER_COMPATIBLE) else {
+        return Ok(None);
+    };
+    let mut interrupts = interrupts(&device.node).ok_or(missing(TIMER_COMPATIBLE, "interrupts"))?;
+    let mut next = || {
+        interrupts
+            .next()
+            .ok_or(missing(TIMER_COMPATIBLE, "interrupts"))
+    };
+
+    Ok(Some(Timer {
+        secure_phys: next()?,
+        phys: next()?,
+        virt: next()?,
+        hyp_phys: next()?,
+    }))
+}
+
+/// Returns the base address of the node named by `stdout-path` in /chosen.
+fn stdout(fdt: &Fdt) -> Option<usize> {
+    let path = fdt
+        .find_node("/chosen")?
+        .property("stdout-path")?
+        .as_str()?;
+    // the path may be followed by options, like “/pl011@9000000:115200n8”
+    let path = path.split(':').next()?;
+    // and if it doesn't start with a slash, it's an alias
+    let path = if path.starts_with('/') {
+        path
+    } else {
+        fdt.aliases()?.resolve(path)?
+    };
+    let bus = match path.rsplit_once('/')? {
+        ("", _) => None,
+        (bus, _) => Some(fdt.find_node(bus)?),
+    };
+    let device = Device {
+        node: fdt.find_node(path)?,
+        bus,
+    };
+
+    device.reg(fdt, 0)
+}
+
+fn is_compatible(node: &FdtNode, compatible: &[&str]) -> bool {
+    node.compatible()
+        .map_or(false, |c| c.all().any(|c| compatible.contains(&c)))
+}
+
+/// A node, and the bus it's on, unless it's at the root.
+struct Device<'b, 'a> {
+    node: FdtNode<'b, 'a>,
+    bus: Option<FdtNode<'b, 'a>>,
+}
+
+impl Device<'_, '_> {
+    /// Returns the physical base address of the `index`th region in the node's `reg` property.
+    fn reg(&self, fdt: &Fdt, index: usize) -> Option<usize> {
+        let address = self.node.reg()?.nth(index)?.starting_address as usize;
+        match self.bus {
+            Some(bus) => translate(fdt, &bus, address),
+            None => Some(address),
+        }
+    }
+}
+
+/// Returns every node at the root, and every node on a bus at the root, in devicetree order.
+fn devices<'b, 'a>(fdt: &'b Fdt<'a>) -> impl Iterator<Item = Device<'b, 'a>> {
+    let root = fdt.find_node("/");
+    root.into_iter()
+        .flat_map(|root| root.children())
+        .flat_map(|node| {
+            // a bus is a node whose children's addresses are translated, even if only one to one
+            let bus = node.property("ranges").map(|_| node);
+            let children = bus.into_iter().flat_map(|bus| {
+                bus.children().map(move |node| Device {
+                    node,
+                    bus: Some(bus),
+                })
+            });
+
+            iter::once(Device { node, bus: None }).chain(children)
+        })
+}
+
+fn find_compatible<'b, 'a>(fdt: &'b Fdt<'a>, compatible: &[&str]) -> Option<Device<'b, 'a>> {
+    devices(fdt).find(|device| is_compatible(&device.node, compatible))
+}
+
+/// Translates an address on `bus` to a physical address, with the bus's `ranges` property, or
+/// returns `None` if it isn't in any of them.
+fn translate(fdt: &Fdt, bus: &FdtNode, address: usize) -> Option<usize> {
+    let ranges = bus.property("ranges")?.value;
+    // an empty `ranges` means the addresses are the same on both sides
+    if ranges.is_empty() {
+        return Some(address);
+    }
+    let child = bus.cell_sizes();
+    let parent_address_cells = fdt.root().cell_sizes().address_cells;
+    let range_cells = child.address_cells + parent_address_cells + child.size_cells;
+
+    ranges.chunks_exact(range_cells * 4).find_map(|range| {
+        let (child_address, rest) = range.split_at(child.address_cells * 4);
+        let (parent_address, size) = rest.split_at(parent_address_cells * 4);
+        let (child_address, parent_address) = (cells(child_address)?, cells(parent_address)?);
+        let offset = address.checked_sub(child_address)?;
+
+        (offset < cells(size)?).then_some(parent_address + offset)
+    })
+}
+
+/// Returns the value of one or two big-endian cells.
+fn cells(bytes: &[u8]) -> Option<usize> {
+    let value = match bytes.len() {
+        4 => u32::from_be_bytes(bytes.try_into().unwrap()).into(),
+        8 => u64::from_be_bytes(bytes.try_into().unwrap()),
+        _ => return None,
+    };
+
+    Some(value as usize)
+}
+
+fn interrupts<'a>(node: &FdtNode<'_, 'a>) -> Option<impl Iterator<Item = Interrupt> + 'a> {
+    Some(Interrupt::parse_all(node.property("interrupts")?.value))
+}
+
+fn missing(compatible: &[&'static str], property: &'static str) -> Error {
+    Error::MissingProperty {
+        compatible: compatible[0],
+        property,
+    }
+}
+
+/// The fixtures are compiled from the .dts files next to them, with
+/// `dtc -I dts -O dtb -o <name>.dtb <name>.dts`.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GIB: usize = 1 << 30;
+
+    fn boot_info(dtb: &[u8]) -> Result<BootInfo, Error> {
+        BootInfo::new(&Fdt::new(dtb).unwrap())
+    }
+
+    fn ppi(interrupt_number: u32, flags: u32) -> Interrupt {
+        Interrupt {
+            interrupt_type: 1,
+            interrupt_number,
+            flags,
+        }
+    }
+
+    #[test]
+    fn virt_gicv2() {
+        let info = boot_info(include_bytes!("../fixtures/virt-gicv2.dtb")).unwrap();
+
+        assert_eq!(
+            info.memory.regions().collect::<Vec<_>>(),
+            vec![0x4000_0000..0x4800_0000]
+        );
+        assert_eq!(
+            info.gic,
+            Some(Gic::V2 {
+                distributor: 0x800_0000,
+                cpu_interface: 0x801_0000,
+            })
+        );
+        assert_eq!(
+            info.timer,
+            Some(Timer {
+                secure_phys: ppi(13, 0xF04),
+                phys: ppi(14, 0xF04),
+                virt: ppi(11, 0xF04),
+                hyp_phys: ppi(10, 0xF04),
+            })
+        );
+        assert_eq!(
+            info.uarts().collect::<Vec<_>>(),
+            [&Uart {
+                base: 0x900_0000,
+                interrupt: Some(Interrupt {
+                    interrupt_type: 0,
+                    interrupt_number: 1,
+                    flags: 4,
+                }),
+            }]
+        );
+        assert_eq!(info.bootargs, Some("console=ttyAMA0 loglevel=debug"));
+        assert_eq!(info.stdout, Some(0x900_0000));
+        assert_eq!(info.fw_cfg, Some(0x902_0000));
+    }
+
+    #[test]
+    fn virt_gicv3() {
+        let info = boot_info(include_bytes!("../fixtures/virt-gicv3.dtb")).unwrap();
+
+        assert_eq!(info.memory.len(), GIB);
+        assert_eq!(
+            info.gic,
+            Some(Gic::V3 {
+                distributor: 0x800_0000,
+                redistributor: 0x80A_0000,
+            })
+        );
+        assert_eq!(info.timer.map(|timer| timer.phys), Some(ppi(14, 4)));
+        // the second UART is named by an alias, with options
+        assert_eq!(info.uarts().count(), 2);
+        assert_eq!(info.stdout, Some(0x904_0000));
+        assert_eq!(info.bootargs, None);
+    }
+
+    #[test]
+    fn virt_highmem() {
+        let info = boot_info(include_bytes!("../fixtures/virt-highmem.dtb")).unwrap();
+
+        // one node spanning 4GiB, less a reservation, and a NUMA node of its own
+        assert_eq!(
+            info.memory.regions().collect::<Vec<_>>(),
+            vec![
+                0x4000_0000..0x1_0000_0000,
+                0x1_0000_1000..0x1_8000_0000,
+                0x2_0000_0000..0x2_8000_0000,
+            ]
+        );
+        assert_eq!(info.memory.len(), 7 * GIB - 0x1000);
+    }
+
+    #[test]
+    fn rpi4() {
+        let info = boot_info(include_bytes!("../fixtures/rpi4.dtb")).unwrap();
+
+        // the firmware's spin tables for the other cores are reserved
+        assert_eq!(
+            info.memory.regions().collect::<Vec<_>>(),
+            vec![0x1000..0x3B40_0000, 0x4000_0000..0x8000_0000]
+        );
+        // the devices on /soc are at bus addresses, which are translated with its ranges
+        assert_eq!(
+            info.gic,
+            Some(Gic::V2 {
+                distributor: 0xFF84_1000,
+                cpu_interface: 0xFF84_2000,
+            })
+        );
+        assert_eq!(info.timer.map(|timer| timer.phys), Some(ppi(14, 0xF08)));
+        assert_eq!(
+            info.uarts().map(|uart| uart.base).collect::<Vec<_>>(),
+            [0xFE20_1000]
+        );
+        assert_eq!(info.stdout, Some(0xFE20_1000));
+        assert_eq!(info.fw_cfg, None);
+        assert_eq!(info.pm, Some(0xFE10_0000));
+    }
+
+    #[test]
+    fn missing_nodes() {
+        let info = boot_info(include_bytes!("../fixtures/virt-minimal.dtb")).unwrap();
+
+        assert_eq!(info.memory.len(), 128 << 20);
+        assert_eq!(info.gic, None);
+        assert_eq!(info.timer, None);
+        assert_eq!(info.uarts().count(), 0);
+        assert_eq!(info.bootargs, None);
+        assert_eq!(info.stdout, None);
+        assert_eq!(info.fw_cfg, None);
+        assert_eq!(info.pm, None);
+    }
+
+    #[test]
+    fn malformed_nodes() {
+        assert_eq!(
+            boot_info(include_bytes!("../fixtures/virt-malformed.dtb")),
+            Err(Error::MissingProperty {
+                compatible: "arm,armv8-timer",
+                property: "interrupts",
+            })
+        );
+    }
+}
@@ -0,0 +1,186 @@
+//! Integers that are always within some inclusive range, such as interrupt IDs or CPU numbers, so
+//! the range only has to be checked once, when the value is created.
+#![cfg_attr(not(test), no_std)]
+
+use core::fmt;
+
+/// Error returned when a value is outside the range of a bounded type.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OutOfBounds;
+
+impl fmt::Display for OutOfBounds {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "value out of bounds")
+    }
+}
+
+/// Defines newtypes around an integer type that can only hold values in an inclusive range.
+///
+/// ```
+/// bounded::bounds_checked! {
+///     /// Zero-based PPI number, as found in devicetree.
+///     #[derive(Clone, Copy, PartialEq)] pub struct PpiNumber(usize (0..=15));
+/// }
+///
+/// const TIMER: PpiNumber = match PpiNumber::new(14) {
+///     Some(ppi) => ppi,
+///     None => panic!("PPI number out of bounds"),
+/// };
+/// assert_eq!(TIMER.checked_add(1), PpiNumber::new(15));
+/// assert_eq!(TIMER.checked_add(2), None);
+/// assert_eq!(PpiNumber::try_from(16), Err(bounded::OutOfBounds));
+/// ```
+///
+/// Each type gets `Debug` and `Display` impls, so don't derive `Debug`.
+#[macro_export]
+macro_rules! bounds_checked {
+    ($(#[$meta:meta])* $vis:vis struct $name:ident ($int:ident ($low:literal ..= $high:literal))) => {
+        $(#[$meta])* $vis struct $name($int);
+
+        #[allow(dead_code)]
+        impl $name {
+            /// Smallest value in bounds.
+            pub const MIN: Self = Self($low);
+            /// Largest value in bounds.
+            pub const MAX: Self = Self($high);
+
+            /// Returns the value if it's in bounds. Unlike `try_from`, this can be used in const
+            /// contexts.
+            pub const fn new(inner: $int) -> Option<Self> {
+                match inner {
+                    $low..=$high => Some(Self(inner)),
+                    _ => None,
+                }
+            }
+
+            /// Returns the value without checking that it's in bounds, except in debug builds.
+            ///
+            /// # Safety
+            ///
+            /// `inner` must be in bounds, because code may rely on that to avoid other checks,
+            /// such as when indexing.
+            pub const unsafe fn unchecked_new(inner: $int) -> Self {
+                debug_assert!(Self::new(inner).is_some(), "value out of bounds");
+
+                Self(inner)
+            }
+
+            pub const fn value(&self) -> $int {
+                self.0
+            }
+
+            /// Adds `rhs`, returning `None` if the result would be out of bounds.
+            pub const fn checked_add(self, rhs: $int) -> Option<Self> {
+                match self.0.checked_add(rhs) {
+                    Some(inner) => Self::new(inner),
+                    None => None,
+                }
+            }
+
+            /// Subtracts `rhs`, returning `None` if the result would be out of bounds.
+            pub const fn checked_sub(self, rhs: $int) -> Option<Self> {
+                match self.0.checked_sub(rhs) {
+                    Some(inner) => Self::new(inner),
+                    None => None,
+                }
+            }
+
+            /// Adds `rhs`, stopping at [`Self::MAX`].
+            pub fn saturating_add(self, rhs: $int) -> Self {
+                Self(self.0.saturating_add(rhs).clamp($low, $high))
+            }
+
+            /// Subtracts `rhs`, stopping at [`Self::MIN`].
+            pub fn saturating_sub(self, rhs: $int) -> Self {
+                Self(self.0.saturating_sub(rhs).clamp($low, $high))
+            }
+        }
+
+        impl TryFrom<$int> for $name {
+            type Error = $crate::OutOfBounds;
+
+            fn try_from(inner: $int) -> Result<Self, Self::Error> {
+                Self::new(inner).ok_or($crate::OutOfBounds)
+            }
+        }
+
+        impl From<$name> for $int {
+            fn from(value: $name) -> Self {
+                value.0
+            }
+        }
+
+        impl ::core::fmt::Debug for $name {
+            fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+                write!(f, "{}({:?})", stringify!($name), self.0)
+            }
+        }
+
+        impl ::core::fmt::Display for $name {
+            fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+                ::core::fmt::Display::fmt(&self.0, f)
+            }
+        }
+    };
+    ($($(#[$meta:meta])* $vis:vis struct $name:ident ($($details:tt)+);)+) => {
+        $($crate::bounds_checked!($(#[$meta])* $vis struct $name ($($details)+));)+
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    bounds_checked! {
+        #[derive(Clone, Copy, PartialEq)] struct Low(u8 (0..=9));
+        #[derive(Clone, Copy, PartialEq)] struct High(u8 (250..=255));
+        #[derive(Clone, Copy, PartialEq)] struct Signed(i8 (-3..=3));
+    }
+
+    #[test]
+    fn construction() {
+        assert_eq!(Low::new(0).map(|x| x.value()), Some(0));
+        assert_eq!(Low::new(9).map(|x| x.value()), Some(9));
+        assert_eq!(Low::new(10), None);
+        assert_eq!(High::new(249), None);
+        assert_eq!(Signed::new(-4), None);
+        assert_eq!(Low::try_from(10), Err(OutOfBounds));
+        assert_eq!(u8::from(Low::MAX), 9);
+
+        const FIVE: Low = match Low::new(5) {
+            Some(five) => five,
+            None => panic!(),
+        };
+        assert_eq!(FIVE.value(), 5);
+        assert_eq!(unsafe { High::unchecked_new(255) }, High::MAX);
+    }
+
+    #[test]
+    #[should_panic = "value out of bounds"]
+    fn unchecked_new_checks_in_debug() {
+        let _ = unsafe { Low::unchecked_new(10) };
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(Low::MIN.checked_add(9), Some(Low::MAX));
+        assert_eq!(Low::MIN.checked_add(10), None);
+        assert_eq!(Low::MIN.checked_sub(1), None);
+        assert_eq!(High::MAX.checked_add(1), None);
+        assert_eq!(High::MIN.checked_sub(1), None);
+        assert_eq!(Signed::MIN.checked_add(6), Some(Signed::MAX));
+
+        assert_eq!(Low::MIN.saturating_add(100), Low::MAX);
+        assert_eq!(Low::MAX.saturating_sub(100), Low::MIN);
+        assert_eq!(High::MAX.saturating_add(1), High::MAX);
+        assert_eq!(High::MAX.saturating_sub(255), High::MIN);
+        assert_eq!(Signed::MIN.saturating_sub(1), Signed::MIN);
+    }
+
+    #[test]
+    fn formatting() {
+        assert_eq!(format!("{:?}", Low::MAX), "Low(9)");
+        assert_eq!(format!("{}", Signed::MIN), "-3");
+        assert_eq!(format!("{:>3}", Low::MAX), "  9");
+    }
+}
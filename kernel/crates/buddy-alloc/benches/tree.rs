@@ -0,0 +1,106 @@
+//! Benchmarks for [`Tree`], mostly of how long the search for a free block takes.
+//!
+//! Run with `cargo bench -p buddy-alloc --features bench`. Each tree has the same number of leaf
+//! blocks as a 4KiB page per leaf block would need for the given amount of RAM.
+use std::hint::black_box;
+use std::sync::atomic::AtomicU64;
+
+use buddy_alloc::atomic::AtomicTree;
+use buddy_alloc::tree::{Placement, Tree};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+/// Leaf blocks in a tree of 16MiB of 4KiB pages.
+const SMALL: usize = 1 << 12;
+/// Leaf blocks in a tree of 4GiB of 4KiB pages.
+const LARGE: usize = 1 << 20;
+
+const PLACEMENTS: [Placement; 3] = [Placement::FirstFit, Placement::TopDown, Placement::BestFit];
+
+/// Allocating then freeing blocks of mixed sizes, in a tree that's already about half allocated,
+/// like a page allocator that's been running for a while.
+fn churn(c: &mut Criterion) {
+    let mut group = c.benchmark_group("churn");
+    for placement in PLACEMENTS {
+        let mut storage = vec![0; Tree::storage_words_required(SMALL)];
+        let mut tree = Tree::new(&mut storage, SMALL).with_placement(placement);
+        for size in (0..).map(|i| 1 << (i % 4)).take(SMALL / 4) {
+            tree.allocate(size).unwrap();
+        }
+
+        group.bench_function(BenchmarkId::new("tree", format!("{placement:?}")), |b| {
+            b.iter(|| {
+                for size in [1, 3, 8, 2] {
+                    let allocation = tree.allocate(black_box(size)).unwrap();
+                    tree.free(allocation.offset).unwrap();
+                }
+            })
+        });
+    }
+
+    let storage: Vec<_> = (0..AtomicTree::storage_words_required(SMALL))
+        .map(|_| AtomicU64::new(0))
+        .collect();
+    let tree = AtomicTree::new(&storage, SMALL);
+    for size in (0..).map(|i| 1 << (i % 4)).take(SMALL / 4) {
+        tree.allocate(size).unwrap();
+    }
+    group.bench_function("atomic tree", |b| {
+        b.iter(|| {
+            for size in [1, 3, 8, 2] {
+                let allocation = tree.allocate(black_box(size)).unwrap();
+                tree.free(allocation.offset).unwrap();
+            }
+        })
+    });
+
+    group.finish();
+}
+
+/// Failing to allocate 2 blocks from a tree where every other leaf block is allocated, so no
+/// superblock is full, but there's no free block that large either, so the allocation fails
+/// without searching, however large the tree.
+fn fragmentation(c: &mut Criterion) {
+    let mut group = c.benchmark_group("fragmentation");
+    for leaf_blocks in [SMALL, LARGE] {
+        let mut storage = vec![0; Tree::storage_words_required(leaf_blocks)];
+        let mut tree = Tree::new(&mut storage, leaf_blocks);
+        for offset in (0..leaf_blocks).step_by(2) {
+            tree.allocate_at(offset, 1).unwrap();
+        }
+
+        group.bench_function(BenchmarkId::from_parameter(leaf_blocks), |b| {
+            b.iter(|| tree.allocate(black_box(2)).unwrap_err())
+        });
+    }
+
+    group.finish();
+}
+
+/// Allocating a leaf block from a large tree whose only free leaf block is the one furthest from
+/// where each placement starts looking, then freeing it again, so the search goes all the way down
+/// a deep tree, skipping the full superblocks beside its path.
+fn large_search(c: &mut Criterion) {
+    let mut group = c.benchmark_group("large search");
+    for placement in PLACEMENTS {
+        let mut storage = vec![0; Tree::storage_words_required(LARGE)];
+        let mut tree = Tree::new(&mut storage, LARGE).with_placement(placement);
+        let free = match placement {
+            Placement::TopDown => 0,
+            _ => LARGE - 1,
+        };
+        tree.allocate_at(0, free).unwrap();
+        tree.allocate_at(free + 1, LARGE - free - 1).unwrap();
+
+        group.bench_function(format!("{placement:?}"), |b| {
+            b.iter(|| {
+                let allocation = tree.allocate(black_box(1)).unwrap();
+                tree.free(allocation.offset).unwrap();
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, churn, fragmentation, large_search);
+criterion_main!(benches);
@@ -0,0 +1,21 @@
+//! Like the tree target, but in a tree over random storage, which must either be rejected by
+//! [`Tree::from_storage`], or behave like any tree that was only ever changed by its own methods.
+#![no_main]
+
+use buddy_alloc::tree::Tree;
+use buddy_alloc_fuzz::{placement, run, Model, Op, MAX_LEAF_BLOCKS};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: (u16, u8, Vec<usize>, Vec<Op>)| {
+    let (leaf_blocks, placement_byte, mut storage, ops) = input;
+    let leaf_blocks = usize::from(leaf_blocks) % MAX_LEAF_BLOCKS + 1;
+
+    let Ok(tree) = Tree::from_storage(&mut storage, leaf_blocks) else {
+        return;
+    };
+    let mut tree = tree.with_placement(placement(placement_byte));
+    let mut model = Model::of(&tree);
+    for op in ops {
+        run(&mut tree, &mut model, op);
+    }
+});
@@ -0,0 +1,19 @@
+//! Allocates, frees and resizes at random in a new tree, checking the tree against a model after
+//! every command (see `src/lib.rs`).
+#![no_main]
+
+use buddy_alloc::tree::Tree;
+use buddy_alloc_fuzz::{placement, run, Model, Op, MAX_LEAF_BLOCKS};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: (u16, u8, Vec<Op>)| {
+    let (leaf_blocks, placement_byte, ops) = input;
+    let leaf_blocks = usize::from(leaf_blocks) % MAX_LEAF_BLOCKS + 1;
+
+    let mut storage = vec![0; Tree::storage_words_required(leaf_blocks)];
+    let mut tree = Tree::new(&mut storage, leaf_blocks).with_placement(placement(placement_byte));
+    let mut model = Model::of(&tree);
+    for op in ops {
+        run(&mut tree, &mut model, op);
+    }
+});
@@ -0,0 +1,271 @@
+//! What the fuzz targets share: commands decoded from the fuzzer's bytes, and a naive model of
+//! what a tree should do with them, which the tree is checked against after every command.
+use std::collections::BTreeMap;
+
+use buddy_alloc::tree::{Allocation, FreeError, OutOfMemoryError, Placement, ResizeError, Tree};
+use libfuzzer_sys::arbitrary::{self, Arbitrary};
+
+/// Largest tree the fuzz targets make, in leaf blocks, which is large enough to need a few levels
+/// of scanning, but small enough for the model to be quick.
+pub const MAX_LEAF_BLOCKS: usize = 1 << 10;
+
+/// A command for a tree. Sizes and offsets are reduced modulo a little more than the size of the
+/// tree, so most commands are in range, but not all of them.
+#[derive(Arbitrary, Clone, Copy, Debug)]
+pub enum Op {
+    Allocate {
+        size: u16,
+        align_log2: u8,
+    },
+    AllocateAt {
+        offset: u16,
+        size: u16,
+    },
+    /// Frees the allocation at this index in the model's list, if there is one, so most frees
+    /// are of real allocations.
+    Free {
+        index: u16,
+    },
+    /// Frees whatever is at this offset, which is usually not an allocation.
+    FreeOffset {
+        offset: u16,
+    },
+    /// Grows the allocation at this index in the model's list, like [`Op::Free`].
+    Grow {
+        index: u16,
+        size: u16,
+    },
+    /// Shrinks the allocation at this index in the model's list, like [`Op::Free`].
+    Shrink {
+        index: u16,
+        size: u16,
+    },
+}
+
+/// Returns the placement chosen by a byte of fuzzer input.
+pub fn placement(byte: u8) -> Placement {
+    match byte % 3 {
+        0 => Placement::FirstFit,
+        1 => Placement::BestFit,
+        _ => Placement::TopDown,
+    }
+}
+
+/// The allocations a tree should have, and the leaf blocks they span.
+pub struct Model {
+    /// Size of the allocation at each offset.
+    allocations: BTreeMap<usize, usize>,
+    /// Offset of the allocation spanning each leaf block, if any.
+    owners: Vec<Option<usize>>,
+}
+
+impl Model {
+    /// Creates a model of `tree`, with the allocations it already has.
+    pub fn of(tree: &Tree) -> Self {
+        let mut model = Self {
+            allocations: BTreeMap::new(),
+            owners: vec![None; tree.leaf_blocks()],
+        };
+        for Allocation { offset, size } in tree.allocations() {
+            model.insert(offset, size);
+        }
+
+        model
+    }
+
+    fn is_free(&self, offset: usize, size: usize) -> bool {
+        offset + size <= self.owners.len()
+            && self.owners[offset..offset + size]
+                .iter()
+                .all(Option::is_none)
+    }
+
+    /// Returns every offset where an allocation of `size` blocks, aligned to `size` and to
+    /// `align_blocks`, would fit, lowest first.
+    fn candidates(&self, size: usize, align_blocks: usize) -> Vec<usize> {
+        (0..self.owners.len())
+            .step_by(size.max(align_blocks))
+            .filter(|&offset| self.is_free(offset, size))
+            .collect()
+    }
+
+    fn insert(&mut self, offset: usize, size: usize) {
+        assert!(self.is_free(offset, size), "{offset}+{size} overlaps");
+        self.allocations.insert(offset, size);
+        self.owners[offset..offset + size].fill(Some(offset));
+    }
+
+    fn remove(&mut self, offset: usize) -> Result<usize, FreeError> {
+        let Some(size) = self.allocations.remove(&offset) else {
+            return Err(match self.owners.get(offset) {
+                None => FreeError::OutOfRange,
+                Some(None) => FreeError::DoubleFree,
+                Some(Some(_)) => FreeError::InsideAllocation,
+            });
+        };
+        self.owners[offset..offset + size].fill(None);
+
+        Ok(size)
+    }
+
+    /// Returns the offset of the allocation at `index` in order of offset, if there are any.
+    fn nth(&self, index: u16) -> Option<usize> {
+        let index = usize::from(index) % self.allocations.len().max(1);
+
+        self.allocations.keys().nth(index).copied()
+    }
+}
+
+/// Runs `op` on both `tree` and `model`, panicking if the tree does anything the model wouldn't,
+/// or if the tree's storage isn't consistent afterwards.
+pub fn run(tree: &mut Tree, model: &mut Model, op: Op) {
+    let leaf_blocks = tree.leaf_blocks();
+    let wrap = |n: u16| usize::from(n) % (leaf_blocks + 3);
+
+    match op {
+        Op::Allocate { size, align_log2 } => {
+            let (size, align_blocks) = (wrap(size), 1 << (align_log2 % 12));
+            let rounded = size.next_power_of_two();
+            let candidates = model.candidates(rounded, align_blocks);
+            match tree.allocate_aligned(size, align_blocks) {
+                Ok(Allocation {
+                    offset,
+                    size: allocated,
+                }) => {
+                    assert_eq!(allocated, rounded, "{op:?} has the wrong size");
+                    assert!(
+                        candidates.contains(&offset),
+                        "{op:?} allocated at {offset}, not one of {candidates:?}"
+                    );
+                    match tree.placement() {
+                        Placement::FirstFit => assert_eq!(Some(&offset), candidates.first()),
+                        Placement::TopDown => assert_eq!(Some(&offset), candidates.last()),
+                        Placement::BestFit => {}
+                    }
+                    model.insert(offset, allocated);
+                }
+                Err(OutOfMemoryError) => assert!(
+                    size == 0 || candidates.is_empty(),
+                    "{op:?} failed, but could have fit at {candidates:?}"
+                ),
+            }
+        }
+        Op::AllocateAt { offset, size } => {
+            let offset = wrap(offset).min(leaf_blocks - 1);
+            let size = wrap(size).min(leaf_blocks - offset);
+            let free = model.is_free(offset, size);
+            assert_eq!(tree.allocate_at(offset, size).is_ok(), free, "{op:?}");
+            if free {
+                // the range is claimed as the fewest aligned blocks that cover it
+                let mut start = offset;
+                while start < offset + size {
+                    let mut block = 1 << start.trailing_zeros().min(usize::BITS - 1);
+                    while start + block > offset + size {
+                        block /= 2;
+                    }
+                    model.insert(start, block);
+                    start += block;
+                }
+            }
+        }
+        Op::Free { index } => {
+            if let Some(offset) = model.nth(index) {
+                assert_eq!(
+                    tree.free(offset),
+                    model.remove(offset).map(|_| ()),
+                    "{op:?}"
+                );
+            }
+        }
+        Op::FreeOffset { offset } => {
+            let offset = wrap(offset);
+            assert_eq!(
+                tree.free(offset),
+                model.remove(offset).map(|_| ()),
+                "{op:?}"
+            );
+        }
+        Op::Grow { index, size } => {
+            let Some(offset) = model.nth(index) else {
+                return;
+            };
+            let old_size = model.allocations[&offset];
+            let new_size = wrap(size).next_power_of_two();
+            let result = tree.grow(offset, wrap(size));
+            if new_size <= old_size {
+                assert_eq!(
+                    result,
+                    Ok(Allocation {
+                        offset,
+                        size: old_size
+                    }),
+                    "{op:?}"
+                );
+            } else if offset % new_size == 0
+                && model.is_free(offset + old_size, new_size - old_size)
+            {
+                assert_eq!(
+                    result,
+                    Ok(Allocation {
+                        offset,
+                        size: new_size
+                    }),
+                    "{op:?}"
+                );
+                model.remove(offset).unwrap();
+                model.insert(offset, new_size);
+            } else {
+                assert_eq!(result, Err(ResizeError::OutOfMemory), "{op:?}");
+            }
+        }
+        Op::Shrink { index, size } => {
+            let Some(offset) = model.nth(index) else {
+                return;
+            };
+            let old_size = model.allocations[&offset];
+            let new_size = wrap(size).max(1).next_power_of_two().min(old_size);
+            assert_eq!(
+                tree.shrink(offset, wrap(size).max(1)),
+                Ok(Allocation {
+                    offset,
+                    size: new_size
+                }),
+                "{op:?}"
+            );
+            model.remove(offset).unwrap();
+            model.insert(offset, new_size);
+        }
+    }
+
+    check(tree, model, op);
+}
+
+/// Panics unless the tree's storage is consistent and it has the same allocations as the model.
+fn check(tree: &Tree, model: &Model, op: Op) {
+    if let Err(error) = tree.check() {
+        panic!(
+            "after {op:?}, the tree is inconsistent: {error:?}\n{}",
+            tree.ascii()
+        );
+    }
+
+    let mut allocations = tree
+        .allocations()
+        .map(|Allocation { offset, size }| (offset, size))
+        .collect::<Vec<_>>();
+    allocations.sort();
+    let expected = model
+        .allocations
+        .iter()
+        .map(|(&offset, &size)| (offset, size));
+    assert!(allocations.iter().copied().eq(expected), "after {op:?}");
+
+    let allocated = model.owners.iter().filter(|owner| owner.is_some()).count();
+    let stats = tree.stats();
+    assert_eq!(stats.allocated_blocks, allocated, "after {op:?}");
+    assert_eq!(
+        stats.free_blocks,
+        tree.leaf_blocks() - allocated,
+        "after {op:?}"
+    );
+}
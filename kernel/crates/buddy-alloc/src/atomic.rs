@@ -0,0 +1,434 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::tree::{cover, Allocation, BlockIndex, FreeError, OutOfMemoryError, Tree};
+
+/// The block itself is allocated.
+const OCCUPIED: u8 = 1 << 0;
+/// Some of the block's left sub-block is allocated, or being allocated.
+const OCCUPIED_LEFT: u8 = 1 << 1;
+/// Some of the block's right sub-block is allocated, or being allocated.
+const OCCUPIED_RIGHT: u8 = 1 << 2;
+/// A block in the left sub-block is being freed, so [`OCCUPIED_LEFT`] is about to be cleared,
+/// unless an allocation in the left sub-block clears this first.
+const COALESCING_LEFT: u8 = 1 << 3;
+/// Like [`COALESCING_LEFT`], but for the right sub-block.
+const COALESCING_RIGHT: u8 = 1 << 4;
+/// State of an allocated block, which has no room for any allocation in either sub-block.
+const BUSY: u8 = OCCUPIED | OCCUPIED_LEFT | OCCUPIED_RIGHT;
+
+/// A binary tree of buddy blocks like [`Tree`], but which can be shared between CPUs, because
+/// blocks are allocated and freed by updating their states with compare-and-swap, rather than
+/// under a lock.
+///
+/// This is the non-blocking buddy system of Marotta et al. (2019). Each block has a byte of
+/// state, packed eight to a word. To allocate a block, its state goes from free to allocated, then
+/// each of its superblocks is marked as having something allocated on that side, unless one of
+/// them turns out to be allocated itself, in which case the allocation is undone and the search
+/// moves on past that superblock. Freeing clears those marks again, as far up as the block's
+/// buddies are free, and marks each superblock as coalescing first, so an allocation that races
+/// with the free isn't forgotten.
+///
+/// Allocations are first fit, by offset. Unlike a [`Tree`], there are no placement strategies,
+/// reservations, snapshots or statistics, and the storage is a byte per block rather than a few
+/// bits (see [`Self::storage_words_required`]).
+#[derive(Debug)]
+pub struct AtomicTree<'s> {
+    /// State of each block, in level order, eight to a word, starting from the low byte.
+    storage: &'s [AtomicU64],
+    /// Count of leaf blocks in the tree.
+    leaf_blocks: usize,
+    /// Total depth of the tree, or equivalently, the number of edges between the root block and a
+    /// leaf block.
+    depth: usize,
+}
+
+impl<'s> AtomicTree<'s> {
+    /// Number of block states in each word of storage.
+    const BLOCKS_PER_WORD: usize = 8;
+
+    /// Returns the number of words required to store a tree with at least the specified number of
+    /// leaf blocks.
+    pub fn storage_words_required(leaf_blocks: usize) -> usize {
+        let blocks: usize = (2 << Tree::depth_required(leaf_blocks)) - 1;
+
+        blocks.div_ceil(Self::BLOCKS_PER_WORD)
+    }
+
+    /// Creates a new tree with all blocks initially marked as free.
+    ///
+    /// Like [`Tree::new`], if `leaf_blocks` isn't a power of two, the blocks past it are
+    /// permanently allocated.
+    pub fn new(storage: &'s [AtomicU64], leaf_blocks: usize) -> Self {
+        let depth = Tree::depth_required(leaf_blocks);
+        let words = Self::storage_words_required(leaf_blocks);
+        assert!(
+            storage.len() >= words,
+            "storage must be at least {words} words long to store a tree with {leaf_blocks} leaf blocks"
+        );
+
+        let tree = Self {
+            storage: &storage[..words],
+            leaf_blocks,
+            depth,
+        };
+        for word in tree.storage {
+            word.store(0, Ordering::SeqCst);
+        }
+        for block in cover(depth, leaf_blocks, (1 << depth) - leaf_blocks) {
+            tree.try_allocate(block)
+                .expect("blocks past the end to be free in a new tree");
+        }
+
+        tree
+    }
+
+    /// Returns the number of leaf blocks in the tree, not counting any past the end.
+    pub fn leaf_blocks(&self) -> usize {
+        self.leaf_blocks
+    }
+
+    /// Attempts to allocate `size` blocks.
+    ///
+    /// If successful, the returned [`Allocation`] may be larger than the requested size due to
+    /// rounding.
+    pub fn allocate(&self, size: usize) -> Result<Allocation, OutOfMemoryError> {
+        self.allocate_aligned(size, 1)
+    }
+
+    /// Attempts to allocate `size` blocks at an offset that's a multiple of `align_blocks`, which
+    /// must be a power of two (see [`Tree::allocate_aligned`]).
+    pub fn allocate_aligned(
+        &self,
+        size: usize,
+        align_blocks: usize,
+    ) -> Result<Allocation, OutOfMemoryError> {
+        assert!(
+            align_blocks.is_power_of_two(),
+            "alignment must be a power of two"
+        );
+
+        let height = match size {
+            0 => return Err(OutOfMemoryError),
+            1 => 0,
+            _ => (size - 1).ilog2() as usize + 1,
+        };
+        if height > self.depth {
+            return Err(OutOfMemoryError);
+        }
+        let depth = self.depth - height;
+
+        // visit the blocks at the requested depth in order of offset, stepping over those that
+        // would be misaligned
+        let step = (align_blocks >> height).max(1);
+        let mut offset = 0;
+        while offset < 1 << depth {
+            let block = BlockIndex::at(depth, offset);
+            if self.state(block) != 0 {
+                offset += step;
+                continue;
+            }
+            match self.try_allocate(block) {
+                Ok(()) => {
+                    return Ok(Allocation {
+                        offset: offset << height,
+                        size: 1 << height,
+                    })
+                }
+                // nothing within the block in the way is free, so carry on past it
+                Err(taken) => {
+                    let past = (taken.offset() + 1) << (depth - taken.depth());
+                    offset = past.next_multiple_of(step);
+                }
+            }
+        }
+
+        Err(OutOfMemoryError)
+    }
+
+    /// Frees a previous [`Allocation`], identified by its offset.
+    pub fn free(&self, offset: usize) -> Result<(), FreeError> {
+        if offset >= self.leaf_blocks {
+            return Err(FreeError::OutOfRange);
+        }
+        let block = self.find_allocation(offset).ok_or_else(|| {
+            let leaf = BlockIndex::at(self.depth, offset);
+            let inside = iter_superblocks(leaf).any(|block| self.state(block) & OCCUPIED != 0);
+            match inside {
+                true => FreeError::InsideAllocation,
+                false => FreeError::DoubleFree,
+            }
+        })?;
+
+        self.release(block, 0);
+
+        Ok(())
+    }
+
+    /// Returns the number of blocks spanned by the allocation at `offset`, which may be more than
+    /// were asked for, or `None` if there's no allocation at `offset`.
+    pub fn allocation_size(&self, offset: usize) -> Option<usize> {
+        if offset >= self.leaf_blocks {
+            return None;
+        }
+        let block = self.find_allocation(offset)?;
+
+        Some(1 << (self.depth - block.depth()))
+    }
+
+    /// Returns the allocated block that starts at `offset`, if any.
+    fn find_allocation(&self, offset: usize) -> Option<BlockIndex> {
+        // the blocks that start at the offset, from the largest down to its leaf block
+        let height = (offset.trailing_zeros() as usize).min(self.depth);
+
+        (0..=height)
+            .rev()
+            .map(|height| BlockIndex::at(self.depth - height, offset >> height))
+            .find(|&block| self.state(block) & OCCUPIED != 0)
+    }
+
+    /// Allocates `block`, then marks its superblocks as having something allocated below them.
+    ///
+    /// If `block` isn't free, returns it, and if one of its superblocks turns out to be
+    /// allocated, undoes the allocation and returns that superblock, so the caller knows that
+    /// nothing within the returned block can be allocated.
+    fn try_allocate(&self, block: BlockIndex) -> Result<(), BlockIndex> {
+        if self
+            .update(block, |state| (state == 0).then_some(BUSY))
+            .is_err()
+        {
+            return Err(block);
+        }
+
+        let mut child = block;
+        while let Some(superblock) = child.superblock() {
+            let (occupied, coalescing) = Self::side(child);
+            // clearing the coalescing bit tells a racing free not to clear the occupied bit
+            let marked = self.update(superblock, |state| {
+                (state & OCCUPIED == 0).then_some(state & !coalescing | occupied)
+            });
+            if marked.is_err() {
+                self.release(block, child.depth());
+                return Err(superblock);
+            }
+            child = superblock;
+        }
+
+        Ok(())
+    }
+
+    /// Frees `block`, then clears the marks on its superblocks down to `top_depth` that said they
+    /// had something allocated below them, where nothing else below them is still allocated.
+    fn release(&self, block: BlockIndex, top_depth: usize) {
+        // first, mark each superblock as coalescing from our side, stopping once we reach one
+        // whose other side is allocated and staying that way, since it'll stay marked anyway
+        let mut runner = block;
+        while runner.depth() > top_depth {
+            let superblock = runner.superblock().expect("block to not be the root");
+            let buddy = runner.buddy().expect("block to not be the root");
+            let (_, coalescing) = Self::side(runner);
+            let (buddy_occupied, buddy_coalescing) = Self::side(buddy);
+            let state = self.update(superblock, |state| Some(state | coalescing));
+            let state = state.expect("update to always succeed");
+            if state & buddy_occupied != 0 && state & buddy_coalescing == 0 {
+                break;
+            }
+            runner = superblock;
+        }
+
+        self.update(block, |_| Some(0))
+            .expect("update to always succeed");
+
+        if block.depth() != top_depth {
+            self.unmark(block, top_depth);
+        }
+    }
+
+    /// Clears the marks on the superblocks of `block` that were marked as coalescing by
+    /// [`Self::release`], stopping at any where an allocation cleared the coalescing bit first.
+    fn unmark(&self, block: BlockIndex, top_depth: usize) {
+        let mut child = block;
+        loop {
+            let superblock = child.superblock().expect("block to not be the root");
+            let buddy = child.buddy().expect("block to not be the root");
+            let (occupied, coalescing) = Self::side(child);
+            let (buddy_occupied, _) = Self::side(buddy);
+            let unmarked = self.update(superblock, |state| {
+                (state & coalescing != 0).then_some(state & !(occupied | coalescing))
+            });
+            let Ok(state) = unmarked else {
+                return;
+            };
+            if superblock.depth() <= top_depth || state & buddy_occupied != 0 {
+                return;
+            }
+            child = superblock;
+        }
+    }
+
+    /// Returns the occupied and coalescing bits for `block`'s side of its superblock.
+    fn side(block: BlockIndex) -> (u8, u8) {
+        // left sub-blocks have odd indices (see BlockIndex::subblocks)
+        if block.0 % 2 == 1 {
+            (OCCUPIED_LEFT, COALESCING_LEFT)
+        } else {
+            (OCCUPIED_RIGHT, COALESCING_RIGHT)
+        }
+    }
+
+    fn state(&self, block: BlockIndex) -> u8 {
+        let (word, shift) = self.locate(block);
+
+        (word.load(Ordering::SeqCst) >> shift) as u8
+    }
+
+    /// Updates the state of `block` with `f`, retrying with the latest state until the word
+    /// holding it can be swapped, or `f` returns `None`. Returns the state `f` was last given, as
+    /// `Ok` if it was updated.
+    fn update(&self, block: BlockIndex, mut f: impl FnMut(u8) -> Option<u8>) -> Result<u8, u8> {
+        let (word, shift) = self.locate(block);
+
+        word.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |word| {
+            let state = f((word >> shift) as u8)?;
+            Some(word & !(0xFF << shift) | u64::from(state) << shift)
+        })
+        .map(|word| (word >> shift) as u8)
+        .map_err(|word| (word >> shift) as u8)
+    }
+
+    /// Returns the word holding the state of `block`, and how far to shift it to get the state.
+    fn locate(&self, block: BlockIndex) -> (&AtomicU64, u32) {
+        let word = &self.storage[block.0 / Self::BLOCKS_PER_WORD];
+        let shift = (block.0 % Self::BLOCKS_PER_WORD) as u32 * 8;
+
+        (word, shift)
+    }
+}
+
+/// Returns every superblock of `block`, from its own superblock up to the root.
+fn iter_superblocks(block: BlockIndex) -> impl Iterator<Item = BlockIndex> {
+    core::iter::successors(block.superblock(), |block| block.superblock())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn allocate_and_free() {
+        let storage: [AtomicU64; 2] = Default::default();
+        let tree = AtomicTree::new(&storage, 8);
+        assert_eq!(tree.allocate(1), Ok(Allocation { offset: 0, size: 1 }));
+        assert_eq!(tree.allocate(2), Ok(Allocation { offset: 2, size: 2 }));
+        assert_eq!(tree.allocate(3), Ok(Allocation { offset: 4, size: 4 }));
+        assert_eq!(tree.allocate(1), Ok(Allocation { offset: 1, size: 1 }));
+        assert_eq!(tree.allocate(1), Err(OutOfMemoryError));
+        assert_eq!(tree.allocation_size(4), Some(4));
+        assert_eq!(tree.allocation_size(5), None);
+
+        assert_eq!(tree.free(5), Err(FreeError::InsideAllocation));
+        assert_eq!(tree.free(8), Err(FreeError::OutOfRange));
+        assert_eq!(tree.free(2), Ok(()));
+        assert_eq!(tree.free(2), Err(FreeError::DoubleFree));
+        assert_eq!(tree.allocate(2), Ok(Allocation { offset: 2, size: 2 }));
+
+        // freeing everything coalesces the whole tree again
+        for offset in [0, 1, 2, 4] {
+            assert_eq!(tree.free(offset), Ok(()));
+        }
+        assert_eq!(tree.allocate(8), Ok(Allocation { offset: 0, size: 8 }));
+    }
+
+    #[test]
+    fn non_power_of_two() {
+        let storage: [AtomicU64; 2] = Default::default();
+        let tree = AtomicTree::new(&storage, 5);
+        assert_eq!(tree.allocate(8), Err(OutOfMemoryError));
+        assert_eq!(tree.allocate(16), Err(OutOfMemoryError));
+        assert_eq!(tree.allocate(4), Ok(Allocation { offset: 0, size: 4 }));
+        assert_eq!(tree.allocate(1), Ok(Allocation { offset: 4, size: 1 }));
+        assert_eq!(tree.allocate(1), Err(OutOfMemoryError));
+        assert_eq!(tree.free(5), Err(FreeError::OutOfRange));
+    }
+
+    #[test]
+    fn allocate_aligned() {
+        let storage: [AtomicU64; 2] = Default::default();
+        let tree = AtomicTree::new(&storage, 8);
+        assert_eq!(tree.allocate(1), Ok(Allocation { offset: 0, size: 1 }));
+        assert_eq!(
+            tree.allocate_aligned(1, 4),
+            Ok(Allocation { offset: 4, size: 1 })
+        );
+        assert_eq!(
+            tree.allocate_aligned(2, 4),
+            Err(OutOfMemoryError),
+            "0 and 4 are taken"
+        );
+        assert_eq!(
+            tree.allocate_aligned(1, 2),
+            Ok(Allocation { offset: 2, size: 1 })
+        );
+    }
+
+    #[test]
+    fn concurrent() {
+        const LEAF_BLOCKS: usize = 256;
+        // Miri checks for data races, but far too slowly to run as many operations
+        let iterations = if cfg!(miri) { 100 } else { 5000 };
+        let storage: Vec<AtomicU64> = (0..AtomicTree::storage_words_required(LEAF_BLOCKS))
+            .map(|_| AtomicU64::new(0))
+            .collect();
+        let tree = AtomicTree::new(&storage, LEAF_BLOCKS);
+        let owned: Vec<AtomicBool> = (0..LEAF_BLOCKS).map(|_| AtomicBool::new(false)).collect();
+
+        thread::scope(|scope| {
+            for thread in 0..4 {
+                let (tree, owned) = (&tree, &owned);
+                scope.spawn(move || {
+                    // keep a few allocations at a time, so frees race with allocations
+                    let mut held = Vec::new();
+                    for i in 0..iterations {
+                        let size = (i * 7 + thread) % 13 + 1;
+                        if let Ok(allocation) = tree.allocate(size) {
+                            let blocks = allocation.offset..allocation.offset + allocation.size;
+                            for block in blocks {
+                                let taken = owned[block].swap(true, Ordering::Relaxed);
+                                assert!(!taken, "block {block} allocated twice");
+                            }
+                            held.push(allocation);
+                        }
+                        if held.len() > 3 || i % 5 == 0 {
+                            let Some(allocation) = held.pop() else {
+                                continue;
+                            };
+                            let blocks = allocation.offset..allocation.offset + allocation.size;
+                            for block in blocks {
+                                owned[block].store(false, Ordering::Relaxed);
+                            }
+                            assert_eq!(tree.free(allocation.offset), Ok(()));
+                        }
+                    }
+                    for allocation in held {
+                        let blocks = allocation.offset..allocation.offset + allocation.size;
+                        for block in blocks {
+                            owned[block].store(false, Ordering::Relaxed);
+                        }
+                        assert_eq!(tree.free(allocation.offset), Ok(()));
+                    }
+                });
+            }
+        });
+
+        // every block was freed, so the whole tree has coalesced again
+        assert_eq!(
+            tree.allocate(LEAF_BLOCKS),
+            Ok(Allocation {
+                offset: 0,
+                size: LEAF_BLOCKS,
+            })
+        );
+    }
+}
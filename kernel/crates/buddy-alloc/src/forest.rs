@@ -0,0 +1,379 @@
+use core::iter;
+
+use crate::tree::{
+    Allocation, AlreadyAllocatedError, FreeError, OutOfMemoryError, Placement, Region, Stats, Tree,
+};
+
+/// A set of [`Tree`]s that allocate as one, each covering its own range of blocks, so blocks that
+/// are split into discontiguous ranges, like RAM split into several memory nodes, can be managed
+/// without storage for the gaps between them.
+///
+/// One tree spanning every range needs storage for every block from the first to the last, gaps
+/// included, rounded up to a power of two (see [`Tree::storage_bits_required`]), but a forest only
+/// needs storage for each tree (see [`Self::storage_bytes_required`]).
+///
+/// Offsets are in the forest's own address space, where each tree's blocks start at the base it
+/// was added at. Allocations are only aligned to their size within their tree, so they're only
+/// aligned to it in the forest if the tree's base is too.
+#[derive(Debug)]
+pub struct Forest<'s, const N: usize> {
+    /// The trees, in order of base, followed by the slots that are still free.
+    trees: [Option<Member<'s>>; N],
+    /// Strategy for choosing which free block to allocate, which every tree is given.
+    placement: Placement,
+}
+
+#[derive(Debug)]
+struct Member<'s> {
+    /// Offset of the tree's first block, in the forest.
+    base: usize,
+    tree: Tree<'s>,
+}
+
+/// Error returned when a tree can't be added to a [`Forest`].
+#[derive(PartialEq, Eq, Debug)]
+pub enum AddTreeError {
+    /// The forest already has as many trees as it can hold.
+    TooManyTrees,
+    /// Some of the tree's range is covered by a tree already in the forest.
+    Overlapping,
+}
+
+impl<'s, const N: usize> Forest<'s, N> {
+    /// Creates a forest with no trees, which fails every allocation until trees are added.
+    pub fn new() -> Self {
+        Self {
+            trees: core::array::from_fn(|_| None),
+            placement: Placement::default(),
+        }
+    }
+
+    /// Returns the total number of bytes required to store a tree for each range, which have the
+    /// given numbers of leaf blocks.
+    pub fn storage_bytes_required(leaf_blocks: impl IntoIterator<Item = usize>) -> usize {
+        leaf_blocks
+            .into_iter()
+            .map(Tree::storage_bytes_required)
+            .sum()
+    }
+
+    /// Sets the strategy for choosing which free block satisfies an allocation.
+    ///
+    /// Trees are tried in order of base, or in reverse for [`Placement::TopDown`], and the first
+    /// that can satisfy the allocation chooses a block by the same strategy. So
+    /// [`Placement::BestFit`] finds the best fit in the first tree with room, not in the forest.
+    pub fn with_placement(mut self, placement: Placement) -> Self {
+        self.placement = placement;
+        for member in self.trees.iter_mut().flatten() {
+            member.tree.set_placement(placement);
+        }
+        self
+    }
+
+    /// Returns the strategy for choosing which free block satisfies an allocation.
+    pub fn placement(&self) -> Placement {
+        self.placement
+    }
+
+    /// Adds a tree, whose blocks start at offset `base` in the forest.
+    ///
+    /// The tree is given the forest's placement (see [`Self::with_placement`]), but anything
+    /// already allocated or reserved in it stays that way.
+    pub fn add(&mut self, base: usize, mut tree: Tree<'s>) -> Result<(), AddTreeError> {
+        let end = base
+            .checked_add(tree.leaf_blocks())
+            .ok_or(AddTreeError::Overlapping)?;
+        if self
+            .members()
+            .any(|member| base < member.end() && member.base < end)
+        {
+            return Err(AddTreeError::Overlapping);
+        }
+        let slot = self
+            .trees
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(AddTreeError::TooManyTrees)?;
+
+        tree.set_placement(self.placement);
+        *slot = Some(Member { base, tree });
+        // keep the trees in order of base, with the free slots last
+        self.trees
+            .sort_unstable_by_key(|slot| slot.as_ref().map_or(usize::MAX, |member| member.base));
+
+        Ok(())
+    }
+
+    /// Returns the base and tree of every tree in the forest, in order of base.
+    pub fn trees(&self) -> impl Iterator<Item = (usize, &Tree<'s>)> {
+        self.members().map(|member| (member.base, &member.tree))
+    }
+
+    /// Attempts to allocate `size` blocks from any of the trees.
+    ///
+    /// If successful, the returned [`Allocation`] may be larger than the requested size due to
+    /// rounding.
+    pub fn allocate(&mut self, size: usize) -> Result<Allocation, OutOfMemoryError> {
+        self.allocate_aligned(size, 1)
+    }
+
+    /// Attempts to allocate `size` blocks at an offset that's a multiple of `align_blocks`, which
+    /// must be a power of two, from any of the trees whose base is a multiple of it too.
+    ///
+    /// Trees with fewer than `size` blocks in all are skipped, since a tree would panic if asked
+    /// for more blocks than it has.
+    pub fn allocate_aligned(
+        &mut self,
+        size: usize,
+        align_blocks: usize,
+    ) -> Result<Allocation, OutOfMemoryError> {
+        let mut members =
+            self.trees.iter_mut().flatten().filter(|member| {
+                member.base % align_blocks == 0 && size <= member.tree.leaf_blocks()
+            });
+        let allocate = |member: &mut Member| {
+            let allocation = member.tree.allocate_aligned(size, align_blocks).ok()?;
+            Some(Allocation {
+                offset: member.base + allocation.offset,
+                size: allocation.size,
+            })
+        };
+
+        match self.placement {
+            Placement::TopDown => members.rev().find_map(allocate),
+            _ => members.find_map(allocate),
+        }
+        .ok_or(OutOfMemoryError)
+    }
+
+    /// Claims the `size` blocks starting at `offset`, which must all be in one tree, failing
+    /// without claiming anything if any of them is already allocated (see [`Tree::allocate_at`]).
+    pub fn allocate_at(&mut self, offset: usize, size: usize) -> Result<(), AlreadyAllocatedError> {
+        let member = self
+            .member_at_mut(offset)
+            .filter(|member| offset + size <= member.end())
+            .expect("range must be within one tree");
+
+        member.tree.allocate_at(offset - member.base, size)
+    }
+
+    /// Frees a previous [`Allocation`], identified by its offset, in whichever tree it's in.
+    ///
+    /// An offset in a gap between trees is [`FreeError::OutOfRange`], like one past the end of a
+    /// tree.
+    pub fn free(&mut self, offset: usize) -> Result<(), FreeError> {
+        let member = self.member_at_mut(offset).ok_or(FreeError::OutOfRange)?;
+
+        member.tree.free(offset - member.base)
+    }
+
+    /// Returns the number of blocks spanned by the allocation at `offset`, or `None` if there's no
+    /// allocation at `offset`.
+    pub fn allocation_size(&self, offset: usize) -> Option<usize> {
+        let member = self.members().find(|member| member.contains(offset))?;
+
+        member.tree.allocation_size(offset - member.base)
+    }
+
+    /// Returns every allocation that hasn't been freed, tree by tree in order of base, and within
+    /// each tree, as [`Tree::allocations`] does.
+    pub fn allocations(&self) -> impl Iterator<Item = Allocation> + '_ {
+        self.members().flat_map(|member| {
+            member.tree.allocations().map(|allocation| Allocation {
+                offset: member.base + allocation.offset,
+                size: allocation.size,
+            })
+        })
+    }
+
+    /// Returns the regions of every tree, in order of offset. The gaps between trees aren't
+    /// regions, and regions in different trees are never merged, even if the trees are adjacent.
+    pub fn regions(&self) -> impl Iterator<Item = Region> + '_ {
+        self.members().flat_map(|member| {
+            member.tree.regions().map(|region| Region {
+                offset: member.base + region.offset,
+                ..region
+            })
+        })
+    }
+
+    /// Returns the sum of the stats of every tree, except [`Stats::largest_free_run`], which is the
+    /// largest of any one tree.
+    pub fn stats(&self) -> Stats {
+        let empty = Stats {
+            total_blocks: 0,
+            allocated_blocks: 0,
+            reserved_blocks: 0,
+            free_blocks: 0,
+            largest_free_run: 0,
+            free_by_height: [0; Stats::MAX_HEIGHTS],
+        };
+
+        self.members()
+            .map(|member| member.tree.stats())
+            .fold(empty, |mut total, stats| {
+                total.total_blocks += stats.total_blocks;
+                total.allocated_blocks += stats.allocated_blocks;
+                total.reserved_blocks += stats.reserved_blocks;
+                total.free_blocks += stats.free_blocks;
+                total.largest_free_run = total.largest_free_run.max(stats.largest_free_run);
+                for (total, count) in iter::zip(&mut total.free_by_height, stats.free_by_height) {
+                    *total += count;
+                }
+                total
+            })
+    }
+
+    fn members(&self) -> impl Iterator<Item = &Member<'s>> {
+        self.trees.iter().flatten()
+    }
+
+    fn member_at_mut(&mut self, offset: usize) -> Option<&mut Member<'s>> {
+        self.trees
+            .iter_mut()
+            .flatten()
+            .find(|member| member.contains(offset))
+    }
+}
+
+impl<const N: usize> Default for Forest<'_, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Member<'_> {
+    /// Returns the offset just past the tree's last block, in the forest.
+    fn end(&self) -> usize {
+        self.base + self.tree.leaf_blocks()
+    }
+
+    fn contains(&self, offset: usize) -> bool {
+        (self.base..self.end()).contains(&offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tree::RegionState;
+
+    #[test]
+    fn routes_by_offset() {
+        let mut low = [0; 8];
+        let mut high = [0; 8];
+        let mut forest = Forest::<2>::new();
+        assert_eq!(forest.add(1024, Tree::new(&mut high, 8)), Ok(()));
+        assert_eq!(forest.add(0, Tree::new(&mut low, 4)), Ok(()));
+        assert_eq!(
+            forest.trees().map(|(base, _)| base).collect::<Vec<_>>(),
+            [0, 1024]
+        );
+
+        // the first tree with room is used, lowest base first
+        assert_eq!(forest.allocate(4), Ok(Allocation { offset: 0, size: 4 }));
+        assert_eq!(
+            forest.allocate(2),
+            Ok(Allocation {
+                offset: 1024,
+                size: 2,
+            })
+        );
+        assert_eq!(forest.allocation_size(1024), Some(2));
+        assert_eq!(forest.allocation_size(512), None);
+        assert_eq!(
+            forest.allocations().collect::<Vec<_>>(),
+            [
+                Allocation { offset: 0, size: 4 },
+                Allocation {
+                    offset: 1024,
+                    size: 2,
+                },
+            ]
+        );
+        assert_eq!(
+            forest.regions().collect::<Vec<_>>(),
+            [
+                Region {
+                    offset: 0,
+                    size: 4,
+                    state: RegionState::Allocated,
+                },
+                Region {
+                    offset: 1024,
+                    size: 2,
+                    state: RegionState::Allocated,
+                },
+                Region {
+                    offset: 1026,
+                    size: 6,
+                    state: RegionState::Free,
+                },
+            ]
+        );
+
+        assert_eq!(forest.free(512), Err(FreeError::OutOfRange));
+        assert_eq!(forest.free(1025), Err(FreeError::InsideAllocation));
+        assert_eq!(forest.free(1024), Ok(()));
+        assert_eq!(forest.free(1024), Err(FreeError::DoubleFree));
+        assert_eq!(forest.allocate(16), Err(OutOfMemoryError));
+
+        let stats = forest.stats();
+        assert_eq!(stats.total_blocks, 12);
+        assert_eq!(stats.allocated_blocks, 4);
+        assert_eq!(stats.free_blocks, 8);
+        assert_eq!(stats.largest_free_run, 8);
+        assert_eq!(stats.largest_free_block(), 8);
+    }
+
+    #[test]
+    fn add_errors() {
+        let mut storage = [[0; 8]; 4];
+        let [a, b, c, d] = &mut storage;
+        let mut forest = Forest::<2>::new();
+        assert_eq!(forest.add(8, Tree::new(a, 8)), Ok(()));
+        assert_eq!(
+            forest.add(12, Tree::new(b, 8)),
+            Err(AddTreeError::Overlapping)
+        );
+        assert_eq!(forest.add(0, Tree::new(c, 8)), Ok(()));
+        assert_eq!(
+            forest.add(16, Tree::new(d, 8)),
+            Err(AddTreeError::TooManyTrees)
+        );
+    }
+
+    #[test]
+    fn placement_and_alignment() {
+        let mut low = [0; 8];
+        let mut high = [0; 8];
+        let mut forest = Forest::<2>::new().with_placement(Placement::TopDown);
+        forest.add(0, Tree::new(&mut low, 8)).unwrap();
+        forest.add(13, Tree::new(&mut high, 8)).unwrap();
+        assert!(forest
+            .trees()
+            .all(|(_, tree)| tree.placement() == Placement::TopDown));
+
+        // top down starts from the highest tree, but that tree's base isn't aligned to 4 blocks
+        assert_eq!(
+            forest.allocate(1),
+            Ok(Allocation {
+                offset: 20,
+                size: 1,
+            })
+        );
+        assert_eq!(
+            forest.allocate_aligned(1, 4),
+            Ok(Allocation { offset: 4, size: 1 })
+        );
+    }
+
+    #[test]
+    fn storage_is_smaller() {
+        // 1GiB of 4KiB pages below and above 4GiB, as on a machine with two memory nodes
+        let pages = 1 << 18;
+        let forest = Forest::<2>::storage_bytes_required([pages, pages]);
+        let tree = Tree::storage_bytes_required((4 << 18) + pages);
+        assert!(forest * 2 < tree, "{forest} bytes, against {tree} bytes");
+    }
+}
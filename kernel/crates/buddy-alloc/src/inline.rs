@@ -0,0 +1,146 @@
+use crate::tree::{Placement, Stats, Tree};
+
+/// A [`Tree`] with `LEAVES` leaf blocks that owns its storage, so it can be a `static` without
+/// finding somewhere else to keep the storage.
+///
+/// The storage is a word per leaf block, which is always enough, since a tree needs fewer than six
+/// bits per leaf block (see [`Tree::storage_bits_required`]), but it's far more than a tree with
+/// many leaf blocks needs. Use [`Tree`] directly where that matters.
+///
+/// Every block is free until the first call to [`Self::tree`], which sets up the storage, since
+/// that can't be done in a `const fn`.
+#[derive(Debug)]
+pub struct InlineTree<const LEAVES: usize> {
+    storage: [usize; LEAVES],
+    /// Whether the storage has been set up by [`Tree::new`].
+    initialised: bool,
+    /// Ranges reserved with [`Tree::reserve`], which aren't kept in the storage, so they're kept
+    /// here and given to each tree that [`Self::tree`] returns.
+    reservations: [Option<(usize, usize)>; Tree::MAX_RESERVATIONS],
+    /// Counts of free blocks at each height, which aren't kept in the storage either, so they're
+    /// kept here rather than counted again by each tree that [`Self::tree`] returns.
+    free_by_height: [usize; Stats::MAX_HEIGHTS],
+    placement: Placement,
+}
+
+impl<const LEAVES: usize> InlineTree<LEAVES> {
+    /// Creates a new tree with all blocks initially marked as free.
+    pub const fn new() -> Self {
+        assert!(LEAVES > 0, "tree must have at least 1 leaf block");
+
+        Self {
+            storage: [0; LEAVES],
+            initialised: false,
+            reservations: [None; Tree::MAX_RESERVATIONS],
+            free_by_height: [0; Stats::MAX_HEIGHTS],
+            placement: Placement::FirstFit,
+        }
+    }
+
+    /// Sets the strategy for choosing which free block satisfies an allocation.
+    pub const fn with_placement(mut self, placement: Placement) -> Self {
+        self.placement = placement;
+        self
+    }
+
+    /// Returns the tree, which allocates from and frees to this tree's storage.
+    ///
+    /// Ranges reserved through the tree, and its counts of free blocks of each size, are kept with
+    /// the storage, so later trees still refuse to free the ranges, and needn't count the blocks.
+    pub fn tree(&mut self) -> Tree<'_> {
+        let tree = if self.initialised {
+            Tree::attach_counted(&mut self.storage, LEAVES, &mut self.free_by_height)
+        } else {
+            self.initialised = true;
+            Tree::new(&mut self.storage, LEAVES).keeping_counts_in(&mut self.free_by_height)
+        };
+
+        tree.with_placement(self.placement)
+            .keeping_reservations_in(&mut self.reservations)
+    }
+}
+
+impl<const LEAVES: usize> Default for InlineTree<LEAVES> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tree::{Allocation, AlreadyAllocatedError, FreeError, Region, RegionState};
+
+    #[test]
+    fn keeps_state() {
+        static mut TREE: InlineTree<6> = InlineTree::new().with_placement(Placement::TopDown);
+
+        // SAFETY: no other test uses the tree.
+        let tree = unsafe { &mut TREE };
+        assert_eq!(tree.tree().placement(), Placement::TopDown);
+        assert_eq!(
+            tree.tree().allocate(2),
+            Ok(Allocation { offset: 4, size: 2 })
+        );
+        assert_eq!(
+            tree.tree().allocate(1),
+            Ok(Allocation { offset: 3, size: 1 })
+        );
+
+        // the blocks past the end are still reserved, since the storage was only set up once
+        assert_eq!(
+            tree.tree().regions().collect::<Vec<_>>(),
+            [
+                Region {
+                    offset: 0,
+                    size: 3,
+                    state: RegionState::Free,
+                },
+                Region {
+                    offset: 3,
+                    size: 1,
+                    state: RegionState::Allocated,
+                },
+                Region {
+                    offset: 4,
+                    size: 2,
+                    state: RegionState::Allocated,
+                },
+            ]
+        );
+        assert_eq!(tree.tree().free(4), Ok(()));
+        assert_eq!(tree.tree().stats().free_blocks, 5);
+
+        // the counts kept between trees match the storage, without being counted again
+        let counted = tree.tree().stats().free_by_height;
+        assert_eq!(tree.free_by_height, counted);
+    }
+
+    #[test]
+    fn keeps_reservations() {
+        let mut tree = InlineTree::<8>::new();
+        assert_eq!(tree.tree().reserve(0, 2), Ok(()));
+
+        // each call attaches a new tree to the storage, which must still know what's reserved
+        assert_eq!(tree.tree().reservations().collect::<Vec<_>>(), [(0, 2)]);
+        assert_eq!(tree.tree().free(0), Err(FreeError::Reserved));
+        assert_eq!(tree.tree().allocations().count(), 0);
+        assert_eq!(
+            tree.tree().allocate(4),
+            Ok(Allocation { offset: 4, size: 4 })
+        );
+        assert_eq!(tree.tree().allocate_at(1, 1), Err(AlreadyAllocatedError));
+        assert_eq!(
+            tree.tree().allocate(2),
+            Ok(Allocation { offset: 2, size: 2 })
+        );
+        assert_eq!(tree.tree().stats().reserved_blocks, 2);
+    }
+
+    #[test]
+    fn storage_is_enough() {
+        for leaf_blocks in 1..=1024 {
+            assert!(Tree::storage_words_required(leaf_blocks) <= leaf_blocks);
+        }
+    }
+}
@@ -1,2 +1,15 @@
+//! Buddy allocation of blocks, measured in leaf blocks of whatever size the caller likes, like
+//! pages.
+//!
+//! [`tree::Tree`] is the front-end: it takes its storage and a number of leaf blocks, from which
+//! it works out its depth (see [`tree::Tree::depth_required`]), allocates and frees by offset, and
+//! has an error type for each way that can fail. The others are built on it:
+//!
+//! - [`inline::InlineTree`] owns its storage, so it can be a `static`.
+//! - [`forest::Forest`] allocates from several trees at once, for blocks in disjoint ranges.
+//! - [`atomic::AtomicTree`] can be shared between CPUs without a lock.
 #![cfg_attr(not(test), no_std)]
+pub mod atomic;
+pub mod forest;
+pub mod inline;
 pub mod tree;
@@ -1,8 +1,13 @@
 #![cfg(feature = "cli")]
-use std::io::{self, Write};
-use std::{env, fs};
+use std::collections::BTreeMap;
+use std::io::{self, IsTerminal, Write};
+use std::{env, fs, process};
 
-use buddy_alloc::tree::Tree;
+use buddy_alloc::tree::{
+    Allocation, FreeError, InvalidStorageError, Placement, RegionState, ReserveError, RestoreError,
+    Tree,
+};
+use trace_format::Event;
 
 enum Command<'l> {
     One(&'l str),
@@ -14,52 +19,177 @@ enum Action {
     Quit,
 }
 
+/// A change made by malloc or free, which undo and redo can make in reverse or again.
+enum Change {
+    Malloc(Allocation),
+    Free(Allocation),
+}
+
+/// Changes that can be undone, oldest first, and changes that were undone and can be redone, most
+/// recently undone last. Both are forgotten by commands that change the tree in other ways.
+#[derive(Default)]
+struct History {
+    undo: Vec<Change>,
+    redo: Vec<Change>,
+}
+
+impl History {
+    /// Records a change made by a command, which can't be redone over.
+    fn push(&mut self, change: Change) {
+        self.undo.push(change);
+        self.redo.clear();
+    }
+
+    fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+}
+
 fn main() {
-    let args = env::args();
-    let depth = args
-        .skip(1)
-        .next()
-        .ok_or("expected tree depth as first command line argument")
+    // --tui can go anywhere, so it's taken out before the other arguments are parsed
+    let mut tui = false;
+    let mut args = env::args().skip(1).filter(|arg| {
+        let flag = arg == "--tui";
+        tui |= flag;
+        !flag
+    });
+    let first = args.next();
+    if first.as_deref() == Some("replay") {
+        let result = args
+            .next()
+            .ok_or_else(|| "expected trace dump after replay".to_owned())
+            .and_then(|path| replay(&path));
+        if let Err(e) = result {
+            println!("error: {e}");
+            process::exit(1);
+        }
+        return;
+    }
+
+    let depth = first
+        .ok_or("expected tree depth, or replay and a trace dump, as command line arguments")
         .and_then(|depth| depth.parse().map_err(|_| "could not parse depth"));
+    let placement = match args.next().as_deref() {
+        None | Some("first-fit") => Ok(Placement::FirstFit),
+        Some("best-fit") => Ok(Placement::BestFit),
+        Some("top-down") => Ok(Placement::TopDown),
+        Some(_) => Err("expected placement (first-fit, best-fit or top-down) as second argument"),
+    };
 
-    let depth = match depth {
-        Ok(depth) => depth,
+    let (depth, placement) = match depth.and_then(|depth| Ok((depth, placement?))) {
+        Ok(args) => args,
         Err(e) => {
             println!("error: {e}");
-            return;
+            process::exit(1);
         }
     };
 
-    // 64 bytes should be enough for anyone
-    let mut storage = [0; 64];
-    let mut tree = Tree::new(&mut storage, depth);
+    // commands piped in or redirected from a file are a script, like a bug report, which is
+    // echoed after the prompt as if it were typed, and stops at the first error
+    let script = !io::stdin().is_terminal();
+
+    // 64 words should be enough for anyone, unless they load a larger snapshot
+    let storage = Box::leak(Box::new([0; 64]));
+    let mut tree = Tree::new(storage, depth).with_placement(placement);
+    let mut history = History::default();
+    if tui {
+        print!("{CLEAR}");
+        draw(&tree);
+    }
 
-    loop {
+    for number in 1.. {
         print!("> ");
         io::stdout()
             .flush()
             .expect("flushing stdout should succeed");
 
         let mut line = String::new();
-        io::stdin()
+        let len = io::stdin()
             .read_line(&mut line)
             .expect("read_line should succeed");
+        if len == 0 {
+            // end of file, which is how a script ends if it doesn't quit
+            println!();
+            break;
+        }
 
         let line = line.trim();
+        if script && !tui {
+            println!("{line}");
+        }
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if tui {
+            // the command's output goes above the tree as it is afterwards
+            print!("{CLEAR}");
+            println!("> {line}");
+        }
         let command = match line.split_once(" ") {
             None => Command::One(&line),
             Some((command, arg)) => Command::Two(command, arg),
         };
 
-        match run_command(command, &mut tree) {
+        match run_command(command, &mut tree, &mut history) {
             Ok(Action::Continue) => {}
             Ok(Action::Quit) => break,
+            Err(e) if script => {
+                println!("error: line {number}: {e}");
+                process::exit(1);
+            }
             Err(e) => println!("error: {e}"),
         }
+        if tui {
+            println!();
+            draw(&tree);
+        }
+    }
+}
+
+/// Moves the cursor to the top left of the terminal and clears it, for `--tui`.
+const CLEAR: &str = "\x1b[H\x1b[2J";
+
+/// Draws the tree level by level, then a map of its blocks and a summary of its stats, for
+/// `--tui`, which shows them after every command without needing a dot viewer.
+fn draw(tree: &Tree) {
+    /// Number of blocks in each line of the map.
+    const WIDTH: usize = 64;
+
+    print!("{:#}", tree.ascii());
+    println!();
+
+    // one character per block, alternating colours so neighbouring allocations can be told apart
+    let mut map = Vec::new();
+    for (i, region) in tree.regions().enumerate() {
+        let block = match region.state {
+            RegionState::Free => "\x1b[2m.\x1b[0m",
+            RegionState::Reserved => "\x1b[33mR\x1b[0m",
+            RegionState::Allocated if i % 2 == 0 => "\x1b[32m#\x1b[0m",
+            RegionState::Allocated => "\x1b[36m#\x1b[0m",
+        };
+        map.extend(std::iter::repeat(block).take(region.size));
     }
+    for (i, line) in map.chunks(WIDTH).enumerate() {
+        println!("{:>6} {}", i * WIDTH, line.concat());
+    }
+
+    let stats = tree.stats();
+    println!(
+        "\n{} allocated, {} reserved, {} free, largest free block {} ({}% fragmented)",
+        stats.allocated_blocks,
+        stats.reserved_blocks,
+        stats.free_blocks,
+        stats.largest_free_block(),
+        stats.fragmentation()
+    );
 }
 
-fn run_command(command: Command, tree: &mut Tree) -> Result<Action, &'static str> {
+fn run_command(
+    command: Command,
+    tree: &mut Tree<'static>,
+    history: &mut History,
+) -> Result<Action, &'static str> {
     let dot_path = env::temp_dir().join("buddy-alloc.dot");
 
     match command {
@@ -67,8 +197,18 @@ fn run_command(command: Command, tree: &mut Tree) -> Result<Action, &'static str
             println!("commands:");
             println!("  exit|quit|q");
             println!("  show");
+            println!("  tree");
+            println!("  bars");
+            println!("  list");
+            println!("  stats");
             println!("  malloc <size in blocks>");
             println!("  free <offset>");
+            println!("  undo");
+            println!("  redo");
+            println!("  reserve <offset> <size in blocks>");
+            println!("  stress <iterations> <seed>");
+            println!("  save <path>");
+            println!("  load <path>");
         }
         Command::One("exit" | "quit" | "q") => return Ok(Action::Quit),
         Command::One("show") => {
@@ -76,6 +216,46 @@ fn run_command(command: Command, tree: &mut Tree) -> Result<Action, &'static str
 
             println!("opened {} in system dot viewer", dot_path.display());
         }
+        Command::One("tree") => print!("{}", tree.ascii()),
+        Command::One("bars") => print!("{:#}", tree.ascii()),
+        Command::One("list") => {
+            for region in tree.regions() {
+                println!(
+                    "{}..{}: {} block{} {}",
+                    region.offset,
+                    region.offset + region.size,
+                    region.size,
+                    if region.size != 1 { "s" } else { "" },
+                    region.state
+                );
+            }
+        }
+        Command::One("stats") => {
+            let stats = tree.stats();
+            let plural = |count| if count != 1 { "s" } else { "" };
+            println!(
+                "{} block{} in total",
+                stats.total_blocks,
+                plural(stats.total_blocks)
+            );
+            println!(
+                "{} block{} allocated, {} reserved",
+                stats.allocated_blocks,
+                plural(stats.allocated_blocks),
+                stats.reserved_blocks
+            );
+            println!(
+                "{} block{} free, in a run of at most {}",
+                stats.free_blocks,
+                plural(stats.free_blocks),
+                stats.largest_free_run
+            );
+            println!(
+                "largest free block {}, so {}% fragmented",
+                stats.largest_free_block(),
+                stats.fragmentation()
+            );
+        }
         Command::Two("malloc", size) => {
             let size = size.parse().map_err(|_| "could not parse size")?;
             let allocation = tree.allocate(size).map_err(|_| "out of memory")?;
@@ -87,12 +267,120 @@ fn run_command(command: Command, tree: &mut Tree) -> Result<Action, &'static str
                 size,
                 allocation.offset
             );
+            history.push(Change::Malloc(allocation));
         }
         Command::Two("free", offset) => {
             let offset = offset.parse().map_err(|_| "could not parse offset")?;
-            tree.free(offset).map_err(|_| "double free")?;
+            let allocation = tree
+                .allocations()
+                .find(|allocation| allocation.offset == offset);
+            tree.free(offset).map_err(|error| match error {
+                FreeError::OutOfRange => "offset out of range",
+                FreeError::DoubleFree => "double free",
+                FreeError::InsideAllocation => "offset is inside an allocation",
+                FreeError::Reserved => "offset is reserved",
+            })?;
 
             println!("freed allocation at offset {}", offset);
+            history.push(Change::Free(
+                allocation.expect("a freed offset to be an allocation"),
+            ));
+        }
+        Command::One("undo") => {
+            let change = history.undo.pop().ok_or("nothing to undo")?;
+            match &change {
+                Change::Malloc(allocation) => {
+                    tree.free(allocation.offset)
+                        .expect("an allocation made by malloc to still be allocated");
+                    println!("undid malloc, freeing offset {}", allocation.offset);
+                }
+                Change::Free(allocation) => {
+                    tree.allocate_at(allocation.offset, allocation.size)
+                        .expect("a freed allocation to still be free");
+                    println!(
+                        "undid free, allocating {} blocks at offset {} again",
+                        allocation.size, allocation.offset
+                    );
+                }
+            }
+            history.redo.push(change);
+        }
+        Command::One("redo") => {
+            let change = history.redo.pop().ok_or("nothing to redo")?;
+            match &change {
+                Change::Malloc(allocation) => {
+                    tree.allocate_at(allocation.offset, allocation.size)
+                        .expect("an undone allocation to still be free");
+                    println!(
+                        "redid malloc, allocating {} blocks at offset {} again",
+                        allocation.size, allocation.offset
+                    );
+                }
+                Change::Free(allocation) => {
+                    tree.free(allocation.offset)
+                        .expect("an undone free to still be allocated");
+                    println!("redid free, freeing offset {}", allocation.offset);
+                }
+            }
+            history.undo.push(change);
+        }
+        Command::Two("reserve", args) => {
+            let (offset, size) = args
+                .split_once(' ')
+                .ok_or("expected offset and size in blocks")?;
+            let offset: usize = offset.parse().map_err(|_| "could not parse offset")?;
+            let size: usize = size.trim().parse().map_err(|_| "could not parse size")?;
+            if size == 0 || offset.saturating_add(size) > tree.stats().total_blocks {
+                return Err("range out of range");
+            }
+            tree.reserve(offset, size).map_err(|error| match error {
+                ReserveError::AlreadyAllocated => "range is already allocated",
+                ReserveError::TooManyReservations => "too many reservations",
+            })?;
+
+            println!("reserved {size} blocks at offset {offset}");
+            // undoing a malloc or free from before can't undo a reservation in the way
+            history.clear();
+        }
+        Command::Two("stress", args) => {
+            let (iterations, seed) = args.split_once(' ').ok_or("expected iterations and seed")?;
+            let iterations = iterations
+                .parse()
+                .map_err(|_| "could not parse iterations")?;
+            let seed = seed.trim().parse().map_err(|_| "could not parse seed")?;
+            stress(tree, iterations, seed).map_err(|e| {
+                println!("{e}");
+                "stress found a bug"
+            })?;
+        }
+        Command::Two("save", path) => {
+            let mut snapshot = vec![0; tree.snapshot_len()];
+            tree.snapshot(&mut snapshot)
+                .expect("snapshot should fit in snapshot_len bytes");
+            fs::write(path, snapshot).map_err(|_| "could not write snapshot")?;
+
+            println!("saved snapshot to {path}");
+        }
+        Command::Two("load", path) => {
+            let snapshot = fs::read(path).map_err(|_| "could not read snapshot")?;
+            // the snapshot is larger than the storage it holds, and the old tree keeps its storage
+            let storage = vec![0; snapshot.len()].leak();
+            let placement = tree.placement();
+            *tree = Tree::restore(storage, &snapshot)
+                .map_err(|error| match error {
+                    RestoreError::NotASnapshot => "not a snapshot",
+                    RestoreError::Truncated => "snapshot truncated",
+                    RestoreError::WrongDepth => "snapshot has the wrong depth",
+                    RestoreError::InvalidReservation => "snapshot has an invalid reservation",
+                    RestoreError::InvalidStorage(InvalidStorageError::TooSmall) => {
+                        "snapshot too large"
+                    }
+                    RestoreError::InvalidStorage(_) => "snapshot has an invalid tree",
+                })?
+                .with_placement(placement);
+
+            println!("loaded snapshot from {path}");
+            history.clear();
         }
         _ => return Err("unknown command"),
     };
@@ -102,3 +390,235 @@ fn run_command(command: Command, tree: &mut Tree) -> Result<Action, &'static str
 
     Ok(Action::Continue)
 }
+
+/// Allocates and frees at random, with sizes and choices from a xorshift64* generator seeded with
+/// `seed`, checking the tree after every step, then frees what's left, so the tree ends up as it
+/// started, and prints a summary.
+///
+/// After each step, the tree must be consistent (see [`Tree::check`]), its allocations must be the
+/// ones it started with plus the ones made here and not yet freed, and an allocation may only fail
+/// if there was no free block large enough, otherwise it stops at that step.
+fn stress(tree: &mut Tree, iterations: usize, seed: u64) -> Result<(), String> {
+    let mut state = seed.max(1);
+    let mut random = |n: usize| {
+        state ^= state >> 12;
+        state ^= state << 25;
+        state ^= state >> 27;
+        (state.wrapping_mul(0x2545_F491_4F6C_DD1D) % n as u64) as usize
+    };
+    let before = tree.stats();
+    let existing = tree
+        .allocations()
+        .map(|allocation| (allocation.offset, allocation.size))
+        .collect::<BTreeMap<_, _>>();
+    // offset and size of each allocation made here and not yet freed
+    let mut live = BTreeMap::new();
+    let max_size = (before.total_blocks / 4).max(1);
+    let (mut allocated, mut failed, mut freed, mut peak) = (0, 0, 0, 0);
+
+    for i in 0..iterations {
+        let failure = |what: String| format!("iteration {i} of seed {seed}: {what}");
+        if live.is_empty() || random(2) == 0 {
+            let size = 1 + random(max_size);
+            let largest = tree.stats().largest_free_block();
+            match tree.allocate(size) {
+                Ok(allocation) => {
+                    if allocation.size < size || allocation.offset % allocation.size != 0 {
+                        return Err(failure(format!(
+                            "{size} blocks went to {allocation:?}, which is too small or \
+                             misaligned"
+                        )));
+                    }
+                    live.insert(allocation.offset, allocation.size);
+                    allocated += 1;
+                }
+                Err(_) if largest >= size => {
+                    return Err(failure(format!(
+                        "no room for {size} blocks, but there was a free block of {largest}"
+                    )));
+                }
+                Err(_) => failed += 1,
+            }
+        } else {
+            let offset = *live
+                .keys()
+                .nth(random(live.len()))
+                .expect("live to be nonempty");
+            tree.free(offset)
+                .map_err(|e| failure(format!("could not free {offset}: {e:?}")))?;
+            live.remove(&offset);
+            freed += 1;
+        }
+
+        tree.check()
+            .map_err(|e| failure(format!("tree is inconsistent: {e:?}")))?;
+        let allocations = tree
+            .allocations()
+            .map(|allocation| (allocation.offset, allocation.size))
+            .collect::<BTreeMap<_, _>>();
+        let mut expected = existing.clone();
+        expected.extend(&live);
+        if allocations != expected {
+            return Err(failure(format!(
+                "tree has allocations {allocations:?}, but expected {expected:?}"
+            )));
+        }
+        peak = peak.max(live.values().sum::<usize>());
+    }
+
+    for &offset in live.keys() {
+        tree.free(offset)
+            .map_err(|e| format!("could not free {offset} after stress: {e:?}"))?;
+    }
+    if tree.stats() != before {
+        return Err(format!(
+            "tree ended up with {:?}, but started with {before:?}",
+            tree.stats()
+        ));
+    }
+
+    println!(
+        "{iterations} iterations: {allocated} allocations ({failed} out of memory), {freed} \
+         frees, at most {peak} blocks allocated at once"
+    );
+
+    Ok(())
+}
+
+/// Replays the page allocations in a page trace dumped from the kernel with `dump-trace --pages`
+/// (see kernel/src/trace.rs) against a tree for each region of the page allocator, with the same
+/// number of pages and placement, printing how fragmented the region is after each event.
+///
+/// Each allocation must be at the same offset as it was in the kernel, the kernel must only have
+/// run out of pages when every tree was too full, and the allocations left at the end must be the
+/// ones the trace never freed, otherwise the replay stops at the first event that disagrees.
+fn replay(path: &str) -> Result<(), String> {
+    let data = fs::read(path).map_err(|e| format!("could not read {path}: {e}"))?;
+    let trace = trace_format::decode(&data).map_err(|e| format!("bad trace dump: {e:?}"))?;
+    if trace.dropped > 0 {
+        return Err(format!(
+            "{} events were overwritten before the trace was dumped, so the page allocator's \
+             state at the start of the trace is unknown",
+            trace.dropped
+        ));
+    }
+    if trace.frequency == 0 {
+        return Err("trace dump has no counter frequency".to_owned());
+    }
+
+    let mut trees = BTreeMap::new();
+    // offset and number of pages of each allocation the trace hasn't freed yet, in each region
+    let mut live = BTreeMap::<u8, BTreeMap<usize, usize>>::new();
+    let mut start = None;
+    println!("      time region event                         free  largest block  largest run  fragmentation");
+    for (i, event) in trace.events().enumerate() {
+        let (timestamp, event) = event.map_err(|e| format!("bad trace record {i}: {e:?}"))?;
+        let millis =
+            (timestamp - *start.get_or_insert(timestamp)) as f64 * 1e3 / trace.frequency as f64;
+        let diverged = |what: String| format!("event {i} at {millis:.3}ms diverged: {what}");
+
+        let (region, description) = match event {
+            Event::PageAllocator {
+                region,
+                placement,
+                pages,
+            } => {
+                let placement = match placement {
+                    trace_format::Placement::FirstFit => Placement::FirstFit,
+                    trace_format::Placement::BestFit => Placement::BestFit,
+                    trace_format::Placement::TopDown => Placement::TopDown,
+                };
+                let pages = pages as usize;
+                let storage = vec![0; Tree::storage_words_required(pages)].leak();
+                trees.insert(region, Tree::new(storage, pages).with_placement(placement));
+                live.insert(region, BTreeMap::new());
+
+                (region, format!("{pages} pages, {placement:?}"))
+            }
+            Event::PagesAllocated {
+                region,
+                pages,
+                offset,
+            } => {
+                let tree = trees
+                    .get_mut(&region)
+                    .ok_or_else(|| diverged(format!("region {region} was never set up")))?;
+                let (pages, offset) = (pages as usize, offset as usize);
+                let allocation = tree.allocate(pages).map_err(|_| {
+                    diverged(format!(
+                        "no room for {pages} pages, which the kernel put at {offset}"
+                    ))
+                })?;
+                if allocation.offset != offset {
+                    return Err(diverged(format!(
+                        "{pages} pages went to {}, but the kernel put them at {offset}",
+                        allocation.offset
+                    )));
+                }
+                live.entry(region).or_default().insert(offset, pages);
+
+                (region, format!("allocate {pages} at {offset}"))
+            }
+            Event::PagesFreed { region, offset } => {
+                let tree = trees
+                    .get_mut(&region)
+                    .ok_or_else(|| diverged(format!("region {region} was never set up")))?;
+                let offset = offset as usize;
+                tree.free(offset)
+                    .map_err(|e| diverged(format!("could not free {offset}: {e:?}")))?;
+                live.entry(region).or_default().remove(&offset);
+
+                (region, format!("free {offset}"))
+            }
+            Event::OutOfPages { pages } => {
+                // the kernel tries every region, so none of them should have room
+                let pages = pages as usize;
+                for (region, tree) in &mut trees {
+                    if let Ok(allocation) = tree.allocate(pages) {
+                        return Err(diverged(format!(
+                            "the kernel was out of pages for {pages}, but region {region} had \
+                             room at {}",
+                            allocation.offset
+                        )));
+                    }
+                }
+                println!("{millis:>8.3}ms    all out of pages for {pages}");
+                continue;
+            }
+            Event::TaskPicked { .. } | Event::TaskRan { .. } | Event::TaskWaited { .. } => {
+                continue;
+            }
+        };
+
+        let stats = trees[&region].stats();
+        println!(
+            "{millis:>8.3}ms {region:>6} {description:<25} {:>8} {:>14} {:>12} {:>13}%",
+            stats.free_blocks,
+            stats.largest_free_block(),
+            stats.largest_free_run,
+            stats.fragmentation(),
+        );
+    }
+
+    for (region, tree) in &trees {
+        let mut allocations = tree
+            .allocations()
+            .map(|allocation| allocation.offset)
+            .collect::<Vec<_>>();
+        allocations.sort_unstable();
+        let expected = live[region].keys().copied().collect::<Vec<_>>();
+        if allocations != expected {
+            return Err(format!(
+                "region {region} ended with allocations at {allocations:?}, but the trace left \
+                 them at {expected:?}"
+            ));
+        }
+        println!(
+            "region {region}: final state matches, with {} allocations of {} pages",
+            expected.len(),
+            live[region].values().sum::<usize>()
+        );
+    }
+
+    Ok(())
+}
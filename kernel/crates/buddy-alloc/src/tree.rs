@@ -1,14 +1,16 @@
+use core::mem::size_of;
+use core::ops::{Deref, DerefMut, Range};
 use core::{fmt, iter};
 
-use bitvec::prelude::*;
 use num::AsUsize;
 
 /// A binary tree tracking the state of arbitrarily-sized memory blocks within a buddy allocation
 /// scheme.
 #[derive(Debug)]
 pub struct Tree<'s> {
-    /// Bit-level storage of block states.
-    storage: &'s mut BitSlice<u8, Msb0>,
+    /// Bit-level storage of block states, where bit `i` is bit `i % usize::BITS` of word
+    /// `i / usize::BITS`, so the states of many blocks can be checked at once.
+    storage: &'s mut [usize],
     /// Count of leaf blocks in the tree.
     leaf_blocks: usize,
     /// Total depth of the tree, or equivalently, the number of edges between the root block and a
@@ -16,6 +18,57 @@ pub struct Tree<'s> {
     depth: usize,
     /// Block index of the first leaf block.
     first_leaf: usize,
+    /// Strategy for choosing which free block to allocate.
+    placement: Placement,
+    /// Offset and size of each range reserved with [`Tree::reserve`].
+    reservations: Kept<'s, [Option<(usize, usize)>; Tree::MAX_RESERVATIONS]>,
+    /// Number of free blocks that can be allocated whole at each height, like
+    /// [`Stats::free_by_height`], kept up to date by [`Tree::set_state`] so allocations that can't
+    /// succeed fail without searching the tree.
+    free_by_height: Kept<'s, [usize; Stats::MAX_HEIGHTS]>,
+}
+
+/// Where a tree keeps state that isn't in its storage: in the tree itself, or with storage that
+/// outlives the tree (see [`Tree::keeping_reservations_in`] and [`Tree::keeping_counts_in`]).
+#[derive(Debug)]
+enum Kept<'s, T> {
+    Owned(T),
+    Borrowed(&'s mut T),
+}
+
+impl<T> Deref for Kept<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        match self {
+            Self::Owned(state) => state,
+            Self::Borrowed(state) => state,
+        }
+    }
+}
+
+impl<T> DerefMut for Kept<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        match self {
+            Self::Owned(state) => state,
+            Self::Borrowed(state) => state,
+        }
+    }
+}
+
+/// Strategy for choosing which free block satisfies an allocation, when more than one could.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Placement {
+    /// Allocate the lowest-addressed block of the requested size, splitting the first free larger
+    /// block found if needed.
+    #[default]
+    FirstFit,
+    /// Allocate the lowest-addressed block of the requested size within the smallest free block
+    /// that is large enough, so larger free blocks are only split when there's no better option.
+    BestFit,
+    /// Allocate the highest-addressed block of the requested size, splitting the last free larger
+    /// block found if needed.
+    TopDown,
 }
 
 /// A successful allocation, measured in blocks.
@@ -29,17 +82,170 @@ pub struct Allocation {
     pub size: usize,
 }
 
+/// A span of blocks that are either all free, or all one allocation.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Region {
+    /// Start of the region.
+    pub offset: usize,
+    /// Number of blocks spanned by this region.
+    pub size: usize,
+    pub state: RegionState,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RegionState {
+    Free,
+    Allocated,
+    /// Reserved with [`Tree::reserve`].
+    Reserved,
+}
+
+impl fmt::Display for RegionState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Free => write!(f, "free"),
+            Self::Allocated => write!(f, "allocated"),
+            Self::Reserved => write!(f, "reserved"),
+        }
+    }
+}
+
+/// Usage and fragmentation of a tree, measured in blocks.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Stats {
+    /// Number of leaf blocks in the tree.
+    pub total_blocks: usize,
+    /// Number of blocks spanned by allocations.
+    pub allocated_blocks: usize,
+    /// Number of blocks reserved with [`Tree::reserve`].
+    pub reserved_blocks: usize,
+    /// Number of blocks not spanned by any allocation.
+    pub free_blocks: usize,
+    /// Size of the largest run of adjacent free blocks, which may be larger than any one free
+    /// block, since adjacent free blocks needn't be buddies.
+    pub largest_free_run: usize,
+    /// Number of free blocks that can be allocated whole at each height, where a block at height
+    /// `h` spans `1 << h` leaf blocks. Sub-blocks of a free block aren't counted.
+    pub free_by_height: [usize; Stats::MAX_HEIGHTS],
+}
+
+impl Stats {
+    /// Number of heights a tree can have, one more than the deepest tree can be.
+    pub const MAX_HEIGHTS: usize = usize::BITS as usize;
+
+    /// Returns the size of the largest allocation that could succeed right now, or zero if the
+    /// tree is full.
+    pub fn largest_free_block(&self) -> usize {
+        self.free_by_height
+            .iter()
+            .rposition(|&count| count > 0)
+            .map_or(0, |height| 1 << height)
+    }
+
+    /// Returns how much of the free space is too fragmented to be allocated at once, as a
+    /// percentage rounded down: zero if the largest free block is all of it, or if there is none.
+    pub fn fragmentation(&self) -> usize {
+        match self.free_blocks {
+            0 => 0,
+            free => (free - self.largest_free_block()) * 100 / free,
+        }
+    }
+}
+
 #[derive(PartialEq, Eq, Debug)]
 pub struct OutOfMemoryError;
 
+/// Error returned when an allocation can't be freed.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FreeError {
+    /// The offset is past the last leaf block, so it can't be an allocation.
+    OutOfRange,
+    /// The offset is in a free block, so it was never allocated, or has already been freed.
+    DoubleFree,
+    /// The offset is inside an allocation, but not at its start, so it can't have come from
+    /// [`Tree::allocate`], which suggests the caller's records are corrupt.
+    InsideAllocation,
+    /// The offset is in a range reserved with [`Tree::reserve`], which can't be freed.
+    Reserved,
+}
+
+#[derive(PartialEq, Eq, Debug)]
+pub struct AlreadyAllocatedError;
+
+/// Error returned when a range can't be reserved.
+#[derive(PartialEq, Eq, Debug)]
+pub enum ReserveError {
+    /// Some of the range is already allocated or reserved.
+    AlreadyAllocated,
+    /// The tree already has [`Tree::MAX_RESERVATIONS`] reserved ranges.
+    TooManyReservations,
+}
+
+/// Error returned when storage doesn't hold the state of a tree with the given number of leaf
+/// blocks.
+#[derive(PartialEq, Eq, Debug)]
+pub enum InvalidStorageError {
+    /// The storage is smaller than [`Tree::storage_words_required`].
+    TooSmall,
+    /// The state of a non-leaf block doesn't agree with the states of its sub-blocks.
+    Inconsistent(BlockIndex),
+    /// A block past the last leaf block isn't reserved, as [`Tree::new`] would have left it.
+    NotReserved(BlockIndex),
+}
+
+/// Error returned when a buffer is too small for [`Tree::snapshot`].
 #[derive(PartialEq, Eq, Debug)]
-pub struct DoubleFreeError;
+pub struct SnapshotTooSmallError;
+
+/// Error returned when a snapshot can't be restored.
+#[derive(PartialEq, Eq, Debug)]
+pub enum RestoreError {
+    /// The snapshot doesn't start with [`Tree::SNAPSHOT_MAGIC`], or has an unknown version.
+    NotASnapshot,
+    /// The snapshot ends before the state of the tree does.
+    Truncated,
+    /// The depth in the snapshot doesn't agree with its number of leaf blocks.
+    WrongDepth,
+    /// A reserved range in the snapshot is out of range, or isn't allocated in its storage.
+    InvalidReservation,
+    /// The state of the tree in the snapshot is invalid, or too large for the storage.
+    InvalidStorage(InvalidStorageError),
+}
+
+/// Error returned when an allocation can't be resized in place.
+#[derive(PartialEq, Eq, Debug)]
+pub enum ResizeError {
+    /// There's no allocation at the given offset.
+    NotAllocated,
+    /// The blocks the allocation would need to grow into aren't free.
+    OutOfMemory,
+}
 
 impl<'s> Tree<'s> {
     /// Size, in bits, of a non-leaf block.
     const NONLEAF_BITS: usize = 2;
     /// Size, in bits, of a leaf block.
     const LEAF_BITS: usize = 1;
+    /// Number of bits in a word of storage.
+    const WORD_BITS: usize = usize::BITS as usize;
+    /// Height of the superblocks whose sub-blocks are searched a level at a time, rather than a
+    /// block at a time, so each level spans a word or two of storage.
+    const SCAN_HEIGHT: usize = usize::BITS.ilog2() as usize - 1;
+    /// The first bit of each non-leaf block in a word of storage, which says whether the block is
+    /// subdivided, since non-leaf blocks never straddle two words.
+    const SUBDIVIDED_BITS: usize = usize::MAX / 3;
+
+    /// Maximum number of ranges that can be reserved with [`Self::reserve`].
+    pub const MAX_RESERVATIONS: usize = 8;
+
+    /// Magic number at the start of a snapshot (see [`Self::snapshot`]).
+    pub const SNAPSHOT_MAGIC: [u8; 4] = *b"BUDY";
+    /// Version of the snapshot format, to be bumped whenever the storage layout changes.
+    const SNAPSHOT_VERSION: u8 = 3;
+    /// Size, in bytes, of a snapshot's header.
+    const SNAPSHOT_HEADER_LEN: usize = 16;
+    /// Size, in bytes, of each reserved range in a snapshot.
+    const SNAPSHOT_RESERVATION_LEN: usize = 16;
 
     /// Returns the number of bits required to store a tree with at least the specified number of
     /// leaf blocks.
@@ -52,36 +258,337 @@ impl<'s> Tree<'s> {
         nonleaf_blocks * Self::NONLEAF_BITS + leaf_blocks * Self::LEAF_BITS
     }
 
+    /// Returns the number of words required to store a tree with at least the specified number of
+    /// leaf blocks, which is [`Self::storage_bits_required`] rounded up to a whole word.
+    pub fn storage_words_required(leaf_blocks: usize) -> usize {
+        Self::storage_bits_required(leaf_blocks).div_ceil(Self::WORD_BITS)
+    }
+
+    /// Returns the number of bytes required to store a tree with at least the specified number of
+    /// leaf blocks, for callers that set aside memory for [`Self::storage_words_required`] words.
+    pub fn storage_bytes_required(leaf_blocks: usize) -> usize {
+        Self::storage_words_required(leaf_blocks) * size_of::<usize>()
+    }
+
+    /// Returns the depth of a tree with the specified number of leaf blocks, which is the number of
+    /// edges between its root block and a leaf block.
+    pub fn depth_required(leaf_blocks: usize) -> usize {
+        assert!(leaf_blocks > 0, "tree must have at least 1 leaf block");
+
+        leaf_blocks.next_power_of_two().ilog2().as_usize()
+    }
+
     /// Creates a new tree with all blocks initially marked as free.
-    pub fn new(storage: &'s mut [u8], leaf_blocks: usize) -> Self {
+    ///
+    /// The tree has a power of two leaf blocks, so if `leaf_blocks` isn't a power of two, the
+    /// blocks past it are permanently reserved, which means they're never allocated, freed or
+    /// counted as free, and don't appear in [`Self::allocations`] or [`Self::regions`].
+    pub fn new(storage: &'s mut [usize], leaf_blocks: usize) -> Self {
+        let mut tree = Self::with_storage(storage, leaf_blocks);
+
+        // initially, every block is free
+        // TODO: can we do this without inlining the encoding of BlockState::Free?
+        tree.storage.fill(0);
+        tree.free_by_height[tree.depth] = 1;
+
+        // reserve the blocks past the end, as the fewest blocks that cover them
+        for block in tree.cover(leaf_blocks, (1 << tree.depth) - leaf_blocks) {
+            tree.mark_allocated(block);
+        }
+
+        tree
+    }
+
+    /// Creates a tree over storage that already holds the state of a tree with `leaf_blocks` leaf
+    /// blocks, as written by an earlier tree created with [`Self::new`].
+    pub(crate) fn attach(storage: &'s mut [usize], leaf_blocks: usize) -> Self {
+        let mut tree = Self::with_storage(storage, leaf_blocks);
+
+        // the storage doesn't keep the counts, so count the free blocks it already has
+        for block in (0..tree.block_count()).map(BlockIndex) {
+            if tree.is_whole_free(block) {
+                tree.free_by_height[tree.depth - block.depth()] += 1;
+            }
+        }
+
+        tree
+    }
+
+    /// Keeps the tree's reservations in `reservations` rather than in the tree, so they outlive it
+    /// like its storage does. `reservations` must be where earlier trees over the same storage
+    /// kept theirs, or empty if the storage was just set up by [`Self::new`].
+    pub(crate) fn keeping_reservations_in(
+        mut self,
+        reservations: &'s mut [Option<(usize, usize)>; Tree::MAX_RESERVATIONS],
+    ) -> Self {
+        self.reservations = Kept::Borrowed(reservations);
+        self
+    }
+
+    /// Keeps the tree's counts of free blocks in `free_by_height` rather than in the tree, starting
+    /// with the counts it has now, so they outlive it like its storage does, and later trees over
+    /// the same storage can take them with [`Self::attach_counted`] rather than counting again.
+    pub(crate) fn keeping_counts_in(
+        mut self,
+        free_by_height: &'s mut [usize; Stats::MAX_HEIGHTS],
+    ) -> Self {
+        *free_by_height = *self.free_by_height;
+        self.free_by_height = Kept::Borrowed(free_by_height);
+        self
+    }
+
+    /// Creates a tree over storage written by an earlier tree, like [`Self::attach`], but with the
+    /// counts of free blocks that earlier trees kept in `free_by_height` (see
+    /// [`Self::keeping_counts_in`]), which takes constant time rather than time proportional to
+    /// `leaf_blocks`.
+    pub(crate) fn attach_counted(
+        storage: &'s mut [usize],
+        leaf_blocks: usize,
+        free_by_height: &'s mut [usize; Stats::MAX_HEIGHTS],
+    ) -> Self {
+        let mut tree = Self::with_storage(storage, leaf_blocks);
+        tree.free_by_height = Kept::Borrowed(free_by_height);
+
+        tree
+    }
+
+    /// Creates a tree over `storage` without reading it, so the tree counts no free blocks, until
+    /// the caller either writes the storage or counts them.
+    fn with_storage(storage: &'s mut [usize], leaf_blocks: usize) -> Self {
         // i have no leaf blocks and i must store state (a tree with no leaf blocks can't manage any
         // allocations)
         assert!(leaf_blocks > 0, "tree must have at least 1 leaf block");
 
-        let depth = leaf_blocks.next_power_of_two().ilog2().as_usize();
+        let depth = Self::depth_required(leaf_blocks);
         let first_leaf = (1 << depth) - 1;
 
         // we must be able to store a complete tree's worth of blocks
-        let storage = storage.view_bits_mut();
-        let bits = Self::storage_bits_required(leaf_blocks);
+        let words = Self::storage_words_required(leaf_blocks);
         assert!(
-            storage.len() >= bits,
-            "storage must be at least {bits} bits wide to store a tree with {leaf_blocks} leaf blocks"
+            storage.len() >= words,
+            "storage must be at least {words} words long to store a tree with {leaf_blocks} leaf blocks"
         );
 
-        // the storage we're provided might be wider than required
-        let storage = &mut storage[0..bits];
-
-        // initially, every block is free
-        // TODO: can we do this without inlining the encoding of BlockState::Free?
-        storage.fill(false);
-
         Self {
-            storage,
+            // the storage we're provided might be longer than required
+            storage: &mut storage[0..words],
             leaf_blocks,
             depth,
             first_leaf,
+            placement: Placement::default(),
+            reservations: Kept::Owned([None; Self::MAX_RESERVATIONS]),
+            free_by_height: Kept::Owned([0; Stats::MAX_HEIGHTS]),
+        }
+    }
+
+    /// Creates a tree over storage that already holds the state of a tree with `leaf_blocks` leaf
+    /// blocks, like one handed over from before a warm reboot, or loaded from a file, rather than
+    /// marking every block as free.
+    ///
+    /// The storage is checked first, so the tree can trust it like any storage it set up itself.
+    /// This takes time proportional to the size of the tree.
+    ///
+    /// Ranges reserved with [`Self::reserve`] aren't kept in the storage, so they look like
+    /// allocations to the new tree. Use [`Self::snapshot`] and [`Self::restore`] to keep them.
+    pub fn from_storage(
+        storage: &'s mut [usize],
+        leaf_blocks: usize,
+    ) -> Result<Self, InvalidStorageError> {
+        if storage.len() < Self::storage_words_required(leaf_blocks) {
+            return Err(InvalidStorageError::TooSmall);
+        }
+        let tree = Self::attach(storage, leaf_blocks);
+        tree.check()?;
+
+        Ok(tree)
+    }
+
+    /// Checks that the state of every block agrees with the states of its sub-blocks, and that
+    /// the blocks past the last leaf block are still reserved, like [`Self::from_storage`] does.
+    ///
+    /// Every operation keeps the tree this way, so an error means the tree has a bug (or its
+    /// storage was corrupted), which is what the fuzz targets look for (see `fuzz/`). This takes
+    /// time proportional to the size of the tree.
+    pub fn check(&self) -> Result<(), InvalidStorageError> {
+        // every non-leaf block's state follows from the states of its sub-blocks, exactly as
+        // mark_allocated and free leave them
+        for block in self.blocks().take(self.first_leaf) {
+            let (left, right) = block.subblocks();
+            let is_full = |block| {
+                matches!(
+                    self.state(block),
+                    BlockState::Allocated | BlockState::SuperblockFull
+                )
+            };
+            let both_free =
+                self.state(left) == BlockState::Free && self.state(right) == BlockState::Free;
+            let both_full = is_full(left) && is_full(right);
+            let consistent = match self.state(block) {
+                // the sub-blocks of free and allocated blocks are all free
+                BlockState::Free | BlockState::Allocated => both_free,
+                BlockState::Superblock => !both_free && !both_full,
+                BlockState::SuperblockFull => both_full,
+            };
+            if !consistent {
+                return Err(InvalidStorageError::Inconsistent(block));
+            }
+        }
+
+        // the blocks past the end must still be reserved, as the fewest blocks that cover them
+        let reserved = (1 << self.depth) - self.leaf_blocks;
+        if let Some(block) = self
+            .cover(self.leaf_blocks, reserved)
+            .find(|&block| self.state(block) != BlockState::Allocated)
+        {
+            return Err(InvalidStorageError::NotReserved(block));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the size, in bytes, of a snapshot of this tree (see [`Self::snapshot`]).
+    pub fn snapshot_len(&self) -> usize {
+        Self::SNAPSHOT_HEADER_LEN
+            + self.reservations().count() * Self::SNAPSHOT_RESERVATION_LEN
+            + Self::storage_bits_required(self.leaf_blocks).div_ceil(8)
+    }
+
+    /// Writes the state of the tree to the start of `out`, returning the size of the snapshot,
+    /// which can be restored by [`Self::restore`], even on another machine.
+    ///
+    /// A snapshot is [`Self::SNAPSHOT_MAGIC`], a version byte, the depth as a byte, the number of
+    /// reserved ranges as a byte, a zero byte, and the number of leaf blocks as a little-endian
+    /// `u64`, followed by the offset and size of each reserved range as little-endian `u64`s, then
+    /// the bytes of the tree's storage as little-endian words, up to the byte with the last bit of
+    /// the tree's state in it, whose bits after that are zero. The placement isn't included.
+    pub fn snapshot(&self, out: &mut [u8]) -> Result<usize, SnapshotTooSmallError> {
+        let len = self.snapshot_len();
+        let out = out.get_mut(..len).ok_or(SnapshotTooSmallError)?;
+        let (header, rest) = out.split_at_mut(Self::SNAPSHOT_HEADER_LEN);
+        let (reservations, bits) =
+            rest.split_at_mut(self.reservations().count() * Self::SNAPSHOT_RESERVATION_LEN);
+
+        header[..4].copy_from_slice(&Self::SNAPSHOT_MAGIC);
+        header[4] = Self::SNAPSHOT_VERSION;
+        header[5] = self.depth as u8;
+        header[6] = self.reservations().count() as u8;
+        header[7] = 0;
+        header[8..].copy_from_slice(&(self.leaf_blocks as u64).to_le_bytes());
+        let chunks = reservations.chunks_exact_mut(Self::SNAPSHOT_RESERVATION_LEN);
+        for (chunk, (offset, size)) in chunks.zip(self.reservations()) {
+            chunk[..8].copy_from_slice(&(offset as u64).to_le_bytes());
+            chunk[8..].copy_from_slice(&(size as u64).to_le_bytes());
+        }
+        let words = self.storage.iter().flat_map(|word| word.to_le_bytes());
+        for (byte, word_byte) in bits.iter_mut().zip(words) {
+            *byte = word_byte;
         }
+        // the last word might have bits past the end of the tree, which aren't part of its state
+        let padding = Self::storage_bits_required(self.leaf_blocks) % 8;
+        if padding != 0 {
+            *bits.last_mut().expect("storage to not be empty") &= (1 << padding) - 1;
+        }
+
+        Ok(len)
+    }
+
+    /// Creates a tree over `storage` with the state in a snapshot written by [`Self::snapshot`].
+    ///
+    /// The state is checked like [`Self::from_storage`] checks it, so a corrupt snapshot is an
+    /// error rather than a tree that can't be trusted.
+    pub fn restore(storage: &'s mut [usize], snapshot: &[u8]) -> Result<Self, RestoreError> {
+        let header = snapshot
+            .get(..Self::SNAPSHOT_HEADER_LEN)
+            .ok_or(RestoreError::Truncated)?;
+        if header[..4] != Self::SNAPSHOT_MAGIC || header[4] != Self::SNAPSHOT_VERSION {
+            return Err(RestoreError::NotASnapshot);
+        }
+        let depth = usize::from(header[5]);
+        let leaf_blocks = u64::from_le_bytes(header[8..].try_into().unwrap());
+        // a tree this large couldn't have been snapshotted on this machine anyway
+        let leaf_blocks = usize::try_from(leaf_blocks)
+            .ok()
+            .filter(|&leaf_blocks| leaf_blocks > 0 && leaf_blocks <= 1 << (usize::BITS - 2))
+            .ok_or(RestoreError::WrongDepth)?;
+        if Self::depth_required(leaf_blocks) != depth {
+            return Err(RestoreError::WrongDepth);
+        }
+        let reservation_count = usize::from(header[6]);
+        if reservation_count > Self::MAX_RESERVATIONS {
+            return Err(RestoreError::InvalidReservation);
+        }
+
+        let reservations_len = reservation_count * Self::SNAPSHOT_RESERVATION_LEN;
+        let reservations = snapshot[Self::SNAPSHOT_HEADER_LEN..]
+            .get(..reservations_len)
+            .ok_or(RestoreError::Truncated)?;
+        let snapshot = snapshot[Self::SNAPSHOT_HEADER_LEN + reservations_len..]
+            .get(..Self::storage_bits_required(leaf_blocks).div_ceil(8))
+            .ok_or(RestoreError::Truncated)?;
+        let words = Self::storage_words_required(leaf_blocks);
+        let storage = storage
+            .get_mut(..words)
+            .ok_or(RestoreError::InvalidStorage(InvalidStorageError::TooSmall))?;
+        for (word, bytes) in storage.iter_mut().zip(snapshot.chunks(size_of::<usize>())) {
+            let mut le_bytes = [0; size_of::<usize>()];
+            le_bytes[..bytes.len()].copy_from_slice(bytes);
+            *word = usize::from_le_bytes(le_bytes);
+        }
+
+        let mut tree =
+            Self::from_storage(storage, leaf_blocks).map_err(RestoreError::InvalidStorage)?;
+
+        // each reserved range must be allocated as the fewest blocks that cover it, like reserve
+        // leaves it
+        for (slot, chunk) in reservations
+            .chunks_exact(Self::SNAPSHOT_RESERVATION_LEN)
+            .enumerate()
+        {
+            let offset = u64::from_le_bytes(chunk[..8].try_into().unwrap());
+            let size = u64::from_le_bytes(chunk[8..].try_into().unwrap());
+            let (offset, size) = usize::try_from(offset)
+                .ok()
+                .zip(usize::try_from(size).ok())
+                .filter(|&(offset, size)| {
+                    size > 0
+                        && offset
+                            .checked_add(size)
+                            .is_some_and(|end| end <= leaf_blocks)
+                })
+                .ok_or(RestoreError::InvalidReservation)?;
+            if tree
+                .cover(offset, size)
+                .any(|block| tree.state(block) != BlockState::Allocated)
+            {
+                return Err(RestoreError::InvalidReservation);
+            }
+            tree.reservations[slot] = Some((offset, size));
+        }
+
+        Ok(tree)
+    }
+
+    /// Sets the strategy for choosing which free block satisfies an allocation.
+    pub fn with_placement(mut self, placement: Placement) -> Self {
+        self.set_placement(placement);
+        self
+    }
+
+    /// Changes the strategy for choosing which free block satisfies an allocation, which only
+    /// affects allocations made from now on.
+    pub fn set_placement(&mut self, placement: Placement) {
+        self.placement = placement;
+    }
+
+    /// Returns the strategy for choosing which free block satisfies an allocation.
+    pub fn placement(&self) -> Placement {
+        self.placement
+    }
+
+    /// Returns the number of leaf blocks in the tree, not counting any past the end (see
+    /// [`Self::new`]).
+    pub fn leaf_blocks(&self) -> usize {
+        self.leaf_blocks
     }
 
     /// Attempts to allocate `size` blocks.
@@ -89,106 +596,120 @@ impl<'s> Tree<'s> {
     /// If successful, the returned [`Allocation`] may be larger than the requested size due to
     /// rounding.
     pub fn allocate(&mut self, size: usize) -> Result<Allocation, OutOfMemoryError> {
+        self.allocate_aligned(size, 1)
+    }
+
+    /// Attempts to allocate `size` blocks at an offset that's a multiple of `align_blocks`, which
+    /// must be a power of two.
+    ///
+    /// Every allocation is already aligned to its own size, so this only constrains allocations
+    /// smaller than `align_blocks`, which are placed at the start of a larger aligned range rather
+    /// than wherever is free.
+    pub fn allocate_aligned(
+        &mut self,
+        size: usize,
+        align_blocks: usize,
+    ) -> Result<Allocation, OutOfMemoryError> {
+        assert!(
+            align_blocks.is_power_of_two(),
+            "alignment must be a power of two"
+        );
+
         // determine block height and depth for requested allocation
         let height = match size {
             0 => return Err(OutOfMemoryError),
             1 => 0,
             _ => (size - 1).ilog2() as usize + 1,
         };
-        let depth = self.depth - height;
+        // a block taller than the root is larger than the whole tree
+        let depth = self.depth.checked_sub(height).ok_or(OutOfMemoryError)?;
+
+        // if there's no free block this tall or taller, there's no point searching for one
+        if self.free_by_height[height..=self.depth]
+            .iter()
+            .all(|&count| count == 0)
+        {
+            return Err(OutOfMemoryError);
+        }
 
-        // find a free block at the requested depth
-        let block = self.preorder(|block| {
-            let at_requested_depth = block.depth() == depth;
-            match (at_requested_depth, self.state(block)) {
-                // if we're at the requested depth and have found a free block, claim it
-                (true, BlockState::Free) => Action::Yield(block),
-                // ...but, if the block isn't free (because it's either been allocated or
-                // subdivided), there's no point descending further since the block's sub-blocks
-                // will all have a higher depth (and thus smaller size) than requested.
-                (true, _) => Action::Skip,
-                // if we're not yet at the requested depth, don't descend into blocks with no
-                // reachable, free sub-blocks
-                (false, BlockState::Allocated | BlockState::SuperblockFull) => Action::Skip,
-                // ...but, descend into blocks that may have reachable, free sub-blocks.
-                (false, _) => Action::Descend,
-            }
-        });
+        // find a free block at the requested depth, and if we don't find one, we're out of memory
+        // (at the requested allocation size)
+        let block = self.find(depth, align_blocks).ok_or(OutOfMemoryError)?;
 
-        // if we didn't find a block, we're out of memory (at the requested allocation size)
-        let block = block.ok_or(OutOfMemoryError)?;
+        self.mark_allocated(block);
 
-        // mark the block as allocated
-        self.set_state(block, BlockState::Allocated);
+        Ok(Allocation {
+            offset: block.offset() << height,
+            size: 1 << height,
+        })
+    }
 
-        // we know the state of our block has changed from free to allocated.
-        //
-        // we now need to mark every superblock of our block as either a superblock or a full
-        // superblock.
-        // - a block where both sub-blocks are either full superblocks or allocated becomes a full
-        //   superblock (no new allocations can take place within the block)
-        // - otherwise, the block must have at least one superblock as a sub-block, and thus becomes
-        //   a superblock (the block cannot be allocated, but it contains sub-blocks available for
-        //   allocation)
-        //
-        // since we just allocated a block, it's not possible for any of the superblocks to become
-        // free.
-        let mut buddies = self.buddies(block);
+    /// Claims the `size` blocks starting at `offset`, which needn't be a buddy block, failing
+    /// without claiming anything if any of them is already allocated.
+    ///
+    /// The range is claimed as the fewest blocks that exactly cover it, each of which must be
+    /// freed separately, by its own offset.
+    pub fn allocate_at(&mut self, offset: usize, size: usize) -> Result<(), AlreadyAllocatedError> {
+        let end = offset.checked_add(size);
+        assert!(
+            end.is_some_and(|end| end <= self.leaf_blocks),
+            "range must be within the tree"
+        );
 
-        // mark as many blocks as full as possible
-        for (buddy, block) in &mut buddies {
-            let block_is_full = match self.state(buddy) {
-                BlockState::Allocated | BlockState::SuperblockFull => true,
-                BlockState::Free | BlockState::Superblock => false,
-            };
+        // check every block first, so we can fail without having claimed some of them
+        if !self
+            .cover(offset, size)
+            .all(|block| self.is_claimable(block))
+        {
+            return Err(AlreadyAllocatedError);
+        }
+        for block in self.cover(offset, size) {
+            self.mark_allocated(block);
+        }
 
-            if !block_is_full {
-                // since the item has been consumed from the iterator, we need to mark the block as
-                // a superblock here otherwise it will be missed by the loop below
-                self.set_state(block, BlockState::Superblock);
-                break;
-            }
+        Ok(())
+    }
 
-            self.set_state(block, BlockState::SuperblockFull);
-        }
+    /// Permanently claims the `size` blocks starting at `offset`, like memory used by firmware or
+    /// by the tree's own storage, failing without claiming anything if any of them is already
+    /// allocated or reserved.
+    ///
+    /// Reserved blocks can't be freed, and aren't allocations, so they don't appear in
+    /// [`Self::allocations`], but they do appear in [`Self::regions`], and are counted in
+    /// [`Stats::reserved_blocks`].
+    pub fn reserve(&mut self, offset: usize, size: usize) -> Result<(), ReserveError> {
+        assert!(size > 0, "size must not be zero");
+
+        let slot = self
+            .reservations
+            .iter()
+            .position(Option::is_none)
+            .ok_or(ReserveError::TooManyReservations)?;
+        self.allocate_at(offset, size)
+            .map_err(|AlreadyAllocatedError| ReserveError::AlreadyAllocated)?;
+        self.reservations[slot] = Some((offset, size));
 
-        // mark remaining blocks as superblocks
-        for (_, block) in &mut buddies {
-            self.set_state(block, BlockState::Superblock);
-        }
+        Ok(())
+    }
 
-        Ok(Allocation {
-            offset: block.offset() << height,
-            size: 1 << height,
-        })
+    /// Returns the offset and size of every range reserved with [`Self::reserve`], in the order
+    /// they were reserved.
+    pub fn reservations(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.reservations.iter().flatten().copied()
     }
 
     /// Frees a previous [`Allocation`], identified by its offset.
-    pub fn free(&mut self, offset: usize) -> Result<(), DoubleFreeError> {
-        // find the block corresponding to this allocation - the offset does not uniquely identify a
-        // block, but does uniquely identify an allocation
-        let block = self.preorder(|block| {
-            let height = self.depth - block.depth();
-            let at_correct_offset = block.offset() << height == offset;
-            match (self.state(block), at_correct_offset) {
-                // if we've found an allocated block with the correct offset, it's the block
-                // corresponding to the allocation
-                (BlockState::Allocated, true) => Action::Yield(block),
-                // ...but, if the block is allocated and has the wrong offset, there's no point
-                // searching its subblocks as they can't possibly contain our allocation.
-                (BlockState::Allocated, false) => Action::Skip,
-                // a free block has no allocated sub-blocks, so it can't possibly contain our
-                // allocation
-                (BlockState::Free, _) => Action::Skip,
-                // ...but if the block has allocated sub-blocks, we need to search them for our
-                // allocation.
-                (BlockState::Superblock | BlockState::SuperblockFull, _) => Action::Descend,
-            }
-        });
-
+    pub fn free(&mut self, offset: usize) -> Result<(), FreeError> {
         // if we couldn't find the block, we've either been passed garbage or we're experiencing a
-        // double free
-        let block = block.ok_or(DoubleFreeError)?;
+        // double free, which we can tell apart by what's at the offset
+        let block = self
+            .find_allocation(offset)
+            .ok_or_else(|| match self.region_at(offset) {
+                None => FreeError::OutOfRange,
+                Some(region) if region.state == RegionState::Free => FreeError::DoubleFree,
+                Some(region) if region.state == RegionState::Reserved => FreeError::Reserved,
+                Some(_) => FreeError::InsideAllocation,
+            })?;
 
         // mark the block as free
         self.set_state(block, BlockState::Free);
@@ -224,58 +745,629 @@ impl<'s> Tree<'s> {
         Ok(())
     }
 
-    fn preorder<T>(&self, mut visitor: impl FnMut(BlockIndex) -> Action<T>) -> Option<T> {
-        fn preorder<T>(
-            tree: &Tree,
-            block: BlockIndex,
-            visitor: &mut impl FnMut(BlockIndex) -> Action<T>,
-        ) -> Option<T> {
-            if !tree.has_block(block) {
-                return None;
-            }
+    /// Returns the number of blocks spanned by the allocation at `offset`, which may be more than
+    /// were asked for, or `None` if there's no allocation at `offset`.
+    pub fn allocation_size(&self, offset: usize) -> Option<usize> {
+        self.find_allocation(offset)
+            .map(|block| self.allocation(block).size)
+    }
 
-            let action = visitor(block);
-            match action {
-                Action::Yield(value) => Some(value),
-                Action::Skip => None,
-                Action::Descend => {
-                    let (left, right) = block.subblocks();
+    /// Returns every allocation that hasn't been freed, largest first, then in order of offset.
+    pub fn allocations(&self) -> impl Iterator<Item = Allocation> + '_ {
+        // blocks are only allocated while all of their sub-blocks are free, so no allocation can
+        // contain another, and every allocated block is an allocation in its own right
+        self.blocks()
+            .filter(|&block| self.state(block) == BlockState::Allocated && !self.is_reserved(block))
+            .map(|block| self.allocation(block))
+    }
 
-                    preorder(tree, left, visitor).or_else(|| preorder(tree, right, visitor))
+    /// Returns the regions of the tree in order of offset, covering every block that isn't past
+    /// the end (see [`Self::new`]). Each allocation is a region of its own, but adjacent free
+    /// blocks are merged into one region, even if they're not buddies, as are adjacent reserved
+    /// blocks.
+    pub fn regions(&self) -> impl Iterator<Item = Region> + '_ {
+        let mut offset = 0;
+
+        iter::from_fn(move || {
+            let mut region = self.region_at(offset)?;
+            if region.state != RegionState::Allocated {
+                while let Some(next) = self
+                    .region_at(region.offset + region.size)
+                    .filter(|next| next.state == region.state)
+                {
+                    region.size += next.size;
                 }
             }
-        }
+            offset = region.offset + region.size;
 
-        preorder(self, BlockIndex::root(), &mut visitor)
+            Some(region)
+        })
     }
 
-    fn state(&self, block: BlockIndex) -> BlockState {
-        assert!(self.has_block(block));
-
-        if block.0 < self.first_leaf {
-            let index = 2 * block.0;
-            let subdivided = self.storage[index];
-            let allocated_or_full = self.storage[index + 1];
+    /// Returns how many blocks are allocated and free, and how fragmented the free blocks are.
+    ///
+    /// This visits every allocated and free block, so it takes time proportional to the number of
+    /// allocations rather than the size of the tree.
+    pub fn stats(&self) -> Stats {
+        let mut stats = Stats {
+            total_blocks: self.leaf_blocks,
+            allocated_blocks: 0,
+            reserved_blocks: 0,
+            free_blocks: 0,
+            largest_free_run: 0,
+            free_by_height: [0; Stats::MAX_HEIGHTS],
+        };
 
-            match (subdivided, allocated_or_full) {
-                (false, false) => BlockState::Free,
-                (false, true) => BlockState::Allocated,
-                (true, false) => BlockState::Superblock,
-                (true, true) => BlockState::SuperblockFull,
+        self.preorder(|block| -> Action<()> {
+            let height = self.depth - block.depth();
+            match self.state(block) {
+                BlockState::Free => {
+                    stats.free_blocks += 1 << height;
+                    stats.free_by_height[height] += 1;
+                    Action::Skip
+                }
+                BlockState::Allocated => {
+                    if !self.is_reserved(block) {
+                        stats.allocated_blocks += 1 << height;
+                    } else if block.offset() << height < self.leaf_blocks {
+                        stats.reserved_blocks += 1 << height;
+                    }
+                    Action::Skip
+                }
+                BlockState::Superblock | BlockState::SuperblockFull => Action::Descend,
             }
-        } else {
-            let index = 2 * self.first_leaf + (block.0 - self.first_leaf);
-            let allocated = self.storage[index];
+        });
+        stats.largest_free_run = self
+            .regions()
+            .filter(|region| region.state == RegionState::Free)
+            .map(|region| region.size)
+            .max()
+            .unwrap_or(0);
+
+        stats
+    }
 
-            match allocated {
-                false => BlockState::Free,
-                true => BlockState::Allocated,
-            }
+    /// Attempts to grow the allocation at `offset` in place to at least `new_size` blocks, by
+    /// claiming the free blocks after it.
+    ///
+    /// An allocation can only grow into the buddy block it's the first half of, so this fails if
+    /// the allocation isn't aligned to the new size, or if any of the blocks after it aren't free,
+    /// leaving the allocation as it was. Allocations that are already large enough are unchanged.
+    pub fn grow(&mut self, offset: usize, new_size: usize) -> Result<Allocation, ResizeError> {
+        let block = self
+            .find_allocation(offset)
+            .ok_or(ResizeError::NotAllocated)?;
+        let height = Self::height_for(new_size);
+        if height <= self.depth - block.depth() {
+            return Ok(self.allocation(block));
+        }
+        if height > self.depth {
+            return Err(ResizeError::OutOfMemory);
         }
-    }
 
-    fn set_state(&mut self, block: BlockIndex, state: BlockState) {
-        assert!(self.has_block(block));
+        // every block on the way up to the new block must be the first half of its superblock,
+        // with a free buddy
+        let depth = self.depth - height;
+        let claimable = iter::successors(Some(block), |block| block.superblock())
+            .take_while(|block| block.depth() > depth)
+            .all(|block| {
+                let buddy = block.buddy().expect("only the root has no buddy");
+                block.offset() % 2 == 0 && self.state(buddy) == BlockState::Free
+            });
+        if !claimable {
+            return Err(ResizeError::OutOfMemory);
+        }
+
+        // free the old block and the superblocks between it and the new block, so the new block's
+        // sub-blocks are all free like any other allocated block's
+        let mut target = block;
+        while target.depth() > depth {
+            self.set_state(target, BlockState::Free);
+            target = target
+                .superblock()
+                .expect("only the root has no superblock");
+        }
+        self.mark_allocated(target);
+
+        Ok(self.allocation(target))
+    }
+
+    /// Shrinks the allocation at `offset` in place to the fewest blocks that can hold `new_size`
+    /// blocks, freeing the blocks after them. Allocations that are already small enough are
+    /// unchanged.
+    pub fn shrink(&mut self, offset: usize, new_size: usize) -> Result<Allocation, ResizeError> {
+        assert!(new_size > 0, "size must not be zero");
+
+        let block = self
+            .find_allocation(offset)
+            .ok_or(ResizeError::NotAllocated)?;
+        let height = Self::height_for(new_size);
+        if height >= self.depth - block.depth() {
+            return Ok(self.allocation(block));
+        }
+
+        // the old block's sub-blocks are all free, so marking the first of them at the new size
+        // as allocated leaves the rest free, and fixes the states of the blocks in between
+        let mut target = block;
+        while target.depth() < self.depth - height {
+            target = target.subblocks().0;
+        }
+        self.set_state(block, BlockState::Free);
+        self.mark_allocated(target);
+
+        Ok(self.allocation(target))
+    }
+
+    /// Finds the allocated block corresponding to the allocation at `offset`.
+    fn find_allocation(&self, offset: usize) -> Option<BlockIndex> {
+        // the reserved blocks look allocated, but they're not allocations
+        if offset >= self.leaf_blocks || self.is_reserved_offset(offset) {
+            return None;
+        }
+
+        // find the block corresponding to this allocation - the offset does not uniquely identify a
+        // block, but does uniquely identify an allocation
+        self.preorder(|block| {
+            let height = self.depth - block.depth();
+            let at_correct_offset = block.offset() << height == offset;
+            match (self.state(block), at_correct_offset) {
+                // if we've found an allocated block with the correct offset, it's the block
+                // corresponding to the allocation
+                (BlockState::Allocated, true) => Action::Yield(block),
+                // ...but, if the block is allocated and has the wrong offset, there's no point
+                // searching its subblocks as they can't possibly contain our allocation.
+                (BlockState::Allocated, false) => Action::Skip,
+                // a free block has no allocated sub-blocks, so it can't possibly contain our
+                // allocation
+                (BlockState::Free, _) => Action::Skip,
+                // ...but if the block has allocated sub-blocks, we need to search them for our
+                // allocation.
+                (BlockState::Superblock | BlockState::SuperblockFull, _) => Action::Descend,
+            }
+        })
+    }
+
+    /// Returns the allocation corresponding to an allocated block.
+    fn allocation(&self, block: BlockIndex) -> Allocation {
+        let height = self.depth - block.depth();
+
+        Allocation {
+            offset: block.offset() << height,
+            size: 1 << height,
+        }
+    }
+
+    /// Returns the largest free or allocated block containing the leaf block at `offset`, as a
+    /// region, or `None` if `offset` is past the end of the tree.
+    fn region_at(&self, offset: usize) -> Option<Region> {
+        // the reserved blocks are past the end of every region
+        if offset >= self.leaf_blocks {
+            return None;
+        }
+
+        // the block containing the offset at each depth is the one its bits lead to from the root
+        let mut block = BlockIndex::root();
+        loop {
+            let height = self.depth - block.depth();
+            let state = match self.state(block) {
+                BlockState::Free => RegionState::Free,
+                BlockState::Allocated if self.is_reserved(block) => RegionState::Reserved,
+                BlockState::Allocated => RegionState::Allocated,
+                BlockState::Superblock | BlockState::SuperblockFull => {
+                    let (left, right) = block.subblocks();
+                    block = if offset >> (height - 1) & 1 == 0 {
+                        left
+                    } else {
+                        right
+                    };
+                    continue;
+                }
+            };
+
+            return Some(Region {
+                offset: block.offset() << height,
+                size: 1 << height,
+                state,
+            });
+        }
+    }
+
+    /// Returns whether `block` is one of the blocks past `leaf_blocks` reserved by [`Self::new`],
+    /// or is in a range reserved with [`Self::reserve`].
+    fn is_reserved(&self, block: BlockIndex) -> bool {
+        let height = self.depth - block.depth();
+        let offset = block.offset() << height;
+
+        offset >= self.leaf_blocks || self.is_reserved_offset(offset)
+    }
+
+    /// Returns whether the leaf block at `offset` is in a range reserved with [`Self::reserve`].
+    fn is_reserved_offset(&self, offset: usize) -> bool {
+        self.reservations()
+            .any(|(start, size)| (start..start + size).contains(&offset))
+    }
+
+    /// Returns the height of the smallest block that can hold `size` blocks.
+    fn height_for(size: usize) -> usize {
+        match size {
+            0 | 1 => 0,
+            _ => (size - 1).ilog2() as usize + 1,
+        }
+    }
+
+    /// Marks a free block as allocated, along with the states of its superblocks.
+    fn mark_allocated(&mut self, block: BlockIndex) {
+        self.set_state(block, BlockState::Allocated);
+
+        // we know the state of our block has changed from free to allocated.
+        //
+        // we now need to mark every superblock of our block as either a superblock or a full
+        // superblock.
+        // - a block where both sub-blocks are either full superblocks or allocated becomes a full
+        //   superblock (no new allocations can take place within the block)
+        // - otherwise, the block must have at least one superblock as a sub-block, and thus becomes
+        //   a superblock (the block cannot be allocated, but it contains sub-blocks available for
+        //   allocation)
+        //
+        // since we just allocated a block, it's not possible for any of the superblocks to become
+        // free.
+        let mut buddies = self.buddies(block);
+
+        // mark as many blocks as full as possible
+        for (buddy, block) in &mut buddies {
+            let block_is_full = match self.state(buddy) {
+                BlockState::Allocated | BlockState::SuperblockFull => true,
+                BlockState::Free | BlockState::Superblock => false,
+            };
+
+            if !block_is_full {
+                // since the item has been consumed from the iterator, we need to mark the block as
+                // a superblock here otherwise it will be missed by the loop below
+                self.set_state(block, BlockState::Superblock);
+                break;
+            }
+
+            self.set_state(block, BlockState::SuperblockFull);
+        }
+
+        // mark remaining blocks as superblocks
+        for (_, block) in &mut buddies {
+            self.set_state(block, BlockState::Superblock);
+        }
+    }
+
+    /// Returns the fewest blocks that exactly cover the `size` blocks starting at `offset`, in
+    /// order of offset.
+    fn cover(&self, offset: usize, size: usize) -> impl Iterator<Item = BlockIndex> {
+        cover(self.depth, offset, size)
+    }
+
+    /// Returns whether `block` is free, and not within an allocated block.
+    fn is_claimable(&self, block: BlockIndex) -> bool {
+        // sub-blocks of an allocated block are still marked as free, so check its superblocks too
+        self.state(block) == BlockState::Free
+            && iter::successors(block.superblock(), |block| block.superblock())
+                .all(|superblock| self.state(superblock) != BlockState::Allocated)
+    }
+
+    /// Finds the free block at `depth` that the placement allocates, whose offset is a multiple of
+    /// `align` leaf blocks.
+    fn find(&self, depth: usize, align: usize) -> Option<BlockIndex> {
+        // find a free block to take a block at the requested depth from
+        let free = match self.placement {
+            Placement::FirstFit => self.preorder(|block| self.find_free(block, depth, align)),
+            Placement::TopDown => {
+                self.reverse_preorder(|block| self.find_free(block, depth, align))
+            }
+            Placement::BestFit => self.find_best_fit(depth, align),
+        };
+
+        Some(self.place(free?, depth, align))
+    }
+
+    /// Visitor for a preorder traversal which yields the first free block at or above `depth`
+    /// that has a sub-block at `depth` whose offset is a multiple of `align` leaf blocks.
+    ///
+    /// Only superblocks are descended into, since the sub-blocks of a free block are all free, so
+    /// every free block visited is whole, and can be split if it's above `depth`.
+    fn find_free(&self, block: BlockIndex, depth: usize, align: usize) -> Action<BlockIndex> {
+        match self.state(block) {
+            // a free block at or above the requested depth has an aligned sub-block at the
+            // requested depth if and only if it's aligned itself (see Self::place)
+            BlockState::Free if self.is_aligned(block, align) => Action::Yield(block),
+            // the sub-blocks of a block at the requested depth are smaller than requested
+            _ if block.depth() == depth => Action::Skip,
+            // don't descend into blocks with no reachable, free sub-blocks
+            BlockState::Free | BlockState::Allocated | BlockState::SuperblockFull => Action::Skip,
+            // the sub-blocks of a small enough superblock are faster to search a level at a time
+            BlockState::Superblock if depth - block.depth() <= Self::SCAN_HEIGHT => {
+                match self.scan(block, depth, align) {
+                    Some(block) => Action::Yield(block),
+                    None => Action::Skip,
+                }
+            }
+            BlockState::Superblock => Action::Descend,
+        }
+    }
+
+    /// Finds the smallest free block at or above `depth` that has a sub-block at `depth` whose
+    /// offset is a multiple of `align` leaf blocks, or the leftmost if there's more than one.
+    fn find_best_fit(&self, depth: usize, align: usize) -> Option<BlockIndex> {
+        // the deepest (and thus smallest) free block above the requested depth seen so far
+        let mut best: Option<BlockIndex> = None;
+        let mut consider = |block: BlockIndex| {
+            // a free block at the requested depth can't be beaten, so claim it
+            if block.depth() == depth {
+                return Action::Yield(block);
+            }
+            // otherwise it's a candidate, but we keep looking for smaller ones, and there's no
+            // point descending into it, since its sub-blocks can only be found by splitting it
+            if best.map_or(true, |best| block.depth() > best.depth()) {
+                best = Some(block);
+            }
+            Action::Skip
+        };
+
+        let exact = self.preorder(|block| match self.state(block) {
+            // a misaligned free block is smaller than the alignment, so none of its sub-blocks
+            // are aligned either
+            BlockState::Free if self.is_aligned(block, align) => consider(block),
+            _ if block.depth() == depth => Action::Skip,
+            BlockState::Free | BlockState::Allocated | BlockState::SuperblockFull => Action::Skip,
+            BlockState::Superblock if depth - block.depth() <= Self::SCAN_HEIGHT => {
+                match self.scan(block, depth, align) {
+                    Some(block) => consider(block),
+                    None => Action::Skip,
+                }
+            }
+            BlockState::Superblock => Action::Descend,
+        });
+
+        exact.or(best)
+    }
+
+    /// Finds the free block among the sub-blocks of `superblock` that the placement would choose
+    /// to take a block at `depth` from (see [`Self::find_free`] and [`Self::find_best_fit`]), by
+    /// scanning the storage for free blocks a level at a time, rather than visiting each block.
+    fn scan(&self, superblock: BlockIndex, depth: usize, align: usize) -> Option<BlockIndex> {
+        // levels with no free blocks anywhere in the tree can be skipped without looking
+        let levels = (superblock.depth() + 1..=depth)
+            .filter(|&level| self.free_by_height[self.depth - level] > 0);
+        let aligned = |block: &BlockIndex| self.is_aligned(*block, align);
+
+        match self.placement {
+            // the free block with the leftmost sub-block at the requested depth, of the first
+            // aligned one at each level
+            Placement::FirstFit => levels
+                .filter_map(|level| self.free_blocks(superblock, level, false).find(aligned))
+                .min_by_key(|&block| self.place(block, depth, align).0),
+            Placement::TopDown => levels
+                .filter_map(|level| self.free_blocks(superblock, level, true).find(aligned))
+                .max_by_key(|&block| self.place(block, depth, align).0),
+            // the leftmost of the deepest free blocks
+            Placement::BestFit => levels
+                .rev()
+                .find_map(|level| self.free_blocks(superblock, level, false).find(aligned)),
+        }
+    }
+
+    /// Returns the sub-block at `depth` of `free`, a free block at or above `depth` that
+    /// [`Self::find_free`] or [`Self::find_best_fit`] found, that the placement allocates.
+    ///
+    /// That's the leftmost sub-block whose offset is a multiple of `align` leaf blocks, or for
+    /// [`Placement::TopDown`], the rightmost, which is at the start of the last range of `align`
+    /// leaf blocks, unless the free block is smaller than that.
+    fn place(&self, free: BlockIndex, depth: usize, align: usize) -> BlockIndex {
+        let height = self.depth - depth;
+        let offset = match self.placement {
+            Placement::TopDown => {
+                let size = 1 << (self.depth - free.depth());
+                let end = (free.offset() + 1) * size;
+
+                (end - size.min(align.max(1 << height))) >> height
+            }
+            Placement::FirstFit | Placement::BestFit => free.offset() << (depth - free.depth()),
+        };
+
+        BlockIndex::at(depth, offset)
+    }
+
+    /// Returns the free blocks at `depth` among the sub-blocks of `superblock` whose superblocks
+    /// are split, so they can be allocated whole, in order of offset, or in reverse.
+    fn free_blocks(
+        &self,
+        superblock: BlockIndex,
+        depth: usize,
+        descending: bool,
+    ) -> impl Iterator<Item = BlockIndex> + '_ {
+        // the superblocks of those blocks are split, and one level up from them
+        let levels_down = depth - 1 - superblock.depth();
+        let offsets = superblock.offset() << levels_down..(superblock.offset() + 1) << levels_down;
+
+        self.split_blocks(depth - 1, offsets, descending)
+            .flat_map(move |superblock| {
+                let (left, right) = superblock.subblocks();
+                if descending {
+                    [right, left]
+                } else {
+                    [left, right]
+                }
+            })
+            .filter(|&block| self.state(block) == BlockState::Free)
+    }
+
+    /// Returns the blocks at `depth`, a non-leaf depth, whose offsets are in `offsets`, that are
+    /// in [`BlockState::Superblock`], in order of offset, or in reverse.
+    ///
+    /// The storage is scanned a word at a time, where the states of many blocks can be checked at
+    /// once, then each matching block is found with a single bit scan.
+    fn split_blocks(
+        &self,
+        depth: usize,
+        offsets: Range<usize>,
+        descending: bool,
+    ) -> impl Iterator<Item = BlockIndex> + '_ {
+        let first = BlockIndex::at(depth, 0).0;
+        let bits = Self::NONLEAF_BITS * (first + offsets.start)
+            ..Self::NONLEAF_BITS * (first + offsets.end);
+        let mut words = bits.start / Self::WORD_BITS..bits.end.div_ceil(Self::WORD_BITS);
+
+        iter::from_fn(move || {
+            if descending {
+                words.next_back()
+            } else {
+                words.next()
+            }
+        })
+        .flat_map(move |index| {
+            let word = self.storage[index];
+            // subdivided, but neither allocated nor full
+            let mut matches = word & !(word >> 1) & Self::SUBDIVIDED_BITS;
+            // ...and within the offsets
+            let word_start = index * Self::WORD_BITS;
+            if bits.start > word_start {
+                matches &= usize::MAX << (bits.start - word_start);
+            }
+            if bits.end < word_start + Self::WORD_BITS {
+                matches &= !(usize::MAX << (bits.end - word_start));
+            }
+
+            iter::from_fn(move || {
+                if matches == 0 {
+                    return None;
+                }
+                let bit = if descending {
+                    Self::WORD_BITS - 1 - matches.leading_zeros() as usize
+                } else {
+                    matches.trailing_zeros() as usize
+                };
+                matches &= !(1 << bit);
+
+                Some(BlockIndex((word_start + bit) / Self::NONLEAF_BITS))
+            })
+        })
+    }
+
+    /// Returns whether the offset of `block` is a multiple of `align` leaf blocks.
+    fn is_aligned(&self, block: BlockIndex, align: usize) -> bool {
+        let height = self.depth - block.depth();
+
+        (block.offset() << height) % align == 0
+    }
+
+    fn preorder<T>(&self, visitor: impl FnMut(BlockIndex) -> Action<T>) -> Option<T> {
+        self.traverse(false, visitor)
+    }
+
+    /// Like [`Self::preorder`], but visits right sub-blocks before left sub-blocks, and thus
+    /// visits the blocks at any given depth in descending order of offset.
+    fn reverse_preorder<T>(&self, visitor: impl FnMut(BlockIndex) -> Action<T>) -> Option<T> {
+        self.traverse(true, visitor)
+    }
+
+    /// Visits blocks in preorder without recursion, so it only needs constant stack space, however
+    /// deep the tree is. Blocks are numbered in level order, so moving to a sub-block, superblock
+    /// or buddy is just arithmetic on the block index.
+    fn traverse<T>(
+        &self,
+        right_first: bool,
+        mut visitor: impl FnMut(BlockIndex) -> Action<T>,
+    ) -> Option<T> {
+        // whether `block` is the sub-block visited second, after its buddy
+        let is_second = |block: BlockIndex| {
+            let (left, right) = block.superblock().map(BlockIndex::subblocks)?;
+            Some(block == if right_first { left } else { right })
+        };
+
+        let mut block = BlockIndex::root();
+        loop {
+            let descend = match visitor(block) {
+                Action::Yield(value) => return Some(value),
+                Action::Skip => false,
+                Action::Descend => true,
+            };
+
+            let (left, right) = block.subblocks();
+            let first = if right_first { right } else { left };
+            if descend && self.has_block(first) {
+                block = first;
+                continue;
+            }
+
+            // go back up past every block whose sub-blocks have all been visited, then on to the
+            // second sub-block of the first superblock that has one left to visit
+            loop {
+                match is_second(block) {
+                    // the root, so the whole tree has been visited
+                    None => return None,
+                    Some(true) => block = block.superblock().expect("block to not be the root"),
+                    Some(false) => {
+                        block = block.buddy().expect("block to not be the root");
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    fn state(&self, block: BlockIndex) -> BlockState {
+        assert!(self.has_block(block));
+
+        if block.0 < self.first_leaf {
+            let index = 2 * block.0;
+            let subdivided = self.bit(index);
+            let allocated_or_full = self.bit(index + 1);
+
+            match (subdivided, allocated_or_full) {
+                (false, false) => BlockState::Free,
+                (false, true) => BlockState::Allocated,
+                (true, false) => BlockState::Superblock,
+                (true, true) => BlockState::SuperblockFull,
+            }
+        } else {
+            let index = 2 * self.first_leaf + (block.0 - self.first_leaf);
+            let allocated = self.bit(index);
+
+            match allocated {
+                false => BlockState::Free,
+                true => BlockState::Allocated,
+            }
+        }
+    }
+
+    /// Sets the state of `block`, and updates [`Self::free_by_height`] to match.
+    fn set_state(&mut self, block: BlockIndex, state: BlockState) {
+        // whether a free block can be allocated whole depends on the state of its superblock, so
+        // this can change whether the block and its sub-blocks are counted
+        let (left, right) = block.subblocks();
+        let affected = [block, left, right];
+        for block in affected {
+            if self.is_whole_free(block) {
+                self.free_by_height[self.depth - block.depth()] -= 1;
+            }
+        }
+
+        self.write_state(block, state);
+
+        for block in affected {
+            if self.is_whole_free(block) {
+                self.free_by_height[self.depth - block.depth()] += 1;
+            }
+        }
+    }
+
+    /// Returns whether `block` is free and its superblock is split, so it can be allocated whole,
+    /// which is what [`Self::free_by_height`] counts. Blocks past the leaves aren't free.
+    fn is_whole_free(&self, block: BlockIndex) -> bool {
+        self.has_block(block)
+            && self.state(block) == BlockState::Free
+            && block.superblock().map_or(true, |superblock| {
+                self.state(superblock) == BlockState::Superblock
+            })
+    }
+
+    fn write_state(&mut self, block: BlockIndex, state: BlockState) {
+        assert!(self.has_block(block));
 
         if block.0 < self.first_leaf {
             let index = 2 * block.0;
@@ -286,8 +1378,8 @@ impl<'s> Tree<'s> {
                 BlockState::SuperblockFull => (true, true),
             };
 
-            self.storage.set(index, subdivided);
-            self.storage.set(index + 1, allocated_or_full);
+            self.set_bit(index, subdivided);
+            self.set_bit(index + 1, allocated_or_full);
         } else {
             let index = 2 * self.first_leaf + (block.0 - self.first_leaf);
             let allocated = match state {
@@ -298,7 +1390,21 @@ impl<'s> Tree<'s> {
                 }
             };
 
-            self.storage.set(index, allocated);
+            self.set_bit(index, allocated);
+        }
+    }
+
+    fn bit(&self, index: usize) -> bool {
+        self.storage[index / Self::WORD_BITS] & 1 << (index % Self::WORD_BITS) != 0
+    }
+
+    fn set_bit(&mut self, index: usize, value: bool) {
+        let word = &mut self.storage[index / Self::WORD_BITS];
+        let mask = 1 << (index % Self::WORD_BITS);
+        if value {
+            *word |= mask;
+        } else {
+            *word &= !mask;
         }
     }
 
@@ -332,6 +1438,46 @@ impl<'s> Tree<'s> {
     pub fn dot(&self) -> Dot {
         Dot(self)
     }
+
+    /// Returns a text rendering of the tree, for when Graphviz isn't around, like on a serial
+    /// console (see [`Ascii`]).
+    pub fn ascii(&self) -> Ascii<'_, 's> {
+        Ascii(self)
+    }
+
+    /// Returns the name of the state of `block`, as rendered by [`Ascii`].
+    fn state_name(&self, block: BlockIndex) -> &'static str {
+        match self.state(block) {
+            BlockState::Free => "free",
+            BlockState::Allocated if self.is_reserved(block) => "reserved",
+            BlockState::Allocated => "allocated",
+            BlockState::Superblock => "superblock",
+            BlockState::SuperblockFull => "full superblock",
+        }
+    }
+}
+
+/// Returns the fewest blocks that exactly cover the `size` blocks starting at `offset`, in order of
+/// offset, in a tree of the given depth.
+pub(crate) fn cover(depth: usize, offset: usize, size: usize) -> impl Iterator<Item = BlockIndex> {
+    let end = offset + size;
+    let mut offset = offset;
+
+    iter::from_fn(move || {
+        if offset >= end {
+            return None;
+        }
+
+        // the largest block that starts at the offset and doesn't go past the end
+        let mut height = (offset.trailing_zeros() as usize).min(depth);
+        while 1 << height > end - offset {
+            height -= 1;
+        }
+        let block = BlockIndex::at(depth - height, offset >> height);
+        offset += 1 << height;
+
+        Some(block)
+    })
 }
 
 #[derive(Debug)]
@@ -355,13 +1501,18 @@ enum BlockState {
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 #[repr(transparent)]
-pub struct BlockIndex(usize);
+pub struct BlockIndex(pub(crate) usize);
 
 impl BlockIndex {
     fn root() -> Self {
         Self(0)
     }
 
+    /// Returns the block at `depth` whose offset, in blocks of that depth, is `offset`.
+    pub(crate) fn at(depth: usize, offset: usize) -> Self {
+        Self((1 << depth) - 1 + offset)
+    }
+
     pub fn is_root(self) -> bool {
         self.0 == 0
     }
@@ -382,176 +1533,1067 @@ impl BlockIndex {
         }
     }
 
-    pub fn subblocks(self) -> (Self, Self) {
-        let left = Self(2 * self.0 + 1);
-        let right = Self(2 * self.0 + 2);
+    pub fn subblocks(self) -> (Self, Self) {
+        let left = Self(2 * self.0 + 1);
+        let right = Self(2 * self.0 + 2);
+
+        (left, right)
+    }
+
+    pub fn depth(self) -> usize {
+        (self.0 + 1).ilog2() as usize
+    }
+
+    pub fn offset(self) -> usize {
+        self.0 + 1 - (1 << self.depth())
+    }
+}
+
+#[derive(Debug)]
+pub struct Dot<'t, 's>(&'t Tree<'s>);
+
+impl fmt::Display for Dot<'_, '_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let tree = self.0;
+
+        writeln!(f, "digraph {{")?;
+        writeln!(f, "  node [style=filled, fixedsize=true];")?;
+        for block in tree.blocks() {
+            const GREEN: &str = "#9dd5c0";
+            const BLUE: &str = "#27a4dd";
+            const RED: &str = "#f1646c";
+            let (fillcolor, shape) = match tree.state(block) {
+                BlockState::Free => (GREEN, "circle"),
+                BlockState::Superblock => (BLUE, "Mcircle"),
+                BlockState::Allocated => (RED, "square"),
+                BlockState::SuperblockFull => (RED, "Msquare"),
+            };
+
+            writeln!(
+                f,
+                "  n{} [fillcolor=\"{}\", shape=\"{}\"];",
+                block.0, fillcolor, shape
+            )?;
+
+            let (left, right) = block.subblocks();
+            for child in [left, right] {
+                if tree.has_block(child) {
+                    writeln!(f, "  n{} -> n{};", block.0, child.0)?;
+                }
+            }
+        }
+        write!(f, "}}")?;
+
+        Ok(())
+    }
+}
+
+/// A text rendering of a tree.
+///
+/// By default, this is an indented tree with a line for each block, except those within free or
+/// allocated blocks, so it has about a line per allocation or free block, however large the tree:
+///
+/// ```text
+/// 0..8 superblock
+///   0..4 full superblock
+///     0..2 allocated
+///     2..4 allocated
+///   4..8 free
+/// ```
+///
+/// The alternate form (`{:#}`) has a line for each depth of the tree, with a character for each
+/// leaf block, so it's only readable for small trees: `.` for free blocks, `#` for allocated
+/// blocks, `x` for reserved blocks, `+` for superblocks, `=` for full superblocks, and spaces for
+/// blocks within free or allocated blocks:
+///
+/// ```text
+/// 0 ++++++++
+/// 1 ====....
+/// 2 ####
+/// 3
+/// ```
+#[derive(Debug)]
+pub struct Ascii<'t, 's>(&'t Tree<'s>);
+
+impl fmt::Display for Ascii<'_, '_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let tree = self.0;
+
+        if !f.alternate() {
+            let error = tree.preorder(|block| {
+                let height = tree.depth - block.depth();
+                let start = block.offset() << height;
+                let result = writeln!(
+                    f,
+                    "{:indent$}{start}..{} {}",
+                    "",
+                    start + (1 << height),
+                    tree.state_name(block),
+                    indent = 2 * block.depth()
+                );
+                match (result, tree.state(block)) {
+                    (Err(error), _) => Action::Yield(error),
+                    (Ok(()), BlockState::Superblock | BlockState::SuperblockFull) => {
+                        Action::Descend
+                    }
+                    (Ok(()), BlockState::Free | BlockState::Allocated) => Action::Skip,
+                }
+            });
+
+            return error.map_or(Ok(()), Err);
+        }
+
+        let width = tree.depth.checked_ilog10().unwrap_or(0) as usize + 1;
+        for depth in 0..=tree.depth {
+            let height = tree.depth - depth;
+            write!(f, "{depth:>width$}")?;
+
+            // spaces are only written once there's something after them, so lines don't end in
+            // spaces
+            let mut spaces = 1;
+            let first = (1 << depth) - 1;
+            for block in (first..2 * first + 1).map(BlockIndex) {
+                let hidden = iter::successors(block.superblock(), |block| block.superblock()).any(
+                    |superblock| {
+                        matches!(
+                            tree.state(superblock),
+                            BlockState::Free | BlockState::Allocated
+                        )
+                    },
+                );
+                let c = match tree.state(block) {
+                    _ if hidden => {
+                        spaces += 1 << height;
+                        continue;
+                    }
+                    BlockState::Free => '.',
+                    BlockState::Allocated if tree.is_reserved(block) => 'x',
+                    BlockState::Allocated => '#',
+                    BlockState::Superblock => '+',
+                    BlockState::SuperblockFull => '=',
+                };
+                write!(f, "{:spaces$}", "")?;
+                spaces = 0;
+                for _ in 0..1 << height {
+                    write!(f, "{c}")?;
+                }
+            }
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn storage_depth_required() {
+        macro_rules! assert_storage_bits_required {
+            ($leaf_blocks:expr, $storage_bits_required:expr) => {
+                let bits = Tree::storage_bits_required($leaf_blocks);
+                assert_eq!(
+                    bits, $storage_bits_required,
+                    "with leaf_blocks = {}",
+                    $leaf_blocks
+                );
+            };
+        }
+
+        // 0 leaf blocks:
+        // -> should panic, no test
+
+        // 1 leaf block:
+        //        1
+        for leaf_blocks in [1] {
+            assert_storage_bits_required!(leaf_blocks, 1 * Tree::LEAF_BITS);
+        }
+
+        // 2 leaf blocks:
+        //        2
+        //    1       1
+        for leaf_blocks in [2] {
+            assert_storage_bits_required!(
+                leaf_blocks,
+                1 * Tree::NONLEAF_BITS + 2 * Tree::LEAF_BITS
+            );
+        }
+
+        // 3 to 4 leaf blocks:
+        //        2
+        //    2       2
+        //  1   1   1   1
+        for leaf_blocks in [3, 4] {
+            assert_storage_bits_required!(
+                leaf_blocks,
+                3 * Tree::NONLEAF_BITS + 4 * Tree::LEAF_BITS
+            );
+        }
+
+        // 5 to 8 leaf blocks:
+        //        2
+        //    2       2
+        //  2   2   2   2
+        // 1 1 1 1 1 1 1 1
+        for leaf_blocks in [5, 6, 7, 8] {
+            assert_storage_bits_required!(
+                leaf_blocks,
+                7 * Tree::NONLEAF_BITS + 8 * Tree::LEAF_BITS
+            );
+        }
+
+        // words are bits rounded up, and depth is the number of edges from root to leaf
+        for (leaf_blocks, words, depth) in [(1, 1, 0), (2, 1, 1), (4, 1, 2), (5, 1, 3), (8, 1, 3)] {
+            assert_eq!(Tree::storage_words_required(leaf_blocks), words);
+            assert_eq!(
+                Tree::storage_bytes_required(leaf_blocks),
+                words * size_of::<usize>()
+            );
+            assert_eq!(Tree::depth_required(leaf_blocks), depth);
+        }
+        assert_eq!(
+            Tree::storage_words_required(1 << 12),
+            1536 / size_of::<usize>()
+        );
+        assert_eq!(Tree::storage_bytes_required(1 << 12), 1536);
+        assert_eq!(Tree::depth_required(1 << 12), 12);
+        assert_eq!(Tree::depth_required((1 << 12) + 1), 13);
+    }
+
+    // offsets:
+    //        0         depth = 0, height = 3
+    //    0       4     depth = 1, height = 2
+    //  0   2   4   6   depth = 2, height = 1
+    // 0 1 2 3 4 5 6 7  depth = 3, height = 0
+    //
+    // block indices:
+    //        0         depth = 0, height = 3
+    //    1       2     depth = 1, height = 2
+    //  3   4   5   6   depth = 2, height = 1
+    // 7 8 9 a b c d e  depth = 3, height = 0
+
+    #[test]
+    fn allocate() {
+        let mut storage = [0; 4];
+        let mut tree = Tree::new(&mut storage, 8);
+        assert_eq!(
+            tree.allocate(9),
+            Err(OutOfMemoryError),
+            "larger than the tree"
+        );
+
+        // block index 7
+        assert_eq!(tree.allocate(1), Ok(Allocation { offset: 0, size: 1 }));
+        eprintln!("{}", tree.dot());
+
+        // block index 8
+        assert_eq!(tree.allocate(1), Ok(Allocation { offset: 1, size: 1 }));
+        eprintln!("{}", tree.dot());
+
+        // block index 9
+        assert_eq!(tree.allocate(1), Ok(Allocation { offset: 2, size: 1 }));
+        eprintln!("{}", tree.dot());
+
+        // block index 5
+        assert_eq!(tree.allocate(2), Ok(Allocation { offset: 4, size: 2 }));
+        eprintln!("{}", tree.dot());
+
+        // block index 10
+        assert_eq!(tree.allocate(1), Ok(Allocation { offset: 3, size: 1 }));
+        eprintln!("{}", tree.dot());
+
+        // block index 13
+        assert_eq!(tree.allocate(1), Ok(Allocation { offset: 6, size: 1 }));
+        eprintln!("{}", tree.dot());
+
+        // block index 14
+        assert_eq!(tree.allocate(1), Ok(Allocation { offset: 7, size: 1 }));
+        eprintln!("{}", tree.dot());
+
+        assert_eq!(tree.allocate(1), Err(OutOfMemoryError));
+    }
+
+    #[test]
+    fn allocate_top_down() {
+        let mut storage = [0; 4];
+        let mut tree = Tree::new(&mut storage, 8).with_placement(Placement::TopDown);
+
+        // block index 14
+        assert_eq!(tree.allocate(1), Ok(Allocation { offset: 7, size: 1 }));
+        // block index 5
+        assert_eq!(tree.allocate(2), Ok(Allocation { offset: 4, size: 2 }));
+        // block index 13
+        assert_eq!(tree.allocate(1), Ok(Allocation { offset: 6, size: 1 }));
+        // block index 1
+        assert_eq!(tree.allocate(4), Ok(Allocation { offset: 0, size: 4 }));
+        eprintln!("{}", tree.dot());
+
+        assert_eq!(tree.allocate(1), Err(OutOfMemoryError));
+    }
+
+    #[test]
+    fn allocate_best_fit() {
+        let mut storage = [0; 4];
+        let mut tree = Tree::new(&mut storage, 8).with_placement(Placement::BestFit);
+
+        // block index 7
+        assert_eq!(tree.allocate(1), Ok(Allocation { offset: 0, size: 1 }));
+        // block index 8: the free leaf next to block 7 is a better fit than anything in block 2
+        assert_eq!(tree.allocate(1), Ok(Allocation { offset: 1, size: 1 }));
+        // block index 4
+        assert_eq!(tree.allocate(2), Ok(Allocation { offset: 2, size: 2 }));
+        // block index 11, splitting block 2 (the only free block left)
+        assert_eq!(tree.allocate(1), Ok(Allocation { offset: 4, size: 1 }));
+        // block index 6: block 12 is a better fit, but too small
+        assert_eq!(tree.allocate(2), Ok(Allocation { offset: 6, size: 2 }));
+        // block index 12
+        assert_eq!(tree.allocate(1), Ok(Allocation { offset: 5, size: 1 }));
+        eprintln!("{}", tree.dot());
+
+        assert_eq!(tree.allocate(1), Err(OutOfMemoryError));
+    }
+
+    #[test]
+    fn allocate_aligned() {
+        let mut storage = [0; 4];
+        let mut tree = Tree::new(&mut storage, 8);
+
+        // block index 7
+        assert_eq!(tree.allocate(1), Ok(Allocation { offset: 0, size: 1 }));
+        // block index 11: blocks 8 and 9 are free, but misaligned
+        assert_eq!(
+            tree.allocate_aligned(1, 4),
+            Ok(Allocation { offset: 4, size: 1 })
+        );
+        // block index 4: already aligned to its size, which is at least the alignment
+        assert_eq!(
+            tree.allocate_aligned(2, 2),
+            Ok(Allocation { offset: 2, size: 2 })
+        );
+        // block index 8
+        assert_eq!(tree.allocate(1), Ok(Allocation { offset: 1, size: 1 }));
+        // block index 12 is free, but nothing in the tree is 8-aligned
+        assert_eq!(tree.allocate_aligned(1, 8), Err(OutOfMemoryError));
+        // block index 13
+        assert_eq!(
+            tree.allocate_aligned(1, 2),
+            Ok(Allocation { offset: 6, size: 1 })
+        );
+        eprintln!("{}", tree.dot());
+
+        let mut storage = [0; 4];
+        let mut tree = Tree::new(&mut storage, 8).with_placement(Placement::TopDown);
+
+        // block index 14
+        assert_eq!(tree.allocate(1), Ok(Allocation { offset: 7, size: 1 }));
+        // block index 13, since block 14 is taken
+        assert_eq!(
+            tree.allocate_aligned(1, 2),
+            Ok(Allocation { offset: 6, size: 1 })
+        );
+        // block index 11: block 12 is free, but misaligned
+        assert_eq!(
+            tree.allocate_aligned(1, 4),
+            Ok(Allocation { offset: 4, size: 1 })
+        );
+
+        let mut storage = [0; 4];
+        let mut tree = Tree::new(&mut storage, 8).with_placement(Placement::BestFit);
+
+        // block index 7
+        assert_eq!(tree.allocate(1), Ok(Allocation { offset: 0, size: 1 }));
+        // block index 9, splitting block 4: block 8 is a better fit, but misaligned
+        assert_eq!(
+            tree.allocate_aligned(1, 2),
+            Ok(Allocation { offset: 2, size: 1 })
+        );
+        // block index 11, splitting block 2: block 10 is a better fit, but misaligned
+        assert_eq!(
+            tree.allocate_aligned(1, 4),
+            Ok(Allocation { offset: 4, size: 1 })
+        );
+        eprintln!("{}", tree.dot());
+    }
+
+    #[test]
+    #[should_panic(expected = "alignment must be a power of two")]
+    fn allocate_aligned_non_power_of_two() {
+        let mut storage = [0; 4];
+        let mut tree = Tree::new(&mut storage, 8);
+
+        let _ = tree.allocate_aligned(1, 3);
+    }
+
+    #[test]
+    fn allocate_at() {
+        let mut storage = [0; 4];
+        let mut tree = Tree::new(&mut storage, 8);
+
+        // blocks 8, 4 and 11
+        assert_eq!(tree.allocate_at(1, 4), Ok(()));
+        eprintln!("{}", tree.dot());
+        assert_eq!(
+            tree.allocations().collect::<Vec<_>>(),
+            [
+                Allocation { offset: 2, size: 2 },
+                Allocation { offset: 1, size: 1 },
+                Allocation { offset: 4, size: 1 },
+            ]
+        );
+
+        // block 11 overlaps, so block 12 stays free too
+        assert_eq!(tree.allocate_at(4, 2), Err(AlreadyAllocatedError));
+        assert_eq!(tree.allocate_at(5, 1), Ok(()));
+
+        // block 6 is free, but sub-blocks of an allocated block aren't
+        assert_eq!(tree.allocate(2), Ok(Allocation { offset: 6, size: 2 }));
+        assert_eq!(tree.allocate_at(7, 1), Err(AlreadyAllocatedError));
+
+        // each block of the range is freed separately
+        assert_eq!(tree.free(2), Ok(()));
+        assert_eq!(tree.allocate_at(2, 1), Ok(()));
+        assert_eq!(tree.allocate(1), Ok(Allocation { offset: 0, size: 1 }));
+        assert_eq!(tree.allocate(1), Ok(Allocation { offset: 3, size: 1 }));
+        assert_eq!(tree.allocate(1), Err(OutOfMemoryError));
+    }
+
+    #[test]
+    #[should_panic(expected = "range must be within the tree")]
+    fn allocate_at_out_of_range() {
+        let mut storage = [0; 4];
+        let mut tree = Tree::new(&mut storage, 8);
+
+        let _ = tree.allocate_at(6, 4);
+    }
+
+    #[test]
+    fn grow() {
+        let mut storage = [0; 4];
+        let mut tree = Tree::new(&mut storage, 8);
+
+        // blocks 7 and 9
+        assert_eq!(tree.allocate(1), Ok(Allocation { offset: 0, size: 1 }));
+        assert_eq!(tree.allocate_at(2, 1), Ok(()));
+
+        // block 7 to block 3, into its free buddy, block 8
+        assert_eq!(tree.grow(0, 2), Ok(Allocation { offset: 0, size: 2 }));
+        // already large enough
+        assert_eq!(tree.grow(0, 1), Ok(Allocation { offset: 0, size: 2 }));
+        // block 4 isn't free
+        assert_eq!(tree.grow(0, 4), Err(ResizeError::OutOfMemory));
+        // block 9 is within block 4, which is the second half of block 1
+        assert_eq!(tree.grow(2, 4), Err(ResizeError::OutOfMemory));
+        assert_eq!(tree.grow(1, 2), Err(ResizeError::NotAllocated));
+        assert_eq!(
+            tree.allocations().collect::<Vec<_>>(),
+            [
+                Allocation { offset: 0, size: 2 },
+                Allocation { offset: 2, size: 1 },
+            ]
+        );
+
+        // block 3 to block 0, two levels up
+        tree.free(2).unwrap();
+        assert_eq!(tree.grow(0, 5), Ok(Allocation { offset: 0, size: 8 }));
+        assert_eq!(tree.grow(0, 16), Err(ResizeError::OutOfMemory));
+        eprintln!("{}", tree.dot());
+        assert_eq!(tree.allocate(1), Err(OutOfMemoryError));
+    }
+
+    #[test]
+    fn shrink() {
+        let mut storage = [0; 4];
+        let mut tree = Tree::new(&mut storage, 8);
+
+        // block 0 to block 7, releasing blocks 8, 4 and 2
+        assert_eq!(tree.allocate(8), Ok(Allocation { offset: 0, size: 8 }));
+        assert_eq!(tree.shrink(0, 1), Ok(Allocation { offset: 0, size: 1 }));
+        // already small enough
+        assert_eq!(tree.shrink(0, 2), Ok(Allocation { offset: 0, size: 1 }));
+        assert_eq!(tree.shrink(4, 1), Err(ResizeError::NotAllocated));
+        eprintln!("{}", tree.dot());
+
+        assert_eq!(tree.allocate(4), Ok(Allocation { offset: 4, size: 4 }));
+        assert_eq!(tree.allocate(2), Ok(Allocation { offset: 2, size: 2 }));
+        assert_eq!(tree.allocate(1), Ok(Allocation { offset: 1, size: 1 }));
+        assert_eq!(tree.allocate(1), Err(OutOfMemoryError));
+
+        // freeing everything merges the blocks back together
+        for offset in [0, 1, 2, 4] {
+            tree.free(offset).unwrap();
+        }
+        assert_eq!(tree.allocate(8), Ok(Allocation { offset: 0, size: 8 }));
+    }
+
+    #[test]
+    fn allocations() {
+        let mut storage = [0; 4];
+        let mut tree = Tree::new(&mut storage, 8);
+        assert_eq!(tree.allocations().count(), 0);
+
+        for size in [1, 2, 1, 4] {
+            tree.allocate(size).unwrap();
+        }
+        tree.free(0).unwrap();
+        eprintln!("{}", tree.dot());
+
+        assert_eq!(
+            tree.allocations().collect::<Vec<_>>(),
+            [
+                Allocation { offset: 4, size: 4 },
+                Allocation { offset: 2, size: 2 },
+                Allocation { offset: 1, size: 1 },
+            ]
+        );
+
+        // freeing the rest leaves nothing behind
+        for offset in [1, 2, 4] {
+            tree.free(offset).unwrap();
+        }
+        assert_eq!(tree.allocations().count(), 0);
+    }
+
+    #[test]
+    fn allocation_size() {
+        let mut storage = [0; 4];
+        let mut tree = Tree::new(&mut storage, 8);
+
+        for size in [1, 3, 1] {
+            tree.allocate(size).unwrap();
+        }
+
+        // sizes are rounded up to a power of two
+        assert_eq!(tree.allocation_size(0), Some(1));
+        assert_eq!(tree.allocation_size(4), Some(4));
+        assert_eq!(tree.allocation_size(1), Some(1));
+
+        // offsets within an allocation, or of free blocks, aren't allocations
+        assert_eq!(tree.allocation_size(5), None);
+        assert_eq!(tree.allocation_size(2), None);
+
+        tree.free(4).unwrap();
+        assert_eq!(tree.allocation_size(4), None);
+    }
+
+    #[test]
+    fn free_invalid() {
+        let mut storage = [0; 4];
+        let mut tree = Tree::new(&mut storage, 8);
+        assert_eq!(tree.allocate(4), Ok(Allocation { offset: 0, size: 4 }));
+        assert_eq!(tree.allocate(1), Ok(Allocation { offset: 4, size: 1 }));
+
+        assert_eq!(tree.free(8), Err(FreeError::OutOfRange));
+        assert_eq!(tree.free(usize::MAX), Err(FreeError::OutOfRange));
+        assert_eq!(tree.free(5), Err(FreeError::DoubleFree));
+        assert_eq!(tree.free(2), Err(FreeError::InsideAllocation));
+
+        assert_eq!(tree.free(4), Ok(()));
+        assert_eq!(tree.free(4), Err(FreeError::DoubleFree));
+        assert_eq!(tree.free(0), Ok(()));
+        assert_eq!(tree.free(2), Err(FreeError::DoubleFree));
+    }
+
+    #[test]
+    fn regions() {
+        let mut storage = [0; 4];
+        let mut tree = Tree::new(&mut storage, 8);
+        let region = |offset, size, state| Region {
+            offset,
+            size,
+            state,
+        };
+        assert_eq!(
+            tree.regions().collect::<Vec<_>>(),
+            [region(0, 8, RegionState::Free)]
+        );
+
+        for size in [1, 1, 1, 1, 2] {
+            tree.allocate(size).unwrap();
+        }
+        tree.free(2).unwrap();
+        tree.free(3).unwrap();
+        eprintln!("{}", tree.dot());
+
+        // each allocation is its own region, even when adjacent to another
+        assert_eq!(
+            tree.regions().collect::<Vec<_>>(),
+            [
+                region(0, 1, RegionState::Allocated),
+                region(1, 1, RegionState::Allocated),
+                region(2, 2, RegionState::Free),
+                region(4, 2, RegionState::Allocated),
+                region(6, 2, RegionState::Free),
+            ]
+        );
+
+        // adjacent free blocks that aren't buddies are merged
+        tree.free(4).unwrap();
+        assert_eq!(
+            tree.regions().collect::<Vec<_>>(),
+            [
+                region(0, 1, RegionState::Allocated),
+                region(1, 1, RegionState::Allocated),
+                region(2, 6, RegionState::Free),
+            ]
+        );
+    }
+
+    #[test]
+    fn stats() {
+        let mut storage = [0; 4];
+        let mut tree = Tree::new(&mut storage, 8);
+        let mut free_by_height = [0; Stats::MAX_HEIGHTS];
+        free_by_height[3] = 1;
+        assert_eq!(
+            tree.stats(),
+            Stats {
+                total_blocks: 8,
+                allocated_blocks: 0,
+                reserved_blocks: 0,
+                free_blocks: 8,
+                largest_free_run: 8,
+                free_by_height,
+            }
+        );
+        assert_eq!(tree.stats().largest_free_block(), 8);
+        assert_eq!(tree.stats().fragmentation(), 0);
+
+        for size in [1, 1, 1, 1, 2] {
+            tree.allocate(size).unwrap();
+        }
+        tree.free(3).unwrap();
+        tree.free(4).unwrap();
+        eprintln!("{}", tree.dot());
+
+        // block 3 and blocks 4..8 are adjacent but not buddies, so no more than 4 blocks can be
+        // allocated at once, despite the run of 5 free blocks
+        let mut free_by_height = [0; Stats::MAX_HEIGHTS];
+        free_by_height[0] = 1;
+        free_by_height[2] = 1;
+        let stats = tree.stats();
+        assert_eq!(
+            stats,
+            Stats {
+                total_blocks: 8,
+                allocated_blocks: 3,
+                reserved_blocks: 0,
+                free_blocks: 5,
+                largest_free_run: 5,
+                free_by_height,
+            }
+        );
+        assert_eq!(stats.largest_free_block(), 4);
+        assert_eq!(stats.fragmentation(), 20);
+
+        for offset in [0, 1, 2] {
+            tree.free(offset).unwrap();
+        }
+        for size in [4, 4] {
+            tree.allocate(size).unwrap();
+        }
+        assert_eq!(tree.stats().largest_free_block(), 0);
+        assert_eq!(tree.stats().fragmentation(), 0);
+    }
+
+    #[test]
+    fn free_by_height() {
+        let mut storage = [0; 8];
+        let mut tree = Tree::new(&mut storage, 13);
+        let check = |tree: &Tree| assert_eq!(*tree.free_by_height, tree.stats().free_by_height);
+        check(&tree);
+
+        tree.allocate(2).unwrap();
+        tree.allocate_at(3, 5).unwrap();
+        tree.reserve(8, 1).unwrap();
+        check(&tree);
+        assert_eq!(tree.grow(0, 4), Err(ResizeError::OutOfMemory));
+        assert_eq!(tree.free(3), Ok(()));
+        assert_eq!(tree.grow(0, 4), Ok(Allocation { offset: 0, size: 4 }));
+        check(&tree);
+        assert_eq!(tree.shrink(0, 1), Ok(Allocation { offset: 0, size: 1 }));
+        check(&tree);
+
+        // the counts aren't kept in the storage, so they're counted again from it
+        let free_by_height = *tree.free_by_height;
+        let mut tree = Tree::from_storage(&mut storage, 13).unwrap();
+        assert_eq!(*tree.free_by_height, free_by_height);
+
+        // with no free block of 4 or more left, allocating 4 fails without searching, even with 7
+        // free blocks, but allocating 2 still succeeds
+        assert_eq!(tree.stats().free_blocks, 7);
+        assert_eq!(tree.free_by_height[2..], [0; Stats::MAX_HEIGHTS - 2]);
+        assert_eq!(tree.allocate(4), Err(OutOfMemoryError));
+        assert_eq!(tree.allocate(2), Ok(Allocation { offset: 2, size: 2 }));
+        check(&tree);
+    }
+
+    #[test]
+    fn non_power_of_two() {
+        let mut storage = [0; 4];
+        let mut tree = Tree::new(&mut storage, 5);
+        eprintln!("{}", tree.dot());
+
+        // blocks 5..8 are reserved, so they're not allocations, regions or free blocks
+        assert_eq!(tree.allocations().count(), 0);
+        assert_eq!(
+            tree.regions().collect::<Vec<_>>(),
+            [Region {
+                offset: 0,
+                size: 5,
+                state: RegionState::Free
+            }]
+        );
+        let stats = tree.stats();
+        assert_eq!(stats.total_blocks, 5);
+        assert_eq!(stats.allocated_blocks, 0);
+        assert_eq!(stats.free_blocks, 5);
+        assert_eq!(stats.largest_free_block(), 4);
+
+        // and allocations can't spill into them
+        assert_eq!(tree.allocate(8), Err(OutOfMemoryError));
+        assert_eq!(tree.allocate(2), Ok(Allocation { offset: 0, size: 2 }));
+        assert_eq!(tree.allocate(2), Ok(Allocation { offset: 2, size: 2 }));
+        assert_eq!(tree.allocate(2), Err(OutOfMemoryError));
+        assert_eq!(tree.allocate(1), Ok(Allocation { offset: 4, size: 1 }));
+        assert_eq!(tree.allocate(1), Err(OutOfMemoryError));
+        assert_eq!(tree.grow(4, 2), Err(ResizeError::OutOfMemory));
+
+        // and the reserved blocks can't be freed
+        assert_eq!(tree.free(5), Err(FreeError::OutOfRange));
+        assert_eq!(tree.free(6), Err(FreeError::OutOfRange));
+        assert_eq!(tree.allocation_size(6), None);
+    }
+
+    #[test]
+    fn from_storage() {
+        let mut storage = [0; 4];
+        let mut tree = Tree::new(&mut storage, 6);
+        assert_eq!(tree.allocate(2), Ok(Allocation { offset: 0, size: 2 }));
+        assert_eq!(tree.allocate(1), Ok(Allocation { offset: 2, size: 1 }));
+        assert_eq!(tree.allocate(1), Ok(Allocation { offset: 3, size: 1 }));
+        assert_eq!(tree.free(2), Ok(()));
+        let allocations = tree.allocations().collect::<Vec<_>>();
+        let stats = tree.stats();
+
+        // the tree picks up where the old one left off, reserved blocks and all
+        let mut tree = Tree::from_storage(&mut storage, 6).expect("storage should be valid");
+        assert_eq!(tree.allocations().collect::<Vec<_>>(), allocations);
+        assert_eq!(tree.stats(), stats);
+        assert_eq!(tree.allocate(2), Ok(Allocation { offset: 4, size: 2 }));
+        assert_eq!(tree.allocate(1), Ok(Allocation { offset: 2, size: 1 }));
+        assert_eq!(tree.allocate(1), Err(OutOfMemoryError));
+    }
+
+    #[test]
+    fn from_storage_invalid() {
+        let mut storage = [0; 4];
+        Tree::new(&mut storage, 6);
+        assert_eq!(
+            Tree::from_storage(&mut storage[..0], 6).map(|_| ()),
+            Err(InvalidStorageError::TooSmall)
+        );
+
+        // leaf block 5 is free, but a tree with 5 leaf blocks would have reserved it
+        assert_eq!(
+            Tree::from_storage(&mut storage, 5).map(|_| ()),
+            Err(InvalidStorageError::NotReserved(BlockIndex(12)))
+        );
+
+        // the root is a superblock, but has no allocated sub-blocks
+        let mut storage = [0; 4];
+        Tree::new(&mut storage, 8);
+        storage[0] |= 0b01;
+        assert_eq!(
+            Tree::from_storage(&mut storage, 8).map(|_| ()),
+            Err(InvalidStorageError::Inconsistent(BlockIndex(0)))
+        );
 
-        (left, right)
+        // the root is allocated, but so is leaf block 0, under free block 3
+        let mut storage = [0; 4];
+        Tree::new(&mut storage, 8);
+        storage[0] |= 0b10 | 1 << 14;
+        assert_eq!(
+            Tree::from_storage(&mut storage, 8).map(|_| ()),
+            Err(InvalidStorageError::Inconsistent(BlockIndex(3)))
+        );
     }
 
-    pub fn depth(self) -> usize {
-        (self.0 + 1).ilog2() as usize
+    #[test]
+    fn snapshot() {
+        let mut storage = [0; 4];
+        let mut tree = Tree::new(&mut storage, 6);
+        assert_eq!(tree.allocate(2), Ok(Allocation { offset: 0, size: 2 }));
+        assert_eq!(tree.allocate(1), Ok(Allocation { offset: 2, size: 1 }));
+        let allocations = tree.allocations().collect::<Vec<_>>();
+        let stats = tree.stats();
+
+        // 8 leaf blocks need 7 * 2 + 8 bits of storage, which is 3 bytes
+        assert_eq!(tree.snapshot_len(), 16 + 3);
+        assert_eq!(tree.snapshot(&mut [0; 18]), Err(SnapshotTooSmallError));
+        let mut snapshot = [0xff; 32];
+        assert_eq!(tree.snapshot(&mut snapshot), Ok(19));
+        assert_eq!(snapshot[..16], *b"BUDY\x03\x03\0\0\x06\0\0\0\0\0\0\0");
+        // the padding after the last bit is zero, and past the snapshot is left alone
+        assert_eq!(snapshot[18] & 0b1100_0000, 0);
+        assert_eq!(snapshot[19], 0xff);
+
+        // the restored tree picks up where the old one left off, in storage of its own
+        let mut storage = [usize::MAX; 1];
+        let mut tree = Tree::restore(&mut storage, &snapshot[..19]).expect("should restore");
+        assert_eq!(tree.allocations().collect::<Vec<_>>(), allocations);
+        assert_eq!(tree.stats(), stats);
+        assert_eq!(tree.allocate(1), Ok(Allocation { offset: 3, size: 1 }));
+        assert_eq!(tree.allocate(2), Ok(Allocation { offset: 4, size: 2 }));
+        assert_eq!(tree.allocate(1), Err(OutOfMemoryError));
     }
 
-    pub fn offset(self) -> usize {
-        self.0 + 1 - (1 << self.depth())
+    #[test]
+    fn snapshot_reservations() {
+        let mut storage = [0; 4];
+        let mut tree = Tree::new(&mut storage, 8);
+        assert_eq!(tree.reserve(2, 3), Ok(()));
+        let mut snapshot = [0; 16 + 16 + 3];
+        assert_eq!(tree.snapshot_len(), snapshot.len());
+        tree.snapshot(&mut snapshot).unwrap();
+        assert_eq!(snapshot[6], 1);
+        assert_eq!(snapshot[16..32], *b"\x02\0\0\0\0\0\0\0\x03\0\0\0\0\0\0\0");
+
+        // the restored tree keeps the reservation, but a tree over the same storage doesn't
+        let mut storage = [0; 4];
+        let mut tree = Tree::restore(&mut storage, &snapshot).expect("should restore");
+        assert_eq!(tree.reservations().collect::<Vec<_>>(), [(2, 3)]);
+        assert_eq!(tree.free(2), Err(FreeError::Reserved));
+        let mut tree = Tree::from_storage(&mut storage, 8).unwrap();
+        assert_eq!(tree.free(2), Ok(()));
+
+        let restore = |snapshot: &[u8]| Tree::restore(&mut [0; 4], snapshot).map(|_| ());
+        assert_eq!(restore(&snapshot[..20]), Err(RestoreError::Truncated));
+        // blocks 5..8 are free, so they can't be reserved
+        let mut bad = snapshot;
+        bad[16] = 5;
+        assert_eq!(restore(&bad), Err(RestoreError::InvalidReservation));
+        let mut bad = snapshot;
+        bad[24] = 7;
+        assert_eq!(restore(&bad), Err(RestoreError::InvalidReservation));
+        let mut bad = snapshot;
+        bad[6] = Tree::MAX_RESERVATIONS as u8 + 1;
+        assert_eq!(restore(&bad), Err(RestoreError::InvalidReservation));
     }
-}
 
-#[derive(Debug)]
-pub struct Dot<'t, 's>(&'t Tree<'s>);
+    #[test]
+    fn restore_invalid() {
+        let mut storage = [0; 4];
+        let tree = Tree::new(&mut storage, 6);
+        let mut snapshot = [0; 19];
+        tree.snapshot(&mut snapshot).unwrap();
 
-impl fmt::Display for Dot<'_, '_> {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let tree = self.0;
+        let restore = |snapshot: &[u8]| Tree::restore(&mut [0; 4], snapshot).map(|_| ());
+        assert_eq!(restore(&snapshot[..10]), Err(RestoreError::Truncated));
+        assert_eq!(restore(&snapshot[..18]), Err(RestoreError::Truncated));
+        assert_eq!(
+            Tree::restore(&mut [0; 0], &snapshot).map(|_| ()),
+            Err(RestoreError::InvalidStorage(InvalidStorageError::TooSmall))
+        );
 
-        writeln!(f, "digraph {{")?;
-        writeln!(f, "  node [style=filled, fixedsize=true];")?;
-        for block in tree.blocks() {
-            const GREEN: &str = "#9dd5c0";
-            const BLUE: &str = "#27a4dd";
-            const RED: &str = "#f1646c";
-            let (fillcolor, shape) = match tree.state(block) {
-                BlockState::Free => (GREEN, "circle"),
-                BlockState::Superblock => (BLUE, "Mcircle"),
-                BlockState::Allocated => (RED, "square"),
-                BlockState::SuperblockFull => (RED, "Msquare"),
-            };
+        let mut bad = snapshot;
+        bad[0] = b'X';
+        assert_eq!(restore(&bad), Err(RestoreError::NotASnapshot));
+        let mut bad = snapshot;
+        bad[4] = 1;
+        assert_eq!(restore(&bad), Err(RestoreError::NotASnapshot));
+
+        // 6 leaf blocks need a depth of 3
+        let mut bad = snapshot;
+        bad[5] = 2;
+        assert_eq!(restore(&bad), Err(RestoreError::WrongDepth));
+        let mut bad = snapshot;
+        bad[8..16].fill(0);
+        assert_eq!(restore(&bad), Err(RestoreError::WrongDepth));
+
+        // leaf blocks 6 and 7 are reserved as block 6, so freeing it leaves its superblock with
+        // nothing allocated under it
+        let mut bad = snapshot;
+        bad[16 + 1] &= !0b0011_0000;
+        assert_eq!(
+            restore(&bad),
+            Err(RestoreError::InvalidStorage(
+                InvalidStorageError::Inconsistent(BlockIndex(2))
+            ))
+        );
+    }
 
-            writeln!(
-                f,
-                "  n{} [fillcolor=\"{}\", shape=\"{}\"];",
-                block.0, fillcolor, shape
-            )?;
+    #[test]
+    fn ascii() {
+        let mut storage = [0; 4];
+        let mut tree = Tree::new(&mut storage, 8);
+        assert_eq!(tree.allocate(2), Ok(Allocation { offset: 0, size: 2 }));
+        assert_eq!(tree.allocate(2), Ok(Allocation { offset: 2, size: 2 }));
 
-            let (left, right) = block.subblocks();
-            for child in [left, right] {
-                if tree.has_block(child) {
-                    writeln!(f, "  n{} -> n{};", block.0, child.0)?;
-                }
-            }
-        }
-        write!(f, "}}")?;
+        assert_eq!(
+            tree.ascii().to_string(),
+            "0..8 superblock\n  0..4 full superblock\n    0..2 allocated\n    2..4 allocated\n  4..8 free\n"
+        );
+        assert_eq!(
+            format!("{:#}", tree.ascii()),
+            "0 ++++++++\n1 ====....\n2 ####\n3\n"
+        );
 
-        Ok(())
+        // reserved blocks are told apart from allocations
+        let mut storage = [0; 4];
+        let mut tree = Tree::new(&mut storage, 5);
+        assert_eq!(tree.allocate(1), Ok(Allocation { offset: 0, size: 1 }));
+        assert_eq!(
+            tree.ascii().to_string(),
+            "0..8 superblock\n  0..4 superblock\n    0..2 superblock\n      0..1 allocated\n      1..2 free\n    2..4 free\n  4..8 superblock\n    4..6 superblock\n      4..5 free\n      5..6 reserved\n    6..8 reserved\n"
+        );
+        assert_eq!(
+            format!("{:#}", tree.ascii()),
+            "0 ++++++++\n1 ++++++++\n2 ++..++xx\n3 #.  .x\n"
+        );
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
 
     #[test]
-    fn storage_depth_required() {
-        macro_rules! assert_storage_bits_required {
-            ($leaf_blocks:expr, $storage_bits_required:expr) => {
-                let bits = Tree::storage_bits_required($leaf_blocks);
-                assert_eq!(
-                    bits, $storage_bits_required,
-                    "with leaf_blocks = {}",
-                    $leaf_blocks
-                );
-            };
-        }
-
-        // 0 leaf blocks:
-        // -> should panic, no test
+    #[should_panic(expected = "range must be within the tree")]
+    fn allocate_at_reserved() {
+        let mut storage = [0; 4];
+        let mut tree = Tree::new(&mut storage, 5);
 
-        // 1 leaf block:
-        //        1
-        for leaf_blocks in [1] {
-            assert_storage_bits_required!(leaf_blocks, 1 * Tree::LEAF_BITS);
-        }
+        let _ = tree.allocate_at(4, 2);
+    }
 
-        // 2 leaf blocks:
-        //        2
-        //    1       1
-        for leaf_blocks in [2] {
-            assert_storage_bits_required!(
-                leaf_blocks,
-                1 * Tree::NONLEAF_BITS + 2 * Tree::LEAF_BITS
-            );
+    #[test]
+    fn reserve() {
+        let mut storage = [0; 4];
+        let mut tree = Tree::new(&mut storage, 8);
+        assert_eq!(tree.allocate(1), Ok(Allocation { offset: 0, size: 1 }));
+        assert_eq!(tree.reserve(0, 2), Err(ReserveError::AlreadyAllocated));
+        assert_eq!(tree.reserve(1, 4), Ok(()));
+        assert_eq!(tree.reservations().collect::<Vec<_>>(), [(1, 4)]);
+
+        // the reserved range can't be allocated or freed, and isn't an allocation, but it is a
+        // region, even though it's three blocks
+        assert_eq!(tree.allocate(2), Ok(Allocation { offset: 6, size: 2 }));
+        assert_eq!(tree.allocate(1), Ok(Allocation { offset: 5, size: 1 }));
+        assert_eq!(tree.allocate(1), Err(OutOfMemoryError));
+        for offset in [1, 2, 4] {
+            assert_eq!(tree.free(offset), Err(FreeError::Reserved));
+            assert_eq!(tree.allocation_size(offset), None);
         }
-
-        // 3 to 4 leaf blocks:
-        //        2
-        //    2       2
-        //  1   1   1   1
-        for leaf_blocks in [3, 4] {
-            assert_storage_bits_required!(
-                leaf_blocks,
-                3 * Tree::NONLEAF_BITS + 4 * Tree::LEAF_BITS
-            );
+        assert_eq!(tree.grow(0, 2), Err(ResizeError::OutOfMemory));
+        assert_eq!(
+            tree.allocations().map(|a| a.offset).collect::<Vec<_>>(),
+            [6, 0, 5]
+        );
+        assert_eq!(
+            tree.regions().collect::<Vec<_>>(),
+            [
+                Region {
+                    offset: 0,
+                    size: 1,
+                    state: RegionState::Allocated,
+                },
+                Region {
+                    offset: 1,
+                    size: 4,
+                    state: RegionState::Reserved,
+                },
+                Region {
+                    offset: 5,
+                    size: 1,
+                    state: RegionState::Allocated,
+                },
+                Region {
+                    offset: 6,
+                    size: 2,
+                    state: RegionState::Allocated,
+                },
+            ]
+        );
+        let stats = tree.stats();
+        assert_eq!(stats.allocated_blocks, 4);
+        assert_eq!(stats.reserved_blocks, 4);
+        assert_eq!(stats.free_blocks, 0);
+
+        // freeing around the reserved range leaves it alone
+        for offset in [0, 5, 6] {
+            tree.free(offset).unwrap();
         }
+        assert_eq!(tree.reserve(6, 1), Ok(()));
+        assert_eq!(tree.stats().reserved_blocks, 5);
+        assert_eq!(tree.allocate(1), Ok(Allocation { offset: 0, size: 1 }));
+        assert_eq!(tree.allocate(1), Ok(Allocation { offset: 5, size: 1 }));
+        assert_eq!(tree.allocate(1), Ok(Allocation { offset: 7, size: 1 }));
+        assert_eq!(tree.reserve(7, 1), Err(ReserveError::AlreadyAllocated));
+    }
 
-        // 5 to 8 leaf blocks:
-        //        2
-        //    2       2
-        //  2   2   2   2
-        // 1 1 1 1 1 1 1 1
-        for leaf_blocks in [5, 6, 7, 8] {
-            assert_storage_bits_required!(
-                leaf_blocks,
-                7 * Tree::NONLEAF_BITS + 8 * Tree::LEAF_BITS
-            );
+    #[test]
+    fn reserve_too_many() {
+        let mut storage = [0; 8];
+        let mut tree = Tree::new(&mut storage, 16);
+        for offset in 0..Tree::MAX_RESERVATIONS {
+            assert_eq!(tree.reserve(offset * 2, 1), Ok(()));
         }
+        assert_eq!(tree.reserve(1, 1), Err(ReserveError::TooManyReservations));
+        assert_eq!(tree.allocate(1), Ok(Allocation { offset: 1, size: 1 }));
     }
 
-    // offsets:
-    //        0         depth = 0, height = 3
-    //    0       4     depth = 1, height = 2
-    //  0   2   4   6   depth = 2, height = 1
-    // 0 1 2 3 4 5 6 7  depth = 3, height = 0
-    //
-    // block indices:
-    //        0         depth = 0, height = 3
-    //    1       2     depth = 1, height = 2
-    //  3   4   5   6   depth = 2, height = 1
-    // 7 8 9 a b c d e  depth = 3, height = 0
-
     #[test]
-    fn allocate() {
-        let mut storage = [0; 4];
-        let mut tree = Tree::new(&mut storage, 8);
+    fn placement_fragmentation() {
+        // leaves a free block of size 2 at offset 0, and a free block of size 1 at offset 5
+        fn fragment(tree: &mut Tree) {
+            for size in [2, 2, 1, 1, 2] {
+                tree.allocate(size).unwrap();
+            }
+            tree.free(5).unwrap();
+            tree.free(0).unwrap();
+        }
 
-        // block index 7
+        // first fit splits the block at offset 0, so there's no longer space for 2 blocks
+        let mut storage = [0; 4];
+        let mut tree = Tree::new(&mut storage, 8).with_placement(Placement::FirstFit);
+        fragment(&mut tree);
         assert_eq!(tree.allocate(1), Ok(Allocation { offset: 0, size: 1 }));
-        eprintln!("{}", tree.dot());
-
-        // block index 8
-        assert_eq!(tree.allocate(1), Ok(Allocation { offset: 1, size: 1 }));
-        eprintln!("{}", tree.dot());
-
-        // block index 9
-        assert_eq!(tree.allocate(1), Ok(Allocation { offset: 2, size: 1 }));
-        eprintln!("{}", tree.dot());
+        assert_eq!(tree.allocate(2), Err(OutOfMemoryError));
 
-        // block index 5
-        assert_eq!(tree.allocate(2), Ok(Allocation { offset: 4, size: 2 }));
-        eprintln!("{}", tree.dot());
+        // best fit fills the hole at offset 5 instead
+        let mut storage = [0; 4];
+        let mut tree = Tree::new(&mut storage, 8).with_placement(Placement::BestFit);
+        fragment(&mut tree);
+        assert_eq!(tree.allocate(1), Ok(Allocation { offset: 5, size: 1 }));
+        assert_eq!(tree.allocate(2), Ok(Allocation { offset: 0, size: 2 }));
+    }
 
-        // block index 10
-        assert_eq!(tree.allocate(1), Ok(Allocation { offset: 3, size: 1 }));
-        eprintln!("{}", tree.dot());
+    #[test]
+    fn preorder_descend() {
+        let mut storage = [0; 4];
+        let tree = Tree::new(&mut storage, 8);
 
-        // block index 13
-        assert_eq!(tree.allocate(1), Ok(Allocation { offset: 6, size: 1 }));
-        eprintln!("{}", tree.dot());
+        let mut preorder = Vec::with_capacity(tree.block_count());
+        let result = tree.preorder(|block| -> Action<()> {
+            preorder.push(block);
 
-        // block index 14
-        assert_eq!(tree.allocate(1), Ok(Allocation { offset: 7, size: 1 }));
-        eprintln!("{}", tree.dot());
+            Action::Descend
+        });
 
-        assert_eq!(tree.allocate(1), Err(OutOfMemoryError));
+        assert_eq!(
+            preorder,
+            [0, 1, 3, 7, 8, 4, 9, 10, 2, 5, 11, 12, 6, 13, 14]
+                .into_iter()
+                .map(BlockIndex)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(result, None);
     }
 
     #[test]
-    fn preorder_descend() {
+    fn reverse_preorder_descend() {
         let mut storage = [0; 4];
         let tree = Tree::new(&mut storage, 8);
 
         let mut preorder = Vec::with_capacity(tree.block_count());
-        let result = tree.preorder(|block| -> Action<()> {
+        let result = tree.reverse_preorder(|block| -> Action<()> {
             preorder.push(block);
 
             Action::Descend
@@ -559,7 +2601,7 @@ mod tests {
 
         assert_eq!(
             preorder,
-            [0, 1, 3, 7, 8, 4, 9, 10, 2, 5, 11, 12, 6, 13, 14]
+            [0, 2, 6, 14, 13, 5, 12, 11, 1, 4, 10, 9, 3, 8, 7]
                 .into_iter()
                 .map(BlockIndex)
                 .collect::<Vec<_>>()
@@ -567,6 +2609,28 @@ mod tests {
         assert_eq!(result, None);
     }
 
+    #[test]
+    #[cfg_attr(miri, ignore = "too slow under Miri")]
+    fn preorder_deep() {
+        // every block is visited once, all the way down to the leaves
+        let leaf_blocks = 1 << 16;
+        let mut storage = vec![0; Tree::storage_bytes_required(leaf_blocks)];
+        let tree = Tree::new(&mut storage, leaf_blocks);
+
+        let mut visited = 0;
+        let mut deepest = 0;
+        let result = tree.preorder(|block| -> Action<()> {
+            visited += 1;
+            deepest = deepest.max(block.depth());
+
+            Action::Descend
+        });
+
+        assert_eq!(visited, tree.block_count());
+        assert_eq!(deepest, 16);
+        assert_eq!(result, None);
+    }
+
     #[test]
     fn preorder_skip() {
         let mut storage = [0; 4];
@@ -715,4 +2779,283 @@ mod tests {
         assert_eq!(block.depth(), 3);
         assert_eq!(block.offset(), 7);
     }
+
+    /// Random sequences of operations on a tree, checked against [`Model`], a naive allocator that
+    /// keeps the owner of each leaf block in a list.
+    mod model {
+        use std::collections::BTreeMap;
+
+        use proptest::prelude::*;
+
+        use super::*;
+
+        #[derive(Clone, Debug)]
+        enum Op {
+            Allocate {
+                size: usize,
+                align_blocks: usize,
+            },
+            AllocateAt {
+                offset: usize,
+                size: usize,
+            },
+            /// Frees the allocation at this index in the model's list, if there is one, so most
+            /// frees are of real allocations.
+            Free {
+                index: usize,
+            },
+            /// Frees whatever is at this offset, which is usually not an allocation.
+            FreeOffset {
+                offset: usize,
+            },
+        }
+
+        /// The allocations a tree should have, and the leaf blocks they span.
+        struct Model {
+            /// Size of the allocation at each offset.
+            allocations: BTreeMap<usize, usize>,
+            /// Offset of the allocation spanning each leaf block, if any.
+            owners: Vec<Option<usize>>,
+        }
+
+        impl Model {
+            fn new(leaf_blocks: usize) -> Self {
+                Self {
+                    allocations: BTreeMap::new(),
+                    owners: vec![None; leaf_blocks],
+                }
+            }
+
+            fn is_free(&self, offset: usize, size: usize) -> bool {
+                offset + size <= self.owners.len()
+                    && self.owners[offset..offset + size]
+                        .iter()
+                        .all(Option::is_none)
+            }
+
+            /// Returns every offset where an allocation of `size` blocks, aligned to `size` and to
+            /// `align_blocks`, would fit, lowest first.
+            fn candidates(&self, size: usize, align_blocks: usize) -> Vec<usize> {
+                (0..self.owners.len())
+                    .step_by(size.max(align_blocks))
+                    .filter(|&offset| self.is_free(offset, size))
+                    .collect()
+            }
+
+            fn insert(&mut self, offset: usize, size: usize) {
+                assert!(self.is_free(offset, size), "{offset}+{size} overlaps");
+                self.allocations.insert(offset, size);
+                self.owners[offset..offset + size].fill(Some(offset));
+            }
+
+            fn remove(&mut self, offset: usize) -> Result<(), FreeError> {
+                let Some(size) = self.allocations.remove(&offset) else {
+                    return Err(match self.owners.get(offset) {
+                        None => FreeError::OutOfRange,
+                        Some(None) => FreeError::DoubleFree,
+                        Some(Some(_)) => FreeError::InsideAllocation,
+                    });
+                };
+                self.owners[offset..offset + size].fill(None);
+
+                Ok(())
+            }
+        }
+
+        fn op(leaf_blocks: usize) -> impl Strategy<Value = Op> {
+            // sizes and offsets go a little past the end, to check those fail too
+            prop_oneof![
+                4 => (0..=leaf_blocks + 2, prop::sample::select(vec![1, 1, 2, 4, 16]))
+                    .prop_map(|(size, align_blocks)| Op::Allocate { size, align_blocks }),
+                1 => (0..leaf_blocks, 1..=leaf_blocks).prop_map(move |(offset, size)| {
+                    Op::AllocateAt {
+                        offset,
+                        size: size.min(leaf_blocks - offset),
+                    }
+                }),
+                3 => any::<usize>().prop_map(|index| Op::Free { index }),
+                1 => (0..leaf_blocks + 4).prop_map(|offset| Op::FreeOffset { offset }),
+            ]
+        }
+
+        fn case() -> impl Strategy<Value = (usize, Placement, Vec<Op>)> {
+            let placement = prop::sample::select(vec![
+                Placement::FirstFit,
+                Placement::BestFit,
+                Placement::TopDown,
+            ]);
+            (1..=70usize, placement).prop_flat_map(|(leaf_blocks, placement)| {
+                let ops = prop::collection::vec(op(leaf_blocks), 1..200);
+                (Just(leaf_blocks), Just(placement), ops)
+            })
+        }
+
+        /// Finds the block that [`Tree::find`] should, by visiting every block that might be free
+        /// in preorder, like it did before it learned to scan the storage a level at a time.
+        fn find_by_preorder(tree: &Tree, depth: usize, align: usize) -> Option<BlockIndex> {
+            // the first free block at `depth` whose offset is a multiple of `align`
+            let find_free = |block: BlockIndex| match (block.depth() == depth, tree.state(block)) {
+                (true, BlockState::Free) if tree.is_aligned(block, align) => Action::Yield(block),
+                (true, _) => Action::Skip,
+                (false, BlockState::Allocated | BlockState::SuperblockFull) => Action::Skip,
+                (false, _) => Action::Descend,
+            };
+
+            match tree.placement {
+                Placement::FirstFit => tree.preorder(find_free),
+                Placement::TopDown => tree.reverse_preorder(find_free),
+                Placement::BestFit => {
+                    // the deepest aligned free block above `depth`, unless there's one at `depth`
+                    let mut best: Option<BlockIndex> = None;
+                    let exact =
+                        tree.preorder(|block| match (block.depth() == depth, tree.state(block)) {
+                            (true, BlockState::Free) if tree.is_aligned(block, align) => {
+                                Action::Yield(block)
+                            }
+                            (true, _) => Action::Skip,
+                            (false, BlockState::Free) if !tree.is_aligned(block, align) => {
+                                Action::Skip
+                            }
+                            (false, BlockState::Free) => {
+                                if best.map_or(true, |best| block.depth() > best.depth()) {
+                                    best = Some(block);
+                                }
+                                Action::Skip
+                            }
+                            (false, BlockState::Allocated | BlockState::SuperblockFull) => {
+                                Action::Skip
+                            }
+                            (false, BlockState::Superblock) => Action::Descend,
+                        });
+
+                    exact.or_else(|| {
+                        let mut block = best?;
+                        while block.depth() < depth {
+                            block = block.subblocks().0;
+                        }
+
+                        Some(block)
+                    })
+                }
+            }
+        }
+
+        proptest! {
+            /// Every placement, at every depth and alignment, finds the same block a level at a
+            /// time as it would block by block, in trees large enough to span many words.
+            #[test]
+            #[cfg_attr(miri, ignore = "too slow under Miri")]
+            fn matches_preorder(
+                leaf_blocks in 1..=1500usize,
+                ops in prop::collection::vec(
+                    (any::<bool>(), any::<prop::sample::Index>(), 0..=5u32),
+                    1..300,
+                ),
+            ) {
+                let mut storage = vec![0; Tree::storage_words_required(leaf_blocks)];
+                let mut tree = Tree::new(&mut storage, leaf_blocks);
+                for (allocate, index, height) in ops {
+                    if allocate {
+                        // ranges over allocations just fail
+                        let offset = index.index(leaf_blocks);
+                        let size = (1 << height).min(leaf_blocks - offset);
+                        let _ = tree.allocate_at(offset, size);
+                    } else {
+                        let offsets = tree.allocations().map(|a| a.offset).collect::<Vec<_>>();
+                        if !offsets.is_empty() {
+                            tree.free(offsets[index.index(offsets.len())]).unwrap();
+                        }
+                    }
+                }
+
+                for placement in [Placement::FirstFit, Placement::TopDown, Placement::BestFit] {
+                    tree.set_placement(placement);
+                    for depth in 0..=tree.depth {
+                        for align in (0..=tree.depth).map(|height| 1 << height) {
+                            prop_assert_eq!(
+                                tree.find(depth, align),
+                                find_by_preorder(&tree, depth, align),
+                                "{:?} at depth {} aligned to {}", placement, depth, align
+                            );
+                        }
+                    }
+                }
+            }
+
+            #[test]
+            fn matches_model((leaf_blocks, placement, ops) in case()) {
+                let mut storage = vec![0; Tree::storage_bytes_required(leaf_blocks)];
+                let mut tree = Tree::new(&mut storage, leaf_blocks).with_placement(placement);
+                let mut model = Model::new(leaf_blocks);
+
+                for op in ops {
+                    match op {
+                        Op::Allocate { size, align_blocks } => {
+                            let rounded = size.next_power_of_two();
+                            let candidates = model.candidates(rounded, align_blocks);
+                            let result = tree.allocate_aligned(size, align_blocks);
+                            let Ok(Allocation { offset, size: allocated }) = result else {
+                                prop_assert!(
+                                    size == 0 || candidates.is_empty(),
+                                    "{op:?} failed, but could have fit at {candidates:?}"
+                                );
+                                continue;
+                            };
+                            prop_assert_eq!(allocated, rounded, "{:?} has the wrong size", op);
+                            prop_assert!(
+                                candidates.contains(&offset),
+                                "{op:?} allocated at {offset}, not one of {candidates:?}"
+                            );
+                            match placement {
+                                Placement::FirstFit => prop_assert_eq!(Some(&offset), candidates.first()),
+                                Placement::TopDown => prop_assert_eq!(Some(&offset), candidates.last()),
+                                Placement::BestFit => {}
+                            }
+                            model.insert(offset, allocated);
+                        }
+                        Op::AllocateAt { offset, size } => {
+                            let free = model.is_free(offset, size);
+                            prop_assert_eq!(tree.allocate_at(offset, size).is_ok(), free, "{:?}", op);
+                            if free {
+                                // the range is claimed as the fewest aligned blocks that cover it
+                                let mut start = offset;
+                                while start < offset + size {
+                                    let mut block = 1 << start.trailing_zeros().min(usize::BITS - 1);
+                                    while start + block > offset + size {
+                                        block /= 2;
+                                    }
+                                    model.insert(start, block);
+                                    start += block;
+                                }
+                            }
+                        }
+                        Op::Free { index } => {
+                            if model.allocations.is_empty() {
+                                continue;
+                            }
+                            let index = index % model.allocations.len();
+                            let offset = *model.allocations.keys().nth(index).unwrap();
+                            prop_assert_eq!(tree.free(offset), model.remove(offset), "{:?}", op);
+                        }
+                        Op::FreeOffset { offset } => {
+                            prop_assert_eq!(tree.free(offset), model.remove(offset), "{:?}", op);
+                        }
+                    }
+
+                    let mut allocations = tree
+                        .allocations()
+                        .map(|Allocation { offset, size }| (offset, size))
+                        .collect::<Vec<_>>();
+                    allocations.sort();
+                    let expected = model.allocations.iter().map(|(&offset, &size)| (offset, size));
+                    prop_assert!(allocations.iter().copied().eq(expected), "after {:?}", op);
+
+                    let allocated = model.owners.iter().filter(|owner| owner.is_some()).count();
+                    prop_assert_eq!(tree.stats().allocated_blocks, allocated);
+                    prop_assert_eq!(tree.stats().free_blocks, leaf_blocks - allocated);
+                    prop_assert_eq!(*tree.free_by_height, tree.stats().free_by_height);
+                }
+            }
+        }
+    }
 }
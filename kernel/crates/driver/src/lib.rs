@@ -0,0 +1,205 @@
+//! The interface between the kernel and optional drivers.
+//!
+//! Optional drivers live in kernel/drivers, each in a crate of its own, and are only linked into
+//! the kernel when its `driver-<name>` feature is enabled (see `cargo xtask drivers`). They can't
+//! see the kernel's internals, so they register with [`initcall!`] from this crate instead, and
+//! report errors with [`Error`], which the kernel converts to its own error type.
+//!
+//! Drivers for devices the kernel can use beyond probing them register them with the kernel here
+//! too, like [`register_alarm`], and any driver can register hooks to quiesce and resume its
+//! device around a system suspend with [`register_suspend`].
+//!
+//! Each driver's log target is the name of its crate, which is the default target for `log`
+//! macros in the crate root, so the kernel can filter a driver's logs by that name.
+#![cfg_attr(not(test), no_std)]
+
+use core::fmt;
+
+use fdt::Fdt;
+
+/// A function that initialises an optional driver, given the FDT passed to the kernel.
+pub type InitFn = fn(&Fdt) -> Result<(), Error>;
+
+/// An optional driver's initcall, as placed in a linker section by [`initcall!`].
+pub struct Initcall {
+    pub name: &'static str,
+    /// Log target of the driver, which is the name of its crate.
+    pub target: &'static str,
+    pub function: InitFn,
+}
+
+/// Registers a function to be run at boot after the kernel's own driver initcalls.
+///
+/// The function must be an [`InitFn`].
+#[macro_export]
+macro_rules! initcall {
+    ($function:path) => {
+        const _: () = {
+            #[used]
+            #[link_section = ".initcall.module"]
+            static INITCALL: $crate::Initcall = $crate::Initcall {
+                name: concat!(module_path!(), "::", stringify!($function)),
+                target: env!("CARGO_CRATE_NAME"),
+                function: $function,
+            };
+        };
+    };
+}
+
+/// An error from probing an optional driver.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Error {
+    /// The devicetree has no node with the given compatible string.
+    DeviceNotFound { compatible: &'static str },
+    /// A devicetree node lacks a property needed to probe the device.
+    MissingProperty {
+        compatible: &'static str,
+        property: &'static str,
+    },
+    /// Another driver has already registered the given kind of device.
+    AlreadyRegistered { what: &'static str },
+    /// Every slot for the given kind of registration is in use.
+    TooManyRegistered { what: &'static str, max: usize },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DeviceNotFound { compatible } => {
+                write!(f, "no device compatible with {compatible:?}")
+            }
+            Self::MissingProperty {
+                compatible,
+                property,
+            } => write!(
+                f,
+                "device compatible with {compatible:?} has no {property:?}"
+            ),
+            Self::AlreadyRegistered { what } => write!(f, "another {what} is already registered"),
+            Self::TooManyRegistered { what, max } => write!(f, "all {max} {what} slots are in use"),
+        }
+    }
+}
+
+/// A device that can raise an interrupt at a wall-clock time, like an RTC alarm, which the kernel
+/// can use as a secondary clock event source for coarse wakeups.
+///
+/// The kernel only calls these with interrupts masked, so they needn't be reentrant.
+pub trait Alarm: Sync {
+    /// Returns the current time, in seconds since the Unix epoch.
+    fn now(&self) -> u64;
+    /// Arms the alarm to raise its interrupt at `deadline`, in seconds since the Unix epoch,
+    /// replacing any deadline it was already armed with.
+    fn set(&self, deadline: u64);
+    /// Disarms the alarm, and clears its interrupt if it was raised.
+    fn clear(&self);
+}
+
+/// An [`Alarm`], and the interrupt it raises.
+#[derive(Clone, Copy)]
+pub struct AlarmSource {
+    /// Name of the driver, for logging.
+    pub name: &'static str,
+    /// The interrupt specifier from the devicetree, with three cells (see [`find_interrupt`]).
+    pub interrupt: [u32; 3],
+    pub alarm: &'static dyn Alarm,
+}
+
+static mut ALARM: Option<AlarmSource> = None;
+
+/// Registers an alarm with the kernel, which can only use one.
+///
+/// Must only be called from an initcall.
+pub fn register_alarm(source: AlarmSource) -> Result<(), Error> {
+    // SAFETY: initcalls run one at a time during boot, and the kernel only reads the alarm after
+    // they've run.
+    let alarm = unsafe { &mut ALARM };
+    if alarm.is_some() {
+        return Err(Error::AlreadyRegistered { what: "alarm" });
+    }
+    *alarm = Some(source);
+
+    Ok(())
+}
+
+/// Returns the alarm registered with [`register_alarm`], if any.
+pub fn alarm() -> Option<AlarmSource> {
+    // SAFETY: see register_alarm.
+    unsafe { ALARM }
+}
+
+/// Maximum number of drivers with suspend hooks.
+pub const MAX_SUSPEND_HOOKS: usize = 8;
+
+/// Hooks run around a system suspend, when only wakeup interrupts are enabled and the CPU waits
+/// for one of them.
+///
+/// They run with interrupts masked, so they can't sleep or take interrupts, but they may poll.
+#[derive(Clone, Copy)]
+pub struct SuspendHooks {
+    /// Name of the driver, for logging.
+    pub name: &'static str,
+    /// Quiesces the device, like by draining its FIFOs or stopping its timers.
+    pub suspend: fn(),
+    /// Undoes whatever `suspend` did.
+    pub resume: fn(),
+}
+
+static mut SUSPEND_HOOKS: [Option<SuspendHooks>; MAX_SUSPEND_HOOKS] = [None; MAX_SUSPEND_HOOKS];
+
+/// Registers hooks to run around a system suspend. Suspend hooks run in the order they were
+/// registered, and resume hooks in the reverse order.
+///
+/// Must only be called from an initcall.
+pub fn register_suspend(hooks: SuspendHooks) -> Result<(), Error> {
+    // SAFETY: see register_alarm.
+    let slot = unsafe { SUSPEND_HOOKS.iter_mut() }
+        .find(|slot| slot.is_none())
+        .ok_or(Error::TooManyRegistered {
+            what: "suspend hook",
+            max: MAX_SUSPEND_HOOKS,
+        })?;
+    *slot = Some(hooks);
+
+    Ok(())
+}
+
+/// Returns the hooks registered with [`register_suspend`], in the order they were registered.
+pub fn suspend_hooks() -> impl DoubleEndedIterator<Item = SuspendHooks> {
+    // SAFETY: see register_alarm.
+    unsafe { SUSPEND_HOOKS }.into_iter().flatten()
+}
+
+/// Returns the base address of the first region in the `reg` property of the first node
+/// compatible with `compatible`.
+pub fn find_reg(fdt: &Fdt, compatible: &'static str) -> Result<*const u8, Error> {
+    let node = fdt
+        .find_compatible(&[compatible])
+        .ok_or(Error::DeviceNotFound { compatible })?;
+
+    node.reg()
+        .and_then(|mut reg| reg.next())
+        .map(|region| region.starting_address)
+        .ok_or(Error::MissingProperty {
+            compatible,
+            property: "reg",
+        })
+}
+
+/// Returns the first interrupt specifier in the `interrupts` property of the first node compatible
+/// with `compatible`, which must have three cells, as for the GIC.
+pub fn find_interrupt(fdt: &Fdt, compatible: &'static str) -> Result<[u32; 3], Error> {
+    let node = fdt
+        .find_compatible(&[compatible])
+        .ok_or(Error::DeviceNotFound { compatible })?;
+    let missing = Error::MissingProperty {
+        compatible,
+        property: "interrupts",
+    };
+
+    let cells = node.property("interrupts").ok_or(missing)?.value;
+    let cells = cells.get(..12).ok_or(missing)?;
+    let cell = |i: usize| u32::from_be_bytes(cells[i * 4..][..4].try_into().unwrap());
+
+    Ok([cell(0), cell(1), cell(2)])
+}
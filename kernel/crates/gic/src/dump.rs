@@ -0,0 +1,289 @@
+//! A table of the state of each interrupt, as printed by the `gic` console command, and a Graphviz
+//! rendering of it.
+//!
+//! The kernel prints the table with [`TABLE_HEADER`], [`PriorityMask`] and [`InterruptState`], and
+//! the host reads it back from a copy of the console output with [`parse`], skipping any other
+//! lines, then renders it with [`Dot`] (see `cargo xtask gic`).
+use core::fmt;
+use core::str::FromStr;
+
+/// Header of the table, lined up with the columns of [`InterruptState`].
+pub const TABLE_HEADER: &str =
+    "  id type enabled pending active priority targets trigger      count handler";
+
+/// Kind of an interrupt, which follows from its ID.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    /// Software generated interrupt, IDs 0 to 15.
+    Sgi,
+    /// Private peripheral interrupt, IDs 16 to 31, which each CPU has its own of.
+    Ppi,
+    /// Shared peripheral interrupt, IDs 32 and up.
+    Spi,
+}
+
+impl Kind {
+    pub fn of(interrupt_id: usize) -> Self {
+        match interrupt_id {
+            0..=15 => Self::Sgi,
+            16..=31 => Self::Ppi,
+            _ => Self::Spi,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Sgi => "SGI",
+            Self::Ppi => "PPI",
+            Self::Spi => "SPI",
+        }
+    }
+}
+
+/// State of an interrupt in the distributor, and how many times it's been handled, as one row of
+/// the table.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InterruptState<'h> {
+    pub interrupt_id: usize,
+    pub enabled: bool,
+    pub pending: bool,
+    pub active: bool,
+    /// Priority, where lower values are higher priorities.
+    pub priority: u8,
+    /// CPUs the interrupt is forwarded to, one bit per CPU interface.
+    pub targets: u8,
+    pub edge_triggered: bool,
+    /// Number of times the interrupt has been acknowledged.
+    pub count: u64,
+    /// What handles the interrupt, which is a single word, or `-` if nothing does.
+    pub handler: &'h str,
+}
+
+impl InterruptState<'_> {
+    /// Returns whether the interrupt can be signalled to a CPU at all, given the CPU interface's
+    /// priority mask.
+    pub fn can_be_signalled(&self, mask: PriorityMask) -> bool {
+        self.enabled && self.targets != 0 && self.priority < mask.0
+    }
+}
+
+impl fmt::Display for InterruptState<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let yes_no = |value| if value { "yes" } else { "no" };
+        let trigger = if self.edge_triggered { "edge" } else { "level" };
+        write!(
+            f,
+            "{:>4} {:<4} {:<7} {:<7} {:<6} {:#04x}     {:#04x}    {:<7} {:>10} {}",
+            self.interrupt_id,
+            Kind::of(self.interrupt_id).name(),
+            yes_no(self.enabled),
+            yes_no(self.pending),
+            yes_no(self.active),
+            self.priority,
+            self.targets,
+            trigger,
+            self.count,
+            self.handler,
+        )
+    }
+}
+
+/// Priority mask of the CPU interface (GICC_PMR), which only signals interrupts with a higher
+/// priority (a lower value) than this.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PriorityMask(pub u8);
+
+impl fmt::Display for PriorityMask {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "priority mask {:#04x}", self.0)
+    }
+}
+
+/// A line of the table, or of what's printed with it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Line<'h> {
+    PriorityMask(PriorityMask),
+    Interrupt(InterruptState<'h>),
+}
+
+/// Parses a line of the table, returning `None` if it isn't one, like the header, a log message or
+/// a prompt.
+pub fn parse(line: &str) -> Option<Line<'_>> {
+    let line = line.trim();
+    if let Some(mask) = line.strip_prefix("priority mask ") {
+        return Some(Line::PriorityMask(PriorityMask(hex(mask)?)));
+    }
+
+    let mut fields = line.split_whitespace();
+    let mut next = || fields.next();
+    let interrupt_id = usize::from_str(next()?).ok()?;
+    if next()? != Kind::of(interrupt_id).name() {
+        return None;
+    }
+    let mut yes_no = || match next()? {
+        "yes" => Some(true),
+        "no" => Some(false),
+        _ => None,
+    };
+    let (enabled, pending, active) = (yes_no()?, yes_no()?, yes_no()?);
+    let (priority, targets) = (hex(next()?)?, hex(next()?)?);
+    let edge_triggered = match next()? {
+        "edge" => true,
+        "level" => false,
+        _ => return None,
+    };
+    let count = u64::from_str(next()?).ok()?;
+    let handler = next()?;
+    if next().is_some() {
+        return None;
+    }
+
+    Some(Line::Interrupt(InterruptState {
+        interrupt_id,
+        enabled,
+        pending,
+        active,
+        priority,
+        targets,
+        edge_triggered,
+        count,
+        handler,
+    }))
+}
+
+/// Parses a byte written as `0x` and two hex digits.
+fn hex(text: &str) -> Option<u8> {
+    u8::from_str_radix(text.strip_prefix("0x")?, 16).ok()
+}
+
+/// A Graphviz rendering of the table, where each interrupt is a node with an edge to each CPU it's
+/// forwarded to.
+///
+/// Nodes are coloured by state: red if active, orange if pending, green if enabled, and grey if
+/// disabled. An enabled interrupt that can't be signalled, because it has no targets or is masked
+/// by the priority mask, has a thick red outline.
+pub struct Dot<'t, 'h> {
+    pub interrupts: &'t [InterruptState<'h>],
+    pub priority_mask: PriorityMask,
+}
+
+impl fmt::Display for Dot<'_, '_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const RED: &str = "#f1646c";
+        const ORANGE: &str = "#f5b66b";
+        const GREEN: &str = "#9dd5c0";
+        const GREY: &str = "#d3d3d3";
+
+        writeln!(f, "digraph {{")?;
+        writeln!(f, "  rankdir=LR;")?;
+        writeln!(f, "  node [style=filled, shape=box];")?;
+
+        let targets = self
+            .interrupts
+            .iter()
+            .fold(0u8, |targets, interrupt| targets | interrupt.targets);
+        for cpu in (0..8).filter(|cpu| targets & 1 << cpu != 0) {
+            writeln!(
+                f,
+                "  cpu{cpu} [label=\"CPU {cpu}\\n{}\", shape=\"doubleoctagon\"];",
+                self.priority_mask
+            )?;
+        }
+
+        for interrupt in self.interrupts {
+            let id = interrupt.interrupt_id;
+            let fillcolor = match interrupt {
+                InterruptState { active: true, .. } => RED,
+                InterruptState { pending: true, .. } => ORANGE,
+                InterruptState { enabled: true, .. } => GREEN,
+                _ => GREY,
+            };
+            let outline = if interrupt.enabled && !interrupt.can_be_signalled(self.priority_mask) {
+                ", color=\"#f1646c\", penwidth=3"
+            } else {
+                ""
+            };
+            writeln!(
+                f,
+                "  i{id} [label=\"{id} {}\\n{}\\npriority {:#04x}, {}\\nhandled {} times\", fillcolor=\"{fillcolor}\"{outline}];",
+                Kind::of(id).name(),
+                interrupt.handler,
+                interrupt.priority,
+                if interrupt.edge_triggered { "edge" } else { "level" },
+                interrupt.count,
+            )?;
+
+            let style = if interrupt.enabled { "solid" } else { "dashed" };
+            for cpu in (0..8).filter(|cpu| interrupt.targets & 1 << cpu != 0) {
+                writeln!(f, "  i{id} -> cpu{cpu} [style={style}];")?;
+            }
+        }
+        write!(f, "}}")?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMER: InterruptState = InterruptState {
+        interrupt_id: 30,
+        enabled: true,
+        pending: false,
+        active: true,
+        priority: 0xa0,
+        targets: 0x01,
+        edge_triggered: false,
+        count: 1234,
+        handler: "timer",
+    };
+
+    #[test]
+    fn round_trip() {
+        let row = TIMER.to_string();
+        assert_eq!(
+            row,
+            "  30 PPI  yes     no      yes    0xa0     0x01    level         1234 timer"
+        );
+        assert_eq!(parse(&row), Some(Line::Interrupt(TIMER)));
+
+        let mask = PriorityMask(0xf0);
+        assert_eq!(parse(&mask.to_string()), Some(Line::PriorityMask(mask)));
+    }
+
+    #[test]
+    fn other_lines() {
+        for line in [
+            TABLE_HEADER,
+            "🐶 gic",
+            "[INFO] 30 PPI yes no yes 0xa0 0x01 level 1234 timer",
+            "  30 SPI  yes     no      yes    0xa0     0x01    level         1234 timer",
+            "  30 PPI  yes     no      yes    0xa0     0x01    level         1234 timer extra",
+            "",
+        ] {
+            assert_eq!(parse(line), None, "{line:?}");
+        }
+    }
+
+    #[test]
+    fn dot() {
+        let masked = InterruptState {
+            interrupt_id: 33,
+            priority: 0xf0,
+            active: false,
+            handler: "-",
+            ..TIMER
+        };
+        let dot = Dot {
+            interrupts: &[TIMER, masked],
+            priority_mask: PriorityMask(0xf0),
+        }
+        .to_string();
+        assert!(dot.contains("cpu0 [label=\"CPU 0\\npriority mask 0xf0\""));
+        assert!(dot.contains("i30 -> cpu0 [style=solid];"));
+        assert!(!dot.contains("i30 [label=\"30 PPI\\ntimer\\npriority 0xa0, level\\nhandled 1234 times\", fillcolor=\"#f1646c\", color"));
+        assert!(dot.contains("i33 [label=\"33 SPI\\n-\\npriority 0xf0, level\\nhandled 1234 times\", fillcolor=\"#9dd5c0\", color=\"#f1646c\", penwidth=3];"));
+    }
+}
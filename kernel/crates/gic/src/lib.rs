@@ -0,0 +1,156 @@
+//! Layouts of the GICv2 distributor's register arrays, which pack a field for each interrupt into
+//! consecutive 32-bit registers, so drivers don't have to work out where each field is by hand.
+//! The [`dump`] module has a table of each interrupt's state, for debugging.
+//!
+//! IHI 0048B.b § 4.3 (Distributor register descriptions)
+#![cfg_attr(not(test), no_std)]
+
+use core::ops::RangeInclusive;
+
+pub mod dump;
+
+/// Number of interrupt IDs with fields in the register arrays. IDs 1020 to 1023 are special, and
+/// have no fields.
+pub const INTERRUPTS: usize = 1020;
+
+/// Where the field for an interrupt is, in a register array with some [`Layout`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Field {
+    /// Index of the register in the array, like the `n` in GICD_IPRIORITYR<n>.
+    pub index: usize,
+    /// Offset of the field's LSB in the register.
+    pub offset: usize,
+    /// Number of bits in the field.
+    pub width: usize,
+}
+
+impl Field {
+    /// Returns the bits of the register occupied by the field, as used by register readers and
+    /// writers.
+    pub fn bits(&self) -> RangeInclusive<usize> {
+        self.offset..=self.offset + self.width - 1
+    }
+}
+
+/// How a register array packs a field for each interrupt into 32-bit registers, starting from the
+/// LSB of the first register.
+pub trait Layout {
+    /// Number of bits in each interrupt's field.
+    const BITS: usize;
+
+    /// Number of fields in each register.
+    const FIELDS_PER_REGISTER: usize = 32 / Self::BITS;
+
+    /// Returns the field for an interrupt ID, unless it's one of the special IDs.
+    fn field(interrupt_id: usize) -> Option<Field> {
+        if interrupt_id >= INTERRUPTS {
+            return None;
+        }
+
+        Some(Field {
+            index: interrupt_id / Self::FIELDS_PER_REGISTER,
+            offset: interrupt_id % Self::FIELDS_PER_REGISTER * Self::BITS,
+            width: Self::BITS,
+        })
+    }
+}
+
+/// One bit per interrupt: GICD_IGROUPR<n>, GICD_I{S,C}ENABLER<n>, GICD_I{S,C}PENDR<n> and
+/// GICD_I{S,C}ACTIVER<n>.
+pub struct Bitmap;
+
+/// Eight bits per interrupt, for its priority: GICD_IPRIORITYR<n>.
+pub struct Priority;
+
+/// Eight bits per interrupt, one for each CPU it's forwarded to: GICD_ITARGETSR<n>.
+pub struct Targets;
+
+/// Two bits per interrupt, for whether it's edge-triggered or level-sensitive: GICD_ICFGR<n>.
+pub struct Config;
+
+impl Layout for Bitmap {
+    const BITS: usize = 1;
+}
+
+impl Layout for Priority {
+    const BITS: usize = 8;
+}
+
+impl Layout for Targets {
+    const BITS: usize = 8;
+}
+
+impl Layout for Config {
+    const BITS: usize = 2;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    fn field(index: usize, offset: usize, width: usize) -> Option<Field> {
+        Some(Field {
+            index,
+            offset,
+            width,
+        })
+    }
+
+    #[test]
+    fn bitmap() {
+        assert_eq!(Bitmap::field(0), field(0, 0, 1));
+        assert_eq!(Bitmap::field(31), field(0, 31, 1));
+        assert_eq!(Bitmap::field(32), field(1, 0, 1));
+        assert_eq!(Bitmap::field(1019), field(31, 27, 1));
+    }
+
+    #[test]
+    fn priority_and_targets() {
+        assert_eq!(Priority::field(0), field(0, 0, 8));
+        assert_eq!(Priority::field(5), field(1, 8, 8));
+        assert_eq!(Priority::field(30), field(7, 16, 8));
+        assert_eq!(Targets::field(1019), field(254, 24, 8));
+        assert_eq!(Priority::field(5).unwrap().bits(), 8..=15);
+    }
+
+    #[test]
+    fn config() {
+        assert_eq!(Config::field(0), field(0, 0, 2));
+        assert_eq!(Config::field(17), field(1, 2, 2));
+        assert_eq!(Config::field(15), field(0, 30, 2));
+        assert_eq!(Config::field(17).unwrap().bits(), 2..=3);
+    }
+
+    #[test]
+    fn special_interrupts() {
+        for interrupt_id in INTERRUPTS..1024 {
+            assert_eq!(Bitmap::field(interrupt_id), None);
+            assert_eq!(Priority::field(interrupt_id), None);
+            assert_eq!(Config::field(interrupt_id), None);
+        }
+    }
+
+    /// Every interrupt has a field of its own, and the fields fit in the registers.
+    fn fields_are_disjoint<L: Layout>(registers: usize) {
+        let mut bits = HashSet::new();
+        for interrupt_id in 0..INTERRUPTS {
+            let field = L::field(interrupt_id).unwrap();
+            assert!(field.index < registers, "interrupt {interrupt_id}");
+            for bit in field.bits() {
+                assert!(bit < 32, "interrupt {interrupt_id}");
+                assert!(bits.insert((field.index, bit)), "interrupt {interrupt_id}");
+            }
+        }
+    }
+
+    #[test]
+    fn disjoint() {
+        // as many registers as there are in each array (see IHI 0048B.b table 4-1)
+        fields_are_disjoint::<Bitmap>(32);
+        fields_are_disjoint::<Priority>(255);
+        fields_are_disjoint::<Targets>(255);
+        fields_are_disjoint::<Config>(64);
+    }
+}
@@ -0,0 +1,181 @@
+//! Traits for the devices the kernel core needs from any board: a [`Console`], an
+//! [`InterruptController`], a [`Timer`] and [`Power`] control.
+//!
+//! The kernel implements these for the devices on QEMU's virt machine (PL011, GICv2, the Arm
+//! generic timer and PSCI), and its core only uses them through these traits (see board.rs in the
+//! kernel), so another board only needs implementations of its own. The [`mock`] implementations,
+//! enabled by the `mock` feature, let code written against the traits be tested on the host.
+#![cfg_attr(not(test), no_std)]
+
+use core::fmt;
+
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+
+/// A serial console, which the kernel logs to and reads debug console input from.
+pub trait Console {
+    /// Writes bytes, which may not be UTF-8, waiting for room if the transmitter is busy.
+    fn write_bytes(&mut self, bytes: &[u8]);
+    /// Reads a byte of input, if there is one.
+    fn read_byte(&mut self) -> Option<u8>;
+    /// Waits until everything written has been transmitted.
+    fn flush(&mut self);
+}
+
+/// A borrowed [`Console`], such as one chosen at boot and kept as a `&'static mut dyn Console`.
+impl<C: Console + ?Sized> Console for &mut C {
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        (**self).write_bytes(bytes);
+    }
+    fn read_byte(&mut self) -> Option<u8> {
+        (**self).read_byte()
+    }
+    fn flush(&mut self) {
+        (**self).flush();
+    }
+}
+
+/// What to do with an interrupt once its handler returns to [`InterruptController::handle`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Completion {
+    /// Deactivate the interrupt immediately.
+    Deactivate,
+    /// Leave the interrupt active, so it won't be signalled again until it's deactivated with
+    /// [`InterruptController::deactivate`].
+    Defer,
+}
+
+/// An interrupt controller, which routes interrupts from devices to the CPU.
+pub trait InterruptController {
+    /// Number of an interrupt, as the controller knows it.
+    type Interrupt: Copy + PartialEq + fmt::Debug;
+
+    fn enable_interrupt(&mut self, interrupt: Self::Interrupt);
+    fn disable_interrupt(&mut self, interrupt: Self::Interrupt);
+    fn is_enabled(&self, interrupt: Self::Interrupt) -> bool;
+    /// Returns whether the interrupt has been raised but not yet handled, even if it's disabled.
+    fn is_pending(&self, interrupt: Self::Interrupt) -> bool;
+    /// Acknowledges the interrupt being signalled, handles it with `handler`, then completes it
+    /// as the handler says.
+    ///
+    /// If no interrupt is being signalled, like when it went away before being acknowledged,
+    /// `handler` may not be called at all, or may be called with an interrupt with no handler.
+    fn handle(&mut self, handler: impl FnOnce(Self::Interrupt) -> Completion);
+    /// Deactivates an interrupt whose completion was deferred by its handler.
+    fn deactivate(&mut self, interrupt: Self::Interrupt);
+}
+
+/// A counter that counts up at a fixed frequency, and a timer that interrupts at a given count.
+///
+/// The timer is the CPU's own, so these don't need exclusive access.
+pub trait Timer {
+    /// Returns the current value of the counter.
+    fn now(&self) -> u64;
+    /// Returns the number of counter ticks per second.
+    fn frequency(&self) -> u64;
+    /// Returns the counter value the timer was last set to interrupt at.
+    fn deadline(&self) -> u64;
+    /// Sets the timer to interrupt `ticks` counter ticks from now, replacing any earlier deadline.
+    fn set_after(&self, ticks: u64);
+    /// Lets the timer interrupt once its deadline passes.
+    fn enable(&self);
+    /// Stops the timer from interrupting, and clears its interrupt if it was raised.
+    fn disable(&self);
+
+    /// Returns the number of counter ticks in `ms` milliseconds.
+    fn ticks_from_ms(&self, ms: u64) -> u64 {
+        self.frequency() * ms / 1000
+    }
+}
+
+/// Control over the power of the whole system, usually by asking firmware.
+pub trait Power {
+    /// Powers the system off, only returning if that's not possible.
+    fn system_off(&self);
+    /// Resets the system, only returning if that's not possible.
+    fn system_reset(&self);
+}
+
+/// A [`Console`] that can be written to with `write!`.
+pub struct Writer<C>(pub C);
+
+impl<C: Console> Writer<C> {
+    /// Writes bytes that may not be UTF-8, such as those written by a task.
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.0.write_bytes(bytes);
+    }
+}
+
+impl<C: Console> fmt::Write for Writer<C> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_bytes(s.as_bytes());
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::fmt::Write;
+
+    use super::mock::{MockConsole, MockInterruptController, MockTimer};
+    use super::*;
+
+    #[test]
+    fn writer() {
+        let mut writer = Writer(MockConsole::new());
+        write!(writer, "woof {}", 42).unwrap();
+        writer.write_bytes(b"\xff");
+        assert_eq!(writer.0.output(), b"woof 42\xff");
+    }
+
+    #[test]
+    fn writer_dyn() {
+        let mut console = MockConsole::new();
+        let mut writer = Writer(&mut console as &mut dyn Console);
+        write!(writer, "woof").unwrap();
+        assert_eq!(console.output(), b"woof");
+    }
+
+    #[test]
+    fn deferred_completion() {
+        let mut controller = MockInterruptController::new();
+        controller.enable_interrupt(3);
+        controller.raise(3);
+        controller.raise(5);
+        assert!(controller.is_pending(5));
+
+        // a deferred interrupt isn't signalled again until it's deactivated, and a disabled one
+        // isn't signalled at all
+        controller.handle(|interrupt| {
+            assert_eq!(interrupt, 3);
+            Completion::Defer
+        });
+        controller.raise(3);
+        controller.handle(|interrupt| panic!("handled {interrupt} while active or disabled"));
+        controller.deactivate(3);
+        let mut handled = None;
+        controller.handle(|interrupt| {
+            handled = Some(interrupt);
+            Completion::Deactivate
+        });
+        assert_eq!(handled, Some(3));
+        assert!(!controller.is_pending(3));
+        assert!(controller.is_pending(5));
+    }
+
+    #[test]
+    fn timer() {
+        let timer = MockTimer::new(62_500_000);
+        assert_eq!(timer.ticks_from_ms(100), 6_250_000);
+
+        timer.set_after(100);
+        timer.advance(100);
+        assert!(!timer.fired(), "disabled timers don't fire");
+        timer.enable();
+        assert!(timer.fired());
+        timer.set_after(100);
+        assert_eq!(timer.deadline(), 200);
+        assert!(!timer.fired());
+    }
+}
@@ -0,0 +1,210 @@
+//! Mock implementations of the traits, which do nothing but remember what was done with them, so
+//! code written against the traits can be tested on the host.
+use core::cell::Cell;
+
+use crate::{Completion, Console, InterruptController, Power, Timer};
+
+/// A [`Console`] that remembers what was written to it, and reads input given to it in advance.
+#[derive(Debug)]
+pub struct MockConsole {
+    output: [u8; Self::CAPACITY],
+    output_len: usize,
+    input: [u8; Self::CAPACITY],
+    input_start: usize,
+    input_len: usize,
+}
+
+impl MockConsole {
+    /// Number of bytes of output and of input the console can hold.
+    pub const CAPACITY: usize = 256;
+
+    pub const fn new() -> Self {
+        Self {
+            output: [0; Self::CAPACITY],
+            output_len: 0,
+            input: [0; Self::CAPACITY],
+            input_start: 0,
+            input_len: 0,
+        }
+    }
+
+    /// Returns everything written so far.
+    pub fn output(&self) -> &[u8] {
+        &self.output[..self.output_len]
+    }
+
+    /// Adds bytes to be read with [`Console::read_byte`], after any that haven't been read yet.
+    pub fn push_input(&mut self, bytes: &[u8]) {
+        self.input
+            .copy_within(self.input_start..self.input_start + self.input_len, 0);
+        self.input_start = 0;
+        self.input[self.input_len..][..bytes.len()].copy_from_slice(bytes);
+        self.input_len += bytes.len();
+    }
+}
+
+impl Default for MockConsole {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Console for MockConsole {
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        self.output[self.output_len..][..bytes.len()].copy_from_slice(bytes);
+        self.output_len += bytes.len();
+    }
+
+    fn read_byte(&mut self) -> Option<u8> {
+        if self.input_len == 0 {
+            return None;
+        }
+        let byte = self.input[self.input_start];
+        self.input_start += 1;
+        self.input_len -= 1;
+
+        Some(byte)
+    }
+
+    fn flush(&mut self) {}
+}
+
+/// An [`InterruptController`] with interrupts 0 to 63, which are only raised by
+/// [`Self::raise`], and signalled lowest first.
+#[derive(Debug, Default)]
+pub struct MockInterruptController {
+    enabled: u64,
+    pending: u64,
+    active: u64,
+}
+
+impl MockInterruptController {
+    pub const fn new() -> Self {
+        Self {
+            enabled: 0,
+            pending: 0,
+            active: 0,
+        }
+    }
+
+    /// Raises an interrupt, as a device would.
+    pub fn raise(&mut self, interrupt: usize) {
+        self.pending |= 1 << interrupt;
+    }
+
+    /// Returns whether an interrupt has been acknowledged, but not yet deactivated.
+    pub fn is_active(&self, interrupt: usize) -> bool {
+        self.active & 1 << interrupt != 0
+    }
+}
+
+impl InterruptController for MockInterruptController {
+    type Interrupt = usize;
+
+    fn enable_interrupt(&mut self, interrupt: usize) {
+        self.enabled |= 1 << interrupt;
+    }
+
+    fn disable_interrupt(&mut self, interrupt: usize) {
+        self.enabled &= !(1 << interrupt);
+    }
+
+    fn is_enabled(&self, interrupt: usize) -> bool {
+        self.enabled & 1 << interrupt != 0
+    }
+
+    fn is_pending(&self, interrupt: usize) -> bool {
+        self.pending & 1 << interrupt != 0
+    }
+
+    fn handle(&mut self, handler: impl FnOnce(usize) -> Completion) {
+        let signalled = self.pending & self.enabled & !self.active;
+        if signalled == 0 {
+            return;
+        }
+        let interrupt = signalled.trailing_zeros() as usize;
+        self.pending &= !(1 << interrupt);
+        self.active |= 1 << interrupt;
+
+        if handler(interrupt) == Completion::Deactivate {
+            self.deactivate(interrupt);
+        }
+    }
+
+    fn deactivate(&mut self, interrupt: usize) {
+        self.active &= !(1 << interrupt);
+    }
+}
+
+/// A [`Timer`] whose counter only moves when told to with [`Self::advance`].
+#[derive(Debug)]
+pub struct MockTimer {
+    now: Cell<u64>,
+    frequency: u64,
+    deadline: Cell<u64>,
+    enabled: Cell<bool>,
+}
+
+impl MockTimer {
+    pub const fn new(frequency: u64) -> Self {
+        Self {
+            now: Cell::new(0),
+            frequency,
+            deadline: Cell::new(0),
+            enabled: Cell::new(false),
+        }
+    }
+
+    /// Moves the counter forward by `ticks`.
+    pub fn advance(&self, ticks: u64) {
+        self.now.set(self.now.get() + ticks);
+    }
+
+    /// Returns whether the timer would be raising its interrupt.
+    pub fn fired(&self) -> bool {
+        self.enabled.get() && self.now.get() >= self.deadline.get()
+    }
+}
+
+impl Timer for MockTimer {
+    fn now(&self) -> u64 {
+        self.now.get()
+    }
+
+    fn frequency(&self) -> u64 {
+        self.frequency
+    }
+
+    fn deadline(&self) -> u64 {
+        self.deadline.get()
+    }
+
+    fn set_after(&self, ticks: u64) {
+        self.deadline.set(self.now.get() + ticks);
+    }
+
+    fn enable(&self) {
+        self.enabled.set(true);
+    }
+
+    fn disable(&self) {
+        self.enabled.set(false);
+    }
+}
+
+/// [`Power`] control that remembers what it was asked to do, then returns as if it couldn't.
+#[derive(Debug, Default)]
+pub struct MockPower {
+    pub off: Cell<bool>,
+    pub reset: Cell<bool>,
+}
+
+impl Power for MockPower {
+    fn system_off(&self) {
+        self.off.set(true);
+    }
+
+    fn system_reset(&self) {
+        self.reset.set(true);
+    }
+}
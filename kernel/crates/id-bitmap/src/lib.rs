@@ -0,0 +1,295 @@
+//! A fixed-capacity allocator of small integer ids, such as task ids and handle numbers, which is a
+//! bitmap of the ids in use.
+//!
+//! Ids are allocated and freed with atomic operations on the words of the bitmap, without a lock,
+//! so an [`IdBitmap`] can be shared between CPUs and interrupt handlers. The lowest free id is
+//! always allocated, so ids are reused as soon as they're freed. Where a stale id could be mistaken
+//! for a newer one, [`Generations`] counts how many times each id has been freed.
+#![cfg_attr(not(test), no_std)]
+
+use core::fmt;
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+/// Number of ids in each word of a bitmap.
+const BITS: usize = usize::BITS as usize;
+
+/// Error returned when freeing an id that isn't allocated.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NotAllocated;
+
+impl fmt::Display for NotAllocated {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "id not allocated")
+    }
+}
+
+/// A bitmap of `WORDS` words, where each set bit is an allocated id.
+pub struct IdBitmap<const WORDS: usize> {
+    words: [AtomicUsize; WORDS],
+    /// Number of ids, which may be fewer than there are bits.
+    capacity: usize,
+}
+
+impl<const WORDS: usize> IdBitmap<WORDS> {
+    /// Creates a bitmap of ids 0 to `capacity - 1`, none of which are allocated.
+    ///
+    /// Panics if the words don't have that many bits.
+    pub const fn new(capacity: usize) -> Self {
+        assert!(capacity <= WORDS * BITS, "not enough words for capacity");
+        // AtomicUsize isn't Copy, but a const can be repeated
+        #[allow(clippy::declare_interior_mutable_const)]
+        const FREE: AtomicUsize = AtomicUsize::new(0);
+
+        Self {
+            words: [FREE; WORDS],
+            capacity,
+        }
+    }
+
+    /// Returns the number of ids, allocated or not.
+    pub const fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Allocates the lowest free id, or returns `None` if they're all allocated.
+    pub fn allocate(&self) -> Option<usize> {
+        for (index, word) in self.words.iter().enumerate() {
+            let mut current = word.load(Ordering::Relaxed);
+            loop {
+                let bit = (!current).trailing_zeros() as usize;
+                let id = index * BITS + bit;
+                if bit == BITS || id >= self.capacity {
+                    break;
+                }
+                // acquire whatever the id's last owner released when freeing it
+                match word.compare_exchange_weak(
+                    current,
+                    current | 1 << bit,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => return Some(id),
+                    Err(actual) => current = actual,
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Allocates `id` in particular, returning whether it was free.
+    ///
+    /// Panics if `id` is out of range.
+    pub fn allocate_at(&self, id: usize) -> bool {
+        assert!(id < self.capacity, "id out of range");
+        let (word, mask) = self.locate(id);
+
+        word.fetch_or(mask, Ordering::Acquire) & mask == 0
+    }
+
+    /// Frees `id`, so it can be allocated again.
+    pub fn free(&self, id: usize) -> Result<(), NotAllocated> {
+        if id >= self.capacity {
+            return Err(NotAllocated);
+        }
+        let (word, mask) = self.locate(id);
+        if word.fetch_and(!mask, Ordering::Release) & mask == 0 {
+            return Err(NotAllocated);
+        }
+
+        Ok(())
+    }
+
+    /// Returns whether `id` is allocated, which is never true if it's out of range.
+    pub fn is_allocated(&self, id: usize) -> bool {
+        if id >= self.capacity {
+            return false;
+        }
+        let (word, mask) = self.locate(id);
+
+        word.load(Ordering::Relaxed) & mask != 0
+    }
+
+    /// Returns the number of allocated ids.
+    pub fn count(&self) -> usize {
+        self.words
+            .iter()
+            .map(|word| word.load(Ordering::Relaxed).count_ones() as usize)
+            .sum()
+    }
+
+    /// Returns every allocated id, lowest first. Ids allocated or freed while iterating may or may
+    /// not be included.
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.capacity).filter(|&id| self.is_allocated(id))
+    }
+
+    /// Returns the word holding `id`, and the mask of its bit in that word.
+    fn locate(&self, id: usize) -> (&AtomicUsize, usize) {
+        (&self.words[id / BITS], 1 << (id % BITS))
+    }
+}
+
+impl<const WORDS: usize> Clone for IdBitmap<WORDS> {
+    /// Returns a bitmap with the ids allocated in this one, as of when each word is read.
+    fn clone(&self) -> Self {
+        let result = Self::new(self.capacity);
+        for (word, copy) in self.words.iter().zip(&result.words) {
+            copy.store(word.load(Ordering::Relaxed), Ordering::Relaxed);
+        }
+
+        result
+    }
+}
+
+impl<const WORDS: usize> fmt::Debug for IdBitmap<WORDS> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+/// An id with the generation it was allocated in, so it can be told apart from the same id once
+/// it's been freed and allocated again.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Generational {
+    pub id: usize,
+    pub generation: u32,
+}
+
+/// A generation counter for each of `N` ids, to go with an [`IdBitmap`].
+///
+/// Bump an id's generation with [`Self::bump`] before freeing it, and any [`Generational`] ids
+/// handed out for it will no longer be current. Generations wrap after `u32::MAX`, so a stale id
+/// that's kept for that many reuses will look current again.
+pub struct Generations<const N: usize> {
+    counts: [AtomicU32; N],
+}
+
+impl<const N: usize> Generations<N> {
+    pub const fn new() -> Self {
+        #[allow(clippy::declare_interior_mutable_const)]
+        const FIRST: AtomicU32 = AtomicU32::new(0);
+
+        Self { counts: [FIRST; N] }
+    }
+
+    /// Returns `id` with its current generation.
+    pub fn current(&self, id: usize) -> Generational {
+        Generational {
+            id,
+            generation: self.counts[id].load(Ordering::Relaxed),
+        }
+    }
+
+    /// Returns whether `id` is of its id's current generation.
+    pub fn is_current(&self, id: Generational) -> bool {
+        self.counts.get(id.id).map_or(false, |count| {
+            count.load(Ordering::Relaxed) == id.generation
+        })
+    }
+
+    /// Moves `id` to its next generation.
+    pub fn bump(&self, id: usize) {
+        self.counts[id].fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl<const N: usize> Default for Generations<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> fmt::Debug for Generations<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let counts = self
+            .counts
+            .iter()
+            .map(|count| count.load(Ordering::Relaxed));
+
+        f.debug_list().entries(counts).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn lowest_first() {
+        let ids = IdBitmap::<1>::new(3);
+        assert_eq!(ids.allocate(), Some(0));
+        assert_eq!(ids.allocate(), Some(1));
+        assert_eq!(ids.allocate(), Some(2));
+        assert_eq!(
+            ids.allocate(),
+            None,
+            "ids past the capacity aren't allocated"
+        );
+
+        assert_eq!(ids.free(1), Ok(()));
+        assert_eq!(ids.free(1), Err(NotAllocated));
+        assert_eq!(ids.free(3), Err(NotAllocated));
+        assert_eq!(ids.count(), 2);
+        assert_eq!(ids.iter().collect::<Vec<_>>(), [0, 2]);
+        assert_eq!(ids.allocate(), Some(1));
+    }
+
+    #[test]
+    fn many_words() {
+        let ids = IdBitmap::<2>::new(BITS + 4);
+        for id in 0..BITS {
+            assert!(ids.allocate_at(id));
+        }
+        assert!(!ids.allocate_at(5));
+        assert_eq!(ids.allocate(), Some(BITS));
+        assert!(ids.is_allocated(BITS));
+        assert!(!ids.is_allocated(BITS + 1));
+        assert!(!ids.is_allocated(BITS * 2));
+
+        let copy = ids.clone();
+        assert_eq!(ids.free(0), Ok(()));
+        assert_eq!(copy.count(), BITS + 1);
+        assert!(copy.is_allocated(0));
+    }
+
+    #[test]
+    fn generations() {
+        let generations = Generations::<2>::new();
+        let stale = generations.current(1);
+        generations.bump(1);
+        assert!(!generations.is_current(stale));
+        assert!(generations.is_current(generations.current(1)));
+        assert!(generations.is_current(generations.current(0)));
+        assert!(!generations.is_current(Generational {
+            id: 2,
+            generation: 0,
+        }));
+    }
+
+    #[test]
+    fn concurrent() {
+        let ids = IdBitmap::<2>::new(100);
+        let held: [AtomicBool; 100] = std::array::from_fn(|_| AtomicBool::new(false));
+
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for _ in 0..10_000 {
+                        let id = ids.allocate().unwrap();
+                        assert!(
+                            !held[id].swap(true, Ordering::Relaxed),
+                            "{id} given out twice"
+                        );
+                        held[id].store(false, Ordering::Relaxed);
+                        ids.free(id).unwrap();
+                    }
+                });
+            }
+        });
+        assert_eq!(ids.count(), 0);
+    }
+}
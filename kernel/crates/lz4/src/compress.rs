@@ -0,0 +1,94 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::MIN_MATCH;
+
+/// The last five bytes of a block are always literals.
+const LAST_LITERALS: usize = 5;
+/// The last match must start at least twelve bytes before the end of the block.
+const MF_LIMIT: usize = 12;
+/// Matches can refer back at most this far, since offsets are 16 bits.
+const MAX_OFFSET: usize = 0xFFFF;
+
+const HASH_BITS: u32 = 12;
+
+/// Compresses `input` into a single block.
+///
+/// This is a simple greedy compressor that only remembers the last position of each (hashed)
+/// four-byte sequence, so it's fast but won't compress as well as the reference implementation.
+pub fn compress(input: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(input.len() / 2);
+    // position + 1 of the last occurrence of each hashed sequence, or 0 if none
+    let mut table = vec![0; 1 << HASH_BITS];
+    let mut anchor = 0;
+    let mut i = 0;
+
+    while i + MF_LIMIT < input.len() {
+        let sequence = read_u32(input, i);
+        let hash = hash(sequence);
+        let candidate = table[hash];
+        table[hash] = i + 1;
+
+        if let Some(candidate) = candidate.checked_sub(1) {
+            if i - candidate <= MAX_OFFSET && read_u32(input, candidate) == sequence {
+                let mut match_len = MIN_MATCH;
+                let max_len = input.len() - LAST_LITERALS - i;
+                while match_len < max_len && input[candidate + match_len] == input[i + match_len] {
+                    match_len += 1;
+                }
+
+                write_sequence(
+                    &mut output,
+                    &input[anchor..i],
+                    Some((i - candidate, match_len)),
+                );
+                i += match_len;
+                anchor = i;
+                continue;
+            }
+        }
+
+        i += 1;
+    }
+
+    write_sequence(&mut output, &input[anchor..], None);
+
+    output
+}
+
+fn read_u32(input: &[u8], i: usize) -> u32 {
+    u32::from_le_bytes([input[i], input[i + 1], input[i + 2], input[i + 3]])
+}
+
+fn hash(sequence: u32) -> usize {
+    // Knuth's multiplicative hash, as used by the reference implementation
+    (sequence.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+}
+
+/// Writes a sequence of literals, followed by a match (offset and length) unless this is the last
+/// sequence.
+fn write_sequence(output: &mut Vec<u8>, literals: &[u8], r#match: Option<(usize, usize)>) {
+    let match_len = r#match.map_or(0, |(_, len)| len - MIN_MATCH);
+    let token = (literals.len().min(0xF) << 4 | match_len.min(0xF)) as u8;
+
+    output.push(token);
+    write_length(output, literals.len());
+    output.extend_from_slice(literals);
+
+    if let Some((offset, _)) = r#match {
+        output.extend_from_slice(&(offset as u16).to_le_bytes());
+        write_length(output, match_len);
+    }
+}
+
+/// Writes the bytes that follow a 4-bit length of 15 in the token, if any.
+fn write_length(output: &mut Vec<u8>, length: usize) {
+    if length >= 0xF {
+        let mut rest = length - 0xF;
+        while rest >= 0xFF {
+            output.push(0xFF);
+            rest -= 0xFF;
+        }
+        output.push(rest as u8);
+    }
+}
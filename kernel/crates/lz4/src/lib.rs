@@ -0,0 +1,195 @@
+//! Decompressor (and, with the `compress` feature, a simple compressor) for the LZ4 block format.
+//!
+//! Only raw blocks are supported, not the LZ4 frame format, so callers need to store the
+//! decompressed size themselves.
+//!
+//! https://github.com/lz4/lz4/blob/v1.9.4/doc/lz4_Block_format.md
+#![cfg_attr(not(test), no_std)]
+
+#[cfg(any(test, feature = "compress"))]
+extern crate alloc;
+
+#[cfg(any(test, feature = "compress"))]
+mod compress;
+
+#[cfg(any(test, feature = "compress"))]
+pub use compress::compress;
+
+/// Length of the shortest possible match, which is encoded as a match length of zero.
+const MIN_MATCH: usize = 4;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DecompressError {
+    /// The input ended in the middle of a sequence.
+    Truncated,
+    /// The decompressed data doesn't fit in the output.
+    OutputTooSmall,
+    /// A match refers to data before the start of the output.
+    InvalidOffset,
+}
+
+/// Decompresses a block from `input` into `output`, returning the decompressed size.
+pub fn decompress(input: &[u8], output: &mut [u8]) -> Result<usize, DecompressError> {
+    let mut i = 0usize;
+    let mut o = 0usize;
+
+    loop {
+        let token = *input.get(i).ok_or(DecompressError::Truncated)?;
+        i += 1;
+
+        // literals, copied as is
+        let literal_len = read_length(input, &mut i, usize::from(token >> 4))?;
+        let literals = i
+            .checked_add(literal_len)
+            .and_then(|end| input.get(i..end))
+            .ok_or(DecompressError::Truncated)?;
+        o.checked_add(literal_len)
+            .and_then(|end| output.get_mut(o..end))
+            .ok_or(DecompressError::OutputTooSmall)?
+            .copy_from_slice(literals);
+        i += literal_len;
+        o += literal_len;
+
+        // the last sequence has literals only
+        if i == input.len() {
+            return Ok(o);
+        }
+
+        // match, copied from earlier in the output, possibly overlapping itself
+        let offset = input.get(i..i + 2).ok_or(DecompressError::Truncated)?;
+        let offset = usize::from(u16::from_le_bytes([offset[0], offset[1]]));
+        i += 2;
+        if offset == 0 || offset > o {
+            return Err(DecompressError::InvalidOffset);
+        }
+
+        let match_len = read_length(input, &mut i, usize::from(token & 0xF))? + MIN_MATCH;
+        if o.checked_add(match_len)
+            .map_or(true, |end| end > output.len())
+        {
+            return Err(DecompressError::OutputTooSmall);
+        }
+        for _ in 0..match_len {
+            output[o] = output[o - offset];
+            o += 1;
+        }
+    }
+}
+
+/// Reads a literal or match length, given the 4-bit length from the token. A length of 15 is
+/// followed by bytes to add to it, up to and including the first byte that isn't 255.
+fn read_length(input: &[u8], i: &mut usize, length: usize) -> Result<usize, DecompressError> {
+    let mut length = length;
+
+    if length == 0xF {
+        loop {
+            let byte = *input.get(*i).ok_or(DecompressError::Truncated)?;
+            *i += 1;
+            length += usize::from(byte);
+            if byte != 0xFF {
+                break;
+            }
+        }
+    }
+
+    Ok(length)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    use super::*;
+
+    fn round_trip(input: &[u8]) {
+        let compressed = compress(input);
+        let mut output = vec![0; input.len()];
+        assert_eq!(decompress(&compressed, &mut output), Ok(input.len()));
+        assert_eq!(output, input);
+    }
+
+    #[test]
+    fn decompress_literals() {
+        let mut output = [0; 5];
+        assert_eq!(decompress(b"\x50hello", &mut output), Ok(5));
+        assert_eq!(&output, b"hello");
+
+        let mut output = [0; 0];
+        assert_eq!(decompress(b"\x00", &mut output), Ok(0));
+    }
+
+    #[test]
+    fn decompress_overlapping_match() {
+        // “ab”, then a match of length 6 at offset 2, then literal “c”
+        let mut output = [0; 9];
+        assert_eq!(decompress(b"\x22ab\x02\x00\x10c", &mut output), Ok(9));
+        assert_eq!(&output, b"ababababc");
+    }
+
+    #[test]
+    fn decompress_long_lengths() {
+        // literal length 15 + 255 + 10
+        let mut input = vec![0xF0, 0xFF, 10];
+        input.extend(core::iter::repeat(b'x').take(280));
+        let mut output = vec![0; 280];
+        assert_eq!(decompress(&input, &mut output), Ok(280));
+        assert!(output.iter().all(|&b| b == b'x'));
+    }
+
+    #[test]
+    fn decompress_errors() {
+        let mut output = [0; 16];
+        assert_eq!(
+            decompress(b"", &mut output),
+            Err(DecompressError::Truncated)
+        );
+        assert_eq!(
+            decompress(b"\x50hel", &mut output),
+            Err(DecompressError::Truncated)
+        );
+        assert_eq!(
+            decompress(b"\x10a\x05\x00", &mut output),
+            Err(DecompressError::InvalidOffset)
+        );
+        assert_eq!(
+            decompress(b"\x10a\x00\x00", &mut output),
+            Err(DecompressError::InvalidOffset)
+        );
+        assert_eq!(
+            decompress(b"\x50hello", &mut output[..4]),
+            Err(DecompressError::OutputTooSmall)
+        );
+        assert_eq!(
+            decompress(b"\x1Fa\x01\x00\xFF\x00", &mut output),
+            Err(DecompressError::OutputTooSmall)
+        );
+    }
+
+    #[test]
+    fn compress_round_trip() {
+        round_trip(b"");
+        round_trip(b"a");
+        round_trip(b"hello, world");
+        round_trip(&[0; 1000]);
+        round_trip(&b"woof wraaaooo! ".repeat(100));
+
+        // xorshift, so that most of the input is incompressible
+        let mut state = 0x2545_F491_4F6C_DD1D_u64;
+        let random = (0..10000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect::<Vec<_>>();
+        round_trip(&random);
+    }
+
+    #[test]
+    fn compress_ratio() {
+        let input = vec![0; 65536];
+        assert!(compress(&input).len() < 512);
+    }
+}
@@ -0,0 +1,342 @@
+//! The physical memory map, which is the RAM described by the devicetree's memory nodes, less any
+//! ranges that are reserved, such as by the FDT's memory reservation block or the kernel image.
+//!
+//! There can be more than one region of RAM. For example, QEMU's virt machine has a second region
+//! above 4GiB when there's more RAM than fits below it, and NUMA machines have a memory node for
+//! each node.
+#![cfg_attr(not(test), no_std)]
+
+use core::fmt;
+use core::ops::Range;
+
+use fdt::Fdt;
+
+/// Maximum number of disjoint regions in a [`MemoryMap`].
+pub const MAX_REGIONS: usize = 8;
+
+/// Error returned when adding or reserving a range would leave a [`MemoryMap`] with more than
+/// [`MAX_REGIONS`] regions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TooManyRegions;
+
+impl fmt::Display for TooManyRegions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "more than {MAX_REGIONS} memory regions")
+    }
+}
+
+/// Disjoint ranges of physical addresses that are usable RAM, sorted by address, with no two
+/// ranges touching.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct MemoryMap {
+    regions: [Range<usize>; MAX_REGIONS],
+    len: usize,
+}
+
+impl MemoryMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builds the memory map from the `reg` of every node whose `device_type` is `"memory"`, less
+    /// the ranges in the memory reservation block.
+    pub fn from_fdt(fdt: &Fdt) -> Result<Self, TooManyRegions> {
+        let mut map = Self::new();
+
+        let nodes = fdt
+            .all_nodes()
+            .filter(|node| node.property("device_type").and_then(|p| p.as_str()) == Some("memory"));
+        for node in nodes {
+            for region in node.reg().into_iter().flatten() {
+                let start = region.starting_address as usize;
+                map.add(start..start.saturating_add(region.size.unwrap_or(0)))?;
+            }
+        }
+        for reservation in fdt.memory_reservations() {
+            let start = reservation.address() as usize;
+            map.reserve(start..start.saturating_add(reservation.size()))?;
+        }
+
+        Ok(map)
+    }
+
+    /// Adds `range` as usable RAM, merging it with any regions it overlaps or touches.
+    pub fn add(&mut self, mut range: Range<usize>) -> Result<(), TooManyRegions> {
+        if range.is_empty() {
+            return Ok(());
+        }
+
+        let mut i = 0;
+        while i < self.len {
+            let region = &self.regions[i];
+            if region.start <= range.end && range.start <= region.end {
+                range = region.start.min(range.start)..region.end.max(range.end);
+                self.remove(i);
+            } else {
+                i += 1;
+            }
+        }
+
+        let index = self.regions().position(|region| region.start > range.start);
+        self.insert(index.unwrap_or(self.len), range)
+    }
+
+    /// Removes `range` from the usable RAM, splitting the region it's in if it's in the middle of
+    /// one.
+    ///
+    /// If this fails, the map is unchanged, because only a range inside a single region can split
+    /// it.
+    pub fn reserve(&mut self, range: Range<usize>) -> Result<(), TooManyRegions> {
+        if range.is_empty() {
+            return Ok(());
+        }
+
+        let mut i = 0;
+        while i < self.len {
+            let region = self.regions[i].clone();
+            if region.end <= range.start || range.end <= region.start {
+                i += 1;
+                continue;
+            }
+
+            let below = region.start..range.start;
+            let above = range.end..region.end;
+            match (below.is_empty(), above.is_empty()) {
+                (false, false) => {
+                    self.insert(i + 1, above)?;
+                    self.regions[i] = below;
+                    i += 2;
+                }
+                (false, true) => {
+                    self.regions[i] = below;
+                    i += 1;
+                }
+                (true, false) => {
+                    self.regions[i] = above;
+                    i += 1;
+                }
+                (true, true) => self.remove(i),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the regions of usable RAM, sorted by address.
+    pub fn regions(&self) -> impl Iterator<Item = Range<usize>> + '_ {
+        self.regions[..self.len].iter().cloned()
+    }
+
+    /// Returns the number of bytes of usable RAM, across every region.
+    pub fn len(&self) -> usize {
+        self.regions().map(|region| region.len()).sum()
+    }
+
+    /// Returns whether there's no usable RAM at all.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn insert(&mut self, index: usize, range: Range<usize>) -> Result<(), TooManyRegions> {
+        if self.len == MAX_REGIONS {
+            return Err(TooManyRegions);
+        }
+
+        self.regions[index..=self.len].rotate_right(1);
+        self.regions[index] = range;
+        self.len += 1;
+
+        Ok(())
+    }
+
+    fn remove(&mut self, index: usize) {
+        self.regions[index..self.len].rotate_left(1);
+        self.len -= 1;
+    }
+}
+
+impl fmt::Debug for MemoryMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.regions()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GIB: usize = 1 << 30;
+
+    /// Builds an FDT whose root has a node for each of `memory`, named `memory@<address>` with the
+    /// given `reg` entries, and a memory reservation block with `reserved`.
+    fn fdt(memory: &[&[(u64, u64)]], reserved: &[(u64, u64)]) -> Vec<u8> {
+        const BEGIN_NODE: u32 = 1;
+        const END_NODE: u32 = 2;
+        const PROP: u32 = 3;
+        const END: u32 = 9;
+
+        let strings = b"#address-cells\0#size-cells\0device_type\0reg\0";
+        let name_offset = |name: &str| {
+            let needle = [name.as_bytes(), b"\0"].concat();
+            strings
+                .windows(needle.len())
+                .position(|w| w == needle)
+                .unwrap() as u32
+        };
+
+        let mut structure = Vec::new();
+        structure.extend(BEGIN_NODE.to_be_bytes());
+        structure.extend([0; 4]);
+        let prop = |structure: &mut Vec<u8>, name: &str, value: &[u8]| {
+            structure.extend(PROP.to_be_bytes());
+            structure.extend((value.len() as u32).to_be_bytes());
+            structure.extend(name_offset(name).to_be_bytes());
+            structure.extend(value);
+            structure.resize(structure.len().next_multiple_of(4), 0);
+        };
+        prop(&mut structure, "#address-cells", &2u32.to_be_bytes());
+        prop(&mut structure, "#size-cells", &2u32.to_be_bytes());
+        for reg in memory {
+            structure.extend(BEGIN_NODE.to_be_bytes());
+            structure.extend(format!("memory@{:x}\0", reg[0].0).as_bytes());
+            structure.resize(structure.len().next_multiple_of(4), 0);
+            prop(&mut structure, "device_type", b"memory\0");
+            let value: Vec<u8> = reg
+                .iter()
+                .flat_map(|&(address, size)| [address.to_be_bytes(), size.to_be_bytes()])
+                .flatten()
+                .collect();
+            prop(&mut structure, "reg", &value);
+            structure.extend(END_NODE.to_be_bytes());
+        }
+        structure.extend(END_NODE.to_be_bytes());
+        structure.extend(END.to_be_bytes());
+
+        let mut reservations: Vec<u8> = reserved
+            .iter()
+            .flat_map(|&(address, size)| [address.to_be_bytes(), size.to_be_bytes()])
+            .flatten()
+            .collect();
+        reservations.extend([0; 16]);
+
+        let header_len = 40;
+        let off_mem_rsvmap = header_len;
+        let off_dt_struct = off_mem_rsvmap + reservations.len();
+        let off_dt_strings = off_dt_struct + structure.len();
+        let total_size = off_dt_strings + strings.len();
+        let header = [
+            0xD00D_FEED,
+            total_size,
+            off_dt_struct,
+            off_dt_strings,
+            off_mem_rsvmap,
+            17,
+            16,
+            0,
+            strings.len(),
+            structure.len(),
+        ];
+
+        let mut blob: Vec<u8> = header
+            .iter()
+            .flat_map(|&field| (field as u32).to_be_bytes())
+            .collect();
+        blob.extend(reservations);
+        blob.extend(structure);
+        blob.extend(strings);
+
+        blob
+    }
+
+    fn regions(map: &MemoryMap) -> Vec<Range<usize>> {
+        map.regions().collect()
+    }
+
+    #[test]
+    fn single_region() {
+        let blob = fdt(&[&[(0x4000_0000, 0x800_0000)]], &[]);
+        let map = MemoryMap::from_fdt(&Fdt::new(&blob).unwrap()).unwrap();
+
+        assert_eq!(map.regions().next(), Some(0x4000_0000..0x4800_0000));
+        assert_eq!(map.regions().count(), 1);
+        assert_eq!(map.len(), 0x800_0000);
+    }
+
+    #[test]
+    fn high_memory() {
+        // adjacent regions, even in one node, are merged...
+        let blob = fdt(
+            &[&[(0x4000_0000, 0xC000_0000), (0x1_0000_0000, 0xC000_0000)]],
+            &[],
+        );
+        let map = MemoryMap::from_fdt(&Fdt::new(&blob).unwrap()).unwrap();
+        assert_eq!(map.regions().next(), Some(0x4000_0000..0x1_C000_0000));
+        assert_eq!(map.regions().count(), 1);
+        assert_eq!(map.len(), 6 * GIB);
+
+        // ...and in separate nodes, with a hole between them
+        let blob = fdt(
+            &[
+                &[(0x4000_0000, 0x4000_0000)],
+                &[(0x2_0000_0000, 0x1_0000_0000)],
+            ],
+            &[],
+        );
+        let map = MemoryMap::from_fdt(&Fdt::new(&blob).unwrap()).unwrap();
+        assert_eq!(
+            regions(&map),
+            [0x4000_0000..0x8000_0000, 0x2_0000_0000..0x3_0000_0000]
+        );
+        assert_eq!(map.len(), 5 * GIB);
+    }
+
+    #[test]
+    fn reservations() {
+        let blob = fdt(
+            &[
+                &[(0x4000_0000, 0x4000_0000)],
+                &[(0x1_0000_0000, 0x4000_0000)],
+            ],
+            &[(0x4000_0000, 0x10_0000), (0x1_1000_0000, 0x1000)],
+        );
+        let mut map = MemoryMap::from_fdt(&Fdt::new(&blob).unwrap()).unwrap();
+        assert_eq!(
+            regions(&map),
+            [
+                0x4010_0000..0x8000_0000,
+                0x1_0000_0000..0x1_1000_0000,
+                0x1_1000_1000..0x1_4000_0000,
+            ]
+        );
+
+        // reserving across regions trims both, and removes any in between
+        map.reserve(0x7000_0000..0x1_2000_0000).unwrap();
+        assert_eq!(
+            regions(&map),
+            [0x4010_0000..0x7000_0000, 0x1_2000_0000..0x1_4000_0000]
+        );
+        map.reserve(0..usize::MAX).unwrap();
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn too_many_regions() {
+        let mut map = MemoryMap::new();
+        for i in 0..MAX_REGIONS {
+            map.add(i * GIB..i * GIB + 0x1000).unwrap();
+        }
+        let before = map.clone();
+
+        assert_eq!(
+            map.add(0x1_0000_0000_0000..0x1_0000_0000_1000),
+            Err(TooManyRegions)
+        );
+        assert_eq!(map.reserve(0x100..0x200), Err(TooManyRegions));
+        assert_eq!(map, before);
+
+        // merging doesn't need another region
+        map.add(0x1000..GIB).unwrap();
+        assert_eq!(map.regions().count(), MAX_REGIONS - 1);
+        assert_eq!(regions(&map)[0], 0..GIB + 0x1000);
+    }
+}
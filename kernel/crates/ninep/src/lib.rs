@@ -0,0 +1,719 @@
+//! Encoding and decoding of 9P2000.L messages, the protocol QEMU shares host directories with
+//! (`-virtfs`), for the kernel's virtio-9p driver.
+//!
+//! Every message is a little-endian `size[4] type[1] tag[2]` header, where the size includes the
+//! header, followed by fields that depend on the type. The client sends T-messages, encoded from a
+//! [`Request`], and the server answers each with an R-message of the same tag, decoded into a
+//! [`Response`]. Only the messages needed to read files and list directories are supported.
+//!
+//! https://github.com/chaos/diod/blob/master/protocol.md
+#![cfg_attr(not(test), no_std)]
+
+/// Version of the protocol we speak, sent in [`Request::Version`].
+pub const VERSION: &str = "9P2000.L";
+
+/// Tag of [`Request::Version`], which is the only message sent without one.
+pub const NOTAG: u16 = 0xffff;
+
+/// Fid meaning no fid, like the `afid` of [`Request::Attach`] when there's no authentication.
+pub const NOFID: u32 = 0xffff_ffff;
+
+/// Most path components a [`Request::Walk`] can have.
+pub const MAX_WALK: usize = 16;
+
+/// Size of everything in an Rread or Rreaddir but the data, so a read's count plus this must fit
+/// in the negotiated msize.
+pub const IO_HEADER_SIZE: u32 = 11;
+
+/// Flag for [`Request::Lopen`] to open for reading only, as in Linux's open flags.
+pub const O_RDONLY: u32 = 0;
+
+/// Fields to ask for in [`Request::Getattr`], which are all of the basic ones.
+pub const GETATTR_BASIC: u64 = 0x7ff;
+
+/// Linux's directory entry type for a directory, as in [`DirEntry::kind`].
+pub const DT_DIR: u8 = 4;
+
+/// Size of the header at the start of every message.
+const HEADER_SIZE: usize = 7;
+
+/// Message types, where the R-message answering each T-message is the next number up.
+mod kind {
+    pub const RLERROR: u8 = 7;
+    pub const TLOPEN: u8 = 12;
+    pub const RLOPEN: u8 = 13;
+    pub const TGETATTR: u8 = 24;
+    pub const RGETATTR: u8 = 25;
+    pub const TREADDIR: u8 = 40;
+    pub const RREADDIR: u8 = 41;
+    pub const TVERSION: u8 = 100;
+    pub const RVERSION: u8 = 101;
+    pub const TATTACH: u8 = 104;
+    pub const RATTACH: u8 = 105;
+    pub const TWALK: u8 = 110;
+    pub const RWALK: u8 = 111;
+    pub const TREAD: u8 = 116;
+    pub const RREAD: u8 = 117;
+    pub const TCLUNK: u8 = 120;
+    pub const RCLUNK: u8 = 121;
+}
+
+/// The server's unique identity for a file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Qid {
+    /// Type of the file, where [`Qid::DIR`] is set for directories.
+    pub kind: u8,
+    /// Version of the file, which changes whenever it's modified.
+    pub version: u32,
+    /// Number unique to the file among those on the server, like an inode number.
+    pub path: u64,
+}
+
+impl Qid {
+    /// Bit of [`Qid::kind`] that's set for directories.
+    pub const DIR: u8 = 0x80;
+
+    /// Returns whether the file is a directory.
+    pub fn is_dir(&self) -> bool {
+        self.kind & Self::DIR != 0
+    }
+}
+
+/// Attributes of a file, from [`Response::Getattr`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Attr {
+    pub qid: Qid,
+    /// Type and permissions of the file, as in Linux's `st_mode`.
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub nlink: u64,
+    /// Size of the file, in bytes.
+    pub size: u64,
+    /// Time the file was last modified, in seconds since the epoch.
+    pub mtime: u64,
+}
+
+/// An entry in a directory, from [`Response::Readdir`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DirEntry<'m> {
+    pub qid: Qid,
+    /// Offset to read the directory from to get the entries after this one.
+    pub offset: u64,
+    /// Type of the entry, as in Linux's `d_type` (see [`DT_DIR`]).
+    pub kind: u8,
+    pub name: &'m str,
+}
+
+/// A T-message, sent from the client to the server.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Request<'a> {
+    /// Starts a session, negotiating the largest message either side will send.
+    Version { msize: u32, version: &'a str },
+    /// Makes `fid` the root of the share named `aname` (any name, for QEMU).
+    Attach {
+        fid: u32,
+        afid: u32,
+        uname: &'a str,
+        aname: &'a str,
+        n_uname: u32,
+    },
+    /// Makes `newfid` the file at `path`, relative to `fid`. The path is split on `/`, ignoring
+    /// empty components, so an empty path makes `newfid` a copy of `fid`.
+    Walk {
+        fid: u32,
+        newfid: u32,
+        path: &'a str,
+    },
+    /// Opens `fid` for reading or writing, as `flags` says.
+    Lopen { fid: u32, flags: u32 },
+    /// Reads up to `count` bytes of the open file `fid`, from `offset`.
+    Read { fid: u32, offset: u64, count: u32 },
+    /// Reads up to `count` bytes of entries from the open directory `fid`, from `offset`, which is
+    /// 0 or the offset of the last entry read.
+    Readdir { fid: u32, offset: u64, count: u32 },
+    /// Gets the attributes of `fid` asked for by `mask` (see [`GETATTR_BASIC`]).
+    Getattr { fid: u32, mask: u64 },
+    /// Forgets `fid`, closing it if it's open.
+    Clunk { fid: u32 },
+}
+
+/// An R-message, sent from the server to the client in answer to a [`Request`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Response<'m> {
+    Version {
+        msize: u32,
+        version: &'m str,
+    },
+    Attach {
+        qid: Qid,
+    },
+    /// Says how many components of the path were walked, and the qid of the last one. If that's
+    /// fewer than the path has, the walk failed, and `newfid` wasn't made.
+    Walk {
+        walked: u16,
+        qid: Option<Qid>,
+    },
+    Lopen {
+        qid: Qid,
+        /// Most bytes a read is guaranteed to return at once, or 0 if there's no such guarantee.
+        iounit: u32,
+    },
+    /// The bytes read, which are none at the end of the file.
+    Read {
+        data: &'m [u8],
+    },
+    /// The entries read, which are none at the end of the directory.
+    Readdir {
+        entries: DirEntries<'m>,
+    },
+    Getattr {
+        attr: Attr,
+    },
+    Clunk,
+    /// The request failed with a Linux error number.
+    Error {
+        errno: u32,
+    },
+}
+
+/// The entries of a [`Response::Readdir`], which are decoded as they're iterated over.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DirEntries<'m>(&'m [u8]);
+
+/// An error encoding or decoding a message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The buffer is too small for the message being encoded.
+    BufferTooSmall,
+    /// A walk's path has more than [`MAX_WALK`] components.
+    TooManyComponents,
+    /// A string is too long for its length to fit in 16 bits.
+    StringTooLong,
+    /// The message ends before its fields do, or is shorter than its size says.
+    Truncated,
+    /// The message has a type we don't know how to decode.
+    UnknownType(u8),
+    /// A string in the message isn't UTF-8.
+    InvalidUtf8,
+}
+
+impl Error {
+    /// Returns a description of the error, for logging.
+    pub fn reason(self) -> &'static str {
+        match self {
+            Self::BufferTooSmall => "message too large for buffer",
+            Self::TooManyComponents => "too many path components",
+            Self::StringTooLong => "string too long",
+            Self::Truncated => "message truncated",
+            Self::UnknownType(_) => "message of unknown type",
+            Self::InvalidUtf8 => "string isn't UTF-8",
+        }
+    }
+}
+
+/// Returns the components of `path`, as a [`Request::Walk`] would walk them.
+pub fn components(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|component| !component.is_empty())
+}
+
+impl Request<'_> {
+    /// Encodes the request with the given tag into `buffer`, returning the size of the message.
+    pub fn encode(&self, tag: u16, buffer: &mut [u8]) -> Result<usize, Error> {
+        let mut writer = Writer {
+            buffer,
+            len: HEADER_SIZE,
+        };
+        let kind = match *self {
+            Self::Version { msize, version } => {
+                writer.u32(msize)?;
+                writer.str(version)?;
+                kind::TVERSION
+            }
+            Self::Attach {
+                fid,
+                afid,
+                uname,
+                aname,
+                n_uname,
+            } => {
+                writer.u32(fid)?;
+                writer.u32(afid)?;
+                writer.str(uname)?;
+                writer.str(aname)?;
+                writer.u32(n_uname)?;
+                kind::TATTACH
+            }
+            Self::Walk { fid, newfid, path } => {
+                let count = components(path).count();
+                if count > MAX_WALK {
+                    return Err(Error::TooManyComponents);
+                }
+                writer.u32(fid)?;
+                writer.u32(newfid)?;
+                writer.u16(count as u16)?;
+                for component in components(path) {
+                    writer.str(component)?;
+                }
+                kind::TWALK
+            }
+            Self::Lopen { fid, flags } => {
+                writer.u32(fid)?;
+                writer.u32(flags)?;
+                kind::TLOPEN
+            }
+            Self::Read { fid, offset, count } => {
+                writer.u32(fid)?;
+                writer.u64(offset)?;
+                writer.u32(count)?;
+                kind::TREAD
+            }
+            Self::Readdir { fid, offset, count } => {
+                writer.u32(fid)?;
+                writer.u64(offset)?;
+                writer.u32(count)?;
+                kind::TREADDIR
+            }
+            Self::Getattr { fid, mask } => {
+                writer.u32(fid)?;
+                writer.u64(mask)?;
+                kind::TGETATTR
+            }
+            Self::Clunk { fid } => {
+                writer.u32(fid)?;
+                kind::TCLUNK
+            }
+        };
+
+        let len = writer.len;
+        let mut header = Writer {
+            buffer: writer.buffer,
+            len: 0,
+        };
+        header.u32(u32::try_from(len).map_err(|_| Error::BufferTooSmall)?)?;
+        header.u8(kind)?;
+        header.u16(tag)?;
+
+        Ok(len)
+    }
+}
+
+/// Decodes the message at the start of `message`, returning its tag and the response.
+pub fn decode(message: &[u8]) -> Result<(u16, Response<'_>), Error> {
+    let size = Reader(message).u32()? as usize;
+    if size < HEADER_SIZE || size > message.len() {
+        return Err(Error::Truncated);
+    }
+
+    let mut reader = Reader(&message[4..size]);
+    let kind = reader.u8()?;
+    let tag = reader.u16()?;
+    let response = match kind {
+        kind::RLERROR => Response::Error {
+            errno: reader.u32()?,
+        },
+        kind::RVERSION => Response::Version {
+            msize: reader.u32()?,
+            version: reader.str()?,
+        },
+        kind::RATTACH => Response::Attach { qid: reader.qid()? },
+        kind::RWALK => {
+            let walked = reader.u16()?;
+            let mut qid = None;
+            for _ in 0..walked {
+                qid = Some(reader.qid()?);
+            }
+            Response::Walk { walked, qid }
+        }
+        kind::RLOPEN => Response::Lopen {
+            qid: reader.qid()?,
+            iounit: reader.u32()?,
+        },
+        kind::RREAD => {
+            let count = reader.u32()? as usize;
+            Response::Read {
+                data: reader.bytes(count)?,
+            }
+        }
+        kind::RREADDIR => {
+            let count = reader.u32()? as usize;
+            Response::Readdir {
+                entries: DirEntries(reader.bytes(count)?),
+            }
+        }
+        kind::RGETATTR => {
+            let _valid = reader.u64()?;
+            let qid = reader.qid()?;
+            let mode = reader.u32()?;
+            let uid = reader.u32()?;
+            let gid = reader.u32()?;
+            let nlink = reader.u64()?;
+            let _rdev = reader.u64()?;
+            let size = reader.u64()?;
+            // skip blksize, blocks, and the access time
+            reader.bytes(4 * 8)?;
+            let mtime = reader.u64()?;
+            Response::Getattr {
+                attr: Attr {
+                    qid,
+                    mode,
+                    uid,
+                    gid,
+                    nlink,
+                    size,
+                    mtime,
+                },
+            }
+        }
+        kind::RCLUNK => Response::Clunk,
+        _ => return Err(Error::UnknownType(kind)),
+    };
+
+    Ok((tag, response))
+}
+
+impl<'m> Iterator for DirEntries<'m> {
+    type Item = Result<DirEntry<'m>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.0.is_empty() {
+            return None;
+        }
+
+        let mut reader = Reader(self.0);
+        let entry = (|| {
+            Ok(DirEntry {
+                qid: reader.qid()?,
+                offset: reader.u64()?,
+                kind: reader.u8()?,
+                name: reader.str()?,
+            })
+        })();
+        // don't try to decode anything after a malformed entry
+        self.0 = if entry.is_ok() { reader.0 } else { &[] };
+
+        Some(entry)
+    }
+}
+
+/// Writes the fields of a message into a buffer, after the first `len` bytes.
+struct Writer<'b> {
+    buffer: &'b mut [u8],
+    len: usize,
+}
+
+impl Writer<'_> {
+    fn bytes(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let end = self.len + bytes.len();
+        self.buffer
+            .get_mut(self.len..end)
+            .ok_or(Error::BufferTooSmall)?
+            .copy_from_slice(bytes);
+        self.len = end;
+
+        Ok(())
+    }
+
+    fn u8(&mut self, value: u8) -> Result<(), Error> {
+        self.bytes(&[value])
+    }
+
+    fn u16(&mut self, value: u16) -> Result<(), Error> {
+        self.bytes(&value.to_le_bytes())
+    }
+
+    fn u32(&mut self, value: u32) -> Result<(), Error> {
+        self.bytes(&value.to_le_bytes())
+    }
+
+    fn u64(&mut self, value: u64) -> Result<(), Error> {
+        self.bytes(&value.to_le_bytes())
+    }
+
+    /// Writes a string, which is a 16-bit length followed by that many bytes.
+    fn str(&mut self, value: &str) -> Result<(), Error> {
+        self.u16(u16::try_from(value.len()).map_err(|_| Error::StringTooLong)?)?;
+        self.bytes(value.as_bytes())
+    }
+}
+
+/// Reads the fields of a message from the front of a slice.
+struct Reader<'m>(&'m [u8]);
+
+impl<'m> Reader<'m> {
+    fn bytes(&mut self, len: usize) -> Result<&'m [u8], Error> {
+        if self.0.len() < len {
+            return Err(Error::Truncated);
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        Ok(self.bytes(N)?.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, Error> {
+        self.array().map(u16::from_le_bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        self.array().map(u32::from_le_bytes)
+    }
+
+    fn u64(&mut self) -> Result<u64, Error> {
+        self.array().map(u64::from_le_bytes)
+    }
+
+    /// Reads a string, which is a 16-bit length followed by that many bytes.
+    fn str(&mut self) -> Result<&'m str, Error> {
+        let len = self.u16()?;
+        core::str::from_utf8(self.bytes(len.into())?).map_err(|_| Error::InvalidUtf8)
+    }
+
+    fn qid(&mut self) -> Result<Qid, Error> {
+        Ok(Qid {
+            kind: self.u8()?,
+            version: self.u32()?,
+            path: self.u64()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a message with the given type, tag and fields.
+    fn message(kind: u8, tag: u16, fields: &[u8]) -> Vec<u8> {
+        let mut result = Vec::new();
+        result.extend_from_slice(&(HEADER_SIZE as u32 + fields.len() as u32).to_le_bytes());
+        result.push(kind);
+        result.extend_from_slice(&tag.to_le_bytes());
+        result.extend_from_slice(fields);
+        result
+    }
+
+    fn qid(kind: u8, path: u64) -> Vec<u8> {
+        let mut result = vec![kind, 1, 0, 0, 0];
+        result.extend_from_slice(&path.to_le_bytes());
+        result
+    }
+
+    fn encode(request: Request, tag: u16) -> Vec<u8> {
+        let mut buffer = [0; 256];
+        let len = request.encode(tag, &mut buffer).unwrap();
+        buffer[..len].to_vec()
+    }
+
+    #[test]
+    fn encode_version() {
+        let request = Request::Version {
+            msize: 4096,
+            version: VERSION,
+        };
+        assert_eq!(
+            encode(request, NOTAG),
+            message(100, NOTAG, b"\x00\x10\x00\x00\x08\x009P2000.L")
+        );
+    }
+
+    #[test]
+    fn encode_walk() {
+        let request = Request::Walk {
+            fid: 0,
+            newfid: 1,
+            path: "/bin//hello/",
+        };
+        assert_eq!(
+            encode(request, 2),
+            message(
+                110,
+                2,
+                b"\x00\x00\x00\x00\x01\x00\x00\x00\x02\x00\x03\x00bin\x05\x00hello"
+            )
+        );
+
+        // an empty path walks nowhere, which clones the fid
+        let request = Request::Walk {
+            fid: 0,
+            newfid: 1,
+            path: "/",
+        };
+        assert_eq!(
+            encode(request, 2),
+            message(110, 2, b"\x00\x00\x00\x00\x01\x00\x00\x00\x00\x00")
+        );
+    }
+
+    #[test]
+    fn encode_invalid() {
+        let path = "a/".repeat(MAX_WALK + 1);
+        let request = Request::Walk {
+            fid: 0,
+            newfid: 1,
+            path: &path,
+        };
+        assert_eq!(
+            request.encode(0, &mut [0; 256]),
+            Err(Error::TooManyComponents)
+        );
+
+        let request = Request::Clunk { fid: 0 };
+        assert_eq!(request.encode(0, &mut [0; 10]), Err(Error::BufferTooSmall));
+        assert_eq!(request.encode(0, &mut [0; 11]), Ok(11));
+        assert_eq!(request.encode(0, &mut [0; 4]), Err(Error::BufferTooSmall));
+
+        let name = "a".repeat(0x10000);
+        let request = Request::Walk {
+            fid: 0,
+            newfid: 1,
+            path: &name,
+        };
+        assert_eq!(
+            request.encode(0, &mut vec![0; 0x20000]),
+            Err(Error::StringTooLong)
+        );
+    }
+
+    #[test]
+    fn decode_responses() {
+        let version = message(101, NOTAG, b"\x00\x10\x00\x00\x08\x009P2000.L");
+        assert_eq!(
+            decode(&version),
+            Ok((
+                NOTAG,
+                Response::Version {
+                    msize: 4096,
+                    version: VERSION
+                }
+            ))
+        );
+
+        let walk = [&b"\x02\x00"[..], &qid(Qid::DIR, 5), &qid(0, 6)].concat();
+        assert_eq!(
+            decode(&message(111, 3, &walk)),
+            Ok((
+                3,
+                Response::Walk {
+                    walked: 2,
+                    qid: Some(Qid {
+                        kind: 0,
+                        version: 1,
+                        path: 6
+                    })
+                }
+            ))
+        );
+
+        let read = message(117, 4, b"\x05\x00\x00\x00hello");
+        assert_eq!(decode(&read), Ok((4, Response::Read { data: b"hello" })));
+
+        // trailing bytes after the message are ignored
+        let mut error = message(7, 5, b"\x02\x00\x00\x00");
+        error.extend_from_slice(b"junk");
+        assert_eq!(decode(&error), Ok((5, Response::Error { errno: 2 })));
+
+        assert_eq!(decode(&message(121, 6, b"")), Ok((6, Response::Clunk)));
+    }
+
+    #[test]
+    fn decode_getattr() {
+        let mut fields = 0x7ffu64.to_le_bytes().to_vec();
+        fields.extend_from_slice(&qid(0, 7));
+        fields.extend_from_slice(&0o100644u32.to_le_bytes());
+        fields.extend_from_slice(&1000u32.to_le_bytes());
+        fields.extend_from_slice(&100u32.to_le_bytes());
+        for value in [
+            1u64,
+            0,
+            1234,
+            4096,
+            8,
+            0,
+            0,
+            1_700_000_000,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+        ] {
+            fields.extend_from_slice(&value.to_le_bytes());
+        }
+        let getattr = message(25, 0, &fields);
+        let (_, response) = decode(&getattr).unwrap();
+        let Response::Getattr { attr } = response else {
+            panic!("expected Rgetattr, got {response:?}");
+        };
+        assert_eq!(attr.qid.path, 7);
+        assert_eq!(attr.mode, 0o100644);
+        assert_eq!((attr.uid, attr.gid), (1000, 100));
+        assert_eq!(attr.nlink, 1);
+        assert_eq!(attr.size, 1234);
+        assert_eq!(attr.mtime, 1_700_000_000);
+    }
+
+    #[test]
+    fn decode_invalid() {
+        assert_eq!(decode(b"\x07\x00"), Err(Error::Truncated));
+
+        // the size says there's more than there is
+        let mut version = message(101, NOTAG, b"\x00\x10\x00\x00\x08\x009P2000.L");
+        version.pop();
+        assert_eq!(decode(&version), Err(Error::Truncated));
+
+        // the fields say there's more than the size does
+        let read = message(117, 0, b"\x05\x00\x00\x00hell");
+        assert_eq!(decode(&read), Err(Error::Truncated));
+
+        assert_eq!(
+            decode(&message(101, 0, b"\x00\x10\x00\x00\x01\x00\xff")),
+            Err(Error::InvalidUtf8)
+        );
+        assert_eq!(decode(&message(99, 0, b"")), Err(Error::UnknownType(99)));
+        // T-messages aren't responses
+        assert_eq!(decode(&message(100, 0, b"")), Err(Error::UnknownType(100)));
+    }
+
+    #[test]
+    fn dir_entries() {
+        let mut data = qid(Qid::DIR, 1);
+        data.extend_from_slice(&1u64.to_le_bytes());
+        data.extend_from_slice(b"\x04\x03\x00bin");
+        data.extend_from_slice(&qid(0, 2));
+        data.extend_from_slice(&2u64.to_le_bytes());
+        data.extend_from_slice(b"\x08\x05\x00hello");
+        let mut fields = (data.len() as u32).to_le_bytes().to_vec();
+        fields.extend_from_slice(&data);
+
+        let readdir = message(41, 0, &fields);
+        let (_, response) = decode(&readdir).unwrap();
+        let Response::Readdir { entries } = response else {
+            panic!("expected Rreaddir, got {response:?}");
+        };
+        let entries = entries.collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(entries[0].qid.is_dir());
+        assert_eq!(
+            (entries[0].offset, entries[0].kind, entries[0].name),
+            (1, DT_DIR, "bin")
+        );
+        assert!(!entries[1].qid.is_dir());
+        assert_eq!((entries[1].offset, entries[1].name), (2, "hello"));
+
+        // a malformed entry is an error, after which there are no more
+        let mut entries = DirEntries(&data[..data.len() - 1]);
+        assert!(entries.next().unwrap().is_ok());
+        assert_eq!(entries.next(), Some(Err(Error::Truncated)));
+        assert_eq!(entries.next(), None);
+    }
+}
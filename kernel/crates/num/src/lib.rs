@@ -25,3 +25,60 @@ impl_for!(u8);
 impl_for!(u16);
 impl_for!(u32);
 impl_for!(u64);
+
+/// Converts a value to `usize`, or returns `None` if the **value** doesn't fit in a `usize`.
+///
+/// Use this for types that may be wider than a `usize`, or signed, where [`AsUsize`] can't be
+/// implemented.
+pub trait TryAsUsize {
+    fn try_as_usize(self) -> Option<usize>;
+}
+
+macro_rules! impl_try_for {
+    ($($ty:ty),+) => {
+        $(
+            impl TryAsUsize for $ty {
+                fn try_as_usize(self) -> Option<usize> {
+                    usize::try_from(self).ok()
+                }
+            }
+        )+
+    };
+}
+
+impl_try_for!(u64, u128, i8, i16, i32, i64, i128, isize);
+
+/// Extracts a typed value from the bits of a register field, where `B` is the register's type.
+///
+/// This is implemented for every type that `B` can be converted to with [`TryFrom`], so the
+/// conversion fails rather than silently truncating a field that's wider than the type.
+pub trait FromBits<B>: Sized {
+    /// Returns the value of `bits`, or `None` if it doesn't fit in `Self`.
+    fn try_from_bits(bits: B) -> Option<Self>;
+}
+
+impl<B, T: TryFrom<B>> FromBits<B> for T {
+    fn try_from_bits(bits: B) -> Option<Self> {
+        T::try_from(bits).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn try_as_usize() {
+        assert_eq!(u64::MAX.try_as_usize(), Some(usize::MAX));
+        assert_eq!(u128::MAX.try_as_usize(), None);
+        assert_eq!((-1i32).try_as_usize(), None);
+        assert_eq!(42i64.try_as_usize(), Some(42));
+    }
+
+    #[test]
+    fn from_bits() {
+        assert_eq!(u8::try_from_bits(0xFFu32), Some(0xFF));
+        assert_eq!(u8::try_from_bits(0x100u32), None);
+        assert_eq!(u64::try_from_bits(u32::MAX), Some(u64::from(u32::MAX)));
+    }
+}
@@ -0,0 +1,752 @@
+//! A map from ranges of addresses that never overlap to what's in each range, like the regions of
+//! an address space, with O(log n) lookup of the range containing an address.
+//!
+//! The ranges are kept in an AVL tree, whose nodes live in a fixed number of slots in the map
+//! itself, linked by index, so it needs no heap. Freed slots are kept on a list threaded through
+//! them, so inserting and removing are O(log n) too.
+#![cfg_attr(not(test), no_std)]
+
+use core::cmp::Ordering;
+use core::ops::Range;
+
+/// Height of the tallest tree the iterators can walk. An AVL tree this tall would need more nodes
+/// than fit in memory.
+const MAX_HEIGHT: usize = 64;
+
+/// Reason a range couldn't be inserted.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InsertError<K> {
+    /// The range has no addresses in it.
+    Empty,
+    /// Every slot is in use.
+    Full,
+    /// The range overlaps this range, which is already in the map.
+    Overlaps(Range<K>),
+}
+
+/// Map is full, so a range couldn't be split in two.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FullError;
+
+/// Reason two ranges couldn't be merged.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MergeError {
+    /// There isn't a range ending at the address, or one starting there.
+    NotAdjacent,
+    /// The ranges on either side of the address have different values.
+    DifferentValues,
+}
+
+/// Ranges that never overlap, each with a value, in up to `N` slots.
+pub struct RegionTree<K, V, const N: usize> {
+    slots: [Slot<K, V>; N],
+    root: Option<usize>,
+    /// First slot on the list of freed slots.
+    free: Option<usize>,
+    /// First slot that has never been used, which is also true of every slot after it.
+    unused: usize,
+    len: usize,
+}
+
+enum Slot<K, V> {
+    /// A free slot, with the next slot on the list of freed slots.
+    Free(Option<usize>),
+    Used(Node<K, V>),
+}
+
+struct Node<K, V> {
+    range: Range<K>,
+    value: V,
+    left: Option<usize>,
+    right: Option<usize>,
+    /// Height of the subtree rooted here, which is 1 for a leaf.
+    height: u8,
+}
+
+impl<K: Ord + Copy, V, const N: usize> RegionTree<K, V, N> {
+    const FREE: Slot<K, V> = Slot::Free(None);
+
+    pub const fn new() -> Self {
+        Self {
+            slots: [Self::FREE; N],
+            root: None,
+            free: None,
+            unused: 0,
+            len: 0,
+        }
+    }
+
+    /// Returns the number of ranges in the map.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Adds `range`, with `value`, failing if it overlaps a range already in the map.
+    pub fn insert(&mut self, range: Range<K>, value: V) -> Result<(), InsertError<K>> {
+        if range.is_empty() {
+            return Err(InsertError::Empty);
+        }
+        // the ranges never overlap, so they end in the same order they start in, and only the
+        // last one starting before the end of the new range can overlap it
+        if let Some(before) = self.last_starting_before(range.end) {
+            let other = &self.node(before).range;
+            if other.end > range.start {
+                return Err(InsertError::Overlaps(other.clone()));
+            }
+        }
+
+        let index = self.allocate(range, value).ok_or(InsertError::Full)?;
+        self.root = Some(self.insert_node(self.root, index));
+        self.len += 1;
+
+        Ok(())
+    }
+
+    /// Removes the range starting at `start`, returning it and its value, if there is one.
+    pub fn remove(&mut self, start: K) -> Option<(Range<K>, V)> {
+        let (root, removed) = self.remove_node(self.root?, start);
+        self.root = root;
+        let removed = removed?;
+        self.len -= 1;
+
+        let Slot::Used(node) = core::mem::replace(&mut self.slots[removed], Slot::Free(self.free))
+        else {
+            unreachable!("removed slot should be in use");
+        };
+        self.free = Some(removed);
+
+        Some((node.range, node.value))
+    }
+
+    /// Returns the range containing `address`, and its value, if any.
+    pub fn get(&self, address: K) -> Option<(&Range<K>, &V)> {
+        let node = self.node(self.find(address)?);
+
+        Some((&node.range, &node.value))
+    }
+
+    /// Returns the range containing `address`, and a mutable reference to its value, if any.
+    pub fn get_mut(&mut self, address: K) -> Option<(&Range<K>, &mut V)> {
+        let index = self.find(address)?;
+        let node = self.node_mut(index);
+
+        Some((&node.range, &mut node.value))
+    }
+
+    /// Splits the range containing `address` in two, so that one of them starts at `address`,
+    /// each with a copy of the value.
+    ///
+    /// Does nothing if a range already starts at `address`, or no range contains it, since the
+    /// ranges already start or end there. Fails without changing anything if the map is full.
+    pub fn split(&mut self, address: K) -> Result<(), FullError>
+    where
+        V: Clone,
+    {
+        let Some(index) = self.find(address) else {
+            return Ok(());
+        };
+        let node = self.node(index);
+        if node.range.start == address {
+            return Ok(());
+        }
+
+        let upper = address..node.range.end;
+        let value = node.value.clone();
+        let upper = self.allocate(upper, value).ok_or(FullError)?;
+        // the lower half keeps its place in the tree, since it still starts where it did
+        self.node_mut(index).range.end = address;
+        self.root = Some(self.insert_node(self.root, upper));
+        self.len += 1;
+
+        Ok(())
+    }
+
+    /// Merges the range ending at `address` and the range starting there into one, if they have
+    /// the same value.
+    pub fn merge(&mut self, address: K) -> Result<(), MergeError>
+    where
+        V: PartialEq,
+    {
+        let lower = self
+            .last_starting_before(address)
+            .filter(|&lower| self.node(lower).range.end == address)
+            .ok_or(MergeError::NotAdjacent)?;
+        let upper = self
+            .find(address)
+            .filter(|&upper| self.node(upper).range.start == address)
+            .ok_or(MergeError::NotAdjacent)?;
+        if self.node(lower).value != self.node(upper).value {
+            return Err(MergeError::DifferentValues);
+        }
+
+        let (upper, _) = self.remove(address).expect("upper range to be in the map");
+        // removing only relinks the other nodes, so the lower range is still in the same slot
+        self.node_mut(lower).range.end = upper.end;
+
+        Ok(())
+    }
+
+    /// Returns every range and its value, lowest first.
+    pub fn iter(&self) -> Iter<'_, K, V, N> {
+        let mut iter = Iter {
+            tree: self,
+            stack: [0; MAX_HEIGHT],
+            depth: 0,
+        };
+        iter.push_left(self.root);
+
+        iter
+    }
+
+    /// Returns the slot of the range containing `address`, if any.
+    fn find(&self, address: K) -> Option<usize> {
+        let mut next = self.root;
+        while let Some(index) = next {
+            let node = self.node(index);
+            next = if address < node.range.start {
+                node.left
+            } else if address >= node.range.end {
+                node.right
+            } else {
+                return Some(index);
+            };
+        }
+
+        None
+    }
+
+    /// Returns the slot of the last range that starts before `address`, if any.
+    fn last_starting_before(&self, address: K) -> Option<usize> {
+        let (mut next, mut found) = (self.root, None);
+        while let Some(index) = next {
+            let node = self.node(index);
+            if node.range.start < address {
+                found = Some(index);
+                next = node.right;
+            } else {
+                next = node.left;
+            }
+        }
+
+        found
+    }
+
+    /// Puts a new node in a free slot, returning the slot, or `None` if every slot is in use.
+    fn allocate(&mut self, range: Range<K>, value: V) -> Option<usize> {
+        let index = match self.free {
+            Some(index) => {
+                let Slot::Free(next) = self.slots[index] else {
+                    unreachable!("slot on the free list should be free");
+                };
+                self.free = next;
+                index
+            }
+            None if self.unused < N => {
+                self.unused += 1;
+                self.unused - 1
+            }
+            None => return None,
+        };
+        self.slots[index] = Slot::Used(Node {
+            range,
+            value,
+            left: None,
+            right: None,
+            height: 1,
+        });
+
+        Some(index)
+    }
+
+    /// Inserts the node in slot `new` into the subtree rooted at `root`, returning the new root of
+    /// the subtree.
+    fn insert_node(&mut self, root: Option<usize>, new: usize) -> usize {
+        let Some(root) = root else {
+            return new;
+        };
+
+        if self.node(new).range.start < self.node(root).range.start {
+            let left = self.insert_node(self.node(root).left, new);
+            self.node_mut(root).left = Some(left);
+        } else {
+            let right = self.insert_node(self.node(root).right, new);
+            self.node_mut(root).right = Some(right);
+        }
+
+        self.rebalance(root)
+    }
+
+    /// Unlinks the node whose range starts at `start` from the subtree rooted at `root`, returning
+    /// the new root of the subtree and the unlinked node's slot, if it was found.
+    fn remove_node(&mut self, root: usize, start: K) -> (Option<usize>, Option<usize>) {
+        let node = self.node(root);
+        let (left, right) = (node.left, node.right);
+        match start.cmp(&node.range.start) {
+            Ordering::Less => {
+                let Some(left) = left else {
+                    return (Some(root), None);
+                };
+                let (left, removed) = self.remove_node(left, start);
+                self.node_mut(root).left = left;
+                (Some(self.rebalance(root)), removed)
+            }
+            Ordering::Greater => {
+                let Some(right) = right else {
+                    return (Some(root), None);
+                };
+                let (right, removed) = self.remove_node(right, start);
+                self.node_mut(root).right = right;
+                (Some(self.rebalance(root)), removed)
+            }
+            Ordering::Equal => match (left, right) {
+                (None, child) | (child, None) => (child, Some(root)),
+                (Some(left), Some(right)) => {
+                    // replace the node with the lowest node to its right
+                    let (right, lowest) = self.remove_lowest(right);
+                    let lowest_node = self.node_mut(lowest);
+                    lowest_node.left = Some(left);
+                    lowest_node.right = right;
+                    (Some(self.rebalance(lowest)), Some(root))
+                }
+            },
+        }
+    }
+
+    /// Unlinks the lowest node in the subtree rooted at `root`, returning the new root of the
+    /// subtree and the unlinked node's slot.
+    fn remove_lowest(&mut self, root: usize) -> (Option<usize>, usize) {
+        let node = self.node(root);
+        match node.left {
+            None => (node.right, root),
+            Some(left) => {
+                let (left, lowest) = self.remove_lowest(left);
+                self.node_mut(root).left = left;
+                (Some(self.rebalance(root)), lowest)
+            }
+        }
+    }
+
+    /// Updates the height of the node in slot `root`, and rotates it if its subtrees differ in
+    /// height by more than one, returning the new root of its subtree.
+    fn rebalance(&mut self, root: usize) -> usize {
+        self.update_height(root);
+        let node = self.node(root);
+        let balance = i16::from(self.height(node.right)) - i16::from(self.height(node.left));
+
+        if balance < -1 {
+            let left = node.left.expect("left-heavy node to have a left subtree");
+            let left_node = self.node(left);
+            if self.height(left_node.right) > self.height(left_node.left) {
+                let left = self.rotate_left(left);
+                self.node_mut(root).left = Some(left);
+            }
+            self.rotate_right(root)
+        } else if balance > 1 {
+            let right = node
+                .right
+                .expect("right-heavy node to have a right subtree");
+            let right_node = self.node(right);
+            if self.height(right_node.left) > self.height(right_node.right) {
+                let right = self.rotate_right(right);
+                self.node_mut(root).right = Some(right);
+            }
+            self.rotate_left(root)
+        } else {
+            root
+        }
+    }
+
+    /// Makes the left child of `root` the root of its subtree, returning it.
+    fn rotate_right(&mut self, root: usize) -> usize {
+        let left = self.node(root).left.expect("node to have a left child");
+        self.node_mut(root).left = self.node(left).right;
+        self.node_mut(left).right = Some(root);
+        self.update_height(root);
+        self.update_height(left);
+
+        left
+    }
+
+    /// Makes the right child of `root` the root of its subtree, returning it.
+    fn rotate_left(&mut self, root: usize) -> usize {
+        let right = self.node(root).right.expect("node to have a right child");
+        self.node_mut(root).right = self.node(right).left;
+        self.node_mut(right).left = Some(root);
+        self.update_height(root);
+        self.update_height(right);
+
+        right
+    }
+
+    fn update_height(&mut self, index: usize) {
+        let node = self.node(index);
+        let height = 1 + self.height(node.left).max(self.height(node.right));
+        self.node_mut(index).height = height;
+    }
+
+    fn height(&self, index: Option<usize>) -> u8 {
+        index.map_or(0, |index| self.node(index).height)
+    }
+
+    fn node(&self, index: usize) -> &Node<K, V> {
+        match &self.slots[index] {
+            Slot::Used(node) => node,
+            Slot::Free(_) => unreachable!("slot {index} should be in use"),
+        }
+    }
+
+    fn node_mut(&mut self, index: usize) -> &mut Node<K, V> {
+        match &mut self.slots[index] {
+            Slot::Used(node) => node,
+            Slot::Free(_) => unreachable!("slot {index} should be in use"),
+        }
+    }
+}
+
+impl<K: Ord + Copy, V, const N: usize> Default for RegionTree<K, V, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Ord + Copy + core::fmt::Debug, V: core::fmt::Debug, const N: usize> core::fmt::Debug
+    for RegionTree<K, V, N>
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+/// Iterator over the ranges in a [`RegionTree`], lowest first.
+pub struct Iter<'t, K, V, const N: usize> {
+    tree: &'t RegionTree<K, V, N>,
+    /// Nodes whose ranges are yet to be returned, along with everything to their right.
+    stack: [usize; MAX_HEIGHT],
+    depth: usize,
+}
+
+impl<'t, K: Ord + Copy, V, const N: usize> Iter<'t, K, V, N> {
+    fn push_left(&mut self, mut next: Option<usize>) {
+        while let Some(index) = next {
+            self.stack[self.depth] = index;
+            self.depth += 1;
+            next = self.tree.node(index).left;
+        }
+    }
+}
+
+impl<'t, K: Ord + Copy, V, const N: usize> Iterator for Iter<'t, K, V, N> {
+    type Item = (&'t Range<K>, &'t V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.depth == 0 {
+            return None;
+        }
+        self.depth -= 1;
+        let node = self.tree.node(self.stack[self.depth]);
+        self.push_left(node.right);
+
+        Some((&node.range, &node.value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use proptest::prelude::*;
+
+    use super::*;
+
+    /// Checks that the tree is ordered and balanced, and that every height is right, returning
+    /// the height of the tree.
+    fn check<K: Ord + Copy, V, const N: usize>(tree: &RegionTree<K, V, N>) -> u8 {
+        fn subtree<K: Ord + Copy, V, const N: usize>(
+            tree: &RegionTree<K, V, N>,
+            index: Option<usize>,
+            count: &mut usize,
+        ) -> u8 {
+            let Some(index) = index else {
+                return 0;
+            };
+            *count += 1;
+            let node = tree.node(index);
+            if let Some(left) = node.left {
+                assert!(tree.node(left).range.end <= node.range.start);
+            }
+            if let Some(right) = node.right {
+                assert!(tree.node(right).range.start >= node.range.end);
+            }
+            let left = subtree(tree, node.left, count);
+            let right = subtree(tree, node.right, count);
+            assert!(
+                left.abs_diff(right) <= 1,
+                "subtrees differ in height too much"
+            );
+            assert_eq!(node.height, 1 + left.max(right));
+
+            node.height
+        }
+
+        let mut count = 0;
+        let height = subtree(tree, tree.root, &mut count);
+        assert_eq!(count, tree.len());
+        let ranges = tree.iter().map(|(range, _)| range).collect::<Vec<_>>();
+        assert!(ranges.windows(2).all(|pair| pair[0].end <= pair[1].start));
+
+        height
+    }
+
+    fn ranges<V: Copy, const N: usize>(tree: &RegionTree<u32, V, N>) -> Vec<(Range<u32>, V)> {
+        tree.iter()
+            .map(|(range, &value)| (range.clone(), value))
+            .collect()
+    }
+
+    #[test]
+    fn insert_and_get() {
+        let mut tree = RegionTree::<u32, char, 8>::new();
+        tree.insert(20..30, 'b').unwrap();
+        tree.insert(0..10, 'a').unwrap();
+        tree.insert(30..40, 'c').unwrap();
+        check(&tree);
+
+        assert_eq!(tree.get(0), Some((&(0..10), &'a')));
+        assert_eq!(tree.get(9), Some((&(0..10), &'a')));
+        assert_eq!(tree.get(10), None);
+        assert_eq!(tree.get(30), Some((&(30..40), &'c')));
+        assert_eq!(tree.get(40), None);
+        *tree.get_mut(25).unwrap().1 = 'B';
+        assert_eq!(ranges(&tree), [(0..10, 'a'), (20..30, 'B'), (30..40, 'c')]);
+    }
+
+    #[test]
+    fn insert_errors() {
+        let mut tree = RegionTree::<u32, (), 2>::new();
+        tree.insert(10..20, ()).unwrap();
+        assert_eq!(tree.insert(5..5, ()), Err(InsertError::Empty));
+        assert_eq!(tree.insert(5..11, ()), Err(InsertError::Overlaps(10..20)));
+        assert_eq!(tree.insert(19..21, ()), Err(InsertError::Overlaps(10..20)));
+        assert_eq!(tree.insert(0..30, ()), Err(InsertError::Overlaps(10..20)));
+        assert_eq!(tree.insert(12..13, ()), Err(InsertError::Overlaps(10..20)));
+        tree.insert(20..30, ()).unwrap();
+        assert_eq!(tree.insert(0..10, ()), Err(InsertError::Full));
+        assert_eq!(tree.len(), 2);
+    }
+
+    #[test]
+    fn remove() {
+        let mut tree = RegionTree::<u32, u32, 16>::new();
+        for i in 0..16 {
+            tree.insert(i * 10..i * 10 + 5, i).unwrap();
+        }
+        assert_eq!(tree.remove(12), None);
+        assert_eq!(tree.remove(70), Some((70..75, 7)));
+        assert_eq!(tree.remove(70), None);
+        assert_eq!(tree.get(72), None);
+        check(&tree);
+
+        // the freed slot is used again
+        tree.insert(70..80, 70).unwrap();
+        assert_eq!(tree.get(79), Some((&(70..80), &70)));
+        for i in 0..16 {
+            assert!(tree.remove(i * 10).is_some());
+            check(&tree);
+        }
+        assert!(tree.is_empty());
+    }
+
+    #[test]
+    fn split() {
+        let mut tree = RegionTree::<u32, char, 4>::new();
+        tree.insert(0..100, 'a').unwrap();
+        tree.insert(200..300, 'b').unwrap();
+
+        // inside a range, so it becomes two with the same value
+        tree.split(40).unwrap();
+        assert_eq!(
+            ranges(&tree),
+            [(0..40, 'a'), (40..100, 'a'), (200..300, 'b')]
+        );
+
+        // at the start of a range, or outside every range, there's already a boundary
+        tree.split(40).unwrap();
+        tree.split(0).unwrap();
+        tree.split(150).unwrap();
+        tree.split(300).unwrap();
+        assert_eq!(tree.len(), 3);
+
+        // the two halves are independent
+        *tree.get_mut(50).unwrap().1 = 'c';
+        tree.split(250).unwrap();
+        assert_eq!(
+            ranges(&tree),
+            [
+                (0..40, 'a'),
+                (40..100, 'c'),
+                (200..250, 'b'),
+                (250..300, 'b')
+            ]
+        );
+        check(&tree);
+
+        // a split needs a slot of its own
+        assert_eq!(tree.split(10), Err(FullError));
+        assert_eq!(tree.get(10), Some((&(0..40), &'a')));
+    }
+
+    #[test]
+    fn merge() {
+        let mut tree = RegionTree::<u32, char, 4>::new();
+        tree.insert(0..10, 'a').unwrap();
+        tree.insert(10..20, 'a').unwrap();
+        tree.insert(20..30, 'b').unwrap();
+        tree.insert(40..50, 'b').unwrap();
+
+        assert_eq!(tree.merge(20), Err(MergeError::DifferentValues));
+        assert_eq!(tree.merge(30), Err(MergeError::NotAdjacent));
+        assert_eq!(tree.merge(5), Err(MergeError::NotAdjacent));
+        assert_eq!(tree.merge(0), Err(MergeError::NotAdjacent));
+        tree.merge(10).unwrap();
+        assert_eq!(ranges(&tree), [(0..20, 'a'), (20..30, 'b'), (40..50, 'b')]);
+        check(&tree);
+
+        // splitting then merging gives back the range as it was
+        tree.split(45).unwrap();
+        tree.merge(45).unwrap();
+        assert_eq!(ranges(&tree), [(0..20, 'a'), (20..30, 'b'), (40..50, 'b')]);
+    }
+
+    #[test]
+    fn balanced() {
+        // inserting in order is the worst case for an unbalanced tree
+        let mut tree = RegionTree::<u32, (), 1023>::new();
+        for i in 0..1023 {
+            tree.insert(i..i + 1, ()).unwrap();
+        }
+        // an AVL tree of n nodes is at most 1.44 log2(n + 2) tall
+        assert!(check(&tree) <= 14);
+
+        for i in (0..1023).step_by(2) {
+            tree.remove(i).unwrap();
+        }
+        assert!(check(&tree) <= 13);
+    }
+
+    #[derive(Clone, Debug)]
+    enum Command {
+        Insert(u8, u8, u8),
+        Remove(u8),
+        Split(u8),
+        Merge(u8),
+    }
+
+    fn command() -> impl Strategy<Value = Command> {
+        prop_oneof![
+            (any::<u8>(), 1..16u8, 0..3u8)
+                .prop_map(|(start, len, value)| Command::Insert(start, len, value)),
+            any::<u8>().prop_map(Command::Remove),
+            any::<u8>().prop_map(Command::Split),
+            any::<u8>().prop_map(Command::Merge),
+        ]
+    }
+
+    proptest! {
+        /// Every command does the same to the tree as it does to a model, which is a map from the
+        /// start of each range to its end and value.
+        #[test]
+        fn matches_model(commands in prop::collection::vec(command(), 1..200)) {
+            let mut tree = RegionTree::<u32, u8, 32>::new();
+            let mut model = BTreeMap::<u32, (u32, u8)>::new();
+            let containing = |model: &BTreeMap<u32, (u32, u8)>, address: u32| {
+                model
+                    .range(..=address)
+                    .next_back()
+                    .filter(|(_, &(end, _))| address < end)
+                    .map(|(&start, &(end, value))| (start, end, value))
+            };
+
+            for command in commands {
+                match command {
+                    Command::Insert(start, len, value) => {
+                        let range = u32::from(start)..u32::from(start) + u32::from(len);
+                        let overlaps = model
+                            .iter()
+                            .any(|(&start, &(end, _))| start < range.end && range.start < end);
+                        let result = tree.insert(range.clone(), value);
+                        if overlaps {
+                            prop_assert!(matches!(result, Err(InsertError::Overlaps(_))));
+                        } else if model.len() == 32 {
+                            prop_assert_eq!(result, Err(InsertError::Full));
+                        } else {
+                            prop_assert_eq!(result, Ok(()));
+                            model.insert(range.start, (range.end, value));
+                        }
+                    }
+                    Command::Remove(start) => {
+                        let start = u32::from(start);
+                        let expected = model
+                            .remove(&start)
+                            .map(|(end, value)| (start..end, value));
+                        prop_assert_eq!(tree.remove(start), expected);
+                    }
+                    Command::Split(address) => {
+                        let address = u32::from(address);
+                        let result = tree.split(address);
+                        match containing(&model, address) {
+                            Some((start, end, value)) if start != address => {
+                                if model.len() == 32 {
+                                    prop_assert_eq!(result, Err(FullError));
+                                } else {
+                                    prop_assert_eq!(result, Ok(()));
+                                    model.insert(start, (address, value));
+                                    model.insert(address, (end, value));
+                                }
+                            }
+                            _ => prop_assert_eq!(result, Ok(())),
+                        }
+                    }
+                    Command::Merge(address) => {
+                        let address = u32::from(address);
+                        let lower = address
+                            .checked_sub(1)
+                            .and_then(|last| containing(&model, last))
+                            .filter(|&(_, end, _)| end == address);
+                        let upper = model.get(&address).copied();
+                        let result = tree.merge(address);
+                        match (lower, upper) {
+                            (Some((start, _, lower)), Some((end, upper))) => {
+                                if lower == upper {
+                                    prop_assert_eq!(result, Ok(()));
+                                    model.remove(&address);
+                                    model.insert(start, (end, lower));
+                                } else {
+                                    prop_assert_eq!(result, Err(MergeError::DifferentValues));
+                                }
+                            }
+                            _ => prop_assert_eq!(result, Err(MergeError::NotAdjacent)),
+                        }
+                    }
+                }
+
+                check(&tree);
+                let expected = model
+                    .iter()
+                    .map(|(&start, &(end, value))| (start..end, value))
+                    .collect::<Vec<_>>();
+                prop_assert_eq!(ranges(&tree), expected);
+                for address in 0..300 {
+                    let expected = containing(&model, address);
+                    let actual = tree
+                        .get(address)
+                        .map(|(range, &value)| (range.start, range.end, value));
+                    prop_assert_eq!(actual, expected);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,581 @@
+//! The binary format of the kernel's trace buffer, which is written by the kernel and dumped with
+//! GDB (see tools/gdb/dump_trace.py), then decoded by host tools like `cargo xtask trace convert`.
+//!
+//! The buffer is a [`Header`] followed by a ring of fixed-size records, each a little-endian
+//! counter timestamp (CNTPCT_EL0) and an encoded [`Event`]. Once the ring is full, new records
+//! overwrite the oldest ones.
+#![cfg_attr(not(test), no_std)]
+
+use core::mem::size_of;
+use core::slice;
+
+/// Magic number at the start of a trace buffer, so we don't decode some other memory by mistake.
+pub const MAGIC: [u8; 8] = *b"PUPTRACE";
+
+/// Version of the format, to be bumped whenever the layout or the event encoding changes.
+pub const VERSION: u32 = 4;
+
+/// Size of an encoded [`Event`].
+const EVENT_SIZE: usize = 8;
+
+/// A trace buffer with room for `N` records.
+#[repr(C)]
+pub struct Buffer<const N: usize> {
+    header: Header,
+    records: [Record; N],
+}
+
+/// The start of a trace buffer.
+///
+/// This has no padding, so it can be read from a dump without caring about the host's layout.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Header {
+    magic: [u8; 8],
+    version: u32,
+    /// Number of records in the ring.
+    capacity: u32,
+    /// Frequency of the counter used for timestamps, in Hz (CNTFRQ_EL0).
+    frequency: u64,
+    /// Number of records ever written, which may be more than the capacity.
+    written: u64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Record {
+    timestamp: u64,
+    event: [u8; EVENT_SIZE],
+}
+
+/// Something that happened in the kernel, as recorded in the trace.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Event {
+    /// The scheduler picked a task to run.
+    TaskPicked {
+        /// Index of the task in the scheduler.
+        task: u8,
+        reason: Reason,
+        /// Number of other tasks ready to run, once this task was picked.
+        ready: u8,
+    },
+    /// The running task was switched away from, having run since it was last picked.
+    TaskRan {
+        task: u8,
+        /// How long the task ran for, in counter ticks, saturating at `u32::MAX`.
+        ran: u32,
+    },
+    /// A task was picked, having been ready to run since it was last switched away from, or since
+    /// it was spawned.
+    TaskWaited {
+        task: u8,
+        /// How long the task waited for, in counter ticks, saturating at `u32::MAX`.
+        waited: u32,
+    },
+    /// A region of RAM was given to the page allocator, which allocates from it with a tree of its
+    /// own (see `buddy_alloc::tree::Tree`).
+    PageAllocator {
+        /// Index of the region in the page allocator.
+        region: u8,
+        placement: Placement,
+        /// Number of pages in the region, which is the number of leaf blocks in its tree.
+        pages: u32,
+    },
+    /// Pages were allocated from a region of the page allocator.
+    PagesAllocated {
+        region: u8,
+        /// Number of pages that were allocated, which is a power of two, since the tree rounds
+        /// every allocation up to one, so it's encoded as its base 2 logarithm.
+        pages: u32,
+        /// Offset of the first page from the start of the region, in pages.
+        offset: u32,
+    },
+    /// An allocation was freed back to a region of the page allocator.
+    PagesFreed {
+        region: u8,
+        /// Offset of the allocation's first page from the start of the region, in pages.
+        offset: u32,
+    },
+    /// No region of the page allocator had room for an allocation.
+    OutOfPages {
+        /// Number of pages that were asked for.
+        pages: u32,
+    },
+}
+
+/// Why the scheduler picked a task.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Reason {
+    /// The scheduler started, and this is the first task.
+    Start = 0,
+    /// The previous task's time slice ran out.
+    Preempted = 1,
+    /// The previous task gave up the rest of its time slice.
+    Yielded = 2,
+    /// The previous task exited.
+    Exited = 3,
+}
+
+/// How a region of the page allocator chooses which free pages satisfy an allocation, like
+/// `buddy_alloc::tree::Placement`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Placement {
+    FirstFit = 0,
+    BestFit = 1,
+    TopDown = 2,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DecodeError {
+    /// The dump is shorter than its header says it should be.
+    Truncated,
+    /// The dump doesn't start with [`MAGIC`].
+    BadMagic,
+    /// The dump was written with a different version of the format.
+    UnsupportedVersion { version: u32 },
+    /// A record has an event kind we don't know about.
+    UnknownEvent { kind: u8 },
+    /// A [`Event::TaskPicked`] record has a reason we don't know about.
+    UnknownReason { reason: u8 },
+    /// A [`Event::PageAllocator`] record has a placement we don't know about.
+    UnknownPlacement { placement: u8 },
+    /// A [`Event::PagesAllocated`] record has a size too large for a `u32`.
+    BadAllocationSize { log2: u8 },
+}
+
+impl<const N: usize> Buffer<N> {
+    pub const fn new() -> Self {
+        Self {
+            header: Header {
+                magic: MAGIC,
+                version: VERSION,
+                capacity: N as u32,
+                frequency: 0,
+                written: 0,
+            },
+            records: [Record {
+                timestamp: 0,
+                event: [0; EVENT_SIZE],
+            }; N],
+        }
+    }
+
+    /// Sets the frequency of the counter used for timestamps, in Hz.
+    pub fn set_frequency(&mut self, frequency: u64) {
+        self.header.frequency = frequency;
+    }
+
+    /// Records an event at the given counter value, overwriting the oldest record if full.
+    pub fn push(&mut self, timestamp: u64, event: Event) {
+        let index = (self.header.written % N as u64) as usize;
+        self.records[index] = Record {
+            timestamp,
+            event: event.encode(),
+        };
+        self.header.written += 1;
+    }
+
+    /// Copies up to `max` records to `copy`, starting with record `next`, returning the record to
+    /// carry on from, or `None` once the copy is complete. Start with `next` of 0.
+    ///
+    /// Records can be pushed between calls, so the copy can be taken a few records at a time
+    /// rather than all at once. Once the last record has been copied, the records pushed since the
+    /// first call are copied again, so the finished copy is the buffer as it is then.
+    pub fn copy_into(&self, copy: &mut Self, next: usize, max: usize) -> Option<usize> {
+        if next == 0 {
+            copy.header = self.header;
+        }
+        let end = next.saturating_add(max).min(N);
+        if next < end {
+            copy.records[next..end].copy_from_slice(&self.records[next..end]);
+        }
+        if end < N {
+            return Some(end);
+        }
+
+        // copy the records that were overwritten after we copied them, or all of them if the copy
+        // was started so long ago (or its header is so corrupt) that we can't tell which
+        let started = copy.header.written;
+        let pushed = self.header.written.checked_sub(started);
+        match pushed {
+            Some(pushed) if pushed < N as u64 => {
+                for written in started..self.header.written {
+                    let index = (written % N as u64) as usize;
+                    copy.records[index] = self.records[index];
+                }
+            }
+            _ => copy.records = self.records,
+        }
+        copy.header = self.header;
+
+        None
+    }
+
+    /// Returns the buffer as bytes, as it would appear in a dump.
+    pub fn as_bytes(&self) -> &[u8] {
+        // SAFETY: the buffer is `repr(C)`, and neither the header nor the records have padding.
+        unsafe { slice::from_raw_parts((self as *const Self).cast(), size_of::<Self>()) }
+    }
+}
+
+impl<const N: usize> Default for Buffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Event {
+    const TASK_PICKED: u8 = 1;
+    const TASK_RAN: u8 = 2;
+    const TASK_WAITED: u8 = 3;
+    const PAGE_ALLOCATOR: u8 = 4;
+    const PAGES_ALLOCATED: u8 = 5;
+    const PAGES_FREED: u8 = 6;
+    const OUT_OF_PAGES: u8 = 7;
+
+    fn encode(self) -> [u8; EVENT_SIZE] {
+        match self {
+            Self::TaskPicked {
+                task,
+                reason,
+                ready,
+            } => [Self::TASK_PICKED, task, reason as u8, ready, 0, 0, 0, 0],
+            Self::TaskRan { task, ran } => Self::encode_duration(Self::TASK_RAN, task, ran),
+            Self::TaskWaited { task, waited } => {
+                Self::encode_duration(Self::TASK_WAITED, task, waited)
+            }
+            Self::PageAllocator {
+                region,
+                placement,
+                pages,
+            } => {
+                let [a, b, c, d] = pages.to_le_bytes();
+                [Self::PAGE_ALLOCATOR, region, placement as u8, 0, a, b, c, d]
+            }
+            Self::PagesAllocated {
+                region,
+                pages,
+                offset,
+            } => {
+                debug_assert!(pages.is_power_of_two(), "allocations must be powers of two");
+                let [a, b, c, d] = offset.to_le_bytes();
+                [
+                    Self::PAGES_ALLOCATED,
+                    region,
+                    pages.ilog2() as u8,
+                    0,
+                    a,
+                    b,
+                    c,
+                    d,
+                ]
+            }
+            Self::PagesFreed { region, offset } => {
+                let [a, b, c, d] = offset.to_le_bytes();
+                [Self::PAGES_FREED, region, 0, 0, a, b, c, d]
+            }
+            Self::OutOfPages { pages } => {
+                let [a, b, c, d] = pages.to_le_bytes();
+                [Self::OUT_OF_PAGES, 0, 0, 0, a, b, c, d]
+            }
+        }
+    }
+
+    /// Encodes an event about a task with a duration, which is little-endian in the last 4 bytes.
+    fn encode_duration(kind: u8, task: u8, duration: u32) -> [u8; EVENT_SIZE] {
+        let [a, b, c, d] = duration.to_le_bytes();
+
+        [kind, task, 0, 0, a, b, c, d]
+    }
+
+    fn decode(bytes: [u8; EVENT_SIZE]) -> Result<Self, DecodeError> {
+        // the last 4 bytes are a duration, a number of pages or an offset, depending on the kind
+        let last = u32::from_le_bytes(bytes[4..].try_into().unwrap());
+        match bytes[0] {
+            Self::TASK_PICKED => Ok(Self::TaskPicked {
+                task: bytes[1],
+                reason: Reason::decode(bytes[2])?,
+                ready: bytes[3],
+            }),
+            Self::TASK_RAN => Ok(Self::TaskRan {
+                task: bytes[1],
+                ran: last,
+            }),
+            Self::TASK_WAITED => Ok(Self::TaskWaited {
+                task: bytes[1],
+                waited: last,
+            }),
+            Self::PAGE_ALLOCATOR => Ok(Self::PageAllocator {
+                region: bytes[1],
+                placement: Placement::decode(bytes[2])?,
+                pages: last,
+            }),
+            Self::PAGES_ALLOCATED => Ok(Self::PagesAllocated {
+                region: bytes[1],
+                pages: 1u32
+                    .checked_shl(bytes[2].into())
+                    .ok_or(DecodeError::BadAllocationSize { log2: bytes[2] })?,
+                offset: last,
+            }),
+            Self::PAGES_FREED => Ok(Self::PagesFreed {
+                region: bytes[1],
+                offset: last,
+            }),
+            Self::OUT_OF_PAGES => Ok(Self::OutOfPages { pages: last }),
+            kind => Err(DecodeError::UnknownEvent { kind }),
+        }
+    }
+}
+
+impl Reason {
+    fn decode(reason: u8) -> Result<Self, DecodeError> {
+        match reason {
+            0 => Ok(Self::Start),
+            1 => Ok(Self::Preempted),
+            2 => Ok(Self::Yielded),
+            3 => Ok(Self::Exited),
+            reason => Err(DecodeError::UnknownReason { reason }),
+        }
+    }
+}
+
+impl Placement {
+    fn decode(placement: u8) -> Result<Self, DecodeError> {
+        match placement {
+            0 => Ok(Self::FirstFit),
+            1 => Ok(Self::BestFit),
+            2 => Ok(Self::TopDown),
+            placement => Err(DecodeError::UnknownPlacement { placement }),
+        }
+    }
+}
+
+/// A decoded trace dump.
+#[derive(Debug)]
+pub struct Trace<'b> {
+    /// Frequency of the counter used for timestamps, in Hz.
+    pub frequency: u64,
+    /// Number of records that were overwritten before the dump was taken.
+    pub dropped: u64,
+    /// The records in the ring, in the order they were written to it.
+    records: &'b [u8],
+    /// Index of the oldest record in the ring.
+    oldest: usize,
+    len: usize,
+}
+
+/// Decodes a trace buffer dumped from the kernel.
+pub fn decode(bytes: &[u8]) -> Result<Trace<'_>, DecodeError> {
+    let header = bytes
+        .get(..size_of::<Header>())
+        .ok_or(DecodeError::Truncated)?;
+    if header[0..8] != MAGIC {
+        return Err(DecodeError::BadMagic);
+    }
+    let version = u32::from_le_bytes(header[8..12].try_into().unwrap());
+    if version != VERSION {
+        return Err(DecodeError::UnsupportedVersion { version });
+    }
+    let capacity = u32::from_le_bytes(header[12..16].try_into().unwrap()) as usize;
+    let frequency = u64::from_le_bytes(header[16..24].try_into().unwrap());
+    let written = u64::from_le_bytes(header[24..32].try_into().unwrap());
+
+    let records = capacity
+        .checked_mul(size_of::<Record>())
+        .and_then(|len| bytes.get(size_of::<Header>()..)?.get(..len))
+        .ok_or(DecodeError::Truncated)?;
+
+    let len = written.min(capacity as u64) as usize;
+    let dropped = written - len as u64;
+    // once the ring has wrapped, the oldest record is the next one to be overwritten
+    let oldest = if len > 0 {
+        (written % len as u64) as usize
+    } else {
+        0
+    };
+
+    Ok(Trace {
+        frequency,
+        dropped,
+        records,
+        oldest,
+        len,
+    })
+}
+
+impl Trace<'_> {
+    /// Returns the events in the trace, oldest first, with their counter timestamps.
+    pub fn events(&self) -> impl Iterator<Item = Result<(u64, Event), DecodeError>> + '_ {
+        (0..self.len).map(|i| {
+            let index = (self.oldest + i) % self.len;
+            let record = &self.records[index * size_of::<Record>()..][..size_of::<Record>()];
+            let timestamp = u64::from_le_bytes(record[..8].try_into().unwrap());
+            let event = Event::decode(record[8..].try_into().unwrap())?;
+
+            Ok((timestamp, event))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn picked(task: u8, reason: Reason) -> Event {
+        Event::TaskPicked {
+            task,
+            reason,
+            ready: 2,
+        }
+    }
+
+    fn events(trace: &Trace) -> Vec<(u64, Event)> {
+        trace.events().collect::<Result<_, _>>().unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut buffer = Buffer::<4>::new();
+        buffer.set_frequency(62_500_000);
+        buffer.push(10, picked(0, Reason::Start));
+        buffer.push(20, picked(1, Reason::Yielded));
+
+        let trace = decode(buffer.as_bytes()).unwrap();
+        assert_eq!(trace.frequency, 62_500_000);
+        assert_eq!(trace.dropped, 0);
+        assert_eq!(
+            events(&trace),
+            [
+                (10, picked(0, Reason::Start)),
+                (20, picked(1, Reason::Yielded))
+            ]
+        );
+    }
+
+    #[test]
+    fn copy_in_chunks() {
+        let mut buffer = Buffer::<4>::new();
+        for timestamp in 0..6 {
+            buffer.push(timestamp, picked(0, Reason::Yielded));
+        }
+
+        let mut copy = Buffer::<4>::new();
+        assert_eq!(buffer.copy_into(&mut copy, 0, 3), Some(3));
+        // overwrites a record we've copied and one we haven't
+        buffer.push(6, picked(1, Reason::Yielded));
+        buffer.push(7, picked(2, Reason::Yielded));
+        assert_eq!(buffer.copy_into(&mut copy, 3, 3), None);
+        assert_eq!(copy.as_bytes(), buffer.as_bytes());
+
+        // more records than the buffer holds, between chunks
+        assert_eq!(buffer.copy_into(&mut copy, 0, 1), Some(1));
+        for timestamp in 8..20 {
+            buffer.push(timestamp, picked(3, Reason::Yielded));
+        }
+        assert_eq!(buffer.copy_into(&mut copy, 1, 4), None);
+        assert_eq!(copy.as_bytes(), buffer.as_bytes());
+    }
+
+    #[test]
+    fn durations() {
+        let ran = Event::TaskRan {
+            task: 1,
+            ran: 12_500_000,
+        };
+        let waited = Event::TaskWaited {
+            task: 2,
+            waited: u32::MAX,
+        };
+        let mut buffer = Buffer::<2>::new();
+        buffer.push(10, ran);
+        buffer.push(10, waited);
+
+        let trace = decode(buffer.as_bytes()).unwrap();
+        assert_eq!(events(&trace), [(10, ran), (10, waited)]);
+    }
+
+    #[test]
+    fn page_allocator() {
+        let recorded = [
+            Event::PageAllocator {
+                region: 1,
+                placement: Placement::TopDown,
+                pages: 0x12345,
+            },
+            Event::PagesAllocated {
+                region: 1,
+                pages: 0x20000,
+                offset: 0x20000,
+            },
+            Event::PagesFreed {
+                region: 1,
+                offset: 0x20000,
+            },
+            Event::OutOfPages { pages: 0x10000 },
+        ];
+        let mut buffer = Buffer::<4>::new();
+        for event in recorded {
+            buffer.push(10, event);
+        }
+
+        let trace = decode(buffer.as_bytes()).unwrap();
+        assert_eq!(events(&trace), recorded.map(|event| (10, event)));
+    }
+
+    #[test]
+    fn wraparound() {
+        let mut buffer = Buffer::<3>::new();
+        for timestamp in 0..5 {
+            buffer.push(timestamp, picked(timestamp as u8, Reason::Preempted));
+        }
+
+        let trace = decode(buffer.as_bytes()).unwrap();
+        assert_eq!(trace.dropped, 2);
+        let timestamps = events(&trace).iter().map(|&(t, _)| t).collect::<Vec<_>>();
+        assert_eq!(timestamps, [2, 3, 4]);
+    }
+
+    #[test]
+    fn decode_errors() {
+        let buffer = Buffer::<2>::new();
+        let bytes = buffer.as_bytes();
+        assert_eq!(
+            decode(&bytes[..bytes.len() - 1]).unwrap_err(),
+            DecodeError::Truncated
+        );
+
+        let mut bytes = bytes.to_vec();
+        bytes[8] = 1;
+        assert_eq!(
+            decode(&bytes).unwrap_err(),
+            DecodeError::UnsupportedVersion { version: 1 }
+        );
+        bytes[0] = b'?';
+        assert_eq!(decode(&bytes).unwrap_err(), DecodeError::BadMagic);
+
+        let mut buffer = Buffer::<1>::new();
+        buffer.push(0, picked(0, Reason::Start));
+        let mut bytes = buffer.as_bytes().to_vec();
+        bytes[size_of::<Header>() + 8] = 0xFF;
+        let trace = decode(&bytes).unwrap();
+        assert_eq!(
+            trace.events().next(),
+            Some(Err(DecodeError::UnknownEvent { kind: 0xFF }))
+        );
+
+        let mut buffer = Buffer::<1>::new();
+        let allocated = Event::PagesAllocated {
+            region: 0,
+            pages: 1,
+            offset: 0,
+        };
+        buffer.push(0, allocated);
+        let mut bytes = buffer.as_bytes().to_vec();
+        bytes[size_of::<Header>() + 8 + 2] = 32;
+        let trace = decode(&bytes).unwrap();
+        assert_eq!(
+            trace.events().next(),
+            Some(Err(DecodeError::BadAllocationSize { log2: 32 }))
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use crate::tt::IntermediateLevel;
+use crate::IntermediateLevel;
 
 use super::*;
 
@@ -2,11 +2,29 @@ use core::marker::PhantomData;
 use core::mem::ManuallyDrop;
 use core::ops::{Deref, DerefMut};
 
+use crate::granule::OUTPUT_ADDRESS_MASK;
+
 mod block;
-mod page;
+pub mod page;
 mod table;
 
+/// Bits of a descriptor that can't be changed in a live translation table without
+/// break-before-make: the type, output address, memory attributes (AttrIndx and SH), nG and the
+/// contiguous bit. Permissions and the access flag can be changed in place.
+const BREAK_BEFORE_MAKE_MASK: u64 =
+    0b11 | OUTPUT_ADDRESS_MASK | 0b111 << 2 | 0b11 << 8 | 1 << 11 | 1 << 52;
+
+/// Returns whether replacing the descriptor `old_bits` with `new_bits` in a live translation table
+/// needs break-before-make, that is, whether both are valid and they differ in any of the bits
+/// that the architecture requires it for.
+pub fn requires_break_before_make(old_bits: u64, new_bits: u64) -> bool {
+    let valid = |bits| bits & 1 == 1;
+
+    valid(old_bits) && valid(new_bits) && (old_bits ^ new_bits) & BREAK_BEFORE_MAKE_MASK != 0
+}
+
 #[derive(Debug)]
+#[repr(transparent)]
 pub struct Descriptor<L, Ty = Unknown> {
     bits: u64,
     phantom: PhantomData<(L, Ty)>,
@@ -16,6 +34,8 @@ impl<L, Ty> Descriptor<L, Ty> {
     /// Bit representation of an invalid descriptor.
     pub const INVALID_BITS: u64 = 0;
 
+    /// # Safety
+    /// `bits` must be a valid descriptor of type `Ty` for a table at level `L`.
     pub unsafe fn from_bits_unchecked(bits: u64) -> Self {
         Self {
             bits,
@@ -23,6 +43,11 @@ impl<L, Ty> Descriptor<L, Ty> {
         }
     }
 
+    /// Returns the raw value of the descriptor.
+    pub fn bits(&self) -> u64 {
+        self.bits
+    }
+
     pub fn into_inner(self) -> u64 {
         let bits = self.bits;
 
@@ -65,6 +90,8 @@ impl<L> DescriptorRefMut<'_, L> {
 }
 
 impl<L, Ty> DescriptorRefMut<'_, L, Ty> {
+    /// # Safety
+    /// See [`Descriptor::from_bits_unchecked`].
     pub unsafe fn from_bits_unchecked(bits: u64) -> Self {
         let inner = Descriptor::from_bits_unchecked(bits);
 
@@ -118,8 +145,8 @@ macro_rules! define_descriptor {
         #[derive(Debug)]
         pub struct $ty;
 
-        type $descriptor<L> = Descriptor<L, $ty>;
-        type $builder<L> = DescriptorBuilder<L, $ty>;
+        pub type $descriptor<L> = Descriptor<L, $ty>;
+        pub type $builder<L> = DescriptorBuilder<L, $ty>;
 
         impl<L> From<$descriptor<L>> for Descriptor<L> {
             fn from(value: $descriptor<L>) -> Self {
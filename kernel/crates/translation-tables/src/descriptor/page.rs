@@ -0,0 +1,233 @@
+use core::marker::PhantomData;
+
+use crate::FinalLevel;
+
+use super::*;
+
+/// Access flag (AF), which is set when the page is first accessed.
+pub const ACCESS_FLAG: u64 = 1 << 10;
+/// AP[2], which makes the page read-only.
+pub const READ_ONLY: u64 = 1 << 7;
+/// Privileged execute-never (PXN) and unprivileged execute-never (UXN).
+pub const EXECUTE_NEVER: u64 = 0b11 << 53;
+/// Dirty bit modifier (DBM), which lets the MMU clear [`READ_ONLY`] on the first write to the
+/// page, to record that it's dirty.
+pub const DIRTY_BIT_MODIFIER: u64 = 1 << 51;
+
+/// Shift of AttrIndx, the index of the page's attributes in MAIR_EL1 (see [`MemoryType`]).
+const ATTR_INDEX_SHIFT: u32 = 2;
+/// AttrIndx (see [`ATTR_INDEX_SHIFT`]).
+const ATTR_INDEX: u64 = 0b111 << ATTR_INDEX_SHIFT;
+
+/// Value of MAIR_EL1, with the attributes for each [`MemoryType`] at its index. entry.s sets this
+/// before enabling the MMU.
+pub const MAIR: u64 = {
+    let mut mair = 0;
+    let mut i = 0;
+    while i < MemoryType::ALL.len() {
+        let ty = MemoryType::ALL[i];
+        mair |= (ty.attributes() as u64) << (8 * ty as u64);
+        i += 1;
+    }
+
+    mair
+};
+
+/// Memory type of a page, which is the index of its attributes in [`MAIR`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MemoryType {
+    /// Normal memory, write-back cacheable, for RAM.
+    #[default]
+    Normal = 0,
+    /// Normal memory that isn't cached, but where accesses can still be merged and reordered, for
+    /// framebuffers and DMA buffers that devices read without snooping caches.
+    NormalNonCacheable = 1,
+    /// Device-nGnRnE memory, the strictest type, which entry.s maps devices with.
+    DeviceNgnrne = 2,
+    /// Device-nGnRE memory, for device registers, where writes can complete before they reach the
+    /// device.
+    Device = 3,
+    /// Device-GRE memory, where accesses can also be merged and reordered, for device memory that
+    /// isn't registers.
+    DeviceGre = 4,
+}
+
+impl MemoryType {
+    const ALL: [Self; 5] = [
+        Self::Normal,
+        Self::NormalNonCacheable,
+        Self::DeviceNgnrne,
+        Self::Device,
+        Self::DeviceGre,
+    ];
+
+    /// Returns the attributes of this memory type, as an Attr<n> field of MAIR_EL1.
+    const fn attributes(self) -> u8 {
+        match self {
+            // inner and outer write-back non-transient, read- and write-allocate
+            Self::Normal => 0xFF,
+            // inner and outer non-cacheable
+            Self::NormalNonCacheable => 0x44,
+            Self::DeviceNgnrne => 0x00,
+            Self::Device => 0x04,
+            Self::DeviceGre => 0x0C,
+        }
+    }
+
+    /// Returns the memory type with attributes at `index` in [`MAIR`], if any.
+    pub fn from_index(index: u64) -> Option<Self> {
+        Self::ALL.into_iter().find(|&ty| ty as u64 == index)
+    }
+
+    /// Returns whether this is a Device memory type, which must never be executable, since the
+    /// processor could speculatively fetch instructions from it.
+    pub fn is_device(self) -> bool {
+        matches!(self, Self::DeviceNgnrne | Self::Device | Self::DeviceGre)
+    }
+}
+
+/// How a page can be accessed and what type of memory it is, for
+/// [`TranslationTable::map_page`](crate::table::TranslationTable::map_page).
+///
+/// Flags alone (like "rw") convert to Normal memory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MapAttributes<'f> {
+    pub(crate) flags: &'f str,
+    pub(crate) memory_type: MemoryType,
+}
+
+impl<'f> MapAttributes<'f> {
+    /// Creates attributes for Normal memory with the given flags (see [`permission_bits`]).
+    pub fn new(flags: &'f str) -> Self {
+        Self {
+            flags,
+            memory_type: MemoryType::Normal,
+        }
+    }
+
+    /// Sets the memory type.
+    pub fn memory_type(mut self, memory_type: MemoryType) -> Self {
+        self.memory_type = memory_type;
+        self
+    }
+
+    /// Returns whether these attributes would make Device memory executable.
+    pub fn is_executable_device(&self) -> bool {
+        self.memory_type.is_device() && self.flags.contains('x')
+    }
+}
+
+impl<'f> From<&'f str> for MapAttributes<'f> {
+    fn from(flags: &'f str) -> Self {
+        Self::new(flags)
+    }
+}
+
+/// Returns the permission bits ([`READ_ONLY`] and [`EXECUTE_NEVER`]) for `flags`, which says how
+/// the page can be accessed, like "rw" or "rx". Pages are always readable.
+pub fn permission_bits(flags: &str) -> u64 {
+    debug_assert!(
+        flags.chars().all(|c| matches!(c, 'r' | 'w' | 'x')),
+        "unknown flags {flags:?}"
+    );
+    let mut bits = 0;
+    if !flags.contains('w') {
+        bits |= READ_ONLY;
+    }
+    if !flags.contains('x') {
+        bits |= EXECUTE_NEVER;
+    }
+
+    bits
+}
+
+impl<L: FinalLevel> DescriptorBuilder<L> {
+    pub fn page(self, pa: usize) -> PageDescriptorBuilder<L> {
+        // TODO: verify PA alignment and size, attributes
+        let bits = pa as u64 | 0b11;
+
+        PageDescriptorBuilder {
+            bits,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<L: FinalLevel> PageDescriptorBuilder<L> {
+    pub fn access_flag(mut self, access_flag: bool) -> PageDescriptorBuilder<L> {
+        if access_flag {
+            self.bits |= 1 << 10;
+        } else {
+            self.bits &= !(1 << 10);
+        }
+
+        self
+    }
+
+    /// Sets the permissions for the page from `flags` (see [`permission_bits`]).
+    pub fn permissions(mut self, flags: &str) -> PageDescriptorBuilder<L> {
+        self.bits &= !(READ_ONLY | EXECUTE_NEVER);
+        self.bits |= permission_bits(flags);
+
+        self
+    }
+
+    /// Sets the memory type of the page.
+    pub fn memory_type(mut self, memory_type: MemoryType) -> PageDescriptorBuilder<L> {
+        self.bits &= !ATTR_INDEX;
+        self.bits |= (memory_type as u64) << ATTR_INDEX_SHIFT;
+
+        self
+    }
+
+    pub fn read_only(mut self, read_only: bool) -> PageDescriptorBuilder<L> {
+        if read_only {
+            self.bits |= READ_ONLY;
+        } else {
+            self.bits &= !READ_ONLY;
+        }
+
+        self
+    }
+
+    pub fn dirty_bit_modifier(mut self, dirty_bit_modifier: bool) -> PageDescriptorBuilder<L> {
+        if dirty_bit_modifier {
+            self.bits |= DIRTY_BIT_MODIFIER;
+        } else {
+            self.bits &= !DIRTY_BIT_MODIFIER;
+        }
+
+        self
+    }
+
+    pub fn build(self) -> PageDescriptor<L> {
+        unsafe { PageDescriptor::from_bits_unchecked(self.bits) }
+    }
+}
+
+impl<L: FinalLevel> Descriptor<L> {
+    /// Returns the descriptor as a page descriptor, or None if it's reserved.
+    pub fn page(&self) -> Option<&PageDescriptor<L>> {
+        // SAFETY: descriptors are repr(transparent), and differ only in their marker types.
+        (self.bits & 0b11 == 0b11)
+            .then(|| unsafe { &*(self as *const Self as *const PageDescriptor<L>) })
+    }
+}
+
+impl<L: FinalLevel> PageDescriptor<L> {
+    /// Returns whether the page has been accessed since its access flag was last cleared.
+    pub fn is_accessed(&self) -> bool {
+        self.bits & ACCESS_FLAG != 0
+    }
+
+    /// Returns the memory type of the page, or None if its AttrIndx isn't one of ours.
+    pub fn memory_type(&self) -> Option<MemoryType> {
+        MemoryType::from_index((self.bits & ATTR_INDEX) >> ATTR_INDEX_SHIFT)
+    }
+
+    /// Returns whether the page has been written since it was last made clean, or None if its
+    /// dirty state isn't tracked (that is, DBM is clear).
+    pub fn is_dirty(&self) -> Option<bool> {
+        (self.bits & DIRTY_BIT_MODIFIER != 0).then_some(self.bits & READ_ONLY == 0)
+    }
+}
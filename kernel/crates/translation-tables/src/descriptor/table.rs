@@ -0,0 +1,63 @@
+use core::marker::PhantomData;
+
+use crate::granule::OUTPUT_ADDRESS_MASK;
+use crate::table::TranslationTable;
+use crate::{IntermediateLevel, Memory};
+
+use super::*;
+
+impl<L: IntermediateLevel> DescriptorBuilder<L> {
+    /// Points to the next level table at physical address `next_table_pa`.
+    pub fn table(self, next_table_pa: usize) -> TableDescriptorBuilder<L> {
+        // TODO: verify PA alignment and size, attributes
+        let bits = next_table_pa as u64 | 0b11;
+
+        TableDescriptorBuilder {
+            bits,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<L: IntermediateLevel> TableDescriptorBuilder<L> {
+    pub fn build(self) -> TableDescriptor<L> {
+        unsafe { TableDescriptor::from_bits_unchecked(self.bits) }
+    }
+}
+
+impl<L: IntermediateLevel> Descriptor<L> {
+    /// Returns the descriptor as a table descriptor, or None if it's a block descriptor.
+    pub fn table(&self) -> Option<&TableDescriptor<L>> {
+        // SAFETY: descriptors are repr(transparent), and differ only in their marker types.
+        self.is_table()
+            .then(|| unsafe { &*(self as *const Self as *const TableDescriptor<L>) })
+    }
+
+    /// Returns the descriptor as a table descriptor, or None if it's a block descriptor.
+    pub fn table_mut(&mut self) -> Option<&mut TableDescriptor<L>> {
+        // SAFETY: see table.
+        self.is_table()
+            .then(|| unsafe { &mut *(self as *mut Self as *mut TableDescriptor<L>) })
+    }
+
+    fn is_table(&self) -> bool {
+        self.bits & 0b11 == 0b11
+    }
+}
+
+impl<L: IntermediateLevel> TableDescriptor<L> {
+    pub fn translation_table(&self, memory: &impl Memory) -> &TranslationTable<L::Next> {
+        unsafe { TranslationTable::from_pa(self.next_level_table_address(), memory) }
+    }
+
+    pub fn translation_table_mut(
+        &mut self,
+        memory: &impl Memory,
+    ) -> &mut TranslationTable<L::Next> {
+        unsafe { TranslationTable::from_pa_mut(self.next_level_table_address(), memory) }
+    }
+
+    pub fn next_level_table_address(&self) -> usize {
+        (self.bits & OUTPUT_ADDRESS_MASK) as usize
+    }
+}
@@ -0,0 +1,69 @@
+//! The translation granule, which is 4KiB unless selected at build time with the `granule-16k` or
+//! `granule-64k` feature.
+//!
+//! The granule sets the size of pages and tables, and thus how many bits of the virtual address
+//! each level resolves. With 48-bit virtual addresses:
+//!
+//! | granule | levels | index bits per level | block sizes                    |
+//! |---------|--------|----------------------|--------------------------------|
+//! | 4KiB    | 0 to 3 | 9, 9, 9, 9           | 1GiB (level 1), 2MiB (level 2) |
+//! | 16KiB   | 0 to 3 | 1, 11, 11, 11        | 32MiB (level 2)                |
+//! | 64KiB   | 1 to 3 | 6, 13, 13            | 512MiB (level 2)               |
+//!
+//! Larger blocks at level 1 with the 16KiB and 64KiB granules need 52-bit addresses, which we
+//! don't support.
+
+/// log2 of the page size.
+#[cfg(not(any(feature = "granule-16k", feature = "granule-64k")))]
+pub const SHIFT: usize = 12;
+/// log2 of the page size.
+#[cfg(feature = "granule-16k")]
+pub const SHIFT: usize = 14;
+/// log2 of the page size.
+#[cfg(feature = "granule-64k")]
+pub const SHIFT: usize = 16;
+
+/// Size of a page, which is also the size (and alignment) of a translation table.
+pub const PAGE_SIZE: usize = 1 << SHIFT;
+
+/// Number of descriptors in a translation table.
+pub const ENTRIES: usize = PAGE_SIZE / 8;
+
+/// Number of virtual address bits resolved by a full table.
+pub const BITS_PER_LEVEL: usize = SHIFT - 3;
+
+/// Size of the virtual address space, for TCR_EL1.T0SZ and TCR_EL1.T1SZ of 16.
+pub const VA_BITS: usize = 48;
+
+/// The level that translation starts at, given [`VA_BITS`].
+pub const START_LEVEL: u8 = if SHIFT == 16 { 1 } else { 0 };
+
+/// Number of levels of translation.
+pub const LEVELS: usize = 4 - START_LEVEL as usize;
+
+/// Mask of the output address in a table, block or page descriptor.
+pub const OUTPUT_ADDRESS_MASK: u64 = ((1 << VA_BITS) - 1) & !(PAGE_SIZE as u64 - 1);
+
+/// Value of TCR_EL1.TG0 (the granule for TTBR0_EL1).
+pub const TCR_TG0: u64 = match SHIFT {
+    12 => 0b00,
+    14 => 0b10,
+    _ => 0b01,
+};
+
+/// Value of TCR_EL1.TG1 (the granule for TTBR1_EL1), which is encoded differently to TG0.
+pub const TCR_TG1: u64 = match SHIFT {
+    12 => 0b10,
+    14 => 0b01,
+    _ => 0b11,
+};
+
+/// Returns the size of the memory mapped by a descriptor at `level`.
+pub const fn block_size(level: u8) -> usize {
+    PAGE_SIZE << (BITS_PER_LEVEL * (3 - level as usize))
+}
+
+/// Returns whether block descriptors are allowed at `level`.
+pub const fn has_blocks(level: u8) -> bool {
+    level == 2 || (level == 1 && SHIFT == 12)
+}
@@ -0,0 +1,91 @@
+//! AArch64 translation tables, for any of the translation granules (see [`granule`]).
+//!
+//! Tables refer to each other by physical address, so they're accessed through a [`Memory`]
+//! that knows how to allocate pages and reach them. The kernel uses its 1:1 physical memory
+//! mapping, while tests use a mock backing store on the host.
+#![cfg_attr(not(test), no_std)]
+
+#[cfg(all(feature = "granule-16k", feature = "granule-64k"))]
+compile_error!("the granule-16k and granule-64k features are mutually exclusive");
+
+pub mod descriptor;
+pub mod granule;
+mod memory;
+#[cfg(test)]
+mod mock;
+pub mod table;
+
+pub use granule::PAGE_SIZE;
+pub use memory::Memory;
+
+pub trait Level {
+    const LEVEL: u8;
+}
+
+pub trait IntermediateLevel: Level {
+    type Next: Level;
+}
+
+pub trait FinalLevel: Level {}
+
+macro_rules! define_levels {
+    ($level:ident = $n:literal, $next_level:ident = $next_n:literal$(, $rest:ident = $rest_n:literal)*) => {
+        #[derive(Debug)]
+        pub struct $level;
+
+        impl Level for $level {
+            const LEVEL: u8 = $n;
+        }
+
+        impl IntermediateLevel for $level {
+            type Next = $next_level;
+        }
+
+        define_levels!($next_level = $next_n$(, $rest = $rest_n)*);
+    };
+
+    ($level:ident = $n:literal) => {
+        #[derive(Debug)]
+        pub struct $level;
+
+        impl Level for $level {
+            const LEVEL: u8 = $n;
+        }
+
+        impl FinalLevel for $level {}
+    };
+}
+
+define_levels!(Level0 = 0, Level1 = 1, Level2 = 2, Level3 = 3);
+
+/// The level that translation starts at (see [`granule::START_LEVEL`]).
+#[cfg(not(feature = "granule-64k"))]
+pub type RootLevel = Level0;
+/// The level that translation starts at (see [`granule::START_LEVEL`]).
+#[cfg(feature = "granule-64k")]
+pub type RootLevel = Level1;
+
+/// Returns the index into the table at `level` of the descriptor for `virtual_address`.
+pub fn index(level: u8, virtual_address: usize) -> usize {
+    assert!((granule::START_LEVEL..=3).contains(&level));
+
+    // the table at the start level may be smaller than a page, but the virtual address has no
+    // more bits to index it with anyway
+    let virtual_address = virtual_address & ((1 << granule::VA_BITS) - 1);
+    let shift = granule::SHIFT + granule::BITS_PER_LEVEL * (3 - level as usize);
+
+    (virtual_address >> shift) & (granule::ENTRIES - 1)
+}
+
+/// An error from mapping or unmapping memory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// An address is not aligned to a page.
+    Misaligned { address: usize },
+    /// A virtual address is already covered by a block mapping at the given level.
+    MappingConflict { virtual_address: usize, level: u8 },
+    /// There's no memory left for a new translation table.
+    OutOfMemory,
+    /// A page of Device memory would be executable.
+    ExecutableDevice { virtual_address: usize },
+}
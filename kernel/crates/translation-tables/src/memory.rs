@@ -0,0 +1,27 @@
+/// Access to the physical memory that translation tables live in.
+pub trait Memory {
+    /// Allocates a page, returning its physical address, or None if there's no memory left.
+    ///
+    /// The page need not be initialised.
+    fn alloc_page(&mut self) -> Option<usize>;
+
+    /// Returns a pointer through which the memory at physical address `pa` can be accessed.
+    fn ptr(&self, pa: usize) -> *mut u8;
+
+    /// Makes previous writes to translation tables visible to the MMU, then invalidates any TLB
+    /// entries (including cached table walks) for `virtual_address`, and waits for that to finish.
+    ///
+    /// This is the part of break-before-make between writing an invalid descriptor and the new one.
+    fn invalidate_tlb(&self, virtual_address: usize);
+
+    /// Makes the translation table at `table` (a pointer from [`Self::ptr`]) writable while
+    /// running `write`, which writes to it.
+    ///
+    /// Every write to a table goes through here, so a memory can keep its tables read-only the
+    /// rest of the time.
+    fn with_writable<R>(&self, table: *mut u8, write: impl FnOnce() -> R) -> R {
+        let _ = table;
+
+        write()
+    }
+}
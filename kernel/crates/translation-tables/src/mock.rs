@@ -0,0 +1,95 @@
+//! A mock backing store for testing on the host.
+use std::cell::RefCell;
+
+use crate::granule::ENTRIES;
+use crate::table::TranslationTable;
+use crate::{Memory, RootLevel, PAGE_SIZE};
+
+/// A page, with the same alignment as a table.
+struct Page(TranslationTable<RootLevel>);
+
+/// Pages allocated on the host heap, posing as physical memory starting at [`MockMemory::BASE`].
+pub struct MockMemory {
+    // raw pointers rather than boxes, since tables are written through pointers from `ptr`
+    pages: Vec<*mut Page>,
+    /// Virtual addresses passed to [`Memory::invalidate_tlb`], in order.
+    invalidations: RefCell<Vec<usize>>,
+    /// Physical addresses of the tables passed to [`Memory::with_writable`], in order.
+    writes: RefCell<Vec<usize>>,
+}
+
+impl MockMemory {
+    /// Physical address of the first page, which is the base of RAM in QEMU’s virt machine.
+    pub const BASE: usize = 0x4000_0000;
+
+    pub fn new() -> Self {
+        Self {
+            pages: Vec::new(),
+            invalidations: RefCell::new(Vec::new()),
+            writes: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Returns the number of pages allocated.
+    pub fn len(&self) -> usize {
+        self.pages.len()
+    }
+
+    /// Returns the descriptors in the page at physical address `pa`.
+    pub fn descriptors(&self, pa: usize) -> Vec<u64> {
+        assert_eq!(pa % PAGE_SIZE, 0);
+
+        unsafe { (*(self.ptr(pa) as *const [u64; ENTRIES])).to_vec() }
+    }
+
+    /// Returns the virtual addresses whose TLB entries have been invalidated, in order.
+    pub fn invalidations(&self) -> Vec<usize> {
+        self.invalidations.borrow().clone()
+    }
+
+    /// Returns the physical addresses of the tables that have been written, in order.
+    pub fn writes(&self) -> Vec<usize> {
+        self.writes.borrow().clone()
+    }
+}
+
+impl Memory for MockMemory {
+    fn alloc_page(&mut self) -> Option<usize> {
+        // poison the page, so tests notice if a table isn't initialised
+        let mut page = Box::new(Page(TranslationTable::new()));
+        unsafe { (&mut page.0 as *mut TranslationTable<_>).write_bytes(0xA5, 1) };
+        self.pages.push(Box::into_raw(page));
+
+        Some(Self::BASE + (self.pages.len() - 1) * PAGE_SIZE)
+    }
+
+    fn ptr(&self, pa: usize) -> *mut u8 {
+        let offset = pa - Self::BASE;
+        let page = self.pages[offset / PAGE_SIZE];
+
+        unsafe { page.cast::<u8>().add(offset % PAGE_SIZE) }
+    }
+
+    fn invalidate_tlb(&self, virtual_address: usize) {
+        self.invalidations.borrow_mut().push(virtual_address);
+    }
+
+    fn with_writable<R>(&self, table: *mut u8, write: impl FnOnce() -> R) -> R {
+        let page = self
+            .pages
+            .iter()
+            .position(|&page| page.cast() == table)
+            .expect("table to be a page from this memory");
+        self.writes.borrow_mut().push(Self::BASE + page * PAGE_SIZE);
+
+        write()
+    }
+}
+
+impl Drop for MockMemory {
+    fn drop(&mut self) {
+        for &page in &self.pages {
+            drop(unsafe { Box::from_raw(page) });
+        }
+    }
+}
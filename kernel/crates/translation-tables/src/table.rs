@@ -0,0 +1,1088 @@
+use core::marker::PhantomData;
+use core::ops::Range;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::descriptor::page::{
+    permission_bits, MapAttributes, MemoryType, ACCESS_FLAG, DIRTY_BIT_MODIFIER, EXECUTE_NEVER,
+    READ_ONLY,
+};
+use crate::descriptor::{
+    requires_break_before_make, Descriptor, DescriptorBuilder, DescriptorRefMut, PageDescriptor,
+};
+use crate::granule::{self, ENTRIES, OUTPUT_ADDRESS_MASK, START_LEVEL};
+use crate::{index, Error, FinalLevel, IntermediateLevel, Level3, Memory, RootLevel, PAGE_SIZE};
+
+/// A translation table of [`ENTRIES`] entries with an in-memory representation equivalent to both
+/// `[u64; ENTRIES]` and a hardware translation table. Each entry is an 8-byte [`Descriptor`] owned
+/// by this translation table.
+#[derive(Debug)]
+#[cfg_attr(
+    not(any(feature = "granule-16k", feature = "granule-64k")),
+    repr(C, align(0x1000))
+)]
+#[cfg_attr(feature = "granule-16k", repr(C, align(0x4000)))]
+#[cfg_attr(feature = "granule-64k", repr(C, align(0x10000)))]
+pub struct TranslationTable<L> {
+    descriptors: [AtomicU64; ENTRIES],
+    phantom: PhantomData<L>,
+}
+
+impl<L> TranslationTable<L> {
+    /// Creates a new translation table initialised with invalid descriptors.
+    pub const fn new() -> Self {
+        // a const, since AtomicU64 isn't Copy, so the array can't be initialised from a value
+        #[allow(clippy::declare_interior_mutable_const)]
+        const INVALID: AtomicU64 = AtomicU64::new(Descriptor::<()>::INVALID_BITS);
+
+        Self {
+            descriptors: [INVALID; ENTRIES],
+            phantom: PhantomData,
+        }
+    }
+
+    /// Allocates a new translation table initialised with invalid descriptors, returning its
+    /// physical address.
+    pub fn alloc(memory: &mut impl Memory) -> Result<usize, Error> {
+        let pa = memory.alloc_page().ok_or(Error::OutOfMemory)?;
+
+        // SAFETY: the page was just allocated, and is as large and aligned as a table.
+        let ptr = memory.ptr(pa);
+        memory.with_writable(ptr, || unsafe { (ptr as *mut Self).write(Self::new()) });
+
+        Ok(pa)
+    }
+
+    /// Returns the translation table at physical address `pa`.
+    ///
+    /// # Safety
+    /// There must be a translation table for this level at `pa`, which must not be mutably
+    /// borrowed for `'tt`.
+    pub unsafe fn from_pa<'tt>(pa: usize, memory: &impl Memory) -> &'tt Self {
+        &*(memory.ptr(pa) as *const Self)
+    }
+
+    /// Returns the translation table at physical address `pa`, mutably.
+    ///
+    /// # Safety
+    /// There must be a translation table for this level at `pa`, which must not be borrowed at all
+    /// for `'tt`.
+    pub unsafe fn from_pa_mut<'tt>(pa: usize, memory: &impl Memory) -> &'tt mut Self {
+        &mut *(memory.ptr(pa) as *mut Self)
+    }
+
+    /// Returns the raw value of the descriptor at `index`, whether or not it's valid.
+    pub fn bits(&self, index: usize) -> u64 {
+        // TODO: ordering
+        self.descriptors[index].load(Ordering::SeqCst)
+    }
+
+    /// Returns the descriptor at `index` from the translation table if the descriptor is valid.
+    fn get_mut(&mut self, index: usize) -> Option<DescriptorRefMut<'_, L>> {
+        DescriptorRefMut::from_bits(self.bits(index))
+    }
+
+    /// Returns the descriptor at `index` from the translation table if the descriptor is valid,
+    /// otherwise, uses `build` to create a new descriptor which is stored at `index` and returned.
+    fn get_mut_or_set<M, B, D>(
+        &mut self,
+        index: usize,
+        memory: &mut M,
+        build: B,
+    ) -> Result<DescriptorRefMut<'_, L>, Error>
+    where
+        M: Memory,
+        B: FnOnce(DescriptorBuilder<L>, &mut M) -> Result<D, Error>,
+        D: Into<Descriptor<L>>,
+    {
+        if let Some(descriptor) = DescriptorRefMut::from_bits(self.bits(index)) {
+            Ok(descriptor)
+        } else {
+            let descriptor = build(DescriptorBuilder::default(), memory)?.into();
+            let bits = descriptor.into_inner();
+
+            self.store(index, bits, memory);
+
+            Ok(unsafe { DescriptorRefMut::from_bits_unchecked(bits) })
+        }
+    }
+
+    /// Writes the raw value of the descriptor at `index`, returning the old value. Every write to
+    /// a table goes through here, and thus through [`Memory::with_writable`].
+    ///
+    /// In a live translation table, this must not be used for changes that need break-before-make
+    /// (see [`Self::update`]), which is checked in debug builds.
+    fn store(&mut self, index: usize, bits: u64, memory: &impl Memory) -> u64 {
+        let table = self as *mut Self as *mut u8;
+        // TODO: ordering
+        let old_bits = memory.with_writable(table, || {
+            self.descriptors[index].swap(bits, Ordering::SeqCst)
+        });
+
+        debug_assert!(
+            !requires_break_before_make(old_bits, bits),
+            "replacing descriptor {old_bits:#x} with {bits:#x} needs break-before-make"
+        );
+
+        old_bits
+    }
+
+    /// Replaces a potentially valid descriptor, which translates `virtual_address`, with a new
+    /// descriptor, returning the previous descriptor if it was valid.
+    ///
+    /// This is safe for live translation tables: if the change needs break-before-make, the old
+    /// descriptor is made invalid and its TLB entries invalidated before the new descriptor is
+    /// written. Other changes, like permissions, are followed by TLB invalidation, so the MMU
+    /// doesn't keep using the old descriptor.
+    fn update<F, D>(
+        &mut self,
+        index: usize,
+        virtual_address: usize,
+        build: F,
+        memory: &impl Memory,
+    ) -> Option<Descriptor<L>>
+    where
+        F: FnOnce(DescriptorBuilder<L>) -> D,
+        D: Into<Descriptor<L>>,
+    {
+        let descriptor = build(DescriptorBuilder::default()).into();
+        let new_bits = descriptor.into_inner();
+
+        let old_bits = self.bits(index);
+        if requires_break_before_make(old_bits, new_bits) {
+            // break
+            self.store(index, Descriptor::<L>::INVALID_BITS, memory);
+            memory.invalidate_tlb(virtual_address);
+            // make
+            self.store(index, new_bits, memory);
+        } else {
+            self.store(index, new_bits, memory);
+            if old_bits & 1 == 1 && old_bits != new_bits {
+                memory.invalidate_tlb(virtual_address);
+            }
+        }
+
+        Descriptor::from_bits(old_bits)
+    }
+
+    /// Replaces a potentially valid descriptor with an invalid descriptor, returning the previous
+    /// descriptor if it was valid.
+    fn take(&mut self, index: usize, memory: &impl Memory) -> Option<Descriptor<L>> {
+        let old_bits = self.store(index, Descriptor::<L>::INVALID_BITS, memory);
+
+        Descriptor::from_bits(old_bits)
+    }
+}
+
+impl<L> Default for TranslationTable<L> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<L: IntermediateLevel> TranslationTable<L> {
+    /// Returns the next level table for `virtual_address`, allocating it if there isn't one yet.
+    fn next_table_or_alloc(
+        &mut self,
+        virtual_address: usize,
+        memory: &mut impl Memory,
+    ) -> Result<&mut TranslationTable<L::Next>, Error> {
+        let index = index(L::LEVEL, virtual_address);
+        let descriptor = self.get_mut_or_set(index, memory, |builder, memory| {
+            Ok(builder
+                .table(TranslationTable::<L::Next>::alloc(memory)?)
+                .build())
+        })?;
+
+        Self::next_table(descriptor, virtual_address, memory)
+    }
+
+    /// Returns the next level table for `virtual_address`, or None if there isn't one.
+    fn next_table_if_present(
+        &mut self,
+        virtual_address: usize,
+        memory: &impl Memory,
+    ) -> Result<Option<&mut TranslationTable<L::Next>>, Error> {
+        match self.get_mut(index(L::LEVEL, virtual_address)) {
+            Some(descriptor) => Self::next_table(descriptor, virtual_address, memory).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the next level table that `descriptor` points to, with the lifetime of this table.
+    fn next_table<'tt>(
+        mut descriptor: DescriptorRefMut<'tt, L>,
+        virtual_address: usize,
+        memory: &impl Memory,
+    ) -> Result<&'tt mut TranslationTable<L::Next>, Error> {
+        let table = descriptor
+            .table_mut()
+            .ok_or(Error::MappingConflict {
+                virtual_address,
+                level: L::LEVEL,
+            })?
+            .translation_table_mut(memory);
+
+        // SAFETY: the table is owned by the descriptor, which is owned by this table, so it lives
+        // (and is borrowed) for as long as this table is.
+        Ok(unsafe { &mut *(table as *mut _) })
+    }
+}
+
+impl<L: FinalLevel> TranslationTable<L> {
+    /// Maps the page at `virtual_address` in this table to `physical_address`, with
+    /// break-before-make if there's already a different mapping.
+    ///
+    /// This is for tables that the caller manages directly, rather than through
+    /// [`TranslationTable::map_page`], like a table for a window onto other tables.
+    pub fn set_page(
+        &mut self,
+        virtual_address: usize,
+        physical_address: usize,
+        read_only: bool,
+        memory: &impl Memory,
+    ) {
+        let old = self.update(
+            index(L::LEVEL, virtual_address),
+            virtual_address,
+            |builder| {
+                builder
+                    .page(physical_address)
+                    .access_flag(true)
+                    .read_only(read_only)
+                    .build()
+            },
+            memory,
+        );
+
+        // TODO: drop old correctly
+        core::mem::forget(old);
+    }
+}
+
+impl TranslationTable<RootLevel> {
+    pub fn map_contiguous<'f>(
+        &mut self,
+        va_start: usize,
+        va_end: usize,
+        pa_start: usize,
+        attributes: impl Into<MapAttributes<'f>>,
+        memory: &mut impl Memory,
+    ) -> Result<(), Error> {
+        let attributes = attributes.into();
+        let mut va = va_start;
+        let mut pa = pa_start;
+        while va < va_end {
+            self.map_page(va, pa, attributes, memory)?;
+            va += PAGE_SIZE;
+            pa += PAGE_SIZE;
+        }
+
+        Ok(())
+    }
+
+    /// Creates a mapping between `virtual_address` and the `physical_address`, replacing any
+    /// existing mapping for the page with break-before-make.
+    ///
+    /// `attributes` says how the page can be accessed (see [`permission_bits`]) and what type of
+    /// memory it is, which can be just the flags for Normal memory. Device memory can't be
+    /// executable.
+    pub fn map_page<'f>(
+        &mut self,
+        virtual_address: usize,
+        physical_address: usize,
+        attributes: impl Into<MapAttributes<'f>>,
+        memory: &mut impl Memory,
+    ) -> Result<(), Error> {
+        let attributes = attributes.into();
+        for address in [virtual_address, physical_address] {
+            if address % PAGE_SIZE != 0 {
+                return Err(Error::Misaligned { address });
+            }
+        }
+        if attributes.is_executable_device() {
+            return Err(Error::ExecutableDevice { virtual_address });
+        }
+
+        let table = self;
+        #[cfg(not(feature = "granule-64k"))]
+        let table = table.next_table_or_alloc(virtual_address, memory)?;
+        let level2 = table.next_table_or_alloc(virtual_address, memory)?;
+        let level3 = level2.next_table_or_alloc(virtual_address, memory)?;
+        let old_level3_descriptor = level3.update(
+            index(3, virtual_address),
+            virtual_address,
+            |builder| {
+                builder
+                    .page(physical_address)
+                    .access_flag(true)
+                    .permissions(attributes.flags)
+                    .memory_type(attributes.memory_type)
+                    .build()
+            },
+            memory,
+        );
+
+        // TODO: drop old_level3_descriptor correctly
+        core::mem::forget(old_level3_descriptor);
+
+        Ok(())
+    }
+
+    /// Removes the mapping for the page at `virtual_address`, returning the page descriptor that
+    /// mapped it, if any.
+    ///
+    /// Tables that become empty are left in place, and the caller is responsible for any TLB
+    /// maintenance.
+    pub fn unmap_page(
+        &mut self,
+        virtual_address: usize,
+        memory: &impl Memory,
+    ) -> Result<Option<u64>, Error> {
+        if virtual_address % PAGE_SIZE != 0 {
+            return Err(Error::Misaligned {
+                address: virtual_address,
+            });
+        }
+
+        let Some(level3) = self.level3_table_if_present(virtual_address, memory)? else {
+            return Ok(None);
+        };
+
+        Ok(level3
+            .take(index(3, virtual_address), memory)
+            .map(Descriptor::into_inner))
+    }
+
+    /// Sets the access flag for the page at `virtual_address`, as an access flag fault handler
+    /// would, returning false if there's no such page.
+    ///
+    /// This needs no TLB maintenance, since the MMU never caches a translation without the access
+    /// flag.
+    pub fn mark_accessed(
+        &mut self,
+        virtual_address: usize,
+        memory: &impl Memory,
+    ) -> Result<bool, Error> {
+        let virtual_address = virtual_address & !(PAGE_SIZE - 1);
+        let Some(level3) = self.level3_table_if_present(virtual_address, memory)? else {
+            return Ok(false);
+        };
+        let index = index(3, virtual_address);
+        let Some(bits) = level3.get_mut(index).and_then(|d| Some(d.page()?.bits())) else {
+            return Ok(false);
+        };
+
+        level3.store(index, bits | ACCESS_FLAG, memory);
+
+        Ok(true)
+    }
+
+    /// Clears the access flag for every page mapped in `range`, so the next access to each page
+    /// will fault (or set it in hardware), returning how many pages had been accessed.
+    pub fn clear_accessed(
+        &mut self,
+        range: Range<usize>,
+        memory: &impl Memory,
+    ) -> Result<usize, Error> {
+        self.update_pages(range, memory, |_, page| {
+            page.is_accessed().then_some(page.bits() & !ACCESS_FLAG)
+        })
+    }
+
+    /// Changes the permissions of every page mapped in `range` to `flags` (see
+    /// [`permission_bits`]), returning how many pages were changed.
+    ///
+    /// Each page changes with a single write, so going from "rw" to "rx" never leaves a page both
+    /// writable and executable. Nothing changes if any of the pages are Device memory and `flags`
+    /// would make them executable.
+    pub fn protect(
+        &mut self,
+        range: Range<usize>,
+        flags: &str,
+        memory: &impl Memory,
+    ) -> Result<usize, Error> {
+        let permissions = permission_bits(flags);
+
+        if permissions & EXECUTE_NEVER != EXECUTE_NEVER {
+            let mut device = None;
+            self.update_pages(range.clone(), memory, |virtual_address, page| {
+                if page.memory_type().is_some_and(MemoryType::is_device) {
+                    device = device.or(Some(virtual_address));
+                }
+                None
+            })?;
+            if let Some(virtual_address) = device {
+                return Err(Error::ExecutableDevice { virtual_address });
+            }
+        }
+
+        self.update_pages(range, memory, |_, page| {
+            let bits = page.bits() & !(READ_ONLY | EXECUTE_NEVER) | permissions;
+            (bits != page.bits()).then_some(bits)
+        })
+    }
+
+    /// Starts tracking the dirty state of every page mapped in `range`, by setting DBM and making
+    /// them clean, returning how many pages were changed.
+    ///
+    /// This is only meaningful if the MMU manages dirty state (TCR_EL1.HD), since otherwise writes
+    /// to clean pages will take permission faults.
+    pub fn track_dirty(
+        &mut self,
+        range: Range<usize>,
+        memory: &impl Memory,
+    ) -> Result<usize, Error> {
+        self.update_pages(range, memory, |_, page| {
+            (page.is_dirty() != Some(false)).then_some(page.bits() | DIRTY_BIT_MODIFIER | READ_ONLY)
+        })
+    }
+
+    /// Makes every dirty page mapped in `range` clean again, returning how many pages were dirty.
+    /// Pages whose dirty state isn't tracked are left alone.
+    pub fn clear_dirty(
+        &mut self,
+        range: Range<usize>,
+        memory: &impl Memory,
+    ) -> Result<usize, Error> {
+        self.update_pages(range, memory, |_, page| {
+            (page.is_dirty() == Some(true)).then_some(page.bits() | READ_ONLY)
+        })
+    }
+
+    /// Uses `update` to change the page descriptors mapped in `range`, given each page's virtual
+    /// address, where it returns the new value, invalidating the TLB entries for the pages that
+    /// change. Returns how many did.
+    ///
+    /// Only changes that don't need break-before-make, like permissions and the access flag, are
+    /// allowed.
+    fn update_pages(
+        &mut self,
+        range: Range<usize>,
+        memory: &impl Memory,
+        mut update: impl FnMut(usize, &PageDescriptor<Level3>) -> Option<u64>,
+    ) -> Result<usize, Error> {
+        for address in [range.start, range.end] {
+            if address % PAGE_SIZE != 0 {
+                return Err(Error::Misaligned { address });
+            }
+        }
+
+        let mut count = 0;
+        for virtual_address in range.step_by(PAGE_SIZE) {
+            let Some(level3) = self.level3_table_if_present(virtual_address, memory)? else {
+                continue;
+            };
+            let index = index(3, virtual_address);
+            let Some(bits) = level3
+                .get_mut(index)
+                .and_then(|d| update(virtual_address, d.page()?))
+            else {
+                continue;
+            };
+
+            level3.store(index, bits, memory);
+            memory.invalidate_tlb(virtual_address);
+            count += 1;
+        }
+
+        Ok(count)
+    }
+
+    /// Makes the table at `level3_pa` the level 3 table for `virtual_address`, allocating any
+    /// tables needed to reach it.
+    ///
+    /// The table isn't owned by these tables, so it can live outside of `memory`, but it must be
+    /// accessible through it.
+    pub fn map_table(
+        &mut self,
+        virtual_address: usize,
+        level3_pa: usize,
+        memory: &mut impl Memory,
+    ) -> Result<(), Error> {
+        let table = self;
+        #[cfg(not(feature = "granule-64k"))]
+        let table = table.next_table_or_alloc(virtual_address, memory)?;
+        let level2 = table.next_table_or_alloc(virtual_address, memory)?;
+
+        let index = index(2, virtual_address);
+        if level2.bits(index) & 1 == 1 {
+            return Err(Error::MappingConflict {
+                virtual_address,
+                level: 2,
+            });
+        }
+        let descriptor = level2.update(
+            index,
+            virtual_address,
+            |builder| builder.table(level3_pa).build(),
+            memory,
+        );
+        debug_assert!(descriptor.is_none());
+
+        Ok(())
+    }
+
+    /// Returns the level 3 table for `virtual_address`, or None if there isn't one.
+    fn level3_table_if_present(
+        &mut self,
+        virtual_address: usize,
+        memory: &impl Memory,
+    ) -> Result<Option<&mut TranslationTable<Level3>>, Error> {
+        let table = self;
+        #[cfg(not(feature = "granule-64k"))]
+        let Some(table) = table.next_table_if_present(virtual_address, memory)?
+        else {
+            return Ok(None);
+        };
+        let Some(level2) = table.next_table_if_present(virtual_address, memory)? else {
+            return Ok(None);
+        };
+
+        level2.next_table_if_present(virtual_address, memory)
+    }
+
+    /// Walks the tables as the MMU would to translate `virtual_address`.
+    pub fn walk(&self, virtual_address: usize, memory: &impl Memory) -> Walk {
+        let mut walk = Walk {
+            virtual_address,
+            descriptors: [0; 4],
+            len: 0,
+        };
+
+        // the descriptors are the same at every level, so treat every table as the root
+        let mut table = self;
+        for level in START_LEVEL..=3 {
+            let bits = table.bits(index(level, virtual_address));
+            walk.descriptors[walk.len] = bits;
+            walk.len += 1;
+
+            // stop at anything other than a table descriptor, or at a page descriptor
+            if bits & 0b11 != 0b11 || level == 3 {
+                break;
+            }
+            let next_table_pa = (bits & OUTPUT_ADDRESS_MASK) as usize;
+            table = unsafe { TranslationTable::from_pa(next_table_pa, memory) };
+        }
+
+        walk
+    }
+}
+
+/// The descriptors visited while translating a virtual address, from the start level down, as
+/// returned by [`TranslationTable::walk`].
+#[derive(Debug, PartialEq)]
+pub struct Walk {
+    virtual_address: usize,
+    descriptors: [u64; 4],
+    len: usize,
+}
+
+impl Walk {
+    /// Returns the descriptors visited, ending with either an invalid descriptor, a block
+    /// descriptor, or a page descriptor.
+    pub fn descriptors(&self) -> &[u64] {
+        &self.descriptors[..self.len]
+    }
+
+    /// Returns the physical address that the virtual address translates to, if it's mapped.
+    pub fn physical_address(&self) -> Option<usize> {
+        let level = START_LEVEL + self.len as u8 - 1;
+        let bits = self.descriptors[self.len - 1];
+
+        let is_page = level == 3 && bits & 0b11 == 0b11;
+        let is_block = granule::has_blocks(level) && bits & 0b11 == 0b01;
+        if !is_page && !is_block {
+            return None;
+        }
+        let size = granule::block_size(level);
+        let output_address = (bits & OUTPUT_ADDRESS_MASK) as usize & !(size - 1);
+
+        Some(output_address | self.virtual_address & (size - 1))
+    }
+
+    /// Returns the access flag and dirty state of the page that the virtual address is in, if it's
+    /// mapped by a page descriptor.
+    pub fn page_state(&self) -> Option<PageState> {
+        if self.len != granule::LEVELS {
+            return None;
+        }
+        let descriptor = DescriptorRefMut::<Level3>::from_bits(self.descriptors[self.len - 1])?;
+        let page = descriptor.page()?;
+
+        Some(PageState {
+            accessed: page.is_accessed(),
+            dirty: page.is_dirty(),
+        })
+    }
+}
+
+/// The state of a page that the MMU (or an access flag fault handler) records in its descriptor,
+/// as returned by [`Walk::page_state`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PageState {
+    /// Whether the page has been accessed since its access flag was last cleared.
+    pub accessed: bool,
+    /// Whether the page has been written since it was last made clean, or None if its dirty state
+    /// isn't tracked.
+    pub dirty: Option<bool>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::descriptor::page::MAIR;
+    use crate::granule::{block_size, LEVELS};
+    use crate::mock::MockMemory;
+    use crate::{Level2, Level3};
+
+    /// A virtual address in the kernel’s half, aligned to every granule, with a different index at
+    /// each level.
+    const VA: usize = 0xffff_8048_c0a1_0000;
+    const PA: usize = 0x4040_0000;
+
+    /// Returns the physical address of the `n`th table allocated from a [`MockMemory`].
+    fn table(n: usize) -> usize {
+        MockMemory::BASE + n * PAGE_SIZE
+    }
+
+    /// Allocates a root table, which will be the first table.
+    fn root(memory: &mut MockMemory) -> &'static mut TranslationTable<RootLevel> {
+        let pa = TranslationTable::<RootLevel>::alloc(memory).unwrap();
+        assert_eq!(pa, table(0));
+
+        unsafe { TranslationTable::from_pa_mut(pa, memory) }
+    }
+
+    #[test]
+    #[cfg(not(any(feature = "granule-16k", feature = "granule-64k")))]
+    fn indices() {
+        assert_eq!(index(0, VA), 256);
+        assert_eq!(index(1, VA), 291);
+        assert_eq!(index(2, VA), 5);
+        assert_eq!(index(3, VA), 16);
+        assert_eq!(block_size(1), 1 << 30);
+        assert_eq!(block_size(2), 2 << 20);
+    }
+
+    #[test]
+    #[cfg(feature = "granule-16k")]
+    fn indices() {
+        // the level 0 table only has two entries
+        assert_eq!(index(0, VA), 1);
+        assert_eq!(index(1, VA), 4);
+        assert_eq!(index(2, VA), 1120);
+        assert_eq!(index(3, VA), 644);
+        assert_eq!(block_size(2), 32 << 20);
+    }
+
+    #[test]
+    #[cfg(feature = "granule-64k")]
+    fn indices() {
+        // there's no level 0, and the level 1 table only has 64 entries
+        assert_eq!(index(1, VA), 32);
+        assert_eq!(index(2, VA), 582);
+        assert_eq!(index(3, VA), 161);
+        assert_eq!(block_size(2), 512 << 20);
+    }
+
+    #[test]
+    fn map_page() {
+        let mut memory = MockMemory::new();
+        let tt = root(&mut memory);
+        tt.map_page(VA, PA, "rwx", &mut memory).unwrap();
+
+        // one table at each level, allocated in order, each pointing to the next
+        assert_eq!(memory.len(), LEVELS);
+        let mut expected = (1..LEVELS)
+            .map(|n| table(n) as u64 | 0b11)
+            .collect::<Vec<_>>();
+        // AF | page
+        expected.push(PA as u64 | 1 << 10 | 0b11);
+
+        for (n, &bits) in expected.iter().enumerate() {
+            let level = START_LEVEL + n as u8;
+            for (i, actual) in memory.descriptors(table(n)).into_iter().enumerate() {
+                let expected = if i == index(level, VA) { bits } else { 0 };
+                assert_eq!(actual, expected, "table {n} (level {level}) index {i}");
+            }
+        }
+
+        let walk = tt.walk(VA, &memory);
+        assert_eq!(walk.descriptors(), expected);
+        assert_eq!(walk.physical_address(), Some(PA));
+        assert_eq!(
+            tt.walk(VA + 0x123, &memory).physical_address(),
+            Some(PA + 0x123)
+        );
+    }
+
+    #[test]
+    #[cfg(not(any(feature = "granule-16k", feature = "granule-64k")))]
+    fn map_page_bits() {
+        let mut memory = MockMemory::new();
+        let tt = root(&mut memory);
+        tt.map_page(VA, PA, "rwx", &mut memory).unwrap();
+
+        assert_eq!(memory.descriptors(0x4000_0000)[256], 0x4000_1003);
+        assert_eq!(memory.descriptors(0x4000_1000)[291], 0x4000_2003);
+        assert_eq!(memory.descriptors(0x4000_2000)[5], 0x4000_3003);
+        assert_eq!(memory.descriptors(0x4000_3000)[16], 0x4040_0403);
+    }
+
+    #[test]
+    #[cfg(feature = "granule-64k")]
+    fn map_page_bits() {
+        let mut memory = MockMemory::new();
+        let tt = root(&mut memory);
+        tt.map_page(VA, PA, "rwx", &mut memory).unwrap();
+
+        assert_eq!(memory.descriptors(0x4000_0000)[32], 0x4001_0003);
+        assert_eq!(memory.descriptors(0x4001_0000)[582], 0x4002_0003);
+        assert_eq!(memory.descriptors(0x4002_0000)[161], 0x4040_0403);
+    }
+
+    #[test]
+    fn map_page_misaligned() {
+        let mut memory = MockMemory::new();
+        let tt = root(&mut memory);
+
+        assert_eq!(
+            tt.map_page(VA + 0x1000 / 2, PA, "rwx", &mut memory),
+            Err(Error::Misaligned {
+                address: VA + 0x1000 / 2
+            })
+        );
+        assert_eq!(
+            tt.map_page(VA, PA + PAGE_SIZE / 2, "rwx", &mut memory),
+            Err(Error::Misaligned {
+                address: PA + PAGE_SIZE / 2
+            })
+        );
+        assert_eq!(memory.len(), 1);
+    }
+
+    #[test]
+    fn map_contiguous() {
+        let mut memory = MockMemory::new();
+        let tt = root(&mut memory);
+
+        // four pages, straddling two level 3 tables
+        let start = 0xffff_8000_0000_0000 + block_size(2) - 2 * PAGE_SIZE;
+        let end = start + 4 * PAGE_SIZE;
+        tt.map_contiguous(start, end, PA, "rwx", &mut memory)
+            .unwrap();
+        assert_eq!(memory.len(), LEVELS + 1);
+
+        for i in 0..4 {
+            let walk = tt.walk(start + i * PAGE_SIZE, &memory);
+            assert_eq!(walk.physical_address(), Some(PA + i * PAGE_SIZE));
+        }
+        assert_eq!(tt.walk(start - PAGE_SIZE, &memory).physical_address(), None);
+        assert_eq!(tt.walk(end, &memory).physical_address(), None);
+
+        let page = |pa: usize| pa as u64 | 1 << 10 | 0b11;
+        let level2 = memory.descriptors(table(LEVELS - 2));
+        assert_eq!(level2[0], table(LEVELS - 1) as u64 | 0b11);
+        assert_eq!(level2[1], table(LEVELS) as u64 | 0b11);
+        let level3 = memory.descriptors(table(LEVELS - 1));
+        assert_eq!(level3[ENTRIES - 2], page(PA));
+        assert_eq!(level3[ENTRIES - 1], page(PA + PAGE_SIZE));
+        let level3 = memory.descriptors(table(LEVELS));
+        assert_eq!(level3[0], page(PA + 2 * PAGE_SIZE));
+        assert_eq!(level3[1], page(PA + 3 * PAGE_SIZE));
+    }
+
+    #[test]
+    fn unmap_page() {
+        let mut memory = MockMemory::new();
+        let tt = root(&mut memory);
+        tt.map_page(VA, PA, "rwx", &mut memory).unwrap();
+        let mapped = tt.walk(VA, &memory).descriptors().to_vec();
+
+        assert_eq!(tt.unmap_page(VA, &memory), Ok(Some(PA as u64 | 0x403)));
+        let walk = tt.walk(VA, &memory);
+        assert_eq!(walk.descriptors()[..LEVELS - 1], mapped[..LEVELS - 1]);
+        assert_eq!(walk.descriptors()[LEVELS - 1], 0);
+        assert_eq!(walk.physical_address(), None);
+
+        // already unmapped, and the tables are left in place
+        assert_eq!(tt.unmap_page(VA, &memory), Ok(None));
+        assert_eq!(memory.len(), LEVELS);
+
+        // never mapped, with no tables to walk
+        let other = 0xffff_0000_0000_0000;
+        assert_eq!(tt.unmap_page(other, &memory), Ok(None));
+        assert_eq!(tt.walk(other, &memory).descriptors(), [0]);
+        assert_eq!(memory.len(), LEVELS);
+    }
+
+    #[test]
+    fn remap_page() {
+        let mut memory = MockMemory::new();
+        let tt = root(&mut memory);
+        tt.map_page(VA, PA, "rwx", &mut memory).unwrap();
+        assert_eq!(memory.invalidations(), []);
+
+        // the same mapping again needs no TLB maintenance
+        tt.map_page(VA, PA, "rwx", &mut memory).unwrap();
+        assert_eq!(memory.invalidations(), []);
+
+        // but a different output address needs break-before-make
+        let new_pa = PA + PAGE_SIZE;
+        tt.map_page(VA, new_pa, "rwx", &mut memory).unwrap();
+        assert_eq!(memory.invalidations(), [VA]);
+        assert_eq!(tt.walk(VA, &memory).physical_address(), Some(new_pa));
+        assert_eq!(memory.len(), LEVELS);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "needs break-before-make")]
+    fn store_without_break_before_make() {
+        let mut memory = MockMemory::new();
+        let tt = root(&mut memory);
+        tt.map_page(VA, PA, "rwx", &mut memory).unwrap();
+
+        let level3_pa = table(LEVELS - 1);
+        let level3 = unsafe { TranslationTable::<Level3>::from_pa_mut(level3_pa, &memory) };
+        let new_bits = (PA + PAGE_SIZE) as u64 | 1 << 10 | 0b11;
+        level3.store(index(3, VA), new_bits, &memory);
+    }
+
+    #[test]
+    fn access_flag() {
+        let mut memory = MockMemory::new();
+        let tt = root(&mut memory);
+        let range = VA..VA + 3 * PAGE_SIZE;
+        tt.map_contiguous(range.start, range.end, PA, "rwx", &mut memory)
+            .unwrap();
+        let state = |tt: &TranslationTable<_>, memory: &_, va| tt.walk(va, memory).page_state();
+        let accessed = Some(PageState {
+            accessed: true,
+            dirty: None,
+        });
+        let not_accessed = Some(PageState {
+            accessed: false,
+            dirty: None,
+        });
+
+        // pages are mapped with the access flag set
+        assert_eq!(state(tt, &memory, VA), accessed);
+        assert_eq!(tt.clear_accessed(range.clone(), &memory), Ok(3));
+        assert_eq!(
+            memory.invalidations(),
+            [VA, VA + PAGE_SIZE, VA + 2 * PAGE_SIZE]
+        );
+        assert_eq!(state(tt, &memory, VA), not_accessed);
+        assert_eq!(tt.clear_accessed(range.clone(), &memory), Ok(0));
+
+        // as if the second page took an access flag fault
+        assert_eq!(tt.mark_accessed(VA + PAGE_SIZE + 0x10, &memory), Ok(true));
+        assert_eq!(state(tt, &memory, VA), not_accessed);
+        assert_eq!(state(tt, &memory, VA + PAGE_SIZE), accessed);
+        assert_eq!(tt.clear_accessed(range.clone(), &memory), Ok(1));
+
+        assert_eq!(tt.mark_accessed(range.end, &memory), Ok(false));
+        assert_eq!(state(tt, &memory, range.end), None);
+        assert_eq!(
+            tt.clear_accessed(VA..VA + 1, &memory),
+            Err(Error::Misaligned { address: VA + 1 })
+        );
+    }
+
+    #[test]
+    fn dirty_state() {
+        let mut memory = MockMemory::new();
+        let tt = root(&mut memory);
+        let range = VA..VA + 2 * PAGE_SIZE;
+        tt.map_contiguous(range.start, range.end, PA, "rwx", &mut memory)
+            .unwrap();
+        let dirty = |tt: &TranslationTable<_>, memory: &_, va| {
+            tt.walk(va, memory)
+                .page_state()
+                .and_then(|state| state.dirty)
+        };
+
+        assert_eq!(dirty(tt, &memory, VA), None);
+        assert_eq!(tt.clear_dirty(range.clone(), &memory), Ok(0));
+        assert_eq!(tt.track_dirty(range.clone(), &memory), Ok(2));
+        assert_eq!(tt.track_dirty(range.clone(), &memory), Ok(0));
+        assert_eq!(dirty(tt, &memory, VA), Some(false));
+        assert_eq!(dirty(tt, &memory, VA + PAGE_SIZE), Some(false));
+
+        // as if the MMU handled a write to the first page
+        let level3 = unsafe { TranslationTable::<Level3>::from_pa_mut(table(LEVELS - 1), &memory) };
+        let index = index(3, VA);
+        level3.store(index, level3.bits(index) & !READ_ONLY, &memory);
+        assert_eq!(dirty(tt, &memory, VA), Some(true));
+        assert_eq!(dirty(tt, &memory, VA + PAGE_SIZE), Some(false));
+
+        assert_eq!(tt.clear_dirty(range.clone(), &memory), Ok(1));
+        assert_eq!(dirty(tt, &memory, VA), Some(false));
+        assert_eq!(memory.invalidations().len(), 3);
+    }
+
+    #[test]
+    fn writes_go_through_memory() {
+        let mut memory = MockMemory::new();
+        let tt = root(&mut memory);
+        tt.map_page(VA, PA, "rwx", &mut memory).unwrap();
+        tt.unmap_page(VA, &memory).unwrap();
+
+        // each table is initialised, then written once, except the last which is also unmapped
+        let mut expected = Vec::new();
+        expected.push(table(0));
+        for n in 1..LEVELS {
+            expected.extend([table(n), table(n - 1)]);
+        }
+        expected.extend([table(LEVELS - 1), table(LEVELS - 1)]);
+        assert_eq!(memory.writes(), expected);
+    }
+
+    #[test]
+    fn map_table() {
+        let mut memory = MockMemory::new();
+        let tt = root(&mut memory);
+        let level3_pa = TranslationTable::<Level3>::alloc(&mut memory).unwrap();
+        tt.map_table(VA, level3_pa, &mut memory).unwrap();
+        assert_eq!(memory.len(), LEVELS);
+        assert_eq!(
+            tt.map_table(VA, level3_pa, &mut memory),
+            Err(Error::MappingConflict {
+                virtual_address: VA,
+                level: 2,
+            })
+        );
+
+        let level3 = unsafe { TranslationTable::<Level3>::from_pa_mut(level3_pa, &memory) };
+        level3.set_page(VA, PA, true, &memory);
+        let walk = tt.walk(VA, &memory);
+        assert_eq!(walk.physical_address(), Some(PA));
+        assert_eq!(walk.descriptors().last(), Some(&(PA as u64 | 0x483)));
+        assert_eq!(memory.invalidations(), []);
+
+        // making the page writable needs no break-before-make, but does need TLB maintenance
+        level3.set_page(VA, PA, false, &memory);
+        assert_eq!(
+            tt.walk(VA, &memory).descriptors().last(),
+            Some(&(PA as u64 | 0x403))
+        );
+        assert_eq!(memory.invalidations(), [VA]);
+    }
+
+    #[test]
+    fn permissions() {
+        let mut memory = MockMemory::new();
+        let tt = root(&mut memory);
+        let range = VA..VA + 2 * PAGE_SIZE;
+        tt.map_contiguous(range.start, range.end, PA, "rw", &mut memory)
+            .unwrap();
+        let last = |tt: &TranslationTable<_>, memory: &_, va| {
+            *tt.walk(va, memory).descriptors().last().unwrap()
+        };
+
+        // AF | page, with PXN and UXN
+        assert_eq!(last(tt, &memory, VA), PA as u64 | 0b11 << 53 | 0x403);
+        assert_eq!(tt.protect(range.clone(), "rw", &memory), Ok(0));
+        assert_eq!(tt.protect(range.clone(), "rx", &memory), Ok(2));
+        // AP[2] set, and PXN and UXN clear
+        assert_eq!(last(tt, &memory, VA), PA as u64 | 0x483);
+        assert_eq!(memory.invalidations(), [VA, VA + PAGE_SIZE]);
+
+        tt.map_page(range.end, PA, "r", &mut memory).unwrap();
+        assert_eq!(last(tt, &memory, range.end), PA as u64 | 0b11 << 53 | 0x483);
+    }
+
+    #[test]
+    fn memory_types() {
+        let mut memory = MockMemory::new();
+        let tt = root(&mut memory);
+        let device = MapAttributes::new("rw").memory_type(MemoryType::Device);
+        tt.map_page(VA, PA, device, &mut memory).unwrap();
+        let write_combining = MapAttributes::new("rw").memory_type(MemoryType::NormalNonCacheable);
+        tt.map_page(VA + PAGE_SIZE, PA, write_combining, &mut memory)
+            .unwrap();
+        // entry.s sets MAIR_EL1 to this
+        assert_eq!(MAIR, 0x0000_000C_0400_44FF);
+
+        let page = |tt: &TranslationTable<_>, memory: &_, va| {
+            let walk = tt.walk(va, memory);
+            let descriptor = DescriptorRefMut::<Level3>::from_bits(*walk.descriptors().last()?)?;
+            descriptor.page()?.memory_type()
+        };
+
+        // AF | page, with PXN, UXN and AttrIndx 3
+        assert_eq!(
+            tt.walk(VA, &memory).descriptors().last(),
+            Some(&(PA as u64 | 0b11 << 53 | 3 << 2 | 0x403))
+        );
+        assert_eq!(page(tt, &memory, VA), Some(MemoryType::Device));
+        assert_eq!(
+            page(tt, &memory, VA + PAGE_SIZE),
+            Some(MemoryType::NormalNonCacheable)
+        );
+        // flags alone are Normal memory
+        tt.map_page(VA + 2 * PAGE_SIZE, PA, "rwx", &mut memory)
+            .unwrap();
+        assert_eq!(
+            page(tt, &memory, VA + 2 * PAGE_SIZE),
+            Some(MemoryType::Normal)
+        );
+
+        // device memory can never be executable, whether mapped or protected that way
+        let executable = MapAttributes::new("rx").memory_type(MemoryType::DeviceGre);
+        assert_eq!(
+            tt.map_page(VA + 3 * PAGE_SIZE, PA, executable, &mut memory),
+            Err(Error::ExecutableDevice {
+                virtual_address: VA + 3 * PAGE_SIZE
+            })
+        );
+        assert_eq!(
+            tt.walk(VA + 3 * PAGE_SIZE, &memory).physical_address(),
+            None
+        );
+        assert_eq!(
+            tt.protect(VA + PAGE_SIZE..VA + 3 * PAGE_SIZE, "rx", &memory),
+            Ok(2)
+        );
+        assert_eq!(
+            tt.protect(VA..VA + 3 * PAGE_SIZE, "rx", &memory),
+            Err(Error::ExecutableDevice {
+                virtual_address: VA
+            })
+        );
+        assert_eq!(tt.protect(VA..VA + 3 * PAGE_SIZE, "r", &memory), Ok(3));
+    }
+
+    #[test]
+    fn block_conflict() {
+        let mut memory = MockMemory::new();
+        let tt = root(&mut memory);
+        tt.map_page(VA, PA, "rwx", &mut memory).unwrap();
+
+        // map the next level 2 block after VA with a block descriptor
+        let block_va = (VA & !(block_size(2) - 1)) + block_size(2);
+        let block_pa = 0x8000_0000;
+        let level2_pa = table(2 - START_LEVEL as usize);
+        let level2 = unsafe { TranslationTable::<Level2>::from_pa_mut(level2_pa, &memory) };
+        let old = level2.update(
+            index(2, block_va),
+            block_va,
+            |mut builder| builder.block(block_pa),
+            &memory,
+        );
+        assert!(old.is_none());
+
+        let walk = tt.walk(block_va + 0x12_3456, &memory);
+        assert_eq!(walk.descriptors().last(), Some(&(block_pa as u64 | 0b01)));
+        assert_eq!(walk.descriptors().len(), LEVELS - 1);
+        assert_eq!(walk.physical_address(), Some(block_pa + 0x12_3456));
+
+        let conflict = Error::MappingConflict {
+            virtual_address: block_va,
+            level: 2,
+        };
+        assert_eq!(tt.map_page(block_va, PA, "rwx", &mut memory), Err(conflict));
+        assert_eq!(tt.unmap_page(block_va, &memory), Err(conflict));
+    }
+}
@@ -0,0 +1,85 @@
+//! Driver for the PL031 real-time clock, which logs the time at boot, and registers its match
+//! interrupt as the kernel's alarm.
+#![no_std]
+
+use fdt::Fdt;
+
+/// 0x000: RTCDR (Data Register), the number of seconds since the Unix epoch in QEMU
+const RTCDR: usize = 0x000;
+/// 0x004: RTCMR (Match Register), which raises the interrupt when RTCDR reaches it
+const RTCMR: usize = 0x004;
+/// 0x010: RTCIMSC (Interrupt Mask Set or Clear register), where bit 0 enables the interrupt
+const RTCIMSC: usize = 0x010;
+/// 0x01C: RTCICR (Interrupt Clear Register), where writing bit 0 clears the interrupt
+const RTCICR: usize = 0x01C;
+
+const COMPATIBLE: &str = "arm,pl031";
+
+static mut PL031: Pl031 = Pl031 {
+    base: core::ptr::null_mut(),
+};
+
+struct Pl031 {
+    base: *mut u8,
+}
+
+// SAFETY: the kernel only uses the alarm with interrupts masked, so only one CPU at a time.
+unsafe impl Sync for Pl031 {}
+
+impl Pl031 {
+    fn read(&self, offset: usize) -> u32 {
+        // SAFETY: the devicetree says there's a PL031 at `base`, and reading its registers has no
+        // side effects.
+        unsafe { self.base.add(offset).cast::<u32>().read_volatile() }
+    }
+
+    fn write(&self, offset: usize, value: u32) {
+        // SAFETY: as in read.
+        unsafe { self.base.add(offset).cast::<u32>().write_volatile(value) }
+    }
+}
+
+impl driver::Alarm for Pl031 {
+    fn now(&self) -> u64 {
+        self.read(RTCDR).into()
+    }
+
+    fn set(&self, deadline: u64) {
+        // the counter is only 32 bits, so a later deadline would never match
+        self.write(RTCMR, deadline.min(u32::MAX.into()) as u32);
+        self.write(RTCICR, 1);
+        self.write(RTCIMSC, 1);
+    }
+
+    fn clear(&self) {
+        self.write(RTCIMSC, 0);
+        self.write(RTCICR, 1);
+    }
+}
+
+fn init(fdt: &Fdt) -> Result<(), driver::Error> {
+    let base = driver::find_reg(fdt, COMPATIBLE)?;
+
+    // SAFETY: initcalls run one at a time during boot, and nothing else uses PL031 yet.
+    let pl031 = unsafe {
+        PL031.base = base as *mut u8;
+        &PL031
+    };
+    log::info!("{} seconds since the epoch", pl031.read(RTCDR));
+
+    // the RTC still tells the time without its interrupt, so it's fine if there isn't one
+    match driver::find_interrupt(fdt, COMPATIBLE) {
+        Ok(interrupt) => {
+            driver::Alarm::clear(pl031);
+            driver::register_alarm(driver::AlarmSource {
+                name: env!("CARGO_CRATE_NAME"),
+                interrupt,
+                alarm: pl031,
+            })?;
+        }
+        Err(error) => log::warn!("no alarm: {error}"),
+    }
+
+    Ok(())
+}
+driver::initcall!(init);
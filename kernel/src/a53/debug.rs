@@ -0,0 +1,187 @@
+//! Self-hosted debug registers, for hardware watchpoints.
+use core::arch::asm;
+
+use crate::reg::prelude::*;
+use crate::reg::system::SystemRegisterSpec;
+
+/// MDSCR_EL1 (Monitor Debug System Control Register)
+#[allow(non_camel_case_types)]
+#[allow(clippy::upper_case_acronyms)]
+pub struct MDSCR_EL1;
+
+impl SystemRegisterSpec for MDSCR_EL1 {
+    unsafe fn mrs() -> u64 {
+        let bits: u64;
+        asm!("mrs {}, MDSCR_EL1", out(reg) bits);
+        bits
+    }
+
+    unsafe fn msr(bits: u64) {
+        asm!("msr MDSCR_EL1, {}", in(reg) bits);
+    }
+}
+
+impl RegisterReadable for MDSCR_EL1 {}
+
+impl RegisterWritable for MDSCR_EL1 {}
+
+impl RegisterInitial for MDSCR_EL1 {
+    const INITIAL_VALUE: Self::Bits = 0;
+}
+
+#[allow(dead_code)]
+impl RegisterReader<MDSCR_EL1> {
+    /// Monitor debug events (breakpoints and watchpoints) enabled.
+    pub fn mde(&self) -> bool {
+        self.bit(15)
+    }
+
+    /// Debug exceptions enabled at the same exception level as they're taken to.
+    pub fn kde(&self) -> bool {
+        self.bit(13)
+    }
+}
+
+impl RegisterWriter<MDSCR_EL1> {
+    pub fn mde(&mut self, mde: bool) {
+        // SAFETY: MDE only enables debug events, which do nothing until a watchpoint is enabled.
+        unsafe { self.bit(15, mde) }
+    }
+
+    pub fn kde(&mut self, kde: bool) {
+        // SAFETY: KDE only lets debug events be taken at EL1, where the kernel handles them.
+        unsafe { self.bit(13, kde) }
+    }
+}
+
+/// OSLAR_EL1 (OS Lock Access Register)
+#[allow(non_camel_case_types)]
+#[allow(clippy::upper_case_acronyms)]
+pub struct OSLAR_EL1;
+
+impl SystemRegisterSpec for OSLAR_EL1 {
+    unsafe fn mrs() -> u64 {
+        unreachable!("OSLAR_EL1 is write-only")
+    }
+
+    unsafe fn msr(bits: u64) {
+        asm!("msr OSLAR_EL1, {}", in(reg) bits);
+    }
+}
+
+impl RegisterWritable for OSLAR_EL1 {}
+
+impl RegisterInitial for OSLAR_EL1 {
+    const INITIAL_VALUE: Self::Bits = 0;
+}
+
+impl RegisterWriter<OSLAR_EL1> {
+    /// OS Lock, which disables debug events (including watchpoints) while set.
+    pub fn oslk(&mut self, oslk: bool) {
+        // SAFETY: OSLK only enables or disables debug events, and every other bit is RES0.
+        unsafe { self.bit(0, oslk) }
+    }
+}
+
+/// ID_AA64DFR0_EL1 (AArch64 Debug Feature Register 0)
+#[allow(non_camel_case_types)]
+#[allow(clippy::upper_case_acronyms)]
+pub struct ID_AA64DFR0_EL1;
+
+impl SystemRegisterSpec for ID_AA64DFR0_EL1 {
+    unsafe fn mrs() -> u64 {
+        let bits: u64;
+        asm!("mrs {}, ID_AA64DFR0_EL1", out(reg) bits);
+        bits
+    }
+
+    unsafe fn msr(_bits: u64) {
+        unreachable!("ID_AA64DFR0_EL1 is read-only")
+    }
+}
+
+impl RegisterReadable for ID_AA64DFR0_EL1 {}
+
+impl RegisterReader<ID_AA64DFR0_EL1> {
+    /// Number of watchpoints implemented, minus one.
+    pub fn wrps(&self) -> u64 {
+        self.field(20..=23)
+    }
+
+    /// Performance Monitors Extension version, which is 0 if it's not implemented, or 0xF if it's
+    /// implementation defined rather than PMUv3.
+    pub fn pmuver(&self) -> u64 {
+        self.field(8..=11)
+    }
+}
+
+numbered_system_register!(
+    DBGWVR,
+    0 => "DBGWVR0_EL1", 1 => "DBGWVR1_EL1", 2 => "DBGWVR2_EL1", 3 => "DBGWVR3_EL1",
+    4 => "DBGWVR4_EL1", 5 => "DBGWVR5_EL1", 6 => "DBGWVR6_EL1", 7 => "DBGWVR7_EL1",
+    8 => "DBGWVR8_EL1", 9 => "DBGWVR9_EL1", 10 => "DBGWVR10_EL1", 11 => "DBGWVR11_EL1",
+    12 => "DBGWVR12_EL1", 13 => "DBGWVR13_EL1", 14 => "DBGWVR14_EL1", 15 => "DBGWVR15_EL1"
+);
+
+numbered_system_register!(
+    DBGWCR,
+    0 => "DBGWCR0_EL1", 1 => "DBGWCR1_EL1", 2 => "DBGWCR2_EL1", 3 => "DBGWCR3_EL1",
+    4 => "DBGWCR4_EL1", 5 => "DBGWCR5_EL1", 6 => "DBGWCR6_EL1", 7 => "DBGWCR7_EL1",
+    8 => "DBGWCR8_EL1", 9 => "DBGWCR9_EL1", 10 => "DBGWCR10_EL1", 11 => "DBGWCR11_EL1",
+    12 => "DBGWCR12_EL1", 13 => "DBGWCR13_EL1", 14 => "DBGWCR14_EL1", 15 => "DBGWCR15_EL1"
+);
+
+impl<const N: usize> RegisterWriter<DBGWVR<N>> {
+    /// Virtual address to watch, which must be aligned to a doubleword.
+    pub fn address(&mut self, address: u64) {
+        // bits [63:49] must be a sign extension of bit 48, which they already are for any
+        // canonical address
+        // SAFETY: any doubleword-aligned canonical address is valid, and watching one that isn't
+        // mapped just never matches.
+        unsafe { self.bits(address & !0b111) }
+    }
+}
+
+/// Which accesses a watchpoint matches, for [`RegisterWriter<DBGWCR>::lsc`].
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LoadStore {
+    Load = 0b01,
+    Store = 0b10,
+    Both = 0b11,
+}
+
+impl<const N: usize> RegisterWriter<DBGWCR<N>> {
+    /// Enable the watchpoint.
+    pub fn e(&mut self, e: bool) {
+        // SAFETY: enabling a watchpoint only causes debug exceptions, which the kernel handles.
+        unsafe { self.bit(0, e) }
+    }
+
+    /// Privilege of accesses to match: EL1 only (0b01), EL0 only (0b10), or both (0b11).
+    pub fn pac(&mut self, pac: u64) {
+        assert!(matches!(pac, 0b01..=0b11));
+        // SAFETY: we've checked that pac is one of the values above, rather than reserved.
+        unsafe { self.field(1..=2, pac) }
+    }
+
+    /// Load/store control.
+    pub fn lsc(&mut self, lsc: LoadStore) {
+        // SAFETY: every LoadStore is a valid LSC, and 0b00 (reserved) isn't one.
+        unsafe { self.field(3..=4, lsc as u64) }
+    }
+
+    /// Byte address select: which bytes of the doubleword at DBGWVR to watch.
+    pub fn bas(&mut self, bas: u8) {
+        // SAFETY: any byte selection is valid, though only contiguous ones are architected to
+        // match.
+        unsafe { self.field(5..=12, bas.into()) }
+    }
+
+    /// Address mask: watch the 2^mask bytes at DBGWVR instead, if at least 3 (with BAS all ones).
+    pub fn mask(&mut self, mask: u64) {
+        assert!(mask == 0 || (3..=31).contains(&mask));
+        // SAFETY: we've checked that mask is 0 or in 3..=31, rather than reserved.
+        unsafe { self.field(24..=28, mask) }
+    }
+}
@@ -1,6 +1,7 @@
+use gic::Field;
 use num::AsUsize;
 
-use crate::gicv2::InterruptId;
+use crate::gicv2::{CpuId, InterruptId};
 use crate::memory_mapped_register as reg;
 use crate::reg::memory_mapped::{PaddingBytes, Register};
 use crate::reg::prelude::*;
@@ -10,7 +11,7 @@ pub struct DistributorRegisterBlock {
     /// 0x000: GICD_CTLR (Distributor Control Register)
     pub ctlr: Register<GICD_CTLR>,
     /// 0x004: GICD_TYPER (Interrupt Controller Type Register)
-    pub typer: Register<u32>,
+    pub typer: Register<GICD_TYPER>,
     /// 0x008: GICD_IIDR (Distributor Implementer Identification Register)
     pub iidr: Register<u32>,
     /// 0x00C-0x01C: Reserved
@@ -24,25 +25,25 @@ pub struct DistributorRegisterBlock {
     /// 0x100-0x17C: GICD_ISENABLERn (Interrupt Set-Enable Registers)
     pub isenabler: [Register<GICD_ISENABLER>; 32],
     /// 0x180-0x1FC: GICD_ICENABLERn (Interrupt Clear-Enable Registers)
-    pub icenabler: [Register<u32>; 32],
+    pub icenabler: [Register<GICD_ICENABLER>; 32],
     /// 0x200-0x27C: GICD_ISPENDRn (Interrupt Set-Pending Registers)
-    pub ispender: [Register<u32>; 32],
+    pub ispender: [Register<GICD_ISPENDR>; 32],
     /// 0x280-0x2FC: GICD_ICPENDRn (Interrupt Clear-Pending Registers)
     pub icpendr: [Register<u32>; 32],
     /// 0x300-0x37C: GICD_ISACTIVERn (GICv2 Interrupt Set-Active Registers)
-    pub isactiver: [Register<u32>; 32],
+    pub isactiver: [Register<GICD_ISACTIVER>; 32],
     /// 0x380-0x3FC: GICD_ICACTIVERn (Interrupt Clear-Active Registers)
     pub icactiver: [Register<u32>; 32],
     /// 0x400-0x7F8: GICD_IPRIORITYRn (Interrupt Priority Registers)
-    pub ipriorityr: [Register<u32>; 255],
+    pub ipriorityr: [Register<GICD_IPRIORITYR>; 255],
     /// 0x7FC: Reserved
     _3: PaddingBytes<0x4>,
     /// 0x800-0x81C: GICD_ITARGETSRn (Interrupt Processor Targets Registers)
-    pub itargetsr: [Register<u32>; 255],
+    pub itargetsr: [Register<GICD_ITARGETSR>; 255],
     /// 0xBFC: Reserved
     _4: PaddingBytes<0x4>,
     /// 0xC00-0xCFC: GICD_ICFGRn (Interrupt Configuration Registers)
-    pub icfgr: [Register<u32>; 64],
+    pub icfgr: [Register<GICD_ICFGR>; 64],
     /// 0xD00-0xDFC: IMPLEMENTATION DEFINED registers
     _5: PaddingBytes<0x100>,
     /// 0xE00-0xEFC: GICD_NSACRn (Non-secure Access Control Registers, optional)
@@ -77,12 +78,131 @@ impl RegisterWriter<GICD_CTLR> {
     }
 }
 
-reg! { GICD_ISENABLER(u32), wi=0x0000_0000 }
+reg! { GICD_TYPER(u32), r }
+
+#[allow(dead_code)]
+impl RegisterReader<GICD_TYPER> {
+    /// ITLinesNumber, where the distributor has 32 × (N + 1) interrupt IDs, up to 1020.
+    pub fn it_lines_number(&self) -> usize {
+        self.field_as(0..=4)
+    }
+}
+
+reg! { GICD_ISENABLER(u32), rwi=0x0000_0000 }
+
+#[allow(dead_code)]
+impl RegisterReader<GICD_ISENABLER> {
+    /// Whether the interrupt whose field (see [`gic::Bitmap`]) is `field` is enabled.
+    pub fn enabled(&self, field: Field) -> bool {
+        self.bit(field.offset)
+    }
+}
 
 #[allow(dead_code)]
 impl RegisterWriter<GICD_ISENABLER> {
-    pub fn set_enable(&mut self, m: usize) {
-        unsafe { self.bit(m, true) }
+    /// Enables the interrupt whose field (see [`gic::Bitmap`]) is `field`.
+    pub fn set_enable(&mut self, field: Field) {
+        // SAFETY: writing one only enables this interrupt, and the zeros have no effect.
+        unsafe { self.bit(field.offset, true) }
+    }
+}
+
+reg! { GICD_ICENABLER(u32), wi=0x0000_0000 }
+
+#[allow(dead_code)]
+impl RegisterWriter<GICD_ICENABLER> {
+    /// Disables the interrupt whose field (see [`gic::Bitmap`]) is `field`.
+    pub fn clear_enable(&mut self, field: Field) {
+        // SAFETY: writing one only disables this interrupt, and the zeros have no effect.
+        unsafe { self.bit(field.offset, true) }
+    }
+}
+
+reg! { GICD_ISPENDR(u32), r }
+
+#[allow(dead_code)]
+impl RegisterReader<GICD_ISPENDR> {
+    /// Whether the interrupt whose field (see [`gic::Bitmap`]) is `field` is pending.
+    pub fn pending(&self, field: Field) -> bool {
+        self.bit(field.offset)
+    }
+}
+
+reg! { GICD_ISACTIVER(u32), r }
+
+#[allow(dead_code)]
+impl RegisterReader<GICD_ISACTIVER> {
+    /// Whether the interrupt whose field (see [`gic::Bitmap`]) is `field` is active.
+    pub fn active(&self, field: Field) -> bool {
+        self.bit(field.offset)
+    }
+}
+
+reg! { GICD_IPRIORITYR(u32), rw }
+
+#[allow(dead_code)]
+impl RegisterReader<GICD_IPRIORITYR> {
+    /// Priority of the interrupt whose field (see [`gic::Priority`]) is `field`, where lower
+    /// values are higher priorities.
+    pub fn priority(&self, field: Field) -> u8 {
+        self.field_as(field.bits())
+    }
+}
+
+#[allow(dead_code)]
+impl RegisterWriter<GICD_IPRIORITYR> {
+    /// Sets the priority of the interrupt whose field (see [`gic::Priority`]) is `field`.
+    ///
+    /// Only the upper bits of the priority may be implemented, so the lower bits may read as zero.
+    pub fn priority(&mut self, field: Field, priority: u8) {
+        // SAFETY: every priority is valid, and unimplemented bits are ignored.
+        unsafe { self.field(field.bits(), priority.into()) }
+    }
+}
+
+reg! { GICD_ITARGETSR(u32), rw }
+
+#[allow(dead_code)]
+impl RegisterReader<GICD_ITARGETSR> {
+    /// CPUs the interrupt whose field (see [`gic::Targets`]) is `field` is forwarded to, one bit
+    /// per CPU interface.
+    pub fn targets(&self, field: Field) -> u8 {
+        self.field_as(field.bits())
+    }
+}
+
+#[allow(dead_code)]
+impl RegisterWriter<GICD_ITARGETSR> {
+    /// Sets the CPUs the interrupt whose field (see [`gic::Targets`]) is `field` is forwarded to.
+    ///
+    /// This has no effect for SGIs and PPIs, whose targets are read-only.
+    pub fn targets(&mut self, field: Field, targets: u8) {
+        // SAFETY: every set of targets is valid, and bits for CPU interfaces that don't exist are
+        // ignored.
+        unsafe { self.field(field.bits(), targets.into()) }
+    }
+}
+
+reg! { GICD_ICFGR(u32), rw }
+
+#[allow(dead_code)]
+impl RegisterReader<GICD_ICFGR> {
+    /// Whether the interrupt whose field (see [`gic::Config`]) is `field` is edge-triggered,
+    /// rather than level-sensitive.
+    pub fn edge_triggered(&self, field: Field) -> bool {
+        // the upper bit of each field is Int_config[1], and the lower bit is reserved
+        self.bit(field.offset + 1)
+    }
+}
+
+#[allow(dead_code)]
+impl RegisterWriter<GICD_ICFGR> {
+    /// Sets whether the interrupt whose field (see [`gic::Config`]) is `field` is edge-triggered.
+    ///
+    /// This has no effect for SGIs, and for PPIs and SPIs whose trigger isn't programmable.
+    pub fn edge_triggered(&mut self, field: Field, edge_triggered: bool) {
+        // SAFETY: the high bit of each field only chooses the trigger, and the low bit is kept.
+        unsafe { self.bit(field.offset + 1, edge_triggered) }
     }
 }
 
@@ -125,7 +245,7 @@ pub struct CpuInterfaceRegisterBlock {
     // for some reason, this gap is left unmentioned
     _3: PaddingBytes<0xf00>,
     /// 0x1000: GICC_DIR (Deactivate Interrupt Register)
-    pub dir: Register<u32>,
+    pub dir: Register<GICC_DIR>,
 }
 
 reg! { GICC_CTLR(u32), rwi=0x0000_0000 }
@@ -135,6 +255,9 @@ impl RegisterReader<GICC_CTLR> {
     pub fn enable(&self) -> bool {
         self.bit(0)
     }
+    pub fn eoi_mode(&self) -> bool {
+        self.bit(9)
+    }
 }
 
 #[allow(dead_code)]
@@ -142,6 +265,14 @@ impl RegisterWriter<GICC_CTLR> {
     pub fn enable(&mut self, enable: bool) {
         unsafe { self.bit(0, enable) }
     }
+    /// EOImode (EOImodeS in the Secure copy, where it only applies to Group 0 interrupts).
+    ///
+    /// When set, writes to GICC_EOIR only drop the running priority, and interrupts must be
+    /// deactivated separately by writing to GICC_DIR.
+    pub fn eoi_mode(&mut self, eoi_mode: bool) {
+        // SAFETY: the IRQ handler deactivates interrupts through GICC_DIR in either mode.
+        unsafe { self.bit(9, eoi_mode) }
+    }
 }
 
 reg! { GICC_PMR(u32), rwi=0x0000_0000 }
@@ -149,14 +280,15 @@ reg! { GICC_PMR(u32), rwi=0x0000_0000 }
 #[allow(dead_code)]
 impl RegisterReader<GICC_PMR> {
     pub fn priority(&self) -> u8 {
-        self.field(0..=7) as _
+        self.field_as(0..=7)
     }
 }
 
 #[allow(dead_code)]
 impl RegisterWriter<GICC_PMR> {
     pub fn priority(&mut self, priority: u8) {
-        unsafe { self.field(0..=7, priority as _) }
+        // SAFETY: every priority mask is valid, and unimplemented bits are ignored.
+        unsafe { self.field(0..=7, priority.into()) }
     }
 }
 
@@ -167,11 +299,13 @@ impl RegisterReader<GICC_IAR> {
     pub fn entire(&self) -> u32 {
         self.bits()
     }
-    pub fn cpuid(&self) -> u8 {
-        self.field(10..=12) as _
+    pub fn cpuid(&self) -> CpuId {
+        // SAFETY: the field is 3 bits wide, so it's always a valid CPU interface number.
+        unsafe { CpuId::unchecked_new(self.field_as(10..=12)) }
     }
     pub fn interrupt_id(&self) -> InterruptId {
-        self.field(0..=9).as_usize().try_into().unwrap()
+        // SAFETY: the field is 10 bits wide, so it's always a valid interrupt ID.
+        unsafe { InterruptId::unchecked_new(self.field(0..=9).as_usize()) }
     }
 }
 
@@ -185,3 +319,16 @@ impl RegisterWriter<GICC_EOIR> {
         unsafe { self.bits(iar) }
     }
 }
+
+// IHI 0048B.b § 4.4.15 “If software writes the ID of a spurious interrupt to the
+// GICC_DIR, the GIC ignores that write.”
+reg! { GICC_DIR(u32), wi=0x000003FF }
+
+#[allow(dead_code)]
+impl RegisterWriter<GICC_DIR> {
+    pub fn entire_iar(&mut self, iar: u32) {
+        // SAFETY: any value read from GICC_IAR is valid, and writing an interrupt that isn't
+        // active is ignored.
+        unsafe { self.bits(iar) }
+    }
+}
@@ -1,4 +1,43 @@
+// HACK: some system registers are numbered, like the watchpoint registers, but asm! needs the
+// register name as a literal, so we match on the number and expand every possible name.
+macro_rules! numbered_system_register {
+    ($name:ident, $($n:literal => $special:literal),+) => {
+        #[allow(clippy::upper_case_acronyms)]
+        pub struct $name<const N: usize>;
+
+        impl<const N: usize> SystemRegisterSpec for $name<N> {
+            unsafe fn mrs() -> u64 {
+                let bits: u64;
+                match N {
+                    $($n => asm!(concat!("mrs {}, ", $special), out(reg) bits),)+
+                    _ => unreachable!(),
+                }
+                bits
+            }
+
+            unsafe fn msr(bits: u64) {
+                match N {
+                    $($n => asm!(concat!("msr ", $special, ", {}"), in(reg) bits),)+
+                    _ => unreachable!(),
+                }
+            }
+        }
+
+        impl<const N: usize> RegisterReadable for $name<N> {}
+
+        impl<const N: usize> RegisterWritable for $name<N> {}
+
+        impl<const N: usize> RegisterInitial for $name<N> {
+            const INITIAL_VALUE: Self::Bits = 0;
+        }
+    };
+}
+
 pub mod daif;
+pub mod debug;
 pub mod gicv2;
 pub mod nzcv;
 pub mod pl011;
+pub mod pmu;
+pub mod sctlr;
+pub mod virtio_mmio;
@@ -11,7 +11,7 @@ pub struct Pl011RegisterBlock {
     /// 0x008-0x014: Reserved
     _0: PaddingBytes<0x10>,
     /// 0x018: UARTFR (Flag Register)
-    pub fr: Register<u32>,
+    pub fr: Register<UARTFR>,
     /// 0x01C: Reserved
     _1: PaddingBytes<0x4>,
     /// 0x020: UARTILPR (IrDA Low-Power Counter Register)
@@ -27,7 +27,7 @@ pub struct Pl011RegisterBlock {
     /// 0x034: UARTIFLS (Interrupt FIFO Level Select Register)
     pub ifls: Register<u32>,
     /// 0x038: UARTIMSC (Interrupt Mask Set/Clear Register)
-    pub imsc: Register<u32>,
+    pub imsc: Register<UARTIMSC>,
     /// 0x03C: UARTRIS (Raw Interrupt Status Register)
     pub ris: Register<u32>,
     /// 0x040: UARTMIS (Masked Interrupt Status Register)
@@ -55,13 +55,61 @@ reg! { UARTDR(u32), rwi=0x0000_0000 }
 #[allow(dead_code)]
 impl RegisterReader<UARTDR> {
     pub fn data(&self) -> u8 {
-        self.field(0..=7) as _
+        self.field_as(0..=7)
     }
 }
 
 #[allow(dead_code)]
 impl RegisterWriter<UARTDR> {
     pub fn data(&mut self, data: u8) {
-        unsafe { self.field(0..=7, data as _) }
+        // SAFETY: any byte can be transmitted.
+        unsafe { self.field(0..=7, data.into()) }
+    }
+}
+
+reg! { UARTFR(u32), r }
+
+#[allow(dead_code)]
+impl RegisterReader<UARTFR> {
+    /// Transmit FIFO full.
+    pub fn txff(&self) -> bool {
+        self.bit(5)
+    }
+    /// Receive FIFO empty.
+    pub fn rxfe(&self) -> bool {
+        self.bit(4)
+    }
+    /// UART busy transmitting, until the transmit FIFO is empty and the last byte has been sent.
+    pub fn busy(&self) -> bool {
+        self.bit(3)
+    }
+}
+
+reg! { UARTIMSC(u32), rwi=0x0000_0000 }
+
+#[allow(dead_code)]
+impl RegisterReader<UARTIMSC> {
+    /// Receive timeout interrupt mask.
+    pub fn rtim(&self) -> bool {
+        self.bit(6)
+    }
+    /// Receive interrupt mask.
+    pub fn rxim(&self) -> bool {
+        self.bit(4)
+    }
+}
+
+#[allow(dead_code)]
+impl RegisterWriter<UARTIMSC> {
+    /// Receive timeout interrupt mask.
+    pub fn rtim(&mut self, rtim: bool) {
+        // SAFETY: the interrupt only reaches the kernel once it's enabled in the GIC, with a
+        // handler.
+        unsafe { self.bit(6, rtim) }
+    }
+    /// Receive interrupt mask.
+    pub fn rxim(&mut self, rxim: bool) {
+        // SAFETY: see rtim.
+        unsafe { self.bit(4, rxim) }
     }
 }
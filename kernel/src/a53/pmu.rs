@@ -0,0 +1,210 @@
+//! Performance Monitors Extension (PMUv3) registers, for counting cycles and events.
+use core::arch::asm;
+
+use crate::reg::prelude::*;
+use crate::reg::system::SystemRegisterSpec;
+
+/// PMCR_EL0 (Performance Monitors Control Register)
+#[allow(non_camel_case_types)]
+#[allow(clippy::upper_case_acronyms)]
+pub struct PMCR_EL0;
+
+impl SystemRegisterSpec for PMCR_EL0 {
+    unsafe fn mrs() -> u64 {
+        let bits: u64;
+        asm!("mrs {}, PMCR_EL0", out(reg) bits);
+        bits
+    }
+
+    unsafe fn msr(bits: u64) {
+        asm!("msr PMCR_EL0, {}", in(reg) bits);
+    }
+}
+
+impl RegisterReadable for PMCR_EL0 {}
+
+impl RegisterWritable for PMCR_EL0 {}
+
+impl RegisterReader<PMCR_EL0> {
+    /// Number of event counters implemented.
+    pub fn n(&self) -> u64 {
+        self.field(11..=15)
+    }
+}
+
+impl RegisterWriter<PMCR_EL0> {
+    /// Enable every counter that's enabled in PMCNTENSET_EL0.
+    pub fn e(&mut self, e: bool) {
+        // SAFETY: counting events has no effect on anything but the counters.
+        unsafe { self.bit(0, e) }
+    }
+
+    /// Reset every event counter to zero. Always reads as zero.
+    pub fn p(&mut self, p: bool) {
+        // SAFETY: the counters are only read by pmu.rs, which expects them to be reset.
+        unsafe { self.bit(1, p) }
+    }
+
+    /// Reset the cycle counter to zero. Always reads as zero.
+    pub fn c(&mut self, c: bool) {
+        // SAFETY: see p.
+        unsafe { self.bit(2, c) }
+    }
+
+    /// Count every cycle, rather than every 64th cycle.
+    pub fn d(&mut self, d: bool) {
+        // SAFETY: the divider only changes how fast the cycle counter counts.
+        unsafe { self.bit(3, d) }
+    }
+
+    /// Overflow the cycle counter at 64 bits, rather than 32 bits.
+    pub fn lc(&mut self, lc: bool) {
+        // SAFETY: LC only changes when the cycle counter overflows, and its overflow interrupt
+        // is never enabled.
+        unsafe { self.bit(6, lc) }
+    }
+}
+
+/// PMCNTENSET_EL0 (Performance Monitors Count Enable Set register)
+///
+/// Writing zero bits has no effect, so this is written from zero to enable counters.
+#[allow(non_camel_case_types)]
+#[allow(clippy::upper_case_acronyms)]
+pub struct PMCNTENSET_EL0;
+
+impl SystemRegisterSpec for PMCNTENSET_EL0 {
+    unsafe fn mrs() -> u64 {
+        let bits: u64;
+        asm!("mrs {}, PMCNTENSET_EL0", out(reg) bits);
+        bits
+    }
+
+    unsafe fn msr(bits: u64) {
+        asm!("msr PMCNTENSET_EL0, {}", in(reg) bits);
+    }
+}
+
+impl RegisterWritable for PMCNTENSET_EL0 {}
+
+impl RegisterInitial for PMCNTENSET_EL0 {
+    const INITIAL_VALUE: Self::Bits = 0;
+}
+
+impl RegisterWriter<PMCNTENSET_EL0> {
+    /// Enable event counter `n`.
+    pub fn p(&mut self, n: usize, p: bool) {
+        assert!(n < 31, "there are at most 31 event counters");
+        // SAFETY: enabling a counter has no effect on anything but the counter, and we've checked
+        // that n is an event counter, not the cycle counter's bit or beyond it.
+        unsafe { self.bit(n, p) }
+    }
+
+    /// Enable the cycle counter.
+    pub fn c(&mut self, c: bool) {
+        // SAFETY: enabling the cycle counter has no effect on anything but the counter.
+        unsafe { self.bit(31, c) }
+    }
+}
+
+/// PMCCFILTR_EL0 (Performance Monitors Cycle Count Filter Register)
+#[allow(non_camel_case_types)]
+#[allow(clippy::upper_case_acronyms)]
+pub struct PMCCFILTR_EL0;
+
+impl SystemRegisterSpec for PMCCFILTR_EL0 {
+    unsafe fn mrs() -> u64 {
+        let bits: u64;
+        asm!("mrs {}, PMCCFILTR_EL0", out(reg) bits);
+        bits
+    }
+
+    unsafe fn msr(bits: u64) {
+        asm!("msr PMCCFILTR_EL0, {}", in(reg) bits);
+    }
+}
+
+impl RegisterWritable for PMCCFILTR_EL0 {}
+
+/// Counts at EL0 and EL1, but not at EL2 or EL3.
+impl RegisterInitial for PMCCFILTR_EL0 {
+    const INITIAL_VALUE: Self::Bits = 0;
+}
+
+/// PMCCNTR_EL0 (Performance Monitors Cycle Count Register)
+#[allow(non_camel_case_types)]
+#[allow(clippy::upper_case_acronyms)]
+pub struct PMCCNTR_EL0;
+
+impl SystemRegisterSpec for PMCCNTR_EL0 {
+    unsafe fn mrs() -> u64 {
+        let bits: u64;
+        asm!("mrs {}, PMCCNTR_EL0", out(reg) bits);
+        bits
+    }
+
+    unsafe fn msr(bits: u64) {
+        asm!("msr PMCCNTR_EL0, {}", in(reg) bits);
+    }
+}
+
+impl RegisterReadable for PMCCNTR_EL0 {}
+
+impl RegisterReader<PMCCNTR_EL0> {
+    /// Number of cycles counted.
+    pub fn ccnt(&self) -> u64 {
+        self.bits()
+    }
+}
+
+/// PMCEID0_EL0 (Performance Monitors Common Event Identification register 0)
+#[allow(non_camel_case_types)]
+#[allow(clippy::upper_case_acronyms)]
+pub struct PMCEID0_EL0;
+
+impl SystemRegisterSpec for PMCEID0_EL0 {
+    unsafe fn mrs() -> u64 {
+        let bits: u64;
+        asm!("mrs {}, PMCEID0_EL0", out(reg) bits);
+        bits
+    }
+
+    unsafe fn msr(_bits: u64) {
+        unreachable!("PMCEID0_EL0 is read-only")
+    }
+}
+
+impl RegisterReadable for PMCEID0_EL0 {}
+
+impl RegisterReader<PMCEID0_EL0> {
+    /// Whether common event `event` (0x00 to 0x1F) is implemented.
+    pub fn id(&self, event: u16) -> bool {
+        assert!(event < 0x20, "PMCEID0_EL0 only covers events 0x00 to 0x1F");
+        self.bit(event.into())
+    }
+}
+
+numbered_system_register!(
+    PMEVTYPER,
+    0 => "PMEVTYPER0_EL0", 1 => "PMEVTYPER1_EL0", 2 => "PMEVTYPER2_EL0"
+);
+
+numbered_system_register!(
+    PMEVCNTR,
+    0 => "PMEVCNTR0_EL0", 1 => "PMEVCNTR1_EL0", 2 => "PMEVCNTR2_EL0"
+);
+
+impl<const N: usize> RegisterWriter<PMEVTYPER<N>> {
+    /// Event to count. Only the low 10 bits are used before ARMv8.1.
+    pub fn evt_count(&mut self, event: u16) {
+        // SAFETY: an event the CPU doesn't implement is counted as nothing, rather than being
+        // undefined.
+        unsafe { self.field(0..=15, event.into()) }
+    }
+}
+
+impl<const N: usize> RegisterReader<PMEVCNTR<N>> {
+    /// Number of events counted.
+    pub fn evcnt(&self) -> u32 {
+        self.field_as(0..=31)
+    }
+}
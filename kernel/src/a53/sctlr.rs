@@ -0,0 +1,70 @@
+//! The system control register, for the MMU and alignment checking.
+use core::arch::asm;
+
+use crate::reg::prelude::*;
+use crate::reg::system::SystemRegisterSpec;
+
+/// SCTLR_EL1 (System Control Register (EL1))
+///
+/// Most of the other bits are RES1 or control things set up by entry.s, so this is only ever
+/// modified, never written from scratch.
+#[allow(non_camel_case_types)]
+#[allow(clippy::upper_case_acronyms)]
+pub struct SCTLR_EL1;
+
+impl SystemRegisterSpec for SCTLR_EL1 {
+    unsafe fn mrs() -> u64 {
+        let bits: u64;
+        asm!("mrs {}, SCTLR_EL1", out(reg) bits);
+        bits
+    }
+
+    unsafe fn msr(bits: u64) {
+        asm!("msr SCTLR_EL1, {}", in(reg) bits);
+    }
+}
+
+impl RegisterReadable for SCTLR_EL1 {}
+
+impl RegisterWritable for SCTLR_EL1 {}
+
+#[allow(dead_code)]
+impl RegisterReader<SCTLR_EL1> {
+    /// MMU enabled for EL1&0 stage 1 translation.
+    pub fn m(&self) -> bool {
+        self.bit(0)
+    }
+
+    /// Alignment checking of loads and stores at EL1 and EL0.
+    pub fn a(&self) -> bool {
+        self.bit(1)
+    }
+
+    /// Stack pointer alignment checking at EL1.
+    pub fn sa(&self) -> bool {
+        self.bit(3)
+    }
+
+    /// Stack pointer alignment checking at EL0.
+    pub fn sa0(&self) -> bool {
+        self.bit(4)
+    }
+}
+
+impl RegisterWriter<SCTLR_EL1> {
+    pub fn a(&mut self, a: bool) {
+        // SAFETY: alignment checking only makes misaligned accesses fault, which the kernel
+        // handles.
+        unsafe { self.bit(1, a) }
+    }
+
+    pub fn sa(&mut self, sa: bool) {
+        // SAFETY: see a. the exception vectors and the scheduler keep SP_EL1 aligned.
+        unsafe { self.bit(3, sa) }
+    }
+
+    pub fn sa0(&mut self, sa0: bool) {
+        // SAFETY: see a.
+        unsafe { self.bit(4, sa0) }
+    }
+}
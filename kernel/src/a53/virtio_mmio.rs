@@ -0,0 +1,232 @@
+use crate::memory_mapped_register as reg;
+use crate::reg::memory_mapped::{PaddingBytes, Register};
+use crate::reg::prelude::*;
+
+/// Registers of a virtio-mmio transport, in both the legacy interface (version 1) and the modern
+/// one (version 2). Registers only in one of them are marked as such.
+#[repr(C)]
+pub struct VirtioMmioRegisterBlock {
+    /// 0x000: MagicValue, which reads as "virt" in little-endian
+    pub magic_value: Register<MagicValue>,
+    /// 0x004: Version
+    pub version: Register<Version>,
+    /// 0x008: DeviceID, which is zero if there's no device behind the transport
+    pub device_id: Register<DeviceID>,
+    /// 0x00C: VendorID
+    pub vendor_id: Register<VendorID>,
+    /// 0x010: DeviceFeatures
+    pub device_features: Register<DeviceFeatures>,
+    /// 0x014: DeviceFeaturesSel
+    pub device_features_sel: Register<DeviceFeaturesSel>,
+    /// 0x018-0x01C: Reserved
+    _0: PaddingBytes<0x8>,
+    /// 0x020: DriverFeatures
+    pub driver_features: Register<DriverFeatures>,
+    /// 0x024: DriverFeaturesSel
+    pub driver_features_sel: Register<DriverFeaturesSel>,
+    /// 0x028: GuestPageSize (legacy only)
+    pub guest_page_size: Register<GuestPageSize>,
+    /// 0x02C: Reserved
+    _1: PaddingBytes<0x4>,
+    /// 0x030: QueueSel
+    pub queue_sel: Register<QueueSel>,
+    /// 0x034: QueueNumMax
+    pub queue_num_max: Register<QueueNumMax>,
+    /// 0x038: QueueNum
+    pub queue_num: Register<QueueNum>,
+    /// 0x03C: QueueAlign (legacy only)
+    pub queue_align: Register<QueueAlign>,
+    /// 0x040: QueuePFN (legacy only)
+    pub queue_pfn: Register<QueuePFN>,
+    /// 0x044: QueueReady (modern only)
+    pub queue_ready: Register<QueueReady>,
+    /// 0x048-0x04C: Reserved
+    _2: PaddingBytes<0x8>,
+    /// 0x050: QueueNotify
+    pub queue_notify: Register<QueueNotify>,
+    /// 0x054-0x05C: Reserved
+    _3: PaddingBytes<0xc>,
+    /// 0x060: InterruptStatus
+    pub interrupt_status: Register<InterruptStatus>,
+    /// 0x064: InterruptACK
+    pub interrupt_ack: Register<InterruptACK>,
+    /// 0x068-0x06C: Reserved
+    _4: PaddingBytes<0x8>,
+    /// 0x070: Status
+    pub status: Register<Status>,
+    /// 0x074-0x07C: Reserved
+    _5: PaddingBytes<0xc>,
+    /// 0x080: QueueDescLow; 0x084: QueueDescHigh (modern only)
+    pub queue_desc: [Register<QueueAddress>; 2],
+    /// 0x088-0x08C: Reserved
+    _6: PaddingBytes<0x8>,
+    /// 0x090: QueueDriverLow; 0x094: QueueDriverHigh (modern only)
+    pub queue_driver: [Register<QueueAddress>; 2],
+    /// 0x098-0x09C: Reserved
+    _7: PaddingBytes<0x8>,
+    /// 0x0A0: QueueDeviceLow; 0x0A4: QueueDeviceHigh (modern only)
+    pub queue_device: [Register<QueueAddress>; 2],
+    /// 0x0A8-0x0F8: Reserved, or shared memory registers we don't use
+    _8: PaddingBytes<0x54>,
+    /// 0x0FC: ConfigGeneration (modern only)
+    pub config_generation: Register<ConfigGeneration>,
+    // 0x100 onwards: device-specific configuration space
+}
+
+/// Offset of the device-specific configuration space from the start of the registers.
+pub const CONFIG_OFFSET: usize = 0x100;
+
+/// Defines registers holding a single 32-bit value, which is all most virtio-mmio registers are.
+macro_rules! value_register {
+    ($($name:ident, r;)*) => {$(
+        reg! { $name(u32), r }
+
+        #[allow(dead_code)]
+        impl RegisterReader<$name> {
+            pub fn value(&self) -> u32 {
+                self.bits()
+            }
+        }
+    )*};
+    ($($name:ident, w;)*) => {$(
+        reg! { $name(u32), wi=0x0000_0000 }
+
+        #[allow(dead_code)]
+        impl RegisterWriter<$name> {
+            pub fn value(&mut self, value: u32) {
+                // SAFETY: these registers hold whole values, like a selector or an address, so
+                // every value is valid as far as the register is concerned. the driver still has
+                // to write values the device makes sense of.
+                unsafe { self.bits(value) }
+            }
+        }
+    )*};
+    ($($name:ident, rw;)*) => {$(
+        reg! { $name(u32), rwi=0x0000_0000 }
+
+        #[allow(dead_code)]
+        impl RegisterReader<$name> {
+            pub fn value(&self) -> u32 {
+                self.bits()
+            }
+        }
+
+        #[allow(dead_code)]
+        impl RegisterWriter<$name> {
+            pub fn value(&mut self, value: u32) {
+                // SAFETY: these registers hold whole values, like a selector or an address, so
+                // every value is valid as far as the register is concerned. the driver still has
+                // to write values the device makes sense of.
+                unsafe { self.bits(value) }
+            }
+        }
+    )*};
+}
+
+value_register! {
+    MagicValue, r;
+    Version, r;
+    DeviceID, r;
+    VendorID, r;
+    DeviceFeatures, r;
+    QueueNumMax, r;
+    ConfigGeneration, r;
+}
+
+value_register! {
+    DeviceFeaturesSel, w;
+    DriverFeatures, w;
+    DriverFeaturesSel, w;
+    GuestPageSize, w;
+    QueueSel, w;
+    QueueNum, w;
+    QueueAlign, w;
+    QueueNotify, w;
+    QueueAddress, w;
+}
+
+value_register! {
+    QueuePFN, rw;
+    QueueReady, rw;
+}
+
+reg! { InterruptStatus(u32), r }
+
+#[allow(dead_code)]
+impl RegisterReader<InterruptStatus> {
+    /// Whether the device has used a buffer in at least one of its queues.
+    pub fn used_buffer(&self) -> bool {
+        self.bit(0)
+    }
+    /// Whether the device's configuration has changed.
+    pub fn config_change(&self) -> bool {
+        self.bit(1)
+    }
+}
+
+reg! { InterruptACK(u32), wi=0x0000_0000 }
+
+#[allow(dead_code)]
+impl RegisterWriter<InterruptACK> {
+    /// Acknowledges the interrupts whose bits (as in [`InterruptStatus`]) are set in `bits`.
+    pub fn ack(&mut self, bits: u32) {
+        // SAFETY: bits that aren't set don't acknowledge anything, and acknowledging an
+        // interrupt that isn't pending does nothing.
+        unsafe { self.bits(bits) }
+    }
+}
+
+reg! { Status(u32), rwi=0x0000_0000 }
+
+#[allow(dead_code)]
+impl RegisterReader<Status> {
+    pub fn acknowledge(&self) -> bool {
+        self.bit(0)
+    }
+    pub fn driver(&self) -> bool {
+        self.bit(1)
+    }
+    pub fn driver_ok(&self) -> bool {
+        self.bit(2)
+    }
+    pub fn features_ok(&self) -> bool {
+        self.bit(3)
+    }
+    /// Whether the device has hit an error it can't recover from without a reset.
+    pub fn device_needs_reset(&self) -> bool {
+        self.bit(6)
+    }
+    pub fn failed(&self) -> bool {
+        self.bit(7)
+    }
+}
+
+#[allow(dead_code)]
+impl RegisterWriter<Status> {
+    /// The driver has noticed the device.
+    pub fn acknowledge(&mut self, acknowledge: bool) {
+        // SAFETY: the status bits are only ever set in the order the virtio spec describes, by
+        // the transport in virtio/mod.rs, and clearing them all resets the device.
+        unsafe { self.bit(0, acknowledge) }
+    }
+    /// The driver knows how to drive the device.
+    pub fn driver(&mut self, driver: bool) {
+        // SAFETY: see acknowledge.
+        unsafe { self.bit(1, driver) }
+    }
+    /// The driver is set up and ready to drive the device.
+    pub fn driver_ok(&mut self, driver_ok: bool) {
+        // SAFETY: see acknowledge.
+        unsafe { self.bit(2, driver_ok) }
+    }
+    /// The driver has acknowledged the features it understands, and negotiation is complete.
+    pub fn features_ok(&mut self, features_ok: bool) {
+        // SAFETY: see acknowledge.
+        unsafe { self.bit(3, features_ok) }
+    }
+    /// The driver has given up on the device.
+    pub fn failed(&mut self, failed: bool) {
+        // SAFETY: see acknowledge.
+        unsafe { self.bit(7, failed) }
+    }
+}
@@ -0,0 +1,51 @@
+//! Untyped virtual and physical addresses.
+//!
+//! Unlike [`crate::tt::page::PhysicalAddress`], these don't point to a value of any particular
+//! type, and can't be dereferenced without first converting them to a pointer.
+use core::fmt;
+
+/// A virtual address, in either the kernel's or a task's address space.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(transparent)]
+pub struct VirtAddr(usize);
+
+/// A physical address.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(transparent)]
+pub struct PhysAddr(usize);
+
+impl VirtAddr {
+    pub const fn new(addr: usize) -> Self {
+        Self(addr)
+    }
+
+    pub const fn addr(self) -> usize {
+        self.0
+    }
+
+    pub fn as_ptr<T>(self) -> *const T {
+        self.0 as *const T
+    }
+}
+
+impl PhysAddr {
+    pub const fn new(addr: usize) -> Self {
+        Self(addr)
+    }
+
+    pub const fn addr(self) -> usize {
+        self.0
+    }
+}
+
+impl fmt::Debug for VirtAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "VirtAddr({:#018x})", self.0)
+    }
+}
+
+impl fmt::Debug for PhysAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PhysAddr({:#018x})", self.0)
+    }
+}
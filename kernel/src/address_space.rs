@@ -0,0 +1,176 @@
+//! The regions of memory each task can use, and where each one came from, for the `pmap` console
+//! command.
+//!
+//! Every task runs in the kernel's translation tables for now, so an [`AddressSpace`] doesn't map
+//! anything itself. It records what was mapped for a task, by whom, and with what permissions, so
+//! that can be shown alongside how much of each region is resident (see [`tt::resident_pages`]).
+use core::fmt;
+use core::ops::Range;
+
+use region_tree::{InsertError, RegionTree};
+
+use crate::addr::VirtAddr;
+use crate::error::KernelError;
+use crate::{linker_symbols, tt};
+
+/// Maximum number of regions in an address space.
+const MAX_REGIONS: usize = 8;
+
+/// What backs the memory of a region.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backing {
+    /// Memory allocated for the task alone, like its stack.
+    Anonymous,
+    /// Loaded from a file, which for now can only be the kernel image that tasks run from.
+    File,
+    /// Memory shared with other tasks or the kernel, which each of them can see the writes of.
+    Shared,
+}
+
+impl fmt::Display for Backing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Anonymous => write!(f, "anon"),
+            Self::File => write!(f, "file"),
+            Self::Shared => write!(f, "shared"),
+        }
+    }
+}
+
+/// A range of pages in an address space, and what it's for.
+#[derive(Clone, Debug)]
+pub struct Region {
+    pub range: Range<VirtAddr>,
+    /// What the task may do with the memory, as translation table flags (see
+    /// [`translation_tables::descriptor::page::permission_bits`]).
+    pub flags: &'static str,
+    pub backing: Backing,
+    /// What the region is, like `stack`, or the name of the file it was loaded from.
+    pub name: &'static str,
+}
+
+impl Region {
+    fn new(range: &Range<VirtAddr>, contents: &Contents) -> Self {
+        Self {
+            range: range.clone(),
+            flags: contents.flags,
+            backing: contents.backing,
+            name: contents.name,
+        }
+    }
+
+    /// Returns the region of the kernel image that's loaded from the ELF, which every task runs
+    /// from. The NOLOAD sections after it, like the boot tasks' stacks, are regions of their own.
+    pub fn kernel_image() -> Self {
+        Self {
+            range: linker_symbols::kernel_start()..linker_symbols::boot_stack_bottom(),
+            // TODO: split this up once the kernel's sections are mapped with their own permissions
+            flags: "rwx",
+            backing: Backing::File,
+            name: "kernel",
+        }
+    }
+
+    /// Returns the region of a task's stack, which is anonymous memory.
+    pub fn stack(range: Range<VirtAddr>) -> Self {
+        Self {
+            range,
+            flags: "rw",
+            backing: Backing::Anonymous,
+            name: "stack",
+        }
+    }
+
+    /// Returns a region of memory that the kernel writes to for the task, like a snapshot.
+    pub fn shared(range: Range<VirtAddr>, name: &'static str) -> Self {
+        Self {
+            range,
+            flags: "rw",
+            backing: Backing::Shared,
+            name,
+        }
+    }
+
+    /// Returns the size of the region, in bytes.
+    pub fn len(&self) -> usize {
+        self.range.end.addr() - self.range.start.addr()
+    }
+
+    /// Returns the number of pages in the region that are mapped, which is every page that was
+    /// ever mapped, since nothing is paged out.
+    pub fn resident_pages(&self) -> usize {
+        tt::resident_pages(self.range.start.addr()..self.range.end.addr())
+    }
+
+    /// Returns the region's flags in `pmap` style, like `rw-`.
+    pub fn permissions(&self) -> [char; 3] {
+        let flag = |c| if self.flags.contains(c) { c } else { '-' };
+
+        [flag('r'), flag('w'), flag('x')]
+    }
+}
+
+/// What's in a region, which is everything about it but its range, as kept in an
+/// [`AddressSpace`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Contents {
+    flags: &'static str,
+    backing: Backing,
+    name: &'static str,
+}
+
+/// The regions a task can use, none of which overlap, kept in a tree so the region containing an
+/// address can be found in O(log n).
+#[derive(Debug)]
+pub struct AddressSpace {
+    regions: RegionTree<VirtAddr, Contents, MAX_REGIONS>,
+}
+
+impl AddressSpace {
+    pub const fn new() -> Self {
+        Self {
+            regions: RegionTree::new(),
+        }
+    }
+
+    /// Adds a region, failing if it overlaps a region already in the address space.
+    pub fn insert(&mut self, region: Region) -> Result<(), KernelError> {
+        let contents = Contents {
+            flags: region.flags,
+            backing: region.backing,
+            name: region.name,
+        };
+
+        self.regions
+            .insert(region.range.clone(), contents)
+            .map_err(|error| match error {
+                InsertError::Empty => KernelError::InvalidArgument {
+                    reason: "region must not be empty",
+                },
+                InsertError::Full => KernelError::TooManyRegions { max: MAX_REGIONS },
+                InsertError::Overlaps(other) => KernelError::RegionOverlaps {
+                    address: other.start.max(region.range.start).addr(),
+                },
+            })
+    }
+
+    /// Returns the region containing `address`, if any.
+    #[allow(dead_code)]
+    pub fn find(&self, address: VirtAddr) -> Option<Region> {
+        self.regions
+            .get(address)
+            .map(|(range, contents)| Region::new(range, contents))
+    }
+
+    /// Returns the first region named `name`, if any.
+    pub fn find_named(&self, name: &str) -> Option<Region> {
+        self.regions().find(|region| region.name == name)
+    }
+
+    /// Returns every region, lowest address first.
+    pub fn regions(&self) -> impl Iterator<Item = Region> + '_ {
+        self.regions
+            .iter()
+            .map(|(range, contents)| Region::new(range, contents))
+    }
+}
@@ -0,0 +1,111 @@
+//! The alarm, a secondary clock event source for coarse wakeups, like when the architectural
+//! timer won't do because the CPU is in a low-power idle state.
+//!
+//! Alarms come from optional drivers (like pl031), which register them with
+//! `driver::register_alarm`. Only one deadline can be pending at once, and it only has a
+//! resolution of one second, so the timer interrupt still drives the scheduler.
+use bootinfo::Interrupt;
+use fdt::Fdt;
+
+use crate::error::KernelError;
+use crate::gicv2::InterruptId;
+use crate::sync::without_interrupts;
+use crate::{irq, timer};
+
+static mut ALARM: Option<State> = None;
+
+struct State {
+    alarm: &'static dyn driver::Alarm,
+    interrupt_id: InterruptId,
+    /// Called when the pending deadline is reached, if there is one.
+    callback: Option<fn()>,
+}
+
+/// Claims the interrupt of the alarm registered by an optional driver, if any.
+fn init(_fdt: &Fdt) -> Result<(), KernelError> {
+    let Some(source) = driver::alarm() else {
+        log::debug!(target: timer::LOG_TARGET, "no alarm");
+        return Ok(());
+    };
+
+    let [interrupt_type, interrupt_number, flags] = source.interrupt;
+    let interrupt_id: InterruptId = Interrupt {
+        interrupt_type,
+        interrupt_number,
+        flags,
+    }
+    .try_into()?;
+
+    // SAFETY: interrupts are masked during boot, so the handler can't be running yet.
+    unsafe {
+        ALARM = Some(State {
+            alarm: source.alarm,
+            interrupt_id,
+            callback: None,
+        })
+    };
+    irq::register(interrupt_id, handle, irq::Mode::Threaded)?;
+    irq::enable_wakeup(interrupt_id)?;
+    log::debug!(
+        target: timer::LOG_TARGET,
+        "alarm from {} on interrupt {interrupt_id:?}",
+        source.name
+    );
+
+    Ok(())
+}
+initcall!(late, init);
+
+/// Returns the alarm's idea of the current time, in seconds since the Unix epoch.
+pub fn now() -> Result<u64, KernelError> {
+    with_alarm(|state| Ok(state.alarm.now()))
+}
+
+/// Arms the alarm to call `callback` from the IRQ thread at `deadline`, in seconds since the Unix
+/// epoch, replacing any deadline that was pending.
+pub fn set(deadline: u64, callback: fn()) -> Result<(), KernelError> {
+    with_alarm(|state| {
+        state.callback = Some(callback);
+        state.alarm.set(deadline);
+        log::trace!(target: timer::LOG_TARGET, "alarm set for {deadline}");
+
+        Ok(())
+    })
+}
+
+/// Arms the alarm to call `callback` after at least `seconds` seconds.
+pub fn set_after(seconds: u64, callback: fn()) -> Result<(), KernelError> {
+    // the current second may be about to end, so wait for one more
+    set(now()?.saturating_add(seconds).saturating_add(1), callback)
+}
+
+/// Disarms the alarm, returning whether a deadline was pending.
+pub fn cancel() -> Result<bool, KernelError> {
+    with_alarm(|state| {
+        state.alarm.clear();
+
+        Ok(state.callback.take().is_some())
+    })
+}
+
+/// Handles the alarm's interrupt by disarming it and calling the pending callback.
+fn handle(interrupt_id: InterruptId) {
+    let callback = with_alarm(|state| {
+        debug_assert!(state.interrupt_id == interrupt_id);
+        state.alarm.clear();
+
+        Ok(state.callback.take())
+    });
+
+    match callback {
+        Ok(Some(callback)) => callback(),
+        // the deadline was cancelled after the interrupt was raised
+        Ok(None) => {}
+        Err(error) => log::warn!(target: timer::LOG_TARGET, "alarm interrupt: {error}"),
+    }
+}
+
+fn with_alarm<R>(f: impl FnOnce(&mut State) -> Result<R, KernelError>) -> Result<R, KernelError> {
+    // SAFETY: the alarm is only used with interrupts masked, and thus by one caller at once.
+    without_interrupts(|| f(unsafe { ALARM.as_mut() }.ok_or(KernelError::NoAlarm)?))
+}
@@ -0,0 +1,127 @@
+//! Alignment checking, which is strict in debug builds to catch misaligned accesses as bugs, and
+//! relaxed in release builds, where the CPU handles them in hardware.
+//!
+//! The policy can be overridden with `alignment=strict` or `alignment=relaxed` on the command line.
+//! Either way, the stack pointer must stay aligned to 16 bytes, since misaligning it is never
+//! intentional. Accesses to device memory must always be aligned, whatever the policy.
+//!
+//! The compiler never makes misaligned accesses of its own, since the kernel is built with
+//! `+strict-align` (see aarch64-unknown-none.json), so strict checking only catches code that
+//! dereferences misaligned pointers.
+use core::fmt;
+
+use fdt::Fdt;
+
+use crate::a53::sctlr::SCTLR_EL1;
+use crate::error::KernelError;
+use crate::reg::system::Register;
+use crate::task::Context;
+use crate::{cmdline, tt};
+
+/// Whether misaligned loads and stores to normal memory fault.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Policy {
+    /// Misaligned loads and stores fault, at EL1 and EL0.
+    Strict,
+    /// Misaligned loads and stores to normal memory are allowed.
+    Relaxed,
+}
+
+impl Policy {
+    const DEFAULT: Self = if cfg!(debug_assertions) {
+        Self::Strict
+    } else {
+        Self::Relaxed
+    };
+}
+
+impl fmt::Display for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Strict => write!(f, "strict"),
+            Self::Relaxed => write!(f, "relaxed"),
+        }
+    }
+}
+
+/// Sets the alignment checking policy in SCTLR_EL1.
+fn init(_fdt: &Fdt) -> Result<(), KernelError> {
+    let requested = cmdline::option("alignment").map(|name| {
+        let policy = match name {
+            "strict" => Some(Policy::Strict),
+            "relaxed" => Some(Policy::Relaxed),
+            _ => None,
+        };
+        (name, policy)
+    });
+    let policy = requested
+        .and_then(|(_, policy)| policy)
+        .unwrap_or(Policy::DEFAULT);
+
+    Register::<SCTLR_EL1>::new().modify(|w| {
+        w.a(policy == Policy::Strict);
+        w.sa(true);
+        w.sa0(true);
+    });
+
+    if let Some((name, None)) = requested {
+        log::warn!("alignment={name} is not strict or relaxed, so using {policy}");
+    }
+    log::debug!("{policy} alignment checking");
+
+    Ok(())
+}
+initcall!(arch, init);
+
+/// Returns whether a synchronous exception with the given syndrome (ESR_EL1) is an alignment
+/// fault.
+pub fn is_fault(syndrome: u64) -> bool {
+    match syndrome >> 26 & 0x3F {
+        // PC or SP alignment fault
+        0x22 | 0x26 => true,
+        // data abort from a lower or the same exception level, with an alignment fault (DFSC
+        // 0b100001)
+        0x24 | 0x25 => syndrome & 0x3F == 0b100001,
+        _ => false,
+    }
+}
+
+/// Logs the details of an alignment fault, given its syndrome (ESR_EL1), before the crash dump.
+pub fn report(context: &Context, syndrome: u64) {
+    // SAFETY: reading FAR_EL1 has no side effects.
+    let far = unsafe { read_special_reg!("FAR_EL1") };
+    let pc = context.pc();
+
+    match syndrome >> 26 & 0x3F {
+        0x22 => log::error!("PC alignment fault: jumped to {far:#x}"),
+        0x26 => log::error!("SP alignment fault: sp is {:#x} at {pc:#x}", context.sp()),
+        _ => {
+            let access = if syndrome & 1 << 6 != 0 {
+                "write"
+            } else {
+                "read"
+            };
+            // ISS.SAS, the size of the access, is only valid if ISS.ISV is set
+            let size = if syndrome & 1 << 24 != 0 {
+                1 << (syndrome >> 22 & 0b11)
+            } else {
+                0
+            };
+            if size > 0 {
+                log::error!("alignment fault: {size}-byte {access} of {far:#x}");
+            } else {
+                log::error!("alignment fault: {access} of {far:#x}");
+            }
+
+            // the instruction was fetched, so it's mapped, but check anyway rather than fault
+            if tt::is_readable(pc) {
+                // SAFETY: the address is mapped for reads, and it's aligned, or this would have
+                // been a PC alignment fault instead.
+                let instruction = unsafe { (pc as *const u32).read_volatile() };
+                log::error!("by instruction {instruction:08x} at {pc:#x}");
+            } else {
+                log::error!("by the instruction at {pc:#x}");
+            }
+        }
+    }
+}
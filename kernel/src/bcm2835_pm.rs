@@ -0,0 +1,122 @@
+//! The power management block of the Raspberry Pi's SoC, whose watchdog is how the Pi is reset or
+//! halted, since its firmware has no PSCI.
+//!
+//! The watchdog resets the system when its timer runs out, so resetting is setting it to run out
+//! almost immediately. Halting is the same, but with the partition to boot from after the reset
+//! set to the one the firmware takes to mean it should halt, until the power is cycled. Every
+//! write to the block needs [`PASSWORD`] in its top byte.
+//!
+//! <https://github.com/torvalds/linux/blob/305230142ae0637213bf6e04f6d9f10bbcb74af8/drivers/watchdog/bcm2835_wdt.c>
+use core::hint::spin_loop;
+
+use fdt::Fdt;
+use hal::{Power, Timer};
+
+use crate::error::KernelError;
+use crate::{board, boot_info};
+
+/// Log target of the power management driver.
+pub const LOG_TARGET: &str = "bcm2835_pm";
+
+const COMPATIBLE: &str = "brcm,bcm2835-pm-wdt";
+
+/// 0x1c: reset control, which says what to do when the watchdog runs out.
+const RSTC: usize = 0x1c;
+/// 0x20: reset status, which also holds the partition to boot from after a reset.
+const RSTS: usize = 0x20;
+/// 0x24: watchdog timer, in ticks of about 16 µs.
+const WDOG: usize = 0x24;
+
+/// Needed in the top byte of every write, or the write is ignored.
+const PASSWORD: u32 = 0x5a00_0000;
+/// RSTC: the field saying what to do when the watchdog runs out.
+const RSTC_WRCFG: u32 = 0x30;
+/// RSTC: reset the whole system when the watchdog runs out.
+const RSTC_WRCFG_FULL_RESET: u32 = 0x20;
+/// RSTS: partition 63, whose bits are spread across the register, which tells the firmware to halt.
+const RSTS_PARTITION_HALT: u32 = 0x555;
+
+/// Watchdog ticks before a reset, which is as soon as Linux does it.
+const RESET_TICKS: u32 = 10;
+
+/// Longest to wait for the reset to happen before giving up, in milliseconds.
+const TIMEOUT_MS: u64 = 10;
+
+/// The watchdog, as the [`Power`] control of the board.
+pub struct Watchdog {
+    base: *mut u8,
+}
+
+/// Power control, which does nothing until the watchdog is found by [`init`].
+static mut POWER: Watchdog = Watchdog::new();
+
+impl Watchdog {
+    /// Returns a watchdog that fails to do anything, until its registers are found by [`init`].
+    pub const fn new() -> Self {
+        Self {
+            base: core::ptr::null_mut(),
+        }
+    }
+
+    fn read(&self, offset: usize) -> u32 {
+        // SAFETY: the devicetree says the block is at `base`.
+        unsafe { self.base.add(offset).cast::<u32>().read_volatile() }
+    }
+
+    fn write(&self, offset: usize, value: u32) {
+        // SAFETY: as in read, and the writes we make only reset the system.
+        unsafe {
+            self.base
+                .add(offset)
+                .cast::<u32>()
+                .write_volatile(PASSWORD | value)
+        };
+    }
+
+    /// Resets the system with the watchdog, returning only if the reset doesn't happen.
+    fn reset(&self) {
+        let control = self.read(RSTC) & !RSTC_WRCFG & !PASSWORD;
+        self.write(WDOG, RESET_TICKS);
+        self.write(RSTC, control | RSTC_WRCFG_FULL_RESET);
+
+        let start = board::timer().now();
+        while board::timer().now() - start < board::timer().ticks_from_ms(TIMEOUT_MS) {
+            spin_loop();
+        }
+        log::warn!(target: LOG_TARGET, "watchdog didn't reset the system");
+    }
+}
+
+impl Power for Watchdog {
+    fn system_off(&self) {
+        if self.base.is_null() {
+            log::warn!(target: LOG_TARGET, "no watchdog to halt with");
+            return;
+        }
+        let status = self.read(RSTS) & !PASSWORD;
+        self.write(RSTS, status | RSTS_PARTITION_HALT);
+        self.reset();
+    }
+
+    fn system_reset(&self) {
+        if self.base.is_null() {
+            log::warn!(target: LOG_TARGET, "no watchdog to reset with");
+            return;
+        }
+        self.reset();
+    }
+}
+
+/// Finds the power management block.
+fn init(_fdt: &Fdt) -> Result<(), KernelError> {
+    let base = boot_info().pm.ok_or(KernelError::DeviceNotFound {
+        compatible: COMPATIBLE,
+    })?;
+    log::debug!(target: LOG_TARGET, "watchdog at {base:#x}");
+
+    // SAFETY: initcalls run before anything else uses the power control.
+    unsafe { POWER.base = base as *mut u8 };
+
+    Ok(())
+}
+initcall!(driver, init);
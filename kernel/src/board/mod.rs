@@ -0,0 +1,51 @@
+//! The devices of the board the kernel runs on, as implementations of the traits in the [`hal`]
+//! crate. The board is QEMU's virt machine, unless the `board-rpi4` feature selects the Raspberry
+//! Pi 4, and what differs between them is in a module for each.
+//!
+//! The rest of the kernel only uses these through the traits, so another board only needs another
+//! of those modules, and drivers for its devices. Interrupts are still numbered by GIC interrupt
+//! ID ([`gicv2::InterruptId`]), since they come from the devicetree that way, and both boards have
+//! a GICv2 (the Pi's GIC-400) and the Arm generic timer.
+use hal::{Console, InterruptController, Timer};
+
+use crate::gicv2::InterruptId;
+use crate::{gicv2, pl011, timer};
+
+#[cfg(feature = "board-rpi4")]
+mod rpi4;
+#[cfg(not(feature = "board-rpi4"))]
+mod virt;
+
+#[cfg(feature = "board-rpi4")]
+pub use rpi4::*;
+#[cfg(not(feature = "board-rpi4"))]
+pub use virt::*;
+
+/// Returns the console on the UART at `base`, which the caller has claimed (see pl011.rs).
+///
+/// # Safety
+///
+/// Must only be called once, since every call returns the same console.
+pub unsafe fn console(base: *const u8) -> &'static mut dyn Console {
+    static mut CONSOLE: Option<pl011::Pl011> = None;
+
+    // SAFETY: the caller ensures that nothing else has the console yet.
+    unsafe { CONSOLE.insert(pl011::Pl011::new(base)) }
+}
+
+/// Returns the interrupt controller, which is found and enabled during arch init (see gicv2.rs).
+///
+/// # Safety
+///
+/// The caller must ensure that nothing else uses the interrupt controller in the meantime, such
+/// as by masking IRQs, unless the registers it uses can't race (see irq.rs).
+pub unsafe fn interrupt_controller(
+) -> &'static mut impl InterruptController<Interrupt = InterruptId> {
+    // SAFETY: the caller upholds our contract.
+    unsafe { &mut gicv2::GIC }
+}
+
+/// Returns the CPU's timer, and the system counter.
+pub fn timer() -> &'static impl Timer {
+    &timer::ArchTimer
+}
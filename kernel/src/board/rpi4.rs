@@ -0,0 +1,21 @@
+//! The Raspberry Pi 4 (BCM2711), which has no PSCI, so it's powered off and reset by the watchdog
+//! in its power management block.
+//!
+//! The Pi's firmware loads the kernel from kernel8.img, as told by config.txt, both of which are
+//! written by `cargo xtask build --board rpi4`. It needs at least 2GiB of RAM, so there's RAM at
+//! 0x4000_0000 for the kernel and its devicetree. The console is the PL011 (UART0), on GPIO 14 and
+//! 15, which the firmware sets up, and its devices are in the 1GiB below 4GiB, in the “low
+//! peripheral” mode the firmware uses unless told otherwise.
+use crate::bcm2835_pm;
+
+/// Name of the board, as logged at boot.
+pub const NAME: &str = "Raspberry Pi 4";
+
+/// Physical address of the UART that entry.s writes to before the MMU is on.
+pub const EARLY_UART: usize = 0xFE20_1000;
+
+/// Physical address of the 1GiB block with the devices in it, which entry.s maps to the same
+/// address as device memory.
+pub const DEVICE_BLOCK: usize = 0xC000_0000;
+
+pub use bcm2835_pm::LOG_TARGET as POWER_LOG_TARGET;
@@ -0,0 +1,14 @@
+//! QEMU's virt machine, which is powered off and reset through PSCI.
+use crate::psci;
+
+/// Name of the board, as logged at boot.
+pub const NAME: &str = "QEMU virt";
+
+/// Physical address of the UART that entry.s writes to before the MMU is on.
+pub const EARLY_UART: usize = 0x900_0000;
+
+/// Physical address of the 1GiB block with the devices in it, which entry.s maps to the same
+/// address as device memory.
+pub const DEVICE_BLOCK: usize = 0;
+
+pub use psci::LOG_TARGET as POWER_LOG_TARGET;
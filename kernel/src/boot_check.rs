@@ -0,0 +1,154 @@
+//! Boot-time checks of what the rest of the kernel assumes about the layout in linker.ld, so a
+//! change to the linker script that breaks them panics with a precise message, rather than
+//! corrupting memory in mysterious ways later.
+use core::ops::Range;
+
+use fdt::Fdt;
+
+use crate::addr::VirtAddr;
+use crate::error::KernelError;
+use crate::{linker_symbols, tt, ALLOCATOR};
+
+/// Size of each task's stack and each kernel stack, as reserved in linker.ld.
+const STACK_SIZE: usize = 0x4000;
+
+/// Checks that every section has the same offset from its physical address, that the image is
+/// page-aligned, and that the stacks are where they belong.
+fn check_image(_fdt: &Fdt) -> Result<(), KernelError> {
+    let start = linker_symbols::kernel_start().addr();
+    let end = linker_symbols::kernel_end().addr();
+    let start_pa = linker_symbols::kernel_start_pa().addr();
+    let trees = linker_symbols::buddy_alloc_tree().addr();
+
+    // mm::ram_va, linker_symbols::kernel_pa and the kernel's own mappings (see tt) all assume that
+    // one offset translates the whole image
+    for (name, va, pa) in [
+        (
+            "_estack",
+            linker_symbols::boot_stack_top(),
+            linker_symbols::boot_stack_top_pa(),
+        ),
+        (
+            "_buddy_alloc_tree",
+            linker_symbols::buddy_alloc_tree(),
+            linker_symbols::buddy_alloc_tree_pa(),
+        ),
+    ] {
+        assert!(
+            linker_symbols::kernel_pa(va) == pa,
+            "{name}_va ({va:?}) and {name}_pa ({pa:?}) aren't {:#x} apart like the rest of the image",
+            start - start_pa
+        );
+    }
+
+    for (name, address) in [
+        ("kernel image (virtual)", start),
+        ("kernel image (physical)", start_pa),
+        ("kernel image end", end),
+        ("page allocator tree", trees),
+    ] {
+        assert!(
+            address % tt::PAGE_SIZE == 0,
+            "{name} at {address:#x} isn't aligned to a {:#x}-byte page",
+            tt::PAGE_SIZE
+        );
+    }
+    // VBAR_EL1[10:0] are RES0
+    let vectors = linker_symbols::vectors().addr();
+    assert!(
+        vectors % 0x800 == 0,
+        "vector table at {vectors:#x} isn't aligned to 0x800 bytes"
+    );
+
+    let stacks = stacks();
+    let area = linker_symbols::boot_stack_bottom().addr()..trees;
+    for (i, (name, stack)) in stacks.iter().enumerate() {
+        // sp must be aligned to 16 bytes whenever it's used to access memory
+        assert!(
+            stack.end % 16 == 0,
+            "{name} stack top at {:#x} isn't aligned to 16 bytes",
+            stack.end
+        );
+        // the area after the loaded image and before the trees is only for stacks
+        assert!(
+            area.start <= stack.start && stack.end <= area.end,
+            "{name} stack at {stack:#x?} overlaps the kernel image outside {area:#x?}"
+        );
+        for (other, other_stack) in &stacks[i + 1..] {
+            assert!(
+                stack.end <= other_stack.start || other_stack.end <= stack.start,
+                "{name} stack at {stack:#x?} overlaps {other} stack at {other_stack:#x?}"
+            );
+        }
+    }
+
+    log::debug!(
+        "kernel image at {:#x}..{end:#x}, offset {:#x} from {start_pa:#x}",
+        start,
+        start - start_pa
+    );
+
+    Ok(())
+}
+initcall!(arch, check_image);
+
+/// Checks that every page allocator region begins after the end of the kernel image, once the
+/// allocator has been set up (see `init_allocator` in main.rs).
+fn check_allocator(_fdt: &Fdt) -> Result<(), KernelError> {
+    let end = linker_symbols::kernel_end();
+
+    // SAFETY: initcalls run before anything else uses the allocator.
+    let allocator = unsafe { ALLOCATOR.get() }.expect("allocator to be initialised");
+    for region in allocator.regions() {
+        let heap = region.heap();
+        // RAM is mapped at the same offset as the kernel image (see mm::ram_va), so the addresses
+        // are comparable
+        assert!(
+            heap.start as usize >= end.addr(),
+            "page allocator region at {heap:?} begins before the end of the kernel image at {end:?}"
+        );
+    }
+
+    Ok(())
+}
+initcall!(driver, check_allocator);
+
+/// Returns the name and range of every stack reserved in linker.ld.
+fn stacks() -> [(&'static str, Range<usize>); 7] {
+    let below = |top: VirtAddr| top.addr().saturating_sub(STACK_SIZE)..top.addr();
+    let between = |bottom: VirtAddr, top: VirtAddr| bottom.addr()..top.addr();
+
+    [
+        (
+            "boot",
+            between(
+                linker_symbols::boot_stack_bottom(),
+                linker_symbols::boot_stack_top(),
+            ),
+        ),
+        ("task1", below(linker_symbols::task1_stack_top())),
+        (
+            "task1 kernel",
+            between(
+                linker_symbols::task1_kernel_stack_bottom(),
+                linker_symbols::task1_kernel_stack_top(),
+            ),
+        ),
+        ("task2", below(linker_symbols::task2_stack_top())),
+        (
+            "task2 kernel",
+            between(
+                linker_symbols::task2_kernel_stack_bottom(),
+                linker_symbols::task2_kernel_stack_top(),
+            ),
+        ),
+        ("IRQ thread", below(linker_symbols::irq_thread_stack_top())),
+        (
+            "IRQ thread kernel",
+            between(
+                linker_symbols::irq_thread_kernel_stack_bottom(),
+                linker_symbols::irq_thread_kernel_stack_top(),
+            ),
+        ),
+    ]
+}
@@ -0,0 +1,40 @@
+//! Metadata about how the kernel was built, embedded by build.rs, so that any serial log can be
+//! traced back to the kernel that produced it.
+use core::fmt;
+
+pub struct BuildInfo {
+    pub version: &'static str,
+    /// Output of `git describe --always --dirty`, or “unknown” if built outside a git checkout.
+    pub commit: &'static str,
+    /// When the kernel was built (or SOURCE_DATE_EPOCH), in RFC 3339 format.
+    pub timestamp: &'static str,
+    /// Cargo profile, like “debug” or “release”.
+    pub profile: &'static str,
+    /// Enabled cargo features, separated by commas.
+    pub features: &'static str,
+}
+
+pub const BUILD_INFO: BuildInfo = BuildInfo {
+    version: env!("CARGO_PKG_VERSION"),
+    commit: env!("MICROPUPPY_BUILD_COMMIT"),
+    timestamp: env!("MICROPUPPY_BUILD_TIMESTAMP"),
+    profile: env!("MICROPUPPY_BUILD_PROFILE"),
+    features: env!("MICROPUPPY_BUILD_FEATURES"),
+};
+
+/// Formats the banner printed at boot.
+impl fmt::Display for BuildInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let features = if self.features.is_empty() {
+            "none"
+        } else {
+            self.features
+        };
+
+        writeln!(f, "micropuppy {}", self.version)?;
+        writeln!(f, "  commit:   {}", self.commit)?;
+        writeln!(f, "  built:    {}", self.timestamp)?;
+        writeln!(f, "  profile:  {}", self.profile)?;
+        write!(f, "  features: {features}")
+    }
+}
@@ -0,0 +1,13 @@
+//! The kernel command line, from `bootargs` in the devicetree's /chosen node (QEMU's `-append`).
+use crate::boot_info;
+
+/// Returns the value of the last `name=value` option on the command line, if any.
+pub fn option(name: &str) -> Option<&'static str> {
+    boot_info()
+        .bootargs?
+        .split_whitespace()
+        .filter_map(|option| option.split_once('='))
+        .filter(|&(key, _)| key == name)
+        .map(|(_, value)| value)
+        .last()
+}
@@ -0,0 +1,904 @@
+//! Interactive debug console on the UART, for inspecting the kernel while it runs.
+//!
+//! Input arrives a byte at a time from the UART receive handler, by way of the TTY whenever a task
+//! has the console open (see tty.rs), and is echoed and collected into a line. Each complete line
+//! is run as a command, whose output goes to the same UART as the log.
+use core::fmt::{self, Write};
+use core::str::SplitWhitespace;
+
+use abi::Errno;
+use fdt::Fdt;
+use gic::dump::{InterruptState, PriorityMask, TABLE_HEADER};
+
+use crate::build_info::BUILD_INFO;
+use crate::error::KernelError;
+use crate::gicv2::{self, InterruptId};
+use crate::hexdump::Hexdump;
+use crate::irq::{self, Mode};
+use crate::pmu::{self, Counter};
+use crate::sync::without_interrupts;
+use crate::virtio::p9;
+use crate::watchpoint::{self, Action};
+use crate::{
+    alarm, dmesg, leak, logging, pl011, profile, scheduler, stats, suspend, syscall, timer, tt,
+    tty, SCHEDULER,
+};
+
+/// Maximum length of a line of input, in bytes.
+const LINE_LEN: usize = 128;
+
+const PROMPT: &str = "🐶 ";
+
+static mut CONSOLE: Console = Console::new();
+
+/// Arguments to a command, after the command name.
+type Args<'l> = SplitWhitespace<'l>;
+
+struct Command {
+    name: &'static str,
+    usage: &'static str,
+    help: &'static str,
+    run: fn(Args, &mut Output) -> Result<(), KernelError>,
+}
+
+const COMMANDS: &[Command] = &[
+    Command {
+        name: "9p",
+        usage: "[ls [<path>] | cat <path> | stat <path>]",
+        help: "show the host directory shared with virtio-9p, or list, print or stat files in it",
+        run: ninep,
+    },
+    Command {
+        name: "alarm",
+        usage: "[<seconds> | cancel]",
+        help: "show the alarm's clock, or log a message after some seconds (see alarm.rs)",
+        run: alarm,
+    },
+    Command {
+        name: "cat",
+        usage: "[raw]",
+        help: "copy console input to output in a new task, until Ctrl+C (Ctrl+D if raw)",
+        run: cat,
+    },
+    Command {
+        name: "dmesg",
+        usage: "",
+        help: "print the log kept in memory, even while it isn't written here (see dmesg.rs)",
+        run: dmesg,
+    },
+    Command {
+        name: "drvlog",
+        usage: "[<driver> <level>]",
+        help: "list drivers' log levels, or set one (off to trace, or default)",
+        run: drvlog,
+    },
+    Command {
+        name: "fg",
+        usage: "[<task>]",
+        help: "give console input back to the foreground task, or to another task with it open",
+        run: fg,
+    },
+    Command {
+        name: "gic",
+        usage: "[all]",
+        help: "list interrupts that are enabled, pending, active or handled, or all of them",
+        run: gic,
+    },
+    Command {
+        name: "help",
+        usage: "",
+        help: "list commands",
+        run: help,
+    },
+    Command {
+        name: "latency",
+        usage: "",
+        help: "show histograms of timer interrupt latency, and of IRQ-to-task wakeup latency",
+        run: latency,
+    },
+    Command {
+        name: "leak",
+        usage: "[mark <name> | <from> [<to>]]",
+        help: "list leak checkpoints, take one, or list pages allocated since one",
+        run: leak,
+    },
+    Command {
+        name: "logsink",
+        usage: "[<sink>... | none]",
+        help: "list where the log is written to, or write it to just the given sinks",
+        run: logsink,
+    },
+    Command {
+        name: "md",
+        usage: "<address> [length [width]]",
+        help: "dump memory (default 64 bytes, up to 4096, 16 per line)",
+        run: md,
+    },
+    Command {
+        name: "pmap",
+        usage: "<task>",
+        help: "list the regions of memory a task can use, and how much of each is resident",
+        run: pmap,
+    },
+    Command {
+        name: "pmu",
+        usage: "",
+        help: "list what the performance counters counted for each task",
+        run: pmu,
+    },
+    Command {
+        name: "profile",
+        usage: "[start [<hz>] | stop | flat | folded]",
+        help: "show the sampling profiler, start or stop it, or print its samples (see profile.rs)",
+        run: profile,
+    },
+    Command {
+        name: "ps",
+        usage: "",
+        help: "list tasks and their groups, with their ticks and most kernel stack used",
+        run: ps,
+    },
+    Command {
+        name: "suspend",
+        usage: "<seconds>",
+        help: "suspend until the alarm goes off after some seconds",
+        run: suspend,
+    },
+    Command {
+        name: "uarts",
+        usage: "",
+        help: "list UARTs, and what claimed them",
+        run: uarts,
+    },
+    Command {
+        name: "version",
+        usage: "",
+        help: "show how the kernel was built",
+        run: version,
+    },
+    Command {
+        name: "watch",
+        usage: "[<address> [length [halt]]]",
+        help: "list watchpoints, or watch memory for writes (default 8 bytes)",
+        run: watch,
+    },
+    Command {
+        name: "unwatch",
+        usage: "<number>",
+        help: "clear a watchpoint",
+        run: unwatch,
+    },
+];
+
+struct Console {
+    line: [u8; LINE_LEN],
+    len: usize,
+}
+
+impl Console {
+    const fn new() -> Self {
+        Self {
+            line: [0; LINE_LEN],
+            len: 0,
+        }
+    }
+
+    fn receive(&mut self, byte: u8, out: &mut Output) {
+        match byte {
+            b'\r' | b'\n' => {
+                writeln!(out);
+                // input is only ever ASCII, since we drop everything else
+                let line = core::str::from_utf8(&self.line[..self.len]).unwrap_or("");
+                run(line, out);
+                self.len = 0;
+                // unless the command gave input to a task, like fg
+                if !tty::takes_input() {
+                    write!(out, "{PROMPT}");
+                }
+            }
+            // backspace or delete
+            0x08 | 0x7F => {
+                if self.len > 0 {
+                    self.len -= 1;
+                    write!(out, "\x08 \x08");
+                }
+            }
+            b' '..=b'~' if self.len < LINE_LEN => {
+                self.line[self.len] = byte;
+                self.len += 1;
+                write!(out, "{}", byte as char);
+            }
+            _ => {}
+        }
+    }
+
+    fn redraw(&self, out: &mut Output) {
+        write!(out, "{PROMPT}");
+        out.write_bytes(&self.line[..self.len]);
+    }
+}
+
+/// Console output, which goes wherever the log goes. Output is best-effort, so errors are ignored.
+pub struct Output;
+
+impl Output {
+    pub fn write_fmt(&mut self, args: fmt::Arguments) {
+        // SAFETY: the writer is only set during early init.
+        if let Some(writer) = unsafe { &mut logging::WRITER } {
+            let _ = writer.write_fmt(args);
+        }
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        // SAFETY: see write_fmt.
+        if let Some(writer) = unsafe { &mut logging::WRITER } {
+            writer.write_bytes(bytes);
+        }
+    }
+}
+
+/// Handles a byte of input, running the current line as a command once it's complete.
+///
+/// Must only be called from one thread (the IRQ thread, via the UART receive handler).
+pub fn receive(byte: u8) {
+    // SAFETY: see above.
+    unsafe { CONSOLE.receive(byte, &mut Output) };
+}
+
+/// Prints the prompt and the line being typed again, after something else was printed over them.
+///
+/// Must only be called from the same thread as [`receive`].
+pub fn redraw() {
+    // SAFETY: see receive.
+    unsafe { CONSOLE.redraw(&mut Output) };
+}
+
+fn run(line: &str, out: &mut Output) {
+    let mut args = line.split_whitespace();
+    let Some(name) = args.next() else {
+        return;
+    };
+
+    match COMMANDS.iter().find(|command| command.name == name) {
+        Some(command) => {
+            // the IRQ thread isn't preemptible, but commands can take a while
+            if let Err(error) = scheduler::preemptible(|| (command.run)(args, out)) {
+                writeln!(out, "{name}: {error}");
+            }
+        }
+        None => writeln!(out, "unknown command {name:?} (try “help”)"),
+    }
+}
+
+/// Parses a number in decimal, or in hexadecimal with a `0x` prefix.
+fn parse_number(arg: &str) -> Result<usize, KernelError> {
+    let result = match arg.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => arg.parse(),
+    };
+
+    result.map_err(|_| KernelError::InvalidArgument {
+        reason: "expected a number",
+    })
+}
+
+fn ninep(mut args: Args, out: &mut Output) -> Result<(), KernelError> {
+    let command = args.next();
+    let path = args.next();
+    let expected_path = KernelError::InvalidArgument {
+        reason: "expected a path",
+    };
+
+    match command {
+        None => p9::tag(|tag| writeln!(out, "share {tag:?}"))?,
+        Some("ls") => p9::list(path.unwrap_or("/"), |entry| {
+            let slash = if entry.qid.is_dir() { "/" } else { "" };
+            writeln!(out, "{}{slash}", entry.name);
+        })?,
+        Some("cat") => p9::read(path.ok_or(expected_path)?, |data| out.write_bytes(data))?,
+        Some("stat") => {
+            let attr = p9::stat(path.ok_or(expected_path)?)?;
+            writeln!(
+                out,
+                "mode {:o}, uid {}, gid {}, {} links, {} bytes, modified {} seconds since the epoch",
+                attr.mode, attr.uid, attr.gid, attr.nlink, attr.size, attr.mtime
+            );
+        }
+        Some(_) => {
+            return Err(KernelError::InvalidArgument {
+                reason: "expected “ls”, “cat” or “stat”",
+            })
+        }
+    }
+
+    Ok(())
+}
+
+fn alarm(mut args: Args, out: &mut Output) -> Result<(), KernelError> {
+    match args.next() {
+        None => writeln!(out, "{} seconds since the epoch", alarm::now()?),
+        Some("cancel") => {
+            if !alarm::cancel()? {
+                writeln!(out, "no alarm was set");
+            }
+        }
+        Some(seconds) => {
+            let seconds = parse_number(seconds)?;
+            alarm::set_after(seconds as u64, || log::info!("alarm went off"))?;
+        }
+    }
+
+    Ok(())
+}
+
+fn cat(mut args: Args, out: &mut Output) -> Result<(), KernelError> {
+    let task_args: &[u8] = match args.next() {
+        None => b"cat\0",
+        Some("raw") => b"cat\0raw\0",
+        Some(_) => {
+            return Err(KernelError::InvalidArgument {
+                reason: "expected “raw”",
+            })
+        }
+    };
+
+    let id = without_interrupts(|| {
+        // SAFETY: see ps.
+        let scheduler = unsafe { SCHEDULER.get_mut() }.ok_or(KernelError::InvalidArgument {
+            reason: "scheduler not started",
+        })?;
+
+        scheduler.spawn(cat_task as usize, task_args)
+    })?;
+    writeln!(out, "cat is task {id}");
+
+    Ok(())
+}
+
+/// Copies console input to output through the TTY, until interrupted with Ctrl+C, or until
+/// Ctrl+D in raw mode, when it's passed `raw` as an argument.
+extern "C" fn cat_task(argc: usize, _argv: *const *const u8, _envp: *const *const u8) -> ! {
+    if let Err(errno) = run_cat(argc > 1) {
+        log::warn!("cat failed: {errno:?}");
+    }
+
+    loop {
+        syscall::yield_now();
+    }
+}
+
+fn run_cat(raw: bool) -> Result<(), Errno> {
+    /// Ctrl+D, which is EOT in ASCII.
+    const END: u8 = 0x04;
+
+    let console = syscall::open_console()?;
+    if raw {
+        syscall::ioctl(console, abi::ioctl::TTY_SET_RAW, 1)?;
+    }
+
+    let mut buffer = [0; 64];
+    let result = loop {
+        let len = match syscall::read(console, &mut buffer) {
+            Ok(len) => len,
+            Err(Errno::Interrupted) => {
+                // Ctrl+C also sent us a signal, which we don't need now that we know
+                let _ = syscall::take_signals();
+                break Ok(());
+            }
+            Err(errno) => break Err(errno),
+        };
+        let bytes = &buffer[..len];
+        let end = bytes.iter().position(|&byte| byte == END);
+        if let Err(errno) = syscall::write(console, &bytes[..end.unwrap_or(len)]) {
+            break Err(errno);
+        }
+        if end.is_some() {
+            break Ok(());
+        }
+    };
+
+    // input goes back to this console once the TTY is closed. in canonical mode, the TTY already
+    // echoed a newline after ^C
+    if raw {
+        syscall::write(console, b"\n")?;
+    }
+    syscall::write(console, PROMPT.as_bytes())?;
+    syscall::close(console)?;
+
+    result
+}
+
+fn dmesg(_args: Args, out: &mut Output) -> Result<(), KernelError> {
+    dmesg::print(out);
+
+    Ok(())
+}
+
+fn drvlog(mut args: Args, out: &mut Output) -> Result<(), KernelError> {
+    let Some(name) = args.next() else {
+        for driver in logging::drivers() {
+            let (level, own) = logging::target_level(driver);
+            let default = if own { "" } else { " (default)" };
+            writeln!(out, "{driver:<8} {level}{default}");
+        }
+        return Ok(());
+    };
+
+    let driver =
+        logging::drivers()
+            .find(|&driver| driver == name)
+            .ok_or(KernelError::InvalidArgument {
+                reason: "no such driver (try “drvlog”)",
+            })?;
+    let level = match args.next() {
+        Some("default") => None,
+        Some(level) => Some(level.parse().map_err(|_| KernelError::InvalidArgument {
+            reason: "expected a level: off, error, warn, info, debug, trace or default",
+        })?),
+        None => {
+            return Err(KernelError::InvalidArgument {
+                reason: "expected a level",
+            })
+        }
+    };
+
+    logging::set_target_level(driver, level)
+}
+
+fn fg(mut args: Args, out: &mut Output) -> Result<(), KernelError> {
+    if let Some(id) = args.next() {
+        let id = parse_number(id)?;
+        without_interrupts(|| {
+            // SAFETY: see ps.
+            let scheduler = unsafe { SCHEDULER.get_mut() }.ok_or(KernelError::NoSuchTask { id })?;
+            let (_, task) = scheduler
+                .tasks()
+                .0
+                .find(|&(task, _)| task == id)
+                .ok_or(KernelError::NoSuchTask { id })?;
+            if !task.has_console_open() {
+                return Err(KernelError::InvalidArgument {
+                    reason: "task doesn't have the console open",
+                });
+            }
+            tty::set_foreground(id);
+
+            Ok(())
+        })?;
+    }
+
+    match tty::resume()? {
+        Some(id) => writeln!(out, "[task {id}]"),
+        None => writeln!(out, "[task]"),
+    }
+
+    Ok(())
+}
+
+fn gic(mut args: Args, out: &mut Output) -> Result<(), KernelError> {
+    let all = match args.next() {
+        None => false,
+        Some("all") => true,
+        Some(_) => {
+            return Err(KernelError::InvalidArgument {
+                reason: "expected all",
+            })
+        }
+    };
+
+    without_interrupts(|| {
+        // SAFETY: the GIC is only written during boot, and by IRQ handling and the IRQ thread,
+        // which can't run while IRQs are masked.
+        let gic = unsafe { &gicv2::GIC };
+        writeln!(out, "{}", PriorityMask(gic.cpu_interface.priority_mask()));
+        writeln!(out, "{TABLE_HEADER}");
+        for interrupt_id in 0..gic.distributor.interrupt_lines() {
+            let id = InterruptId::try_from(interrupt_id).expect("interrupt lines to be valid IDs");
+            let state = InterruptState {
+                interrupt_id,
+                enabled: gic.distributor.is_enabled(id),
+                pending: gic.distributor.is_pending(id),
+                active: gic.distributor.is_active(id),
+                priority: gic.distributor.priority(id),
+                targets: gic.distributor.targets(id),
+                edge_triggered: gic.distributor.is_edge_triggered(id),
+                count: stats::INTERRUPTS[interrupt_id].get(),
+                handler: handler_name(id),
+            };
+            let interesting = state.enabled
+                || state.pending
+                || state.active
+                || state.count > 0
+                || state.handler != "-";
+            if all || interesting {
+                writeln!(out, "{state}");
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Returns what handles an interrupt, for the handler column of the `gic` table.
+fn handler_name(interrupt_id: InterruptId) -> &'static str {
+    if timer::is_interrupt(interrupt_id) {
+        return "timer";
+    }
+    if profile::is_interrupt(interrupt_id) {
+        return "profile";
+    }
+    match irq::mode(interrupt_id) {
+        Some(Mode::Direct) => "direct",
+        Some(Mode::Threaded) => "threaded",
+        None => "-",
+    }
+}
+
+fn help(_args: Args, out: &mut Output) -> Result<(), KernelError> {
+    for command in COMMANDS {
+        writeln!(
+            out,
+            "{:<8} {:<28} {}",
+            command.name, command.usage, command.help
+        );
+    }
+
+    Ok(())
+}
+
+fn latency(_args: Args, out: &mut Output) -> Result<(), KernelError> {
+    without_interrupts(|| {
+        // SAFETY: the histograms are only written by IRQ handling and the scheduler, which can't
+        // run while IRQs are masked.
+        let (timer, wakeup) = unsafe { (&stats::TIMER_LATENCY, &stats::WAKEUP_LATENCY) };
+        write!(out, "{timer}{wakeup}");
+    });
+
+    Ok(())
+}
+
+fn leak(mut args: Args, out: &mut Output) -> Result<(), KernelError> {
+    match args.next() {
+        None => leak::checkpoints(|name, len, truncated| {
+            let truncated = if truncated { ", truncated" } else { "" };
+            writeln!(out, "{name:<16} {len} allocations{truncated}");
+        }),
+        Some("mark") => {
+            let name = args.next().ok_or(KernelError::InvalidArgument {
+                reason: "expected a checkpoint name",
+            })?;
+            leak::checkpoint(name)?;
+        }
+        Some(from) => {
+            let to = args.next();
+            let diff = leak::diff(from, to, |allocation| writeln!(out, "{allocation}"))?;
+            let partial = if diff.partial {
+                " (partial, since the checkpoint was truncated)"
+            } else {
+                ""
+            };
+            writeln!(
+                out,
+                "{} allocations since {from} still live at {}{partial}",
+                diff.leaked,
+                to.unwrap_or("now")
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn logsink(args: Args, out: &mut Output) -> Result<(), KernelError> {
+    let mut args = args.peekable();
+    if args.peek().is_none() {
+        logging::for_each_sink(|name, _, active| {
+            writeln!(out, "{name:<8} {}", if active { "on" } else { "off" });
+        });
+        return Ok(());
+    }
+
+    let mut bits = 0;
+    for name in args.filter(|&name| name != "none") {
+        let mut bit = None;
+        logging::for_each_sink(|sink, b, _| {
+            if sink == name {
+                bit = Some(b);
+            }
+        });
+        bits |= bit.ok_or(KernelError::InvalidArgument {
+            reason: "no such sink (try “logsink”)",
+        })?;
+    }
+    logging::set_sinks(bits)?;
+
+    Ok(())
+}
+
+fn md(mut args: Args, out: &mut Output) -> Result<(), KernelError> {
+    const MAX_LEN: usize = 4096;
+
+    let address = args.next().ok_or(KernelError::InvalidArgument {
+        reason: "expected an address",
+    })?;
+    let address = parse_number(address)?;
+    let len = args.next().map(parse_number).transpose()?.unwrap_or(64);
+    if len > MAX_LEN {
+        return Err(KernelError::InvalidArgument {
+            reason: "length too large",
+        });
+    }
+    let width = args.next().map(parse_number).transpose()?.unwrap_or(16);
+    if width == 0 {
+        return Err(KernelError::InvalidArgument {
+            reason: "width must not be zero",
+        });
+    }
+
+    write!(out, "{}", Hexdump::memory(address, len)?.width(width));
+
+    Ok(())
+}
+
+fn pmap(mut args: Args, out: &mut Output) -> Result<(), KernelError> {
+    let id = args.next().ok_or(KernelError::InvalidArgument {
+        reason: "expected a task id",
+    })?;
+    let id = parse_number(id)?;
+
+    without_interrupts(|| {
+        // SAFETY: see ps.
+        let scheduler = unsafe { SCHEDULER.get_mut() }.ok_or(KernelError::NoSuchTask { id })?;
+        let (_, task) = scheduler
+            .tasks()
+            .0
+            .find(|&(task, _)| task == id)
+            .ok_or(KernelError::NoSuchTask { id })?;
+
+        writeln!(out, "task {id} ({})", task.name());
+        writeln!(
+            out,
+            "address                 size       rss perm backing name"
+        );
+        let (mut size, mut rss) = (0, 0);
+        for region in task.address_space().regions() {
+            let region_rss = region.resident_pages() * tt::PAGE_SIZE;
+            let [r, w, x] = region.permissions();
+            writeln!(
+                out,
+                "{:#018x} {:>8}K {:>8}K {r}{w}{x}  {:<7} {}",
+                region.range.start.addr(),
+                region.len() / 1024,
+                region_rss / 1024,
+                region.backing,
+                region.name,
+            );
+            size += region.len();
+            rss += region_rss;
+        }
+        writeln!(
+            out,
+            "total              {:>8}K {:>8}K",
+            size / 1024,
+            rss / 1024
+        );
+
+        Ok(())
+    })
+}
+
+fn pmu(_args: Args, out: &mut Output) -> Result<(), KernelError> {
+    without_interrupts(|| {
+        // SAFETY: see ps.
+        let Some(scheduler) = (unsafe { SCHEDULER.get_mut() }) else {
+            writeln!(out, "scheduler not started");
+            return;
+        };
+
+        write!(out, " id name        ");
+        for counter in Counter::ALL {
+            write!(out, " {:>14}", counter.name());
+        }
+        writeln!(out);
+
+        let (tasks, current) = scheduler.tasks();
+        for (id, task) in tasks {
+            // the running task's counts so far are still in the PMU
+            let mut counts = task.pmu_counts();
+            if id == current {
+                counts = counts + pmu::read();
+            }
+
+            let running = if id == current { '*' } else { ' ' };
+            write!(out, "{running}{id:>2} {:<12}", task.name());
+            for counter in Counter::ALL {
+                if pmu::is_counting(counter) {
+                    write!(out, " {:>14}", counts.get(counter));
+                } else {
+                    write!(out, " {:>14}", "-");
+                }
+            }
+            writeln!(out);
+        }
+    });
+
+    Ok(())
+}
+
+fn profile(mut args: Args, out: &mut Output) -> Result<(), KernelError> {
+    match args.next() {
+        None => {
+            let (len, overwritten) = profile::len();
+            match profile::rate() {
+                Some(rate) => write!(out, "sampling at {rate} Hz"),
+                None => write!(out, "stopped"),
+            }
+            writeln!(out, ", {len} samples ({overwritten} overwritten)");
+        }
+        Some("start") => {
+            let rate = args.next().map(parse_number).transpose()?;
+            profile::start(rate.map_or(profile::DEFAULT_RATE, |rate| rate as u64))?;
+        }
+        Some("stop") => profile::stop(),
+        Some("flat") => {
+            let (len, _) = profile::len();
+            profile::flat(|pc, count| {
+                // in tenths of a percent, since the kernel doesn't use floating point
+                let permille = count * 1000 / len.max(1);
+                writeln!(
+                    out,
+                    "{count:>6} {:>3}.{}% {pc:#x}",
+                    permille / 10,
+                    permille % 10
+                );
+            });
+        }
+        Some("folded") => without_interrupts(|| {
+            // SAFETY: see ps.
+            let scheduler = unsafe { SCHEDULER.get_mut() };
+            profile::folded(|task, frames, count| {
+                // the task may have exited since it was sampled
+                let name = scheduler
+                    .as_ref()
+                    .and_then(|scheduler| scheduler.tasks().0.find(|&(id, _)| id == task))
+                    .map(|(_, task)| task.name());
+                match name {
+                    Some(name) => write!(out, "{name}"),
+                    None => write!(out, "task {task}"),
+                }
+                // folded stacks go from the outermost frame to the innermost
+                for pc in frames.iter().rev() {
+                    write!(out, ";{pc:#x}");
+                }
+                writeln!(out, " {count}");
+            });
+        }),
+        Some(_) => {
+            return Err(KernelError::InvalidArgument {
+                reason: "expected start, stop, flat or folded",
+            })
+        }
+    }
+
+    Ok(())
+}
+
+fn ps(_args: Args, out: &mut Output) -> Result<(), KernelError> {
+    without_interrupts(|| {
+        // SAFETY: the scheduler is otherwise only accessed from exception handlers and kernel_main,
+        // and we've masked the exceptions that could interrupt us.
+        let Some(scheduler) = (unsafe { SCHEDULER.get_mut() }) else {
+            writeln!(out, "scheduler not started");
+            return;
+        };
+
+        let (tasks, current) = scheduler.tasks();
+        writeln!(out, " id group name         ticks kernel stack");
+        for (id, task) in tasks {
+            let usage = task.kernel_stack_usage();
+            let running = if id == current { '*' } else { ' ' };
+            write!(
+                out,
+                "{running}{id:>2} {:>5} {:<12} {:>5} {usage}",
+                task.group(),
+                task.name(),
+                task.ticks()
+            );
+            if usage.is_high() {
+                write!(out, " (high)");
+            }
+            if let Some((status, _)) = task.exited() {
+                write!(out, " (exited {status})");
+            }
+            writeln!(out);
+        }
+
+        // groups are named by their leaders' ids, so each group is listed once, in id order
+        for (group, _) in scheduler.tasks().0 {
+            let (tasks, ticks) = scheduler
+                .tasks()
+                .0
+                .filter(|(_, task)| task.group() == group)
+                .fold((0, 0), |(tasks, ticks), (_, task)| {
+                    (tasks + 1, ticks + task.ticks())
+                });
+            if tasks > 0 {
+                writeln!(out, "group {group}: {tasks} tasks, {ticks} ticks");
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn suspend(mut args: Args, _out: &mut Output) -> Result<(), KernelError> {
+    let seconds = parse_number(args.next().ok_or(KernelError::InvalidArgument {
+        reason: "expected a number of seconds",
+    })?)?;
+
+    // the console's own interrupt stays disabled while this runs, so it can't wake us
+    suspend::suspend(Some(seconds as u64))
+}
+
+fn uarts(_args: Args, out: &mut Output) -> Result<(), KernelError> {
+    for uart in pl011::list() {
+        write!(out, "ttyAMA{} at {:p}", uart.index, uart.base);
+        if let Some(interrupt) = uart.interrupt {
+            write!(out, ", interrupt {interrupt:?}");
+        }
+        writeln!(out, ", {}", uart.owner.unwrap_or("unclaimed"));
+    }
+
+    Ok(())
+}
+
+fn version(_args: Args, out: &mut Output) -> Result<(), KernelError> {
+    writeln!(out, "{BUILD_INFO}");
+
+    Ok(())
+}
+
+fn watch(mut args: Args, out: &mut Output) -> Result<(), KernelError> {
+    let Some(address) = args.next() else {
+        for (number, watchpoint) in watchpoint::list() {
+            writeln!(
+                out,
+                "{number}: {:#x} ({} bytes), {:?}",
+                watchpoint.address, watchpoint.len, watchpoint.action
+            );
+        }
+        return Ok(());
+    };
+    let address = parse_number(address)?;
+    let len = args.next().map(parse_number).transpose()?.unwrap_or(8);
+    let action = match args.next() {
+        None => Action::Continue,
+        Some("halt") => Action::Halt,
+        Some(_) => {
+            return Err(KernelError::InvalidArgument {
+                reason: "expected “halt”",
+            })
+        }
+    };
+
+    let number = watchpoint::set(address, len, action)?;
+    writeln!(out, "watchpoint {number} set");
+
+    Ok(())
+}
+
+fn unwatch(mut args: Args, _out: &mut Output) -> Result<(), KernelError> {
+    let number = args.next().ok_or(KernelError::InvalidArgument {
+        reason: "expected a watchpoint number",
+    })?;
+
+    watchpoint::clear(parse_number(number)?)
+}
+
+/// Prints the first prompt, once everything else has had a chance to log during boot.
+fn init(_fdt: &Fdt) -> Result<(), KernelError> {
+    write!(Output, "{PROMPT}");
+
+    Ok(())
+}
+initcall!(late, init);
@@ -0,0 +1,97 @@
+//! The kernel log, kept in a ring buffer in memory, which overwrites the oldest lines once full.
+//!
+//! The buffer is a log sink (see [`LogSink`]) like the console UART, so it keeps the log even when
+//! the log isn't written to the UART, and the console's `dmesg` command prints it.
+use core::fmt::{self, Write};
+
+use fdt::Fdt;
+
+use crate::console::Output;
+use crate::error::KernelError;
+use crate::logging::{self, LogSink};
+use crate::sync::without_interrupts;
+
+/// Size of the ring buffer, in bytes.
+const LEN: usize = 0x4000;
+
+/// Number of bytes [`print`] copies out of the ring buffer at a time, with interrupts masked.
+const CHUNK_LEN: usize = 256;
+
+static mut RING: Ring = Ring {
+    bytes: [0; LEN],
+    end: 0,
+    wrapped: false,
+};
+
+struct Ring {
+    bytes: [u8; LEN],
+    /// Index of the byte after the newest, which is the oldest once the buffer has wrapped.
+    end: usize,
+    /// Whether the buffer has been filled at least once, so it starts at `end` rather than 0.
+    wrapped: bool,
+}
+
+impl Write for Ring {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for &byte in s.as_bytes() {
+            self.bytes[self.end] = byte;
+            self.end = (self.end + 1) % LEN;
+            self.wrapped |= self.end == 0;
+        }
+
+        Ok(())
+    }
+}
+
+impl LogSink for Ring {
+    fn log(&mut self, record: &log::Record) {
+        let level = record.level();
+        let file = record.file().unwrap_or("<unknown file>");
+        let line = record.line().unwrap_or(0);
+        let _ = writeln!(self, "[{level:<5} {file}:{line}] {}", record.args());
+    }
+}
+
+/// Starts keeping the log in the ring buffer.
+fn init(_fdt: &Fdt) -> Result<(), KernelError> {
+    // SAFETY: RING is only written through the log sinks, which are only used with interrupts
+    // masked.
+    logging::register_sink("ring", abi::log_sink::RING, unsafe { &mut RING })
+}
+initcall!(early, init);
+
+/// Prints the lines in the ring buffer, from oldest to newest, leaving out the oldest line if it
+/// was partly overwritten.
+///
+/// The buffer is copied out a chunk at a time with interrupts masked, and printed with them
+/// unmasked, so the log can still be written to while it's printed, at the cost of a garbled line
+/// or two if the buffer wraps around to what's being printed.
+pub fn print(out: &mut Output) {
+    // SAFETY: RING is only written through the log sinks, with interrupts masked, so nothing
+    // writes to it while we read it with interrupts masked too.
+    let (end, wrapped) = without_interrupts(|| unsafe { (RING.end, RING.wrapped) });
+    let (start, len) = if wrapped { (end, LEN) } else { (0, end) };
+
+    let mut skipping = wrapped;
+    let mut chunk = [0; CHUNK_LEN];
+    for offset in (0..len).step_by(CHUNK_LEN) {
+        let chunk = &mut chunk[..CHUNK_LEN.min(len - offset)];
+        without_interrupts(|| {
+            // SAFETY: as above.
+            let ring = unsafe { &RING };
+            for (i, byte) in chunk.iter_mut().enumerate() {
+                *byte = ring.bytes[(start + offset + i) % LEN];
+            }
+        });
+
+        let mut bytes = &*chunk;
+        if skipping {
+            let Some(newline) = bytes.iter().position(|&byte| byte == b'\n') else {
+                continue;
+            };
+            bytes = &bytes[newline + 1..];
+            skipping = false;
+        }
+        out.write_bytes(bytes);
+    }
+}
@@ -0,0 +1,79 @@
+//! The entropy pool, which collects random bytes from hardware random number generators, like
+//! virtio-rng, for anything that needs an unpredictable seed, like ASLR or a getrandom system call.
+//!
+//! Every byte added is credited as 8 bits of entropy, so only sources whose every bit is
+//! unpredictable may add to the pool. Bytes are taken out at most once, so no two seeds share
+//! entropy. Bytes added while the pool is full are mixed into the bytes already there, which can't
+//! make them any more predictable, but aren't credited.
+use crate::error::KernelError;
+use crate::sync::without_interrupts;
+
+/// Number of bytes of entropy the pool can hold.
+pub const POOL_SIZE: usize = 256;
+
+static mut POOL: Pool = Pool {
+    bytes: [0; POOL_SIZE],
+    start: 0,
+    len: 0,
+    mix: 0,
+};
+
+/// A ring of bytes, added at the end and taken from the start.
+struct Pool {
+    bytes: [u8; POOL_SIZE],
+    start: usize,
+    /// Number of bytes in the pool, each credited with 8 bits of entropy.
+    len: usize,
+    /// Where the next byte added to a full pool is mixed in.
+    mix: usize,
+}
+
+/// Adds random bytes from a hardware source to the pool.
+pub fn add(bytes: &[u8]) {
+    without_interrupts(|| {
+        // SAFETY: the pool is only used with interrupts masked.
+        let pool = unsafe { &mut POOL };
+        for &byte in bytes {
+            if pool.len < POOL_SIZE {
+                pool.bytes[(pool.start + pool.len) % POOL_SIZE] = byte;
+                pool.len += 1;
+            } else {
+                pool.bytes[pool.mix % POOL_SIZE] ^= byte;
+                pool.mix = pool.mix.wrapping_add(1);
+            }
+        }
+    });
+}
+
+/// Fills `seed` with bytes taken from the pool, or fails with [`KernelError::WouldBlock`] if the
+/// pool doesn't have that many yet, taking none of them.
+#[allow(dead_code)]
+pub fn take(seed: &mut [u8]) -> Result<(), KernelError> {
+    if seed.len() > POOL_SIZE {
+        return Err(KernelError::InvalidArgument {
+            reason: "seed is larger than the entropy pool",
+        });
+    }
+
+    without_interrupts(|| {
+        // SAFETY: see add.
+        let pool = unsafe { &mut POOL };
+        if pool.len < seed.len() {
+            return Err(KernelError::WouldBlock);
+        }
+        for byte in seed.iter_mut() {
+            // forget the byte, so it can't be taken again
+            *byte = core::mem::take(&mut pool.bytes[pool.start]);
+            pool.start = (pool.start + 1) % POOL_SIZE;
+            pool.len -= 1;
+        }
+
+        Ok(())
+    })
+}
+
+/// Returns the number of bytes in the pool, which is how many can be taken right now.
+pub fn available() -> usize {
+    // SAFETY: see add.
+    without_interrupts(|| unsafe { POOL.len })
+}
@@ -5,7 +5,35 @@
 
 .globl _start
 _start:
-    mov x0, #0x9000000
+    // firmware that enters at EL2, like a Raspberry Pi's, needs to be left for EL1 first
+    mrs x0, CurrentEL
+    cmp x0, #(2 << 2)
+    b.ne .el1
+
+    // let EL1 use the physical counter and timer (EL1PCTEN | EL1PCEN), with the virtual counter
+    // at no offset from it, since EL2 won't be there to configure them
+    mrs x0, CNTHCTL_EL2
+    orr x0, x0, #0b11
+    msr CNTHCTL_EL2, x0
+    msr CNTVOFF_EL2, xzr
+    // EL1 is AArch64 (RW), and nothing it does traps to EL2, not even floating point (CPTR_EL2
+    // is only its RES1 bits)
+    mov x0, #(1 << 31)
+    msr HCR_EL2, x0
+    mov x0, #0x33ff
+    msr CPTR_EL2, x0
+    // SCTLR_EL1 is UNKNOWN out of reset, so start from its RES1 bits, with the MMU off
+    ldr x0, =0x30d00800
+    msr SCTLR_EL1, x0
+    // “return” to EL1h, with DAIF masked
+    mov x0, #0x3c5
+    msr SPSR_EL2, x0
+    adr x0, .el1
+    msr ELR_EL2, x0
+    eret
+
+.el1:
+    ldr x0, =EARLY_UART
     mov w1, #'u'
     mov w2, #'p'
     strb w1, [x0]               // “u”
@@ -23,11 +51,12 @@ _start:
     orr x2, x2, #0b11 // D_Table
     str x2, [x0, x1, lsl #3]
 
-    // lower VA range, level 1 (index 0)
+    // lower VA range, level 1 (the board's DEVICE_BLOCK), which is devices, so AttrIndx 2
+    // (Device-nGnRnE)
     ldr x0, =tt_lower_level1
-    mov x1, #0 // index (TODO: use ubfx and VA)
-    mov x2, #0x0000000000000000 // TODO: use VA
-    mov x3, #(0b1 << 10) | (0b01 << 0) // AF | D_Block
+    ldr x2, =DEVICE_BLOCK
+    ubfx x1, x2, #30, #9 // IA[38:30]
+    mov x3, #(0b1 << 10) | (2 << 2) | (0b01 << 0) // AF | AttrIndx | D_Block
     orr x2, x2, x3
     str x2, [x0, x1, lsl #3]
 
@@ -112,10 +141,17 @@ _start:
 
     // need to set both half regions to 2^48, because values that are too small
     // (that is, regions that are too big) may yield L0TF exceptions (R[SXWGM]).
+    // these tables use the 4KiB granule, even if the kernel was built for another one, until
+    // tt::init replaces them.
     mrs x5, TCR_EL1
-    ldr x5, =((16 << 16) | (16 << 0))   // T1SZ = 16, T0SZ = 16
+    ldr x5, =((0b10 << 30) | (16 << 16) | (16 << 0))   // TG1 = 4KiB, T1SZ = 16, T0SZ = 16
     msr TCR_EL1, x5
 
+    // memory attributes for each AttrIndx, which must match MAIR in the translation-tables crate
+    // (checked by tt::init). everything else here is AttrIndx 0 (Normal).
+    ldr x5, =0x0000000C040044FF
+    msr MAIR_EL1, x5
+
     mrs x5, SCTLR_EL1
     orr x5, x5, #1              // mmu enable
 .enable_mmu:
@@ -180,69 +216,69 @@ define_vector_trampoline 0x780, el0_a32, serror
 
 .section ".text"
 
-// **These macros MUST be kept in sync with the `Context` struct defined in `task.rs`.**
+// The CONTEXT_* offsets are those of the `Context` struct in task.rs, and are defined in main.rs.
 .macro task_save
-    sub sp, sp, #0x110
+    sub sp, sp, #CONTEXT_SIZE
 
     // GPRs x0 through x29 => context.gprs[0] through context.gprs[29]
-    stp x0, x1, [sp, #0x00]
-    stp x2, x3, [sp, #0x10]
-    stp x4, x5, [sp, #0x20]
-    stp x6, x7, [sp, #0x30]
-    stp x8, x9, [sp, #0x40]
-    stp x10, x11, [sp, #0x50]
-    stp x12, x13, [sp, #0x60]
-    stp x14, x15, [sp, #0x70]
-    stp x16, x17, [sp, #0x80]
-    stp x18, x19, [sp, #0x90]
-    stp x20, x21, [sp, #0xa0]
-    stp x22, x23, [sp, #0xb0]
-    stp x24, x25, [sp, #0xc0]
-    stp x26, x27, [sp, #0xd0]
-    stp x28, x29, [sp, #0xe0]
+    stp x0, x1, [sp, #CONTEXT_X0 + 0x00]
+    stp x2, x3, [sp, #CONTEXT_X0 + 0x10]
+    stp x4, x5, [sp, #CONTEXT_X0 + 0x20]
+    stp x6, x7, [sp, #CONTEXT_X0 + 0x30]
+    stp x8, x9, [sp, #CONTEXT_X0 + 0x40]
+    stp x10, x11, [sp, #CONTEXT_X0 + 0x50]
+    stp x12, x13, [sp, #CONTEXT_X0 + 0x60]
+    stp x14, x15, [sp, #CONTEXT_X0 + 0x70]
+    stp x16, x17, [sp, #CONTEXT_X0 + 0x80]
+    stp x18, x19, [sp, #CONTEXT_X0 + 0x90]
+    stp x20, x21, [sp, #CONTEXT_X0 + 0xa0]
+    stp x22, x23, [sp, #CONTEXT_X0 + 0xb0]
+    stp x24, x25, [sp, #CONTEXT_X0 + 0xc0]
+    stp x26, x27, [sp, #CONTEXT_X0 + 0xd0]
+    stp x28, x29, [sp, #CONTEXT_X0 + 0xe0]
 
     // GPR x30 => context.gprs[30]
     // PSTATE => context.psr (we can clobber x0, since it's already been saved)
     mrs x0, SPSR_EL1
-    stp x30, x0, [sp, #0xf0]
+    stp x30, x0, [sp, #CONTEXT_X30]
 
     // PC => context.pc
     // SP => context.sp
     mrs x0, ELR_EL1
     mrs x1, SP_EL0
-    stp x0, x1, [sp, #0x100]
+    stp x0, x1, [sp, #CONTEXT_PC]
 .endm
 
 .macro task_restore
     // context.sp => SP
     // context.pc => PC
-    ldp x0, x1, [sp, #0x100]
+    ldp x0, x1, [sp, #CONTEXT_PC]
     msr SP_EL0, x1
     msr ELR_EL1, x0
 
     // context.psr => PSTATE
     // GPR x30 => context.gprs[30] (we can clobber x0 since it hasn't been restored yet)
-    ldp x30, x0, [sp, #0xf0]
+    ldp x30, x0, [sp, #CONTEXT_X30]
     msr SPSR_EL1, x0
 
     // context.gprs[0] through context.gprs[29] => GPRs x0 through x29
-    ldp x28, x29, [sp, #0xe0]
-    ldp x26, x27, [sp, #0xd0]
-    ldp x24, x25, [sp, #0xc0]
-    ldp x22, x23, [sp, #0xb0]
-    ldp x20, x21, [sp, #0xa0]
-    ldp x18, x19, [sp, #0x90]
-    ldp x16, x17, [sp, #0x80]
-    ldp x14, x15, [sp, #0x70]
-    ldp x12, x13, [sp, #0x60]
-    ldp x10, x11, [sp, #0x50]
-    ldp x8, x9, [sp, #0x40]
-    ldp x6, x7, [sp, #0x30]
-    ldp x4, x5, [sp, #0x20]
-    ldp x2, x3, [sp, #0x10]
-    ldp x0, x1, [sp, #0x00]
-
-    add sp, sp, #0x110
+    ldp x28, x29, [sp, #CONTEXT_X0 + 0xe0]
+    ldp x26, x27, [sp, #CONTEXT_X0 + 0xd0]
+    ldp x24, x25, [sp, #CONTEXT_X0 + 0xc0]
+    ldp x22, x23, [sp, #CONTEXT_X0 + 0xb0]
+    ldp x20, x21, [sp, #CONTEXT_X0 + 0xa0]
+    ldp x18, x19, [sp, #CONTEXT_X0 + 0x90]
+    ldp x16, x17, [sp, #CONTEXT_X0 + 0x80]
+    ldp x14, x15, [sp, #CONTEXT_X0 + 0x70]
+    ldp x12, x13, [sp, #CONTEXT_X0 + 0x60]
+    ldp x10, x11, [sp, #CONTEXT_X0 + 0x50]
+    ldp x8, x9, [sp, #CONTEXT_X0 + 0x40]
+    ldp x6, x7, [sp, #CONTEXT_X0 + 0x30]
+    ldp x4, x5, [sp, #CONTEXT_X0 + 0x20]
+    ldp x2, x3, [sp, #CONTEXT_X0 + 0x10]
+    ldp x0, x1, [sp, #CONTEXT_X0 + 0x00]
+
+    add sp, sp, #CONTEXT_SIZE
 .endm
 
 .macro define_vector_stub, source:req, type:req
@@ -262,11 +298,11 @@ define_vector_trampoline 0x780, el0_a32, serror
         eret
 .endm
 
-// Exception taken from EL1 with SP_EL0
-define_vector_stub el1_sp0, synchronous
-define_vector_stub el1_sp0, irq
-define_vector_stub el1_sp0, fiq
-define_vector_stub el1_sp0, serror
+// Exception taken from EL1 with SP_EL0 (i.e. from a kernel thread)
+define_vector_task el1_sp0, synchronous
+define_vector_task el1_sp0, irq
+define_vector_task el1_sp0, fiq
+define_vector_task el1_sp0, serror
 
 // Exception taken from EL1 with SP_EL1
 define_vector_stub el1_sp1, synchronous
@@ -0,0 +1,343 @@
+//! Errors returned by fallible kernel operations.
+use core::fmt;
+
+use abi::Errno;
+use buddy_alloc::tree::{FreeError, OutOfMemoryError};
+
+use crate::gicv2::InterruptId;
+
+/// An error from any kernel subsystem, with enough context to explain it in a log message.
+///
+/// Syscalls translate these into the error codes seen by tasks (see [`Errno`]), so a new variant
+/// should be added here rather than reporting errors some other way.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KernelError {
+    // allocator
+    /// There's no free block large enough for the allocation.
+    OutOfMemory,
+    /// The allocation being freed was not allocated, or was already freed.
+    DoubleFree,
+    /// The address being freed is inside an allocation or outside the allocator, so it can't
+    /// have come from the allocator, which suggests whoever kept it is corrupt.
+    NotAnAllocation,
+    /// RAM is split into more regions than the memory map or the allocator can manage.
+    TooManyMemoryRegions { max: usize },
+
+    // mapping
+    /// An address passed to the mapping code is not aligned to a page.
+    Misaligned { address: usize },
+    /// An address is not mapped, or not accessible.
+    Unmapped { address: usize },
+    /// A virtual address is already covered by a block or page mapping at a higher level, so it
+    /// can't be mapped with a page.
+    MappingConflict { virtual_address: usize, level: u8 },
+    /// Every slot for a region in a task's address space is in use.
+    TooManyRegions { max: usize },
+    /// A region overlaps another one already in the address space, at the given address.
+    RegionOverlaps { address: usize },
+
+    // driver probe
+    /// The devicetree has no node with the given compatible string.
+    DeviceNotFound { compatible: &'static str },
+    /// A devicetree node lacks a property needed to probe the device.
+    MissingProperty {
+        compatible: &'static str,
+        property: &'static str,
+    },
+    /// An interrupt specifier in the devicetree is malformed or out of range.
+    InvalidInterrupt {
+        interrupt_type: u32,
+        interrupt_number: u32,
+    },
+    /// The device has already been claimed by another subsystem.
+    DeviceClaimed {
+        compatible: &'static str,
+        owner: &'static str,
+    },
+    /// Every device with the given compatible string has already been claimed.
+    NoFreeDevice { compatible: &'static str },
+    /// A device didn't behave as its driver needs, for the given reason, so it can't be used.
+    DeviceFailed {
+        compatible: &'static str,
+        reason: &'static str,
+    },
+    /// Another driver has already registered the given kind of device.
+    AlreadyRegistered { what: &'static str },
+    /// Every slot for the given kind of driver registration is in use.
+    TooManyRegistered { what: &'static str, max: usize },
+    /// No driver has registered an alarm (see [`crate::alarm`]).
+    NoAlarm,
+    /// The host didn't pass a file with the given name (see [`crate::fw_cfg`]).
+    NoSuchFile { name: &'static str },
+    /// Every slot in the interrupt handler table is taken.
+    TooManyHandlers { interrupt_id: InterruptId },
+
+    /// The CPU lacks an optional architectural feature.
+    Unsupported { feature: &'static str },
+
+    // debugging
+    /// Every hardware watchpoint is in use.
+    NoFreeWatchpoint { count: usize },
+    /// There's no leak checkpoint with the given name (see [`crate::leak`]).
+    NoSuchCheckpoint,
+    /// Every leak checkpoint slot is in use.
+    TooManyCheckpoints { max: usize },
+    /// A leak checkpoint was taken when more allocations were live than it could remember, so
+    /// diffing from it would report the ones it missed as leaks.
+    CheckpointTruncated { max: usize },
+
+    // scheduler
+    /// Every task slot is in use.
+    TooManyTasks { max: usize },
+    /// There's no task with the given id.
+    NoSuchTask { id: usize },
+    /// There's no task in the task group with the given id.
+    NoSuchGroup { group: usize },
+    /// The caller isn't allowed to do that to another task.
+    NotPermitted { id: usize },
+    /// The operation can't complete until another task makes progress, so the caller must wait.
+    WouldBlock,
+
+    // handles and pipes
+    /// The handle is not open, or not open for the operation.
+    BadHandle { handle: usize },
+    /// Every slot in the task's handle table is in use.
+    TooManyHandles { max: usize },
+    /// Every pipe slot is in use.
+    TooManyPipes { max: usize },
+    /// The pipe being written to has no readers left.
+    BrokenPipe,
+    /// The operation was interrupted by Ctrl+C on the console.
+    Interrupted,
+
+    // logging
+    /// Every slot for a log target with a level of its own is in use.
+    TooManyLogTargets { max: usize },
+    /// Every slot for a log sink is in use.
+    TooManyLogSinks { max: usize },
+    /// Too many items are already waiting to be freed once their readers are done.
+    TooManyDeferred { max: usize },
+
+    // filesystems
+    /// There's no host directory shared with virtio-9p (see [`crate::virtio::p9`]).
+    NoShare,
+    /// The host failed a filesystem operation with the given Linux error number.
+    HostError { errno: u32 },
+
+    // syscalls and console commands
+    /// An argument is invalid, for the given reason.
+    InvalidArgument { reason: &'static str },
+    /// A task made a system call with an unknown `svc` immediate.
+    UnknownSyscall { number: u16 },
+}
+
+impl fmt::Display for KernelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::OutOfMemory => write!(f, "out of memory"),
+            Self::DoubleFree => write!(f, "double free"),
+            Self::NotAnAllocation => write!(f, "free of something that isn't an allocation"),
+            Self::TooManyMemoryRegions { max } => write!(f, "more than {max} regions of RAM"),
+            Self::Misaligned { address } => write!(f, "address {address:#x} is misaligned"),
+            Self::Unmapped { address } => write!(f, "address {address:#x} is not mapped"),
+            Self::MappingConflict {
+                virtual_address,
+                level,
+            } => write!(
+                f,
+                "virtual address {virtual_address:#x} is already mapped by a level {level} block"
+            ),
+            Self::TooManyRegions { max } => write!(f, "all {max} region slots are in use"),
+            Self::RegionOverlaps { address } => {
+                write!(f, "region overlaps another at {address:#x}")
+            }
+            Self::DeviceNotFound { compatible } => {
+                write!(f, "no device compatible with {compatible:?}")
+            }
+            Self::MissingProperty {
+                compatible,
+                property,
+            } => write!(
+                f,
+                "device compatible with {compatible:?} has no {property:?}"
+            ),
+            Self::DeviceClaimed { compatible, owner } => write!(
+                f,
+                "device compatible with {compatible:?} is already claimed by {owner}"
+            ),
+            Self::NoFreeDevice { compatible } => {
+                write!(f, "every device compatible with {compatible:?} is claimed")
+            }
+            Self::DeviceFailed { compatible, reason } => {
+                write!(f, "device compatible with {compatible:?} failed: {reason}")
+            }
+            Self::AlreadyRegistered { what } => write!(f, "another {what} is already registered"),
+            Self::TooManyRegistered { what, max } => write!(f, "all {max} {what} slots are in use"),
+            Self::NoAlarm => write!(f, "no alarm"),
+            Self::NoSuchFile { name } => write!(f, "no fw_cfg file {name:?}"),
+            Self::InvalidInterrupt {
+                interrupt_type,
+                interrupt_number,
+            } => write!(
+                f,
+                "invalid interrupt specifier (type {interrupt_type}, number {interrupt_number})"
+            ),
+            Self::TooManyHandlers { interrupt_id } => {
+                write!(f, "no free handler slot for interrupt {interrupt_id:?}")
+            }
+            Self::Unsupported { feature } => write!(f, "{feature} is not supported by this CPU"),
+            Self::NoFreeWatchpoint { count } => write!(f, "all {count} watchpoints are in use"),
+            Self::NoSuchCheckpoint => write!(f, "no such checkpoint"),
+            Self::TooManyCheckpoints { max } => write!(f, "all {max} checkpoints are in use"),
+            Self::CheckpointTruncated { max } => {
+                write!(
+                    f,
+                    "checkpoint only remembers {max} of the allocations live at the time"
+                )
+            }
+            Self::TooManyTasks { max } => write!(f, "all {max} task slots are in use"),
+            Self::NoSuchTask { id } => write!(f, "no task {id}"),
+            Self::NoSuchGroup { group } => write!(f, "no task group {group}"),
+            Self::NotPermitted { id } => write!(f, "not permitted for task {id}"),
+            Self::WouldBlock => write!(f, "operation would block"),
+            Self::BadHandle { handle } => write!(f, "handle {handle} is not open for that"),
+            Self::TooManyHandles { max } => write!(f, "all {max} handles are in use"),
+            Self::TooManyPipes { max } => write!(f, "all {max} pipe slots are in use"),
+            Self::BrokenPipe => write!(f, "pipe has no readers"),
+            Self::Interrupted => write!(f, "interrupted"),
+            Self::TooManyLogTargets { max } => {
+                write!(f, "all {max} log targets with their own level are in use")
+            }
+            Self::TooManyLogSinks { max } => write!(f, "all {max} log sinks are in use"),
+            Self::TooManyDeferred { max } => {
+                write!(f, "all {max} slots for deferred frees are in use")
+            }
+            Self::NoShare => write!(f, "no shared host directory"),
+            Self::HostError { errno } => write!(f, "host error {errno}"),
+            Self::InvalidArgument { reason } => write!(f, "invalid argument: {reason}"),
+            Self::UnknownSyscall { number } => write!(f, "unknown system call: svc #{number}"),
+        }
+    }
+}
+
+impl From<OutOfMemoryError> for KernelError {
+    fn from(_: OutOfMemoryError) -> Self {
+        Self::OutOfMemory
+    }
+}
+
+impl From<FreeError> for KernelError {
+    fn from(error: FreeError) -> Self {
+        match error {
+            FreeError::DoubleFree => Self::DoubleFree,
+            FreeError::OutOfRange | FreeError::InsideAllocation | FreeError::Reserved => {
+                Self::NotAnAllocation
+            }
+        }
+    }
+}
+
+impl From<memory_map::TooManyRegions> for KernelError {
+    fn from(_: memory_map::TooManyRegions) -> Self {
+        Self::TooManyMemoryRegions {
+            max: memory_map::MAX_REGIONS,
+        }
+    }
+}
+
+impl From<allocator::TooManyRegions> for KernelError {
+    fn from(_: allocator::TooManyRegions) -> Self {
+        Self::TooManyMemoryRegions {
+            max: memory_map::MAX_REGIONS,
+        }
+    }
+}
+
+impl From<driver::Error> for KernelError {
+    fn from(error: driver::Error) -> Self {
+        match error {
+            driver::Error::DeviceNotFound { compatible } => Self::DeviceNotFound { compatible },
+            driver::Error::MissingProperty {
+                compatible,
+                property,
+            } => Self::MissingProperty {
+                compatible,
+                property,
+            },
+            driver::Error::AlreadyRegistered { what } => Self::AlreadyRegistered { what },
+            driver::Error::TooManyRegistered { what, max } => Self::TooManyRegistered { what, max },
+        }
+    }
+}
+
+impl From<translation_tables::Error> for KernelError {
+    fn from(error: translation_tables::Error) -> Self {
+        match error {
+            translation_tables::Error::Misaligned { address } => Self::Misaligned { address },
+            translation_tables::Error::MappingConflict {
+                virtual_address,
+                level,
+            } => Self::MappingConflict {
+                virtual_address,
+                level,
+            },
+            translation_tables::Error::OutOfMemory => Self::OutOfMemory,
+            translation_tables::Error::ExecutableDevice { .. } => Self::InvalidArgument {
+                reason: "device memory can't be executable",
+            },
+        }
+    }
+}
+
+impl From<KernelError> for Errno {
+    fn from(error: KernelError) -> Self {
+        match error {
+            KernelError::OutOfMemory => Self::NoMemory,
+            KernelError::DoubleFree => Self::InvalidArgument,
+            KernelError::NotAnAllocation => Self::InvalidArgument,
+            KernelError::TooManyMemoryRegions { .. } => Self::NoMemory,
+            KernelError::Misaligned { .. } => Self::InvalidArgument,
+            KernelError::Unmapped { .. } => Self::BadAddress,
+            KernelError::MappingConflict { .. } => Self::AlreadyExists,
+            KernelError::TooManyRegions { .. } => Self::NoMemory,
+            KernelError::RegionOverlaps { .. } => Self::AlreadyExists,
+            KernelError::DeviceNotFound { .. } => Self::NoDevice,
+            KernelError::MissingProperty { .. } => Self::NoDevice,
+            KernelError::DeviceClaimed { .. } => Self::Busy,
+            KernelError::NoFreeDevice { .. } => Self::Busy,
+            KernelError::DeviceFailed { .. } => Self::NoDevice,
+            KernelError::AlreadyRegistered { .. } => Self::AlreadyExists,
+            KernelError::TooManyRegistered { .. } => Self::NoMemory,
+            KernelError::NoAlarm => Self::NoDevice,
+            KernelError::NoSuchFile { .. } => Self::NotFound,
+            KernelError::InvalidInterrupt { .. } => Self::InvalidArgument,
+            KernelError::TooManyHandlers { .. } => Self::Busy,
+            KernelError::Unsupported { .. } => Self::NoSys,
+            KernelError::NoFreeWatchpoint { .. } => Self::Busy,
+            KernelError::NoSuchCheckpoint => Self::NotFound,
+            KernelError::TooManyCheckpoints { .. } => Self::Busy,
+            KernelError::CheckpointTruncated { .. } => Self::InvalidArgument,
+            KernelError::TooManyTasks { .. } => Self::WouldBlock,
+            KernelError::NoSuchTask { .. } => Self::NotFound,
+            KernelError::NoSuchGroup { .. } => Self::NotFound,
+            KernelError::NotPermitted { .. } => Self::NotPermitted,
+            KernelError::WouldBlock => Self::WouldBlock,
+            KernelError::BadHandle { .. } => Self::BadHandle,
+            KernelError::TooManyHandles { .. } => Self::TooManyHandles,
+            KernelError::TooManyPipes { .. } => Self::NoMemory,
+            KernelError::BrokenPipe => Self::BrokenPipe,
+            KernelError::Interrupted => Self::Interrupted,
+            KernelError::TooManyLogTargets { .. } => Self::NoMemory,
+            KernelError::TooManyLogSinks { .. } => Self::NoMemory,
+            KernelError::TooManyDeferred { .. } => Self::Busy,
+            KernelError::NoShare => Self::NoDevice,
+            // the ABI's error numbers are Linux's, so the host's can be passed on as they are
+            KernelError::HostError { errno } => u16::try_from(errno)
+                .ok()
+                .filter(|code| (1..=abi::MAX_ERRNO).contains(code))
+                .map_or(Self::InvalidArgument, Self::from_code),
+            KernelError::InvalidArgument { .. } => Self::InvalidArgument,
+            KernelError::UnknownSyscall { .. } => Self::NoSys,
+        }
+    }
+}
@@ -0,0 +1,149 @@
+//! A fuzzer for system calls, which makes random system calls with adversarial arguments, like
+//! wild pointers, huge lengths and bad handles, as if from the current task.
+//!
+//! The kernel must never panic or hang on a system call, whatever its arguments, only return an
+//! error, and it must never let a task change its exception level or interrupt masks. The fuzzer
+//! runs as a selftest (see selftest.rs) with a fixed seed, so failures can be reproduced, or with
+//! the seed given by `fuzzseed=<n>` on the command line.
+//!
+//! System calls are dispatched directly, rather than with `svc`, since the scheduler hasn't
+//! started, so the arguments are in a context made up for each call. Opening the console is never
+//! fuzzed, so the fuzzer can't write whatever it likes to the console and confuse `cargo xtask ci`,
+//! and nor is changing the log sinks, which could stop the selftest results reaching the console.
+//! Nor is exiting, which would end the boot tasks one by one until there were none left to switch
+//! to.
+use core::ptr::{self, addr_of_mut};
+
+use abi::Errno;
+
+use crate::sync::without_interrupts;
+use crate::task::Context;
+use crate::{cmdline, linker_symbols, syscall, tt};
+
+/// Seed used unless `fuzzseed=<n>` is given.
+const DEFAULT_SEED: u64 = 0x5EED_CAFE_F00D_D065;
+
+/// Highest `svc` immediate to try, which is past the last system call, so unknown system calls
+/// are tried too.
+const MAX_IMMEDIATE: u16 = abi::syscall::HIGHEST + 4;
+
+/// System calls that are never fuzzed (see above).
+const EXCLUDED: [u16; 3] = [
+    abi::syscall::OPEN_CONSOLE,
+    abi::syscall::LOG_SINKS,
+    abi::syscall::EXIT,
+];
+
+/// Bits of `PSTATE` that a system call must not change: the exception level and stack pointer
+/// (M), and the interrupt masks (DAIF).
+const PSR_PROTECTED: u64 = 0x3CF;
+
+/// How the fuzzed system calls turned out.
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub seed: u64,
+    pub succeeded: usize,
+    pub failed: usize,
+}
+
+/// A xorshift64* generator, which is plenty random for picking arguments.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // xorshift gets stuck at zero
+        Self(seed.max(1))
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    fn pick(&mut self, choices: &[u64]) -> u64 {
+        choices[self.below(choices.len() as u64) as usize]
+    }
+}
+
+/// Makes `iterations` random system calls, returning an error if any of them did something a
+/// system call must never do.
+pub fn run(iterations: usize) -> Result<Summary, &'static str> {
+    let seed = match cmdline::option("fuzzseed").map(str::parse) {
+        Some(Ok(seed)) => seed,
+        Some(Err(_)) => return Err("fuzzseed is not a number"),
+        None => DEFAULT_SEED,
+    };
+    let mut rng = Rng::new(seed);
+    let mut summary = Summary {
+        seed,
+        ..Summary::default()
+    };
+
+    // memory the task could really read and write, in a task's stack, which is never used since
+    // the scheduler hasn't started, and the last word of that stack, so buffers run off its end
+    let top = linker_symbols::task1_stack_top().addr() as u64;
+    let pointers = [
+        0,
+        1,
+        top - tt::PAGE_SIZE as u64,
+        top - tt::PAGE_SIZE as u64 + 1,
+        top - 8,
+        crate::kernel_main as usize as u64,
+        0x4000_0000,
+        0xFFFF_0000_0000_0000,
+        1 << 63,
+        u64::MAX - 7,
+        u64::MAX,
+    ];
+    let lengths = [0, 1, 8, 64, tt::PAGE_SIZE as u64, 1 << 40, u64::MAX];
+    let small = [0, 1, 2, 7, 8, 9, 31, 32, 64, u32::MAX as u64 + 1, u64::MAX];
+
+    for _ in 0..iterations {
+        let immediate = loop {
+            let immediate = rng.below(u64::from(MAX_IMMEDIATE) + 1) as u16;
+            if !EXCLUDED.contains(&immediate) {
+                break immediate;
+            }
+        };
+        let mut context = Context::new(ptr::null(), ptr::null());
+        for n in 0..3 {
+            // each argument is a pointer, a length or a handle, depending on the system call, so
+            // try each kind in every position
+            let value = match rng.below(4) {
+                0 => rng.pick(&pointers),
+                1 => rng.pick(&lengths),
+                2 => rng.pick(&small),
+                _ => rng.next(),
+            };
+            context.set_x(n, value);
+        }
+        let psr = context.psr();
+
+        // system calls are only made with exceptions masked, and may write to the context
+        let result =
+            without_interrupts(|| syscall::dispatch(immediate, addr_of_mut!(context).cast_const()));
+        match result {
+            Ok(_) => summary.succeeded += 1,
+            // every error must be something we can tell the task
+            Err(error) => {
+                let _ = Errno::from(error);
+                summary.failed += 1;
+            }
+        }
+        if (context.psr() ^ psr) & PSR_PROTECTED != 0 {
+            log::error!(
+                "svc #{immediate} changed PSTATE from {psr:#x} to {:#x}",
+                context.psr()
+            );
+            return Err("a system call changed the exception level or interrupt masks");
+        }
+    }
+
+    Ok(summary)
+}
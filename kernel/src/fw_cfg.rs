@@ -0,0 +1,126 @@
+//! QEMU's firmware configuration device (fw_cfg), through which the host can pass files to the
+//! kernel with `-fw_cfg name=<name>,file=<path>`, such as which selftests to run (see
+//! `cargo xtask ci --selftest`).
+//!
+//! https://www.qemu.org/docs/master/specs/fw_cfg.html
+use fdt::Fdt;
+
+use crate::boot_info;
+use crate::error::KernelError;
+use crate::sync::without_interrupts;
+
+/// Log target of the fw_cfg driver.
+pub const LOG_TARGET: &str = "fw_cfg";
+
+/// 0x0: data register, which reads the selected item a byte at a time.
+const DATA: usize = 0x0;
+/// 0x8: selector register, which is big-endian in the MMIO interface.
+const SELECTOR: usize = 0x8;
+
+/// Item holding the signature "QEMU".
+const SIGNATURE: u16 = 0x0000;
+/// Item holding the directory of named files.
+const FILE_DIR: u16 = 0x0019;
+
+/// Size of the NUL-padded name in a file directory entry.
+const FILE_NAME_LEN: usize = 56;
+
+static mut FW_CFG: Option<FwCfg> = None;
+
+/// Finds the device, if there is one, and checks its signature.
+fn init(_fdt: &Fdt) -> Result<(), KernelError> {
+    let Some(base) = boot_info().fw_cfg else {
+        log::debug!(target: LOG_TARGET, "no fw_cfg device");
+        return Ok(());
+    };
+
+    let mut fw_cfg = FwCfg {
+        base: base as *mut u8,
+    };
+    fw_cfg.select(SIGNATURE);
+    let mut signature = [0; 4];
+    fw_cfg.read(&mut signature);
+    if &signature != b"QEMU" {
+        log::warn!(target: LOG_TARGET, "bad signature at {base:#x}: {signature:02x?}");
+        return Ok(());
+    }
+    log::debug!(target: LOG_TARGET, "fw_cfg at {base:#x}");
+
+    // SAFETY: interrupts are masked during boot, so nothing else is using the device yet.
+    unsafe { FW_CFG = Some(fw_cfg) };
+
+    Ok(())
+}
+initcall!(driver, init);
+
+/// Reads the file `name` into `buffer`, returning its full size, which may be more than was read
+/// if `buffer` is too small.
+pub fn read_file(name: &'static str, buffer: &mut [u8]) -> Result<usize, KernelError> {
+    without_interrupts(|| {
+        // SAFETY: the device is only used with interrupts masked, since reads depend on the
+        // selector.
+        let fw_cfg = unsafe { FW_CFG.as_mut() }.ok_or(KernelError::DeviceNotFound {
+            compatible: "qemu,fw-cfg-mmio",
+        })?;
+        let (select, size) = fw_cfg.find(name).ok_or(KernelError::NoSuchFile { name })?;
+
+        fw_cfg.select(select);
+        let len = buffer.len().min(size);
+        fw_cfg.read(&mut buffer[..len]);
+
+        Ok(size)
+    })
+}
+
+struct FwCfg {
+    base: *mut u8,
+}
+
+impl FwCfg {
+    /// Selects an item, which is then read from its start.
+    fn select(&mut self, key: u16) {
+        // SAFETY: the devicetree says there's a fw_cfg device at `base`.
+        unsafe {
+            self.base
+                .add(SELECTOR)
+                .cast::<u16>()
+                .write_volatile(key.to_be())
+        };
+    }
+
+    /// Reads the next bytes of the selected item.
+    fn read(&mut self, buffer: &mut [u8]) {
+        for byte in buffer {
+            // SAFETY: as in select, and the data register can be read a byte at a time.
+            *byte = unsafe { self.base.add(DATA).read_volatile() };
+        }
+    }
+
+    fn read_array<const N: usize>(&mut self) -> [u8; N] {
+        let mut bytes = [0; N];
+        self.read(&mut bytes);
+
+        bytes
+    }
+
+    /// Returns the item and size of the file `name`, from the file directory.
+    fn find(&mut self, name: &str) -> Option<(u16, usize)> {
+        self.select(FILE_DIR);
+
+        // each entry is a big-endian size and item, two reserved bytes, then the name
+        let count = u32::from_be_bytes(self.read_array());
+        for _ in 0..count {
+            let size = u32::from_be_bytes(self.read_array());
+            let select = u16::from_be_bytes(self.read_array());
+            let _reserved: [u8; 2] = self.read_array();
+            let entry: [u8; FILE_NAME_LEN] = self.read_array();
+
+            let len = entry.iter().position(|&b| b == 0).unwrap_or(FILE_NAME_LEN);
+            if &entry[..len] == name.as_bytes() {
+                return Some((select, size as usize));
+            }
+        }
+
+        None
+    }
+}
@@ -1,34 +1,66 @@
+use bootinfo::Interrupt;
+use bounded::bounds_checked;
 use byteorder::{BigEndian, ByteOrder};
+use fdt::Fdt;
+use gic::{Field, Layout};
+use hal::{Completion, InterruptController};
 use num::AsUsize;
 
 use crate::a53::gicv2::{CpuInterfaceRegisterBlock, DistributorRegisterBlock};
+use crate::boot_info;
+use crate::error::KernelError;
 
-macro_rules! bounds_checked {
-    ($(#[$meta:meta])* $vis:vis struct $name:ident ($int:ident ($low:literal ..= $high:literal))) => {
-        $(#[$meta])* $vis struct $name($int);
-        impl $name {
-            pub fn value(&self) -> $int {
-                self.0
-            }
+/// Log target of the GIC driver.
+pub const LOG_TARGET: &str = "gicv2";
+
+/// Finds and enables the GIC's distributor and CPU interface.
+fn init(_fdt: &Fdt) -> Result<(), KernelError> {
+    let (distributor, cpu_interface) = match boot_info().gic {
+        Some(bootinfo::Gic::V2 {
+            distributor,
+            cpu_interface,
+        }) => (distributor, cpu_interface),
+        Some(bootinfo::Gic::V3 { .. }) => {
+            return Err(KernelError::Unsupported { feature: "GICv3" })
         }
-        impl TryFrom<$int> for $name {
-            type Error = ();
-            fn try_from(inner: $int) -> Result<Self, Self::Error> {
-                match inner {
-                    $low ..= $high => Ok(Self(inner)),
-                    _ => Err(()),
-                }
-            }
+        None => {
+            return Err(KernelError::DeviceNotFound {
+                compatible: "arm,cortex-a15-gic",
+            })
         }
     };
-    ($($(#[$meta:meta])* $vis:vis struct $name:ident ($($details:tt)+);)+) => {
-        $(bounds_checked!($(#[$meta])* $vis struct $name ($($details)+));)+
-    };
+
+    log::debug!(
+        target: LOG_TARGET,
+        "distributor at {distributor:#x}, CPU interface at {cpu_interface:#x}"
+    );
+
+    // SAFETY: interrupts are masked during boot, so nothing else is using the GIC yet.
+    unsafe {
+        GIC = Gic::new(distributor as *const u8, cpu_interface as *const u8);
+        GIC.enable();
+    }
+
+    Ok(())
 }
+initcall!(arch, init);
 
 pub struct Distributor(*mut DistributorRegisterBlock);
 pub struct CpuInterface(*mut CpuInterfaceRegisterBlock);
 
+/// A GICv2, as the [`InterruptController`] of the board: a distributor, and the CPU interface of
+/// the one CPU we run on.
+pub struct Gic {
+    pub distributor: Distributor,
+    pub cpu_interface: CpuInterface,
+}
+
+/// The GIC, which is found and enabled by [`init`], and used through
+/// [`board::interrupt_controller`] everywhere but here and the `gic` console command.
+///
+/// [`board::interrupt_controller`]: crate::board::interrupt_controller
+pub static mut GIC: Gic = Gic::new(core::ptr::null(), core::ptr::null());
+
 /// Interrupt specifier, as found in devicetree.
 ///
 /// https://www.kernel.org/doc/Documentation/devicetree/bindings/interrupt-controller/interrupts.txt
@@ -38,13 +70,16 @@ pub struct InterruptSpecifier<'dt>(&'dt [u8]);
 
 bounds_checked! {
     /// GIC interrupt ID.
-    #[derive(Clone, Copy, Debug, PartialEq)] pub struct InterruptId(usize (0..=1023));
+    #[derive(Clone, Copy, PartialEq)] pub struct InterruptId(usize (0..=1023));
 
     /// Zero-based PPI number, as found in devicetree.
-    #[derive(Clone, Copy, Debug, PartialEq)] pub struct PpiNumber(usize (0..=15));
+    #[derive(Clone, Copy, PartialEq)] pub struct PpiNumber(usize (0..=15));
 
     /// Zero-based SPI number, as found in devicetree.
-    #[derive(Clone, Copy, Debug, PartialEq)] pub struct SpiNumber(usize (0..=987));
+    #[derive(Clone, Copy, PartialEq)] pub struct SpiNumber(usize (0..=987));
+
+    /// Number of a CPU interface, as in the CPUID field of GICC_IAR for SGIs.
+    #[derive(Clone, Copy, PartialEq)] pub struct CpuId(u8 (0..=7));
 }
 
 impl Distributor {
@@ -61,11 +96,111 @@ impl Distributor {
 
     pub fn enable_interrupt(&mut self, interrupt_id: impl Into<InterruptId>) {
         let gicd = unsafe { &*self.0 };
+        let field = interrupt_id.into().field::<gic::Bitmap>();
+
+        gicd.isenabler[field.index].write_initial(|w| w.set_enable(field));
+    }
+
+    pub fn disable_interrupt(&mut self, interrupt_id: impl Into<InterruptId>) {
+        // SAFETY: the distributor's registers are mapped for as long as the kernel runs, and the
+        // register API only accesses them volatilely.
+        let gicd = unsafe { &*self.0 };
+        let field = interrupt_id.into().field::<gic::Bitmap>();
+
+        gicd.icenabler[field.index].write_initial(|w| w.clear_enable(field));
+    }
+
+    pub fn is_enabled(&self, interrupt_id: impl Into<InterruptId>) -> bool {
+        // SAFETY: see disable_interrupt.
+        let gicd = unsafe { &*self.0 };
+        let field = interrupt_id.into().field::<gic::Bitmap>();
+
+        gicd.isenabler[field.index].read(|r| r.enabled(field))
+    }
+
+    /// Returns whether an interrupt is pending, even if it's disabled or IRQs are masked.
+    pub fn is_pending(&self, interrupt_id: impl Into<InterruptId>) -> bool {
+        // SAFETY: see disable_interrupt.
+        let gicd = unsafe { &*self.0 };
+        let field = interrupt_id.into().field::<gic::Bitmap>();
+
+        gicd.ispender[field.index].read(|r| r.pending(field))
+    }
+
+    /// Returns whether an interrupt is active, which it is from when it's acknowledged until it's
+    /// deactivated.
+    pub fn is_active(&self, interrupt_id: impl Into<InterruptId>) -> bool {
+        // SAFETY: see disable_interrupt.
+        let gicd = unsafe { &*self.0 };
+        let field = interrupt_id.into().field::<gic::Bitmap>();
+
+        gicd.isactiver[field.index].read(|r| r.active(field))
+    }
+
+    /// Returns the number of interrupt IDs the distributor implements, starting from zero.
+    pub fn interrupt_lines(&self) -> usize {
+        // SAFETY: see disable_interrupt.
+        let gicd = unsafe { &*self.0 };
+        let lines = gicd.typer.read(|r| 32 * (r.it_lines_number() + 1));
+
+        lines.min(gic::INTERRUPTS)
+    }
+
+    /// Returns the priority of an interrupt, where lower values are higher priorities.
+    pub fn priority(&self, interrupt_id: impl Into<InterruptId>) -> u8 {
+        // SAFETY: see disable_interrupt.
+        let gicd = unsafe { &*self.0 };
+        let field = interrupt_id.into().field::<gic::Priority>();
+
+        gicd.ipriorityr[field.index].read(|r| r.priority(field))
+    }
+
+    /// Returns the CPUs an interrupt is forwarded to, one bit per CPU interface.
+    pub fn targets(&self, interrupt_id: impl Into<InterruptId>) -> u8 {
+        // SAFETY: see disable_interrupt.
+        let gicd = unsafe { &*self.0 };
+        let field = interrupt_id.into().field::<gic::Targets>();
+
+        gicd.itargetsr[field.index].read(|r| r.targets(field))
+    }
+
+    /// Returns whether an interrupt is edge-triggered, rather than level-sensitive.
+    pub fn is_edge_triggered(&self, interrupt_id: impl Into<InterruptId>) -> bool {
+        // SAFETY: see disable_interrupt.
+        let gicd = unsafe { &*self.0 };
+        let field = interrupt_id.into().field::<gic::Config>();
+
+        gicd.icfgr[field.index].read(|r| r.edge_triggered(field))
+    }
+
+    /// Sets the priority of an interrupt, where lower values are higher priorities.
+    #[allow(dead_code)]
+    pub fn set_priority(&mut self, interrupt_id: impl Into<InterruptId>, priority: u8) {
+        // SAFETY: see disable_interrupt.
+        let gicd = unsafe { &*self.0 };
+        let field = interrupt_id.into().field::<gic::Priority>();
 
-        let interrupt_id = interrupt_id.into().value();
-        let (n, m) = (interrupt_id / 32, interrupt_id % 32);
+        gicd.ipriorityr[field.index].modify(|w| w.priority(field, priority));
+    }
+
+    /// Sets the CPUs an SPI is forwarded to, one bit per CPU interface.
+    #[allow(dead_code)]
+    pub fn set_targets(&mut self, interrupt_id: impl Into<InterruptId>, targets: u8) {
+        // SAFETY: see disable_interrupt.
+        let gicd = unsafe { &*self.0 };
+        let field = interrupt_id.into().field::<gic::Targets>();
+
+        gicd.itargetsr[field.index].modify(|w| w.targets(field, targets));
+    }
+
+    /// Sets whether an interrupt is edge-triggered, rather than level-sensitive.
+    #[allow(dead_code)]
+    pub fn set_edge_triggered(&mut self, interrupt_id: impl Into<InterruptId>, edge: bool) {
+        // SAFETY: see disable_interrupt.
+        let gicd = unsafe { &*self.0 };
+        let field = interrupt_id.into().field::<gic::Config>();
 
-        gicd.isenabler[n].write_initial(|w| w.set_enable(m));
+        gicd.icfgr[field.index].modify(|w| w.edge_triggered(field, edge));
     }
 }
 
@@ -77,25 +212,105 @@ impl CpuInterface {
     pub fn enable(&mut self) {
         let gicc = unsafe { &*self.0 };
 
-        // enable group 0 interrupts
-        gicc.ctlr.write_initial(|w| w.enable(true));
+        // enable group 0 interrupts, and split priority drop (GICC_EOIR) from deactivation
+        // (GICC_DIR) so that interrupts can be deactivated after their handling is deferred
+        gicc.ctlr.write_initial(|w| {
+            w.enable(true);
+            w.eoi_mode(true);
+        });
 
         // set priority threshold to most lenient
         gicc.pmr.write_initial(|w| w.priority(0xff));
     }
 
+    /// Returns the priority mask, where only interrupts with a higher priority (a lower value) are
+    /// signalled.
+    pub fn priority_mask(&self) -> u8 {
+        // SAFETY: see deactivate.
+        let gicc = unsafe { &*self.0 };
+
+        gicc.pmr.read(|r| r.priority())
+    }
+
     /// Acknowledges an interrupt, handles it, and signals completion of interrupt processing.
     ///
-    /// The cpuid and interrupt id read from GICC_IAR are provided to the handler closure.
-    pub fn handle(&mut self, handler: impl FnOnce(u8, InterruptId)) {
+    /// The cpuid and interrupt id read from GICC_IAR are provided to the handler closure. The
+    /// running priority is always dropped once the handler returns, but if the handler returns
+    /// [`Completion::Defer`], the interrupt stays active (and won't be signalled again) until it
+    /// is deactivated with [`CpuInterface::deactivate`].
+    pub fn handle(&mut self, handler: impl FnOnce(CpuId, InterruptId) -> Completion) {
         let gicc = unsafe { &mut *self.0 };
         let (iar, cpuid, interrupt_id) =
             gicc.iar.read(|r| (r.entire(), r.cpuid(), r.interrupt_id()));
 
-        handler(cpuid, interrupt_id);
+        let completion = handler(cpuid, interrupt_id);
 
         // Write back the entire GICC_IAR as recommended by the GICC_EOIR docs
-        gicc.eoir.write_initial(|w| w.entire_iar(iar))
+        gicc.eoir.write_initial(|w| w.entire_iar(iar));
+
+        if completion == Completion::Deactivate {
+            gicc.dir.write_initial(|w| w.entire_iar(iar));
+        }
+    }
+
+    /// Deactivates an interrupt whose completion was deferred by its handler.
+    ///
+    /// Only the interrupt id is written to GICC_DIR, so this can't be used for SGIs, which also
+    /// need the cpuid of the requesting processor.
+    pub fn deactivate(&mut self, interrupt_id: InterruptId) {
+        // SAFETY: the CPU interface's registers are mapped for as long as the kernel runs, and the
+        // register API only accesses them volatilely.
+        let gicc = unsafe { &mut *self.0 };
+
+        // interrupt ids are at most 1023, so this can't fail
+        let interrupt_id = u32::try_from(interrupt_id.value()).expect("interrupt id to fit in u32");
+        gicc.dir.write_initial(|w| w.entire_iar(interrupt_id));
+    }
+}
+
+impl Gic {
+    pub const fn new(distributor: *const u8, cpu_interface: *const u8) -> Self {
+        Self {
+            distributor: Distributor::new(distributor),
+            cpu_interface: CpuInterface::new(cpu_interface),
+        }
+    }
+
+    pub fn enable(&mut self) {
+        self.distributor.enable();
+        self.cpu_interface.enable();
+    }
+}
+
+impl InterruptController for Gic {
+    type Interrupt = InterruptId;
+
+    fn enable_interrupt(&mut self, interrupt: InterruptId) {
+        self.distributor.enable_interrupt(interrupt);
+    }
+
+    fn disable_interrupt(&mut self, interrupt: InterruptId) {
+        self.distributor.disable_interrupt(interrupt);
+    }
+
+    fn is_enabled(&self, interrupt: InterruptId) -> bool {
+        self.distributor.is_enabled(interrupt)
+    }
+
+    fn is_pending(&self, interrupt: InterruptId) -> bool {
+        self.distributor.is_pending(interrupt)
+    }
+
+    /// Handles the interrupt in GICC_IAR, which is [`InterruptId::spurious`] if there's none.
+    fn handle(&mut self, handler: impl FnOnce(InterruptId) -> Completion) {
+        self.cpu_interface.handle(|cpuid, interrupt_id| {
+            log::trace!(target: LOG_TARGET, "cpuid = {cpuid}, interrupt_id = {interrupt_id:?}");
+            handler(interrupt_id)
+        });
+    }
+
+    fn deactivate(&mut self, interrupt: InterruptId) {
+        self.cpu_interface.deactivate(interrupt);
     }
 }
 
@@ -103,6 +318,13 @@ impl InterruptId {
     pub const fn spurious() -> Self {
         Self(1023)
     }
+
+    /// Returns where this interrupt's field is in distributor registers with the given layout.
+    ///
+    /// Panics if this is one of the special interrupt IDs (1020 to 1023), which have no fields.
+    pub fn field<L: Layout>(self) -> Field {
+        L::field(self.0).expect("special interrupt IDs have no fields")
+    }
 }
 
 impl From<PpiNumber> for InterruptId {
@@ -122,13 +344,37 @@ impl InterruptSpecifier<'_> {
         InterruptSpecifierIter(interrupts)
     }
 
-    pub fn interrupt_id(&self) -> Result<InterruptId, ()> {
-        let interrupt_type = BigEndian::read_u32(&self.0[0..]);
-        let interrupt_number = BigEndian::read_u32(&self.0[4..]);
+    pub fn interrupt_id(&self) -> Result<InterruptId, KernelError> {
+        Interrupt {
+            interrupt_type: BigEndian::read_u32(&self.0[0..]),
+            interrupt_number: BigEndian::read_u32(&self.0[4..]),
+            flags: BigEndian::read_u32(&self.0[8..]),
+        }
+        .try_into()
+    }
+}
+
+impl TryFrom<Interrupt> for InterruptId {
+    type Error = KernelError;
+
+    fn try_from(interrupt: Interrupt) -> Result<Self, Self::Error> {
+        let Interrupt {
+            interrupt_type,
+            interrupt_number,
+            ..
+        } = interrupt;
+        let error = KernelError::InvalidInterrupt {
+            interrupt_type,
+            interrupt_number,
+        };
         match interrupt_type {
-            0 => Ok(SpiNumber::try_from(interrupt_number.as_usize())?.into()),
-            1 => Ok(PpiNumber::try_from(interrupt_number.as_usize())?.into()),
-            _ => panic!(),
+            0 => Ok(SpiNumber::try_from(interrupt_number.as_usize())
+                .map_err(|_| error)?
+                .into()),
+            1 => Ok(PpiNumber::try_from(interrupt_number.as_usize())
+                .map_err(|_| error)?
+                .into()),
+            _ => Err(error),
         }
     }
 }
@@ -0,0 +1,92 @@
+//! Canonical hexdumps (offset, hex and ASCII columns, like `hexdump -C`) of memory.
+use core::{fmt, slice};
+
+use crate::error::KernelError;
+use crate::tt;
+
+/// A hexdump of some bytes, formatted with [`fmt::Display`].
+pub struct Hexdump<'b> {
+    bytes: &'b [u8],
+    /// Address shown for the first byte.
+    base: usize,
+    /// Number of bytes per line.
+    width: usize,
+}
+
+impl<'b> Hexdump<'b> {
+    /// Creates a hexdump of `bytes`, with 16 bytes per line and addresses starting at the address
+    /// of `bytes`.
+    pub fn new(bytes: &'b [u8]) -> Self {
+        Self {
+            bytes,
+            base: bytes.as_ptr() as usize,
+            width: 16,
+        }
+    }
+
+    /// Sets the number of bytes per line, which must not be zero.
+    pub fn width(self, width: usize) -> Self {
+        assert!(width > 0, "hexdump width must not be zero");
+
+        Self { width, ..self }
+    }
+}
+
+impl Hexdump<'static> {
+    /// Creates a hexdump of `len` bytes of memory at `address`, if every byte is mapped.
+    ///
+    /// The mapping is checked when the hexdump is created, so it must be displayed before any of
+    /// the memory can be unmapped. Beware that reading device memory may have side effects.
+    pub fn memory(address: usize, len: usize) -> Result<Self, KernelError> {
+        let end = address
+            .checked_add(len)
+            .ok_or(KernelError::Unmapped { address })?;
+
+        // one address per page is enough, since pages are mapped as a whole
+        let mut page = address & !(tt::PAGE_SIZE - 1);
+        while page < end {
+            let first = page.max(address);
+            if !tt::is_readable(first) {
+                return Err(KernelError::Unmapped { address: first });
+            }
+            page += tt::PAGE_SIZE;
+        }
+
+        // SAFETY: we've checked that the whole range is mapped for reads.
+        let bytes = unsafe { slice::from_raw_parts(address as *const u8, len) };
+
+        Ok(Self::new(bytes))
+    }
+}
+
+impl fmt::Display for Hexdump<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, line) in self.bytes.chunks(self.width).enumerate() {
+            write!(f, "{:016x} ", self.base.wrapping_add(i * self.width))?;
+
+            for column in 0..self.width {
+                // an extra space between the two halves of each line, like `hexdump -C`
+                if column == self.width / 2 {
+                    write!(f, " ")?;
+                }
+                match line.get(column) {
+                    Some(byte) => write!(f, " {byte:02x}")?,
+                    None => write!(f, "   ")?,
+                }
+            }
+
+            write!(f, "  |")?;
+            for &byte in line {
+                let c = if byte.is_ascii_graphic() || byte == b' ' {
+                    byte as char
+                } else {
+                    '.'
+                };
+                write!(f, "{c}")?;
+            }
+            writeln!(f, "|")?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,127 @@
+//! Boot-time initialisation of kernel subsystems, which register themselves with [`initcall!`].
+//!
+//! Each initcall is placed in a linker section for its [`Level`] (see linker.ld), and the levels
+//! are run in order by [`run_all`]. Initcalls within a level run in link order, so anything that
+//! depends on another subsystem being initialised must be at a later level.
+//!
+//! Optional drivers (see kernel/drivers) can't see [`initcall!`], so they register with
+//! `driver::initcall!` instead, and their initcalls run after the rest of the driver level.
+use core::mem::size_of;
+use core::slice;
+
+use fdt::Fdt;
+
+use crate::addr::VirtAddr;
+use crate::error::KernelError;
+use crate::linker_symbols;
+
+/// A function that initialises a subsystem, given the FDT passed to the kernel.
+pub type InitFn = fn(&Fdt) -> Result<(), KernelError>;
+
+/// An initcall, as placed in a linker section by [`initcall!`].
+pub struct Initcall {
+    pub name: &'static str,
+    pub function: InitFn,
+}
+
+/// When an initcall runs, relative to the others.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Level {
+    /// Console and logging, so later levels can report problems.
+    Early,
+    /// Core architectural state: memory management and the interrupt controller.
+    Arch,
+    /// Device drivers.
+    Driver,
+    /// Anything that needs drivers, like the scheduler.
+    Late,
+}
+
+impl Level {
+    pub const ALL: [Level; 4] = [Self::Early, Self::Arch, Self::Driver, Self::Late];
+
+    /// Returns the initcalls registered at this level.
+    fn initcalls(self) -> &'static [Initcall] {
+        // each level's section starts where the last one ends
+        let (start, end) = match self {
+            Self::Early => (
+                linker_symbols::initcall_early(),
+                linker_symbols::initcall_arch(),
+            ),
+            Self::Arch => (
+                linker_symbols::initcall_arch(),
+                linker_symbols::initcall_driver(),
+            ),
+            Self::Driver => (
+                linker_symbols::initcall_driver(),
+                linker_symbols::initcall_module(),
+            ),
+            Self::Late => (
+                linker_symbols::initcall_late(),
+                linker_symbols::initcall_end(),
+            ),
+        };
+
+        // SAFETY: the linker symbols bound sections containing only `Initcall`s, placed there by
+        // `initcall!`, and each section is aligned for `Initcall`.
+        unsafe { section(start, end) }
+    }
+}
+
+/// Returns the initcalls registered by optional drivers.
+pub fn modules() -> &'static [driver::Initcall] {
+    // SAFETY: the linker symbols bound a section containing only `driver::Initcall`s, placed there
+    // by `driver::initcall!`, and the section is aligned for `driver::Initcall`.
+    unsafe {
+        section(
+            linker_symbols::initcall_module(),
+            linker_symbols::initcall_late(),
+        )
+    }
+}
+
+/// Returns the `T`s between `start` and `end`.
+///
+/// # Safety
+///
+/// The range must be aligned for `T` and contain only valid `T`s.
+pub unsafe fn section<T>(start: VirtAddr, end: VirtAddr) -> &'static [T] {
+    let len = (end.addr() - start.addr()) / size_of::<T>();
+
+    // SAFETY: the caller ensures that the range is aligned and contains only valid `T`s.
+    unsafe { slice::from_raw_parts(start.as_ptr(), len) }
+}
+
+/// Runs the initcalls at every level, in order.
+///
+/// Failures at the early and arch levels leave the kernel unable to boot, so they panic, but the
+/// kernel carries on without any driver or late initcall that fails.
+pub fn run_all(fdt: &Fdt) {
+    for level in Level::ALL {
+        for initcall in level.initcalls() {
+            log::trace!("initcall {:?} {}", level, initcall.name);
+
+            if let Err(error) = (initcall.function)(fdt) {
+                match level {
+                    Level::Early | Level::Arch => {
+                        panic!("initcall {} failed: {error}", initcall.name)
+                    }
+                    Level::Driver | Level::Late => {
+                        log::error!("initcall {} failed: {error}", initcall.name)
+                    }
+                }
+            }
+        }
+
+        if level == Level::Driver {
+            for initcall in modules() {
+                log::trace!("initcall {:?} {}", level, initcall.name);
+
+                if let Err(error) = (initcall.function)(fdt) {
+                    let error = KernelError::from(error);
+                    log::error!("initcall {} failed: {error}", initcall.name)
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,273 @@
+//! Interrupt handler registration and dispatch.
+//!
+//! Handlers are either run directly in the IRQ exception handler (the “top half”), or threaded:
+//! the top half only masks the line and queues the interrupt, leaving it active so the GIC won't
+//! signal it again, and the handler runs later in the IRQ thread, which deactivates and unmasks
+//! the interrupt afterwards. This keeps the time spent with IRQs masked short, at the cost of
+//! some latency.
+//!
+//! Some interrupts can be marked as wakeup sources with [`enable_wakeup`], which are the only ones
+//! left enabled while the system is suspended (see suspend.rs).
+use hal::{Completion, InterruptController};
+
+use crate::error::KernelError;
+use crate::gicv2::InterruptId;
+use crate::sync::without_interrupts;
+use crate::{board, reclaim, stats, syscall};
+
+/// Maximum number of interrupts with registered handlers.
+const MAX_HANDLERS: usize = 16;
+
+static mut HANDLERS: [Option<Handler>; MAX_HANDLERS] = [None; MAX_HANDLERS];
+static mut PENDING: Pending = Pending::new();
+/// Counter value at entry to the IRQ handler that first queued an interrupt for the IRQ thread,
+/// since the IRQ thread last ran out of queued interrupts.
+static mut WOKEN_AT: Option<u64> = None;
+
+/// How a registered handler should be run.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    /// Run the handler in the IRQ exception handler.
+    #[allow(dead_code)]
+    Direct,
+    /// Run the handler in the IRQ thread.
+    Threaded,
+}
+
+#[derive(Clone, Copy)]
+struct Handler {
+    interrupt_id: InterruptId,
+    function: fn(InterruptId),
+    mode: Mode,
+    /// Whether the interrupt can wake the system from suspend.
+    wakeup: bool,
+    /// Whether the interrupt was disabled by [`suspend`], to be enabled again by [`resume`].
+    suspended: bool,
+}
+
+/// Registers `function` as the handler for `interrupt_id`, and enables the interrupt.
+///
+/// Must be called before the scheduler starts, since the handler table is not locked.
+pub fn register(
+    interrupt_id: InterruptId,
+    function: fn(InterruptId),
+    mode: Mode,
+) -> Result<(), KernelError> {
+    // SAFETY: handlers are only registered during boot, before interrupts are unmasked.
+    let handlers = unsafe { &mut HANDLERS };
+    let slot = handlers
+        .iter_mut()
+        .find(|slot| slot.is_none())
+        .ok_or(KernelError::TooManyHandlers { interrupt_id })?;
+
+    *slot = Some(Handler {
+        interrupt_id,
+        function,
+        mode,
+        wakeup: false,
+        suspended: false,
+    });
+
+    // SAFETY: as above.
+    unsafe { board::interrupt_controller().enable_interrupt(interrupt_id) };
+
+    Ok(())
+}
+
+/// Marks the interrupt of a registered handler as a wakeup source.
+///
+/// Must be called before the scheduler starts, like [`register`].
+pub fn enable_wakeup(interrupt_id: InterruptId) -> Result<(), KernelError> {
+    // SAFETY: see register.
+    let handlers = unsafe { &mut HANDLERS };
+    let handler = handlers
+        .iter_mut()
+        .flatten()
+        .find(|handler| handler.interrupt_id == interrupt_id)
+        .ok_or(KernelError::InvalidArgument {
+            reason: "no handler for the wakeup interrupt",
+        })?;
+    handler.wakeup = true;
+
+    Ok(())
+}
+
+/// Disables every enabled interrupt that isn't a wakeup source, returning the number of wakeup
+/// sources that are enabled, and so can wake the system.
+///
+/// Interrupts whose threaded handlers are queued or running are already disabled, so they stay
+/// that way, even if they're wakeup sources, and the IRQ thread enables them once their handlers
+/// are done as usual.
+pub fn suspend() -> usize {
+    without_interrupts(|| {
+        // SAFETY: the handler table is only modified with IRQs masked after boot, and only here
+        // and in resume, which can't run concurrently.
+        let handlers = unsafe { &mut HANDLERS };
+        let mut wakeup = 0;
+        for handler in handlers.iter_mut().flatten() {
+            // SAFETY: as above, and the IRQ thread can't run while IRQs are masked.
+            let enabled = unsafe { board::interrupt_controller().is_enabled(handler.interrupt_id) };
+            if handler.wakeup {
+                wakeup += usize::from(enabled);
+            } else if enabled {
+                // SAFETY: as above.
+                unsafe { board::interrupt_controller().disable_interrupt(handler.interrupt_id) };
+                handler.suspended = true;
+            }
+        }
+
+        wakeup
+    })
+}
+
+/// Enables the interrupts disabled by [`suspend`].
+pub fn resume() {
+    without_interrupts(|| {
+        // SAFETY: see suspend.
+        let handlers = unsafe { &mut HANDLERS };
+        for handler in handlers.iter_mut().flatten() {
+            if core::mem::take(&mut handler.suspended) {
+                // SAFETY: see suspend.
+                unsafe { board::interrupt_controller().enable_interrupt(handler.interrupt_id) };
+            }
+        }
+    })
+}
+
+/// Returns whether any wakeup source is pending, even if IRQs are masked.
+pub fn wakeup_pending() -> bool {
+    // SAFETY: the handler table is never modified after boot, other than by suspend and resume.
+    let handlers = unsafe { &HANDLERS };
+
+    handlers
+        .iter()
+        .flatten()
+        .filter(|handler| handler.wakeup)
+        // SAFETY: reading whether an interrupt is pending has no side effects.
+        .any(|handler| unsafe { board::interrupt_controller().is_pending(handler.interrupt_id) })
+}
+
+/// Returns how the handler registered for `interrupt_id` is run, or `None` if there's no handler.
+pub fn mode(interrupt_id: InterruptId) -> Option<Mode> {
+    handler(interrupt_id).map(|handler| handler.mode)
+}
+
+/// Handles an interrupt acknowledged by the IRQ exception handler (the top half), running its
+/// handler directly or queueing it for the IRQ thread. `entry` is the counter value when the IRQ
+/// was taken, which the wakeup latency of the IRQ thread is measured from.
+pub fn dispatch(interrupt_id: InterruptId, entry: u64) -> Completion {
+    let Some(handler) = handler(interrupt_id) else {
+        log::warn!("no handler for interrupt {interrupt_id:?}");
+        return Completion::Deactivate;
+    };
+
+    match handler.mode {
+        Mode::Direct => {
+            (handler.function)(interrupt_id);
+            Completion::Deactivate
+        }
+        Mode::Threaded => {
+            // SAFETY: we're in the IRQ exception handler, so IRQs are masked. GICD_ICENABLERn is
+            // write-one-to-clear, so this can't race with the IRQ thread unmasking another line.
+            unsafe {
+                board::interrupt_controller().disable_interrupt(interrupt_id);
+                PENDING.push(interrupt_id);
+                WOKEN_AT.get_or_insert(entry);
+            }
+            Completion::Defer
+        }
+    }
+}
+
+/// Entry point of the IRQ thread, a kernel thread that runs threaded handlers.
+pub fn irq_thread() -> ! {
+    log::trace!("irq thread start");
+
+    loop {
+        // SAFETY: the queue is only otherwise accessed by the top half, which can't run while IRQs
+        // are masked. once it's empty, interrupts queued while we ran didn't need to wake us.
+        while let Some(interrupt_id) = without_interrupts(|| unsafe {
+            let interrupt_id = PENDING.pop();
+            if interrupt_id.is_none() {
+                WOKEN_AT = None;
+            }
+            interrupt_id
+        }) {
+            let handler = handler(interrupt_id).expect("queued interrupts have handlers");
+            (handler.function)(interrupt_id);
+
+            // SAFETY: GICC_DIR is write-only and GICD_ISENABLERn is write-one-to-set, so this
+            // can't race with the top half.
+            unsafe {
+                board::interrupt_controller().deactivate(interrupt_id);
+                board::interrupt_controller().enable_interrupt(interrupt_id);
+            }
+        }
+
+        // the IRQ thread is the closest thing we have to an idle task, so it frees deferred items
+        reclaim::collect();
+        syscall::yield_now();
+    }
+}
+
+/// Records how long the IRQ thread took to be scheduled after the IRQ that queued an interrupt for
+/// it, if any were queued since it last ran out of them. The scheduler calls this whenever it
+/// switches to the IRQ thread, with IRQs masked.
+pub fn thread_scheduled(now: u64) {
+    // SAFETY: WOKEN_AT and the histogram are only accessed with IRQs masked.
+    unsafe {
+        if let Some(woken_at) = WOKEN_AT.take() {
+            stats::WAKEUP_LATENCY.record_between(woken_at, now);
+        }
+    }
+}
+
+fn handler(interrupt_id: InterruptId) -> Option<Handler> {
+    // SAFETY: the handler table is never modified after boot.
+    let handlers = unsafe { &HANDLERS };
+
+    handlers
+        .iter()
+        .flatten()
+        .find(|handler| handler.interrupt_id == interrupt_id)
+        .copied()
+}
+
+/// Queue of interrupts waiting for their threaded handlers to run.
+///
+/// Queued interrupts stay active until their handlers have run, so each interrupt can only be
+/// queued once, and the queue can't hold more interrupts than there are handlers.
+struct Pending {
+    interrupt_ids: [InterruptId; MAX_HANDLERS],
+    head: usize,
+    len: usize,
+}
+
+impl Pending {
+    const fn new() -> Self {
+        Self {
+            interrupt_ids: [InterruptId::spurious(); MAX_HANDLERS],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, interrupt_id: InterruptId) {
+        assert!(self.len < MAX_HANDLERS, "pending interrupt queue overflow");
+
+        self.interrupt_ids[(self.head + self.len) % MAX_HANDLERS] = interrupt_id;
+        self.len += 1;
+    }
+
+    fn pop(&mut self) -> Option<InterruptId> {
+        if self.len == 0 {
+            return None;
+        }
+
+        let interrupt_id = self.interrupt_ids[self.head];
+        self.head = (self.head + 1) % MAX_HANDLERS;
+        self.len -= 1;
+
+        Some(interrupt_id)
+    }
+}
@@ -0,0 +1,268 @@
+//! Leak detection for the page allocator, by diffing snapshots of its live allocations taken at
+//! named checkpoints.
+//!
+//! Every allocation made through [`crate::mm`] is tagged with its owner, the code that called
+//! [`crate::mm::alloc_pages`] or [`crate::mm::valloc`], so a leak can be traced back to where it
+//! came from. To hunt for leaks in a new subsystem, take a checkpoint with `leak mark <name>`
+//! before exercising it, then `leak <name>` lists whatever it allocated that's still live.
+//!
+//! Tags and snapshots are kept in fixed-size tables, so when too many pages are live, some may be
+//! reported without an owner, and snapshots may be truncated. Diffing from a truncated snapshot is
+//! refused, since everything it missed would look like a leak, and diffing to one is marked as
+//! partial, since it may have missed leaks.
+use core::fmt;
+use core::panic::Location;
+
+use crate::error::KernelError;
+use crate::sync::without_interrupts;
+use crate::units::{HexRange, HumanSize};
+use crate::ALLOCATOR;
+
+/// Number of live allocations that can be tagged with their owners.
+const MAX_TAGS: usize = 256;
+
+/// Number of checkpoints that can be kept at once.
+const MAX_CHECKPOINTS: usize = 4;
+
+/// Number of allocations each checkpoint can remember.
+const MAX_SNAPSHOT_LEN: usize = 128;
+
+/// Maximum length of a checkpoint's name, in bytes.
+const MAX_NAME_LEN: usize = 16;
+
+static mut TAGS: [Option<Tag>; MAX_TAGS] = [None; MAX_TAGS];
+
+static mut CHECKPOINTS: [Checkpoint; MAX_CHECKPOINTS] = [Checkpoint::UNUSED; MAX_CHECKPOINTS];
+
+#[derive(Clone, Copy)]
+struct Tag {
+    address: usize,
+    owner: &'static Location<'static>,
+}
+
+/// A live allocation, and the code that allocated it, if it was tagged.
+#[derive(Clone, Copy)]
+pub struct Allocation {
+    pub address: usize,
+    pub size: usize,
+    pub owner: Option<&'static Location<'static>>,
+}
+
+impl Allocation {
+    const EMPTY: Self = Self {
+        address: 0,
+        size: 0,
+        owner: None,
+    };
+
+    fn is_same(&self, other: &Self) -> bool {
+        self.address == other.address && self.size == other.size
+    }
+}
+
+impl fmt::Display for Allocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let range = HexRange(self.address, self.address + self.size);
+        write!(f, "{range} ({}), ", HumanSize(self.size))?;
+        match self.owner {
+            Some(owner) => write!(f, "from {owner}"),
+            None => write!(f, "owner unknown"),
+        }
+    }
+}
+
+/// A snapshot of the live allocations, which is kept in place rather than built on the stack,
+/// since it's too big for that.
+struct Checkpoint {
+    name: [u8; MAX_NAME_LEN],
+    /// Length of the name, or zero if the checkpoint is unused.
+    name_len: usize,
+    allocations: [Allocation; MAX_SNAPSHOT_LEN],
+    len: usize,
+    /// Whether there were more live allocations than the snapshot could remember.
+    truncated: bool,
+}
+
+impl Checkpoint {
+    const UNUSED: Self = Self {
+        name: [0; MAX_NAME_LEN],
+        name_len: 0,
+        allocations: [Allocation::EMPTY; MAX_SNAPSHOT_LEN],
+        len: 0,
+        truncated: false,
+    };
+
+    fn is_used(&self) -> bool {
+        self.name_len > 0
+    }
+
+    fn name(&self) -> &str {
+        // names are only ever copied from a str, and never truncated
+        core::str::from_utf8(&self.name[..self.name_len]).unwrap_or("")
+    }
+
+    fn allocations(&self) -> &[Allocation] {
+        &self.allocations[..self.len]
+    }
+}
+
+/// Tags the allocation at `address` as owned by `owner`, once it has been allocated.
+pub fn tag(address: usize, owner: &'static Location<'static>) {
+    without_interrupts(|| {
+        // SAFETY: the tags are only used with interrupts masked.
+        let tags = unsafe { &mut TAGS };
+
+        // if every slot is taken, the allocation is simply reported without an owner
+        if let Some(slot) = tags.iter_mut().find(|slot| slot.is_none()) {
+            *slot = Some(Tag { address, owner });
+        }
+    });
+}
+
+/// Forgets the owner of the allocation at `address`, once it has been freed.
+pub fn untag(address: usize) {
+    without_interrupts(|| {
+        // SAFETY: see tag.
+        let tags = unsafe { &mut TAGS };
+
+        if let Some(slot) = tags
+            .iter_mut()
+            .find(|slot| slot.is_some_and(|tag| tag.address == address))
+        {
+            *slot = None;
+        }
+    });
+}
+
+/// Snapshots the page allocator's live allocations as the checkpoint `name`, replacing any
+/// checkpoint with that name.
+pub fn checkpoint(name: &str) -> Result<(), KernelError> {
+    if name.is_empty() || name.len() > MAX_NAME_LEN {
+        return Err(KernelError::InvalidArgument {
+            reason: "checkpoint names must be 1 to 16 bytes long",
+        });
+    }
+
+    without_interrupts(|| {
+        // SAFETY: checkpoints are only used with interrupts masked.
+        let checkpoints = unsafe { &mut CHECKPOINTS };
+        let index = checkpoints
+            .iter()
+            .position(|checkpoint| checkpoint.is_used() && checkpoint.name() == name)
+            .or_else(|| {
+                checkpoints
+                    .iter()
+                    .position(|checkpoint| !checkpoint.is_used())
+            })
+            .ok_or(KernelError::TooManyCheckpoints {
+                max: MAX_CHECKPOINTS,
+            })?;
+
+        let checkpoint = &mut checkpoints[index];
+        checkpoint.name[..name.len()].copy_from_slice(name.as_bytes());
+        checkpoint.name_len = name.len();
+        checkpoint.len = 0;
+        checkpoint.truncated = false;
+        for allocation in live() {
+            if checkpoint.len == MAX_SNAPSHOT_LEN {
+                checkpoint.truncated = true;
+                break;
+            }
+            checkpoint.allocations[checkpoint.len] = allocation;
+            checkpoint.len += 1;
+        }
+
+        Ok(())
+    })
+}
+
+/// Calls `f` with the name of each checkpoint, how many allocations it remembers, and whether
+/// there were more than it could remember.
+pub fn checkpoints(mut f: impl FnMut(&str, usize, bool)) {
+    without_interrupts(|| {
+        // SAFETY: see checkpoint.
+        for checkpoint in unsafe { CHECKPOINTS.iter() }.filter(|checkpoint| checkpoint.is_used()) {
+            f(checkpoint.name(), checkpoint.len, checkpoint.truncated);
+        }
+    })
+}
+
+/// How many allocations [`diff`] found, and whether there could have been more.
+pub struct Diff {
+    pub leaked: usize,
+    /// Whether the checkpoint diffed to was truncated, so allocations it missed weren't reported.
+    pub partial: bool,
+}
+
+/// Calls `f` with each allocation that's in the checkpoint `to` but not in the checkpoint `from`,
+/// or that's live now but not in `from` if `to` is `None`.
+///
+/// Returns [`KernelError::CheckpointTruncated`] if `from` was truncated.
+pub fn diff(
+    from: &str,
+    to: Option<&str>,
+    mut f: impl FnMut(&Allocation),
+) -> Result<Diff, KernelError> {
+    without_interrupts(|| {
+        // SAFETY: see checkpoint.
+        let checkpoints = unsafe { &CHECKPOINTS };
+        let find = |name| {
+            checkpoints
+                .iter()
+                .find(|checkpoint| checkpoint.is_used() && checkpoint.name() == name)
+                .ok_or(KernelError::NoSuchCheckpoint)
+        };
+        let from = find(from)?;
+        if from.truncated {
+            return Err(KernelError::CheckpointTruncated {
+                max: MAX_SNAPSHOT_LEN,
+            });
+        }
+        let mut leaked = 0;
+        let mut report = |allocation: &Allocation| {
+            if !from.allocations().iter().any(|old| old.is_same(allocation)) {
+                f(allocation);
+                leaked += 1;
+            }
+        };
+
+        let partial = match to {
+            Some(to) => {
+                let to = find(to)?;
+                to.allocations().iter().for_each(&mut report);
+                to.truncated
+            }
+            None => {
+                live().for_each(|allocation| report(&allocation));
+                false
+            }
+        };
+
+        Ok(Diff { leaked, partial })
+    })
+}
+
+/// Returns the page allocator's live allocations, tagged with their owners.
+///
+/// Must be called with interrupts masked, like the allocator itself.
+fn live() -> impl Iterator<Item = Allocation> {
+    // SAFETY: the caller ensures that interrupts are masked, so nothing else is using the
+    // allocator or the tags.
+    let (allocator, tags) =
+        unsafe { (ALLOCATOR.get().expect("allocator to be initialised"), &TAGS) };
+
+    allocator.allocations().map(|allocation| {
+        let address = allocation.ptr as usize;
+        let owner = tags
+            .iter()
+            .flatten()
+            .find(|tag| tag.address == address)
+            .map(|tag| tag.owner);
+
+        Allocation {
+            address,
+            size: allocation.size,
+            owner,
+        }
+    })
+}
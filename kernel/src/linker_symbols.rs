@@ -0,0 +1,125 @@
+//! Addresses of symbols defined in linker.ld and entry.s, which mark the bounds of the kernel
+//! image and of regions within it.
+//!
+//! The symbols are `extern` statics whose values are meaningless; only their addresses matter.
+//! Taking those addresses is easy to get subtly wrong (and needs `unsafe`), so it's only done here.
+use core::arch::asm;
+use core::ptr::addr_of;
+
+use crate::addr::{PhysAddr, VirtAddr};
+
+macro_rules! virtual_symbols {
+    ($($(#[$meta:meta])* $name:ident = $symbol:ident;)+) => {
+        $(
+            $(#[$meta])*
+            pub fn $name() -> VirtAddr {
+                extern "C" {
+                    static $symbol: u8;
+                }
+
+                // SAFETY: we only take the address of the symbol, and never read from it.
+                VirtAddr::new(unsafe { addr_of!($symbol) } as usize)
+            }
+        )+
+    };
+}
+
+// The kernel runs in the higher half, so PC-relative relocations can't reach symbols with lower
+// half (physical) addresses. Instead, we load their addresses from a literal pool.
+macro_rules! physical_symbols {
+    ($($(#[$meta:meta])* $name:ident = $symbol:ident;)+) => {
+        $(
+            $(#[$meta])*
+            pub fn $name() -> PhysAddr {
+                let addr: usize;
+
+                // SAFETY: this only loads a constant from the literal pool.
+                unsafe { asm!(concat!("ldr {}, =", stringify!($symbol)), out(reg) addr) };
+
+                PhysAddr::new(addr)
+            }
+        )+
+    };
+}
+
+virtual_symbols! {
+    /// Start of the kernel image, which is the exception vector table.
+    kernel_start = _kernel_va;
+    /// End of the kernel image, including its zero-initialised and NOLOAD sections.
+    kernel_end = _ekernel_va;
+
+    /// Exception vector table, for VBAR_EL1.
+    vectors = VECTORS;
+
+    /// Storage for the page allocator's tree, which is followed by the rest of RAM.
+    buddy_alloc_tree = _buddy_alloc_tree_va;
+
+    /// Lowest address of the boot stack, which kernel_main runs on, and thus the end of everything
+    /// in the kernel image that's loaded (as opposed to NOLOAD).
+    boot_stack_bottom = _stack_va;
+    /// Initial stack pointer of the boot stack.
+    boot_stack_top = _estack_va;
+
+    /// Initial stack pointer of task1.
+    task1_stack_top = TASK1_INITIAL_SP;
+    /// Lowest address of task1's stack.
+    task1_stack_bottom = TASK1_STACK_BOTTOM;
+    /// Initial stack pointer of task1's kernel stack.
+    task1_kernel_stack_top = TASK1_KERNEL_INITIAL_SP;
+    /// Lowest address of task1's kernel stack.
+    task1_kernel_stack_bottom = TASK1_KERNEL_STACK_BOTTOM;
+    /// Initial stack pointer of task2.
+    task2_stack_top = TASK2_INITIAL_SP;
+    /// Lowest address of task2's stack.
+    task2_stack_bottom = TASK2_STACK_BOTTOM;
+    /// Initial stack pointer of task2's kernel stack.
+    task2_kernel_stack_top = TASK2_KERNEL_INITIAL_SP;
+    /// Lowest address of task2's kernel stack.
+    task2_kernel_stack_bottom = TASK2_KERNEL_STACK_BOTTOM;
+    /// Initial stack pointer of the IRQ thread.
+    irq_thread_stack_top = IRQ_THREAD_INITIAL_SP;
+    /// Lowest address of the IRQ thread's stack.
+    irq_thread_stack_bottom = IRQ_THREAD_STACK_BOTTOM;
+    /// Initial stack pointer of the IRQ thread's kernel stack.
+    irq_thread_kernel_stack_top = IRQ_THREAD_KERNEL_INITIAL_SP;
+    /// Lowest address of the IRQ thread's kernel stack.
+    irq_thread_kernel_stack_bottom = IRQ_THREAD_KERNEL_STACK_BOTTOM;
+
+    /// Start of the early initcalls, and thus of all initcalls.
+    initcall_early = _initcall_early;
+    /// Start of the arch initcalls, and end of the early initcalls.
+    initcall_arch = _initcall_arch;
+    /// Start of the driver initcalls, and end of the arch initcalls.
+    initcall_driver = _initcall_driver;
+    /// Start of the optional drivers' initcalls, and end of the driver initcalls.
+    initcall_module = _initcall_module;
+    /// Start of the late initcalls, and end of the optional drivers' initcalls.
+    initcall_late = _initcall_late;
+    /// End of the late initcalls, and thus of all initcalls.
+    initcall_end = _einitcall;
+
+    /// Start of the system call handlers.
+    syscall_start = _syscall;
+    /// End of the system call handlers.
+    syscall_end = _esyscall;
+}
+
+physical_symbols! {
+    /// Physical address of [`kernel_start`].
+    kernel_start_pa = _kernel_pa;
+    /// Physical address of [`boot_stack_top`].
+    boot_stack_top_pa = _estack_pa;
+    /// Physical address of [`buddy_alloc_tree`].
+    buddy_alloc_tree_pa = _buddy_alloc_tree_pa;
+}
+
+/// Translates a virtual address within the kernel image to its physical address.
+pub fn kernel_pa(va: VirtAddr) -> PhysAddr {
+    let (start, end) = (kernel_start(), kernel_end());
+    assert!(
+        (start..end).contains(&va),
+        "{va:?} is outside the kernel image"
+    );
+
+    PhysAddr::new(va.addr() - start.addr() + kernel_start_pa().addr())
+}
@@ -1,21 +1,285 @@
-use core::fmt::{self, Write};
+use core::fmt::Write;
 
-use crate::a53::pl011::Pl011RegisterBlock;
+use fdt::Fdt;
+use hal::{Console, Writer};
+use log::LevelFilter;
 
-pub fn init(writer: Pl011Writer, max_level: log::LevelFilter) {
+use crate::build_info::BUILD_INFO;
+use crate::error::KernelError;
+use crate::sync::without_interrupts;
+use crate::{board, cmdline, fw_cfg, gicv2, init, pl011, timer};
+
+/// Maximum number of log targets that can have a level of their own.
+const MAX_TARGET_LEVELS: usize = 8;
+
+/// Maximum number of log sinks that can be registered.
+const MAX_SINKS: usize = 4;
+
+/// Log targets of the built-in drivers. Optional drivers log to the names of their crates (see
+/// [`driver::Initcall::target`]).
+const BUILTIN_DRIVERS: [&str; 5] = [
+    fw_cfg::LOG_TARGET,
+    gicv2::LOG_TARGET,
+    pl011::LOG_TARGET,
+    board::POWER_LOG_TARGET,
+    timer::LOG_TARGET,
+];
+
+static mut LEVELS: Levels = Levels {
+    default: LevelFilter::Trace,
+    targets: [None; MAX_TARGET_LEVELS],
+};
+
+/// A registered log sink, with its name and its bit in [`abi::log_sink`].
+type Sink = (&'static str, u64, &'static mut dyn LogSink);
+
+static mut SINKS: [Option<Sink>; MAX_SINKS] = [None, None, None, None];
+
+/// Bits in [`abi::log_sink`] of the sinks the log is written to.
+static mut ACTIVE_SINKS: u64 = 0;
+
+/// Console UART sink, which writes to [`WRITER`].
+static mut UART: Uart = Uart;
+
+/// Somewhere the log can be written to, like the console UART or the ring buffer read by `dmesg`
+/// (see dmesg.rs).
+///
+/// Sinks are registered with [`register_sink`], and which of them the log goes to can be changed
+/// at runtime with [`set_sinks`].
+pub trait LogSink {
+    /// Writes `record`, which has already been checked against the level of its target.
+    fn log(&mut self, record: &log::Record);
+}
+
+/// The most verbose level logged for each target.
+struct Levels {
+    /// Level of every target without a level of its own.
+    default: LevelFilter,
+    /// Targets with a level of their own, which also applies to the modules below them.
+    targets: [Option<(&'static str, LevelFilter)>; MAX_TARGET_LEVELS],
+}
+
+impl Levels {
+    /// Returns the level of the most specific target that `target` is or is below, if any.
+    fn get(&self, target: &str) -> Option<LevelFilter> {
+        self.targets
+            .iter()
+            .flatten()
+            .filter(|(prefix, _)| match target.strip_prefix(prefix) {
+                Some(rest) => rest.is_empty() || rest.starts_with("::"),
+                None => false,
+            })
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|&(_, level)| level)
+    }
+}
+
+/// Starts logging to `writer`, at `max_level` and below unless a target has a level of its own.
+pub fn init(writer: Writer<&'static mut dyn Console>, max_level: LevelFilter) {
     unsafe { WRITER = Some(writer) };
+    // SAFETY: UART is only used through the sinks, which are only used with interrupts masked.
+    register_sink("uart", abi::log_sink::UART, unsafe { &mut UART })
+        .expect("the console UART to be registered once");
+    with_levels(|levels| levels.default = max_level);
     log::set_logger(&Logger).unwrap();
-    log::set_max_level(max_level);
+    // targets can be more verbose than the default, so the logger filters everything itself
+    log::set_max_level(LevelFilter::Trace);
+}
+
+/// Sets the most verbose level logged for `target` and the modules below it, or with `None`, makes
+/// it use the default level again.
+pub fn set_target_level(
+    target: &'static str,
+    level: Option<LevelFilter>,
+) -> Result<(), KernelError> {
+    with_levels(|levels| {
+        let existing = levels
+            .targets
+            .iter_mut()
+            .find(|slot| matches!(slot, Some((t, _)) if *t == target));
+        let slot = match (existing, level) {
+            (Some(slot), _) => slot,
+            (None, None) => return Ok(()),
+            (None, Some(_)) => levels
+                .targets
+                .iter_mut()
+                .find(|slot| slot.is_none())
+                .ok_or(KernelError::TooManyLogTargets {
+                    max: MAX_TARGET_LEVELS,
+                })?,
+        };
+        *slot = level.map(|level| (target, level));
+
+        Ok(())
+    })
+}
+
+/// Returns the most verbose level logged for `target`, and whether it's a level of its own rather
+/// than the default.
+pub fn target_level(target: &str) -> (LevelFilter, bool) {
+    with_levels(|levels| match levels.get(target) {
+        Some(level) => (level, true),
+        None => (levels.default, false),
+    })
+}
+
+/// Registers `sink` under `name`, for its `bit` in [`abi::log_sink`], and starts writing the log to
+/// it.
+pub fn register_sink(
+    name: &'static str,
+    bit: u64,
+    sink: &'static mut dyn LogSink,
+) -> Result<(), KernelError> {
+    with_sinks(|sinks, active| {
+        if sinks
+            .iter()
+            .flatten()
+            .any(|&(n, b, _)| n == name || b == bit)
+        {
+            return Err(KernelError::InvalidArgument {
+                reason: "log sink is already registered",
+            });
+        }
+        let slot = sinks
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(KernelError::TooManyLogSinks { max: MAX_SINKS })?;
+        *slot = Some((name, bit, sink));
+        *active |= bit;
+
+        Ok(())
+    })
+}
+
+/// Returns the bits in [`abi::log_sink`] of the sinks the log is written to.
+pub fn sinks() -> u64 {
+    with_sinks(|_, active| *active)
+}
+
+/// Writes the log to the sinks whose bits in [`abi::log_sink`] are set in `bits`, and only those,
+/// returning the bits of the sinks it was written to before.
+pub fn set_sinks(bits: u64) -> Result<u64, KernelError> {
+    with_sinks(|sinks, active| {
+        let registered = sinks
+            .iter()
+            .flatten()
+            .fold(0, |all, &(_, bit, _)| all | bit);
+        if bits & !registered != 0 {
+            return Err(KernelError::InvalidArgument {
+                reason: "no such log sink",
+            });
+        }
+
+        Ok(core::mem::replace(active, bits))
+    })
+}
+
+/// Calls `f` with the name and bit in [`abi::log_sink`] of each registered sink, and whether the
+/// log is written to it.
+pub fn for_each_sink(mut f: impl FnMut(&'static str, u64, bool)) {
+    let mut names = [None; MAX_SINKS];
+    let active = with_sinks(|sinks, active| {
+        for (name, sink) in names.iter_mut().zip(sinks.iter()) {
+            *name = sink.as_ref().map(|&(name, bit, _)| (name, bit));
+        }
+        *active
+    });
+
+    // without interrupts masked, so f can log
+    for (name, bit) in names.into_iter().flatten() {
+        f(name, bit, active & bit != 0);
+    }
+}
+
+/// Returns the log targets of every driver, built-in or optional.
+pub fn drivers() -> impl Iterator<Item = &'static str> {
+    let modules = init::modules();
+    let optional = modules
+        .iter()
+        .enumerate()
+        // an optional driver may have more than one initcall
+        .filter(move |&(i, m)| modules[..i].iter().all(|n| n.target != m.target))
+        .map(|(_, m)| m.target);
+
+    BUILTIN_DRIVERS.into_iter().chain(optional)
+}
+
+fn with_levels<R>(f: impl FnOnce(&mut Levels) -> R) -> R {
+    // SAFETY: the levels are only accessed with interrupts masked, and thus by one caller at once.
+    without_interrupts(|| f(unsafe { &mut LEVELS }))
+}
+
+fn with_sinks<R>(f: impl FnOnce(&mut [Option<Sink>; MAX_SINKS], &mut u64) -> R) -> R {
+    // SAFETY: the sinks are only accessed with interrupts masked, and thus by one caller at once.
+    without_interrupts(|| f(unsafe { &mut SINKS }, unsafe { &mut ACTIVE_SINKS }))
 }
 
+/// Logs to the console UART, which is the one named by `console=` on the command line (see
+/// [`pl011::find`]), otherwise the one named by `stdout-path` in /chosen, otherwise the first.
+///
+/// Logs at `loglevel=` on the command line and below, or every level by default.
+fn init_console(_fdt: &Fdt) -> Result<(), KernelError> {
+    pl011::probe();
+    let requested = cmdline::option("console").map(|name| (name, pl011::find(name)));
+    let index = requested
+        .and_then(|(_, index)| index)
+        .or_else(pl011::stdout)
+        .unwrap_or(0);
+
+    let uart = pl011::claim(index, "console")?;
+    let requested_level = cmdline::option("loglevel").map(|name| (name, name.parse().ok()));
+    let level = requested_level
+        .and_then(|(_, level)| level)
+        .unwrap_or(LevelFilter::Trace);
+    // SAFETY: initcalls only run once, and the console is only found here.
+    init(Writer(unsafe { board::console(uart.base) }), level);
+    log::info!("{BUILD_INFO}");
+    log::info!("board: {}", board::NAME);
+
+    if let Some((name, None)) = requested {
+        log::warn!("console={name} is not a UART, so using ttyAMA{index}");
+    }
+    if let Some((name, None)) = requested_level {
+        log::warn!("loglevel={name} is not a level, so logging at {level}");
+    }
+    log::debug!("console on ttyAMA{index} at {:p}", uart.base);
+
+    Ok(())
+}
+initcall!(early, init_console);
+
 struct Logger;
 
 impl log::Log for Logger {
-    fn enabled(&self, _metadata: &log::Metadata) -> bool {
-        unsafe { WRITER.is_some() }
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        let writing = sinks() != 0;
+
+        writing && metadata.level() <= target_level(metadata.target()).0
     }
 
     fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        with_sinks(|sinks, active| {
+            for (_, _, sink) in sinks
+                .iter_mut()
+                .flatten()
+                .filter(|(_, bit, _)| *active & bit != 0)
+            {
+                sink.log(record);
+            }
+        });
+    }
+
+    fn flush(&self) {}
+}
+
+/// The console UART, as a log sink, which highlights each level in its own colour.
+struct Uart;
+
+impl LogSink for Uart {
+    fn log(&mut self, record: &log::Record) {
         if let Some(writer) = unsafe { &mut WRITER } {
             let level = record.level();
             let file = record.file().unwrap_or("<unknown file>");
@@ -38,27 +302,6 @@ impl log::Log for Logger {
             .unwrap();
         }
     }
-
-    fn flush(&self) {}
-}
-
-pub static mut WRITER: Option<Pl011Writer> = None;
-
-pub struct Pl011Writer(*mut Pl011RegisterBlock);
-
-impl Pl011Writer {
-    pub fn new(base_address: *const u8) -> Self {
-        Self(base_address as *mut Pl011RegisterBlock)
-    }
 }
 
-impl fmt::Write for Pl011Writer {
-    fn write_str(&mut self, s: &str) -> Result<(), fmt::Error> {
-        let uart = unsafe { &*self.0 };
-        for byte in s.bytes() {
-            uart.dr.write_initial(|w| w.data(byte));
-        }
-
-        Ok(())
-    }
-}
+pub static mut WRITER: Option<Writer<&'static mut dyn Console>> = None;
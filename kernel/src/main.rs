@@ -1,5 +1,7 @@
 #![no_std]
 #![no_main]
+#![feature(asm_const)]
+#![feature(offset_of)]
 #![feature(panic_info_message)]
 #![deny(clippy::undocumented_unsafe_blocks)]
 
@@ -26,63 +28,174 @@ macro_rules! write_special_reg {
     };
 }
 
+/// Registers a function to be run at boot by [`init::run_all`], at a level given by one of
+/// `early`, `arch`, `driver` or `late` (see [`init::Level`]).
+///
+/// The function must be an [`init::InitFn`]. An unknown level places the initcall in a section
+/// that linker.ld doesn't know about, which fails the build.
+macro_rules! initcall {
+    ($level:ident, $function:path) => {
+        const _: () = {
+            #[used]
+            #[link_section = concat!(".initcall.", stringify!($level))]
+            static INITCALL: $crate::init::Initcall = $crate::init::Initcall {
+                name: concat!(module_path!(), "::", stringify!($function)),
+                function: $function,
+            };
+        };
+    };
+}
+
+/// Registers a function as the handler for a system call, given its number from [`abi::syscall`],
+/// which [`syscall::dispatch`] calls for `svc` with that immediate.
+///
+/// The function must be a [`syscall::Handler`]. A number that isn't in [`abi::syscall::ALL`] fails
+/// the build, and a number with more than one handler fails the boot (see `syscall::init`).
+macro_rules! syscall {
+    ($number:path, $function:path) => {
+        const _: () = {
+            assert!(
+                abi::syscall::is_known($number),
+                concat!(stringify!($number), " is not in abi::syscall::ALL")
+            );
+
+            #[used]
+            #[link_section = ".syscall"]
+            static SYSCALL: $crate::syscall::Syscall = $crate::syscall::Syscall {
+                number: $number,
+                name: stringify!($function),
+                function: $function,
+            };
+        };
+    };
+}
+
 mod a53;
+mod addr;
+mod address_space;
+mod alarm;
+mod alignment;
+#[cfg(feature = "board-rpi4")]
+mod bcm2835_pm;
+mod board;
+mod boot_check;
+mod build_info;
+mod cmdline;
+mod console;
+mod dmesg;
+mod entropy;
+mod error;
+mod fuzz;
+mod fw_cfg;
 mod gicv2;
+mod hexdump;
+mod init;
+mod irq;
+mod leak;
+mod linker_symbols;
 mod logging;
+mod mm;
+mod nesting;
+mod pipe;
+mod pl011;
+mod pmu;
+mod probe;
+mod profile;
+#[cfg(not(feature = "board-rpi4"))]
+mod psci;
+mod reclaim;
 mod reg;
 mod scheduler;
+mod selftest;
+mod semihosting;
+mod signal;
+mod stats;
+mod suspend;
 mod sync;
+mod syscall;
 mod task;
+mod timer;
+mod trace;
 mod tt;
+mod tty;
+mod units;
+mod vector_check;
+mod virtio;
+mod watchpoint;
+
+// optional drivers, which register themselves with `driver::initcall!`
+include!(concat!(env!("OUT_DIR"), "/drivers.rs"));
 
 use core::arch::{asm, global_asm};
 use core::fmt::Write;
 use core::panic::PanicInfo;
-use core::ptr::null;
+use core::{mem, slice};
 
-use allocator::Allocator;
+use allocator::{Allocator, RegionAllocator};
+use bootinfo::BootInfo;
+use buddy_alloc::tree::Placement;
+use fdt::Fdt;
+use hal::{Completion, InterruptController, Timer};
 use scheduler::Scheduler;
 use task::Context;
 
-use crate::gicv2::InterruptId;
-use crate::logging::Pl011Writer;
+use crate::addr::PhysAddr;
+use crate::error::KernelError;
+use crate::hexdump::Hexdump;
+use crate::nesting::{Kind, Source};
 use crate::sync::OnceCell;
-use crate::tt::page::PageBox;
-use crate::tt::table::TranslationTable;
-use crate::tt::Level0;
+use crate::units::{HexRange, HumanSize};
 // use crate::tt::{PageBox, TranslationTable};
 
-global_asm!(include_str!("entry.s"), options(raw));
-
-extern "C" {
-    static VECTORS: [u8; 0x800];
-}
-
-// TODO starting with the incorrect values seems bad, is this bad?
-static mut TIMER_INTERRUPT: InterruptId = InterruptId::spurious();
-static mut GICD: gicv2::Distributor = gicv2::Distributor::new(null());
-static mut GICC: gicv2::CpuInterface = gicv2::CpuInterface::new(null());
+// the offsets of the fields of `Context` are computed here, so entry.s can't disagree with them,
+// as are the addresses that depend on the board
+global_asm!(
+    ".equ CONTEXT_SIZE, {size}",
+    ".equ CONTEXT_X0, {x0}",
+    ".equ CONTEXT_X30, {x30}",
+    ".equ CONTEXT_PC, {pc}",
+    ".equ EARLY_UART, {early_uart}",
+    ".equ DEVICE_BLOCK, {device_block}",
+    include_str!("entry.s"),
+    size = const Context::SIZE,
+    x0 = const Context::OFFSET_X0,
+    x30 = const Context::OFFSET_X30,
+    pc = const Context::OFFSET_PC,
+    early_uart = const board::EARLY_UART,
+    device_block = const board::DEVICE_BLOCK,
+);
+
+static mut BOOT_INFO: OnceCell<BootInfo<'static>> = OnceCell::new();
 static mut SCHEDULER: OnceCell<Scheduler> = OnceCell::new();
-static mut ALLOCATOR: OnceCell<Allocator> = OnceCell::new();
+static mut ALLOCATOR: OnceCell<RegionAllocator<{ memory_map::MAX_REGIONS }>> = OnceCell::new();
 
 #[no_mangle]
-unsafe extern "C" fn vector_el1_sp0_synchronous() {
+unsafe extern "C" fn vector_el1_sp0_synchronous(context: *const Context) -> *const Context {
+    let _nesting = nesting::enter(Kind::Synchronous, Source::El1);
     log::trace!("vector_el1_sp0_synchronous");
-    panic_on_synchronous_or_serror(b'A');
+    check_context(context, "entry");
+    check_context(signal::deliver(handle_synchronous(context, b'A')), "exit")
 }
 
 #[no_mangle]
-unsafe extern "C" fn vector_el1_sp0_irq() {
+unsafe extern "C" fn vector_el1_sp0_irq(context: *const Context) -> *const Context {
+    let _nesting = nesting::enter(Kind::Irq, Source::El1);
     log::trace!("vector_el1_sp0_irq");
+    check_context(context, "entry");
+    check_context(signal::deliver(handle_irq(context)), "exit")
 }
 
 #[no_mangle]
-unsafe extern "C" fn vector_el1_sp0_fiq() {
+unsafe extern "C" fn vector_el1_sp0_fiq(context: *const Context) -> *const Context {
+    let _nesting = nesting::enter(Kind::Fiq, Source::El1);
     log::trace!("vector_el1_sp0_fiq");
+    check_context(context, "entry");
+
+    context
 }
 
 #[no_mangle]
-unsafe extern "C" fn vector_el1_sp0_serror() {
+unsafe extern "C" fn vector_el1_sp0_serror(_context: *const Context) -> *const Context {
     log::trace!("vector_el1_sp0_serror");
     panic_on_synchronous_or_serror(b'D');
 }
@@ -110,36 +223,26 @@ unsafe extern "C" fn vector_el1_sp1_serror(_context: *const Context) -> *const C
 }
 
 #[no_mangle]
-unsafe extern "C" fn vector_el0_a64_synchronous(_context: *const Context) -> *const Context {
+unsafe extern "C" fn vector_el0_a64_synchronous(context: *const Context) -> *const Context {
+    let _nesting = nesting::enter(Kind::Synchronous, Source::El0);
     log::trace!("vector_el0_a64_synchronous");
-    panic_on_synchronous_or_serror(b'I');
+    check_context(context, "entry");
+    check_context(signal::deliver(handle_synchronous(context, b'I')), "exit")
 }
 
 #[no_mangle]
-unsafe extern "C" fn vector_el0_a64_irq(mut context: *const Context) -> *const Context {
+unsafe extern "C" fn vector_el0_a64_irq(context: *const Context) -> *const Context {
+    let _nesting = nesting::enter(Kind::Irq, Source::El0);
     log::trace!("vector_el0_a64_irq");
-    log::debug!("{:?}", *context);
-
-    GICC.handle(|cpuid, interrupt_id| {
-        log::trace!("elx_irq cpuid = {cpuid}, interrupt_id = {interrupt_id:?}");
-        match interrupt_id {
-            x if x == TIMER_INTERRUPT => {
-                write_special_reg!("CNTP_TVAL_EL0", read_special_reg!("CNTFRQ_EL0") / 10);
-
-                if let Some(scheduler) = SCHEDULER.get_mut() {
-                    context = scheduler.schedule().context();
-                }
-            }
-            _ => {}
-        }
-    });
-
-    context
+    check_context(context, "entry");
+    check_context(signal::deliver(handle_irq(context)), "exit")
 }
 
 #[no_mangle]
 unsafe extern "C" fn vector_el0_a64_fiq(context: *const Context) -> *const Context {
+    let _nesting = nesting::enter(Kind::Fiq, Source::El0);
     log::trace!("vector_el0_a64_fiq");
+    check_context(context, "entry");
 
     context
 }
@@ -172,6 +275,119 @@ unsafe extern "C" fn vector_el0_a32_serror() {
     panic_on_synchronous_or_serror(b'P');
 }
 
+/// Checks, in debug builds, that a context being saved or restored by entry.s is on the running
+/// task's kernel stack (see [`Scheduler::check_context`]), returning it.
+///
+/// # Safety
+///
+/// Must only be called from an exception handler, where the scheduler can't be used concurrently.
+unsafe fn check_context(context: *const Context, when: &str) -> *const Context {
+    if cfg!(debug_assertions) {
+        if let Some(scheduler) = SCHEDULER.get() {
+            scheduler.check_context(context, when);
+        }
+    }
+
+    context
+}
+
+/// Handles a synchronous exception taken from a task, returning the context of the task to switch
+/// to.
+unsafe fn handle_synchronous(context: *const Context, kind: u8) -> *const Context {
+    // TODO migrate to SystemRegister api
+    let syndrome = read_special_reg!("ESR_EL1");
+    let exception_class = syndrome >> 26 & 0x3F;
+    match exception_class {
+        // SVC instruction execution in AArch64 state, with the immediate in ISS[15:0]
+        0x15 => syscall::handle(syndrome as u16, context),
+        // instruction or data abort from a lower or the same exception level, with an access flag
+        // fault (IFSC/DFSC 0b0010LL) at level 1, 2 or 3
+        0x20 | 0x21 | 0x24 | 0x25
+            if matches!(syndrome & 0x3F, 0b001001..=0b001011)
+                && tt::handle_access_flag_fault(read_special_reg!("FAR_EL1") as usize) =>
+        {
+            context
+        }
+        // BRK instruction execution in AArch64 state, with the immediate in ISS[15:0], from the
+        // boot-time vector check
+        0x3C if syndrome as u16 == vector_check::BRK_IMMEDIATE => {
+            vector_check::handle(&mut *(context as *mut Context), kind);
+            context
+        }
+        // watchpoint from a lower or the same exception level
+        0x34 | 0x35 => {
+            watchpoint::handle(&*context, syndrome);
+            context
+        }
+        _ if alignment::is_fault(syndrome) => {
+            alignment::report(&*context, syndrome);
+            crash_dump(&*context);
+            panic_on_synchronous_or_serror(kind)
+        }
+        _ => {
+            crash_dump(&*context);
+            panic_on_synchronous_or_serror(kind)
+        }
+    }
+}
+
+/// Logs the state of a task that took an unexpected exception, including the memory around its
+/// program counter and stack pointer.
+fn crash_dump(context: &Context) {
+    // TODO migrate to SystemRegister api
+    // SAFETY: reading FAR_EL1 has no side effects.
+    let far = unsafe { read_special_reg!("FAR_EL1") };
+    log::error!("crash dump (FAR_EL1 = {far:016X}h)\n{context:?}");
+
+    for (name, address, before) in [("pc", context.pc(), 16), ("sp", context.sp(), 0)] {
+        let start = address.saturating_sub(before) & !0xF;
+        match Hexdump::memory(start, 64) {
+            Ok(hexdump) => log::error!("memory at {name}:\n{hexdump}"),
+            Err(error) => log::error!("memory at {name}: {error}"),
+        }
+    }
+}
+
+/// Handles an IRQ taken from a task, returning the context of the task to switch to.
+unsafe fn handle_irq(mut context: *const Context) -> *const Context {
+    let entry = board::timer().now();
+    log::debug!("{:?}", *context);
+
+    board::interrupt_controller().handle(|interrupt_id| {
+        stats::IRQS.increment();
+        if let Some(count) = stats::INTERRUPTS.get(interrupt_id.value()) {
+            count.increment();
+        }
+        log::trace!("elx_irq interrupt_id = {interrupt_id:?}");
+        match interrupt_id {
+            x if timer::is_interrupt(x) => {
+                // the deadline still holds the one we set, until we set the next one
+                stats::TIMER_LATENCY.record_between(board::timer().deadline(), entry);
+
+                timer::tick();
+
+                if let Some(scheduler) = SCHEDULER.get_mut() {
+                    context = scheduler.schedule().context();
+                }
+
+                stats::TIMER_TICKS.increment();
+                virtio::rng::tick();
+
+                Completion::Deactivate
+            }
+            x if profile::is_interrupt(x) => {
+                let task = SCHEDULER.get_mut().map(|scheduler| scheduler.current_id());
+                profile::sample(&*context, task);
+
+                Completion::Deactivate
+            }
+            x => irq::dispatch(x, entry),
+        }
+    });
+
+    context
+}
+
 fn panic_on_synchronous_or_serror(kind: u8) -> ! {
     // TODO get rid of these kind codes, no need to call this from asm
     let kind = match kind {
@@ -191,6 +407,9 @@ fn panic_on_synchronous_or_serror(kind: u8) -> ! {
     let reason = match exception_class {
         0x00 => Some("Unknown reason"),
         0x15 => Some("SVC instruction execution in AArch64 state"),
+        0x22 => Some("PC alignment fault"),
+        0x24 | 0x25 if alignment::is_fault(syndrome) => Some("Data Abort (alignment fault)"),
+        0x26 => Some("SP alignment fault"),
         _ => None,
     };
     if let Some(reason) = reason {
@@ -240,11 +459,144 @@ fn on_panic(info: &PanicInfo) -> ! {
             write!(writer, "<no message>").ignore();
         }
         write!(writer, "\n\n").ignore();
+
+        let nesting = nesting::current();
+        if nesting.depth() > 0 {
+            writeln!(writer, "while handling {nesting}\n").ignore();
+        }
+
+        let mut header = false;
+        mm::memory_map(|range, state| {
+            if !mem::replace(&mut header, true) {
+                writeln!(writer, "memory map:").ignore();
+            }
+            writeln!(writer, "    {range} ({}) {state}", range.size()).ignore();
+        });
+
+        // the trees are much longer than the memory map, so they're only written if asked for
+        mm::allocator_trees(|range, tree| {
+            if cmdline::option("panictree") == Some("on") {
+                writeln!(writer, "page allocator tree for {range}, in pages:\n{tree}").ignore();
+            }
+        });
+    }
+
+    // there's nobody watching a selftest kernel, so tell QEMU to exit rather than hanging
+    if cfg!(feature = "selftest") {
+        semihosting::exit(101);
     }
 
     loop {}
 }
 
+/// Points VBAR_EL1 at the exception vector table.
+fn init_vectors(_fdt: &Fdt) -> Result<(), KernelError> {
+    // SAFETY: the vector table is defined in entry.s, and is aligned as required by VBAR_EL1.
+    unsafe {
+        asm!(
+            "msr VBAR_EL1, {}",
+            "isb",
+            in(reg) linker_symbols::vectors().addr(),
+        )
+    };
+    vector_check::run();
+
+    Ok(())
+}
+initcall!(arch, init_vectors);
+
+/// Sets up the page allocator to manage every region of RAM in the devicetree, except the start
+/// of RAM up to the end of the kernel image.
+fn init_allocator(_fdt: &Fdt) -> Result<(), KernelError> {
+    let mut map = boot_info().memory.clone();
+    // the FDT, the translation table pool (see tt::page) and the kernel image are all below here
+    let image_len = linker_symbols::kernel_end().addr() - linker_symbols::kernel_start().addr();
+    map.reserve(0..linker_symbols::kernel_start_pa().addr() + image_len)?;
+    for region in map.regions() {
+        let region = HexRange::from(region);
+        log::debug!("usable memory: {region} ({})", region.size());
+    }
+
+    // each region's tree goes in the kernel image, so the region's pages can stay unmapped until
+    // they're allocated (see mm::alloc_pages)
+    let trees = linker_symbols::buddy_alloc_tree();
+    let trees_len = linker_symbols::kernel_end().addr() - trees.addr();
+    // SAFETY: the space for the trees is reserved in linker.ld, page-aligned, and nothing else
+    // uses it.
+    let mut trees = unsafe {
+        slice::from_raw_parts_mut(
+            trees.as_ptr::<usize>() as *mut usize,
+            trees_len / mem::size_of::<usize>(),
+        )
+    };
+
+    // which free pages each allocation comes from can be changed with placement=, to experiment
+    // with fragmentation
+    let requested = cmdline::option("placement").map(|name| {
+        let placement = match name {
+            "first-fit" => Some(Placement::FirstFit),
+            "best-fit" => Some(Placement::BestFit),
+            "top-down" => Some(Placement::TopDown),
+            _ => None,
+        };
+        (name, placement)
+    });
+    let placement = requested
+        .and_then(|(_, placement)| placement)
+        .unwrap_or_default();
+    if let Some((name, None)) = requested {
+        log::warn!(
+            "placement={name} is not first-fit, best-fit or top-down, so using {placement:?}"
+        );
+    }
+
+    let mut allocator = RegionAllocator::new();
+    for region in map.regions() {
+        // the allocator's pages must be whole translation granules to be mapped
+        let start = region.start.next_multiple_of(tt::PAGE_SIZE);
+        let end = region.end & !(tt::PAGE_SIZE - 1);
+        if start >= end {
+            continue;
+        }
+        let tree_len = Allocator::tree_len((end - start) / allocator::PAGE_SIZE);
+        let tree_words = tree_len / mem::size_of::<usize>();
+        if tree_words > trees.len() {
+            log::warn!(
+                "no space for a {} page allocator tree for {}, so it won't be used",
+                HumanSize(tree_len),
+                HexRange::from(region),
+            );
+            continue;
+        }
+        log::info!(
+            "page allocator for {} ({}), with a {} tree and {placement:?} placement",
+            HexRange(start, end),
+            HumanSize(end - start),
+            HumanSize(tree_len),
+        );
+
+        let (tree, rest) = mem::take(&mut trees).split_at_mut(tree_words);
+        trees = rest;
+        let start = mm::ram_va(PhysAddr::new(start)).as_ptr();
+        let end = mm::ram_va(PhysAddr::new(end)).as_ptr();
+        allocator.add(Allocator::with_tree(tree, start, end).with_placement(placement))?;
+    }
+
+    // SAFETY: initcalls run before anything else uses the allocator.
+    unsafe { dbg!(ALLOCATOR.get_or_init(|| allocator)) };
+    mm::trace_regions();
+    mm::memory_map(|range, state| log::debug!("{state} memory: {range} ({})", range.size()));
+
+    Ok(())
+}
+initcall!(arch, init_allocator);
+
+/// Returns what was found in the devicetree at boot (see [`bootinfo`]).
+pub fn boot_info() -> &'static BootInfo<'static> {
+    // SAFETY: BOOT_INFO is only written by kernel_main, before the initcalls run.
+    unsafe { BOOT_INFO.get() }.expect("boot info to be found before the initcalls run")
+}
+
 #[no_mangle]
 pub extern "C" fn kernel_main() {
     // SAFETY: QEMU loads a FDT at the base of memory (0x4000_0000) for non-Linux images (e.g. ELFs)
@@ -256,36 +608,16 @@ pub extern "C" fn kernel_main() {
     // (hopefully) does not the FDT magic value.
     //
     // See https://qemu-project.gitlab.io/qemu/system/arm/virt.html#hardware-configuration-information-for-bare-metal-programming.
-    let fdt = unsafe { fdt::Fdt::from_ptr(0x4000_0000 as *const u8).unwrap() };
-
-    let uart0 = fdt.find_compatible(&["arm,pl011"]).unwrap();
-    let uart0 = uart0.reg().unwrap().next().unwrap();
-    let uart0 = Pl011Writer::new(uart0.starting_address);
-    logging::init(uart0, log::LevelFilter::Trace);
-
-    extern "C" {
-        static _kernel_va: u8;
-        static _kernel_pa: u8;
-        static _ekernel_va: u8;
-    }
-
-    // TODO: PageBox
-    let mut tt = PageBox::new(TranslationTable::<Level0>::new());
-
-    // annoying: relocation fails (out of range) when we try and use the PA like we do the VAs below
-    let pa: usize;
-    unsafe { asm!("ldr {}, =_kernel_pa", out(reg) pa) };
+    //
+    // A Raspberry Pi's firmware is told to load its FDT there too, by the config.txt written by
+    // `cargo xtask build --board rpi4`.
+    let fdt = unsafe { Fdt::from_ptr(0x4000_0000 as *const u8).unwrap() };
+    // like the FDT itself, we can't boot without knowing what's in it, and this runs before the
+    // console is up, so there's nobody to report a malformed devicetree to
+    // SAFETY: nothing reads BOOT_INFO until the initcalls run.
+    unsafe { BOOT_INFO.get_or_init(|| BootInfo::new(&fdt).unwrap()) };
 
-    tt.map_contiguous(
-        unsafe { &_kernel_va } as *const _ as usize,
-        unsafe { &_ekernel_va } as *const _ as usize,
-        pa,
-        "rx",
-    );
-
-    unsafe {
-        asm!("msr TTBR1_EL1, {:x}", "dsb sy", in(reg) tt.addr().addr());
-    }
+    init::run_all(&fdt);
 
     log::error!("error woof");
     log::warn!("warn woof");
@@ -295,54 +627,9 @@ pub extern "C" fn kernel_main() {
 
     log::debug!("woof!!!! wraaaooo!!");
 
-    // enable timer interrupts
-    unsafe {
-        log::debug!("CNTFRQ_EL0 = {:016X}h", read_special_reg!("CNTFRQ_EL0"));
-        write_special_reg!("CNTP_CTL_EL0", 1u64);
-    }
-
-    let timer = fdt.find_compatible(&["arm,armv8-timer"]).unwrap();
-    let timer_interrupts = timer.property("interrupts").unwrap().value;
-    let mut timer_interrupts = gicv2::InterruptSpecifier::interrupts_iter(timer_interrupts);
-    unsafe { TIMER_INTERRUPT = timer_interrupts.nth(1).unwrap().interrupt_id().unwrap() };
-
-    let gic = fdt.find_compatible(&["arm,cortex-a15-gic"]).unwrap();
-    let mut gic = gic.reg().unwrap();
-    unsafe {
-        GICD = gicv2::Distributor::new(gic.next().unwrap().starting_address);
-        GICD.enable();
-
-        // TODO document this, is it the virt or the non-secure phys?
-        // https://github.com/torvalds/linux/blob/90b0c2b2edd1adff742c621e246562fbefa11b70/Documentation/devicetree/bindings/timer/arm%2Carch_timer.yaml#L44-L58
-        GICD.enable_interrupt(TIMER_INTERRUPT);
-
-        GICC = gicv2::CpuInterface::new(gic.next().unwrap().starting_address);
-        GICC.enable();
-    }
-
-    unsafe {
-        // set up vector table base address
-        asm!("msr VBAR_EL1, {}", in(reg) &VECTORS);
-
-        SCHEDULER.get_or_init(|| Scheduler::new());
-    }
-
-    extern "C" {
-        // FIXME relocation R_AARCH64_ADR_PREL_PG_HI21 out of range:
-        // 281476054814720 is not in [-4294967296, 4294967295]; references '_buddy_alloc_tree_pa'
-        // static _buddy_alloc_tree_pa: u8;
-        // static _kernel_pa: u8;
-        static _buddy_alloc_tree_va: u8;
-    }
-    let ram = fdt.memory().regions().next().unwrap();
-    let allocator_start = unsafe { &_buddy_alloc_tree_va } as *const u8;
-    let allocator_start_pa = unsafe { allocator_start.sub(0xffff000000000000 - 0x40000000) };
-    let allocator_len = unsafe {
-        ram.size.unwrap() - allocator_start_pa.offset_from(ram.starting_address) as usize
-    };
-    let allocator_end = unsafe { (&_buddy_alloc_tree_va as *const u8).add(allocator_len) };
-    unsafe {
-        dbg!(ALLOCATOR.get_or_init(|| Allocator::new(allocator_start, allocator_end)));
+    // A selftest kernel exits once its tests have run, instead of starting the scheduler.
+    if cfg!(feature = "selftest") {
+        selftest::run_all();
     }
 
     // Permanently transfer control to the scheduler.
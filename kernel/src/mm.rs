@@ -0,0 +1,402 @@
+//! Memory management that needs both the page allocator and the translation tables.
+use core::arch::asm;
+use core::ops::Range;
+use core::panic::Location;
+use core::{mem, ptr};
+
+use allocator::{Allocator, RegionAllocator};
+use buddy_alloc::tree::{Ascii, FreeError, Placement, RegionState};
+use trace_format::Event;
+
+use crate::addr::{PhysAddr, VirtAddr};
+use crate::error::KernelError;
+use crate::sync::without_interrupts;
+use crate::units::{HexRange, HumanSize};
+use crate::{leak, linker_symbols, nesting, stats, trace, tt, ALLOCATOR};
+
+/// Kernel virtual addresses for [`valloc`], which are far away from both the kernel image and
+/// the RAM it maps with [`alloc_pages`].
+pub const VALLOC_AREA: Range<usize> = 0xFFFF_C000_0000_0000..0xFFFF_C000_0800_0000;
+
+/// Allocates virtual addresses in [`VALLOC_AREA`], in pages of [`allocator::PAGE_SIZE`].
+static mut VALLOC: Option<Allocator> = None;
+
+/// Tree for [`VALLOC`], which is enough for the 32768 pages in [`VALLOC_AREA`] (see
+/// [`Allocator::tree_len`]).
+static mut VALLOC_TREE: [usize; 0x600] = [0; 0x600];
+
+/// Copies `code` into newly allocated pages and makes them executable, returning the address of
+/// the copy.
+///
+/// The pages are writable and execute-never while the code is copied, then change straight to
+/// read-only and executable, so they're never writable and executable at once. The pages are
+/// never freed.
+#[allow(dead_code)]
+#[track_caller]
+pub fn alloc_executable(code: &[u8]) -> Result<VirtAddr, KernelError> {
+    if code.is_empty() {
+        return Err(KernelError::InvalidArgument {
+            reason: "code must not be empty",
+        });
+    }
+
+    let len = (code.len() + tt::PAGE_SIZE - 1) & !(tt::PAGE_SIZE - 1);
+    let va = alloc_pages(len)?.addr();
+
+    // SAFETY: the pages were just allocated, and mapped writable.
+    unsafe { ptr::copy_nonoverlapping(code.as_ptr(), va as *mut u8, code.len()) };
+    sync_instruction_cache(va, code.len());
+    tt::protect(va..va + len, "rx")?;
+
+    Ok(VirtAddr::new(va))
+}
+
+/// Allocates `len` bytes of newly mapped, writable memory, which must be a whole number of
+/// translation granules, returning its address. [`free_pages`] frees them.
+#[track_caller]
+pub fn alloc_pages(len: usize) -> Result<VirtAddr, KernelError> {
+    debug_assert_eq!(len % tt::PAGE_SIZE, 0);
+
+    let va = alloc_ram(len, Location::caller())?;
+    tt::map(va.addr(), ram_pa(va).addr(), len, "rw")?;
+
+    Ok(va)
+}
+
+/// Unmaps and frees memory allocated by [`alloc_pages`], given the address and the `len` it was
+/// allocated with.
+pub fn free_pages(va: VirtAddr, len: usize) -> Result<(), KernelError> {
+    debug_assert_eq!(len % tt::PAGE_SIZE, 0);
+
+    for page in (va.addr()..va.addr() + len).step_by(tt::PAGE_SIZE) {
+        tt::unmap(page)?;
+    }
+
+    free_ram(va, len)
+}
+
+/// Memory allocated by [`alloc_pages`], which is freed when dropped, so it can't leak when
+/// something after the allocation fails. [`Self::keep`] keeps it once nothing else can.
+pub struct Pages {
+    va: VirtAddr,
+    len: usize,
+}
+
+impl Pages {
+    /// Allocates `len` bytes like [`alloc_pages`].
+    #[track_caller]
+    pub fn alloc(len: usize) -> Result<Self, KernelError> {
+        Ok(Self {
+            va: alloc_pages(len)?,
+            len,
+        })
+    }
+
+    /// Returns the address of the memory.
+    pub fn addr(&self) -> VirtAddr {
+        self.va
+    }
+
+    /// Keeps the memory rather than freeing it, returning its address.
+    pub fn keep(self) -> VirtAddr {
+        let va = self.va;
+        mem::forget(self);
+
+        va
+    }
+}
+
+impl Drop for Pages {
+    fn drop(&mut self) {
+        if let Err(error) = free_pages(self.va, self.len) {
+            let range = HexRange(self.va.addr(), self.va.addr() + self.len);
+            log::warn!("failed to free {range}: {error}");
+        }
+    }
+}
+
+/// Allocates `len` bytes of writable memory that are virtually contiguous, but may be scattered
+/// across physical memory, returning its address. `len` is rounded up to a whole number of
+/// translation granules.
+///
+/// Unlike [`alloc_pages`], this only needs free pages, not a free contiguous range, so it's
+/// better for large buffers. The memory is mapped in [`VALLOC_AREA`], with an unmapped guard page
+/// after it, so overrunning it faults rather than corrupting the next allocation.
+#[track_caller]
+pub fn valloc(len: usize) -> Result<VirtAddr, KernelError> {
+    let owner = Location::caller();
+    // mapping each page can allocate translation tables, and log, which is too slow for an
+    // interrupt handler
+    nesting::assert_not_in_interrupt("valloc");
+    if len == 0 {
+        return Err(KernelError::InvalidArgument {
+            reason: "len must not be zero",
+        });
+    }
+    let len = len.next_multiple_of(tt::PAGE_SIZE);
+
+    let area = without_interrupts(|| {
+        // SAFETY: the valloc area allocator is only used with interrupts masked.
+        let allocator = unsafe { VALLOC.get_or_insert_with(new_valloc_allocator) };
+        allocator.allocate((len + tt::PAGE_SIZE) / allocator::PAGE_SIZE)
+    })?;
+    let start = area.ptr as usize;
+
+    for va in (start..start + len).step_by(tt::PAGE_SIZE) {
+        let mapped = alloc_ram(tt::PAGE_SIZE, owner).and_then(|page| {
+            tt::map(va, ram_pa(page).addr(), tt::PAGE_SIZE, "rw").map_err(|error| {
+                let _ = free_ram(page, tt::PAGE_SIZE);
+                error
+            })
+        });
+        if let Err(error) = mapped {
+            // give back the pages we did map, and the area itself
+            vfree(VirtAddr::new(start), va - start)?;
+            return Err(error);
+        }
+    }
+
+    Ok(VirtAddr::new(start))
+}
+
+/// Frees memory allocated by [`valloc`], given the address and the `len` it was allocated with.
+pub fn vfree(va: VirtAddr, len: usize) -> Result<(), KernelError> {
+    let start = va.addr();
+    if !VALLOC_AREA.contains(&start) || start % tt::PAGE_SIZE != 0 {
+        return Err(KernelError::InvalidArgument {
+            reason: "address was not returned by valloc",
+        });
+    }
+    let len = len.next_multiple_of(tt::PAGE_SIZE);
+
+    for va in (start..start + len).step_by(tt::PAGE_SIZE) {
+        if let Some(pa) = tt::unmap(va)? {
+            free_ram(ram_va(PhysAddr::new(pa)), tt::PAGE_SIZE)?;
+        }
+    }
+
+    without_interrupts(|| {
+        // SAFETY: see valloc.
+        let allocator = unsafe { VALLOC.as_mut() }.ok_or(KernelError::DoubleFree)?;
+        allocator.free(allocator::Allocation {
+            ptr: start as *mut _,
+            size: len + tt::PAGE_SIZE,
+        })?;
+
+        Ok(())
+    })
+}
+
+/// Creates the allocator for virtual addresses in [`VALLOC_AREA`], whose pages are only mapped
+/// once they're allocated.
+fn new_valloc_allocator() -> Allocator {
+    // SAFETY: this is only called once, to initialise VALLOC, which then owns the tree.
+    let tree = unsafe { &mut VALLOC_TREE };
+    let area = VALLOC_AREA.start as *const u8..VALLOC_AREA.end as *const u8;
+
+    Allocator::with_tree(tree, area.start, area.end)
+}
+
+/// Allocates `len` bytes of physically contiguous RAM, aligned to a translation granule, returning
+/// the address the page allocator uses for it (see [`ram_va`]), which isn't mapped yet.
+///
+/// The allocation is tagged with `owner` for [`leak`] reports.
+fn alloc_ram(len: usize, owner: &'static Location<'static>) -> Result<VirtAddr, KernelError> {
+    let allocation = without_interrupts(|| {
+        // SAFETY: the allocator is only used with interrupts masked.
+        let allocator = unsafe { ALLOCATOR.get_mut() }.expect("allocator to be initialised");
+        let pages = len / allocator::PAGE_SIZE;
+        let allocation = match allocator.allocate(pages) {
+            Ok(allocation) => allocation,
+            Err(error) => {
+                trace(Event::OutOfPages {
+                    pages: u32::try_from(pages).unwrap_or(u32::MAX),
+                });
+                log::warn!("out of memory for {} ({pages} pages)", HumanSize(len));
+                for region in allocator.regions() {
+                    let stats = region.stats();
+                    let heap = region.heap();
+                    let start = ram_pa(VirtAddr::new(heap.start as usize)).addr();
+                    let end = ram_pa(VirtAddr::new(heap.end as usize)).addr();
+                    log::warn!(
+                        "{} free in {}, but no more than {} at once ({} in a row)",
+                        HumanSize(stats.free_blocks * allocator::PAGE_SIZE),
+                        HexRange(start, end),
+                        HumanSize(stats.largest_free_block() * allocator::PAGE_SIZE),
+                        HumanSize(stats.largest_free_run * allocator::PAGE_SIZE),
+                    );
+                }
+                return Err(error.into());
+            }
+        };
+        let (region, offset) = trace_location(allocator, allocation.ptr as usize);
+        trace(Event::PagesAllocated {
+            region,
+            pages: (allocation.size / allocator::PAGE_SIZE) as u32,
+            offset,
+        });
+        if allocation.ptr as usize % tt::PAGE_SIZE != 0 {
+            let address = allocation.ptr as usize;
+            allocator.free(allocation)?;
+            trace(Event::PagesFreed { region, offset });
+            return Err(KernelError::Misaligned { address });
+        }
+
+        stats::PAGES_ALLOCATED.add((allocation.size / allocator::PAGE_SIZE) as u64);
+        leak::tag(allocation.ptr as usize, owner);
+
+        Ok(allocation)
+    })?;
+
+    Ok(VirtAddr::new(allocation.ptr as usize))
+}
+
+/// Frees `len` bytes of RAM allocated by [`alloc_ram`].
+fn free_ram(va: VirtAddr, len: usize) -> Result<(), KernelError> {
+    without_interrupts(|| {
+        // SAFETY: see alloc_ram.
+        let allocator = unsafe { ALLOCATOR.get_mut() }.expect("allocator to be initialised");
+        let (region, offset) = trace_location(allocator, va.addr());
+        let result = allocator.free(allocator::Allocation {
+            ptr: va.addr() as *mut _,
+            size: len,
+        });
+        // a double free is a bug in the caller, but freeing something that was never allocated
+        // suggests that the caller's record of its allocations is corrupt
+        if let Err(error) = result {
+            let range = HexRange(va.addr(), va.addr() + len);
+            match error {
+                FreeError::DoubleFree => log::warn!("double free of {range}"),
+                FreeError::OutOfRange => {
+                    log::error!("free of {range}, which is outside the page allocator")
+                }
+                FreeError::InsideAllocation => {
+                    log::error!("free of {range}, which is inside an allocation")
+                }
+                FreeError::Reserved => log::error!("free of {range}, which is reserved"),
+            }
+        }
+        result?;
+
+        trace(Event::PagesFreed { region, offset });
+        stats::PAGES_FREED.add((len / allocator::PAGE_SIZE) as u64);
+        leak::untag(va.addr());
+
+        Ok(())
+    })
+}
+
+/// Records each region of the page allocator in its trace, so the allocations traced after it can
+/// be replayed on the host, with `buddy-alloc replay` (see kernel/crates/buddy-alloc/src/main.rs).
+pub fn trace_regions() {
+    // SAFETY: see memory_map.
+    let Some(allocator) = (unsafe { ALLOCATOR.get() }) else {
+        return;
+    };
+
+    for (region, allocator) in allocator.regions().enumerate() {
+        let heap = allocator.heap();
+        trace(Event::PageAllocator {
+            region: region as u8,
+            placement: match allocator.placement() {
+                Placement::FirstFit => trace_format::Placement::FirstFit,
+                Placement::BestFit => trace_format::Placement::BestFit,
+                Placement::TopDown => trace_format::Placement::TopDown,
+            },
+            pages: ((heap.end as usize - heap.start as usize) / allocator::PAGE_SIZE) as u32,
+        });
+    }
+}
+
+/// Returns the region of the page allocator that `va` is in, and the offset of its page from the
+/// start of that region in pages, as they're recorded in the trace.
+fn trace_location<const N: usize>(allocator: &RegionAllocator<N>, va: usize) -> (u8, u32) {
+    // an address outside every region can't be freed, so it'll fail before it's traced
+    let (region, offset) = allocator.locate(va as *const u8).unwrap_or((0, 0));
+
+    (region as u8, offset as u32)
+}
+
+/// Records a page allocator event in the trace.
+fn trace(event: Event) {
+    // SAFETY: the page allocator is only used with interrupts masked, so its events are never
+    // recorded concurrently.
+    unsafe { trace::record_page(event) };
+}
+
+/// Calls `f` with each free or allocated range of RAM managed by the page allocator, by physical
+/// address, if the allocator has been set up.
+///
+/// Interrupts aren't masked, so this can be used by the panic handler, even if the allocator was
+/// interrupted halfway through something, at the cost of an inconsistent map in that case.
+pub fn memory_map(mut f: impl FnMut(HexRange, RegionState)) {
+    // SAFETY: the allocator is only read, and a torn read only makes the map inconsistent.
+    let Some(allocator) = (unsafe { ALLOCATOR.get() }) else {
+        return;
+    };
+
+    for (range, state) in allocator.regions().flat_map(Allocator::memory_map) {
+        let start = ram_pa(VirtAddr::new(range.start as usize)).addr();
+        let end = ram_pa(VirtAddr::new(range.end as usize)).addr();
+        f(HexRange(start, end), state);
+    }
+}
+
+/// Calls `f` with the physical address range and a text rendering of the tree (see
+/// [`Ascii`]) of each region of RAM managed by the page allocator, if the allocator has been set
+/// up.
+///
+/// Like [`memory_map`], this can be used by the panic handler.
+pub fn allocator_trees(mut f: impl FnMut(HexRange, Ascii)) {
+    // SAFETY: see memory_map.
+    let Some(allocator) = (unsafe { ALLOCATOR.get() }) else {
+        return;
+    };
+
+    for region in allocator.regions() {
+        let heap = region.heap();
+        let start = ram_pa(VirtAddr::new(heap.start as usize)).addr();
+        let end = ram_pa(VirtAddr::new(heap.end as usize)).addr();
+        f(HexRange(start, end), region.ascii());
+    }
+}
+
+/// Returns the virtual address that the page allocator uses for `pa`, in any region of RAM.
+///
+/// RAM is mapped at the same offset from its physical address as the kernel image, so this works
+/// for RAM above 4GiB too, but only [`alloc_pages`] actually maps it.
+pub fn ram_va(pa: PhysAddr) -> VirtAddr {
+    let offset = linker_symbols::kernel_start().addr() - linker_symbols::kernel_start_pa().addr();
+
+    VirtAddr::new(pa.addr() + offset)
+}
+
+/// Returns the physical address of `va`, which was returned by [`ram_va`].
+pub fn ram_pa(va: VirtAddr) -> PhysAddr {
+    let offset = linker_symbols::kernel_start().addr() - linker_symbols::kernel_start_pa().addr();
+
+    PhysAddr::new(va.addr() - offset)
+}
+
+/// Makes instructions written to the `len` bytes at `address` visible to instruction fetches,
+/// by cleaning the data cache and invalidating the instruction cache to the point of unification.
+fn sync_instruction_cache(address: usize, len: usize) {
+    // SAFETY: reading CTR_EL0 has no side effects.
+    let ctr = unsafe { read_special_reg!("CTR_EL0") };
+    // CTR_EL0.DminLine and IminLine, log2 of the number of words in the smallest cache line
+    let data_line = 4 << (ctr >> 16 & 0xF);
+    let instruction_line = 4 << (ctr & 0xF);
+    let end = address + len;
+
+    // SAFETY: cache maintenance by address doesn't change the contents of memory.
+    unsafe {
+        for line in (address & !(data_line - 1)..end).step_by(data_line) {
+            asm!("dc cvau, {}", in(reg) line);
+        }
+        asm!("dsb ish");
+        for line in (address & !(instruction_line - 1)..end).step_by(instruction_line) {
+            asm!("ic ivau, {}", in(reg) line);
+        }
+        asm!("dsb ish", "isb");
+    }
+}
@@ -0,0 +1,122 @@
+//! How deeply exceptions are nested on the CPU, so code that must not run in an interrupt handler,
+//! like waiting for a lock that the interrupted task may hold, can check that it isn't.
+//!
+//! Each exception vector in main.rs calls [`enter`] on entry, and the guard it returns leaves the
+//! exception again just before entry.s returns from it. There's only one CPU, so there's only one
+//! nesting state, which belongs to the CPU rather than to any task, since the scheduler can switch
+//! tasks in the middle of an exception.
+use core::fmt;
+
+/// Deepest that exceptions may be nested, past which [`enter`] panics. Handlers run with interrupts
+/// masked, and exceptions taken with SP_EL1 never reach [`enter`], so anything deeper than one
+/// means a handler unmasked interrupts, or a guard was leaked.
+const MAX_DEPTH: usize = 4;
+
+static mut NESTING: Nesting = Nesting {
+    depth: 0,
+    exceptions: [None; MAX_DEPTH],
+};
+
+/// Type of exception, by which vector it was taken to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    Synchronous,
+    Irq,
+    Fiq,
+}
+
+/// Exception level an exception was taken from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Source {
+    /// A task running at EL0.
+    El0,
+    /// A kernel thread running at EL1, with SP_EL0.
+    El1,
+}
+
+/// The exceptions the CPU is handling, from the outermost in.
+pub struct Nesting {
+    depth: usize,
+    exceptions: [Option<(Kind, Source)>; MAX_DEPTH],
+}
+
+impl Nesting {
+    /// Returns how many exceptions are being handled, each inside the one before.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Returns the exceptions being handled, from the outermost in.
+    fn exceptions(&self) -> impl Iterator<Item = (Kind, Source)> + '_ {
+        self.exceptions[..self.depth].iter().flatten().copied()
+    }
+}
+
+impl fmt::Display for Nesting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.depth == 0 {
+            return write!(f, "no exceptions");
+        }
+        write!(f, "{} exception", self.depth)?;
+        if self.depth != 1 {
+            write!(f, "s")?;
+        }
+        for (i, (kind, from)) in self.exceptions().enumerate() {
+            let separator = if i == 0 { ": " } else { ", in " };
+            write!(f, "{separator}{kind:?} from {from:?}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Records that the CPU has taken an exception, until the returned guard is dropped.
+///
+/// Must only be called by the exception vectors, with interrupts masked.
+pub fn enter(kind: Kind, from: Source) -> Guard {
+    // SAFETY: the nesting state is only written with interrupts masked, on the only CPU.
+    let nesting = unsafe { &mut NESTING };
+    if nesting.depth == MAX_DEPTH {
+        panic!("exceptions nested too deeply, taking {kind:?} from {from:?} in {nesting}");
+    }
+    nesting.exceptions[nesting.depth] = Some((kind, from));
+    nesting.depth += 1;
+
+    Guard(())
+}
+
+/// Records that the CPU has returned from an exception taken with [`enter`], when dropped.
+pub struct Guard(());
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        // SAFETY: see enter.
+        let nesting = unsafe { &mut NESTING };
+        nesting.depth -= 1;
+        nesting.exceptions[nesting.depth] = None;
+    }
+}
+
+/// Returns the exceptions the CPU is handling, from the outermost in.
+pub fn current() -> &'static Nesting {
+    // SAFETY: an exception taken while this is read leaves the nesting state as it was before
+    // returning, so the reader never sees it change.
+    unsafe { &NESTING }
+}
+
+/// Returns whether the CPU is handling an interrupt, rather than running a task or handling an
+/// exception it caused, like a system call.
+pub fn in_interrupt() -> bool {
+    current()
+        .exceptions()
+        .any(|(kind, _)| matches!(kind, Kind::Irq | Kind::Fiq))
+}
+
+/// Panics if the CPU is handling an interrupt, since `what` may wait for the interrupted task,
+/// which can't run again until the interrupt handler returns.
+#[track_caller]
+pub fn assert_not_in_interrupt(what: &str) {
+    if in_interrupt() {
+        panic!("{what} in an interrupt handler, in {}", current());
+    }
+}
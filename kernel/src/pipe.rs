@@ -0,0 +1,131 @@
+//! Pipes, which carry bytes from tasks holding their write ends to tasks holding their read ends.
+//!
+//! Each pipe is a ring buffer with a count of the open handles to each end. Reading from an empty
+//! pipe or writing to a full one returns [`KernelError::WouldBlock`], which the system call layer
+//! turns into waiting. Once every write end is closed, reading an empty pipe returns 0 (EOF), and
+//! once every read end is closed, writing fails with [`KernelError::BrokenPipe`].
+use crate::error::KernelError;
+use crate::sync::without_interrupts;
+
+/// Maximum number of pipes that can be open at once.
+const MAX_PIPES: usize = 8;
+
+/// Number of bytes a pipe can hold before writers have to wait.
+const CAPACITY: usize = 512;
+
+static mut PIPES: [Option<Pipe>; MAX_PIPES] = [NO_PIPE; MAX_PIPES];
+const NO_PIPE: Option<Pipe> = None;
+
+/// One end of a pipe, identified by the pipe's index.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum End {
+    Read(usize),
+    Write(usize),
+}
+
+struct Pipe {
+    buffer: [u8; CAPACITY],
+    /// Index of the oldest byte in the buffer.
+    start: usize,
+    /// Number of bytes in the buffer.
+    len: usize,
+    /// Number of open handles to the read end.
+    readers: usize,
+    /// Number of open handles to the write end.
+    writers: usize,
+}
+
+/// Creates a pipe, returning its read and write ends, each with one open handle.
+pub fn create() -> Result<(End, End), KernelError> {
+    with_pipes(|pipes| {
+        let index = pipes
+            .iter()
+            .position(Option::is_none)
+            .ok_or(KernelError::TooManyPipes { max: MAX_PIPES })?;
+        pipes[index] = Some(Pipe {
+            buffer: [0; CAPACITY],
+            start: 0,
+            len: 0,
+            readers: 1,
+            writers: 1,
+        });
+
+        Ok((End::Read(index), End::Write(index)))
+    })
+}
+
+/// Reads as many bytes as are available from pipe `index` into `buffer`, returning how many were
+/// read, or 0 if the pipe is empty and every write end is closed.
+pub fn read(index: usize, buffer: &mut [u8]) -> Result<usize, KernelError> {
+    with_pipes(|pipes| {
+        let pipe = pipes[index].as_mut().expect("pipe to be open");
+        if pipe.len == 0 {
+            return match pipe.writers {
+                0 => Ok(0),
+                _ if buffer.is_empty() => Ok(0),
+                _ => Err(KernelError::WouldBlock),
+            };
+        }
+
+        let n = buffer.len().min(pipe.len);
+        for (i, byte) in buffer[..n].iter_mut().enumerate() {
+            *byte = pipe.buffer[(pipe.start + i) % CAPACITY];
+        }
+        pipe.start = (pipe.start + n) % CAPACITY;
+        pipe.len -= n;
+
+        Ok(n)
+    })
+}
+
+/// Writes as many bytes from `buffer` to pipe `index` as there's room for, returning how many were
+/// written.
+pub fn write(index: usize, buffer: &[u8]) -> Result<usize, KernelError> {
+    with_pipes(|pipes| {
+        let pipe = pipes[index].as_mut().expect("pipe to be open");
+        if pipe.readers == 0 {
+            return Err(KernelError::BrokenPipe);
+        }
+        if pipe.len == CAPACITY && !buffer.is_empty() {
+            return Err(KernelError::WouldBlock);
+        }
+
+        let n = buffer.len().min(CAPACITY - pipe.len);
+        for (i, &byte) in buffer[..n].iter().enumerate() {
+            pipe.buffer[(pipe.start + pipe.len + i) % CAPACITY] = byte;
+        }
+        pipe.len += n;
+
+        Ok(n)
+    })
+}
+
+/// Opens another handle to `end`, such as when a spawned task inherits it.
+pub fn retain(end: End) {
+    with_pipes(|pipes| match end {
+        End::Read(index) => pipes[index].as_mut().expect("pipe to be open").readers += 1,
+        End::Write(index) => pipes[index].as_mut().expect("pipe to be open").writers += 1,
+    });
+}
+
+/// Closes a handle to `end`, freeing the pipe once both of its ends are closed.
+pub fn release(end: End) {
+    with_pipes(|pipes| {
+        let index = match end {
+            End::Read(index) | End::Write(index) => index,
+        };
+        let pipe = pipes[index].as_mut().expect("pipe to be open");
+        match end {
+            End::Read(_) => pipe.readers -= 1,
+            End::Write(_) => pipe.writers -= 1,
+        }
+        if pipe.readers == 0 && pipe.writers == 0 {
+            pipes[index] = None;
+        }
+    });
+}
+
+fn with_pipes<R>(f: impl FnOnce(&mut [Option<Pipe>; MAX_PIPES]) -> R) -> R {
+    // SAFETY: the pipes are only accessed with interrupts masked, and thus by one caller at once.
+    without_interrupts(|| f(unsafe { &mut PIPES }))
+}
@@ -0,0 +1,231 @@
+//! PL011 UARTs, which are found in the devicetree by [`probe`] and then claimed by subsystems.
+//!
+//! The logger claims one for the console during early init (see logging.rs), and anything else
+//! that needs a UART of its own, like a GDB stub, can claim one of the others with [`claim_any`].
+use core::ptr::null;
+
+use fdt::Fdt;
+use hal::Console;
+
+use crate::a53::pl011::Pl011RegisterBlock;
+use crate::error::KernelError;
+use crate::gicv2::InterruptId;
+use crate::sync::without_interrupts;
+use crate::{boot_info, irq, tty};
+
+const COMPATIBLE: &str = "arm,pl011";
+
+/// Log target of the PL011 driver.
+pub const LOG_TARGET: &str = "pl011";
+
+/// Maximum number of UARTs we keep track of, which is plenty for QEMU's virt machine.
+const MAX_UARTS: usize = 4;
+
+/// The console UART, as far as reading input from it goes, which is set up by
+/// [`init_console_input`]. Output goes through the logger's writer instead (see logging.rs).
+static mut CONSOLE_UART: Pl011 = Pl011::new(null());
+
+/// Every UART found by [`probe`], in devicetree order.
+static mut UARTS: [Option<Uart>; MAX_UARTS] = [None; MAX_UARTS];
+
+/// A PL011 UART found in the devicetree.
+#[derive(Clone, Copy, Debug)]
+pub struct Uart {
+    /// Index of the UART in devicetree order, as in `console=ttyAMA<index>`.
+    pub index: usize,
+    pub base: *const u8,
+    /// The UART's interrupt, unless the devicetree doesn't say.
+    pub interrupt: Option<InterruptId>,
+    /// Name of the subsystem that claimed the UART, if any.
+    pub owner: Option<&'static str>,
+}
+
+/// Finds every UART in the devicetree, forgetting any claims.
+///
+/// Called during early init, before the logger is up, so interrupts we can't use are silently
+/// skipped.
+pub fn probe() {
+    let mut uarts = boot_info().uarts().enumerate().map(|(index, uart)| Uart {
+        index,
+        base: uart.base as *const u8,
+        interrupt: uart.interrupt.and_then(|i| i.try_into().ok()),
+        owner: None,
+    });
+
+    without_interrupts(|| {
+        // SAFETY: interrupts are masked, and nothing else refers to UARTS.
+        for slot in unsafe { UARTS.iter_mut() } {
+            *slot = uarts.next();
+        }
+    });
+}
+
+/// Returns the index of the UART named by `name`, which is either `ttyAMA<index>` (as in Linux)
+/// or the UART's base address in hexadecimal, like `0x9000000`.
+pub fn find(name: &str) -> Option<usize> {
+    // like Linux, ignore any options after the name, like the “,115200” a Pi's firmware adds
+    let name = name.split(',').next().unwrap_or(name);
+    if let Some(index) = name.strip_prefix("ttyAMA") {
+        let index = index.parse().ok()?;
+        return list()
+            .find(|uart| uart.index == index)
+            .map(|uart| uart.index);
+    }
+
+    let base = usize::from_str_radix(name.strip_prefix("0x")?, 16).ok()?;
+    list()
+        .find(|uart| uart.base as usize == base)
+        .map(|uart| uart.index)
+}
+
+/// Returns the index of the UART named by `stdout-path` in the devicetree's /chosen node.
+pub fn stdout() -> Option<usize> {
+    let base = boot_info().stdout? as *const u8;
+
+    list().find(|uart| uart.base == base).map(|uart| uart.index)
+}
+
+/// Claims the UART at `index` for `owner`.
+pub fn claim(index: usize, owner: &'static str) -> Result<Uart, KernelError> {
+    without_interrupts(|| {
+        // SAFETY: interrupts are masked, and nothing else refers to UARTS.
+        let uart = unsafe { UARTS.iter_mut() }
+            .flatten()
+            .find(|uart| uart.index == index)
+            .ok_or(KernelError::DeviceNotFound {
+                compatible: COMPATIBLE,
+            })?;
+        if let Some(owner) = uart.owner {
+            return Err(KernelError::DeviceClaimed {
+                compatible: COMPATIBLE,
+                owner,
+            });
+        }
+        uart.owner = Some(owner);
+
+        Ok(*uart)
+    })
+}
+
+/// Claims the first UART that hasn't been claimed yet for `owner`.
+#[allow(dead_code)]
+pub fn claim_any(owner: &'static str) -> Result<Uart, KernelError> {
+    let uart = list()
+        .find(|uart| uart.owner.is_none())
+        .ok_or(KernelError::NoFreeDevice {
+            compatible: COMPATIBLE,
+        })?;
+
+    claim(uart.index, owner)
+}
+
+/// Returns every UART found by [`probe`], and who claimed it.
+pub fn list() -> impl Iterator<Item = Uart> {
+    // SAFETY: interrupts are masked, and nothing else refers to UARTS.
+    let uarts = without_interrupts(|| unsafe { UARTS });
+
+    uarts.into_iter().flatten()
+}
+
+/// Sets up the console UART to receive input, which is handled by [`console_receive`].
+fn init_console_input(_fdt: &Fdt) -> Result<(), KernelError> {
+    let uart =
+        list()
+            .find(|uart| uart.owner == Some("console"))
+            .ok_or(KernelError::DeviceNotFound {
+                compatible: COMPATIBLE,
+            })?;
+    let interrupt = uart.interrupt.ok_or(KernelError::MissingProperty {
+        compatible: COMPATIBLE,
+        property: "interrupts",
+    })?;
+
+    // SAFETY: interrupts are masked during boot, so the receive handler can't be running yet.
+    unsafe { CONSOLE_UART = Pl011::new(uart.base) };
+    irq::register(interrupt, console_receive, irq::Mode::Threaded)?;
+    // typing on the console wakes the system from suspend, unless a console command suspended it
+    irq::enable_wakeup(interrupt)?;
+    // SAFETY: as above, and the handler is registered before the interrupt is enabled.
+    unsafe { CONSOLE_UART.enable_receive_interrupt() };
+    driver::register_suspend(driver::SuspendHooks {
+        name: LOG_TARGET,
+        suspend: suspend_console,
+        resume: || {},
+    })?;
+    log::debug!(
+        target: LOG_TARGET,
+        "console input from ttyAMA{} on interrupt {interrupt:?}",
+        uart.index
+    );
+
+    Ok(())
+}
+initcall!(driver, init_console_input);
+
+/// Threaded handler for the console UART's receive interrupt.
+fn console_receive(_interrupt_id: InterruptId) {
+    // SAFETY: the console UART is only read from by this handler, which only runs in the IRQ
+    // thread.
+    while let Some(byte) = unsafe { CONSOLE_UART.read_byte() } {
+        tty::input(byte);
+    }
+}
+
+/// Waits for the console UART to finish transmitting, so nothing logged before the system
+/// suspends is left in its FIFO.
+fn suspend_console() {
+    // SAFETY: suspend hooks run with IRQs masked, and this only reads the flag register.
+    unsafe { CONSOLE_UART.flush() };
+}
+
+/// A PL011 UART.
+pub struct Pl011(*mut Pl011RegisterBlock);
+
+impl Pl011 {
+    pub const fn new(base_address: *const u8) -> Self {
+        Self(base_address as *mut Pl011RegisterBlock)
+    }
+
+    /// Raises an interrupt when data is received, or when the receive FIFO is not empty and no
+    /// more data has been received for a while.
+    pub fn enable_receive_interrupt(&mut self) {
+        // SAFETY: the UART's registers are mapped for as long as the kernel runs, and the register
+        // API only accesses them volatilely.
+        let uart = unsafe { &*self.0 };
+
+        uart.imsc.write_initial(|w| {
+            w.rxim(true);
+            w.rtim(true);
+        });
+    }
+}
+
+impl Console for Pl011 {
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        // SAFETY: see enable_receive_interrupt.
+        let uart = unsafe { &*self.0 };
+        for &byte in bytes {
+            uart.dr.write_initial(|w| w.data(byte));
+        }
+    }
+
+    /// Reads a byte from the receive FIFO, if it's not empty.
+    fn read_byte(&mut self) -> Option<u8> {
+        // SAFETY: see enable_receive_interrupt.
+        let uart = unsafe { &*self.0 };
+
+        if uart.fr.read(|r| r.rxfe()) {
+            None
+        } else {
+            Some(uart.dr.read(|r| r.data()))
+        }
+    }
+
+    /// Waits until the UART has finished transmitting everything written to it.
+    fn flush(&mut self) {
+        // SAFETY: see enable_receive_interrupt.
+        let uart = unsafe { &*self.0 };
+
+        while uart.fr.read(|r| r.busy()) {}
+    }
+}
@@ -0,0 +1,190 @@
+//! Per-task performance counters, using the Performance Monitors Extension (PMUv3).
+//!
+//! The counters are reset whenever the scheduler switches tasks (see [`take`]), and what they
+//! counted is added to the task that was running, so each task's counts include the exception
+//! handlers that ran while it was the current task.
+//!
+//! QEMU implements the PMU under TCG, but doesn't count most events (and only counts instructions
+//! with `-icount`), so events that aren't implemented are reported as unavailable, not as zero.
+use core::arch::asm;
+use core::ops::Add;
+
+use fdt::Fdt;
+
+use crate::a53::debug::ID_AA64DFR0_EL1;
+use crate::a53::pmu::{
+    PMCCFILTR_EL0, PMCCNTR_EL0, PMCEID0_EL0, PMCNTENSET_EL0, PMCR_EL0, PMEVCNTR, PMEVTYPER,
+};
+use crate::error::KernelError;
+use crate::reg::system::Register;
+
+/// Something the PMU can count.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Counter {
+    Cycles,
+    Instructions,
+    CacheMisses,
+    BranchMispredicts,
+}
+
+impl Counter {
+    pub const ALL: [Self; 4] = [
+        Self::Cycles,
+        Self::Instructions,
+        Self::CacheMisses,
+        Self::BranchMispredicts,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Cycles => "cycles",
+            Self::Instructions => "instructions",
+            Self::CacheMisses => "L1D refills",
+            Self::BranchMispredicts => "mispredicts",
+        }
+    }
+
+    /// Returns the common event number and the event counter to count it with, or `None` for the
+    /// cycle counter.
+    fn event(self) -> Option<(u16, usize)> {
+        match self {
+            Self::Cycles => None,
+            // INST_RETIRED
+            Self::Instructions => Some((0x08, 0)),
+            // L1D_CACHE_REFILL
+            Self::CacheMisses => Some((0x03, 1)),
+            // BR_MIS_PRED
+            Self::BranchMispredicts => Some((0x10, 2)),
+        }
+    }
+}
+
+/// Which of [`Counter::ALL`] are being counted, set by [`init`].
+static mut COUNTING: [bool; Counter::ALL.len()] = [false; Counter::ALL.len()];
+
+/// Values counted by the PMU, for each [`Counter`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Counts([u64; Counter::ALL.len()]);
+
+impl Counts {
+    pub fn get(&self, counter: Counter) -> u64 {
+        self.0[counter as usize]
+    }
+}
+
+impl Add for Counts {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self(core::array::from_fn(|i| self.0[i].wrapping_add(other.0[i])))
+    }
+}
+
+/// Returns whether `counter` is being counted, which it isn't if the PMU doesn't implement it.
+pub fn is_counting(counter: Counter) -> bool {
+    // SAFETY: COUNTING is only written by init, before anything else runs.
+    unsafe { COUNTING[counter as usize] }
+}
+
+/// Returns what the PMU has counted since it was last reset.
+pub fn read() -> Counts {
+    let mut counts = Counts::default();
+
+    for counter in Counter::ALL.into_iter().filter(|&c| is_counting(c)) {
+        counts.0[counter as usize] = match counter.event() {
+            None => Register::<PMCCNTR_EL0>::new().read(|r| r.ccnt()),
+            // reading an event counter that isn't implemented is undefined, but init only counts
+            // with counters that are
+            Some((_, 0)) => Register::<PMEVCNTR<0>>::new().read(|r| r.evcnt()).into(),
+            Some((_, 1)) => Register::<PMEVCNTR<1>>::new().read(|r| r.evcnt()).into(),
+            Some((_, 2)) => Register::<PMEVCNTR<2>>::new().read(|r| r.evcnt()).into(),
+            Some(_) => unreachable!(),
+        };
+    }
+
+    counts
+}
+
+/// Returns what the PMU has counted since it was last reset, then resets it, so the next task
+/// starts counting from zero.
+///
+/// The event counters are only 32 bits wide, so they could overflow if a task ran for more than
+/// 2^32 events without being switched away from, but a time slice is far shorter than that.
+pub fn take() -> Counts {
+    if !Counter::ALL.into_iter().any(is_counting) {
+        return Counts::default();
+    }
+
+    let counts = read();
+    Register::<PMCR_EL0>::new().modify(|w| {
+        w.p(true);
+        w.c(true);
+    });
+
+    counts
+}
+
+/// Sets up the cycle counter, and an event counter for each other [`Counter`] the PMU implements.
+fn init(_fdt: &Fdt) -> Result<(), KernelError> {
+    let version = Register::<ID_AA64DFR0_EL1>::new().read(|r| r.pmuver());
+    if version == 0 || version == 0xF {
+        log::debug!("no PMUv3, so no performance counters");
+        return Ok(());
+    }
+
+    let event_counters = Register::<PMCR_EL0>::new().read(|r| r.n()) as usize;
+    let implemented = Register::<PMCEID0_EL0>::new().read(|r| {
+        Counter::ALL.map(|counter| match counter.event() {
+            None => true,
+            Some((event, n)) => n < event_counters && r.id(event),
+        })
+    });
+
+    // count at EL0 and EL1, which is what PMEVTYPER counts with its filter bits clear too
+    Register::<PMCCFILTR_EL0>::new().write_initial(|_| {});
+    for counter in Counter::ALL {
+        match counter.event() {
+            Some((event, 0)) if implemented[counter as usize] => {
+                Register::<PMEVTYPER<0>>::new().write_initial(|w| w.evt_count(event))
+            }
+            Some((event, 1)) if implemented[counter as usize] => {
+                Register::<PMEVTYPER<1>>::new().write_initial(|w| w.evt_count(event))
+            }
+            Some((event, 2)) if implemented[counter as usize] => {
+                Register::<PMEVTYPER<2>>::new().write_initial(|w| w.evt_count(event))
+            }
+            _ => {}
+        }
+    }
+    Register::<PMCNTENSET_EL0>::new().write_initial(|w| {
+        for counter in Counter::ALL
+            .into_iter()
+            .filter(|&c| implemented[c as usize])
+        {
+            match counter.event() {
+                None => w.c(true),
+                Some((_, n)) => w.p(n, true),
+            }
+        }
+    });
+    Register::<PMCR_EL0>::new().modify(|w| {
+        w.e(true);
+        w.p(true);
+        w.c(true);
+        w.d(false);
+        w.lc(true);
+    });
+    // SAFETY: isb has no side effects beyond synchronising the context.
+    unsafe { asm!("isb") };
+
+    // SAFETY: this runs before anything else can use the counters.
+    unsafe { COUNTING = implemented };
+
+    log::debug!("PMUv3 with {event_counters} event counters");
+    for counter in Counter::ALL.into_iter().filter(|&c| !is_counting(c)) {
+        log::debug!("PMU can't count {}", counter.name());
+    }
+
+    Ok(())
+}
+initcall!(arch, init);
@@ -0,0 +1,67 @@
+//! Finding devices and their resources in the devicetree, for probing drivers.
+//!
+//! The devices the kernel needs to boot are found up front instead (see [`bootinfo`]), so this is
+//! for drivers of anything else.
+use fdt::node::FdtNode;
+use fdt::Fdt;
+
+use crate::error::KernelError;
+use crate::gicv2::{InterruptId, InterruptSpecifier};
+
+/// A devicetree node found by its compatible string.
+#[allow(dead_code)]
+pub struct Device<'b, 'a> {
+    compatible: &'static str,
+    node: FdtNode<'b, 'a>,
+}
+
+#[allow(dead_code)]
+impl<'b, 'a> Device<'b, 'a> {
+    /// Finds the first node compatible with `compatible`.
+    pub fn find(fdt: &'b Fdt<'a>, compatible: &'static str) -> Result<Self, KernelError> {
+        let node = fdt
+            .find_compatible(&[compatible])
+            .ok_or(KernelError::DeviceNotFound { compatible })?;
+
+        Ok(Self { compatible, node })
+    }
+
+    /// Finds every node compatible with `compatible`, in devicetree order.
+    pub fn find_all(fdt: &'b Fdt<'a>, compatible: &'static str) -> impl Iterator<Item = Self> + 'b {
+        fdt.all_nodes()
+            .filter(move |node| {
+                node.compatible()
+                    .map_or(false, |c| c.all().any(|c| c == compatible))
+            })
+            .map(move |node| Self { compatible, node })
+    }
+
+    /// Returns the base address of the `index`th region in the node's `reg` property.
+    pub fn reg(&self, index: usize) -> Result<*const u8, KernelError> {
+        self.node
+            .reg()
+            .and_then(|mut reg| reg.nth(index))
+            .map(|region| region.starting_address)
+            .ok_or(self.missing("reg"))
+    }
+
+    /// Returns the `index`th interrupt in the node's `interrupts` property.
+    pub fn interrupt(&self, index: usize) -> Result<InterruptId, KernelError> {
+        let interrupts = self
+            .node
+            .property("interrupts")
+            .ok_or(self.missing("interrupts"))?;
+
+        InterruptSpecifier::interrupts_iter(interrupts.value)
+            .nth(index)
+            .ok_or(self.missing("interrupts"))?
+            .interrupt_id()
+    }
+
+    fn missing(&self, property: &'static str) -> KernelError {
+        KernelError::MissingProperty {
+            compatible: self.compatible,
+            property,
+        }
+    }
+}
@@ -0,0 +1,325 @@
+//! A sampling profiler, driven by the virtual timer, so it can sample at its own rate without
+//! changing the scheduler's tick.
+//!
+//! Each time the virtual timer fires, the IRQ handler records the id of the current task and the
+//! stack of the code it interrupted: the interrupted pc, then the return address in each frame
+//! record on the stack. Samples are kept in a ring buffer, which overwrites the oldest once full.
+//!
+//! Start the profiler with `profile=<hz>` on the command line, or `profile start [<hz>]` in the
+//! console, then `profile folded` prints the samples as folded stacks, one line per distinct stack
+//! with its count, which is what flamegraph tools like `flamegraph.pl` and `inferno` take. The
+//! stacks are addresses, since the kernel has no symbol table of its own, so copy them from the
+//! console to a file, then symbolize them with `cargo xtask profile <file>`.
+//!
+//! Unwinding relies on frame records, which the kernel is built to keep (see
+//! aarch64-unknown-none.json). A sample taken in a function's prologue, before it has pushed its
+//! frame record, is missing that function's caller.
+use core::mem;
+
+use fdt::Fdt;
+use hal::{InterruptController, Timer};
+
+use crate::error::KernelError;
+use crate::gicv2::InterruptId;
+use crate::sync::without_interrupts;
+use crate::task::Context;
+use crate::{board, boot_info, cmdline, tt};
+
+/// Number of samples kept, after which the oldest are overwritten.
+const CAPACITY: usize = 256;
+
+/// Number of frames kept in each sample, including the interrupted pc.
+const MAX_DEPTH: usize = 8;
+
+/// Sampling rate used by `profile start` without a rate, in Hz.
+pub const DEFAULT_RATE: u64 = 100;
+
+/// Highest sampling rate, in Hz, so the profiler can't leave no time for anything else.
+const MAX_RATE: u64 = 10_000;
+
+/// Log target of the profiler.
+const LOG_TARGET: &str = "profile";
+
+static mut INTERRUPT: InterruptId = InterruptId::spurious();
+
+/// Sampling rate in Hz, or zero if the profiler is stopped.
+static mut RATE: u64 = 0;
+
+static mut SAMPLES: Samples = Samples::new();
+
+#[derive(Clone, Copy)]
+struct Sample {
+    task: usize,
+    depth: usize,
+    /// The interrupted pc, then the call site in each caller, innermost first.
+    frames: [usize; MAX_DEPTH],
+}
+
+impl Sample {
+    const EMPTY: Self = Self {
+        task: 0,
+        depth: 0,
+        frames: [0; MAX_DEPTH],
+    };
+
+    fn frames(&self) -> &[usize] {
+        &self.frames[..self.depth]
+    }
+
+    fn is_same(&self, other: &Self) -> bool {
+        self.task == other.task && self.frames() == other.frames()
+    }
+}
+
+struct Samples {
+    samples: [Sample; CAPACITY],
+    /// Number of samples ever recorded, which may be more than the capacity.
+    written: u64,
+}
+
+impl Samples {
+    const fn new() -> Self {
+        Self {
+            samples: [Sample::EMPTY; CAPACITY],
+            written: 0,
+        }
+    }
+
+    fn push(&mut self, sample: Sample) {
+        self.samples[(self.written % CAPACITY as u64) as usize] = sample;
+        self.written += 1;
+    }
+
+    fn as_slice(&self) -> &[Sample] {
+        &self.samples[..self.written.min(CAPACITY as u64) as usize]
+    }
+}
+
+/// Sets up the virtual timer's interrupt, and starts the profiler if `profile=<hz>` was given.
+fn init(_fdt: &Fdt) -> Result<(), KernelError> {
+    let timer = boot_info().timer.ok_or(KernelError::DeviceNotFound {
+        compatible: "arm,armv8-timer",
+    })?;
+
+    // SAFETY: initcalls run before interrupts are unmasked.
+    unsafe {
+        INTERRUPT = timer.virt.try_into()?;
+        board::interrupt_controller().enable_interrupt(INTERRUPT);
+    }
+    driver::register_suspend(driver::SuspendHooks {
+        name: LOG_TARGET,
+        suspend,
+        resume,
+    })?;
+
+    if let Some(rate) = cmdline::option("profile") {
+        match rate.parse() {
+            Ok(rate) => start(rate)?,
+            Err(_) => log::warn!(target: LOG_TARGET, "profile={rate} is not a rate in Hz"),
+        }
+    }
+
+    Ok(())
+}
+initcall!(driver, init);
+
+/// Starts sampling `rate` times a second, forgetting any earlier samples.
+pub fn start(rate: u64) -> Result<(), KernelError> {
+    if !(1..=MAX_RATE).contains(&rate) {
+        return Err(KernelError::InvalidArgument {
+            reason: "sampling rate must be 1 to 10000 Hz",
+        });
+    }
+
+    without_interrupts(|| {
+        // SAFETY: the profiler is only used with IRQs masked, including by the IRQ handler.
+        unsafe {
+            SAMPLES.written = 0;
+            RATE = rate;
+            arm();
+            write_special_reg!("CNTV_CTL_EL0", 1u64);
+        }
+    });
+    log::debug!(target: LOG_TARGET, "sampling at {rate} Hz");
+
+    Ok(())
+}
+
+/// Stops sampling, keeping the samples taken so far.
+pub fn stop() {
+    without_interrupts(|| {
+        // SAFETY: see start.
+        unsafe {
+            write_special_reg!("CNTV_CTL_EL0", 0u64);
+            RATE = 0;
+        }
+    });
+}
+
+/// Returns the sampling rate in Hz, or `None` if the profiler is stopped.
+pub fn rate() -> Option<u64> {
+    // SAFETY: see start.
+    let rate = without_interrupts(|| unsafe { RATE });
+
+    (rate > 0).then_some(rate)
+}
+
+/// Returns the number of samples kept, and how many older ones were overwritten.
+pub fn len() -> (usize, u64) {
+    without_interrupts(|| {
+        // SAFETY: see start.
+        let samples = unsafe { &SAMPLES };
+        let len = samples.as_slice().len();
+
+        (len, samples.written - len as u64)
+    })
+}
+
+/// Returns whether `interrupt_id` is the profiler's timer interrupt.
+pub fn is_interrupt(interrupt_id: InterruptId) -> bool {
+    // SAFETY: INTERRUPT is only written by init, before interrupts are unmasked.
+    interrupt_id == unsafe { INTERRUPT }
+}
+
+/// Records a sample of `context`, the context interrupted by the profiler's timer, which was
+/// running as the task `task`, then sets the timer for the next sample.
+///
+/// Nothing is recorded if the scheduler hasn't started, so there's no task to blame.
+///
+/// # Safety
+///
+/// Must only be called from the IRQ handler, with IRQs masked.
+pub unsafe fn sample(context: &Context, task: Option<usize>) {
+    // SAFETY: the caller ensures that IRQs are masked.
+    let (samples, rate) = unsafe { (&mut SAMPLES, RATE) };
+    if rate == 0 {
+        // stopped after the interrupt became pending, so mask it until the next start
+        // SAFETY: as above.
+        unsafe {
+            write_special_reg!("CNTV_CTL_EL0", 0u64);
+        }
+        return;
+    }
+    // the timer's interrupt stays asserted until it's set again
+    // SAFETY: as above.
+    unsafe { arm() };
+    let Some(task) = task else {
+        return;
+    };
+
+    let mut sample = Sample {
+        task,
+        ..Sample::EMPTY
+    };
+    sample.frames[0] = context.pc();
+    sample.depth = 1;
+
+    // each frame record is the caller's frame pointer, then the return address, and callers'
+    // frames are at higher addresses, so we stop at anything else rather than loop or fault
+    let mut fp = context.x(29) as usize;
+    while sample.depth < MAX_DEPTH && fp != 0 && fp % mem::align_of::<usize>() == 0 {
+        let lr = fp + mem::size_of::<usize>();
+        if !tt::is_readable(fp) || !tt::is_readable(lr) {
+            break;
+        }
+        // SAFETY: both words are mapped for reads, and aligned.
+        let (next, lr) = unsafe {
+            (
+                (fp as *const usize).read_volatile(),
+                (lr as *const usize).read_volatile(),
+            )
+        };
+        if lr == 0 {
+            break;
+        }
+        // the return address is after the call, which could be in the next function
+        sample.frames[sample.depth] = lr.wrapping_sub(4);
+        sample.depth += 1;
+        if next <= fp {
+            break;
+        }
+        fp = next;
+    }
+
+    samples.push(sample);
+}
+
+/// Calls `f` with each distinct task and stack that was sampled, innermost frame first, and the
+/// number of samples of it.
+pub fn folded(mut f: impl FnMut(usize, &[usize], usize)) {
+    without_interrupts(|| {
+        // SAFETY: see start.
+        let samples = unsafe { SAMPLES.as_slice() };
+        for (i, sample) in samples.iter().enumerate() {
+            // only report each stack where it first appears
+            if samples[..i].iter().any(|other| other.is_same(sample)) {
+                continue;
+            }
+            let count = samples[i..]
+                .iter()
+                .filter(|other| other.is_same(sample))
+                .count();
+            f(sample.task, sample.frames(), count);
+        }
+    })
+}
+
+/// Calls `f` with each distinct pc that was sampled and the number of samples of it, most sampled
+/// first.
+pub fn flat(mut f: impl FnMut(usize, usize)) {
+    without_interrupts(|| {
+        // SAFETY: see start.
+        let samples = unsafe { SAMPLES.as_slice() };
+        let mut pcs = [(0, 0); CAPACITY];
+        let mut len = 0;
+        for sample in samples {
+            let pc = sample.frames[0];
+            match pcs[..len].iter_mut().find(|(other, _)| *other == pc) {
+                Some((_, count)) => *count += 1,
+                None => {
+                    pcs[len] = (pc, 1);
+                    len += 1;
+                }
+            }
+        }
+
+        let pcs = &mut pcs[..len];
+        pcs.sort_unstable_by(|(_, a), (_, b)| b.cmp(a));
+        for &(pc, count) in pcs.iter() {
+            f(pc, count);
+        }
+    })
+}
+
+/// Sets the virtual timer to fire after one sampling period.
+///
+/// # Safety
+///
+/// Must be called with IRQs masked.
+unsafe fn arm() {
+    // SAFETY: the caller ensures that IRQs are masked, so the timer can't fire in the meantime.
+    unsafe {
+        write_special_reg!("CNTV_TVAL_EL0", board::timer().frequency() / RATE.max(1));
+    }
+}
+
+/// Stops the virtual timer, so it doesn't wake the system from suspend (see suspend.rs).
+fn suspend() {
+    // SAFETY: suspend hooks run with IRQs masked, so the timer interrupt can't be handled.
+    unsafe {
+        write_special_reg!("CNTV_CTL_EL0", 0u64);
+        board::interrupt_controller().disable_interrupt(INTERRUPT);
+    }
+}
+
+/// Restarts the virtual timer, if the profiler was running.
+fn resume() {
+    // SAFETY: see suspend.
+    unsafe {
+        if RATE > 0 {
+            arm();
+            write_special_reg!("CNTV_CTL_EL0", 1u64);
+        }
+        board::interrupt_controller().enable_interrupt(INTERRUPT);
+    }
+}
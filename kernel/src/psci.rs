@@ -0,0 +1,116 @@
+//! The Power State Coordination Interface, which is how the kernel asks firmware (or QEMU) to
+//! power the system off or reset it.
+//!
+//! https://developer.arm.com/documentation/den0022/latest
+use core::arch::asm;
+
+use fdt::Fdt;
+use hal::Power;
+
+use crate::error::KernelError;
+
+/// Log target of the PSCI driver.
+pub const LOG_TARGET: &str = "psci";
+
+/// Compatible strings of every PSCI version with the standard function ids.
+const COMPATIBLE: [&str; 2] = ["arm,psci-1.0", "arm,psci-0.2"];
+
+/// Function id of SYSTEM_OFF.
+const SYSTEM_OFF: u32 = 0x8400_0008;
+
+/// Function id of SYSTEM_RESET.
+const SYSTEM_RESET: u32 = 0x8400_0009;
+
+/// How PSCI calls are made, which depends on which exception level the firmware runs at.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Conduit {
+    /// `hvc`, for firmware at EL2, which is QEMU itself unless it emulates EL2.
+    Hvc,
+    /// `smc`, for firmware at EL3.
+    Smc,
+}
+
+/// PSCI firmware, as the [`Power`] control of the board.
+pub struct Psci {
+    conduit: Option<Conduit>,
+}
+
+/// Power control, which does nothing until its firmware interface is found by [`init`].
+static mut POWER: Psci = Psci::new();
+
+impl Psci {
+    /// Returns a PSCI that fails every call, until its conduit is found by [`init`].
+    pub const fn new() -> Self {
+        Self { conduit: None }
+    }
+
+    /// Makes a PSCI call that returns only if it fails, and logs why.
+    fn call(&self, function: u32) {
+        let Some(conduit) = self.conduit else {
+            log::warn!(target: LOG_TARGET, "no PSCI firmware to call");
+            return;
+        };
+
+        let result: i64;
+        // SAFETY: the calls we make either never return, or fail without side effects, and
+        // SMCCC only clobbers the registers a C function would.
+        unsafe {
+            match conduit {
+                Conduit::Hvc => asm!(
+                    "hvc #0",
+                    inlateout("x0") u64::from(function) => result,
+                    clobber_abi("C"),
+                ),
+                Conduit::Smc => asm!(
+                    "smc #0",
+                    inlateout("x0") u64::from(function) => result,
+                    clobber_abi("C"),
+                ),
+            }
+        }
+        log::warn!(target: LOG_TARGET, "PSCI call {function:#x} failed with {result}");
+    }
+}
+
+impl Power for Psci {
+    fn system_off(&self) {
+        self.call(SYSTEM_OFF);
+    }
+
+    fn system_reset(&self) {
+        self.call(SYSTEM_RESET);
+    }
+}
+
+/// Finds how to call the PSCI firmware, from the `method` property of its devicetree node.
+fn init(fdt: &Fdt) -> Result<(), KernelError> {
+    let node = fdt
+        .find_compatible(&COMPATIBLE)
+        .ok_or(KernelError::DeviceNotFound {
+            compatible: COMPATIBLE[0],
+        })?;
+    let method = node
+        .property("method")
+        .and_then(|method| method.as_str())
+        .ok_or(KernelError::MissingProperty {
+            compatible: COMPATIBLE[0],
+            property: "method",
+        })?;
+    let conduit = match method {
+        "hvc" => Conduit::Hvc,
+        "smc" => Conduit::Smc,
+        _ => {
+            return Err(KernelError::DeviceFailed {
+                compatible: COMPATIBLE[0],
+                reason: "unknown method",
+            })
+        }
+    };
+    log::debug!(target: LOG_TARGET, "calls made with {method}");
+
+    // SAFETY: initcalls run before anything else uses the power control.
+    unsafe { POWER.conduit = Some(conduit) };
+
+    Ok(())
+}
+initcall!(driver, init);
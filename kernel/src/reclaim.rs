@@ -0,0 +1,105 @@
+//! Deferred freeing of shared kernel data, so readers don't need locks (a very simple RCU).
+//!
+//! Readers only use shared data with IRQs masked, so they can't be preempted or interrupted while
+//! they hold a pointer to it. A writer replaces the pointer, then passes the old one to
+//! [`defer_free`] instead of freeing it at once. Passing through the scheduler is a quiescent
+//! state, because no reader can be in the middle of a read when a context switch happens, so once
+//! every CPU has done that (and there's only one CPU for now), the old data has no readers left
+//! and its destructor can run.
+//!
+//! Destructors run in the IRQ thread (see [`collect`]), rather than in the scheduler, so they
+//! don't add to the time spent with IRQs masked.
+use crate::error::KernelError;
+use crate::sync::without_interrupts;
+
+/// Maximum number of items that can be waiting to be freed at once.
+const MAX_DEFERRED: usize = 16;
+
+static mut QUEUE: Queue = Queue::new();
+
+/// Destructor for an item passed to [`defer_free`].
+pub type Destructor = unsafe fn(*mut ());
+
+#[derive(Clone, Copy)]
+struct Deferred {
+    ptr: *mut (),
+    destructor: Destructor,
+    /// Grace period the item was queued in, which must end before it's freed.
+    epoch: u64,
+}
+
+struct Queue {
+    items: [Option<Deferred>; MAX_DEFERRED],
+    /// Number of quiescent states so far, each of which ends a grace period.
+    epoch: u64,
+}
+
+impl Queue {
+    const fn new() -> Self {
+        Self {
+            items: [None; MAX_DEFERRED],
+            epoch: 0,
+        }
+    }
+}
+
+/// Queues `ptr` to be passed to `destructor` once no reader can still be using it.
+///
+/// # Safety
+///
+/// `ptr` must no longer be reachable by new readers, and `destructor` must be safe to call with
+/// it once the readers that could already reach it are done.
+pub unsafe fn defer_free(ptr: *mut (), destructor: Destructor) -> Result<(), KernelError> {
+    with_queue(|queue| {
+        let epoch = queue.epoch;
+        let slot = queue
+            .items
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(KernelError::TooManyDeferred { max: MAX_DEFERRED })?;
+        *slot = Some(Deferred {
+            ptr,
+            destructor,
+            epoch,
+        });
+
+        Ok(())
+    })
+}
+
+/// Notes that the CPU has passed through a quiescent state, ending the current grace period.
+pub fn quiescent() {
+    with_queue(|queue| queue.epoch += 1);
+}
+
+/// Runs the destructors of every item whose grace period has ended, returning how many ran.
+pub fn collect() -> usize {
+    let mut count = 0;
+
+    // take one item at a time, so IRQs aren't masked while the destructors run
+    while let Some(item) = with_queue(|queue| {
+        let epoch = queue.epoch;
+        queue
+            .items
+            .iter_mut()
+            .find(|slot| slot.map_or(false, |item| item.epoch < epoch))?
+            .take()
+    }) {
+        // SAFETY: a quiescent state has passed since the item was queued, so no reader can still
+        // be using it, and defer_free's caller promised the destructor is safe to call then.
+        unsafe { (item.destructor)(item.ptr) };
+        count += 1;
+    }
+
+    count
+}
+
+/// Returns the number of items waiting to be freed.
+pub fn pending() -> usize {
+    with_queue(|queue| queue.items.iter().flatten().count())
+}
+
+fn with_queue<R>(f: impl FnOnce(&mut Queue) -> R) -> R {
+    // SAFETY: the queue is only accessed with interrupts masked, and thus by one caller at once.
+    without_interrupts(|| f(unsafe { &mut QUEUE }))
+}
@@ -45,6 +45,19 @@ impl<S: RegisterSpec + RegisterWritable> Register<S> {
     }
 }
 
+impl<S: RegisterSpec + RegisterReadable + RegisterWritable> Register<S> {
+    /// Reads the current value of the register, then writes back a value built by an instance of
+    /// [`RegisterWriter`], initialised to the value read.
+    ///
+    /// This is not atomic, so the caller must ensure that nothing else writes to the register in
+    /// between, and should only use it for registers whose fields are not modified by hardware.
+    pub fn modify(&self, writer: impl FnOnce(&mut RegisterWriter<S>)) {
+        let mut w = RegisterWriter::new(self.0.get());
+        writer(&mut w);
+        self.0.set(w.bits);
+    }
+}
+
 impl<S: RegisterSpec + RegisterInitial> Register<S> {
     /// Writes a value built by an instance of [`RegisterWriter`], initialised to the register's
     /// initial value (provided by [`RegisterInitial`]), to the register.
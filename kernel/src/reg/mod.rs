@@ -1,7 +1,10 @@
 //! Provides safe, strongly-typed access to registers (e.g. memory-mapped or system registers).
+use core::any::type_name;
 use core::cmp;
 use core::ops::{self, RangeInclusive};
 
+use num::FromBits;
+
 pub mod memory_mapped;
 pub mod system;
 
@@ -75,6 +78,10 @@ impl<S: RegisterSpec> RegisterReader<S> {
 }
 
 impl<S: RegisterSpec> RegisterWriter<S> {
+    fn new(bits: S::Bits) -> Self {
+        Self { bits }
+    }
+
     fn zero() -> Self {
         Self {
             bits: S::Bits::zero(),
@@ -114,6 +121,17 @@ impl<S: RegisterSpec> RegisterReader<S> {
 
         (self.bits >> offset) & S::Bits::mask(size)
     }
+
+    /// Returns the value of a contiguous bit field (see [`RegisterReader::field`]) as a narrower
+    /// type.
+    ///
+    /// Panics if the value doesn't fit in `T`, which means the field is wider than `T`.
+    pub fn field_as<T: FromBits<S::Bits>>(&self, range: RangeInclusive<usize>) -> T {
+        let bits = self.field(range.clone());
+
+        T::try_from_bits(bits)
+            .unwrap_or_else(|| panic!("field {range:?} doesn't fit in {}", type_name::<T>()))
+    }
 }
 
 impl<S: RegisterSpec> RegisterWriter<S> {
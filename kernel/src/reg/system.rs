@@ -59,6 +59,22 @@ impl<S: SystemRegisterSpec + RegisterWritable> Register<S> {
     }
 }
 
+impl<S: SystemRegisterSpec + RegisterReadable + RegisterWritable> Register<S> {
+    /// Reads the current value of the register, then writes back a value built by an instance of
+    /// [`RegisterWriter`], initialised to the value read.
+    ///
+    /// This is for registers with fields that must be preserved, like RES1 bits or settings made
+    /// elsewhere, where [`Register::write_initial`] would clobber them.
+    pub fn modify(&self, writer: impl FnOnce(&mut RegisterWriter<S>)) {
+        // SAFETY: the register is readable.
+        let mut w = RegisterWriter::new(unsafe { S::mrs() });
+        writer(&mut w);
+        // SAFETY: the register is writable, and the value is what it held before, with only the
+        // changes made through the writer's setters, whose callers ensure that they're valid.
+        unsafe { S::msr(w.bits) }
+    }
+}
+
 impl<S: SystemRegisterSpec + RegisterWritable + RegisterInitial> Register<S> {
     /// Writes a value built by an instance of [`RegisterWriter`], initialised to the register's
     /// initial value (provided by [`RegisterInitial`]), to the register.
@@ -1,47 +1,571 @@
+use core::ffi::{c_char, CStr};
+use core::mem::size_of;
+use core::ops::Range;
+use core::{array, iter, ptr};
+
+use abi::Errno;
+use fdt::Fdt;
+use hal::Timer;
+use id_bitmap::IdBitmap;
+use trace_format::{Event, Reason};
+
+use crate::addr::VirtAddr;
+use crate::address_space::{Backing, Region};
+use crate::error::KernelError;
+use crate::sync::without_interrupts;
 use crate::task::{Context, Task};
+use crate::units::HexRange;
+use crate::{
+    board, cmdline, irq, linker_symbols, mm, pmu, reclaim, signal, stats, syscall, timer, trace,
+    SCHEDULER,
+};
+
+/// Creates the scheduler, which kernel_main starts once every initcall has run.
+fn init(_fdt: &Fdt) -> Result<(), KernelError> {
+    // SAFETY: the scheduler isn't used until it's started, after initcalls have run.
+    unsafe { SCHEDULER.get_or_init(Scheduler::new) };
+
+    Ok(())
+}
+initcall!(late, init);
 
 pub struct Scheduler {
-    tasks: [Task; 2],
+    /// Every task, indexed by task id. A task that has exited keeps its id, and its exit status,
+    /// until a task waiting for its group reaps it (see [`Scheduler::wait_group`]).
+    tasks: [Option<Task>; Self::MAX_TASKS],
+    /// Which task ids are in use.
+    ids: IdBitmap<1>,
     current_index: usize,
+    /// Number of timer ticks the current task has run for since it was last scheduled.
+    ticks: usize,
+    /// Whether [`Scheduler::start`] has been called, so exceptions are taken from tasks.
+    started: bool,
+    /// Number of timer ticks a task that isn't preemptible may run for without yielding, before
+    /// the watchdog preempts it anyway.
+    watchdog_ticks: usize,
 }
 
 impl Scheduler {
+    /// Number of timer ticks a task may run for before it is preempted.
+    pub const TICKS_PER_SLICE: usize = 2;
+    /// Maximum number of tasks, including those created at boot.
+    const MAX_TASKS: usize = 8;
+    /// Id of the IRQ thread, which is the third boot task.
+    const IRQ_THREAD: usize = 2;
+    /// Size of the user and kernel stacks of a spawned task, in bytes.
+    const SPAWN_STACK_SIZE: usize = 0x4000;
+    /// Time a task that isn't preemptible may run for without yielding, unless changed with
+    /// `watchdog=<ms>` on the command line.
+    const DEFAULT_WATCHDOG_MS: u64 = 1000;
+
     pub fn new() -> Self {
-        extern "C" {
-            static TASK1_INITIAL_SP: ();
-            static TASK1_KERNEL_INITIAL_SP: ();
-            static TASK2_INITIAL_SP: ();
-            static TASK2_KERNEL_INITIAL_SP: ();
-        }
-
-        let task_context =
-            Context::new(task1 as *const _, unsafe { &TASK1_INITIAL_SP } as *const _);
-        let task1 = Task::new(unsafe { &TASK1_KERNEL_INITIAL_SP }, task_context);
-        let task_context =
-            Context::new(task2 as *const _, unsafe { &TASK2_INITIAL_SP } as *const _);
-        let task2 = Task::new(unsafe { &TASK2_KERNEL_INITIAL_SP }, task_context);
-
-        Self {
-            tasks: [task1, task2],
+        // the tasks are built in place, since they're too big to move around on the boot stack
+        let mut scheduler = Self {
+            tasks: array::from_fn(|_| None),
+            ids: IdBitmap::new(Self::MAX_TASKS),
             current_index: 0,
-        }
+            ticks: 0,
+            started: false,
+            watchdog_ticks: watchdog_ticks(),
+        };
+
+        // in id order, so the IRQ thread is Self::IRQ_THREAD
+        scheduler.add_boot_task(
+            "task1",
+            Context::new(
+                task1 as *const _,
+                linker_symbols::task1_stack_top().as_ptr(),
+            ),
+            linker_symbols::task1_kernel_stack_bottom()..linker_symbols::task1_kernel_stack_top(),
+            linker_symbols::task1_stack_bottom()..linker_symbols::task1_stack_top(),
+        );
+        scheduler.add_boot_task(
+            "task2",
+            Context::new(
+                task2 as *const _,
+                linker_symbols::task2_stack_top().as_ptr(),
+            ),
+            linker_symbols::task2_kernel_stack_bottom()..linker_symbols::task2_kernel_stack_top(),
+            linker_symbols::task2_stack_bottom()..linker_symbols::task2_stack_top(),
+        );
+        let irq_thread = scheduler.add_boot_task(
+            "irq_thread",
+            Context::new_kernel(
+                irq::irq_thread as *const _,
+                linker_symbols::irq_thread_stack_top().as_ptr(),
+            ),
+            linker_symbols::irq_thread_kernel_stack_bottom()
+                ..linker_symbols::irq_thread_kernel_stack_top(),
+            linker_symbols::irq_thread_stack_bottom()..linker_symbols::irq_thread_stack_top(),
+        );
+        // threaded interrupt handlers should run to completion, and the IRQ thread yields as soon
+        // as there are none left to run, but the console commands it runs can still be preempted
+        // (see console.rs)
+        irq_thread.set_preemptible(false);
+
+        scheduler
+    }
+
+    /// Adds a task that runs from boot, with the given kernel stack and stack, which leads a task
+    /// group of its own.
+    fn add_boot_task(
+        &mut self,
+        name: &'static str,
+        context: Context,
+        kernel_stack: Range<VirtAddr>,
+        stack: Range<VirtAddr>,
+    ) -> &mut Task {
+        let id = self.ids.allocate().expect("room for the boot tasks");
+        let task = self.tasks[id].insert(Task::new(name, kernel_stack, context));
+        add_regions(task, stack).expect("room for the boot tasks' regions");
+        task.set_group(id);
+
+        task
     }
 
+    /// Accounts for a timer tick, preempting the current task if its time slice has run out.
+    ///
+    /// A task that isn't preemptible (see [`Task::set_preemptible`]) keeps running past the end of
+    /// its time slice, until it has run for the watchdog's budget without yielding, when it's
+    /// preempted anyway, with a warning.
     pub fn schedule(&mut self) -> &Task {
-        self.current_index += 1;
-        self.current_index %= 4;
+        self.ticks += 1;
+        self.current_mut().tick();
+        if self.ticks >= Self::TICKS_PER_SLICE {
+            if self.current().preemptible() {
+                self.switch(Reason::Preempted);
+            } else if self.ticks >= self.watchdog_ticks {
+                log::warn!(
+                    "task {} ({}) ran for {}ms without yielding, so preempting it",
+                    self.current_index,
+                    self.current().name(),
+                    self.ticks as u64 * timer::TICK_MS,
+                );
+                self.switch(Reason::Preempted);
+            }
+        }
+
+        self.current()
+    }
+
+    /// Gives up the remainder of the current task's time slice, switching to the next task.
+    pub fn yield_current(&mut self) -> &Task {
+        self.switch(Reason::Yielded);
+
+        self.current()
+    }
+
+    /// Ends the current task with exit status `status`, switching to the next task.
+    pub fn exit_current(&mut self, status: u8) -> &Task {
+        let id = self.current_index;
+        self.current_mut().exit(status, board::timer().now());
+        log::debug!(
+            "task {id} ({}) exited with status {status}",
+            self.current().name()
+        );
+        self.switch(Reason::Exited);
+
+        self.current()
+    }
+
+    /// Reaps every task in task group `group` other than the current one, once they've all exited,
+    /// returning the status of the first of them to exit with a status other than 0, or 0 if none
+    /// did. Returns [`KernelError::WouldBlock`] while any of them is still running.
+    pub fn wait_group(&mut self, group: usize) -> Result<u8, KernelError> {
+        let current = self.current_index;
+        let (tasks, _) = self.tasks();
+        let mut members = 0;
+        // (when, status) of the earliest exit with a status other than 0
+        let mut failed = None::<(u64, u8)>;
+        for (_, task) in tasks.filter(|&(id, task)| id != current && task.group() == group) {
+            let Some((status, when)) = task.exited() else {
+                return Err(KernelError::WouldBlock);
+            };
+            members += 1;
+            if status != 0 && failed.map_or(true, |(first, _)| when < first) {
+                failed = Some((when, status));
+            }
+        }
+        if members == 0 {
+            return Err(KernelError::NoSuchGroup { group });
+        }
+
+        for id in 0..self.tasks.len() {
+            let member = self.tasks[id]
+                .as_ref()
+                .map_or(false, |task| task.group() == group);
+            if id != current && member {
+                self.reap(id);
+            }
+        }
+
+        Ok(failed.map_or(0, |(_, status)| status))
+    }
+
+    /// Removes task `id`, which has exited, freeing its id and, if it was spawned, its stacks.
+    fn reap(&mut self, id: usize) {
+        let task = self.tasks[id].take().expect("task to exist");
+        debug_assert!(task.exited().is_some());
+        self.ids.free(id).expect("task's id to be allocated");
+        // the boot tasks' stacks are reserved in linker.ld rather than allocated
+        if task.parent().is_none() {
+            return;
+        }
 
-        &self.tasks[self.current_index >> 1]
+        let kernel_stack = task.kernel_stack();
+        let stacks = task
+            .address_space()
+            .regions()
+            .filter(|region| region.backing == Backing::Anonymous)
+            .map(|region| region.range.start.addr()..region.range.end.addr());
+        for range in iter::once(kernel_stack).chain(stacks) {
+            let len = range.end - range.start;
+            if let Err(error) = mm::free_pages(VirtAddr::new(range.start), len) {
+                let range = HexRange(range.start, range.end);
+                log::warn!("failed to free {range} of task {id}: {error}");
+            }
+        }
     }
 
     pub fn start(&mut self) -> ! {
-        self.tasks[self.current_index >> 1].start();
+        self.started = true;
+        self.trace(Reason::Start);
+        // every task has been waiting since now, rather than since it was created during boot
+        let now = board::timer().now();
+        for task in self.tasks.iter_mut().flatten() {
+            task.take_elapsed(now);
+        }
+        self.current().start();
+    }
+
+    /// Returns every task with its id, and the id of the one that's running.
+    pub fn tasks(&self) -> (impl Iterator<Item = (usize, &Task)>, usize) {
+        let tasks = self.tasks.iter().enumerate();
+        let tasks = tasks.filter_map(|(id, task)| Some((id, task.as_ref()?)));
+
+        (tasks, self.current_index)
+    }
+
+    /// Creates a task that starts at `entry` with the given arguments, returning its id.
+    ///
+    /// The arguments are NUL-terminated strings, one after another, which are copied to the new
+    /// task's stack and passed to `entry` like `main` in C (see [`syscall::TaskEntry`]). The task
+    /// shares the kernel's address space, because there are no others yet, and it runs once the
+    /// current task's time slice is over. It starts with the same handles open as the current
+    /// task, so the current task can give it a pipe to read from or write to.
+    pub fn spawn(&mut self, entry: usize, args: &[u8]) -> Result<usize, KernelError> {
+        if !(linker_symbols::kernel_start()..linker_symbols::kernel_end())
+            .contains(&VirtAddr::new(entry))
+        {
+            return Err(KernelError::InvalidArgument {
+                reason: "entry point is outside the kernel image",
+            });
+        }
+        if args.last().map_or(false, |&byte| byte != 0) {
+            return Err(KernelError::InvalidArgument {
+                reason: "arguments must be NUL-terminated",
+            });
+        }
+        let Some(id) = self.ids.allocate() else {
+            return Err(KernelError::TooManyTasks {
+                max: Self::MAX_TASKS,
+            });
+        };
+        // once the task exists, its id is freed when it's reaped (see Self::reap)
+        let result = self.create(id, entry, args);
+        if result.is_err() {
+            self.ids.free(id).expect("id to have just been allocated");
+        }
+
+        result
+    }
+
+    /// Creates a spawned task with id `id` (see [`Self::spawn`]).
+    fn create(&mut self, id: usize, entry: usize, args: &[u8]) -> Result<usize, KernelError> {
+        // from the top of the stack down: the strings, then argv and envp, which are arrays of
+        // pointers terminated by a null pointer. envp is always empty for now. the stack is
+        // page-aligned, so we can work out where they go before allocating it.
+        if args.len() > Self::SPAWN_STACK_SIZE / 2 {
+            return Err(KernelError::InvalidArgument {
+                reason: "arguments must fit in half of the new task's stack",
+            });
+        }
+        let argc = args.iter().filter(|&&byte| byte == 0).count();
+        let strings_offset = (Self::SPAWN_STACK_SIZE - args.len()) & !(size_of::<usize>() - 1);
+        let envp_offset = strings_offset - size_of::<usize>();
+        let argv_offset = envp_offset.checked_sub((argc + 1) * size_of::<usize>());
+        let sp_offset = argv_offset.map(|offset| offset & !0xF);
+        if sp_offset.map_or(true, |offset| offset < Self::SPAWN_STACK_SIZE / 2) {
+            return Err(KernelError::InvalidArgument {
+                reason: "arguments must fit in half of the new task's stack",
+            });
+        }
+
+        // both stacks are freed if anything fails before the task is created
+        let stack = mm::Pages::alloc(Self::SPAWN_STACK_SIZE)?;
+        let kernel_stack = mm::Pages::alloc(Self::SPAWN_STACK_SIZE)?;
+        let stack_bottom = stack.addr().addr();
+        let stack_top = stack_bottom + Self::SPAWN_STACK_SIZE;
+        let strings = stack_bottom + strings_offset;
+        let envp = stack_bottom + envp_offset;
+        let argv = stack_bottom + argv_offset.expect("arguments to have been checked");
+        let sp = argv & !0xF;
+
+        // SAFETY: the stack was just allocated and mapped, and we've checked that everything we
+        // write is within it.
+        unsafe {
+            ptr::copy_nonoverlapping(args.as_ptr(), strings as *mut u8, args.len());
+            let argv = argv as *mut usize;
+            let mut string = strings;
+            for i in 0..argc {
+                argv.add(i).write(string);
+                string += args[string - strings..]
+                    .iter()
+                    .position(|&b| b == 0)
+                    .unwrap()
+                    + 1;
+            }
+            argv.add(argc).write(0);
+            (envp as *mut usize).write(0);
+        }
+
+        let mut context = Context::new(entry as *const _, sp as *const _);
+        context.set_x(0, argc as u64);
+        context.set_x(1, argv as u64);
+        context.set_x(2, envp as u64);
+        let kernel_stack_bottom = kernel_stack.addr();
+        let kernel_stack_top = VirtAddr::new(kernel_stack_bottom.addr() + Self::SPAWN_STACK_SIZE);
+        let mut task = Task::new("spawned", kernel_stack_bottom..kernel_stack_top, context);
+        add_regions(&mut task, stack.addr()..VirtAddr::new(stack_top))?;
+        task.inherit_handles(self.current());
+        task.set_parent(self.current_index);
+        task.set_group(self.current().group());
+        // once the task exists, its stacks are freed when it's reaped (see Self::reap)
+        stack.keep();
+        kernel_stack.keep();
+        self.tasks[id] = Some(task);
+        log::debug!("spawned task {id} at {entry:#x}, with {argc} arguments");
+
+        Ok(id)
+    }
+
+    /// Panics unless `context` is where entry.s saves the running task's context, on `when`
+    /// (exception entry or exit): 16-byte aligned, like SP_EL1 must be, and within the task's
+    /// kernel stack. This catches entry.s and the `Context` struct drifting apart.
+    ///
+    /// Nothing is checked until the scheduler has started, because the boot-time vector check
+    /// saves its context on the boot stack.
+    pub fn check_context(&self, context: *const Context, when: &str) {
+        if !self.started {
+            return;
+        }
+
+        let task = self.current();
+        let address = context as usize;
+        assert!(
+            address % 16 == 0,
+            "exception {when}: context at {address:#x} isn't 16-byte aligned, so neither was SP_EL1"
+        );
+        let stack = task.kernel_stack();
+        assert!(
+            stack.start <= address && address + size_of::<Context>() <= stack.end,
+            "exception {when}: context at {address:#x} isn't within the kernel stack of {} ({:#x}..{:#x})",
+            task.name(),
+            stack.start,
+            stack.end,
+        );
+    }
+
+    fn current(&self) -> &Task {
+        self.tasks[self.current_index]
+            .as_ref()
+            .expect("current task to exist")
+    }
+
+    /// Returns the number of timer ticks a task that isn't preemptible may run for without
+    /// yielding.
+    pub fn watchdog_ticks(&self) -> usize {
+        self.watchdog_ticks
+    }
+
+    /// Returns the id of the running task.
+    pub fn current_id(&self) -> usize {
+        self.current_index
+    }
+
+    /// Returns the task with the given id, if there is one.
+    pub fn task_mut(&mut self, id: usize) -> Option<&mut Task> {
+        self.tasks.get_mut(id)?.as_mut()
+    }
+
+    /// Returns every task in task group `group`, with their ids.
+    ///
+    /// Task groups are sets of related tasks, like a pipeline and the tasks it spawns, which can be
+    /// signalled together. Each group is named by the id of its leader, which started the group
+    /// with the `new_group` system call (or is a boot task), and spawned tasks join the group of
+    /// the task that spawned them. A group ends once its tasks have exited and been reaped (see
+    /// [`Self::wait_group`]).
+    pub fn group_mut(&mut self, group: usize) -> impl Iterator<Item = (usize, &mut Task)> {
+        let tasks = self.tasks.iter_mut().enumerate();
+
+        tasks.filter_map(move |(id, task)| {
+            Some((id, task.as_mut()?)).filter(|(_, task)| task.group() == group)
+        })
+    }
+
+    /// Returns the running task.
+    pub fn current_mut(&mut self) -> &mut Task {
+        self.tasks[self.current_index]
+            .as_mut()
+            .expect("current task to exist")
+    }
+
+    fn switch(&mut self, reason: Reason) {
+        let now = board::timer().now();
+        if let Some(task) = &mut self.tasks[self.current_index] {
+            task.check_kernel_stack();
+            task.add_pmu_counts(pmu::take());
+            let ran = task.take_elapsed(now);
+            self.trace_duration(Event::TaskRan {
+                task: self.current_index as u8,
+                ran: ran.try_into().unwrap_or(u32::MAX),
+            });
+        }
+        let previous_index = self.current_index;
+        // the IRQ thread never exits, so this finds a task that hasn't eventually
+        loop {
+            self.current_index += 1;
+            self.current_index %= self.tasks.len();
+            let task = &self.tasks[self.current_index];
+            if task.as_ref().map_or(false, |task| task.exited().is_none()) {
+                break;
+            }
+        }
+        if self.current_index != previous_index {
+            stats::CONTEXT_SWITCHES.increment();
+        }
+        if self.current_index == Self::IRQ_THREAD {
+            irq::thread_scheduled(now);
+        }
+        reclaim::quiescent();
+        self.ticks = 0;
+        self.trace(reason);
+        let waited = self.current_mut().take_elapsed(now);
+        self.trace_duration(Event::TaskWaited {
+            task: self.current_index as u8,
+            waited: waited.try_into().unwrap_or(u32::MAX),
+        });
+    }
+
+    /// Records that the current task was picked to run next.
+    fn trace(&self, reason: Reason) {
+        // every task that hasn't exited is always ready to run, so the others are all waiting in
+        // the queue
+        let ready = self.tasks.iter().flatten();
+        let event = Event::TaskPicked {
+            task: self.current_index as u8,
+            reason,
+            ready: (ready.filter(|task| task.exited().is_none()).count() - 1) as u8,
+        };
+
+        // SAFETY: the scheduler is only used from exception handlers and kernel_main, with
+        // exceptions masked, so it's never called concurrently.
+        unsafe { trace::record(event) };
+    }
+
+    /// Records how long a task ran or waited for, if per-task scheduling statistics are enabled.
+    fn trace_duration(&self, event: Event) {
+        if trace::sched_stats() {
+            // SAFETY: see trace.
+            unsafe { trace::record(event) };
+        }
     }
 }
 
+/// Runs `f` with the current task preemptible, then makes it as preemptible as it was before.
+///
+/// This lets a task that isn't preemptible, like the IRQ thread, do work that isn't latency
+/// critical and may take a while, like a console command, without starving other tasks until the
+/// watchdog preempts it.
+pub fn preemptible<R>(f: impl FnOnce() -> R) -> R {
+    let previous = set_current_preemptible(true);
+    let result = f();
+    if let Some(previous) = previous {
+        set_current_preemptible(previous);
+    }
+
+    result
+}
+
+/// Sets whether the current task is preemptible, returning whether it was, unless there's no
+/// scheduler yet.
+fn set_current_preemptible(preemptible: bool) -> Option<bool> {
+    without_interrupts(|| {
+        // SAFETY: the scheduler is only used with interrupts masked, and thus by one caller at
+        // once.
+        let task = unsafe { SCHEDULER.get_mut() }?.current_mut();
+        let previous = task.preemptible();
+        task.set_preemptible(preemptible);
+
+        Some(previous)
+    })
+}
+
+/// Returns the watchdog's budget in timer ticks, from `watchdog=<ms>` on the command line, which is
+/// rounded up to a whole number of ticks.
+fn watchdog_ticks() -> usize {
+    let ms = match cmdline::option("watchdog").map(str::parse::<u64>) {
+        Some(Ok(ms)) if ms > 0 => ms,
+        Some(_) => {
+            log::warn!(
+                "watchdog={} is not a positive number of milliseconds, so using {}",
+                cmdline::option("watchdog").unwrap_or_default(),
+                Scheduler::DEFAULT_WATCHDOG_MS
+            );
+            Scheduler::DEFAULT_WATCHDOG_MS
+        }
+        None => Scheduler::DEFAULT_WATCHDOG_MS,
+    };
+
+    ms.div_ceil(timer::TICK_MS) as usize
+}
+
+/// Records the memory a task runs with in its address space: the kernel image, and its stack.
+fn add_regions(task: &mut Task, stack: Range<VirtAddr>) -> Result<(), KernelError> {
+    let address_space = task.address_space_mut();
+    address_space.insert(Region::kernel_image())?;
+    address_space.insert(Region::stack(stack))
+}
+
 fn task1() {
     log::trace!("task1 start");
 
+    let mut build_info = [0; 256];
+    match syscall::build_info(&mut build_info) {
+        Ok(len) => {
+            let build_info = &build_info[..len.min(build_info.len())];
+            let build_info = core::str::from_utf8(build_info).unwrap_or("(invalid)");
+            log::debug!("task1 running on {build_info}");
+        }
+        Err(errno) => log::warn!("task1 failed to get build info: {errno:?}"),
+    }
+
+    if let Err(errno) = run_echo() {
+        log::warn!("task1 failed to run echo: {errno:?}");
+    }
+    if let Err(errno) = check_yield_switches() {
+        log::warn!("task1 failed to get metrics: {errno:?}");
+    }
+    if let Err(errno) = check_trace_snapshot() {
+        log::warn!("task1 failed to snapshot the trace: {errno:?}");
+    }
+    if let Err(errno) = check_log_sinks() {
+        log::warn!("task1 failed to change the log sinks: {errno:?}");
+    }
+    if let Err(errno) = check_group_wait() {
+        log::warn!("task1 failed to wait for a task group: {errno:?}");
+    }
+
     loop {
         log::trace!("task1");
         for _ in 0..500000 {}
@@ -51,13 +575,214 @@ fn task1() {
 fn task2() {
     log::trace!("task2 start");
 
-    let mut x = 0;
     loop {
         log::trace!("task2");
         for _ in 0..1000000 {}
-        x += 1;
-        if x > 10 {
-            unsafe { core::arch::asm!("svc #0") }
+
+        // task2 has nothing better to do, so let task1 run without waiting for the timer
+        syscall::yield_now();
+    }
+}
+
+/// Spawns [`echo`] with a pipe as its output, and logs what it writes.
+fn run_echo() -> Result<(), Errno> {
+    let (output, input) = syscall::pipe()?;
+    let id = syscall::spawn(echo, b"echo\0hello\0world\0")?;
+    log::debug!("task1 spawned echo as task {id}");
+
+    // echo has its own handle to the write end, so we'll see the end once echo closes it
+    syscall::close(input)?;
+    let mut line = [0; 64];
+    let mut len = 0;
+    while len < line.len() {
+        match syscall::read(output, &mut line[len..])? {
+            0 => break,
+            n => len += n,
+        }
+    }
+    syscall::close(output)?;
+    let line = core::str::from_utf8(&line[..len]).unwrap_or("(invalid)");
+    log::info!("echo said: {line:?}");
+
+    // echo has nothing left to do, but it can still handle signals
+    syscall::kill(id, abi::signal::USER)?;
+    // echo leads a task group of its own, so this signals echo again, but not task1
+    let count = syscall::kill_group(id, abi::signal::USER)?;
+    log::debug!("task1 signalled {count} tasks in echo's group");
+
+    Ok(())
+}
+
+/// Checks that yielding switches away from task1 and back, using the kernel's metrics.
+fn check_yield_switches() -> Result<(), Errno> {
+    let before = syscall::metrics()?;
+    syscall::yield_now();
+    let after = syscall::metrics()?.since(&before);
+
+    // the other tasks may yield or be preempted in the meantime, but never less than this
+    if after.context_switches < 2 {
+        log::warn!("task1 yielded, but saw {after:?}");
+    } else {
+        log::debug!("task1 yielded, and saw {after:?}");
+    }
+
+    Ok(())
+}
+
+/// Takes a snapshot of the kernel's trace, and checks that it recorded task1 yielding, which
+/// [`check_yield_switches`] just did.
+fn check_trace_snapshot() -> Result<(), Errno> {
+    // SAFETY: task1 doesn't take another trace snapshot while it uses this one.
+    let bytes = unsafe { syscall::snapshot(abi::snapshot::TRACE)? };
+    let trace = match trace_format::decode(bytes) {
+        Ok(trace) => trace,
+        Err(error) => {
+            log::warn!("task1 took a trace snapshot it can't decode: {error:?}");
+            return Ok(());
+        }
+    };
+
+    let (mut events, mut yields) = (0, 0);
+    for (_, event) in trace.events().flatten() {
+        events += 1;
+        if let Event::TaskPicked {
+            reason: Reason::Yielded,
+            ..
+        } = event
+        {
+            yields += 1;
         }
     }
+    if yields == 0 {
+        log::warn!("task1 yielded, but its trace snapshot of {events} events has no yields");
+    } else {
+        log::debug!(
+            "task1 took a trace snapshot of {events} events ({} dropped), with {yields} yields",
+            trace.dropped
+        );
+    }
+
+    Ok(())
+}
+
+/// Writes the log to the console UART alone for a moment, then to the sinks it was written to
+/// before, checking that the kernel reports the change.
+fn check_log_sinks() -> Result<(), Errno> {
+    let before = syscall::log_sinks(None)?;
+    let previous = syscall::log_sinks(Some(abi::log_sink::UART))?;
+    let during = syscall::log_sinks(Some(before))?;
+
+    if previous != before || during != abi::log_sink::UART {
+        log::warn!(
+            "task1 changed the log sinks from {before:#x}, but saw {previous:#x} then {during:#x}"
+        );
+    } else {
+        log::debug!("task1 changed the log sinks from {before:#x} and back");
+    }
+
+    Ok(())
+}
+
+/// Spawns [`exit_leader`], which leads a task group of tasks that exit with different statuses, and
+/// checks that waiting for the group sees the first status other than 0.
+fn check_group_wait() -> Result<(), Errno> {
+    let (output, input) = syscall::pipe()?;
+    let leader = syscall::spawn(exit_leader, b"exit_leader\0")?;
+    syscall::close(input)?;
+    // the leader writes once it has started its group, so there's something to wait for
+    let mut byte = [0];
+    let read = syscall::read(output, &mut byte)?;
+    syscall::close(output)?;
+    if read == 0 {
+        log::warn!("task1 saw exit_leader close its pipe without starting its group");
+        return Ok(());
+    }
+
+    let status = syscall::wait_group(leader)?;
+    if status != 3 {
+        log::warn!("task1 waited for exit_leader's group, but saw exit status {status}");
+    } else {
+        log::debug!("task1 waited for exit_leader's group, and saw exit status {status}");
+    }
+
+    Ok(())
+}
+
+/// Starts a task group, spawns [`exit_with`] into it twice, then tells [`check_group_wait`] to
+/// wait for the group, and exits with status 0.
+extern "C" fn exit_leader(_argc: usize, _argv: *const *const u8, _envp: *const *const u8) -> ! {
+    /// Handle that exit_leader writes to, which is the write end of [`check_group_wait`]'s pipe.
+    const OUTPUT: usize = 1;
+
+    if let Err(errno) = syscall::new_group() {
+        log::warn!("exit_leader failed to start a task group: {errno:?}");
+    }
+    for args in [b"exit_with\x000\0", b"exit_with\x003\0"] {
+        if let Err(errno) = syscall::spawn(exit_with, args) {
+            log::warn!("exit_leader failed to spawn exit_with: {errno:?}");
+        }
+    }
+    if let Err(errno) = syscall::write(OUTPUT, b"!") {
+        log::warn!("exit_leader failed to write: {errno:?}");
+    }
+
+    syscall::exit(0)
+}
+
+/// Exits with the status given as its argument, a single decimal digit.
+extern "C" fn exit_with(argc: usize, argv: *const *const u8, _envp: *const *const u8) -> ! {
+    if argc < 2 {
+        syscall::exit(1);
+    }
+    // SAFETY: spawn passes argc valid pointers to NUL-terminated strings in argv.
+    let arg = unsafe { CStr::from_ptr(*argv.add(1) as *const c_char) };
+    let status = match arg.to_bytes() {
+        &[digit @ b'0'..=b'9'] => digit - b'0',
+        _ => 1,
+    };
+
+    syscall::exit(status)
+}
+
+/// Writes its arguments to handle 1, like echo(1), then yields forever.
+extern "C" fn echo(argc: usize, argv: *const *const u8, _envp: *const *const u8) -> ! {
+    /// Handle that echo writes to, which is the write end of [`run_echo`]'s pipe.
+    const OUTPUT: usize = 1;
+
+    // so signalling echo's group doesn't signal task1 too, since echo started in task1's group
+    if let Err(errno) = syscall::new_group() {
+        log::warn!("echo failed to start a task group: {errno:?}");
+    }
+    if let Err(errno) = syscall::set_signal_handler(Some(echo_signal)) {
+        log::warn!("echo failed to set its signal handler: {errno:?}");
+    }
+
+    for i in 1..argc {
+        // SAFETY: spawn passes argc valid pointers to NUL-terminated strings in argv.
+        let arg = unsafe { CStr::from_ptr(*argv.add(i) as *const c_char) };
+        let separator: &[u8] = if i + 1 < argc { b" " } else { b"\n" };
+        for mut bytes in [arg.to_bytes(), separator] {
+            while !bytes.is_empty() {
+                match syscall::write(OUTPUT, bytes) {
+                    Ok(n) => bytes = &bytes[n..],
+                    Err(errno) => {
+                        log::warn!("echo failed to write: {errno:?}");
+                        break;
+                    }
+                }
+            }
+        }
+    }
+    let _ = syscall::close(OUTPUT);
+
+    loop {
+        syscall::yield_now();
+    }
+}
+
+/// Signal handler for [`echo`], which logs the signal and returns.
+extern "C" fn echo_signal(signal: u32, frame: *const signal::Frame) -> ! {
+    log::info!("echo got signal {signal}");
+
+    syscall::signal_return(frame)
 }
@@ -0,0 +1,284 @@
+//! Tests that run inside the kernel at boot, in kernels built with the `selftest` feature.
+//!
+//! A selftest kernel runs its tests once every initcall has run, then exits QEMU with semihosting
+//! instead of starting the scheduler. `cargo xtask ci` runs one and turns its output into a JUnit
+//! report, so the output format here MUST be kept in sync with xtask/src/ci.rs.
+//!
+//! If the host passes a fw_cfg file named [`SELECTION`], which `cargo xtask ci --selftest <name>`
+//! does, only the selftests named in it run.
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::addr::VirtAddr;
+use crate::error::KernelError;
+use crate::scheduler::Scheduler;
+use crate::virtio::queue::{Buffer, Virtqueue};
+use crate::{fuzz, fw_cfg, mm, reclaim, semihosting, tt, virtio, ALLOCATOR, SCHEDULER};
+
+/// Name of the fw_cfg file listing the selftests to run, separated by whitespace.
+const SELECTION: &str = "opt/micropuppy/selftests";
+
+/// A selftest, which returns the condition that failed, if any.
+struct Selftest {
+    name: &'static str,
+    function: fn() -> Result<(), &'static str>,
+}
+
+/// Fails the selftest, unless the condition holds.
+macro_rules! check {
+    ($condition:expr) => {
+        if !$condition {
+            return Err(concat!(file!(), ":", line!(), ": ", stringify!($condition)));
+        }
+    };
+}
+
+const SELFTESTS: &[Selftest] = &[
+    Selftest {
+        name: "allocator",
+        function: allocator,
+    },
+    Selftest {
+        name: "deferred_free",
+        function: deferred_free,
+    },
+    Selftest {
+        name: "kernel_not_writable_by_task",
+        function: kernel_not_writable_by_task,
+    },
+    Selftest {
+        name: "scheduler_initialised",
+        function: scheduler_initialised,
+    },
+    Selftest {
+        name: "syscall_fuzz",
+        function: syscall_fuzz,
+    },
+    Selftest {
+        name: "valloc",
+        function: valloc,
+    },
+    Selftest {
+        name: "virtio",
+        function: virtio,
+    },
+    Selftest {
+        name: "watchdog",
+        function: watchdog,
+    },
+];
+
+/// Runs every selftest, or those selected by the host, then exits QEMU with status 0 if they all
+/// passed, or 1 otherwise.
+pub fn run_all() -> ! {
+    let mut buffer = [0; 512];
+    let selection = match fw_cfg::read_file(SELECTION, &mut buffer) {
+        Ok(len) if len > buffer.len() => {
+            log::error!("{SELECTION} is longer than {} bytes", buffer.len());
+            semihosting::exit(1);
+        }
+        Ok(len) => match core::str::from_utf8(&buffer[..len]) {
+            Ok(selection) => Some(selection),
+            Err(_) => {
+                log::error!("{SELECTION} is not UTF-8");
+                semihosting::exit(1);
+            }
+        },
+        Err(KernelError::DeviceNotFound { .. } | KernelError::NoSuchFile { .. }) => None,
+        Err(error) => {
+            log::error!("failed to read {SELECTION}: {error}");
+            semihosting::exit(1);
+        }
+    };
+    if let Some(selection) = selection {
+        for name in selection.split_whitespace() {
+            if !SELFTESTS.iter().any(|selftest| selftest.name == name) {
+                log::warn!("no selftest named {name:?}");
+            }
+        }
+    }
+
+    let selected = SELFTESTS.iter().filter(|selftest| {
+        selection.map_or(true, |selection| {
+            selection
+                .split_whitespace()
+                .any(|name| name == selftest.name)
+        })
+    });
+    let mut ran = 0;
+    let mut failed = 0;
+
+    for selftest in selected {
+        ran += 1;
+        log::info!("selftest {} ...", selftest.name);
+        match (selftest.function)() {
+            Ok(()) => log::info!("selftest {} ... ok", selftest.name),
+            Err(condition) => {
+                log::error!("selftest {} ... FAILED: {condition}", selftest.name);
+                failed += 1;
+            }
+        }
+    }
+
+    let passed = ran - failed;
+    log::info!("selftest result: {passed} passed; {failed} failed");
+    semihosting::exit(if failed == 0 { 0 } else { 1 });
+}
+
+/// The page allocator hands out distinct pages, and catches double frees.
+fn allocator() -> Result<(), &'static str> {
+    // SAFETY: selftests run in kernel_main before the scheduler starts, so nothing else is using
+    // the allocator.
+    let Some(allocator) = (unsafe { ALLOCATOR.get_mut() }) else {
+        return Err("allocator not initialised");
+    };
+
+    let first = allocator.allocate(1).map_err(|_| "out of memory")?;
+    let second = allocator.allocate(1).map_err(|_| "out of memory")?;
+    check!(first.ptr != second.ptr);
+
+    let ptr = first.ptr;
+    check!(allocator.free(first).is_ok());
+    check!(allocator
+        .free(allocator::Allocation { ptr, size: 0 })
+        .is_err());
+    check!(allocator.free(second).is_ok());
+
+    Ok(())
+}
+
+/// Deferred frees wait for a quiescent state, then run their destructors exactly once.
+fn deferred_free() -> Result<(), &'static str> {
+    static FREED: AtomicUsize = AtomicUsize::new(0);
+
+    /// Adds the "pointer" to FREED, so we can tell which item was freed.
+    unsafe fn destructor(ptr: *mut ()) {
+        FREED.fetch_add(ptr as usize, Ordering::Relaxed);
+    }
+
+    // SAFETY: the destructor doesn't dereference the pointer.
+    unsafe { reclaim::defer_free(42 as *mut (), destructor) }.map_err(|_| "queue full")?;
+    check!(reclaim::pending() == 1);
+    check!(reclaim::collect() == 0);
+
+    reclaim::quiescent();
+    check!(reclaim::collect() == 1);
+    check!(FREED.load(Ordering::Relaxed) == 42);
+    check!(reclaim::pending() == 0);
+
+    Ok(())
+}
+
+/// Tasks can't write to the kernel image.
+fn kernel_not_writable_by_task() -> Result<(), &'static str> {
+    check!(!tt::is_writable_by_task(crate::kernel_main as usize));
+
+    Ok(())
+}
+
+/// The scheduler was created by its initcall, so the late level ran.
+fn scheduler_initialised() -> Result<(), &'static str> {
+    // SAFETY: see allocator.
+    check!(unsafe { SCHEDULER.get_mut() }.is_some());
+
+    Ok(())
+}
+
+/// Random system calls with adversarial arguments fail with errors, rather than panicking.
+fn syscall_fuzz() -> Result<(), &'static str> {
+    let summary = fuzz::run(2000)?;
+    log::info!(
+        "fuzzed with seed {:#x}: {} system calls succeeded, {} failed",
+        summary.seed,
+        summary.succeeded,
+        summary.failed
+    );
+    check!(summary.failed > 0);
+
+    Ok(())
+}
+
+/// valloc maps memory with a guard page after it, and vfree unmaps it again.
+fn valloc() -> Result<(), &'static str> {
+    let len = 3 * tt::PAGE_SIZE;
+    let start = mm::valloc(len).map_err(|_| "valloc failed")?.addr();
+    check!(mm::VALLOC_AREA.contains(&start));
+    check!((start..start + len)
+        .step_by(tt::PAGE_SIZE)
+        .all(tt::is_readable));
+    check!(!tt::is_readable(start + len));
+
+    // SAFETY: the memory was just allocated for us, and is mapped writable.
+    unsafe { (start as *mut u64).add(len / 8 - 1).write_volatile(0x5A) };
+
+    check!(mm::vfree(VirtAddr::new(start + 8), len).is_err());
+    check!(mm::vfree(VirtAddr::new(start), len).is_ok());
+    check!(!tt::is_readable(start));
+
+    Ok(())
+}
+
+/// The virtio transports were found, and a virtqueue hands out each of its descriptors once.
+fn virtio() -> Result<(), &'static str> {
+    check!(virtio::transports() > 0);
+
+    let mut queue = Virtqueue::new(0, 8).map_err(|_| "out of memory")?;
+    check!(Virtqueue::new(0, 6).is_err());
+    check!(queue.add(&[]).is_err());
+
+    // the buffers are never given to a device, so they can point anywhere
+    let buffer = Buffer {
+        pa: queue.descriptor_area(),
+        len: 16,
+        writable: false,
+    };
+    let mut heads = [0; 3];
+    for head in &mut heads {
+        *head = queue.add(&[buffer; 2]).map_err(|_| "queue full")?;
+    }
+    check!(queue.free() == 2);
+    check!(heads[0] != heads[1] && heads[1] != heads[2] && heads[0] != heads[2]);
+    check!(matches!(
+        queue.add(&[buffer; 3]),
+        Err(KernelError::WouldBlock)
+    ));
+    check!(queue.add(&[buffer; 2]).is_ok());
+    check!(queue.free() == 0);
+    check!(matches!(queue.pop_used(), Ok(None)));
+
+    Ok(())
+}
+
+/// A task that isn't preemptible keeps running past the end of its time slice, until it has run
+/// for the watchdog's budget, when it's preempted anyway.
+fn watchdog() -> Result<(), &'static str> {
+    /// Returns how many ticks the current task runs for before it's switched away from, if it's
+    /// switched away from within `limit` ticks.
+    fn ticks_until_switch(
+        scheduler: &mut Scheduler,
+        preemptible: bool,
+        limit: usize,
+    ) -> Option<usize> {
+        let id = scheduler.current_id();
+        let task = scheduler.current_mut();
+        let previous = task.preemptible();
+        task.set_preemptible(preemptible);
+        let ticks = (1..=limit).find(|_| {
+            scheduler.schedule();
+            scheduler.current_id() != id
+        });
+        scheduler.task_mut(id)?.set_preemptible(previous);
+
+        ticks
+    }
+
+    // SAFETY: see allocator. Selftest kernels never start the scheduler, so switching tasks here
+    // only changes which task it would have started with.
+    let scheduler = unsafe { SCHEDULER.get_mut() }.ok_or("scheduler not initialised")?;
+    let slice = Scheduler::TICKS_PER_SLICE;
+    let budget = scheduler.watchdog_ticks().max(slice);
+
+    check!(ticks_until_switch(scheduler, true, 2 * budget) == Some(slice));
+    check!(ticks_until_switch(scheduler, false, 2 * budget) == Some(budget));
+
+    Ok(())
+}
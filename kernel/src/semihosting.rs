@@ -0,0 +1,31 @@
+//! Arm semihosting, which lets the kernel ask QEMU (run with `-semihosting`) to do things for it.
+//!
+//! https://github.com/ARM-software/abi-aa/blob/2023Q3/semihosting/semihosting.rst
+use core::arch::asm;
+
+/// SYS_EXIT (0x18), which tells the host that the kernel has finished running.
+const SYS_EXIT: u64 = 0x18;
+/// ADP_Stopped_ApplicationExit, the exit reason that lets us pass an exit status.
+const ADP_STOPPED_APPLICATION_EXIT: u64 = 0x20026;
+
+/// Exits QEMU with the given exit status.
+///
+/// This only works if semihosting is enabled, otherwise the `hlt` is an undefined instruction.
+pub fn exit(status: u32) -> ! {
+    let block = [ADP_STOPPED_APPLICATION_EXIT, u64::from(status)];
+
+    // SAFETY: SYS_EXIT only reads the parameter block, and doesn't return if it succeeds.
+    unsafe {
+        asm!(
+            "hlt #0xf000",
+            in("x0") SYS_EXIT,
+            in("x1") block.as_ptr(),
+            options(nostack),
+        )
+    };
+
+    // if the host ignored the call, there's nothing left to do
+    loop {
+        core::hint::spin_loop();
+    }
+}
@@ -0,0 +1,186 @@
+//! Signals, which notify a task of something asynchronously, such as Ctrl+C on the console.
+//!
+//! Posting a signal to a task makes it pending. A task can take its pending signals by polling
+//! with a system call, or register a handler that the kernel diverts the task to when it's next
+//! about to return to it. The task's context is saved in a [`Frame`] on its stack while the
+//! handler runs, and the handler must finish by passing the frame to the `signal_return` system
+//! call, which restores it. Handlers don't nest, so other signals stay pending until then.
+use core::mem::size_of;
+use core::ptr;
+
+use bounded::bounds_checked;
+
+use crate::error::KernelError;
+use crate::task::Context;
+use crate::{tt, SCHEDULER};
+
+/// Bits of `PSTATE` a handler may change by returning: the condition flags, NZCV.
+const PSR_FLAGS: u64 = 0xF << 28;
+
+bounds_checked! {
+    /// A signal number, like those in [`abi::signal`].
+    #[derive(Clone, Copy, PartialEq)] pub struct Signal(u32 (1..=63));
+}
+const _: () = assert!(Signal::MAX.value() == abi::signal::MAX);
+
+impl Signal {
+    /// Sent for Ctrl+C on the console (see [`abi::signal::INTERRUPT`]).
+    pub const INTERRUPT: Self = match Self::new(abi::signal::INTERRUPT) {
+        Some(signal) => signal,
+        None => panic!("signal number out of bounds"),
+    };
+}
+
+/// The signals pending for a task, and how it handles them.
+#[derive(Debug, Default)]
+pub struct Signals {
+    /// Bit `n` is set if signal `n` is pending.
+    pending: u64,
+    /// Entry point of the task's handler, if it has one.
+    handler: Option<usize>,
+    /// Whether the task is running its handler, and hasn't returned from it yet.
+    handling: bool,
+}
+
+impl Signals {
+    /// Makes `signal` pending.
+    pub fn post(&mut self, signal: Signal) {
+        self.pending |= 1 << signal.value();
+    }
+
+    /// Returns the pending signals as a bit mask, and clears them.
+    pub fn take(&mut self) -> u64 {
+        core::mem::take(&mut self.pending)
+    }
+
+    /// Sets the handler entry point, or removes it if `None`.
+    pub fn set_handler(&mut self, handler: Option<usize>) {
+        self.handler = handler;
+    }
+
+    /// Takes the lowest pending signal and returns it with the handler, if it should be handled
+    /// now.
+    fn next(&mut self) -> Option<(Signal, usize)> {
+        let handler = self.handler.filter(|_| !self.handling)?;
+        if self.pending == 0 {
+            return None;
+        }
+
+        let signal = self.pending.trailing_zeros();
+        self.pending &= !(1 << signal);
+        self.handling = true;
+
+        // SAFETY: only valid signal numbers are ever posted.
+        Some((unsafe { Signal::unchecked_new(signal) }, handler))
+    }
+}
+
+/// The context of a task when it was diverted to its signal handler, which is saved on the
+/// task's stack until the handler returns.
+#[repr(C)]
+pub struct Frame {
+    context: Context,
+}
+
+/// Returns the task group that task `id` is in, so the whole group can be signalled.
+pub fn group_of(id: usize) -> Result<usize, KernelError> {
+    // SAFETY: the scheduler is only accessed from exception handlers and kernel_main with
+    // exceptions masked, or by the IRQ thread with interrupts masked.
+    let scheduler = unsafe { SCHEDULER.get_mut() }.ok_or(KernelError::NoSuchTask { id })?;
+    let task = scheduler
+        .task_mut(id)
+        .ok_or(KernelError::NoSuchTask { id })?;
+
+    Ok(task.group())
+}
+
+/// Posts `signal` to every task in task group `group`, returning how many there were.
+pub fn post_group(group: usize, signal: Signal) -> Result<usize, KernelError> {
+    // SAFETY: see group_of.
+    let scheduler = unsafe { SCHEDULER.get_mut() }.ok_or(KernelError::NoSuchGroup { group })?;
+    let mut count = 0;
+    // tasks that have exited can't handle signals, and are only waiting to be reaped
+    let tasks = scheduler.group_mut(group);
+    for (_, task) in tasks.filter(|(_, task)| task.exited().is_none()) {
+        task.signals_mut().post(signal);
+        count += 1;
+    }
+
+    match count {
+        0 => Err(KernelError::NoSuchGroup { group }),
+        count => Ok(count),
+    }
+}
+
+/// Diverts the task being returned to into its signal handler, if it has a signal to handle,
+/// returning the context to restore.
+///
+/// # Safety
+///
+/// Must only be called on the way out of an exception handler, with `context` being the context
+/// it's about to restore.
+pub unsafe fn deliver(context: *const Context) -> *const Context {
+    let Some(scheduler) = SCHEDULER.get_mut() else {
+        return context;
+    };
+    let task = scheduler.current_mut();
+    // the vector check at boot runs before the scheduler starts, with a context of its own
+    if !ptr::eq(task.context(), context) {
+        return context;
+    }
+    let Some((signal, handler)) = task.signals_mut().next() else {
+        return context;
+    };
+
+    let context = &mut *(context as *mut Context);
+    let frame = (context.sp() - size_of::<Frame>()) & !0xF;
+    // the frame is smaller than a page, so it can only span two pages
+    let end = frame + size_of::<Frame>() - 1;
+    if ![frame, end].into_iter().all(tt::is_writable_by_task) {
+        log::warn!("dropping signal {signal}, because the task's stack is full or unmapped");
+        task.signals_mut().handling = false;
+        return context;
+    }
+
+    (frame as *mut Frame).write(Frame {
+        context: context.clone(),
+    });
+    context.set_pc(handler);
+    context.set_sp(frame);
+    context.set_x(0, signal.value().into());
+    context.set_x(1, frame as u64);
+    // the handler must not return normally, so make sure it can't
+    context.set_x(30, 0);
+
+    context
+}
+
+/// Restores the context saved in the `frame` at `address` by [`deliver`], returning `x0` from it
+/// so it isn't clobbered by the system call's own result.
+pub fn restore(context: &mut Context, address: usize) -> Result<u64, KernelError> {
+    // SAFETY: see group_of.
+    let scheduler = unsafe { SCHEDULER.get_mut() }.expect("scheduler to be started");
+    let signals = scheduler.current_mut().signals_mut();
+    if !signals.handling {
+        return Err(KernelError::InvalidArgument {
+            reason: "not in a signal handler",
+        });
+    }
+    let end = address
+        .checked_add(size_of::<Frame>() - 1)
+        .ok_or(KernelError::Unmapped { address })?;
+    if address % 16 != 0 || ![address, end].into_iter().all(tt::is_readable_by_task) {
+        return Err(KernelError::Unmapped { address });
+    }
+
+    // SAFETY: we've checked that the task could read the frame itself, and it can't run while
+    // we read it.
+    let saved = unsafe { &(*(address as *const Frame)).context };
+    // the handler can't change the exception level or interrupt masks it returns to
+    let psr = context.psr() & !PSR_FLAGS | saved.psr() & PSR_FLAGS;
+    *context = saved.clone();
+    context.set_psr(psr);
+    signals.handling = false;
+
+    Ok(context.x(0))
+}
@@ -0,0 +1,153 @@
+//! Statistics collected by the kernel at runtime, for evaluating changes to the scheduler or to
+//! interrupt handling.
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::units::Ticks;
+
+/// Latency between the timer interrupt's programmed deadline (CNTP_CVAL_EL0) and entry to the
+/// interrupt handler, in counter ticks.
+pub static mut TIMER_LATENCY: Histogram = Histogram::new("timer interrupt latency");
+
+/// Latency between entry to the IRQ handler that queues an interrupt for the IRQ thread, and the
+/// scheduler next switching to the IRQ thread to run its handler, in counter ticks (see
+/// [`crate::irq::thread_scheduled`]).
+pub static mut WAKEUP_LATENCY: Histogram = Histogram::new("IRQ-to-task wakeup latency");
+
+/// Number of times the scheduler switched from one task to another.
+pub static CONTEXT_SWITCHES: Counter = Counter::new();
+
+/// Number of IRQs taken, including timer interrupts.
+pub static IRQS: Counter = Counter::new();
+
+/// Number of times each interrupt was acknowledged, by interrupt ID, not counting spurious
+/// interrupts.
+pub static INTERRUPTS: [Counter; gic::INTERRUPTS] = {
+    // Counter isn't Copy, but a const can be repeated
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ZERO: Counter = Counter::new();
+
+    [ZERO; gic::INTERRUPTS]
+};
+
+/// Number of timer interrupts taken.
+pub static TIMER_TICKS: Counter = Counter::new();
+
+/// Number of system calls made, whether or not they succeeded.
+pub static SYSCALLS: Counter = Counter::new();
+
+/// Number of pages allocated by the page allocator, for [`crate::mm`].
+pub static PAGES_ALLOCATED: Counter = Counter::new();
+
+/// Number of pages freed back to the page allocator, by [`crate::mm`].
+pub static PAGES_FREED: Counter = Counter::new();
+
+/// Returns the current value of every counter, for the `metrics` system call.
+pub fn metrics() -> abi::Metrics {
+    abi::Metrics {
+        context_switches: CONTEXT_SWITCHES.get(),
+        irqs: IRQS.get(),
+        timer_ticks: TIMER_TICKS.get(),
+        syscalls: SYSCALLS.get(),
+        pages_allocated: PAGES_ALLOCATED.get(),
+        pages_freed: PAGES_FREED.get(),
+    }
+}
+
+/// A counter that only ever goes up, which can be updated from anywhere without masking
+/// interrupts.
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub const fn new() -> Self {
+        Self(AtomicU64::new(0))
+    }
+
+    pub fn increment(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, n: u64) {
+        // the counters don't order anything else, they only need to add up eventually
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// A histogram of durations measured in counter ticks (CNTPCT_EL0), with power-of-two buckets.
+///
+/// Bucket 0 counts durations of 0 or 1 ticks, and each bucket `n > 0` counts durations in the
+/// range `2^n..2^(n+1)`.
+pub struct Histogram {
+    name: &'static str,
+    buckets: [u64; Self::BUCKETS],
+    count: u64,
+    min: u64,
+    max: u64,
+}
+
+impl Histogram {
+    const BUCKETS: usize = 32;
+
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            buckets: [0; Self::BUCKETS],
+            count: 0,
+            min: u64::MAX,
+            max: 0,
+        }
+    }
+
+    /// Records a single duration, in counter ticks.
+    pub fn record(&mut self, ticks: u64) {
+        let bucket = ticks.checked_ilog2().unwrap_or(0) as usize;
+        // durations too long for the last bucket are counted there anyway
+        let bucket = bucket.min(Self::BUCKETS - 1);
+
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.min = self.min.min(ticks);
+        self.max = self.max.max(ticks);
+    }
+
+    /// Records the duration between `start` and `end`, both counter values.
+    pub fn record_between(&mut self, start: u64, end: u64) {
+        self.record(end.saturating_sub(start));
+    }
+}
+
+impl fmt::Display for Histogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        /// Width of the bar for the fullest bucket.
+        const BAR_WIDTH: u64 = 40;
+
+        write!(f, "{} ({} samples", self.name, self.count)?;
+        if self.count == 0 {
+            return writeln!(f, ")");
+        }
+        writeln!(f, ", min {}, max {})", Ticks(self.min), Ticks(self.max))?;
+
+        // only print buckets between the first and last non-empty buckets
+        let first = self.buckets.iter().position(|&n| n > 0).unwrap_or(0);
+        let last = self.buckets.iter().rposition(|&n| n > 0).unwrap_or(0);
+        let fullest = self.buckets.iter().copied().max().unwrap_or(0);
+
+        for (bucket, &n) in self.buckets.iter().enumerate().take(last + 1).skip(first) {
+            let low = if bucket == 0 { 0 } else { 1u64 << bucket };
+            let high = (1u64 << (bucket + 1)) - 1;
+            let width = n * BAR_WIDTH / fullest;
+
+            write!(f, "  {low:>10}..={high:<10} {n:>8} |")?;
+            for _ in 0..width {
+                write!(f, "#")?;
+            }
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,63 @@
+//! Suspend-to-idle, the shallowest system sleep state, where the CPU waits for a wakeup interrupt
+//! with every device quiesced, rather than anything being powered down.
+//!
+//! Suspending runs every driver's suspend hook (see `driver::register_suspend`), disables every
+//! interrupt but the wakeup sources (see [`irq::enable_wakeup`]), and waits in WFI until one of
+//! them is pending, then undoes all that in reverse. It all happens with IRQs masked, and the timer
+//! is stopped by its suspend hook, so tasks are frozen simply by never being scheduled. They carry
+//! on where they left off once the system resumes, as if they'd been preempted for a long time.
+use core::arch::asm;
+
+use hal::Timer;
+
+use crate::error::KernelError;
+use crate::sync::without_interrupts;
+use crate::units::Ticks;
+use crate::{alarm, board, irq};
+
+/// Suspends the system until a wakeup interrupt, or until the alarm goes off after `seconds`.
+pub fn suspend(seconds: Option<u64>) -> Result<(), KernelError> {
+    if let Some(seconds) = seconds {
+        // the interrupt is what wakes us, so there's nothing for the callback to do
+        alarm::set_after(seconds, || {})?;
+    }
+
+    let start = board::timer().now();
+    log::info!("suspending");
+    let result = without_interrupts(|| {
+        for hooks in driver::suspend_hooks() {
+            (hooks.suspend)();
+        }
+
+        let result = if irq::suspend() == 0 {
+            Err(KernelError::InvalidArgument {
+                reason: "no wakeup source is enabled",
+            })
+        } else {
+            // a pending interrupt ends WFI even while IRQs are masked, but WFI can also end
+            // spuriously, so check that it was a wakeup source
+            while !irq::wakeup_pending() {
+                // SAFETY: waiting for an interrupt has no effect on memory.
+                unsafe { asm!("wfi", options(nomem, nostack)) };
+            }
+            Ok(())
+        };
+
+        irq::resume();
+        for hooks in driver::suspend_hooks().rev() {
+            (hooks.resume)();
+        }
+
+        result
+    });
+
+    // the alarm may not have gone off, if something else woke us
+    if seconds.is_some() {
+        alarm::cancel()?;
+    }
+    result?;
+    let elapsed = board::timer().now() - start;
+    log::info!("resumed after {}", Ticks(elapsed));
+
+    Ok(())
+}
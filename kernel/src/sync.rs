@@ -1,7 +1,12 @@
+use core::arch::asm;
 use core::sync::atomic::{AtomicBool, Ordering};
 
 use lock_api::{GuardSend, RawMutex};
 
+use crate::a53::daif::DAIF;
+use crate::nesting;
+use crate::reg::system::Register;
+
 pub struct RawSpinlock(AtomicBool);
 
 unsafe impl RawMutex for RawSpinlock {
@@ -11,6 +16,13 @@ unsafe impl RawMutex for RawSpinlock {
     type GuardMarker = GuardSend;
 
     fn lock(&self) {
+        if self.try_lock() {
+            return;
+        }
+        // there's only one CPU, so the lock is held by the interrupted task, which would never
+        // get to unlock it
+        nesting::assert_not_in_interrupt("waiting for a spinlock");
+
         // Note: This isn't the best way of implementing a spinlock, but it
         // suffices for the sake of this example.
         while !self.try_lock() {}
@@ -28,3 +40,18 @@ unsafe impl RawMutex for RawSpinlock {
 }
 
 pub type OnceCell<T> = generic_once_cell::OnceCell<RawSpinlock, T>;
+
+/// Runs `f` with IRQs masked, restoring the previous mask afterwards.
+pub fn without_interrupts<R>(f: impl FnOnce() -> R) -> R {
+    let masked = Register::<DAIF>::new().read(|r| r.i());
+
+    // SAFETY: masking IRQs can't violate any invariants of the interrupted code.
+    unsafe { asm!("msr DAIFSet, #0b0010") };
+    let result = f();
+    if !masked {
+        // SAFETY: IRQs were unmasked when we were called, so we can unmask them again.
+        unsafe { asm!("msr DAIFClr, #0b0010") };
+    }
+
+    result
+}
@@ -0,0 +1,955 @@
+//! System calls, made by tasks with `svc #imm` and dispatched on the `svc` immediate.
+//!
+//! The immediates are defined in [`abi::syscall`], which the wrappers here make system calls with,
+//! and each handler registers itself for its immediate with [`syscall!`]. Registrations are placed
+//! in a linker section (see linker.ld), which [`init`] builds the dispatch table from at boot,
+//! checking that every system call in the ABI has exactly one handler.
+use core::arch::asm;
+use core::fmt::{self, Write};
+use core::mem::size_of;
+use core::slice;
+
+use abi::Errno;
+use fdt::Fdt;
+
+use crate::addr::VirtAddr;
+use crate::address_space::Region;
+use crate::error::KernelError;
+use crate::scheduler::Scheduler;
+use crate::signal::Signal;
+use crate::task::{Context, Handle};
+use crate::tty::Mode;
+use crate::{
+    build_info, init, linker_symbols, logging, mm, pipe, signal, stats, trace, tt, tty, SCHEDULER,
+};
+
+/// Signal handler registered with [`set_signal_handler`], which is passed the signal number and
+/// the frame to pass to [`signal_return`] once it's done.
+pub type SignalHandler = extern "C" fn(signal: u32, frame: *const signal::Frame) -> !;
+
+/// Entry point of a task created by [`spawn`], which is passed its arguments like `main` in C:
+/// the number of arguments, then null-terminated arrays of pointers to the NUL-terminated
+/// arguments and environment variables.
+pub type TaskEntry =
+    extern "C" fn(argc: usize, argv: *const *const u8, envp: *const *const u8) -> !;
+
+/// A function that handles a system call, given the caller's saved context, returning the context
+/// of the task to switch to and the result for the caller (see [`dispatch`]).
+pub type Handler = fn(*const Context) -> Result<(*const Context, u64), KernelError>;
+
+/// A system call handler, as placed in a linker section by [`syscall!`].
+pub struct Syscall {
+    pub number: u16,
+    pub name: &'static str,
+    pub function: Handler,
+}
+
+/// Number of trace records [`sys_snapshot`] copies at a time, with interrupts masked.
+const TRACE_SNAPSHOT_CHUNK: usize = 128;
+
+/// Handler for each system call, indexed by number, built by [`init`].
+static mut TABLE: [Option<&Syscall>; abi::syscall::HIGHEST as usize + 1] =
+    [None; abi::syscall::HIGHEST as usize + 1];
+
+/// Builds the dispatch table from the handlers registered with [`syscall!`], panicking if a system
+/// call has no handler or more than one, so the kernel can't boot with its ABI out of step with
+/// [`abi::syscall`].
+fn init(_fdt: &Fdt) -> Result<(), KernelError> {
+    // SAFETY: the linker symbols bound a section containing only `Syscall`s, placed there by
+    // `syscall!`, and the section is aligned for `Syscall`.
+    let syscalls: &[Syscall] = unsafe {
+        init::section(
+            linker_symbols::syscall_start(),
+            linker_symbols::syscall_end(),
+        )
+    };
+
+    // SAFETY: nothing makes system calls until the scheduler starts.
+    let table = unsafe { &mut TABLE };
+    for syscall in syscalls {
+        // syscall! only accepts numbers in abi::syscall::ALL, which are all in the table
+        let entry = &mut table[usize::from(syscall.number)];
+        if let Some(other) = entry {
+            panic!(
+                "svc #{} has two handlers: {} and {}",
+                syscall.number, other.name, syscall.name
+            );
+        }
+        *entry = Some(syscall);
+    }
+    for number in abi::syscall::ALL {
+        assert!(
+            table[usize::from(number)].is_some(),
+            "svc #{number} has no handler"
+        );
+    }
+
+    Ok(())
+}
+initcall!(arch, init);
+
+/// Returns the dispatch table.
+fn table() -> &'static [Option<&'static Syscall>] {
+    // SAFETY: the table is only written by init, before the scheduler starts.
+    unsafe { &TABLE }
+}
+
+/// Gives up the remainder of the calling task's time slice, without waiting for the next timer
+/// tick.
+///
+/// The task's context is saved by the usual exception entry path (entry.s), so from the caller's
+/// point of view this is just a function call that returns some time later.
+pub fn yield_now() {
+    // SAFETY: the exception entry and return paths save and restore the entire context of the
+    // task, except for `x0`, which holds the result.
+    unsafe { asm!("svc #{number}", number = const abi::syscall::YIELD, lateout("x0") _) };
+}
+
+/// Writes the kernel's build info (as shown in the boot banner) to `buffer`, returning its full
+/// length, which may be more than was written if `buffer` is too small.
+pub fn build_info(buffer: &mut [u8]) -> Result<usize, Errno> {
+    let result: u64;
+
+    // SAFETY: the kernel only writes within the buffer, after checking that it's writable.
+    unsafe {
+        asm!(
+            "svc #{number}",
+            number = const abi::syscall::BUILD_INFO,
+            inlateout("x0") buffer.as_mut_ptr() => result,
+            in("x1") buffer.len(),
+        )
+    };
+
+    abi::decode(result).map(|len| len as usize)
+}
+
+/// Creates a task that starts at `entry`, returning its task id.
+///
+/// `args` holds the arguments passed to the task, each terminated by a NUL byte, such as
+/// `b"worker\0--fast\0"`.
+///
+/// For now, a task can only be started from a function in the kernel image, rather than from a
+/// program loaded by path, since there's no initramfs or ELF loader to load one from, and every
+/// task shares the kernel's address space. Loading programs by path will need all three.
+pub fn spawn(entry: TaskEntry, args: &[u8]) -> Result<usize, Errno> {
+    let result: u64;
+
+    // SAFETY: the kernel only reads from the arguments, after checking that they're readable.
+    unsafe {
+        asm!(
+            "svc #{number}",
+            number = const abi::syscall::SPAWN,
+            inlateout("x0") entry as usize => result,
+            in("x1") args.as_ptr(),
+            in("x2") args.len(),
+        )
+    };
+
+    abi::decode(result).map(|id| id as usize)
+}
+
+/// Creates a pipe, returning handles to its read and write ends.
+pub fn pipe() -> Result<(usize, usize), Errno> {
+    let mut handles = [0u64; 2];
+    let result: u64;
+
+    // SAFETY: the kernel only writes within the array, after checking that it's writable.
+    unsafe {
+        asm!(
+            "svc #{number}",
+            number = const abi::syscall::PIPE,
+            inlateout("x0") handles.as_mut_ptr() => result,
+        )
+    };
+
+    abi::decode(result).map(|_| (handles[0] as usize, handles[1] as usize))
+}
+
+/// Reads from `handle` into `buffer`, returning how many bytes were read, or 0 at the end.
+///
+/// Waits until at least one byte can be read, unless there's nothing left to read.
+pub fn read(handle: usize, buffer: &mut [u8]) -> Result<usize, Errno> {
+    let result: u64;
+
+    // SAFETY: the kernel only writes within the buffer, after checking that it's writable.
+    unsafe {
+        asm!(
+            "svc #{number}",
+            number = const abi::syscall::READ,
+            inlateout("x0") handle => result,
+            in("x1") buffer.as_mut_ptr(),
+            in("x2") buffer.len(),
+        )
+    };
+
+    abi::decode(result).map(|len| len as usize)
+}
+
+/// Writes from `buffer` to `handle`, returning how many bytes were written.
+///
+/// Waits until at least one byte can be written, but may write fewer bytes than asked.
+pub fn write(handle: usize, buffer: &[u8]) -> Result<usize, Errno> {
+    let result: u64;
+
+    // SAFETY: the kernel only reads from the buffer, after checking that it's readable.
+    unsafe {
+        asm!(
+            "svc #{number}",
+            number = const abi::syscall::WRITE,
+            inlateout("x0") handle => result,
+            in("x1") buffer.as_ptr(),
+            in("x2") buffer.len(),
+        )
+    };
+
+    abi::decode(result).map(|len| len as usize)
+}
+
+/// Closes `handle`, so the task can no longer use it.
+pub fn close(handle: usize) -> Result<(), Errno> {
+    let result: u64;
+
+    // SAFETY: closing a handle doesn't touch the task's memory.
+    unsafe {
+        asm!("svc #{number}", number = const abi::syscall::CLOSE, inlateout("x0") handle => result)
+    };
+
+    abi::decode(result).map(|_| ())
+}
+
+/// Opens the console TTY (see [`crate::tty`]), returning a handle to it, and makes the calling
+/// task the foreground task that Ctrl+C interrupts.
+pub fn open_console() -> Result<usize, Errno> {
+    let result: u64;
+
+    // SAFETY: opening the console doesn't touch the task's memory.
+    unsafe {
+        asm!("svc #{number}", number = const abi::syscall::OPEN_CONSOLE, lateout("x0") result)
+    };
+
+    abi::decode(result).map(|handle| handle as usize)
+}
+
+/// Controls the object behind `handle` with one of the requests in [`abi::ioctl`].
+pub fn ioctl(handle: usize, request: u64, arg: u64) -> Result<u64, Errno> {
+    let result: u64;
+
+    // SAFETY: none of the requests touch the task's memory.
+    unsafe {
+        asm!(
+            "svc #{number}",
+            number = const abi::syscall::IOCTL,
+            inlateout("x0") handle => result,
+            in("x1") request,
+            in("x2") arg,
+        )
+    };
+
+    abi::decode(result)
+}
+
+/// Posts `signal` (see [`abi::signal`]) to task `id`, which must be the caller or a task it
+/// spawned.
+pub fn kill(id: usize, signal: u32) -> Result<(), Errno> {
+    let result: u64;
+
+    // SAFETY: posting a signal doesn't touch the task's memory.
+    unsafe {
+        asm!(
+            "svc #{number}",
+            number = const abi::syscall::KILL,
+            inlateout("x0") id => result,
+            in("x1") signal,
+        )
+    };
+
+    abi::decode(result).map(|_| ())
+}
+
+/// Makes the caller the leader of a new task group, whose id is the caller's own id, and returns
+/// it. Tasks the caller spawns from then on join the new group.
+pub fn new_group() -> Result<usize, Errno> {
+    let result: u64;
+
+    // SAFETY: starting a task group doesn't touch the task's memory.
+    unsafe { asm!("svc #{number}", number = const abi::syscall::NEW_GROUP, lateout("x0") result) };
+
+    abi::decode(result).map(|group| group as usize)
+}
+
+/// Posts `signal` (see [`abi::signal`]) to every task in task group `group`, which the caller must
+/// be in, or whose leader must be the caller or a task it spawned. Returns the number of tasks
+/// signalled.
+pub fn kill_group(group: usize, signal: u32) -> Result<usize, Errno> {
+    let result: u64;
+
+    // SAFETY: posting a signal doesn't touch the task's memory.
+    unsafe {
+        asm!(
+            "svc #{number}",
+            number = const abi::syscall::KILL_GROUP,
+            inlateout("x0") group => result,
+            in("x1") signal,
+        )
+    };
+
+    abi::decode(result).map(|count| count as usize)
+}
+
+/// Returns the caller's pending signals as a bit mask, with bit `n` set for signal `n`, and
+/// clears them.
+pub fn take_signals() -> Result<u64, Errno> {
+    let result: u64;
+
+    // SAFETY: taking signals doesn't touch the task's memory.
+    unsafe {
+        asm!("svc #{number}", number = const abi::syscall::TAKE_SIGNALS, lateout("x0") result)
+    };
+
+    abi::decode(result)
+}
+
+/// Sets the handler that the caller is diverted to when it has a pending signal, or removes it
+/// if `None`, so signals stay pending until taken with [`take_signals`].
+pub fn set_signal_handler(handler: Option<SignalHandler>) -> Result<(), Errno> {
+    let result: u64;
+
+    // SAFETY: setting the handler doesn't touch the task's memory.
+    unsafe {
+        asm!(
+            "svc #{number}",
+            number = const abi::syscall::SET_SIGNAL_HANDLER,
+            inlateout("x0") handler.map_or(0, |f| f as usize) => result,
+        )
+    };
+
+    abi::decode(result).map(|_| ())
+}
+
+/// Returns from a signal handler, resuming whatever the task was doing when it was diverted.
+pub fn signal_return(frame: *const signal::Frame) -> ! {
+    // SAFETY: the kernel restores the context saved in the frame, after checking that the task
+    // could read it, so this never returns.
+    unsafe {
+        asm!(
+            "svc #{number}",
+            number = const abi::syscall::SIGNAL_RETURN,
+            in("x0") frame,
+            options(noreturn),
+        )
+    };
+}
+
+/// Returns the kernel's metrics (see [`abi::Metrics`]), which are counted from boot.
+pub fn metrics() -> Result<abi::Metrics, Errno> {
+    let mut metrics = abi::Metrics::default();
+    let result: u64;
+
+    // SAFETY: the kernel only writes within the struct, after checking that it's writable.
+    unsafe {
+        asm!(
+            "svc #{number}",
+            number = const abi::syscall::METRICS,
+            inlateout("x0") &mut metrics as *mut abi::Metrics => result,
+            in("x1") size_of::<abi::Metrics>(),
+        )
+    };
+
+    abi::decode(result).map(|_| metrics)
+}
+
+/// Copies `what` (see [`abi::snapshot`]) into memory the kernel shares with the caller, and
+/// returns the copy, so the caller can take its time reading it without the kernel having to stop
+/// recording in the meantime. Only the boot tasks, which no task spawned, may take snapshots.
+///
+/// Each task has one region of shared memory for each kind of snapshot, which is reused by every
+/// snapshot of that kind, and shows up in `pmap`.
+///
+/// # Safety
+///
+/// The caller's next snapshot of the same kind overwrites the copy, so the returned bytes must not
+/// be used after that.
+pub unsafe fn snapshot(what: u64) -> Result<&'static [u8], Errno> {
+    let mut address = 0u64;
+    let mut next = 0u64;
+
+    // the kernel copies a chunk at a time, so it can handle interrupts in between, and writes the
+    // address once it's done
+    while address == 0 {
+        let result: u64;
+        // SAFETY: the kernel only writes within the u64, after checking that it's writable, and
+        // to the shared region, which nothing else in the task uses.
+        unsafe {
+            asm!(
+                "svc #{number}",
+                number = const abi::syscall::SNAPSHOT,
+                inlateout("x0") what => result,
+                in("x1") &mut address as *mut u64,
+                in("x2") next,
+            )
+        };
+        next = abi::decode(result)?;
+    }
+
+    // SAFETY: the kernel copied this many bytes to the shared region at `address`, and the caller
+    // ensures that they aren't used after they're overwritten.
+    Ok(unsafe { slice::from_raw_parts(address as *const u8, next as usize) })
+}
+
+/// Returns which sinks the kernel log is written to (see [`abi::log_sink`]), and with `Some`,
+/// writes it to those sinks instead. Only the boot tasks, which no task spawned, may change them.
+pub fn log_sinks(sinks: Option<u64>) -> Result<u64, Errno> {
+    let result: u64;
+
+    // SAFETY: the system call doesn't touch the caller's memory.
+    unsafe {
+        asm!(
+            "svc #{number}",
+            number = const abi::syscall::LOG_SINKS,
+            inlateout("x0") sinks.unwrap_or(abi::log_sink::UNCHANGED) => result,
+        )
+    };
+
+    abi::decode(result)
+}
+
+/// Ends the caller with exit status `status`, closing its handles. Whoever waits for its task group
+/// sees the status (see [`wait_group`]).
+pub fn exit(status: u8) -> ! {
+    // SAFETY: exiting doesn't touch the task's memory, and the kernel never runs the task again.
+    unsafe {
+        asm!(
+            "svc #{number}",
+            number = const abi::syscall::EXIT,
+            in("x0") u64::from(status),
+            options(noreturn),
+        )
+    };
+}
+
+/// Waits for every task in task group `group` other than the caller to exit, then returns the
+/// status of the first of them to exit with a status other than 0, or 0 if none did. The caller
+/// must be in the group, or its leader must be the caller or a task it spawned, like with
+/// [`kill_group`]. Once waited for, the tasks are gone, and their ids can be reused.
+pub fn wait_group(group: usize) -> Result<u8, Errno> {
+    let result: u64;
+
+    // SAFETY: waiting for a task group doesn't touch the task's memory.
+    unsafe {
+        asm!(
+            "svc #{number}",
+            number = const abi::syscall::WAIT_GROUP,
+            inlateout("x0") group => result,
+        )
+    };
+
+    abi::decode(result).map(|status| status as u8)
+}
+
+/// Handles a system call, given the `svc` immediate (from ESR_EL1.ISS) and the calling task's
+/// saved context.
+///
+/// Returns the context of the task to switch to, which may be the calling task's own context. The
+/// result is returned to the caller in `x0`, encoded as described in [`abi`].
+///
+/// System calls that can't complete yet return [`KernelError::WouldBlock`], and the caller waits
+/// for them to complete (see [`wait`]).
+pub fn handle(immediate: u16, context: *const Context) -> *const Context {
+    stats::SYSCALLS.increment();
+    let (next, result) = match dispatch(immediate, context) {
+        Ok((next, value)) => (next, Ok(value)),
+        Err(KernelError::WouldBlock) => return wait(context),
+        Err(error) => {
+            log::warn!("syscall: svc #{immediate} failed: {error}");
+
+            (context, Err(Errno::from(error)))
+        }
+    };
+
+    // SAFETY: the context was saved on the caller's kernel stack by entry.s, and nothing else
+    // refers to it until the caller is restored.
+    unsafe { (*(context as *mut Context)).set_x0(abi::encode(result)) };
+
+    next
+}
+
+/// Makes the caller wait for a system call that can't complete yet, returning the context of the
+/// task to run in the meantime.
+///
+/// There's no way to wake a task when whatever it's waiting for happens, so instead the caller
+/// makes the same system call again, with the same arguments, when it's next scheduled.
+fn wait(context: *const Context) -> *const Context {
+    // SAFETY: see handle.
+    let caller = unsafe { &mut *(context as *mut Context) };
+    // ELR_EL1 is the address after the svc, which is always 4 bytes
+    caller.set_pc(caller.pc() - 4);
+
+    // SAFETY: see scheduler.
+    match unsafe { SCHEDULER.get_mut() } {
+        Some(scheduler) => scheduler.yield_current().context(),
+        None => context,
+    }
+}
+
+/// Runs a system call, returning the context to switch to and the result for the caller.
+///
+/// This is [`handle`] without waiting or returning the result in `x0`, which the syscall fuzzer
+/// (see fuzz.rs) calls directly.
+pub fn dispatch(
+    immediate: u16,
+    context: *const Context,
+) -> Result<(*const Context, u64), KernelError> {
+    let handler = table()
+        .get(usize::from(immediate))
+        .copied()
+        .flatten()
+        .ok_or(KernelError::UnknownSyscall { number: immediate })?;
+    log::trace!("syscall: {}", handler.name);
+
+    (handler.function)(context)
+}
+
+/// Handles [`yield_now`].
+fn sys_yield(context: *const Context) -> Result<(*const Context, u64), KernelError> {
+    // SAFETY: the scheduler is only accessed from exception handlers and kernel_main, and
+    // exceptions are masked while handling them.
+    if let Some(scheduler) = unsafe { SCHEDULER.get_mut() } {
+        return Ok((scheduler.yield_current().context(), 0));
+    }
+
+    Ok((context, 0))
+}
+syscall!(abi::syscall::YIELD, sys_yield);
+
+/// Handles [`build_info`].
+fn sys_build_info(context: *const Context) -> Result<(*const Context, u64), KernelError> {
+    // SAFETY: see handle.
+    let (address, len) = unsafe { ((*context).x(0) as usize, (*context).x(1) as usize) };
+    let buffer = task_buffer(address, len)?;
+    let mut writer = Truncating { buffer, len: 0 };
+    let _ = write!(writer, "{}", build_info::BUILD_INFO);
+
+    Ok((context, writer.len as u64))
+}
+syscall!(abi::syscall::BUILD_INFO, sys_build_info);
+
+/// Handles [`spawn`].
+fn sys_spawn(context: *const Context) -> Result<(*const Context, u64), KernelError> {
+    // SAFETY: see handle.
+    let (entry, address, len) = unsafe {
+        let context = &*context;
+        (
+            context.x(0) as usize,
+            context.x(1) as usize,
+            context.x(2) as usize,
+        )
+    };
+    let args = task_bytes(address, len)?;
+
+    let id = scheduler().spawn(entry, args)?;
+
+    Ok((context, id as u64))
+}
+syscall!(abi::syscall::SPAWN, sys_spawn);
+
+/// Handles [`pipe`].
+fn sys_pipe(context: *const Context) -> Result<(*const Context, u64), KernelError> {
+    // SAFETY: see handle.
+    let address = unsafe { (*context).x(0) as usize };
+    let buffer = task_buffer(address, 2 * size_of::<u64>())?;
+    let (read_end, write_end) = pipe::create()?;
+    let task = scheduler().current_mut();
+    let read_handle = task.open(Handle::Pipe(read_end)).map_err(|error| {
+        pipe::release(read_end);
+        pipe::release(write_end);
+        error
+    })?;
+    let write_handle = task.open(Handle::Pipe(write_end)).map_err(|error| {
+        pipe::release(write_end);
+        let _ = task.close(read_handle);
+        error
+    })?;
+    buffer[..8].copy_from_slice(&(read_handle as u64).to_ne_bytes());
+    buffer[8..].copy_from_slice(&(write_handle as u64).to_ne_bytes());
+
+    Ok((context, 0))
+}
+syscall!(abi::syscall::PIPE, sys_pipe);
+
+/// Handles [`read`].
+fn sys_read(context: *const Context) -> Result<(*const Context, u64), KernelError> {
+    // SAFETY: see handle.
+    let (handle, address, len) = unsafe { args3(context) };
+    let buffer = task_buffer(address, len)?;
+    let scheduler = scheduler();
+    let len = match scheduler.current_mut().handle(handle)? {
+        Handle::Pipe(pipe::End::Read(index)) => pipe::read(index, buffer)?,
+        Handle::Pipe(pipe::End::Write(_)) => return Err(KernelError::BadHandle { handle }),
+        Handle::Console => tty::read(scheduler.current_id(), buffer)?,
+    };
+
+    Ok((context, len as u64))
+}
+syscall!(abi::syscall::READ, sys_read);
+
+/// Handles [`write`].
+fn sys_write(context: *const Context) -> Result<(*const Context, u64), KernelError> {
+    // SAFETY: see handle.
+    let (handle, address, len) = unsafe { args3(context) };
+    let buffer = task_bytes(address, len)?;
+    let len = match scheduler().current_mut().handle(handle)? {
+        Handle::Pipe(pipe::End::Write(index)) => pipe::write(index, buffer)?,
+        Handle::Pipe(pipe::End::Read(_)) => return Err(KernelError::BadHandle { handle }),
+        Handle::Console => tty::write(buffer),
+    };
+
+    Ok((context, len as u64))
+}
+syscall!(abi::syscall::WRITE, sys_write);
+
+/// Handles [`close`].
+fn sys_close(context: *const Context) -> Result<(*const Context, u64), KernelError> {
+    // SAFETY: see handle.
+    let handle = unsafe { (*context).x(0) as usize };
+    scheduler().current_mut().close(handle)?;
+
+    Ok((context, 0))
+}
+syscall!(abi::syscall::CLOSE, sys_close);
+
+/// Handles [`open_console`].
+fn sys_open_console(context: *const Context) -> Result<(*const Context, u64), KernelError> {
+    let scheduler = scheduler();
+    let id = scheduler.current_id();
+    let handle = scheduler.current_mut().open(Handle::Console)?;
+    tty::open(id);
+
+    Ok((context, handle as u64))
+}
+syscall!(abi::syscall::OPEN_CONSOLE, sys_open_console);
+
+/// Handles [`ioctl`].
+fn sys_ioctl(context: *const Context) -> Result<(*const Context, u64), KernelError> {
+    // SAFETY: see handle.
+    let (handle, request, arg) = unsafe { args3(context) };
+    match (scheduler().current_mut().handle(handle)?, request as u64) {
+        (Handle::Console, abi::ioctl::TTY_SET_RAW) => {
+            tty::set_mode(if arg != 0 { Mode::Raw } else { Mode::Canonical })
+        }
+        _ => {
+            return Err(KernelError::InvalidArgument {
+                reason: "unknown ioctl request for handle",
+            })
+        }
+    }
+
+    Ok((context, 0))
+}
+syscall!(abi::syscall::IOCTL, sys_ioctl);
+
+/// Handles [`kill`].
+fn sys_kill(context: *const Context) -> Result<(*const Context, u64), KernelError> {
+    // SAFETY: see handle.
+    let (id, signal) = unsafe { ((*context).x(0) as usize, (*context).x(1) as u32) };
+    let scheduler = scheduler();
+    let caller = scheduler.current_id();
+    let task = scheduler
+        .task_mut(id)
+        .ok_or(KernelError::NoSuchTask { id })?;
+    if id != caller && task.parent() != Some(caller) {
+        return Err(KernelError::NotPermitted { id });
+    }
+    let signal = Signal::try_from(signal).map_err(|_| KernelError::InvalidArgument {
+        reason: "no such signal",
+    })?;
+    task.signals_mut().post(signal);
+
+    Ok((context, 0))
+}
+syscall!(abi::syscall::KILL, sys_kill);
+
+/// Handles [`take_signals`].
+fn sys_take_signals(context: *const Context) -> Result<(*const Context, u64), KernelError> {
+    let signals = scheduler().current_mut().signals_mut().take();
+
+    Ok((context, signals))
+}
+syscall!(abi::syscall::TAKE_SIGNALS, sys_take_signals);
+
+/// Handles [`set_signal_handler`].
+fn sys_set_signal_handler(context: *const Context) -> Result<(*const Context, u64), KernelError> {
+    // SAFETY: see handle.
+    let handler = unsafe { (*context).x(0) as usize };
+    let handler = Some(handler).filter(|&handler| handler != 0);
+    scheduler().current_mut().signals_mut().set_handler(handler);
+
+    Ok((context, 0))
+}
+syscall!(abi::syscall::SET_SIGNAL_HANDLER, sys_set_signal_handler);
+
+/// Handles [`signal_return`].
+fn sys_signal_return(context: *const Context) -> Result<(*const Context, u64), KernelError> {
+    // SAFETY: see handle.
+    let caller = unsafe { &mut *(context as *mut Context) };
+    let x0 = signal::restore(caller, caller.x(0) as usize)?;
+
+    Ok((context, x0))
+}
+syscall!(abi::syscall::SIGNAL_RETURN, sys_signal_return);
+
+/// Handles [`metrics`].
+fn sys_metrics(context: *const Context) -> Result<(*const Context, u64), KernelError> {
+    // SAFETY: see handle.
+    let (address, len) = unsafe { ((*context).x(0) as usize, (*context).x(1) as usize) };
+    let buffer = task_buffer(address, len)?;
+    let metrics = stats::metrics();
+    let bytes = metrics_bytes(&metrics);
+    // older callers may know about fewer fields, so only write what fits
+    let n = len.min(bytes.len());
+    buffer[..n].copy_from_slice(&bytes[..n]);
+
+    Ok((context, bytes.len() as u64))
+}
+syscall!(abi::syscall::METRICS, sys_metrics);
+
+/// Handles [`new_group`].
+fn sys_new_group(context: *const Context) -> Result<(*const Context, u64), KernelError> {
+    let scheduler = scheduler();
+    let caller = scheduler.current_id();
+    scheduler.current_mut().set_group(caller);
+
+    Ok((context, caller as u64))
+}
+syscall!(abi::syscall::NEW_GROUP, sys_new_group);
+
+/// Handles [`kill_group`].
+fn sys_kill_group(context: *const Context) -> Result<(*const Context, u64), KernelError> {
+    // SAFETY: see handle.
+    let (group, signal) = unsafe { ((*context).x(0) as usize, (*context).x(1) as u32) };
+    let scheduler = scheduler();
+    check_group_access(scheduler, group)?;
+    let signal = Signal::try_from(signal).map_err(|_| KernelError::InvalidArgument {
+        reason: "no such signal",
+    })?;
+    let count = signal::post_group(group, signal)?;
+
+    Ok((context, count as u64))
+}
+syscall!(abi::syscall::KILL_GROUP, sys_kill_group);
+
+/// Handles [`snapshot`].
+fn sys_snapshot(context: *const Context) -> Result<(*const Context, u64), KernelError> {
+    // SAFETY: see handle.
+    let (what, address, next) = unsafe {
+        let context = &*context;
+        (context.x(0), context.x(1) as usize, context.x(2) as usize)
+    };
+    let buffer = task_buffer(address, size_of::<u64>())?;
+    let scheduler = scheduler();
+    let caller = scheduler.current_id();
+    let task = scheduler.current_mut();
+    if task.parent().is_some() {
+        return Err(KernelError::NotPermitted { id: caller });
+    }
+
+    let (name, len) = match what {
+        abi::snapshot::TRACE => ("trace snapshot", size_of::<trace::Copy>()),
+        abi::snapshot::METRICS => ("metrics snapshot", size_of::<abi::Metrics>()),
+        _ => {
+            return Err(KernelError::InvalidArgument {
+                reason: "no such snapshot",
+            })
+        }
+    };
+    let range = match task.address_space().find_named(name) {
+        Some(region) => region.range.clone(),
+        None => {
+            let pages_len = len.next_multiple_of(tt::PAGE_SIZE);
+            let pages = mm::Pages::alloc(pages_len)?;
+            let start = pages.addr();
+            let range = start..VirtAddr::new(start.addr() + pages_len);
+            task.address_space_mut()
+                .insert(Region::shared(range.clone(), name))?;
+            pages.keep();
+            range
+        }
+    };
+
+    // the trace is copied a chunk at a time, returning to the caller in between, so interrupts
+    // aren't masked for the whole copy and tracing carries on as usual. the metrics are small
+    // enough to copy in one go.
+    // TODO: map the buffer copy-on-write instead, once tasks have translation tables of
+    // their own, rather than sharing the kernel's
+    let next = match what {
+        abi::snapshot::TRACE => {
+            // SAFETY: the region was allocated for trace snapshots, so it's big enough and
+            // page-aligned, and the task can't run while we write to it.
+            let copy = unsafe { &mut *(range.start.addr() as *mut trace::Copy) };
+            // SAFETY: exceptions are masked while handling them, so nothing records events while
+            // we copy them.
+            unsafe { trace::copy_into(copy, next, TRACE_SNAPSHOT_CHUNK) }
+        }
+        _ => {
+            let metrics = stats::metrics();
+            let bytes = metrics_bytes(&metrics);
+            // SAFETY: the region was allocated for metrics snapshots, so it's big enough, and the
+            // task can't run while we write to it.
+            let shared = unsafe { slice::from_raw_parts_mut(range.start.addr() as *mut u8, len) };
+            shared.copy_from_slice(bytes);
+            None
+        }
+    };
+    if let Some(next) = next {
+        return Ok((context, next as u64));
+    }
+    buffer.copy_from_slice(&(range.start.addr() as u64).to_ne_bytes());
+
+    Ok((context, len as u64))
+}
+syscall!(abi::syscall::SNAPSHOT, sys_snapshot);
+
+/// Handles [`log_sinks`].
+fn sys_log_sinks(context: *const Context) -> Result<(*const Context, u64), KernelError> {
+    // SAFETY: see handle.
+    let sinks = unsafe { (*context).x(0) };
+    if sinks == abi::log_sink::UNCHANGED {
+        return Ok((context, logging::sinks()));
+    }
+    let scheduler = scheduler();
+    let caller = scheduler.current_id();
+    if scheduler.current_mut().parent().is_some() {
+        return Err(KernelError::NotPermitted { id: caller });
+    }
+    let previous = logging::set_sinks(sinks)?;
+
+    Ok((context, previous))
+}
+syscall!(abi::syscall::LOG_SINKS, sys_log_sinks);
+
+/// Handles [`exit`].
+fn sys_exit(context: *const Context) -> Result<(*const Context, u64), KernelError> {
+    // SAFETY: see handle.
+    let status = unsafe { (*context).x(0) };
+    // exit never returns, so rather than failing, keep the low 8 bits of the status like wait(2)
+    let next = scheduler().exit_current(status as u8);
+
+    Ok((next.context(), 0))
+}
+syscall!(abi::syscall::EXIT, sys_exit);
+
+/// Handles [`wait_group`].
+fn sys_wait_group(context: *const Context) -> Result<(*const Context, u64), KernelError> {
+    // SAFETY: see handle.
+    let group = unsafe { (*context).x(0) as usize };
+    let scheduler = scheduler();
+    check_group_access(scheduler, group)?;
+    let status = scheduler.wait_group(group)?;
+
+    Ok((context, status.into()))
+}
+syscall!(abi::syscall::WAIT_GROUP, sys_wait_group);
+
+/// Returns the metrics as bytes, as they're copied to tasks.
+fn metrics_bytes(metrics: &abi::Metrics) -> &[u8] {
+    // SAFETY: Metrics is plain old data, with no padding, since every field is a u64.
+    unsafe {
+        slice::from_raw_parts(
+            metrics as *const abi::Metrics as *const u8,
+            size_of::<abi::Metrics>(),
+        )
+    }
+}
+
+/// Returns the scheduler, which has always been started by the time tasks make system calls.
+fn scheduler() -> &'static mut Scheduler {
+    // SAFETY: the scheduler is only accessed from exception handlers and kernel_main, and
+    // exceptions are masked while handling them.
+    unsafe { SCHEDULER.get_mut() }.expect("scheduler to be started")
+}
+
+/// Checks that the current task may signal or wait for task group `group`: it must be in the
+/// group, or the group's leader must be the current task or a task it spawned.
+fn check_group_access(scheduler: &mut Scheduler, group: usize) -> Result<(), KernelError> {
+    let caller = scheduler.current_id();
+    let leader = scheduler.task_mut(group).and_then(|task| task.parent());
+    if scheduler.current_mut().group() != group && group != caller && leader != Some(caller) {
+        return Err(KernelError::NotPermitted { id: group });
+    }
+
+    Ok(())
+}
+
+/// Returns the first three arguments of a system call, `x0` through `x2`.
+///
+/// # Safety
+///
+/// `context` must point to the caller's saved context, as in [`handle`].
+unsafe fn args3(context: *const Context) -> (usize, usize, usize) {
+    let context = &*context;
+
+    (
+        context.x(0) as usize,
+        context.x(1) as usize,
+        context.x(2) as usize,
+    )
+}
+
+/// Returns a buffer passed to a system call by a task, if the task could write to all of it.
+fn task_buffer(address: usize, len: usize) -> Result<&'static mut [u8], KernelError> {
+    check_task_access(address, len, tt::is_writable_by_task)?;
+
+    // SAFETY: we've checked that the task could write to the whole buffer, and the task can't
+    // run while we use it.
+    Ok(unsafe { slice::from_raw_parts_mut(address as *mut u8, len) })
+}
+
+/// Returns bytes passed to a system call by a task, if the task could read all of them.
+fn task_bytes(address: usize, len: usize) -> Result<&'static [u8], KernelError> {
+    check_task_access(address, len, tt::is_readable_by_task)?;
+
+    // SAFETY: we've checked that the task could read all of the bytes, and the task can't run
+    // while we use them.
+    Ok(unsafe { slice::from_raw_parts(address as *const u8, len) })
+}
+
+/// Checks that a task could access all `len` bytes at `address`, as checked by `accessible`.
+fn check_task_access(
+    address: usize,
+    len: usize,
+    accessible: fn(usize) -> bool,
+) -> Result<(), KernelError> {
+    let end = address
+        .checked_add(len)
+        .ok_or(KernelError::Unmapped { address })?;
+
+    // one address per page is enough, since pages are mapped as a whole
+    let mut page = address & !(tt::PAGE_SIZE - 1);
+    while page < end {
+        let first = page.max(address);
+        if !accessible(first) {
+            return Err(KernelError::Unmapped { address: first });
+        }
+        page += tt::PAGE_SIZE;
+    }
+
+    Ok(())
+}
+
+/// Writes as much as fits in a buffer, while counting the length of everything written.
+struct Truncating<'b> {
+    buffer: &'b mut [u8],
+    len: usize,
+}
+
+impl fmt::Write for Truncating<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let bytes = s.as_bytes();
+        if let Some(rest) = self.buffer.get_mut(self.len..) {
+            let n = rest.len().min(bytes.len());
+            rest[..n].copy_from_slice(&bytes[..n]);
+        }
+        self.len += bytes.len();
+
+        Ok(())
+    }
+}
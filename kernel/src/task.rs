@@ -1,16 +1,313 @@
 use core::fmt;
+use core::mem::{self, offset_of, size_of};
+use core::ops::Range;
+
+use hal::Timer;
+use id_bitmap::IdBitmap;
+
+use crate::addr::VirtAddr;
+use crate::address_space::AddressSpace;
+use crate::error::KernelError;
+use crate::signal::Signals;
+use crate::{board, pipe, pmu, tty};
+
+/// Fills each kernel stack when its task is created, so we can tell how much of it has ever been
+/// used by finding the lowest word that no longer holds the pattern.
+///
+/// A task could write this value to its stack by chance, which would make it look like less of
+/// the stack was used than really was, but that's unlikely enough for a debugging aid.
+const STACK_PATTERN: u64 = 0x57ac_57ac_57ac_57ac;
+
+/// Percentage of a kernel stack that a task may use before we warn about it.
+const STACK_WARN_PERCENT: usize = 75;
+
+/// Maximum number of handles a task can have open at once.
+const MAX_HANDLES: usize = 8;
+
+/// Something a task can read from or write to, through a handle number that indexes the task's
+/// handle table.
+#[derive(Clone, Copy, Debug)]
+pub enum Handle {
+    Pipe(pipe::End),
+    /// The console TTY.
+    Console,
+}
+
+impl Handle {
+    /// Opens another reference to the object, for another handle.
+    fn retain(self) {
+        match self {
+            Self::Pipe(end) => pipe::retain(end),
+            Self::Console => tty::retain(),
+        }
+    }
+
+    /// Closes a reference to the object, which may free it.
+    fn release(self) {
+        match self {
+            Self::Pipe(end) => pipe::release(end),
+            Self::Console => tty::release(),
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct Task {
+    name: &'static str,
     /// Pointer to the bottom of the task's kernel stack.
     sp_el1: *const (),
+    /// Lowest address of the task's kernel stack, which grows down towards it from `sp_el1`.
+    stack_limit: *const u64,
+    /// Whether we've warned that the task used more than [`STACK_WARN_PERCENT`] of its stack.
+    stack_warned: bool,
+    /// Objects the task has open, indexed by handle number.
+    handles: [Option<Handle>; MAX_HANDLES],
+    /// Which handle numbers are in use.
+    handle_numbers: IdBitmap<1>,
+    /// Id of the task that spawned this one, if any.
+    parent: Option<usize>,
+    /// Id of the task group this one is in, which is the id of the group's leader.
+    group: usize,
+    /// Whether the scheduler may switch away from the task when its time slice runs out, rather
+    /// than only when it yields, or when it runs past the watchdog's budget.
+    preemptible: bool,
+    /// Number of timer ticks the task has been running for.
+    ticks: u64,
+    /// What the PMU counted while the task was running, up to when it was last switched away from.
+    pmu_counts: pmu::Counts,
+    /// Counter value (CNTPCT_EL0) when the task was last switched to or from, or was spawned.
+    switched_at: u64,
+    signals: Signals,
+    /// Regions of memory the task can use.
+    address_space: AddressSpace,
+    /// Exit status the task exited with, and the counter value (CNTPCT_EL0) when it did, if it has.
+    exited: Option<(u8, u64)>,
 }
 
 impl Task {
-    pub fn new(sp_el1: *const (), context: Context) -> Self {
+    pub fn new(name: &'static str, kernel_stack: Range<VirtAddr>, context: Context) -> Self {
+        let stack_limit = kernel_stack.start.as_ptr::<u64>();
+        let sp_el1 = kernel_stack.end.as_ptr();
+        let words = (kernel_stack.end.addr() - kernel_stack.start.addr()) / size_of::<u64>();
+
+        // SAFETY: the kernel stack is reserved for this task in linker.ld, and nothing uses it
+        // until the task first runs.
+        unsafe {
+            for i in 0..words {
+                (stack_limit as *mut u64)
+                    .add(i)
+                    .write_volatile(STACK_PATTERN);
+            }
+        }
         unsafe { Context::from_sp_el1_mut(sp_el1 as *mut _).write(context) }
 
-        Self { sp_el1 }
+        Self {
+            name,
+            sp_el1,
+            stack_limit,
+            stack_warned: false,
+            handles: [None; MAX_HANDLES],
+            handle_numbers: IdBitmap::new(MAX_HANDLES),
+            parent: None,
+            group: 0,
+            preemptible: true,
+            ticks: 0,
+            pmu_counts: pmu::Counts::default(),
+            switched_at: board::timer().now(),
+            signals: Signals::default(),
+            address_space: AddressSpace::new(),
+            exited: None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Returns the id of the task that spawned this one, if any.
+    pub fn parent(&self) -> Option<usize> {
+        self.parent
+    }
+
+    /// Records that task `id` spawned this one.
+    pub fn set_parent(&mut self, id: usize) {
+        self.parent = Some(id);
+    }
+
+    /// Returns the id of the task group this one is in.
+    pub fn group(&self) -> usize {
+        self.group
+    }
+
+    /// Moves this task into task group `group`.
+    pub fn set_group(&mut self, group: usize) {
+        self.group = group;
+    }
+
+    /// Returns the status the task exited with, and the counter value when it did, if it has.
+    pub fn exited(&self) -> Option<(u8, u64)> {
+        self.exited
+    }
+
+    /// Records that the task exited with `status` at counter value `now`, closing its handles, so
+    /// the other ends of its pipes see it go. The scheduler never runs it again.
+    pub fn exit(&mut self, status: u8, now: u64) {
+        for number in 0..MAX_HANDLES {
+            let _ = self.close(number);
+        }
+        self.exited = Some((status, now));
+    }
+
+    /// Returns whether the scheduler may switch away from the task when its time slice runs out.
+    pub fn preemptible(&self) -> bool {
+        self.preemptible
+    }
+
+    /// Sets whether the scheduler may switch away from the task when its time slice runs out.
+    ///
+    /// A task that isn't preemptible, like a latency-critical driver thread, keeps running until
+    /// it yields, unless it runs past the budget of the scheduler's watchdog (see
+    /// [`crate::scheduler::Scheduler::schedule`]).
+    pub fn set_preemptible(&mut self, preemptible: bool) {
+        self.preemptible = preemptible;
+    }
+
+    /// Returns the number of timer ticks the task has been running for.
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    /// Accounts for a timer tick while the task was running.
+    pub fn tick(&mut self) {
+        self.ticks += 1;
+    }
+
+    /// Returns what the PMU counted while the task was running, not including the time since it
+    /// last started running, if it's running now (see [`pmu::read`]).
+    pub fn pmu_counts(&self) -> pmu::Counts {
+        self.pmu_counts
+    }
+
+    /// Accounts for what the PMU counted while the task was running.
+    pub fn add_pmu_counts(&mut self, counts: pmu::Counts) {
+        self.pmu_counts = self.pmu_counts + counts;
+    }
+
+    /// Returns how long it's been since the task was last switched to or from, in counter ticks,
+    /// given the counter value `now`, then starts counting again from `now`.
+    ///
+    /// When the task is switched away from, this is how long it ran for, and when it's switched
+    /// to, this is how long it waited to run.
+    pub fn take_elapsed(&mut self, now: u64) -> u64 {
+        now.saturating_sub(mem::replace(&mut self.switched_at, now))
+    }
+
+    pub fn signals_mut(&mut self) -> &mut Signals {
+        &mut self.signals
+    }
+
+    pub fn address_space(&self) -> &AddressSpace {
+        &self.address_space
+    }
+
+    pub fn address_space_mut(&mut self) -> &mut AddressSpace {
+        &mut self.address_space
+    }
+
+    /// Adds `handle` to the task's handle table, returning its handle number.
+    pub fn open(&mut self, handle: Handle) -> Result<usize, KernelError> {
+        let number = self
+            .handle_numbers
+            .allocate()
+            .ok_or(KernelError::TooManyHandles { max: MAX_HANDLES })?;
+        self.handles[number] = Some(handle);
+
+        Ok(number)
+    }
+
+    /// Returns the object that handle `number` refers to.
+    pub fn handle(&self, number: usize) -> Result<Handle, KernelError> {
+        self.handles
+            .get(number)
+            .copied()
+            .flatten()
+            .ok_or(KernelError::BadHandle { handle: number })
+    }
+
+    /// Returns whether any of the task's handles refers to the console TTY.
+    pub fn has_console_open(&self) -> bool {
+        self.handles
+            .iter()
+            .flatten()
+            .any(|handle| matches!(handle, Handle::Console))
+    }
+
+    /// Removes handle `number` from the task's handle table, closing the object it refers to.
+    pub fn close(&mut self, number: usize) -> Result<(), KernelError> {
+        let handle = self.handle(number)?;
+        self.handles[number] = None;
+        self.handle_numbers
+            .free(number)
+            .expect("open handle to have its number allocated");
+        handle.release();
+
+        Ok(())
+    }
+
+    /// Opens each of `parent`'s handles in this task too, with the same handle numbers.
+    pub fn inherit_handles(&mut self, parent: &Task) {
+        for handle in parent.handles.iter().flatten() {
+            handle.retain();
+        }
+        self.handles = parent.handles;
+        self.handle_numbers = parent.handle_numbers.clone();
+    }
+
+    /// Returns the most of its kernel stack the task has used since it was created.
+    pub fn kernel_stack_usage(&self) -> StackUsage {
+        let size = self.sp_el1 as usize - self.stack_limit as usize;
+        let words = size / size_of::<u64>();
+
+        let unused = (0..words)
+            // SAFETY: the whole kernel stack is mapped and reserved for this task. it may be in
+            // use while we look at it, so we read it volatilely, and at worst get a slightly stale
+            // answer.
+            .take_while(|&i| unsafe { self.stack_limit.add(i).read_volatile() } == STACK_PATTERN)
+            .count();
+
+        StackUsage {
+            used: size - unused * size_of::<u64>(),
+            size,
+        }
+    }
+
+    /// Returns the addresses of the task's kernel stack, where its context is saved on exception
+    /// entry.
+    pub fn kernel_stack(&self) -> Range<usize> {
+        self.stack_limit as usize..self.sp_el1 as usize
+    }
+
+    /// Warns, once, if the task has used more than [`STACK_WARN_PERCENT`] of its kernel stack.
+    ///
+    /// This is cheap enough to do on every context switch, because it only needs to look at the
+    /// one word at the threshold.
+    pub fn check_kernel_stack(&mut self) {
+        if self.stack_warned {
+            return;
+        }
+
+        let size = self.sp_el1 as usize - self.stack_limit as usize;
+        let threshold = size * (100 - STACK_WARN_PERCENT) / 100 / size_of::<u64>();
+
+        // SAFETY: see kernel_stack_usage.
+        if unsafe { self.stack_limit.add(threshold).read_volatile() } != STACK_PATTERN {
+            self.stack_warned = true;
+            log::warn!(
+                "{} has used {} of its kernel stack",
+                self.name,
+                self.kernel_stack_usage()
+            );
+        }
     }
 
     pub fn context(&self) -> &Context {
@@ -31,10 +328,40 @@ impl Task {
     }
 }
 
+/// How much of a kernel stack has been used, at most.
+#[derive(Clone, Copy, Debug)]
+pub struct StackUsage {
+    /// Number of bytes used, at the deepest point the stack has reached.
+    pub used: usize,
+    /// Size of the stack, in bytes.
+    pub size: usize,
+}
+
+impl StackUsage {
+    /// Returns whether more than [`STACK_WARN_PERCENT`] of the stack has been used.
+    pub fn is_high(&self) -> bool {
+        self.used * 100 > self.size * STACK_WARN_PERCENT
+    }
+}
+
+impl fmt::Display for StackUsage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}/{} bytes ({}%)",
+            self.used,
+            self.size,
+            self.used * 100 / self.size
+        )
+    }
+}
+
 /// The processor state of a task, saved and restored on context switches.
 ///
-/// **This struct MUST be kept in sync with the `task_save` and `task_restore` macros defined in
-/// `entry.s`.**
+/// The `task_save` and `task_restore` macros in entry.s use the offsets in the `OFFSET_*`
+/// constants, but they also store fields in pairs with `stp` and `ldp`, which the assertions
+/// below check are still next to each other.
+#[derive(Clone)]
 #[repr(C)]
 pub struct Context {
     /// General-purpose registers `x0` through `x30`.
@@ -49,16 +376,100 @@ pub struct Context {
     sp: *const (),
 }
 
+// the pinned toolchain's clippy lints assertions on constants even in a const item
+#[allow(clippy::assertions_on_constants)]
+const _: () = assert!(
+    Context::SIZE % 16 == 0,
+    "Context must keep SP_EL1 16-byte aligned"
+);
+const _: () = assert!(
+    offset_of!(Context, psr) == Context::OFFSET_X30 + 8,
+    "psr must follow x30"
+);
+const _: () = assert!(
+    offset_of!(Context, sp) == Context::OFFSET_PC + 8,
+    "sp must follow pc"
+);
+
 impl Context {
+    /// Size of a context, which entry.s reserves on the kernel stack.
+    pub const SIZE: usize = size_of::<Self>();
+    /// Offset of `x0`, which is followed by `x1` through `x30`.
+    pub const OFFSET_X0: usize = offset_of!(Self, gprs);
+    /// Offset of `x30`, which entry.s saves with `psr`.
+    pub const OFFSET_X30: usize = Self::OFFSET_X0 + 30 * size_of::<u64>();
+    /// Offset of `pc`, which entry.s saves with `sp`.
+    pub const OFFSET_PC: usize = offset_of!(Self, pc);
+
+    /// `PSTATE.M` for tasks running at EL0 (EL0t).
+    const PSR_EL0T: u64 = 0b0000;
+    /// `PSTATE.M` for kernel threads, which run at EL1 using SP_EL0 (EL1t).
+    const PSR_EL1T: u64 = 0b0100;
+
     pub fn new(initial_pc: *const (), initial_sp: *const ()) -> Self {
         Self {
             gprs: [0; 31],
             pc: initial_pc,
-            psr: 0,
+            psr: Self::PSR_EL0T,
             sp: initial_sp,
         }
     }
 
+    /// Creates the initial context of a kernel thread, which runs at EL1 rather than EL0.
+    ///
+    /// Exceptions taken from kernel threads are handled by the EL1 SP_EL0 vectors.
+    pub fn new_kernel(initial_pc: *const (), initial_sp: *const ()) -> Self {
+        Self {
+            psr: Self::PSR_EL1T,
+            ..Self::new(initial_pc, initial_sp)
+        }
+    }
+
+    /// Returns the program counter.
+    pub fn pc(&self) -> usize {
+        self.pc as usize
+    }
+
+    /// Sets the program counter, where the task resumes when it's restored.
+    pub fn set_pc(&mut self, pc: usize) {
+        self.pc = pc as *const ();
+    }
+
+    /// Returns the saved program status register (`PSTATE`).
+    pub fn psr(&self) -> u64 {
+        self.psr
+    }
+
+    /// Sets the saved program status register, which is restored to `PSTATE`.
+    pub fn set_psr(&mut self, psr: u64) {
+        self.psr = psr;
+    }
+
+    /// Returns the stack pointer.
+    pub fn sp(&self) -> usize {
+        self.sp as usize
+    }
+
+    /// Sets the stack pointer.
+    pub fn set_sp(&mut self, sp: usize) {
+        self.sp = sp as *const ();
+    }
+
+    /// Returns `x<n>`, such as a system call argument.
+    pub fn x(&self, n: usize) -> u64 {
+        self.gprs[n]
+    }
+
+    /// Sets `x<n>`, such as an argument to the task's entry point.
+    pub fn set_x(&mut self, n: usize, value: u64) {
+        self.gprs[n] = value;
+    }
+
+    /// Sets `x0`, which holds the return value of a system call when the task is restored.
+    pub fn set_x0(&mut self, value: u64) {
+        self.gprs[0] = value;
+    }
+
     fn from_sp_el1(sp_el1: *const ()) -> *const Context {
         unsafe { (sp_el1 as *const Context).sub(1) }
     }
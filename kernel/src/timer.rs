@@ -0,0 +1,114 @@
+//! The EL1 physical timer of the Arm generic timer, which drives the scheduler, and the system
+//! counter it compares against.
+//!
+//! Firmware that enters the kernel at EL2, like a Raspberry Pi's, may not let EL1 use them, so
+//! entry.s does that before dropping to EL1. The counter frequency is whatever firmware set
+//! CNTFRQ_EL0 to, like 62.5 MHz on QEMU and 54 MHz on a Pi 4.
+use fdt::Fdt;
+use hal::{InterruptController, Timer};
+
+use crate::error::KernelError;
+use crate::gicv2::InterruptId;
+use crate::{board, boot_info};
+
+/// Log target of the timer driver.
+pub const LOG_TARGET: &str = "timer";
+
+/// Time between scheduler ticks, in milliseconds.
+pub const TICK_MS: u64 = 100;
+
+// TODO starting with the incorrect values seems bad, is this bad?
+static mut INTERRUPT: InterruptId = InterruptId::spurious();
+
+/// The EL1 physical timer (CNTP_*_EL0), and the physical count (CNTPCT_EL0).
+pub struct ArchTimer;
+
+impl Timer for ArchTimer {
+    fn now(&self) -> u64 {
+        // SAFETY: reading the counter has no side effects.
+        unsafe { read_special_reg!("CNTPCT_EL0") }
+    }
+
+    fn frequency(&self) -> u64 {
+        // SAFETY: reading the counter frequency has no side effects.
+        unsafe { read_special_reg!("CNTFRQ_EL0") }
+    }
+
+    fn deadline(&self) -> u64 {
+        // SAFETY: reading the compare value has no side effects.
+        unsafe { read_special_reg!("CNTP_CVAL_EL0") }
+    }
+
+    fn set_after(&self, ticks: u64) {
+        // SAFETY: the timer is only programmed by this driver, with IRQs masked.
+        unsafe {
+            write_special_reg!("CNTP_CVAL_EL0", self.now() + ticks);
+        }
+    }
+
+    fn enable(&self) {
+        // SAFETY: as above.
+        unsafe {
+            write_special_reg!("CNTP_CTL_EL0", 1u64);
+        }
+    }
+
+    fn disable(&self) {
+        // SAFETY: as above.
+        unsafe {
+            write_special_reg!("CNTP_CTL_EL0", 0u64);
+        }
+    }
+}
+
+/// Returns whether `interrupt_id` is the timer's interrupt.
+pub fn is_interrupt(interrupt_id: InterruptId) -> bool {
+    // SAFETY: INTERRUPT is only written during boot.
+    interrupt_id == unsafe { INTERRUPT }
+}
+
+/// Sets the timer to fire at the next scheduler tick.
+pub fn tick() {
+    board::timer().set_after(board::timer().ticks_from_ms(TICK_MS));
+}
+
+/// Enables the EL1 physical timer and its interrupt, which drives the scheduler.
+fn init(_fdt: &Fdt) -> Result<(), KernelError> {
+    let timer = boot_info().timer.ok_or(KernelError::DeviceNotFound {
+        compatible: "arm,armv8-timer",
+    })?;
+
+    log::debug!(target: LOG_TARGET, "CNTFRQ_EL0 = {:016X}h", board::timer().frequency());
+    board::timer().enable();
+
+    // SAFETY: initcalls run before interrupts are unmasked.
+    unsafe {
+        // TODO document this, is it the virt or the non-secure phys?
+        // https://github.com/torvalds/linux/blob/90b0c2b2edd1adff742c621e246562fbefa11b70/Documentation/devicetree/bindings/timer/arm%2Carch_timer.yaml#L44-L58
+        INTERRUPT = timer.phys.try_into()?;
+        board::interrupt_controller().enable_interrupt(INTERRUPT);
+    }
+    driver::register_suspend(driver::SuspendHooks {
+        name: LOG_TARGET,
+        suspend,
+        resume,
+    })?;
+
+    Ok(())
+}
+initcall!(driver, init);
+
+/// Stops the timer, so it doesn't wake the system from suspend (see suspend.rs).
+fn suspend() {
+    board::timer().disable();
+    // SAFETY: suspend hooks run with IRQs masked, so the timer interrupt can't be handled.
+    unsafe { board::interrupt_controller().disable_interrupt(INTERRUPT) };
+}
+
+/// Restarts the timer with a fresh tick, since the last one expired long ago.
+fn resume() {
+    tick();
+    board::timer().enable();
+    // SAFETY: see suspend.
+    unsafe { board::interrupt_controller().enable_interrupt(INTERRUPT) };
+}
@@ -0,0 +1,103 @@
+//! A trace of scheduler decisions and page allocations, kept in a ring buffer in the format
+//! defined by `trace_format`.
+//!
+//! Dump the buffer with `dump-trace <file>` in GDB (see tools/gdb/dump_trace.py), then convert it
+//! to Chrome trace JSON for Perfetto (https://ui.perfetto.dev) with `cargo xtask trace convert`.
+//!
+//! Page allocations go in a buffer of their own, so the scheduler can't push them out of it. Dump
+//! that one with `dump-trace --pages <file>`, then replay it on the host with `buddy-alloc replay
+//! <file>`, as long as it hasn't wrapped since the page allocator was set up.
+//!
+//! With `schedstats=on` on the command line, each context switch also records how long the
+//! previous task ran for and how long the next task waited to run, which the converter turns into
+//! scheduling latency percentiles for each task. These are off by default, since they fill the
+//! buffer three times as fast.
+use fdt::Fdt;
+use hal::Timer;
+use trace_format::{Buffer, Event};
+
+use crate::error::KernelError;
+use crate::{board, cmdline};
+
+/// Number of records kept, after which the oldest are overwritten.
+const CAPACITY: usize = 1024;
+
+/// Number of page allocator records kept, which is enough for every allocation made during boot
+/// and the self-tests, with room to spare.
+const PAGE_CAPACITY: usize = 4096;
+
+/// The trace buffer, which GDB finds by its unmangled name.
+#[no_mangle]
+static mut TRACE_BUFFER: Buffer<CAPACITY> = Buffer::new();
+
+/// The trace buffer for page allocator events, which GDB also finds by its unmangled name.
+#[no_mangle]
+static mut PAGE_TRACE_BUFFER: Buffer<PAGE_CAPACITY> = Buffer::new();
+
+/// Whether to record [`Event::TaskRan`] and [`Event::TaskWaited`], set by `schedstats=on`.
+static mut SCHED_STATS: bool = false;
+
+/// Records the counter frequency, so timestamps can be converted to real time, and reads the
+/// `schedstats` option.
+fn init(_fdt: &Fdt) -> Result<(), KernelError> {
+    // SAFETY: initcalls run one at a time with interrupts masked, so nothing else is recording.
+    unsafe {
+        TRACE_BUFFER.set_frequency(board::timer().frequency());
+        PAGE_TRACE_BUFFER.set_frequency(board::timer().frequency());
+    }
+
+    let enabled = match cmdline::option("schedstats") {
+        Some("on") => true,
+        Some("off") | None => false,
+        Some(value) => {
+            log::warn!("schedstats={value} is not on or off, so leaving it off");
+            false
+        }
+    };
+    // SAFETY: nothing reads SCHED_STATS until the scheduler starts.
+    unsafe { SCHED_STATS = enabled };
+
+    Ok(())
+}
+initcall!(early, init);
+
+/// Returns whether per-task scheduling statistics should be recorded on every context switch.
+pub fn sched_stats() -> bool {
+    // SAFETY: SCHED_STATS is only written by init, before the scheduler starts.
+    unsafe { SCHED_STATS }
+}
+
+/// Records an event, timestamped with the current counter value.
+///
+/// # Safety
+///
+/// Must not be called concurrently, such as from an exception handler that can interrupt another
+/// caller, since the buffer isn't locked.
+pub unsafe fn record(event: Event) {
+    // SAFETY: the caller ensures that nothing else is using the trace buffer.
+    unsafe { TRACE_BUFFER.push(board::timer().now(), event) };
+}
+
+/// Records a page allocator event in the page allocator's own buffer, like [`record`].
+///
+/// # Safety
+///
+/// Must not be called concurrently, like [`record`].
+pub unsafe fn record_page(event: Event) {
+    // SAFETY: the caller ensures that nothing else is using the page trace buffer.
+    unsafe { PAGE_TRACE_BUFFER.push(board::timer().now(), event) };
+}
+
+/// A copy of the trace buffer, as taken by [`copy_into`].
+pub type Copy = Buffer<CAPACITY>;
+
+/// Copies up to `max` records of the trace buffer to `copy`, starting with record `next`, returning
+/// the record to carry on from, or `None` once the copy is complete (see [`Buffer::copy_into`]).
+///
+/// # Safety
+///
+/// Must not be called concurrently with [`record`].
+pub unsafe fn copy_into(copy: &mut Copy, next: usize, max: usize) -> Option<usize> {
+    // SAFETY: the caller ensures that nothing else is using the trace buffer.
+    unsafe { TRACE_BUFFER.copy_into(copy, next, max) }
+}
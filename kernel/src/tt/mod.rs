@@ -1,31 +1,253 @@
-pub mod descriptor;
+use core::arch::asm;
+use core::ops::Range;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use fdt::Fdt;
+use translation_tables::descriptor::page::{MapAttributes, MAIR};
+use translation_tables::table::TranslationTable;
+use translation_tables::{granule, RootLevel};
+
+use crate::error::KernelError;
+use crate::linker_symbols;
+use crate::sync::without_interrupts;
+use crate::tt::page::KernelMemory;
+
 pub mod page;
-pub mod table;
+mod protect;
+
+pub use translation_tables::PAGE_SIZE;
+
+/// Physical address of the kernel's root translation table, once [`init`] has replaced the tables
+/// from entry.s.
+static KERNEL_TT: AtomicUsize = AtomicUsize::new(0);
+
+/// Whether the MMU manages the dirty state of pages with DBM set (TCR_EL1.HD).
+static HARDWARE_DIRTY: AtomicBool = AtomicBool::new(false);
+
+/// Asks the MMU to translate an address with the given AT operation, returning whether it can.
+macro_rules! translate {
+    ($operation:literal, $address:expr) => {{
+        // SAFETY: AT only writes PAR_EL1, which we read back before anything else can use it.
+        let par: u64 = without_interrupts(|| unsafe {
+            let par;
+            asm!(
+                concat!("at ", $operation, ", {address}"),
+                "isb",
+                "mrs {par}, PAR_EL1",
+                address = in(reg) $address,
+                par = out(reg) par,
+            );
+            par
+        });
+
+        // PAR_EL1.F is set if the translation failed
+        par & 1 == 0
+    }};
+}
+
+/// Returns whether `address` is mapped for reads at EL1, by asking the MMU to translate it.
+pub fn is_readable(address: usize) -> bool {
+    translate!("s1e1r", address)
+}
+
+/// Returns whether `address` is mapped for reads at EL0, so a task could read it itself.
+pub fn is_readable_by_task(address: usize) -> bool {
+    translate!("s1e0r", address)
+}
+
+/// Returns whether `address` is mapped for writes at EL0, so a task could write to it itself.
+pub fn is_writable_by_task(address: usize) -> bool {
+    translate!("s1e0w", address)
+}
+
+/// Runs `f` with the kernel's translation tables, or returns None if they haven't been set up yet.
+fn with_kernel_tt<R>(
+    f: impl FnOnce(&mut TranslationTable<RootLevel>, &KernelMemory) -> R,
+) -> Option<R> {
+    let root = KERNEL_TT.load(Ordering::Relaxed);
+    if root == 0 {
+        return None;
+    }
+
+    without_interrupts(|| {
+        // SAFETY: the tables are only borrowed with interrupts masked, and thus only by one
+        // caller.
+        let tt = unsafe { TranslationTable::<RootLevel>::from_pa_mut(root, &KernelMemory) };
+        Some(f(tt, &KernelMemory))
+    })
+}
+
+/// Handles an access flag fault for a kernel address by setting the access flag, returning false
+/// if the fault can't be handled that way.
+pub fn handle_access_flag_fault(address: usize) -> bool {
+    // only the upper half (TTBR1_EL1) is translated by the kernel's tables
+    if address >> 48 != 0xFFFF {
+        return false;
+    }
 
-pub trait IntermediateLevel {
-    type Next;
+    with_kernel_tt(|tt, memory| tt.mark_accessed(address, memory))
+        .and_then(Result::ok)
+        .unwrap_or(false)
 }
 
-pub trait FinalLevel {}
+/// Clears the access flag of the kernel pages in `range`, returning how many had been accessed
+/// since the last time.
+#[allow(dead_code)]
+pub fn clear_accessed(range: Range<usize>) -> Result<usize, KernelError> {
+    let address = range.start;
+    with_kernel_tt(|tt, memory| tt.clear_accessed(range, memory))
+        .ok_or(KernelError::Unmapped { address })?
+        .map_err(Into::into)
+}
 
-macro_rules! define_levels {
-    ($level:ident, $next_level:ident$(, $rest:ident)*) => {
-        #[derive(Debug)]
-        pub struct $level;
+/// Starts tracking whether the kernel pages in `range` are dirty, if the MMU can do so.
+#[allow(dead_code)]
+pub fn track_dirty(range: Range<usize>) -> Result<usize, KernelError> {
+    if !HARDWARE_DIRTY.load(Ordering::Relaxed) {
+        return Err(KernelError::Unsupported {
+            feature: "hardware dirty state management",
+        });
+    }
 
-        impl IntermediateLevel for $level {
-            type Next = $next_level;
-        }
+    let address = range.start;
+    with_kernel_tt(|tt, memory| tt.track_dirty(range, memory))
+        .ok_or(KernelError::Unmapped { address })?
+        .map_err(Into::into)
+}
 
-        define_levels!($next_level$(, $rest)*);
-    };
+/// Makes the dirty kernel pages in `range` clean again, returning how many were dirty.
+#[allow(dead_code)]
+pub fn clear_dirty(range: Range<usize>) -> Result<usize, KernelError> {
+    let address = range.start;
+    with_kernel_tt(|tt, memory| tt.clear_dirty(range, memory))
+        .ok_or(KernelError::Unmapped { address })?
+        .map_err(Into::into)
+}
 
-    ($level:ident) => {
-        #[derive(Debug)]
-        pub struct $level;
+/// Maps `len` bytes of kernel virtual memory at `virtual_address` to `physical_address`, with
+/// the given flags (see [`translation_tables::descriptor::page::permission_bits`]), and memory
+/// type if it's not Normal memory (see [`MapAttributes`]).
+pub fn map<'f>(
+    virtual_address: usize,
+    physical_address: usize,
+    len: usize,
+    attributes: impl Into<MapAttributes<'f>>,
+) -> Result<(), KernelError> {
+    let attributes = attributes.into();
+    with_kernel_tt(|tt, _| {
+        let end = virtual_address + len;
+        tt.map_contiguous(
+            virtual_address,
+            end,
+            physical_address,
+            attributes,
+            &mut KernelMemory,
+        )
+    })
+    .ok_or(KernelError::Unmapped {
+        address: virtual_address,
+    })?
+    .map_err(Into::into)
+}
+
+/// Unmaps the kernel page at `virtual_address`, returning the physical address it was mapped to,
+/// if it was mapped.
+pub fn unmap(virtual_address: usize) -> Result<Option<usize>, KernelError> {
+    with_kernel_tt(|tt, memory| -> Result<_, KernelError> {
+        let Some(physical_address) = tt.walk(virtual_address, memory).physical_address() else {
+            return Ok(None);
+        };
+        tt.unmap_page(virtual_address, memory)?;
+        page::invalidate_tlb(virtual_address);
+
+        Ok(Some(physical_address))
+    })
+    .ok_or(KernelError::Unmapped {
+        address: virtual_address,
+    })?
+}
 
-        impl FinalLevel for $level {}
-    };
+/// Changes the permissions of the kernel pages in `range` to the given flags, returning how many
+/// pages changed.
+pub fn protect(range: Range<usize>, flags: &str) -> Result<usize, KernelError> {
+    let address = range.start;
+    with_kernel_tt(|tt, memory| tt.protect(range, flags, memory))
+        .ok_or(KernelError::Unmapped { address })?
+        .map_err(Into::into)
 }
 
-define_levels!(Level0, Level1, Level2, Level3);
+/// Returns how many of the kernel pages in `range` are mapped.
+pub fn resident_pages(range: Range<usize>) -> usize {
+    let start = range.start & !(PAGE_SIZE - 1);
+    with_kernel_tt(|tt, memory| {
+        (start..range.end)
+            .step_by(PAGE_SIZE)
+            .filter(|&address| tt.walk(address, memory).physical_address().is_some())
+            .count()
+    })
+    .unwrap_or(0)
+}
+
+/// Replaces the kernel's translation tables from entry.s with ones managed by this module.
+fn init(_fdt: &Fdt) -> Result<(), KernelError> {
+    let mut memory = KernelMemory;
+    let root = TranslationTable::<RootLevel>::alloc(&mut memory)?;
+    // SAFETY: we just allocated the table, and the tables stay in use (and are never freed) for
+    // as long as the kernel runs.
+    let tt = unsafe { TranslationTable::<RootLevel>::from_pa_mut(root, &memory) };
+
+    tt.map_contiguous(
+        linker_symbols::kernel_start().addr(),
+        linker_symbols::kernel_end().addr(),
+        linker_symbols::kernel_start_pa().addr(),
+        // TODO: map each section with its own permissions, once they're page-aligned
+        "rwx",
+        &mut memory,
+    )?;
+    protect::map_window(tt, &mut memory)?;
+
+    // our tables use the same memory attributes as entry.s's
+    // SAFETY: reading MAIR_EL1 has no side effects.
+    let mair = unsafe { read_special_reg!("MAIR_EL1") };
+    assert!(
+        mair == MAIR,
+        "MAIR_EL1 from entry.s is {mair:#x}, but the translation tables use {MAIR:#x}"
+    );
+
+    // let the MMU manage the access flag and dirty state, if it can (ID_AA64MMFR1_EL1.HAFDBS),
+    // instead of taking access flag faults
+    // SAFETY: reading ID_AA64MMFR1_EL1 has no side effects.
+    let hafdbs = unsafe { read_special_reg!("ID_AA64MMFR1_EL1") } & 0xF;
+    let (tcr_ha, tcr_hd) = (u64::from(hafdbs >= 1), u64::from(hafdbs >= 2));
+    HARDWARE_DIRTY.store(hafdbs >= 2, Ordering::Relaxed);
+    log::debug!(
+        "hardware access flag: {}, dirty state: {}",
+        hafdbs >= 1,
+        hafdbs >= 2
+    );
+
+    // entry.s always uses the 4KiB granule, so switch TCR_EL1.TG1 to our granule at the same time
+    // as switching the tables, then discard any TLB entries from the old tables.
+    // SAFETY: the new tables map the kernel image at the same addresses as the old ones.
+    unsafe {
+        let mut tcr: u64;
+        asm!("mrs {}, TCR_EL1", out(reg) tcr);
+        tcr = tcr & !(0b11 << 30 | 0b11 << 39) | granule::TCR_TG1 << 30;
+        tcr |= tcr_ha << 39 | tcr_hd << 40;
+        asm!(
+            "dsb ishst",
+            "msr TTBR1_EL1, {root:x}",
+            "msr TCR_EL1, {tcr:x}",
+            "isb",
+            "tlbi vmalle1",
+            "dsb ish",
+            "isb",
+            root = in(reg) root,
+            tcr = in(reg) tcr,
+        );
+    }
+    KERNEL_TT.store(root, Ordering::Relaxed);
+
+    Ok(())
+}
+initcall!(arch, init);
@@ -1,10 +1,12 @@
 use core::alloc::Layout;
+use core::arch::asm;
 use core::marker::PhantomData;
-use core::ops::{Deref, DerefMut};
+use core::ops::Range;
+
+use translation_tables::Memory;
 
 /// A physical address, with an in-memory representation equivalent to a regular pointer to a value
-/// of type `T`. Uses the kernel's 1:1 physical memory mapping for accesses via [`Self::ptr()`] and
-/// [`Self::ptr_mut()`].
+/// of type `T`. Uses the kernel's 1:1 physical memory mapping for accesses via [`Self::ptr_mut()`].
 ///
 /// As with a regular pointer, this type confers no guarantees of validity or alignment.
 ///
@@ -27,7 +29,7 @@ pub struct PhysicalAddress<T> {
 
 impl<T> PhysicalAddress<T> {
     /// The base of the kernel's 1:1 physical memory mapping.
-    const PHYS_BASE: usize = 0xffff_0000_0000_0000;
+    pub const PHYS_BASE: usize = 0xffff_0000_0000_0000;
 
     pub fn from_addr(addr: usize) -> Self {
         Self {
@@ -41,11 +43,6 @@ impl<T> PhysicalAddress<T> {
         self.addr
     }
 
-    /// Returns a regular pointer using the kernel's 1:1 physical memory mapping.
-    pub fn ptr(self) -> *const T {
-        (Self::PHYS_BASE + self.addr) as *const _
-    }
-
     /// Returns a regular, mutable pointer using the kernel's 1:1 physical memory mapping.
     pub fn ptr_mut(self) -> *mut T {
         (Self::PHYS_BASE + self.addr) as *mut _
@@ -70,62 +67,22 @@ impl<T> Clone for PhysicalAddress<T> {
 // a pointer is Copy even if T isn't Copy (so we can't just `#[derive(Copy)]`)
 impl<T> Copy for PhysicalAddress<T> {}
 
-/// An owned page.
-#[derive(Debug)]
-#[repr(transparent)]
-pub struct PageBox<T>(PhysicalAddress<T>);
-
-impl<T> PageBox<T> {
-    /// Allocates a new page and places `val` into it.
-    pub fn new(val: T) -> Self {
-        let layout = Layout::for_value(&val);
-        let pa = PageAllocator.alloc(layout).cast::<T>();
-
-        unsafe { pa.ptr_mut().write_volatile(val) };
-
-        Self(pa)
-    }
-
-    pub fn addr(&self) -> PhysicalAddress<T> {
-        self.0
-    }
-
-    pub fn leak(self) -> PhysicalAddress<T> {
-        self.0
-    }
-}
-
-impl<T> Drop for PageBox<T> {
-    fn drop(&mut self) {
-        unsafe { self.0.ptr_mut().drop_in_place() }
-    }
-}
-
-impl<T> Deref for PageBox<T> {
-    type Target = T;
-
-    fn deref(&self) -> &Self::Target {
-        unsafe { &*self.0.ptr() }
-    }
-}
-
-impl<T> DerefMut for PageBox<T> {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        unsafe { &mut *self.0.ptr_mut() }
-    }
-}
+/// Physical memory for translation tables, between the FDT and the kernel image. This is the only
+/// memory in the kernel's 1:1 physical memory mapping (see [`super::protect`]).
+pub const TABLE_POOL: Range<usize> = 0x4010_0000..0x4020_0000;
 
 // TODO: move this somewhere better, and implement a better allocator that actually tracks
 // allocations
-static mut ALLOC_BASE: usize = 0x4000_0000 + 0x10_0000;
+static mut ALLOC_BASE: usize = TABLE_POOL.start;
 
 struct PageAllocator;
 
 impl PageAllocator {
-    const PAGE_SIZE: usize = 0x1000;
+    const PAGE_SIZE: usize = translation_tables::PAGE_SIZE;
 
-    /// Allocates a page in physical memory and returns the physical address of the page.
-    fn alloc(&self, layout: Layout) -> PhysicalAddress<[u8; Self::PAGE_SIZE]> {
+    /// Allocates a page in physical memory and returns the physical address of the page, or None
+    /// if the pool is exhausted.
+    fn alloc(&self, layout: Layout) -> Option<PhysicalAddress<[u8; Self::PAGE_SIZE]>> {
         // we don't support zero-sized allocations
         // TODO: should we support zero-sized allocations?
         assert!(layout.size() > 0);
@@ -136,9 +93,52 @@ impl PageAllocator {
         assert!(layout.align() <= Self::PAGE_SIZE);
 
         unsafe {
+            if ALLOC_BASE >= TABLE_POOL.end {
+                return None;
+            }
             let pa = PhysicalAddress::from_addr(ALLOC_BASE);
             ALLOC_BASE += Self::PAGE_SIZE;
-            pa
+            Some(pa)
         }
     }
 }
+
+/// Memory for translation tables, allocated with [`PageAllocator`] and accessed through the
+/// kernel's 1:1 physical memory mapping.
+pub struct KernelMemory;
+
+impl Memory for KernelMemory {
+    fn alloc_page(&mut self) -> Option<usize> {
+        let layout = Layout::new::<[u8; PageAllocator::PAGE_SIZE]>();
+
+        Some(PageAllocator.alloc(layout)?.addr())
+    }
+
+    fn ptr(&self, pa: usize) -> *mut u8 {
+        PhysicalAddress::<u8>::from_addr(pa).ptr_mut()
+    }
+
+    fn invalidate_tlb(&self, virtual_address: usize) {
+        invalidate_tlb(virtual_address);
+    }
+
+    fn with_writable<R>(&self, table: *mut u8, write: impl FnOnce() -> R) -> R {
+        super::protect::with_writable(table as usize, write)
+    }
+}
+
+/// Makes previous writes to translation tables visible to the MMU, then invalidates any TLB
+/// entries for `virtual_address` (see [`Memory::invalidate_tlb`]).
+pub fn invalidate_tlb(virtual_address: usize) {
+    // SAFETY: TLB maintenance only makes later translations use the tables in memory.
+    unsafe {
+        asm!(
+            "dsb ishst",
+            // by VA[55:12], for any ASID, including cached walks (unlike vaale1is)
+            "tlbi vaae1is, {page}",
+            "dsb ish",
+            "isb",
+            page = in(reg) virtual_address >> 12,
+        );
+    }
+}
@@ -0,0 +1,108 @@
+//! Protection for the kernel's translation tables, which are read-only once boot is done.
+//!
+//! The tables are only reachable through a window in the kernel's 1:1 physical memory mapping,
+//! which covers [`TABLE_POOL`] with a level 3 table of its own in the kernel image. Once the late
+//! initcalls run, every page in the window is made read-only, and [`with_writable`] makes one
+//! table writable at a time for the translation-tables crate to write to it, so a stray write to
+//! a table faults instead of silently changing the kernel's mappings.
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use fdt::Fdt;
+use translation_tables::granule::block_size;
+use translation_tables::table::TranslationTable;
+use translation_tables::{Level3, Memory, RootLevel, PAGE_SIZE};
+
+use crate::addr::VirtAddr;
+use crate::error::KernelError;
+use crate::linker_symbols;
+use crate::sync::without_interrupts;
+use crate::tt::page::{self, KernelMemory, PhysicalAddress, TABLE_POOL};
+
+/// The level 3 table for the window, which isn't in the pool itself, so it can always be written.
+static mut WINDOW: TranslationTable<Level3> = TranslationTable::new();
+
+/// Whether the window has been made read-only.
+static PROTECTED: AtomicBool = AtomicBool::new(false);
+
+/// The memory of [`WINDOW`], which only ever needs TLB maintenance.
+struct WindowMemory;
+
+impl Memory for WindowMemory {
+    fn alloc_page(&mut self) -> Option<usize> {
+        None
+    }
+
+    fn ptr(&self, _pa: usize) -> *mut u8 {
+        unreachable!("the window has no tables below it")
+    }
+
+    fn invalidate_tlb(&self, virtual_address: usize) {
+        page::invalidate_tlb(virtual_address);
+    }
+}
+
+/// Maps the table pool into the kernel's 1:1 physical memory mapping in `tt`, writable for now.
+pub fn map_window(
+    tt: &mut TranslationTable<RootLevel>,
+    memory: &mut KernelMemory,
+) -> Result<(), KernelError> {
+    let start = PhysicalAddress::<u8>::PHYS_BASE + TABLE_POOL.start;
+    let end = PhysicalAddress::<u8>::PHYS_BASE + TABLE_POOL.end;
+    // the window must fit in one level 3 table
+    assert_eq!(start / block_size(2), (end - 1) / block_size(2));
+
+    // SAFETY: the window is only borrowed with interrupts masked, or before they're unmasked.
+    let window = unsafe { &mut WINDOW };
+    let window_pa = linker_symbols::kernel_pa(VirtAddr::new(window as *mut _ as usize));
+    tt.map_table(start, window_pa.addr(), memory)?;
+    for pa in TABLE_POOL.step_by(PAGE_SIZE) {
+        window.set_page(start + pa - TABLE_POOL.start, pa, false, &WindowMemory);
+    }
+
+    Ok(())
+}
+
+/// Runs `write`, which writes to the table at virtual address `table` in the window, with the
+/// table writable (see [`Memory::with_writable`]).
+pub fn with_writable<R>(table: usize, write: impl FnOnce() -> R) -> R {
+    if !PROTECTED.load(Ordering::Relaxed) {
+        return write();
+    }
+
+    let pa = table - PhysicalAddress::<u8>::PHYS_BASE;
+    assert!(
+        TABLE_POOL.contains(&pa),
+        "{table:#x} is not in the table pool"
+    );
+
+    without_interrupts(|| {
+        // SAFETY: see map_window.
+        let window = unsafe { &mut WINDOW };
+        window.set_page(table, pa, false, &WindowMemory);
+        let result = write();
+        window.set_page(table, pa, true, &WindowMemory);
+
+        result
+    })
+}
+
+/// Makes the tables read-only, now that the kernel's mappings are set up.
+fn protect(_fdt: &Fdt) -> Result<(), KernelError> {
+    without_interrupts(|| {
+        // SAFETY: see map_window.
+        let window = unsafe { &mut WINDOW };
+        for pa in TABLE_POOL.step_by(PAGE_SIZE) {
+            window.set_page(
+                PhysicalAddress::<u8>::PHYS_BASE + pa,
+                pa,
+                true,
+                &WindowMemory,
+            );
+        }
+        PROTECTED.store(true, Ordering::Relaxed);
+    });
+    log::debug!("translation tables in {TABLE_POOL:x?} are now read-only");
+
+    Ok(())
+}
+initcall!(late, protect);
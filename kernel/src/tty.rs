@@ -0,0 +1,309 @@
+//! The console TTY, through which tasks read from and write to the console UART.
+//!
+//! While any task has the console open, input goes through the line discipline here rather than
+//! to the debug console. In canonical mode (the default), input is echoed, backspace erases the
+//! last byte, and reads only return whole lines. In raw mode, reads return bytes as soon as they
+//! arrive, with no echo or editing.
+//!
+//! In canonical mode, Ctrl+C discards the line being typed and interrupts the foreground task,
+//! which is the task that most recently opened the console, or was chosen with the `fg` console
+//! command: every task in its task group is sent [`abi::signal::INTERRUPT`], and the foreground
+//! task's next read fails with [`KernelError::Interrupted`].
+//!
+//! Like in QEMU and screen, Ctrl+A starts an escape sequence, whatever the mode. Ctrl+A then `c`
+//! switches input between the foreground task and the debug console, so the debug console can be
+//! used while a task has the console open, and Ctrl+A twice sends a Ctrl+A. Input waits in the
+//! buffer while it goes to the debug console.
+use crate::console::{self, Output};
+use crate::error::KernelError;
+use crate::signal::{self, Signal};
+use crate::sync::without_interrupts;
+
+/// Number of bytes of input that can wait to be read, including the line being typed.
+const BUFFER_LEN: usize = 256;
+
+/// Ctrl+C, which is ETX in ASCII.
+const INTERRUPT: u8 = 0x03;
+
+/// Ctrl+A, which is SOH in ASCII, and starts an escape sequence.
+const ESCAPE: u8 = 0x01;
+
+/// What each escape sequence does, as printed by Ctrl+A then `h`.
+const ESCAPE_HELP: &str = "\
+Ctrl+A c       switch between the foreground task and the debug console
+Ctrl+A Ctrl+A  send Ctrl+A
+";
+
+static mut TTY: Tty = Tty::new();
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    Canonical,
+    Raw,
+}
+
+struct Tty {
+    mode: Mode,
+    buffer: [u8; BUFFER_LEN],
+    /// Number of bytes in the buffer.
+    len: usize,
+    /// Number of bytes at the start of the buffer that can be read, which in canonical mode is
+    /// only the lines that are complete.
+    ready: usize,
+    /// Number of open handles to the console.
+    handles: usize,
+    /// Id of the task that Ctrl+C interrupts.
+    foreground: Option<usize>,
+    /// Whether Ctrl+C was pressed since the foreground task last read.
+    interrupted: bool,
+    /// Whether Ctrl+A was the last byte of input, so the next byte is an escape.
+    escaped: bool,
+    /// Whether input was switched to the debug console with Ctrl+A then `c`, while a task has
+    /// the console open.
+    in_console: bool,
+}
+
+impl Tty {
+    const fn new() -> Self {
+        Self {
+            mode: Mode::Canonical,
+            buffer: [0; BUFFER_LEN],
+            len: 0,
+            ready: 0,
+            handles: 0,
+            foreground: None,
+            interrupted: false,
+            escaped: false,
+            in_console: false,
+        }
+    }
+
+    /// Handles a byte of input from the console UART, returning whether it's for the debug
+    /// console instead.
+    fn input(&mut self, byte: u8, out: &mut Output) -> bool {
+        if self.escaped {
+            self.escaped = false;
+            match byte {
+                // Ctrl+A twice sends Ctrl+A itself
+                ESCAPE => {}
+                b'c' => {
+                    self.switch(out);
+                    return false;
+                }
+                b'h' | b'?' => {
+                    write!(out, "\n{ESCAPE_HELP}");
+                    self.redraw(out);
+                    return false;
+                }
+                _ => return false,
+            }
+        } else if byte == ESCAPE {
+            self.escaped = true;
+            return false;
+        }
+
+        if !self.takes_input() {
+            return true;
+        }
+        self.receive(byte, out);
+
+        false
+    }
+
+    /// Returns whether input goes to the foreground task, rather than the debug console.
+    fn takes_input(&self) -> bool {
+        self.handles > 0 && !self.in_console
+    }
+
+    /// Switches input between the foreground task and the debug console.
+    fn switch(&mut self, out: &mut Output) {
+        if self.handles == 0 {
+            out.write_bytes(b"\n[no task has the console open]\n");
+        } else {
+            self.in_console = !self.in_console;
+            match (self.in_console, self.foreground) {
+                (true, Some(id)) => {
+                    write!(out, "\n[debug console, Ctrl+A c returns to task {id}]\n")
+                }
+                (true, None) => write!(out, "\n[debug console, Ctrl+A c returns to the task]\n"),
+                (false, Some(id)) => write!(out, "\n[task {id}]\n"),
+                (false, None) => write!(out, "\n[task]\n"),
+            }
+        }
+        self.redraw(out);
+    }
+
+    /// Prints the line being typed again, wherever input goes, after something else was printed
+    /// over it.
+    fn redraw(&self, out: &mut Output) {
+        if !self.takes_input() {
+            console::redraw();
+        } else if self.mode == Mode::Canonical {
+            out.write_bytes(&self.buffer[self.ready..self.len]);
+        }
+    }
+
+    fn receive(&mut self, byte: u8, out: &mut Output) {
+        if self.mode == Mode::Raw {
+            if self.len < BUFFER_LEN {
+                self.buffer[self.len] = byte;
+                self.len += 1;
+                self.ready = self.len;
+            }
+            return;
+        }
+
+        match byte {
+            b'\r' | b'\n' if self.len < BUFFER_LEN => {
+                out.write_bytes(b"\n");
+                self.buffer[self.len] = b'\n';
+                self.len += 1;
+                self.ready = self.len;
+            }
+            // backspace or delete, which can't erase lines that are already complete
+            0x08 | 0x7F => {
+                if self.len > self.ready {
+                    self.len -= 1;
+                    out.write_bytes(b"\x08 \x08");
+                }
+            }
+            INTERRUPT => {
+                out.write_bytes(b"^C\n");
+                self.len = self.ready;
+                if let Some(id) = self.foreground {
+                    self.interrupted = true;
+                    if let Err(error) = signal::group_of(id)
+                        .and_then(|group| signal::post_group(group, Signal::INTERRUPT))
+                    {
+                        log::warn!("failed to interrupt task {id}: {error}");
+                    }
+                }
+            }
+            // leave room for the newline that ends the line
+            b' '..=b'~' if self.len + 1 < BUFFER_LEN => {
+                self.buffer[self.len] = byte;
+                self.len += 1;
+                out.write_bytes(&[byte]);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Opens a handle to the console for task `id`, which becomes the foreground task, and takes
+/// input from the debug console if it had it.
+pub fn open(id: usize) {
+    with_tty(|tty| {
+        tty.handles += 1;
+        tty.foreground = Some(id);
+        tty.in_console = false;
+    });
+}
+
+/// Makes task `id`, which must have the console open, the foreground task, and switches input
+/// to it from the debug console.
+pub fn set_foreground(id: usize) {
+    with_tty(|tty| {
+        tty.foreground = Some(id);
+        tty.in_console = false;
+    });
+}
+
+/// Switches input back to the foreground task from the debug console, like Ctrl+A then `c`,
+/// returning the id of the foreground task.
+pub fn resume() -> Result<Option<usize>, KernelError> {
+    with_tty(|tty| {
+        if tty.handles == 0 {
+            return Err(KernelError::InvalidArgument {
+                reason: "no task has the console open",
+            });
+        }
+        tty.in_console = false;
+
+        Ok(tty.foreground)
+    })
+}
+
+/// Opens another handle to the console, such as when a spawned task inherits it.
+pub fn retain() {
+    with_tty(|tty| tty.handles += 1);
+}
+
+/// Closes a handle to the console, handing input back to the debug console once every handle is
+/// closed.
+pub fn release() {
+    with_tty(|tty| {
+        tty.handles -= 1;
+        if tty.handles == 0 {
+            *tty = Tty {
+                escaped: tty.escaped,
+                ..Tty::new()
+            };
+        }
+    });
+}
+
+/// Returns whether input goes to the foreground task, rather than the debug console.
+pub fn takes_input() -> bool {
+    with_tty(|tty| tty.takes_input())
+}
+
+/// Handles a byte of input from the console UART, passing it on to the debug console unless it's
+/// for the foreground task or an escape sequence.
+///
+/// Must only be called from one thread (the IRQ thread, via the UART receive handler), like
+/// [`console::receive`].
+pub fn input(byte: u8) {
+    if with_tty(|tty| tty.input(byte, &mut Output)) {
+        // outside with_tty, since commands can take a while, and might use the TTY themselves
+        console::receive(byte);
+    }
+}
+
+/// Reads as much input as is ready into `buffer` for task `id`, returning how many bytes were
+/// read.
+pub fn read(id: usize, buffer: &mut [u8]) -> Result<usize, KernelError> {
+    with_tty(|tty| {
+        if tty.interrupted && tty.foreground == Some(id) {
+            tty.interrupted = false;
+            return Err(KernelError::Interrupted);
+        }
+        if tty.ready == 0 {
+            return match buffer.is_empty() {
+                true => Ok(0),
+                false => Err(KernelError::WouldBlock),
+            };
+        }
+
+        let n = buffer.len().min(tty.ready);
+        buffer[..n].copy_from_slice(&tty.buffer[..n]);
+        tty.buffer.copy_within(n..tty.len, 0);
+        tty.len -= n;
+        tty.ready -= n;
+
+        Ok(n)
+    })
+}
+
+/// Writes `buffer` to the console, returning how many bytes were written.
+pub fn write(buffer: &[u8]) -> usize {
+    without_interrupts(|| Output.write_bytes(buffer));
+
+    buffer.len()
+}
+
+/// Switches between canonical and raw mode, keeping any input that's waiting to be read.
+pub fn set_mode(mode: Mode) {
+    with_tty(|tty| {
+        tty.mode = mode;
+        // in raw mode, the line being typed can be read at once
+        if mode == Mode::Raw {
+            tty.ready = tty.len;
+        }
+    });
+}
+
+fn with_tty<R>(f: impl FnOnce(&mut Tty) -> R) -> R {
+    // SAFETY: the TTY is only accessed with interrupts masked, and thus by one caller at once.
+    without_interrupts(|| f(unsafe { &mut TTY }))
+}
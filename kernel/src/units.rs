@@ -0,0 +1,88 @@
+//! Human-readable formatting of sizes, address ranges and durations, for logs.
+use core::fmt;
+use core::ops::Range;
+
+use hal::Timer;
+
+use crate::board;
+
+/// A size in bytes, formatted with the largest binary unit it has at least one of, like `1.5 MiB`.
+///
+/// Sizes that aren't a whole number of that unit show one decimal place, truncated.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HumanSize(pub usize);
+
+impl fmt::Display for HumanSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+        let bytes = self.0 as u64;
+        let exponent = (bytes.checked_ilog2().unwrap_or(0) / 10).min(UNITS.len() as u32 - 1);
+
+        scaled(
+            f,
+            bytes.into(),
+            1 << (exponent * 10),
+            UNITS[exponent as usize],
+        )
+    }
+}
+
+/// A half-open range of addresses, formatted in hexadecimal, like `0x40000000..0x48000000`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HexRange(pub usize, pub usize);
+
+impl HexRange {
+    /// Returns the number of bytes in the range.
+    pub fn size(&self) -> HumanSize {
+        HumanSize(self.1.saturating_sub(self.0))
+    }
+}
+
+impl From<Range<usize>> for HexRange {
+    fn from(range: Range<usize>) -> Self {
+        Self(range.start, range.end)
+    }
+}
+
+impl fmt::Display for HexRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#x}..{:#x}", self.0, self.1)
+    }
+}
+
+/// A duration in counter ticks (CNTPCT_EL0), formatted in the largest unit of time it has at least
+/// one of, like `1.5 ms`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Ticks(pub u64);
+
+impl fmt::Display for Ticks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let frequency = board::timer().frequency();
+        if frequency == 0 {
+            return write!(f, "{} ticks", self.0);
+        }
+
+        let nanoseconds = u128::from(self.0) * 1_000_000_000 / u128::from(frequency);
+        let (unit, name) = match nanoseconds {
+            0..=999 => (1, "ns"),
+            1_000..=999_999 => (1_000, "µs"),
+            1_000_000..=999_999_999 => (1_000_000, "ms"),
+            _ => (1_000_000_000, "s"),
+        };
+
+        scaled(f, nanoseconds, unit, name)
+    }
+}
+
+/// Writes `value` as a number of `unit`s, with one decimal place if it's not a whole number.
+fn scaled(f: &mut fmt::Formatter<'_>, value: u128, unit: u128, name: &str) -> fmt::Result {
+    let whole = value / unit;
+    let tenths = value % unit * 10 / unit;
+
+    if tenths == 0 {
+        write!(f, "{whole} {name}")
+    } else {
+        write!(f, "{whole}.{tenths} {name}")
+    }
+}
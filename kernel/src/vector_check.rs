@@ -0,0 +1,111 @@
+//! A boot-time check of the exception vectors, and the context save and restore path in entry.s.
+//!
+//! Once VBAR_EL1 is programmed, [`run`] switches to SP_EL0 (like a kernel thread) and executes a
+//! `brk` with an immediate reserved for the check. The exception is taken through the same vector
+//! and `task_save`/`task_restore` path as any other exception from a kernel thread, so if entry.s
+//! or the `Context` layout in task.rs are broken, we find out here rather than once tasks start.
+use core::arch::asm;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::task::Context;
+
+/// `brk` immediate for the check, which [`handle`] handles instead of treating it as a crash.
+///
+/// **This MUST be kept in sync with the `brk` in [`run`].**
+pub const BRK_IMMEDIATE: u16 = 0xC0DE;
+
+/// Values put in x0 through x3 before the `brk`, which the handler expects to see in the context.
+const PATTERNS: [u64; 4] = [
+    0x0123_4567_89AB_CDEF,
+    0x1111_2222_3333_4444,
+    0x5555_6666_7777_8888,
+    0x9999_AAAA_BBBB_CCCC,
+];
+
+/// Value the handler puts in x0, which we expect to see once the context is restored.
+const RESPONSE: u64 = !PATTERNS[0];
+
+/// `PSTATE.M` expected in the saved context: EL1 using SP_EL0 (EL1t).
+const PSR_M_EL1T: u64 = 0b0100;
+
+/// Stack pointer used while on SP_EL0, which is never actually used to access memory.
+static mut STACK: [u128; 16] = [0; 16];
+
+/// What the handler saw, or 0 if the exception never reached it.
+static HANDLED_SP: AtomicU64 = AtomicU64::new(0);
+
+/// Checks that a synchronous exception taken from EL1 with SP_EL0 reaches [`handle`] with the
+/// right context, and that changes to the context are restored. Panics if not.
+pub fn run() {
+    // SAFETY: we only take the address of the stack, which is 16-byte aligned as SP requires.
+    let stack = unsafe { STACK.as_ptr_range().end } as u64;
+    let x0: u64;
+    let x1: u64;
+    let sp: u64;
+
+    // SAFETY: SP_EL0 is restored before returning, and nothing accesses memory through it in
+    // between. The handler skips the `brk`, so execution continues at the next instruction.
+    unsafe {
+        asm!(
+            "mrs {saved}, SP_EL0",
+            "msr SP_EL0, {stack}",
+            "msr SPSel, #0",
+            "brk #0xC0DE",
+            "mov {sp}, sp",
+            "msr SPSel, #1",
+            "msr SP_EL0, {saved}",
+            stack = in(reg) stack,
+            saved = out(reg) _,
+            sp = out(reg) sp,
+            inout("x0") PATTERNS[0] => x0,
+            inout("x1") PATTERNS[1] => x1,
+            in("x2") PATTERNS[2],
+            in("x3") PATTERNS[3],
+        )
+    };
+
+    let handled_sp = HANDLED_SP.load(Ordering::Relaxed);
+    assert_ne!(
+        handled_sp, 0,
+        "vector check: exception didn't reach the handler"
+    );
+    assert_eq!(
+        handled_sp, stack,
+        "vector check: handler saw the wrong SP_EL0"
+    );
+    assert_eq!(
+        x0, RESPONSE,
+        "vector check: x0 wasn't restored from the context"
+    );
+    assert_eq!(x1, PATTERNS[1], "vector check: x1 wasn't preserved");
+    assert_eq!(
+        sp, stack,
+        "vector check: SP_EL0 wasn't restored from the context"
+    );
+
+    log::debug!("vector check: exception vectors and context switching ok");
+}
+
+/// Handles the `brk` executed by [`run`], given the `kind` of vector it was taken through (see
+/// `handle_synchronous` in main.rs).
+///
+/// Checks the saved context, then changes it so [`run`] can check that it was restored.
+pub fn handle(context: &mut Context, kind: u8) {
+    assert_eq!(
+        kind, b'A',
+        "vector check: exception taken through the wrong vector"
+    );
+    for (n, &pattern) in PATTERNS.iter().enumerate() {
+        assert_eq!(context.x(n), pattern, "vector check: x{n} wasn't saved");
+    }
+    assert_eq!(
+        context.psr() & 0xF,
+        PSR_M_EL1T,
+        "vector check: PSTATE wasn't saved"
+    );
+
+    HANDLED_SP.store(context.sp() as u64, Ordering::Relaxed);
+    context.set_x0(RESPONSE);
+    // skip the `brk`, which would otherwise be executed again
+    context.set_pc(context.pc() + 4);
+}
@@ -0,0 +1,356 @@
+//! The virtio-mmio transport, which is how virtio devices appear on QEMU's virt machine: as
+//! `virtio,mmio` nodes in the devicetree, 32 of them, most with no device behind them.
+//!
+//! This is the common layer for virtio drivers. At boot, it finds each transport with a device,
+//! resets and acknowledges the device, then hands it to the driver in [`DRIVERS`] for its device
+//! type, which negotiates features, sets up its queues and keeps the [`Transport`] to drive the
+//! device with. Both the legacy interface (version 1), which QEMU uses unless given
+//! `-global virtio-mmio.force-legacy=false`, and the modern one (version 2) are supported.
+//!
+//! https://docs.oasis-open.org/virtio/virtio/v1.2/virtio-v1.2.html
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use fdt::Fdt;
+
+use crate::a53::virtio_mmio::{VirtioMmioRegisterBlock, CONFIG_OFFSET};
+use crate::error::KernelError;
+use crate::gicv2::InterruptId;
+use crate::probe::Device;
+
+pub mod p9;
+pub mod queue;
+pub mod rng;
+
+use queue::{Virtqueue, LEGACY_ALIGN};
+
+/// Log target of the virtio transport.
+pub const LOG_TARGET: &str = "virtio";
+
+/// Compatible string of the transport in the devicetree.
+pub const COMPATIBLE: &str = "virtio,mmio";
+
+/// Value of MagicValue, "virt" in little-endian.
+const MAGIC: u32 = 0x7472_6976;
+
+/// The device complies with virtio 1.0 or later, which is what the modern interface requires.
+#[allow(dead_code)]
+pub const VERSION_1: u64 = 1 << 32;
+
+/// Device types, as in DeviceID.
+pub mod device_id {
+    pub const NETWORK: u32 = 1;
+    pub const BLOCK: u32 = 2;
+    pub const CONSOLE: u32 = 3;
+    pub const ENTROPY: u32 = 4;
+    pub const NINEP: u32 = 9;
+    pub const INPUT: u32 = 18;
+}
+
+/// A driver for one type of virtio device.
+pub struct Driver {
+    pub name: &'static str,
+    /// Type of device the driver is for (see [`device_id`]).
+    pub device_id: u32,
+    /// Sets up a device found at boot, which has been reset and acknowledged, by negotiating
+    /// features, setting up queues, then marking the driver as ready with
+    /// [`Transport::driver_ok`].
+    pub probe: fn(Transport) -> Result<(), KernelError>,
+}
+
+/// Drivers for each type of virtio device.
+const DRIVERS: &[Driver] = &[
+    Driver {
+        name: "virtio-rng",
+        device_id: device_id::ENTROPY,
+        probe: rng::probe,
+    },
+    Driver {
+        name: "virtio-9p",
+        device_id: device_id::NINEP,
+        probe: p9::probe,
+    },
+];
+
+/// Number of transports found at boot, with or without a device.
+static TRANSPORTS: AtomicUsize = AtomicUsize::new(0);
+
+/// Finds every transport with a device, and probes its driver, if there is one.
+fn init(fdt: &Fdt) -> Result<(), KernelError> {
+    for device in Device::find_all(fdt, COMPATIBLE) {
+        let base = device.reg(0)?;
+        // SAFETY: the devicetree says there's a transport at `base`, and each node is only found
+        // once.
+        let Some(transport) = (unsafe { Transport::new(base, device.interrupt(0)?) }) else {
+            continue;
+        };
+        TRANSPORTS.fetch_add(1, Ordering::Relaxed);
+        if transport.device_id == 0 {
+            continue;
+        }
+
+        let name = device_name(transport.device_id);
+        log::debug!(
+            target: LOG_TARGET,
+            "{name} device at {base:?} (version {})",
+            transport.version
+        );
+        let Some(driver) = DRIVERS
+            .iter()
+            .find(|driver| driver.device_id == transport.device_id)
+        else {
+            log::debug!(target: LOG_TARGET, "no driver for {name} device at {base:?}");
+            continue;
+        };
+
+        transport.reset();
+        transport.set_status(|w| {
+            w.acknowledge(true);
+            w.driver(true);
+        });
+        // the driver keeps the transport, so make another for giving up on the device
+        let interrupt = transport.interrupt;
+        if let Err(error) = (driver.probe)(transport) {
+            log::warn!(target: LOG_TARGET, "{} at {base:?}: {error}", driver.name);
+            // SAFETY: as above, and the driver has given up on the device.
+            if let Some(transport) = unsafe { Transport::new(base, interrupt) } {
+                transport.set_status(|w| w.failed(true));
+            }
+        }
+    }
+    log::debug!(
+        target: LOG_TARGET,
+        "{} transports",
+        TRANSPORTS.load(Ordering::Relaxed)
+    );
+
+    Ok(())
+}
+initcall!(driver, init);
+
+/// Returns the number of transports found at boot, with or without a device.
+pub fn transports() -> usize {
+    TRANSPORTS.load(Ordering::Relaxed)
+}
+
+/// Returns the name of a device type, for logging.
+fn device_name(device_id: u32) -> &'static str {
+    match device_id {
+        device_id::NETWORK => "network",
+        device_id::BLOCK => "block",
+        device_id::CONSOLE => "console",
+        device_id::ENTROPY => "entropy",
+        device_id::NINEP => "9p",
+        device_id::INPUT => "input",
+        _ => "unknown",
+    }
+}
+
+/// A virtio-mmio transport, through which a driver talks to its device.
+pub struct Transport {
+    regs: *mut VirtioMmioRegisterBlock,
+    version: u32,
+    device_id: u32,
+    interrupt: InterruptId,
+}
+
+#[allow(dead_code)]
+impl Transport {
+    /// Returns the transport at `base`, or None if it's not a transport we support.
+    ///
+    /// # Safety
+    /// There must be a virtio-mmio transport at `base`, which nothing else uses.
+    unsafe fn new(base: *const u8, interrupt: InterruptId) -> Option<Self> {
+        let regs = base as *mut VirtioMmioRegisterBlock;
+        // SAFETY: the caller ensures that there's a transport at `base`, and these registers are
+        // read-only, so reading them has no side effects.
+        let (magic, version, device_id) = unsafe {
+            (
+                (*regs).magic_value.read(|r| r.value()),
+                (*regs).version.read(|r| r.value()),
+                (*regs).device_id.read(|r| r.value()),
+            )
+        };
+        if magic != MAGIC || !(1..=2).contains(&version) {
+            log::warn!(
+                target: LOG_TARGET,
+                "no transport at {base:?} (magic {magic:#x}, version {version})"
+            );
+            return None;
+        }
+
+        Some(Self {
+            regs,
+            version,
+            device_id,
+            interrupt,
+        })
+    }
+
+    fn regs(&self) -> &VirtioMmioRegisterBlock {
+        // SAFETY: see new.
+        unsafe { &*self.regs }
+    }
+
+    /// Returns the type of the device (see [`device_id`]).
+    pub fn device_id(&self) -> u32 {
+        self.device_id
+    }
+
+    /// Returns whether the transport uses the legacy interface.
+    pub fn is_legacy(&self) -> bool {
+        self.version == 1
+    }
+
+    /// Returns the interrupt the device raises when it uses buffers or its configuration changes.
+    pub fn interrupt(&self) -> InterruptId {
+        self.interrupt
+    }
+
+    /// Resets the device, which forgets its features and queues.
+    fn reset(&self) {
+        self.regs().status.write_initial(|_| {});
+    }
+
+    /// Sets bits in the device status.
+    fn set_status(
+        &self,
+        writer: impl FnOnce(&mut crate::reg::RegisterWriter<crate::a53::virtio_mmio::Status>),
+    ) {
+        self.regs().status.modify(writer);
+    }
+
+    /// Negotiates features with the device, accepting those it offers of `supported`, and returns
+    /// the features accepted.
+    ///
+    /// The legacy interface only has the first 32 feature bits, and the modern one needs
+    /// [`VERSION_1`], which is accepted whether or not it's in `supported`.
+    pub fn negotiate(&mut self, supported: u64) -> Result<u64, KernelError> {
+        let regs = self.regs();
+        let mut offered = 0;
+        for select in 0..2 {
+            regs.device_features_sel.write_initial(|w| w.value(select));
+            offered |= u64::from(regs.device_features.read(|r| r.value())) << (32 * select);
+        }
+
+        let mut features = offered & supported;
+        if self.is_legacy() {
+            features &= u64::from(u32::MAX);
+        } else if offered & VERSION_1 == 0 {
+            return Err(self.failed("device doesn't offer VIRTIO_F_VERSION_1"));
+        } else {
+            features |= VERSION_1;
+        }
+        for select in 0..2 {
+            regs.driver_features_sel.write_initial(|w| w.value(select));
+            regs.driver_features
+                .write_initial(|w| w.value((features >> (32 * select)) as u32));
+        }
+
+        // the legacy interface has no way for the device to reject the features
+        if !self.is_legacy() {
+            self.set_status(|w| w.features_ok(true));
+            if !regs.status.read(|r| r.features_ok()) {
+                return Err(self.failed("device rejected the features"));
+            }
+        }
+
+        Ok(features)
+    }
+
+    /// Sets up the queue numbered `index`, with at most `max_size` descriptors, or fewer if the
+    /// device can't take that many.
+    pub fn setup_queue(&mut self, index: u16, max_size: u16) -> Result<Virtqueue, KernelError> {
+        let regs = self.regs();
+        regs.queue_sel.write_initial(|w| w.value(index.into()));
+        let in_use = if self.is_legacy() {
+            regs.queue_pfn.read(|r| r.value()) != 0
+        } else {
+            regs.queue_ready.read(|r| r.value()) != 0
+        };
+        if in_use {
+            return Err(self.failed("queue is already set up"));
+        }
+        let device_max = regs.queue_num_max.read(|r| r.value());
+        if device_max == 0 {
+            return Err(self.failed("queue doesn't exist"));
+        }
+
+        // queue sizes are powers of two, at least in the legacy interface
+        let size = device_max.min(max_size.into());
+        let size = 1 << size.ilog2();
+        let queue = Virtqueue::new(index, size as u16)?;
+        regs.queue_num.write_initial(|w| w.value(size));
+
+        if self.is_legacy() {
+            // the legacy interface finds the rings from the descriptor table's page number
+            let pfn = queue.descriptor_area().addr() / LEGACY_ALIGN;
+            let pfn = u32::try_from(pfn).map_err(|_| self.failed("queue is out of reach"))?;
+            regs.guest_page_size
+                .write_initial(|w| w.value(LEGACY_ALIGN as u32));
+            regs.queue_align
+                .write_initial(|w| w.value(LEGACY_ALIGN as u32));
+            regs.queue_pfn.write_initial(|w| w.value(pfn));
+        } else {
+            for (registers, pa) in [
+                (&regs.queue_desc, queue.descriptor_area()),
+                (&regs.queue_driver, queue.driver_area()),
+                (&regs.queue_device, queue.device_area()),
+            ] {
+                let pa = pa.addr() as u64;
+                registers[0].write_initial(|w| w.value(pa as u32));
+                registers[1].write_initial(|w| w.value((pa >> 32) as u32));
+            }
+            regs.queue_ready.write_initial(|w| w.value(1));
+        }
+
+        Ok(queue)
+    }
+
+    /// Tells the device that the driver is ready, after which it can use the queues.
+    pub fn driver_ok(&mut self) {
+        self.set_status(|w| w.driver_ok(true));
+    }
+
+    /// Tells the device that there are new buffers available in `queue`.
+    pub fn notify(&self, queue: &Virtqueue) {
+        self.regs()
+            .queue_notify
+            .write_initial(|w| w.value(queue.index().into()));
+    }
+
+    /// Acknowledges the device's interrupt, returning whether it was for used buffers, and whether
+    /// it was for a configuration change.
+    pub fn ack_interrupt(&self) -> (bool, bool) {
+        let regs = self.regs();
+        let (bits, used_buffer, config_change) = regs
+            .interrupt_status
+            .read(|r| (r.bits(), r.used_buffer(), r.config_change()));
+        regs.interrupt_ack.write_initial(|w| w.ack(bits));
+
+        (used_buffer, config_change)
+    }
+
+    /// Reads the `T` at `offset` in the device-specific configuration space.
+    ///
+    /// The modern interface can change the configuration as it's read, so reads of more than 32
+    /// bits are retried until the configuration generation is the same before and after.
+    pub fn read_config<T: Copy>(&self, offset: usize) -> T {
+        let ptr = (self.regs as usize + CONFIG_OFFSET + offset) as *const T;
+        loop {
+            let before = self.regs().config_generation.read(|r| r.value());
+            // SAFETY: the configuration space follows the registers, and drivers know its layout.
+            let value = unsafe { ptr.read_volatile() };
+            if self.is_legacy() || self.regs().config_generation.read(|r| r.value()) == before {
+                return value;
+            }
+        }
+    }
+
+    /// Returns an error for the device failing for `reason`.
+    fn failed(&self, reason: &'static str) -> KernelError {
+        KernelError::DeviceFailed {
+            compatible: COMPATIBLE,
+            reason,
+        }
+    }
+}
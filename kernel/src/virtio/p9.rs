@@ -0,0 +1,367 @@
+//! The virtio-9p driver, a 9P2000.L client (see the ninep crate) for a host directory shared with
+//! QEMU's `-virtfs`, so files can be read from the host without rebuilding anything.
+//!
+//! The share is read-only, and only one is supported. There's no VFS to mount it in yet, so for
+//! now it's reachable with the `9p` console command (see console.rs).
+//!
+//! Requests are synchronous, and made one at a time. Each is a chain of a page holding the
+//! T-message and a page for the device to write the R-message to, which the driver polls for until
+//! it's answered, or [`TIMEOUT_MS`] passes. Interrupts stay unmasked while it polls, so the rest of
+//! the system carries on in the meantime. A request that times out might
+//! still be answered later, so the share can't be used again after that.
+use core::hint::spin_loop;
+use core::slice;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use abi::Errno;
+use hal::Timer;
+use ninep::{Attr, DirEntry, Request, Response};
+
+use crate::addr::VirtAddr;
+use crate::error::KernelError;
+use crate::{board, mm, tt};
+
+use super::queue::{Buffer, Virtqueue};
+use super::{Transport, COMPATIBLE, LOG_TARGET};
+
+/// The device has a tag for the share, which is the `mount_tag` given to `-virtfs`.
+const MOUNT_TAG: u64 = 1 << 0;
+
+/// Longest tag we keep, in bytes.
+const MAX_TAG_LEN: usize = 32;
+
+/// Longest to wait for the server to answer a request, in milliseconds.
+const TIMEOUT_MS: u64 = 1000;
+
+/// Fid of the root of the share, which is attached at probe.
+const ROOT_FID: u32 = 0;
+
+/// Fid of the file being looked at, which is walked to from the root, then clunked when done.
+const FILE_FID: u32 = 1;
+
+static mut SHARE: Option<Share> = None;
+
+/// Whether [`SHARE`] is being used, so nothing else uses it until the request being made is done.
+static BUSY: AtomicBool = AtomicBool::new(false);
+
+struct Share {
+    transport: Transport,
+    queue: Virtqueue,
+    /// A page holding the T-message of the current request.
+    request: VirtAddr,
+    /// A page the device writes the R-message to.
+    response: VirtAddr,
+    /// Largest message either side will send, as negotiated with the server.
+    msize: u32,
+    tag: [u8; MAX_TAG_LEN],
+    tag_len: usize,
+    /// Tag of the next request.
+    next_tag: u16,
+    /// Whether a request went unanswered, so the queue can't be trusted.
+    broken: bool,
+}
+
+/// Sets up a virtio-9p device, then starts a session with the server and attaches to the share.
+pub fn probe(mut transport: Transport) -> Result<(), KernelError> {
+    // SAFETY: devices are probed during boot, with interrupts masked.
+    if unsafe { SHARE.is_some() } {
+        return Err(KernelError::AlreadyRegistered {
+            what: "virtio-9p device",
+        });
+    }
+
+    let features = transport.negotiate(MOUNT_TAG)?;
+    // each request is a chain of two buffers
+    let queue = transport.setup_queue(0, 2)?;
+    let request = mm::alloc_pages(tt::PAGE_SIZE)?;
+    let response = mm::alloc_pages(tt::PAGE_SIZE)?;
+    transport.driver_ok();
+
+    let mut tag = [0; MAX_TAG_LEN];
+    let mut tag_len = 0;
+    if features & MOUNT_TAG != 0 {
+        tag_len = usize::from(transport.read_config::<u16>(0)).min(MAX_TAG_LEN);
+        for (i, byte) in tag[..tag_len].iter_mut().enumerate() {
+            *byte = transport.read_config(2 + i);
+        }
+    }
+
+    let mut share = Share {
+        transport,
+        queue,
+        request,
+        response,
+        msize: tt::PAGE_SIZE as u32,
+        tag,
+        tag_len,
+        next_tag: 0,
+        broken: false,
+    };
+    let request = Request::Version {
+        msize: share.msize,
+        version: ninep::VERSION,
+    };
+    let Response::Version { msize, version } = share.transact(request)? else {
+        return Err(unexpected());
+    };
+    if version != ninep::VERSION {
+        return Err(failed("server doesn't speak 9P2000.L"));
+    }
+    share.msize = share.msize.min(msize);
+    if share.msize <= ninep::IO_HEADER_SIZE {
+        return Err(failed("server's msize is too small"));
+    }
+    let request = Request::Attach {
+        fid: ROOT_FID,
+        afid: ninep::NOFID,
+        uname: "",
+        aname: "",
+        n_uname: 0,
+    };
+    let Response::Attach { .. } = share.transact(request)? else {
+        return Err(unexpected());
+    };
+    log::info!(
+        target: LOG_TARGET,
+        "virtio-9p: attached to share {:?}, msize {}",
+        share.tag(),
+        share.msize
+    );
+
+    // SAFETY: devices are probed during boot, before anything can use the share.
+    unsafe { SHARE = Some(share) };
+
+    Ok(())
+}
+
+/// Calls `f` with the tag of the share, which is empty if the device didn't say.
+pub fn tag<T>(f: impl FnOnce(&str) -> T) -> Result<T, KernelError> {
+    with_share(|share| Ok(f(share.tag())))
+}
+
+/// Calls `f` with each entry of the directory at `path`, in the share.
+pub fn list(path: &str, mut f: impl FnMut(&DirEntry)) -> Result<(), KernelError> {
+    with_share(|share| {
+        share.with_file(path, |share| {
+            share.open()?;
+            let count = share.msize - ninep::IO_HEADER_SIZE;
+            let mut offset = 0;
+            loop {
+                let request = Request::Readdir {
+                    fid: FILE_FID,
+                    offset,
+                    count,
+                };
+                let Response::Readdir { entries } = share.transact(request)? else {
+                    return Err(unexpected());
+                };
+                let mut empty = true;
+                for entry in entries {
+                    let entry = entry.map_err(|error| failed(error.reason()))?;
+                    f(&entry);
+                    offset = entry.offset;
+                    empty = false;
+                }
+                if empty {
+                    return Ok(());
+                }
+            }
+        })
+    })
+}
+
+/// Calls `f` with each chunk of the file at `path`, in the share, in order.
+pub fn read(path: &str, mut f: impl FnMut(&[u8])) -> Result<(), KernelError> {
+    with_share(|share| {
+        share.with_file(path, |share| {
+            share.open()?;
+            let count = share.msize - ninep::IO_HEADER_SIZE;
+            let mut offset = 0;
+            loop {
+                let request = Request::Read {
+                    fid: FILE_FID,
+                    offset,
+                    count,
+                };
+                let Response::Read { data } = share.transact(request)? else {
+                    return Err(unexpected());
+                };
+                if data.is_empty() {
+                    return Ok(());
+                }
+                f(data);
+                offset += data.len() as u64;
+            }
+        })
+    })
+}
+
+/// Returns the attributes of the file at `path`, in the share.
+pub fn stat(path: &str) -> Result<Attr, KernelError> {
+    with_share(|share| {
+        share.with_file(path, |share| {
+            let request = Request::Getattr {
+                fid: FILE_FID,
+                mask: ninep::GETATTR_BASIC,
+            };
+            let Response::Getattr { attr } = share.transact(request)? else {
+                return Err(unexpected());
+            };
+
+            Ok(attr)
+        })
+    })
+}
+
+/// Calls `f` with the share, unless it's already being used.
+fn with_share<T>(f: impl FnOnce(&mut Share) -> Result<T, KernelError>) -> Result<T, KernelError> {
+    if BUSY.swap(true, Ordering::Acquire) {
+        return Err(KernelError::DeviceClaimed {
+            compatible: COMPATIBLE,
+            owner: "another 9p request",
+        });
+    }
+    // SAFETY: the share is only written by probe, during boot, and otherwise only used while BUSY
+    // is set, which only one caller can do at a time.
+    let result = unsafe { SHARE.as_mut() }
+        .ok_or(KernelError::NoShare)
+        .and_then(f);
+    BUSY.store(false, Ordering::Release);
+
+    result
+}
+
+impl Share {
+    fn tag(&self) -> &str {
+        core::str::from_utf8(&self.tag[..self.tag_len]).unwrap_or("?")
+    }
+
+    /// Walks [`FILE_FID`] to `path`, calls `f`, then clunks it, even if `f` failed.
+    fn with_file<T>(
+        &mut self,
+        path: &str,
+        f: impl FnOnce(&mut Self) -> Result<T, KernelError>,
+    ) -> Result<T, KernelError> {
+        let request = Request::Walk {
+            fid: ROOT_FID,
+            newfid: FILE_FID,
+            path,
+        };
+        let Response::Walk { walked, .. } = self.transact(request)? else {
+            return Err(unexpected());
+        };
+        // a walk that fails partway doesn't make the fid, so there's nothing to clunk
+        if usize::from(walked) != ninep::components(path).count() {
+            return Err(KernelError::HostError {
+                errno: Errno::NotFound.code().into(),
+            });
+        }
+
+        let result = f(self);
+        let clunked = self.transact(Request::Clunk { fid: FILE_FID }).map(|_| ());
+
+        result.and_then(|result| clunked.map(|()| result))
+    }
+
+    /// Opens [`FILE_FID`] for reading.
+    fn open(&mut self) -> Result<(), KernelError> {
+        let request = Request::Lopen {
+            fid: FILE_FID,
+            flags: ninep::O_RDONLY,
+        };
+        let Response::Lopen { .. } = self.transact(request)? else {
+            return Err(unexpected());
+        };
+
+        Ok(())
+    }
+
+    /// Sends a request to the server and waits for the response, which lasts until the next
+    /// request.
+    fn transact(&mut self, request: Request) -> Result<Response<'_>, KernelError> {
+        if self.broken {
+            return Err(failed("an earlier request went unanswered"));
+        }
+        let tag = match request {
+            Request::Version { .. } => ninep::NOTAG,
+            _ => {
+                let tag = self.next_tag;
+                self.next_tag = (tag + 1) % ninep::NOTAG;
+                tag
+            }
+        };
+
+        // SAFETY: the page is ours, and the device only reads it while a request is outstanding,
+        // which none is.
+        let buffer = unsafe {
+            slice::from_raw_parts_mut(self.request.addr() as *mut u8, self.msize as usize)
+        };
+        let len = request
+            .encode(tag, buffer)
+            .map_err(|error| failed(error.reason()))?;
+        self.queue.add(&[
+            Buffer {
+                pa: mm::ram_pa(self.request),
+                len: len as u32,
+                writable: false,
+            },
+            Buffer {
+                pa: mm::ram_pa(self.response),
+                len: self.msize,
+                writable: true,
+            },
+        ])?;
+        self.transport.notify(&self.queue);
+
+        let start = board::timer().now();
+        let len = loop {
+            match self.queue.pop_used() {
+                Ok(Some((_, len))) => break len,
+                Ok(None) => {}
+                Err(error) => {
+                    self.broken = true;
+                    return Err(error);
+                }
+            }
+            if board::timer().now() - start > board::timer().ticks_from_ms(TIMEOUT_MS) {
+                self.broken = true;
+                return Err(failed("server didn't answer"));
+            }
+            spin_loop();
+        };
+        // there's no handler for the interrupt, but the device still expects it acknowledged
+        self.transport.ack_interrupt();
+
+        // SAFETY: the device has finished writing the response, and won't again until the next
+        // request, which needs another borrow of self.
+        let message = unsafe {
+            slice::from_raw_parts(
+                self.response.addr() as *const u8,
+                (len as usize).min(self.msize as usize),
+            )
+        };
+        let (response_tag, response) =
+            ninep::decode(message).map_err(|error| failed(error.reason()))?;
+        if response_tag != tag {
+            return Err(failed("response has the wrong tag"));
+        }
+
+        match response {
+            Response::Error { errno } => Err(KernelError::HostError { errno }),
+            response => Ok(response),
+        }
+    }
+}
+
+/// Returns an error for the device failing for `reason`.
+fn failed(reason: &'static str) -> KernelError {
+    KernelError::DeviceFailed {
+        compatible: COMPATIBLE,
+        reason,
+    }
+}
+
+/// Returns an error for the server answering with the wrong type of response.
+fn unexpected() -> KernelError {
+    failed("response of the wrong type")
+}
@@ -0,0 +1,257 @@
+//! Split virtqueues, through which a driver passes buffers to a virtio device and gets them back.
+//!
+//! A queue is a table of descriptors, each pointing to a buffer and maybe to the next descriptor
+//! in a chain, then the available ring, where the driver puts the head of each chain it passes to
+//! the device, and the used ring, where the device puts each chain it's done with.
+//!
+//! The queue's memory is shared with the device, so writes to it are ordered with barriers, but
+//! it needs no cache maintenance, since the kernel runs with its data cache off (SCTLR_EL1.C).
+use core::arch::asm;
+use core::mem;
+use core::ptr::addr_of;
+
+use super::COMPATIBLE;
+use crate::addr::PhysAddr;
+use crate::error::KernelError;
+use crate::{mm, tt};
+
+/// Alignment of the used ring, as the legacy interface requires, which is also the page size we
+/// tell it we use (see [`super::Transport::setup_queue`]).
+pub const LEGACY_ALIGN: usize = 4096;
+
+/// The descriptor continues in the one at `next`.
+const DESCRIPTOR_NEXT: u16 = 1 << 0;
+/// The buffer is for the device to write, rather than read.
+const DESCRIPTOR_WRITE: u16 = 1 << 1;
+
+#[repr(C)]
+struct Descriptor {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+/// An entry in the used ring: the head of a chain, and how many bytes the device wrote to it.
+#[repr(C)]
+struct UsedElement {
+    id: u32,
+    len: u32,
+}
+
+/// A buffer in physical memory, for the device to read, or write if `writable`.
+#[derive(Clone, Copy, Debug)]
+pub struct Buffer {
+    pub pa: PhysAddr,
+    pub len: u32,
+    pub writable: bool,
+}
+
+/// A split virtqueue, whose memory is freed when it's dropped, so the device must be reset (or
+/// never have been told about the queue) before then.
+pub struct Virtqueue {
+    index: u16,
+    size: u16,
+    /// The queue's memory, starting with the descriptor table.
+    pages: mm::Pages,
+    /// Offsets of the available and used rings from `base`.
+    avail_offset: usize,
+    used_offset: usize,
+    /// First descriptor of the chain of free descriptors, linked by `next`.
+    free_head: u16,
+    free: u16,
+    /// Number of chains ever made available, which wraps like the available ring's index.
+    avail_idx: u16,
+    /// Number of chains ever taken back from the used ring.
+    last_used_idx: u16,
+}
+
+#[allow(dead_code)]
+impl Virtqueue {
+    /// Allocates a queue with `size` descriptors, which must be a power of two, for the queue of
+    /// the device numbered `index`.
+    pub fn new(index: u16, size: u16) -> Result<Self, KernelError> {
+        if !size.is_power_of_two() {
+            return Err(KernelError::InvalidArgument {
+                reason: "queue size must be a power of two",
+            });
+        }
+        let n = usize::from(size);
+
+        // the used ring is aligned for the legacy interface, but it's fine for the modern one too
+        let avail_offset = n * mem::size_of::<Descriptor>();
+        let used_offset = (avail_offset + 6 + 2 * n).next_multiple_of(LEGACY_ALIGN);
+        let len = used_offset + 6 + n * mem::size_of::<UsedElement>();
+        let len = len.next_multiple_of(tt::PAGE_SIZE);
+
+        let pages = mm::Pages::alloc(len)?;
+        // SAFETY: the pages were just allocated for us, and are mapped writable.
+        unsafe { (pages.addr().addr() as *mut u8).write_bytes(0, len) };
+
+        let mut queue = Self {
+            index,
+            size,
+            pages,
+            avail_offset,
+            used_offset,
+            free_head: 0,
+            free: size,
+            avail_idx: 0,
+            last_used_idx: 0,
+        };
+        for i in 0..size {
+            // the last descriptor's next is out of range, but it's never followed
+            queue.descriptor(i).next = i.wrapping_add(1);
+        }
+
+        Ok(queue)
+    }
+
+    /// Returns the number of the queue in its device.
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+
+    /// Returns the number of descriptors in the queue.
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    /// Returns the number of descriptors not in any chain passed to the device.
+    pub fn free(&self) -> u16 {
+        self.free
+    }
+
+    /// Returns the physical address of the descriptor table.
+    pub fn descriptor_area(&self) -> PhysAddr {
+        mm::ram_pa(self.pages.addr())
+    }
+
+    /// Returns the physical address of the available ring.
+    pub fn driver_area(&self) -> PhysAddr {
+        PhysAddr::new(self.descriptor_area().addr() + self.avail_offset)
+    }
+
+    /// Returns the physical address of the used ring.
+    pub fn device_area(&self) -> PhysAddr {
+        PhysAddr::new(self.descriptor_area().addr() + self.used_offset)
+    }
+
+    /// Makes a chain of `buffers` available to the device, returning the descriptor at the head of
+    /// the chain, which [`Self::pop_used`] returns when the device is done with it.
+    ///
+    /// The device won't look at the chain until it's notified (see [`super::Transport::notify`]).
+    pub fn add(&mut self, buffers: &[Buffer]) -> Result<u16, KernelError> {
+        if buffers.is_empty() {
+            return Err(KernelError::InvalidArgument {
+                reason: "chain must have at least one buffer",
+            });
+        }
+        if buffers.len() > usize::from(self.free) {
+            return Err(KernelError::WouldBlock);
+        }
+
+        let head = self.free_head;
+        for (i, buffer) in buffers.iter().enumerate() {
+            let index = self.free_head;
+            let descriptor = self.descriptor(index);
+            descriptor.addr = buffer.pa.addr() as u64;
+            descriptor.len = buffer.len;
+            descriptor.flags = if buffer.writable { DESCRIPTOR_WRITE } else { 0 };
+            if i + 1 < buffers.len() {
+                descriptor.flags |= DESCRIPTOR_NEXT;
+            }
+            // the last descriptor's next is left pointing into the free list, but without
+            // DESCRIPTOR_NEXT, the device won't follow it
+            self.free_head = descriptor.next;
+        }
+        self.free -= buffers.len() as u16;
+
+        let slot = usize::from(self.avail_idx % self.size);
+        self.avail_idx = self.avail_idx.wrapping_add(1);
+        // SAFETY: the ring is in the queue's memory, which only we and the device use, and the
+        // device only reads the entries before the index.
+        unsafe {
+            self.avail_ring().add(2 + slot).write_volatile(head);
+            // the device must see the entry before the index that covers it
+            barrier();
+            self.avail_ring().add(1).write_volatile(self.avail_idx);
+            // and the index before it's notified
+            barrier();
+        }
+
+        Ok(head)
+    }
+
+    /// Takes back the next chain the device is done with, if any, returning the descriptor at its
+    /// head (as returned by [`Self::add`]) and how many bytes the device wrote to it.
+    ///
+    /// Fails if the device returned a descriptor that isn't in the queue, which leaves the entry in
+    /// the used ring, so the queue can't be used again.
+    pub fn pop_used(&mut self) -> Result<Option<(u16, u32)>, KernelError> {
+        // SAFETY: see add, and the device only writes entries before it updates the index.
+        let (id, len) = unsafe {
+            let used_idx = self.used_ring().add(1).read_volatile();
+            if used_idx == self.last_used_idx {
+                return Ok(None);
+            }
+            // the entry must be read after the index that covers it
+            barrier();
+
+            let slot = usize::from(self.last_used_idx % self.size);
+            let element = self.used_ring().add(2).cast::<UsedElement>().add(slot);
+            (
+                addr_of!((*element).id).read_volatile(),
+                addr_of!((*element).len).read_volatile(),
+            )
+        };
+        // the device is trusted with our buffers, but not to keep us from panicking
+        let id = match u16::try_from(id) {
+            Ok(id) if id < self.size => id,
+            _ => {
+                return Err(KernelError::DeviceFailed {
+                    compatible: COMPATIBLE,
+                    reason: "used descriptor out of range",
+                })
+            }
+        };
+        self.last_used_idx = self.last_used_idx.wrapping_add(1);
+
+        // put the chain back on the free list
+        let mut last = id;
+        self.free += 1;
+        while self.descriptor(last).flags & DESCRIPTOR_NEXT != 0 {
+            last = self.descriptor(last).next;
+            self.free += 1;
+        }
+        self.descriptor(last).next = self.free_head;
+        self.free_head = id;
+
+        Ok(Some((id, len)))
+    }
+
+    fn descriptor(&mut self, index: u16) -> &mut Descriptor {
+        assert!(index < self.size, "descriptor {index} is out of range");
+
+        // SAFETY: the descriptor table is at the start of the queue's memory, and the device only
+        // reads descriptors in chains it's been given, which we don't change until it's done.
+        unsafe { &mut *(self.pages.addr().addr() as *mut Descriptor).add(usize::from(index)) }
+    }
+
+    /// Returns the available ring: its flags, its index, then an entry for each descriptor.
+    fn avail_ring(&self) -> *mut u16 {
+        (self.pages.addr().addr() + self.avail_offset) as *mut u16
+    }
+
+    /// Returns the used ring: its flags, its index, then an entry for each descriptor.
+    fn used_ring(&self) -> *const u16 {
+        (self.pages.addr().addr() + self.used_offset) as *const u16
+    }
+}
+
+/// Orders accesses to the queue's memory with each other and with accesses to the transport's
+/// registers, as seen by the device.
+fn barrier() {
+    // SAFETY: a barrier has no effect other than ordering memory accesses.
+    unsafe { asm!("dmb sy", options(nostack, preserves_flags)) };
+}
@@ -0,0 +1,174 @@
+//! The virtio-rng driver, which keeps the entropy pool (see entropy.rs) topped up with random bytes
+//! from the host.
+//!
+//! Every [`REQUEST_INTERVAL`] timer ticks, if the pool has room, the driver asks the device for
+//! enough bytes to fill it. Only one request is ever outstanding, so a host that's slow to answer
+//! (because its own entropy is starved, or it's rate-limited with `max-bytes`) leaves the pool
+//! starved too. When a request goes unanswered for [`STARVATION_TICKS`], the driver warns, and
+//! doubles the interval between requests, up to [`MAX_INTERVAL`], so a host that can't keep up is
+//! asked less often. The interval goes back to normal once the host answers in time again.
+use crate::addr::VirtAddr;
+use crate::error::KernelError;
+use crate::gicv2::InterruptId;
+use crate::sync::without_interrupts;
+use crate::{entropy, irq, mm, stats, tt};
+
+use super::queue::{Buffer, Virtqueue};
+use super::{Transport, LOG_TARGET};
+
+/// Number of timer ticks between requests, while the host keeps up.
+const REQUEST_INTERVAL: u64 = 10;
+
+/// Longest the interval between requests can get, in timer ticks.
+const MAX_INTERVAL: u64 = 640;
+
+/// Number of timer ticks a request can go unanswered before the pool is considered starved.
+const STARVATION_TICKS: u64 = 50;
+
+static mut RNG: Option<Rng> = None;
+
+struct Rng {
+    transport: Transport,
+    queue: Virtqueue,
+    /// A page the device writes random bytes to.
+    buffer: VirtAddr,
+    /// Timer tick when the outstanding request was made, if there is one.
+    requested_at: Option<u64>,
+    /// Timer tick after which the next request can be made.
+    next_request: u64,
+    /// Number of timer ticks between requests.
+    interval: u64,
+    /// Whether the outstanding request has gone unanswered for too long.
+    starved: bool,
+}
+
+/// Sets up a virtio-rng device, then asks it for enough bytes to fill the entropy pool.
+pub fn probe(mut transport: Transport) -> Result<(), KernelError> {
+    // SAFETY: devices are probed during boot, with interrupts masked.
+    if unsafe { RNG.is_some() } {
+        return Err(KernelError::AlreadyRegistered {
+            what: "virtio-rng device",
+        });
+    }
+
+    // the device has no features we need
+    transport.negotiate(0)?;
+    let queue = transport.setup_queue(0, 1)?;
+    let buffer = mm::alloc_pages(tt::PAGE_SIZE)?;
+    irq::register(transport.interrupt(), handle, irq::Mode::Threaded)?;
+    transport.driver_ok();
+
+    without_interrupts(|| {
+        // SAFETY: the state is only used with interrupts masked.
+        unsafe {
+            RNG = Some(Rng {
+                transport,
+                queue,
+                buffer,
+                requested_at: None,
+                next_request: 0,
+                interval: REQUEST_INTERVAL,
+                starved: false,
+            })
+        };
+    });
+    tick();
+
+    Ok(())
+}
+
+/// Asks the device for more bytes if it's time to, and checks for starvation. Called on every
+/// timer tick.
+pub fn tick() {
+    without_interrupts(|| {
+        // SAFETY: see probe.
+        let Some(rng) = (unsafe { RNG.as_mut() }) else {
+            return;
+        };
+        let now = stats::TIMER_TICKS.get();
+
+        match rng.requested_at {
+            Some(requested_at) if !rng.starved && now - requested_at >= STARVATION_TICKS => {
+                rng.starved = true;
+                rng.interval = (rng.interval * 2).min(MAX_INTERVAL);
+                log::warn!(
+                    target: LOG_TARGET,
+                    "virtio-rng: no answer in {STARVATION_TICKS} ticks, so asking every {} ticks",
+                    rng.interval
+                );
+            }
+            Some(_) => {}
+            None if now >= rng.next_request => {
+                let wanted = entropy::POOL_SIZE - entropy::available();
+                if wanted > 0 {
+                    if let Err(error) = rng.request(wanted, now) {
+                        log::warn!(target: LOG_TARGET, "virtio-rng: {error}");
+                    }
+                }
+            }
+            None => {}
+        }
+    });
+}
+
+/// Handles the device's interrupt, crediting the bytes it wrote to the entropy pool.
+fn handle(_interrupt_id: InterruptId) {
+    without_interrupts(|| {
+        // SAFETY: see probe.
+        let Some(rng) = (unsafe { RNG.as_mut() }) else {
+            return;
+        };
+        rng.transport.ack_interrupt();
+
+        loop {
+            // a request left outstanding is never retried, so a broken device is given up on
+            let (_, len) = match rng.queue.pop_used() {
+                Ok(Some(used)) => used,
+                Ok(None) => break,
+                Err(error) => {
+                    log::warn!(target: LOG_TARGET, "virtio-rng: {error}");
+                    break;
+                }
+            };
+            let now = stats::TIMER_TICKS.get();
+            let len = (len as usize).min(tt::PAGE_SIZE);
+            // SAFETY: the device has finished writing to the buffer, and only we use it now.
+            let bytes =
+                unsafe { core::slice::from_raw_parts_mut(rng.buffer.addr() as *mut u8, len) };
+            entropy::add(bytes);
+            // the bytes are in the pool now, so don't leave a copy lying around
+            bytes.fill(0);
+
+            let took = rng
+                .requested_at
+                .take()
+                .map_or(0, |requested_at| now - requested_at);
+            if rng.starved {
+                log::info!(
+                    target: LOG_TARGET,
+                    "virtio-rng: answered after {took} ticks with {len} bytes"
+                );
+                rng.starved = false;
+            } else {
+                rng.interval = REQUEST_INTERVAL;
+            }
+            rng.next_request = now + rng.interval;
+        }
+    });
+}
+
+impl Rng {
+    /// Asks the device for `len` bytes, up to a page.
+    fn request(&mut self, len: usize, now: u64) -> Result<(), KernelError> {
+        let len = len.min(tt::PAGE_SIZE);
+        self.queue.add(&[Buffer {
+            pa: mm::ram_pa(self.buffer),
+            len: len as u32,
+            writable: true,
+        }])?;
+        self.transport.notify(&self.queue);
+        self.requested_at = Some(now);
+
+        Ok(())
+    }
+}
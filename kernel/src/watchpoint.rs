@@ -0,0 +1,231 @@
+//! Hardware watchpoints, for finding the code that writes to some memory.
+//!
+//! A watchpoint fires before the write happens, with a synchronous exception that lands in
+//! [`handle`]. The handler dumps the context of the writer, then either halts the kernel or clears
+//! the watchpoint and lets the write go ahead. Writes made with debug exceptions masked (that is,
+//! by exception handlers) can't be caught, but writes by tasks and kernel threads can.
+use core::arch::asm;
+
+use fdt::Fdt;
+
+use crate::a53::debug::{LoadStore, DBGWCR, DBGWVR, ID_AA64DFR0_EL1, MDSCR_EL1, OSLAR_EL1};
+use crate::error::KernelError;
+use crate::reg::system::Register;
+use crate::task::Context;
+
+/// Number of watchpoints the architecture allows for, though fewer may be implemented.
+const MAX_WATCHPOINTS: usize = 16;
+
+static mut WATCHPOINTS: [Option<Watchpoint>; MAX_WATCHPOINTS] = [None; MAX_WATCHPOINTS];
+
+/// Number of watchpoints implemented, set by [`init`].
+static mut COUNT: usize = 0;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Watchpoint {
+    pub address: usize,
+    pub len: usize,
+    pub action: Action,
+}
+
+/// What to do when a watchpoint fires, after dumping the context of the writer.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    /// Clear the watchpoint and let the write go ahead.
+    Continue,
+    /// Panic, leaving the memory as it was before the write.
+    Halt,
+}
+
+impl Watchpoint {
+    /// Returns the values of DBGWVR and the address-related fields of DBGWCR (BAS and MASK) that
+    /// watch the memory.
+    fn registers(&self) -> Result<(usize, u8, u64), KernelError> {
+        let offset = self.address & 0b111;
+
+        if self.len == 0 {
+            Err(KernelError::InvalidArgument {
+                reason: "length must not be zero",
+            })
+        } else if offset + self.len <= 8 {
+            // any bytes within a doubleword
+            let bas = ((1u16 << self.len) - 1) << offset;
+            Ok((self.address - offset, bas as u8, 0))
+        } else if self.len.is_power_of_two() && self.address % self.len == 0 && self.len <= 1 << 31
+        {
+            // a naturally aligned block of eight or more bytes
+            Ok((self.address, 0xFF, self.len.trailing_zeros().into()))
+        } else {
+            Err(KernelError::InvalidArgument {
+                reason: "watched memory must be within a doubleword, or an aligned power of two",
+            })
+        }
+    }
+
+    /// Returns true if a watchpoint exception for `address` could have come from this watchpoint.
+    ///
+    /// The address of the access reported in FAR_EL1 may be anywhere in the doubleword containing
+    /// the watched memory, and may even be before it for accesses that span doublewords.
+    fn contains(&self, address: usize) -> bool {
+        let start = self.address & !0b111;
+        let end = self.address.saturating_add(self.len + 0b111) & !0b111;
+
+        (start..end).contains(&address)
+    }
+}
+
+/// Watches `len` bytes at `address` for writes from EL0 or EL1, returning the watchpoint number.
+pub fn set(address: usize, len: usize, action: Action) -> Result<usize, KernelError> {
+    let watchpoint = Watchpoint {
+        address,
+        len,
+        action,
+    };
+    let (value, bas, mask) = watchpoint.registers()?;
+
+    // SAFETY: watchpoints are only changed by the console, which runs on the IRQ thread, and
+    // read by the watchpoint exception handler, which can't interrupt them being changed.
+    let (watchpoints, count) = unsafe { (&mut WATCHPOINTS, COUNT) };
+    let Some(number) = watchpoints[..count].iter().position(Option::is_none) else {
+        return Err(KernelError::NoFreeWatchpoint { count });
+    };
+
+    watchpoints[number] = Some(watchpoint);
+    program(number, Some((value, bas, mask)));
+
+    Ok(number)
+}
+
+/// Clears the given watchpoint.
+pub fn clear(number: usize) -> Result<(), KernelError> {
+    // SAFETY: see set.
+    let (watchpoints, count) = unsafe { (&mut WATCHPOINTS, COUNT) };
+    match watchpoints[..count].get_mut(number) {
+        Some(watchpoint @ Some(_)) => {
+            *watchpoint = None;
+            program(number, None);
+
+            Ok(())
+        }
+        _ => Err(KernelError::InvalidArgument {
+            reason: "no such watchpoint",
+        }),
+    }
+}
+
+/// Returns the watchpoints that are set, and their numbers.
+pub fn list() -> impl Iterator<Item = (usize, Watchpoint)> {
+    // SAFETY: see set.
+    let (watchpoints, count) = unsafe { (&WATCHPOINTS, COUNT) };
+
+    watchpoints[..count]
+        .iter()
+        .enumerate()
+        .filter_map(|(number, watchpoint)| Some((number, (*watchpoint)?)))
+}
+
+/// Handles a watchpoint exception, given the syndrome from ESR_EL1.
+///
+/// If the task is allowed to continue, the watchpoint has been cleared, so the task can simply
+/// return to the faulting instruction and retry it.
+pub fn handle(context: &Context, syndrome: u64) {
+    // SAFETY: reading FAR_EL1 has no side effects.
+    let far = unsafe { read_special_reg!("FAR_EL1") } as usize;
+    // ISS.WnR, which should always be set since we only watch stores
+    let access = if syndrome >> 6 & 1 == 1 {
+        "write"
+    } else {
+        "read"
+    };
+
+    let Some((number, watchpoint)) = list().find(|(_, watchpoint)| watchpoint.contains(far)) else {
+        crate::crash_dump(context);
+        panic!("{access} of {far:#x} hit an unknown watchpoint");
+    };
+
+    log::warn!(
+        "watchpoint {number} hit: {access} of {far:#x} by pc {:#x}",
+        context.pc()
+    );
+    crate::crash_dump(context);
+
+    match watchpoint.action {
+        Action::Continue => {
+            // the watchpoint is known to be set, so this can't fail
+            let _ = clear(number);
+            log::warn!("watchpoint {number} cleared, continuing");
+        }
+        Action::Halt => panic!("halted by watchpoint {number}"),
+    }
+}
+
+/// Programs the registers for a watchpoint, disabling it if `registers` is None.
+fn program(number: usize, registers: Option<(usize, u8, u64)>) {
+    fn program<const N: usize>(registers: Option<(usize, u8, u64)>) {
+        // disable the watchpoint before changing its address, so it can't fire in between
+        Register::<DBGWCR<N>>::new().write_initial(|w| w.e(false));
+
+        let Some((value, bas, mask)) = registers else {
+            return;
+        };
+        Register::<DBGWVR<N>>::new().write_initial(|w| w.address(value as u64));
+        Register::<DBGWCR<N>>::new().write_initial(|w| {
+            w.mask(mask);
+            w.bas(bas);
+            w.lsc(LoadStore::Store);
+            // EL0 and EL1
+            w.pac(0b11);
+            w.e(true);
+        });
+    }
+
+    match number {
+        0 => program::<0>(registers),
+        1 => program::<1>(registers),
+        2 => program::<2>(registers),
+        3 => program::<3>(registers),
+        4 => program::<4>(registers),
+        5 => program::<5>(registers),
+        6 => program::<6>(registers),
+        7 => program::<7>(registers),
+        8 => program::<8>(registers),
+        9 => program::<9>(registers),
+        10 => program::<10>(registers),
+        11 => program::<11>(registers),
+        12 => program::<12>(registers),
+        13 => program::<13>(registers),
+        14 => program::<14>(registers),
+        15 => program::<15>(registers),
+        _ => unreachable!(),
+    }
+
+    // SAFETY: isb has no side effects beyond synchronising the context.
+    unsafe { asm!("isb") };
+}
+
+/// Disables every watchpoint, then enables debug exceptions so they can be set by the console.
+fn init(_fdt: &Fdt) -> Result<(), KernelError> {
+    let count = Register::<ID_AA64DFR0_EL1>::new().read(|r| r.wrps()) as usize + 1;
+    let count = count.min(MAX_WATCHPOINTS);
+
+    // SAFETY: this runs before anything else can use watchpoints.
+    unsafe { COUNT = count };
+    // the watchpoint registers reset to unknown values
+    for number in 0..count {
+        program(number, None);
+    }
+
+    // the OS lock may be set on reset, which would mask watchpoints
+    Register::<OSLAR_EL1>::new().write_initial(|w| w.oslk(false));
+    Register::<MDSCR_EL1>::new().write_initial(|w| {
+        w.mde(true);
+        w.kde(true);
+    });
+    // SAFETY: see program.
+    unsafe { asm!("isb") };
+
+    log::debug!("{count} hardware watchpoints");
+
+    Ok(())
+}
+initcall!(arch, init);
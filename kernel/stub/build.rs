@@ -0,0 +1,33 @@
+use std::path::PathBuf;
+use std::{env, fs};
+
+fn main() {
+    // See kernel/build.rs for why the linker script path must be fully qualified.
+    let linker_script = env::current_dir()
+        .expect("build script to have a valid current working directory")
+        .join("src/linker.ld");
+    let linker_script = linker_script
+        .to_str()
+        .expect("linker script path to be valid");
+
+    println!("cargo:rerun-if-changed={linker_script}");
+    println!("cargo:rustc-link-arg=-T{linker_script}");
+
+    // The compressed kernel is made by `cargo xtask build --compressed`, which passes its path in
+    // $KERNEL_PAYLOAD. When building the workspace without it, embed an empty payload instead, so
+    // the stub still builds (but refuses to boot).
+    println!("cargo:rerun-if-env-changed=KERNEL_PAYLOAD");
+    let payload = match env::var("KERNEL_PAYLOAD") {
+        Ok(path) if !path.is_empty() => PathBuf::from(path),
+        _ => {
+            let path = PathBuf::from(env::var("OUT_DIR").expect("cargo to set $OUT_DIR"))
+                .join("empty-payload");
+            fs::write(&path, []).expect("empty payload to be writable");
+            path
+        }
+    };
+    let payload = payload.to_str().expect("payload path to be valid");
+
+    println!("cargo:rerun-if-changed={payload}");
+    println!("cargo:rustc-env=KERNEL_PAYLOAD={payload}");
+}
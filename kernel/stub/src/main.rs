@@ -0,0 +1,145 @@
+//! Decompression stub for compressed kernel images, made by `cargo xtask build --compressed`.
+//!
+//! The stub is loaded in place of the kernel, with the kernel's loadable segments embedded as an
+//! LZ4-compressed payload. It decompresses the kernel to its physical load address, zeroes the
+//! kernel's uninitialised memory, then jumps to the kernel's `_start` as if the kernel had been
+//! loaded directly. This all happens with the MMU off, and leaves the FDT at the base of RAM
+//! untouched.
+#![no_std]
+#![no_main]
+
+use core::arch::{asm, global_asm};
+use core::panic::PanicInfo;
+use core::slice;
+
+global_asm!(
+    r#"
+.section ".start", "ax"
+
+.globl _start
+_start:
+    ldr x30, =_estack
+    mov sp, x30
+    bl stub_main
+"#
+);
+
+/// The compressed kernel, prefixed with a [`Header`].
+static PAYLOAD: &[u8] = include_bytes!(env!("KERNEL_PAYLOAD"));
+
+/// Base address of the PL011 UART in QEMU's virt machine, as also used by `_start` in the kernel.
+const UART0: *mut u8 = 0x900_0000 as *mut u8;
+
+/// Header of the payload, all fields being little-endian `u64`.
+///
+/// **This struct MUST be kept in sync with `xtask/src/compress.rs`.**
+struct Header {
+    /// Physical address to decompress the kernel to.
+    load_address: usize,
+    /// Physical address of the kernel's entry point.
+    entry: usize,
+    /// Size of the decompressed kernel.
+    image_size: usize,
+    /// Size of the kernel in memory, including zero-initialised memory after the image.
+    memory_size: usize,
+}
+
+impl Header {
+    const MAGIC: &'static [u8; 8] = b"MPUPLZ4\0";
+    const LEN: usize = 40;
+
+    /// Parses the header, returning it and the compressed kernel that follows it.
+    fn parse(payload: &[u8]) -> Option<(Self, &[u8])> {
+        if payload.len() < Self::LEN || &payload[..8] != Self::MAGIC {
+            return None;
+        }
+
+        let field = |i: usize| {
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(&payload[8 * i..][..8]);
+            u64::from_le_bytes(bytes) as usize
+        };
+        let header = Self {
+            load_address: field(1),
+            entry: field(2),
+            image_size: field(3),
+            memory_size: field(4),
+        };
+
+        if header.image_size > header.memory_size {
+            return None;
+        }
+
+        Some((header, &payload[Self::LEN..]))
+    }
+}
+
+#[no_mangle]
+extern "C" fn stub_main() -> ! {
+    extern "C" {
+        static _stub_start: u8;
+        static _stub_end: u8;
+    }
+
+    let Some((header, compressed)) = Header::parse(PAYLOAD) else {
+        fail("no kernel payload (build with `cargo xtask build --compressed`)");
+    };
+
+    // don't overwrite ourselves (including the payload) while decompressing
+    let stub = unsafe { &_stub_start as *const u8 as usize..&_stub_end as *const u8 as usize };
+    let kernel = header.load_address..header.load_address + header.memory_size;
+    if kernel.start < stub.end && stub.start < kernel.end {
+        fail("kernel overlaps decompression stub");
+    }
+
+    // SAFETY: the MMU is off, so this is physical memory, and we've checked that it doesn't
+    // overlap the stub. The FDT and any other memory below the kernel's load address are left
+    // untouched.
+    let memory =
+        unsafe { slice::from_raw_parts_mut(header.load_address as *mut u8, header.memory_size) };
+    let (image, uninit) = memory.split_at_mut(header.image_size);
+
+    print("decompressing kernel\n");
+    match lz4::decompress(compressed, image) {
+        Ok(len) if len == header.image_size => {}
+        _ => fail("kernel payload is corrupt"),
+    }
+    uninit.fill(0);
+
+    // SAFETY: we've written the kernel's code with data accesses, so make sure instruction
+    // fetches see it, then enter the kernel in the same state as if it had been loaded directly.
+    unsafe {
+        asm!(
+            "dsb sy",
+            "ic iallu",
+            "dsb sy",
+            "isb",
+            "br {entry}",
+            entry = in(reg) header.entry,
+            options(noreturn),
+        )
+    }
+}
+
+fn print(message: &str) {
+    for byte in message.bytes() {
+        // SAFETY: QEMU's PL011 accepts writes to UARTDR without any setup.
+        unsafe { UART0.write_volatile(byte) };
+    }
+}
+
+fn fail(message: &str) -> ! {
+    print("stub: ");
+    print(message);
+    print("\n");
+
+    loop {
+        // SAFETY: wfe has no side effects beyond waiting.
+        unsafe { asm!("wfe") };
+    }
+}
+
+#[panic_handler]
+fn on_panic(_info: &PanicInfo) -> ! {
+    fail("panicked");
+}
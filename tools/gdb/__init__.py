@@ -7,6 +7,7 @@ file = Path(os.path.realpath(__file__))
 sys.path.append(str(file.parent))
 
 # === all real imports should be below this line ===
+import dump_trace
 import info_tt
 import qemu
 
@@ -0,0 +1,37 @@
+# pyright: reportMissingModuleSource=false
+import gdb
+
+
+class DumpTraceCommand(gdb.Command):
+    """Dump the kernel's trace buffer to a file.
+    dump-trace [--pages] [file]
+
+    Writes the trace buffer (see kernel/src/trace.rs) to trace.bin by default. Convert the dump to
+    Chrome trace JSON with “cargo xtask trace convert <file>”, then open it in Perfetto.
+
+    With --pages, writes the page allocator's trace buffer to pages.bin by default instead, which
+    can be replayed with “buddy-alloc replay <file>”.
+    """
+
+    def __init__(self):
+        super().__init__("dump-trace", gdb.COMMAND_USER, gdb.COMPLETE_FILENAME)
+
+    def invoke(self, argument, from_tty):
+        argument = gdb.string_to_argv(argument)
+
+        buffer, path = "TRACE_BUFFER", "trace.bin"
+        if argument[:1] == ["--pages"]:
+            argument = argument[1:]
+            buffer, path = "PAGE_TRACE_BUFFER", "pages.bin"
+
+        if len(argument) == 1:
+            path = argument[0]
+        elif len(argument) > 1:
+            raise RuntimeError("too many arguments")
+
+        gdb.execute(f"dump binary value {path} {buffer}")
+        print(f"trace dumped to {path}")
+        self.dont_repeat()
+
+
+DumpTraceCommand()
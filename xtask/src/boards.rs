@@ -0,0 +1,147 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{bail, eyre, Context};
+use color_eyre::Result;
+
+/// The directory containing the board profiles, each in a `<name>.board` file of its own.
+const BOARDS_DIR: &str = "kernel/boards";
+
+/// A board the kernel can be built for, and how to run it there.
+#[derive(Debug)]
+pub struct Board {
+    pub name: String,
+    pub description: String,
+    /// Target specification, relative to the repository root.
+    pub target: String,
+    /// Linker script, relative to the kernel package.
+    pub linker_script: String,
+    /// Kernel features the board needs.
+    pub features: Vec<String>,
+    /// QEMU arguments for the machine, if the board can run in QEMU.
+    pub qemu: Option<String>,
+    /// Files the board boots from, if it can't boot the kernel ELF itself.
+    pub boot_files: Option<BootFiles>,
+    /// What to do with the boot files once they're written.
+    pub flash: Option<String>,
+}
+
+/// Files written after the kernel is built, for a board to boot from.
+#[derive(Debug, PartialEq, Eq)]
+pub enum BootFiles {
+    /// kernel8.img and config.txt, for a Raspberry Pi's firmware (see rpi.rs).
+    Rpi,
+}
+
+impl Board {
+    /// Returns the directory Cargo builds into for the board's target, like
+    /// `target/aarch64-unknown-none`.
+    pub fn target_dir(&self) -> PathBuf {
+        let name = self.target.strip_suffix(".json").unwrap_or(&self.target);
+
+        Path::new("target").join(name)
+    }
+
+    /// Returns the directory the board's boot files are written to.
+    pub fn boot_files_dir(&self) -> PathBuf {
+        Path::new("target/boards").join(&self.name)
+    }
+}
+
+/// Returns the board profiles in kernel/boards, sorted by name.
+pub fn list() -> Result<Vec<Board>> {
+    let mut boards = vec![];
+
+    for entry in fs::read_dir(BOARDS_DIR).wrap_err("failed to list board profiles")? {
+        let path = entry?.path();
+        let Some(name) = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_suffix(".board"))
+        else {
+            continue;
+        };
+
+        let profile = fs::read_to_string(&path)
+            .wrap_err_with(|| format!("failed to read {}", path.display()))?;
+        let board =
+            parse(name, &profile).wrap_err_with(|| format!("bad profile {}", path.display()))?;
+        boards.push(board);
+    }
+    boards.sort_by(|p, q| p.name.cmp(&q.name));
+
+    Ok(boards)
+}
+
+/// Returns the board with the given name, failing if it doesn't exist.
+pub fn select(name: &str) -> Result<Board> {
+    list()?
+        .into_iter()
+        .find(|board| board.name == name)
+        .ok_or_else(|| eyre!("no such board: {name} (see `cargo xtask boards`)"))
+}
+
+/// Parses a board profile, which is `key = "value"` lines, and `#` comments.
+///
+/// Like the optional drivers' descriptions (see drivers.rs), this is a tiny subset of TOML, so we
+/// don't need a TOML parser for it.
+fn parse(name: &str, profile: &str) -> Result<Board> {
+    let mut board = Board {
+        name: name.to_owned(),
+        description: String::new(),
+        target: String::new(),
+        linker_script: String::new(),
+        features: vec![],
+        qemu: None,
+        boot_files: None,
+        flash: None,
+    };
+
+    for line in profile.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            bail!("expected `key = \"value\"`, not {line:?}");
+        };
+        let (key, value) = (key.trim(), value.trim());
+        let Some(value) = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) else {
+            bail!("value of {key} isn't a string");
+        };
+        let value = value.to_owned();
+
+        match key {
+            "description" => board.description = value,
+            "target" => board.target = value,
+            "linker-script" => board.linker_script = value,
+            "features" => {
+                board.features = value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|feature| !feature.is_empty())
+                    .map(str::to_owned)
+                    .collect()
+            }
+            "qemu" => board.qemu = Some(value),
+            "boot-files" => {
+                board.boot_files = match &*value {
+                    "rpi" => Some(BootFiles::Rpi),
+                    _ => bail!("unknown boot files {value:?}"),
+                }
+            }
+            "flash" => board.flash = Some(value),
+            _ => bail!("unknown key {key}"),
+        }
+    }
+
+    for (key, value) in [
+        ("target", &board.target),
+        ("linker-script", &board.linker_script),
+    ] {
+        if value.is_empty() {
+            bail!("no {key}");
+        }
+    }
+
+    Ok(board)
+}
@@ -0,0 +1,231 @@
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use color_eyre::eyre::Context;
+use color_eyre::Result;
+
+/// Lines that must appear in the output of a selftest kernel, in order (see the file itself).
+const EXPECTED: &str = "kernel/selftest.expected";
+
+/// Where `--selftest` writes the names of the selftests to run, for QEMU to pass to the kernel.
+pub const SELFTESTS: &str = "target/ci/selftests";
+/// Name of the fw_cfg file the kernel reads the selftests to run from (see kernel/src/selftest.rs).
+pub const SELFTESTS_FW_CFG: &str = "opt/micropuppy/selftests";
+
+/// The outcome of a test case in the report.
+pub enum Outcome {
+    Passed,
+    Failed(String),
+    /// The kernel never finished running the test.
+    Error(String),
+}
+
+pub struct TestCase {
+    pub name: String,
+    pub outcome: Outcome,
+}
+
+/// The result of booting a selftest kernel, as checked against its console output.
+pub struct Report {
+    /// Checks on the boot as a whole: exit status, panics, timeouts and expected output.
+    pub boot: Vec<TestCase>,
+    /// The kernel's selftests, from its selftest lines (see kernel/src/selftest.rs).
+    pub selftests: Vec<TestCase>,
+    pub duration: Duration,
+}
+
+/// How the kernel's QEMU run ended.
+pub enum Exit {
+    /// QEMU (or rather make, which runs it) exited with this status.
+    Status(Option<i32>),
+    TimedOut,
+}
+
+impl Report {
+    /// Checks the console output of a selftest kernel, given how QEMU exited.
+    pub fn new(output: &str, exit: Exit, duration: Duration) -> Result<Self> {
+        let lines = output.lines().map(strip_ansi).collect::<Vec<_>>();
+        let selftests = selftests(&lines);
+
+        let mut boot = vec![];
+        boot.push(TestCase {
+            name: "exit".to_owned(),
+            outcome: match exit {
+                Exit::Status(Some(0)) => Outcome::Passed,
+                Exit::Status(Some(status)) => {
+                    Outcome::Failed(format!("QEMU exited with status {status}"))
+                }
+                Exit::Status(None) => Outcome::Failed("QEMU was killed by a signal".to_owned()),
+                Exit::TimedOut => Outcome::Error("kernel timed out".to_owned()),
+            },
+        });
+        boot.push(TestCase {
+            name: "no_panic".to_owned(),
+            outcome: match lines.iter().position(|line| line.contains("panicked")) {
+                Some(index) => Outcome::Failed(lines[index..].join("\n")),
+                None => Outcome::Passed,
+            },
+        });
+
+        let expected = fs::read_to_string(EXPECTED)
+            .wrap_err_with(|| format!("failed to read expected output from {EXPECTED}"))?;
+        boot.push(TestCase {
+            name: "expected_output".to_owned(),
+            outcome: match missing_expected(&expected, &lines) {
+                Some(missing) => Outcome::Failed(format!("expected output not found: {missing}")),
+                None => Outcome::Passed,
+            },
+        });
+
+        Ok(Self {
+            boot,
+            selftests,
+            duration,
+        })
+    }
+
+    /// Returns the number of test cases that didn't pass.
+    pub fn failures(&self) -> usize {
+        self.boot
+            .iter()
+            .chain(&self.selftests)
+            .filter(|case| !matches!(case.outcome, Outcome::Passed))
+            .count()
+    }
+
+    /// Returns the report as JUnit XML, with a test suite for the boot and one for the selftests.
+    pub fn to_junit(&self) -> String {
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        let time = self.duration.as_secs_f64();
+        writeln!(xml, "<testsuites name=\"micropuppy\" time=\"{time:.3}\">").unwrap();
+        for (name, cases) in [("boot", &self.boot), ("selftest", &self.selftests)] {
+            let count = |f: fn(&Outcome) -> bool| cases.iter().filter(|c| f(&c.outcome)).count();
+            writeln!(
+                xml,
+                "  <testsuite name=\"{name}\" tests=\"{}\" failures=\"{}\" errors=\"{}\">",
+                cases.len(),
+                count(|outcome| matches!(outcome, Outcome::Failed(_))),
+                count(|outcome| matches!(outcome, Outcome::Error(_))),
+            )
+            .unwrap();
+            for case in cases.iter() {
+                let case_name = escape(&case.name);
+                write!(
+                    xml,
+                    "    <testcase classname=\"{name}\" name=\"{case_name}\""
+                )
+                .unwrap();
+                match &case.outcome {
+                    Outcome::Passed => xml.push_str("/>\n"),
+                    Outcome::Failed(message) => writeln!(
+                        xml,
+                        ">\n      <failure message=\"{}\"/>\n    </testcase>",
+                        escape(message)
+                    )
+                    .unwrap(),
+                    Outcome::Error(message) => writeln!(
+                        xml,
+                        ">\n      <error message=\"{}\"/>\n    </testcase>",
+                        escape(message)
+                    )
+                    .unwrap(),
+                }
+            }
+            xml.push_str("  </testsuite>\n");
+        }
+        xml.push_str("</testsuites>\n");
+
+        xml
+    }
+}
+
+/// Returns the selftests started by the kernel, and their outcomes.
+///
+/// Each selftest logs `selftest <name> ...` when it starts, then `selftest <name> ... ok` or
+/// `selftest <name> ... FAILED: <condition>` when it finishes, so a selftest that started but
+/// didn't finish must have panicked or hung.
+fn selftests(lines: &[String]) -> Vec<TestCase> {
+    let mut cases: Vec<TestCase> = vec![];
+
+    for line in lines {
+        let Some((_, rest)) = line.split_once("selftest ") else {
+            continue;
+        };
+        let Some((name, result)) = rest.split_once(" ...") else {
+            continue;
+        };
+        let outcome = match result.trim() {
+            "" => Outcome::Error("selftest didn't finish".to_owned()),
+            "ok" => Outcome::Passed,
+            result => Outcome::Failed(result.trim_start_matches("FAILED: ").to_owned()),
+        };
+
+        match cases.iter_mut().find(|case| case.name == name) {
+            Some(case) => case.outcome = outcome,
+            None => cases.push(TestCase {
+                name: name.to_owned(),
+                outcome,
+            }),
+        }
+    }
+
+    cases
+}
+
+/// Returns the first expected line that wasn't found in the output after the ones before it.
+fn missing_expected<'e>(expected: &'e str, lines: &[String]) -> Option<&'e str> {
+    let mut lines = lines.iter();
+
+    expected
+        .lines()
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .find(|expected| !lines.any(|line| line.contains(expected)))
+}
+
+/// Removes ANSI escape sequences (like the colours in log messages) from a line of output.
+pub fn strip_ansi(line: &str) -> String {
+    let mut result = String::with_capacity(line.len());
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // CSI sequences end with a byte in the range @ through ~
+            if chars.next() == Some('[') {
+                chars.find(|c| ('@'..='~').contains(c));
+            }
+        } else if c != '\r' {
+            result.push(c);
+        }
+    }
+
+    result
+}
+
+/// Escapes text for use in an XML attribute.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\n', "&#10;")
+}
+
+/// Writes the report to `path`, creating its directory if needed.
+pub fn write_junit(report: &Report, path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, report.to_junit()).wrap_err_with(|| format!("failed to write {path:?}"))
+}
+
+/// Writes the names of the selftests to run, one per line.
+pub fn write_selftests(names: &[String], path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut contents = names.join("\n");
+    contents.push('\n');
+    fs::write(path, contents).wrap_err_with(|| format!("failed to write {path:?}"))
+}
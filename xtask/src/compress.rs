@@ -0,0 +1,44 @@
+use std::fs;
+use std::path::Path;
+
+use color_eyre::eyre::Context;
+use color_eyre::Result;
+
+use crate::image;
+
+/// Magic number at the start of the payload header.
+///
+/// **The header MUST be kept in sync with `Header` in `kernel/stub/src/main.rs`.**
+const MAGIC: &[u8; 8] = b"MPUPLZ4\0";
+
+/// Sizes of the kernel image before and after compression.
+pub struct Sizes {
+    pub image: usize,
+    pub payload: usize,
+}
+
+/// Flattens the kernel ELF at `kernel` into an image (see [`image::flatten`]), then writes it to
+/// `payload`, compressed and prefixed with the header expected by the decompression stub.
+pub fn write_payload(kernel: &Path, payload: &Path) -> Result<Sizes> {
+    let image = image::flatten(kernel)?;
+
+    let compressed = lz4::compress(&image.bytes);
+    let mut output = Vec::with_capacity(40 + compressed.len());
+    output.extend_from_slice(MAGIC);
+    for field in [
+        image.load_address,
+        image.entry,
+        image.bytes.len() as u64,
+        image.memory_size,
+    ] {
+        output.extend_from_slice(&field.to_le_bytes());
+    }
+    output.extend_from_slice(&compressed);
+
+    fs::write(payload, &output).wrap_err_with(|| format!("failed to write {payload:?}"))?;
+
+    Ok(Sizes {
+        image: image.bytes.len(),
+        payload: output.len(),
+    })
+}
@@ -0,0 +1,71 @@
+use std::fs;
+use std::path::Path;
+
+use color_eyre::eyre::{bail, Context};
+use color_eyre::Result;
+
+/// The directory containing the optional drivers, each in a package of its own.
+const DRIVERS_DIR: &str = "kernel/drivers";
+
+/// An optional driver, which is built into the kernel if its `driver-<name>` feature is enabled.
+#[derive(Debug)]
+pub struct Driver {
+    pub name: String,
+    pub description: String,
+}
+
+impl Driver {
+    /// Returns the kernel feature that builds this driver into the kernel.
+    pub fn feature(&self) -> String {
+        format!("driver-{}", self.name)
+    }
+}
+
+/// Returns the optional drivers in kernel/drivers, sorted by name.
+pub fn list() -> Result<Vec<Driver>> {
+    let mut drivers = vec![];
+
+    for entry in fs::read_dir(DRIVERS_DIR).wrap_err("failed to list optional drivers")? {
+        let path = entry?.path();
+        let manifest = path.join("Cargo.toml");
+        if !manifest.exists() {
+            continue;
+        }
+
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        let description = description(&manifest)?.unwrap_or_default();
+        drivers.push(Driver { name, description });
+    }
+    drivers.sort_by(|p, q| p.name.cmp(&q.name));
+
+    Ok(drivers)
+}
+
+/// Returns the optional drivers with the given names, failing if any of them don't exist.
+pub fn select(names: &[String]) -> Result<Vec<Driver>> {
+    let mut drivers = list()?;
+
+    for name in names {
+        if !drivers.iter().any(|driver| &driver.name == name) {
+            bail!("no such optional driver: {name} (see `cargo xtask drivers`)");
+        }
+    }
+    drivers.retain(|driver| names.contains(&driver.name));
+
+    Ok(drivers)
+}
+
+/// Returns the package description in a Cargo.toml, if any.
+///
+/// This only understands `description = "..."` on a line of its own, which is how the drivers'
+/// manifests are written, so we don't need a TOML parser for it.
+fn description(manifest: &Path) -> Result<Option<String>> {
+    let manifest = fs::read_to_string(manifest)
+        .wrap_err_with(|| format!("failed to read {}", manifest.display()))?;
+
+    Ok(manifest.lines().find_map(|line| {
+        let value = line.strip_prefix("description")?.trim_start();
+        let value = value.strip_prefix('=')?.trim();
+        Some(value.strip_prefix('"')?.strip_suffix('"')?.to_owned())
+    }))
+}
@@ -0,0 +1,56 @@
+use std::fs;
+use std::path::Path;
+
+use color_eyre::eyre::{bail, Context};
+use color_eyre::Result;
+use gic::dump::{Dot, Line};
+
+/// Number of interrupts in a rendered table, and how many of them can't be signalled.
+pub struct Counts {
+    pub interrupts: usize,
+    pub unsignalled: usize,
+}
+
+/// Renders the table printed by `gic` in the kernel console (see kernel/src/console.rs), copied to
+/// `input`, as Graphviz dot at `output`.
+///
+/// Lines that aren't part of the table, like log messages and prompts, are skipped, so `input` can
+/// be a copy of everything the console printed. If the table was printed more than once, the last
+/// one is rendered.
+pub fn render(input: &Path, output: &Path) -> Result<Counts> {
+    let text = fs::read_to_string(input).wrap_err_with(|| format!("failed to read {input:?}"))?;
+
+    let mut interrupts = vec![];
+    let mut priority_mask = None;
+    for line in text.lines() {
+        match gic::dump::parse(line) {
+            // each table starts with the priority mask
+            Some(Line::PriorityMask(mask)) => {
+                priority_mask = Some(mask);
+                interrupts.clear();
+            }
+            Some(Line::Interrupt(interrupt)) => interrupts.push(interrupt),
+            None => {}
+        }
+    }
+    let Some(priority_mask) = priority_mask.filter(|_| !interrupts.is_empty()) else {
+        bail!("no interrupt table in {input:?} (copy it from “gic” in the console)");
+    };
+
+    let dot = Dot {
+        interrupts: &interrupts,
+        priority_mask,
+    };
+    fs::write(output, format!("{dot}\n"))
+        .wrap_err_with(|| format!("failed to write {output:?}"))?;
+
+    let unsignalled = interrupts
+        .iter()
+        .filter(|interrupt| interrupt.enabled && !interrupt.can_be_signalled(priority_mask))
+        .count();
+
+    Ok(Counts {
+        interrupts: interrupts.len(),
+        unsignalled,
+    })
+}
@@ -0,0 +1,66 @@
+use std::fs;
+use std::path::Path;
+
+use color_eyre::eyre::{bail, eyre, Context};
+use color_eyre::Result;
+use object::elf::PT_LOAD;
+use object::read::elf::{ElfFile64, FileHeader, ProgramHeader};
+use object::Endianness;
+
+/// The loadable segments of the kernel ELF, flattened into one image, as if loaded at their
+/// physical addresses.
+pub struct Image {
+    /// Physical address of the start of the image.
+    pub load_address: u64,
+    /// Physical address the kernel starts running at.
+    pub entry: u64,
+    /// The image, where gaps between segments, and any zero-initialised parts of segments within
+    /// it, are zero.
+    pub bytes: Vec<u8>,
+    /// Size of the memory the kernel uses from `load_address`, which is more than the image if it
+    /// ends with zero-initialised memory, like .bss.
+    pub memory_size: u64,
+}
+
+/// Flattens the loadable segments of the kernel ELF at `kernel` into an image.
+pub fn flatten(kernel: &Path) -> Result<Image> {
+    let data = fs::read(kernel).wrap_err_with(|| format!("failed to read {kernel:?}"))?;
+    let elf = ElfFile64::<Endianness>::parse(&*data).wrap_err("failed to parse kernel ELF")?;
+    let endian = elf.endian();
+    let header = elf.raw_header();
+
+    let segments = header
+        .program_headers(endian, &*data)?
+        .iter()
+        .filter(|segment| segment.p_type(endian) == PT_LOAD && segment.p_memsz(endian) > 0)
+        .collect::<Vec<_>>();
+    let Some(load_address) = segments.iter().map(|s| s.p_paddr(endian)).min() else {
+        bail!("kernel ELF has no loadable segments");
+    };
+    let image_end = segments
+        .iter()
+        .map(|s| s.p_paddr(endian) + s.p_filesz(endian))
+        .max()
+        .unwrap_or(load_address);
+    let memory_end = segments
+        .iter()
+        .map(|s| s.p_paddr(endian) + s.p_memsz(endian))
+        .max()
+        .unwrap_or(load_address);
+
+    let mut bytes = vec![0; usize::try_from(image_end - load_address)?];
+    for segment in &segments {
+        let start = usize::try_from(segment.p_paddr(endian) - load_address)?;
+        let data = segment
+            .data(endian, &*data)
+            .map_err(|()| eyre!("kernel ELF segment is out of bounds"))?;
+        bytes[start..][..data.len()].copy_from_slice(data);
+    }
+
+    Ok(Image {
+        load_address,
+        entry: header.e_entry(endian),
+        bytes,
+        memory_size: memory_end - load_address,
+    })
+}
@@ -1,15 +1,26 @@
 #![feature(exit_status_error)]
 
+mod boards;
+mod ci;
 mod command;
+mod compress;
+mod drivers;
+mod gic;
+mod image;
+mod profile;
+mod rpi;
 mod runner;
+mod trace;
 
 use std::env::{self, VarError};
 use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
 use clap::{Args, Parser, Subcommand};
 use color_eyre::eyre::{bail, Context};
 use color_eyre::Result;
 
+use crate::boards::BootFiles;
 use crate::runner::Runner;
 
 #[derive(Parser, Debug)]
@@ -24,10 +35,17 @@ struct RunnerArgs {
 
 #[derive(Subcommand, Debug)]
 enum RunnerCommand {
-    /// Build the kernel binary.
+    /// Build the kernel binary, and the tools that run on the host, at the same time.
+    ///
+    /// For a board that can't boot the kernel ELF itself, also write the files it boots from.
     Build,
     /// Run tests for platform-independent packages.
     Test,
+    /// Run tests for the allocator packages under Miri, to catch undefined behaviour in their
+    /// unsafe code before it corrupts a running kernel.
+    ///
+    /// Needs the miri component: “rustup component add miri”.
+    Miri,
     /// Remove build artifacts.
     Clean,
     /// Build the kernel binary, then run the kernel in QEMU.
@@ -41,6 +59,68 @@ enum RunnerCommand {
     },
     /// Run GDB, configured to attach to QEMU.
     Gdb,
+    /// Build a selftest kernel, then run it in QEMU headlessly and write a JUnit report.
+    ///
+    /// Fails if the kernel panics, times out, fails a selftest, or doesn't print the output
+    /// expected in kernel/selftest.expected.
+    Ci {
+        /// How long to let the kernel run before giving up, in seconds.
+        #[arg(long, default_value_t = 60)]
+        timeout: u64,
+        /// Where to write the JUnit XML report.
+        #[arg(long, default_value = "target/ci/junit.xml")]
+        junit: PathBuf,
+        /// Only run this selftest, rather than all of them. Can be given more than once.
+        #[arg(long = "selftest", value_name = "NAME")]
+        selftests: Vec<String>,
+    },
+    /// List the boards the kernel can be built for with --board.
+    Boards,
+    /// List the optional drivers that can be built into the kernel with --driver.
+    Drivers,
+    /// Render the table printed by “gic” in the kernel console as Graphviz dot.
+    ///
+    /// Each interrupt is a node, coloured by whether it's active, pending, enabled or disabled,
+    /// with an edge to each CPU it's forwarded to. Enabled interrupts that can't be signalled are
+    /// outlined in red.
+    Gic {
+        /// A copy of the console output, with the table in it.
+        input: PathBuf,
+        /// Where to write the graph. [default: the input, with a .dot extension]
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Symbolize a profile printed by “profile folded” in the kernel console.
+    ///
+    /// Writes folded stacks, for flamegraph tools like flamegraph.pl or inferno, or a flat profile
+    /// of the functions that were sampled most.
+    Profile {
+        /// A copy of the console output, with the folded stacks in it.
+        input: PathBuf,
+        /// Write a flat profile, rather than folded stacks.
+        #[arg(long)]
+        flat: bool,
+        /// Where to write the profile. [default: the input, with a .folded or .flat extension]
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Work with traces dumped from the kernel.
+    Trace {
+        #[command(subcommand)]
+        command: TraceCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum TraceCommand {
+    /// Convert a trace dumped with “dump-trace” in GDB to Chrome trace JSON, for Perfetto.
+    Convert {
+        /// The trace dump to convert.
+        input: PathBuf,
+        /// Where to write the JSON. [default: the input, with a .json extension]
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
 }
 
 #[derive(Debug)]
@@ -75,11 +155,20 @@ struct TargetArgs {
     /// Use a release build.
     #[arg(long, global = true)]
     release: bool,
+    /// Boot a compressed kernel, which is decompressed by a stub before it runs.
+    #[arg(long, global = true)]
+    compressed: bool,
+    /// Build an optional driver into the kernel (see `cargo xtask drivers`). Can be repeated.
+    #[arg(long = "driver", value_name = "NAME", global = true)]
+    drivers: Vec<String>,
+    /// Build for, and run on, this board (see `cargo xtask boards`).
+    #[arg(long, value_name = "NAME", default_value = "virt", global = true)]
+    board: String,
 }
 
 impl TargetArgs {
     fn as_target(&self) -> Result<Target> {
-        let Self { debug, release } = *self;
+        let Self { debug, release, .. } = *self;
         if debug && release {
             // TODO: encode this through clap
             bail!("can't specify both debug and release as target");
@@ -138,18 +227,163 @@ fn main() -> Result<()> {
         binaries,
     } = RunnerArgs::parse();
 
+    let compressed = target.compressed;
+    let drivers = drivers::select(&target.drivers)?;
+    let board = boards::select(&target.board)?;
     let target = target.as_target()?;
     let binaries = binaries.into_binaries()?;
-    let kernel = Path::new("target/aarch64-unknown-none")
-        .join(target.cargo_profile_dir())
-        .join("kernel");
+    let target_dir = board.target_dir().join(target.cargo_profile_dir());
+    let kernel = target_dir.join("kernel");
+    // The binary to boot, which is not the kernel itself if the kernel is compressed.
+    let image = if compressed {
+        target_dir.join("stub")
+    } else {
+        kernel.clone()
+    };
 
     let runner = Runner::new(binaries);
 
-    let build = || -> Result<()> {
-        runner.step("build");
+    let features = drivers
+        .iter()
+        .map(|driver| driver.feature())
+        .chain(board.features.iter().cloned())
+        .collect::<Vec<_>>();
+
+    let build = |features: &[String]| -> Result<()> {
+        let mut flags = vec![target.cargo_profile_flag().to_owned()];
+        if !features.is_empty() {
+            flags.push("--features".to_owned());
+            flags.push(features.join(","));
+        }
+
+        if compressed && board.boot_files.is_some() {
+            bail!("board {} can't boot a compressed kernel", board.name);
+        }
+
+        // The kernel and the host tools don't depend on each other, so build them at once.
+        let mut build_kernel = command::make("build");
+        build_kernel
+            .directory("kernel/")
+            .variable("CARGOFLAGS", flags.join(" "))
+            .variable(
+                "CARGOFLAGS_TARGET",
+                format!("-Zbuild-std --target ../{}", board.target),
+            )
+            .variable("LINKER_SCRIPT", &board.linker_script);
+        let mut build_host = command::make("build-host");
+        build_host
+            .directory("kernel/")
+            .variable("CARGOFLAGS", target.cargo_profile_flag());
+        let steps = vec![
+            ("build kernel", &mut build_kernel),
+            ("build host tools", &mut build_host),
+        ];
+        let names = steps.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+        let outputs = runner.run_parallel(steps)?;
+        for (name, output) in names.iter().zip(&outputs) {
+            // Cargo says it's compiling each package it rebuilds, maybe with colour codes in
+            // between the word and the package name.
+            let rebuilt = ci::strip_ansi(&output.stderr).contains("Compiling ");
+            let status = if rebuilt { "rebuilt" } else { "up to date" };
+            runner.note(&format!("{name}: {status}"));
+        }
+
+        if compressed {
+            // The stub's build script runs in its own package directory, so the payload path
+            // needs to be absolute.
+            let payload = env::current_dir()?.join(&target_dir).join("kernel.lz4");
+
+            runner.step("compress");
+            let sizes = compress::write_payload(&kernel, &payload)?;
+            runner.note(&format!(
+                "compressed {} byte kernel image to {} bytes",
+                sizes.image, sizes.payload
+            ));
+            runner.run(
+                command::make("build-stub")
+                    .directory("kernel/")
+                    .variable("CARGOFLAGS", target.cargo_profile_flag())
+                    .variable("KERNEL_PAYLOAD", payload.to_str().unwrap()),
+            )?;
+        }
+
+        if let Some(BootFiles::Rpi) = board.boot_files {
+            let output = board.boot_files_dir();
+
+            runner.step("boot files");
+            let sizes = rpi::write_boot_files(&kernel, &output)?;
+            runner.note(&format!(
+                "wrote {} byte kernel8.img and config.txt to {}",
+                sizes.image,
+                output.display()
+            ));
+            if let Some(flash) = &board.flash {
+                runner.note(&format!("now {flash}"));
+            }
+        }
+
+        Ok(())
+    };
+
+    let test = || -> Result<()> {
+        let mut flags = vec![target.cargo_profile_flag()];
+        for package in [
+            "abi",
+            "allocator",
+            "bootinfo",
+            "bounded",
+            "buddy-alloc",
+            "gic",
+            "hal",
+            "id-bitmap",
+            "lz4",
+            "memory-map",
+            "ninep",
+            "region-tree",
+            "trace-format",
+            "translation-tables",
+        ] {
+            flags.push("-p");
+            flags.push(package);
+        }
+
+        runner.step("test");
         runner.run(
-            command::make("build")
+            command::make("test")
+                .directory("kernel/")
+                .variable("CARGOFLAGS", flags.join(" ")),
+        )?;
+
+        // The translation granule is selected at build time, so test the other granules too.
+        for feature in ["granule-16k", "granule-64k"] {
+            let flags = [
+                target.cargo_profile_flag(),
+                "-p translation-tables --features",
+                feature,
+            ];
+
+            runner.step(&format!("test ({feature})"));
+            runner.run(
+                command::make("test")
+                    .directory("kernel/")
+                    .variable("CARGOFLAGS", flags.join(" ")),
+            )?;
+        }
+
+        // The mocks are built for hal's own tests anyway, but check that they build for other
+        // crates' tests too, which only get them with the feature.
+        let flags = [target.cargo_profile_flag(), "-p hal --features mock"];
+        runner.step("test (hal mock)");
+        runner.run(
+            command::make("test")
+                .directory("kernel/")
+                .variable("CARGOFLAGS", flags.join(" ")),
+        )?;
+
+        // nothing else builds the fuzz targets, so they'd otherwise rot as buddy-alloc changes
+        runner.step("check fuzz targets");
+        runner.run(
+            command::make("check-fuzz")
                 .directory("kernel/")
                 .variable("CARGOFLAGS", target.cargo_profile_flag()),
         )?;
@@ -157,16 +391,16 @@ fn main() -> Result<()> {
         Ok(())
     };
 
-    let test = || -> Result<()> {
+    let miri = || -> Result<()> {
         let mut flags = vec![target.cargo_profile_flag()];
         for package in ["allocator", "buddy-alloc"] {
             flags.push("-p");
             flags.push(package);
         }
 
-        runner.step("test");
+        runner.step("miri");
         runner.run(
-            command::make("test")
+            command::make("miri")
                 .directory("kernel/")
                 .variable("CARGOFLAGS", flags.join(" ")),
         )?;
@@ -181,21 +415,70 @@ fn main() -> Result<()> {
         Ok(())
     };
 
+    // QEMU's arguments for the board's machine, which only some boards have
+    let machine = || -> Result<&str> {
+        match &board.qemu {
+            Some(machine) => Ok(machine),
+            None => bail!("board {} can't run in QEMU", board.name),
+        }
+    };
+
     let qemu = |debugger| -> Result<()> {
         let qemuflags = if debugger { "-S -s" } else { "" };
-        let kernel = Path::new("..").join(&kernel);
+        let image = Path::new("..").join(&image);
 
         runner.step("qemu");
         runner.exec(
             command::make("run-kernel")
                 .directory("qemu/")
                 .variable("QEMUFLAGS", qemuflags)
-                .variable("KERNEL", kernel.to_str().unwrap()),
+                .variable("MACHINE", machine()?)
+                .variable("KERNEL", image.to_str().unwrap()),
         )?;
 
         Ok(())
     };
 
+    let ci = |timeout: u64, junit: &Path, selftests: &[String]| -> Result<()> {
+        let image = Path::new("..").join(&image);
+        let mut qemuflags = "-semihosting-config enable=on,target=native".to_owned();
+        if !selftests.is_empty() {
+            // the kernel reads the list with its fw_cfg driver (see kernel/src/selftest.rs)
+            let path = Path::new(ci::SELFTESTS);
+            ci::write_selftests(selftests, path)?;
+            let path = Path::new("..").join(path);
+            qemuflags += &format!(
+                " -fw_cfg name={},file={}",
+                ci::SELFTESTS_FW_CFG,
+                path.display()
+            );
+        }
+
+        runner.step("ci");
+        let start = Instant::now();
+        let (output, status) = runner.run_with_timeout(
+            command::make("run-kernel")
+                .directory("qemu/")
+                .variable("QEMUFLAGS", &qemuflags)
+                .variable("MACHINE", machine()?)
+                .variable("KERNEL", image.to_str().unwrap()),
+            Duration::from_secs(timeout),
+        )?;
+        let exit = match status {
+            Some(status) => ci::Exit::Status(status.code()),
+            None => ci::Exit::TimedOut,
+        };
+
+        let report = ci::Report::new(&output, exit, start.elapsed())?;
+        ci::write_junit(&report, junit)?;
+        runner.note(&format!("wrote JUnit report to {}", junit.display()));
+
+        match report.failures() {
+            0 => Ok(()),
+            failures => bail!("{failures} test cases failed (see {})", junit.display()),
+        }
+    };
+
     let gdb = || -> Result<()> {
         runner.step("gdb");
         runner.exec(
@@ -207,12 +490,118 @@ fn main() -> Result<()> {
         Ok(())
     };
 
+    let list_boards = || -> Result<()> {
+        for listed in boards::list()? {
+            let marker = if listed.name == board.name { "*" } else { " " };
+            println!("{marker} {:<16} {}", listed.name, listed.description);
+        }
+
+        Ok(())
+    };
+
+    let list_drivers = || -> Result<()> {
+        for driver in drivers::list()? {
+            let selected = drivers.iter().any(|selected| selected.name == driver.name);
+            let marker = if selected { "*" } else { " " };
+            println!("{marker} {:<16} {}", driver.name, driver.description);
+        }
+
+        Ok(())
+    };
+
+    let convert_trace = |input: &Path, output: Option<PathBuf>| -> Result<()> {
+        let output = output.unwrap_or_else(|| input.with_extension("json"));
+
+        runner.step("trace convert");
+        let counts = trace::convert(input, &output)?;
+        runner.note(&format!(
+            "wrote {} events to {}",
+            counts.events,
+            output.display()
+        ));
+        if counts.dropped > 0 {
+            runner.note(&format!(
+                "{} older events were overwritten before the trace was dumped",
+                counts.dropped
+            ));
+        }
+        for stats in counts.tasks {
+            runner.note(&format!(
+                "task {}: ran for {:.0} µs, waited {} times, p50 {:.0} µs, p99 {:.0} µs",
+                stats.task, stats.ran, stats.waits, stats.p50, stats.p99
+            ));
+        }
+
+        Ok(())
+    };
+
+    let render_gic = |input: &Path, output: Option<PathBuf>| -> Result<()> {
+        let output = output.unwrap_or_else(|| input.with_extension("dot"));
+
+        runner.step("gic");
+        let counts = gic::render(input, &output)?;
+        runner.note(&format!(
+            "wrote {} interrupts to {}",
+            counts.interrupts,
+            output.display()
+        ));
+        if counts.unsignalled > 0 {
+            runner.note(&format!(
+                "{} enabled interrupts can't be signalled, because they have no targets or are masked",
+                counts.unsignalled
+            ));
+        }
+
+        Ok(())
+    };
+
+    let symbolize_profile = |input: &Path, flat: bool, output: Option<PathBuf>| -> Result<()> {
+        let output =
+            output.unwrap_or_else(|| input.with_extension(if flat { "flat" } else { "folded" }));
+
+        runner.step("profile");
+        let counts = profile::symbolize(&kernel, input, &output, flat)?;
+        runner.note(&format!(
+            "wrote {} samples of {} stacks to {}",
+            counts.samples,
+            counts.stacks,
+            output.display()
+        ));
+
+        Ok(())
+    };
+
     match command {
-        RunnerCommand::Build => build(),
+        RunnerCommand::Build => build(&features),
         RunnerCommand::Test => test(),
+        RunnerCommand::Miri => miri(),
         RunnerCommand::Clean => clean(),
-        RunnerCommand::Qemu { debugger } => build().and_then(|_| qemu(debugger)),
+        // fail before building for a board that can't run in QEMU anyway
+        RunnerCommand::Qemu { debugger } => machine()
+            .and_then(|_| build(&features))
+            .and_then(|_| qemu(debugger)),
         RunnerCommand::Gdb => gdb(),
+        RunnerCommand::Ci {
+            timeout,
+            junit,
+            selftests,
+        } => {
+            let features = [&features[..], &["selftest".to_owned()]].concat();
+            machine()
+                .and_then(|_| build(&features))
+                .and_then(|_| ci(timeout, &junit, &selftests))
+        }
+        RunnerCommand::Boards => list_boards(),
+        RunnerCommand::Drivers => list_drivers(),
+        RunnerCommand::Gic { input, output } => render_gic(&input, output),
+        RunnerCommand::Profile {
+            input,
+            flat,
+            output,
+        } => symbolize_profile(&input, flat, output),
+        RunnerCommand::Trace {
+            command: TraceCommand::Convert { input, output },
+        } => convert_trace(&input, output),
     }?;
 
     runner.done();
@@ -0,0 +1,139 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+use color_eyre::eyre::{bail, Context};
+use color_eyre::Result;
+use object::read::elf::ElfFile64;
+use object::{Endianness, Object, ObjectSymbol, SymbolKind};
+
+/// Number of samples in a symbolized profile, and how many distinct stacks they had.
+pub struct Counts {
+    pub samples: usize,
+    pub stacks: usize,
+}
+
+/// The kernel's functions, sorted by address.
+struct Symbols(Vec<(u64, u64, String)>);
+
+impl Symbols {
+    fn read(kernel: &Path) -> Result<Self> {
+        let data = fs::read(kernel).wrap_err_with(|| format!("failed to read {kernel:?}"))?;
+        let elf = ElfFile64::<Endianness>::parse(&*data).wrap_err("failed to parse kernel ELF")?;
+
+        let mut symbols = elf
+            .symbols()
+            .filter(|symbol| symbol.kind() == SymbolKind::Text)
+            .filter_map(|symbol| {
+                let name = symbol.name().ok()?;
+                // {:#} leaves out the hash at the end of the mangled name, and semicolons separate
+                // frames in folded stacks, so they can't be in names, like in `[T; N]`
+                let name = format!("{:#}", rustc_demangle::demangle(name)).replace(';', ",");
+                Some((symbol.address(), symbol.size(), name))
+            })
+            .collect::<Vec<_>>();
+        symbols.sort();
+
+        Ok(Self(symbols))
+    }
+
+    /// Returns the name of the function containing `address`.
+    ///
+    /// Symbols defined in assembly have no size, so they're assumed to extend to the next symbol.
+    fn lookup(&self, address: u64) -> Option<&str> {
+        let index = self.0.partition_point(|&(start, _, _)| start <= address);
+        let (start, size, name) = self.0.get(index.checked_sub(1)?)?;
+
+        (*size == 0 || address < start + size).then_some(name)
+    }
+}
+
+/// Symbolizes the folded stacks printed by `profile folded` in the kernel console (see
+/// kernel/src/profile.rs), copied to `input`, with the symbols of the kernel ELF at `kernel`.
+///
+/// Lines that aren't folded stacks, like log messages and prompts, are skipped, so `input` can be
+/// a copy of everything the console printed. Stacks that only differ by where they were in each
+/// function are merged, then written to `output` as folded stacks, for flamegraph tools, or if
+/// `flat`, as a flat profile of the functions that were sampled most.
+pub fn symbolize(kernel: &Path, input: &Path, output: &Path, flat: bool) -> Result<Counts> {
+    let symbols = Symbols::read(kernel)?;
+    let text = fs::read_to_string(input).wrap_err_with(|| format!("failed to read {input:?}"))?;
+
+    let mut stacks = BTreeMap::<Vec<String>, usize>::new();
+    for line in text.lines() {
+        let Some((stack, count)) = line.trim().rsplit_once(' ') else {
+            continue;
+        };
+        let Ok(count) = count.parse::<usize>() else {
+            continue;
+        };
+        // the first frame is the task, followed by at least the interrupted pc
+        let mut frames = stack.split(';');
+        let Some(task) = frames.next() else {
+            continue;
+        };
+        let addresses = frames
+            .map(|frame| u64::from_str_radix(frame.strip_prefix("0x")?, 16).ok())
+            .collect::<Option<Vec<_>>>();
+        let Some(addresses) = addresses.filter(|addresses| !addresses.is_empty()) else {
+            continue;
+        };
+
+        let mut stack = vec![task.to_owned()];
+        for address in addresses {
+            match symbols.lookup(address) {
+                Some(name) => stack.push(name.to_owned()),
+                None => stack.push(format!("{address:#x}")),
+            }
+        }
+        *stacks.entry(stack).or_default() += count;
+    }
+    if stacks.is_empty() {
+        bail!("no folded stacks in {input:?} (copy them from “profile folded” in the console)");
+    }
+    let samples = stacks.values().sum::<usize>();
+
+    let mut result = String::new();
+    if flat {
+        // samples in each function itself, and in it or anything it called
+        let mut functions = BTreeMap::<&str, (usize, usize)>::new();
+        for (stack, &count) in &stacks {
+            let frames = &stack[1..];
+            for (i, frame) in frames.iter().enumerate() {
+                // count recursive functions once per sample
+                if frames[..i].contains(frame) {
+                    continue;
+                }
+                functions.entry(frame).or_default().1 += count;
+            }
+            // the innermost frame is the one that was running
+            if let Some(leaf) = frames.last() {
+                functions.entry(leaf).or_default().0 += count;
+            }
+        }
+        let mut functions = functions.into_iter().collect::<Vec<_>>();
+        functions.sort_by(|(_, a), (_, b)| b.cmp(a));
+
+        let percent = |count: usize| count as f64 * 100.0 / samples as f64;
+        writeln!(result, "  self            total")?;
+        for (name, (own, total)) in functions {
+            writeln!(
+                result,
+                "{own:>6} {:>5.1}%  {total:>6} {:>5.1}%  {name}",
+                percent(own),
+                percent(total)
+            )?;
+        }
+    } else {
+        for (stack, count) in &stacks {
+            writeln!(result, "{} {count}", stack.join(";"))?;
+        }
+    }
+    fs::write(output, result).wrap_err_with(|| format!("failed to write {output:?}"))?;
+
+    Ok(Counts {
+        samples,
+        stacks: stacks.len(),
+    })
+}
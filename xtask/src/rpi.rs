@@ -0,0 +1,62 @@
+use std::fs;
+use std::path::Path;
+
+use color_eyre::eyre::{bail, Context};
+use color_eyre::Result;
+
+use crate::image;
+
+/// Where the firmware is told to load the devicetree, which is where the kernel looks for it (see
+/// `kernel_main`), like on QEMU.
+const DEVICE_TREE_ADDRESS: u64 = 0x4000_0000;
+
+/// Sizes of what was written to the boot partition.
+pub struct Sizes {
+    pub image: usize,
+}
+
+/// Writes kernel8.img, the kernel flattened into an image (see [`image::flatten`]), and a
+/// config.txt that tells the Pi's firmware to load it where it was linked, into `output`.
+pub fn write_boot_files(kernel: &Path, output: &Path) -> Result<Sizes> {
+    let mut image = image::flatten(kernel)?;
+    // the firmware jumps to the start of the image, wherever the ELF says the entry point is
+    if image.entry != image.load_address {
+        bail!(
+            "kernel entry point {:#x} is not at the start of the image, {:#x}",
+            image.entry,
+            image.load_address
+        );
+    }
+    // and unlike an ELF loader, it doesn't zero .bss, so the image does
+    image.bytes.resize(usize::try_from(image.memory_size)?, 0);
+
+    fs::create_dir_all(output).wrap_err_with(|| format!("failed to create {output:?}"))?;
+    let path = output.join("kernel8.img");
+    fs::write(&path, &image.bytes).wrap_err_with(|| format!("failed to write {path:?}"))?;
+    let path = output.join("config.txt");
+    fs::write(&path, config(image.load_address))
+        .wrap_err_with(|| format!("failed to write {path:?}"))?;
+
+    Ok(Sizes {
+        image: image.bytes.len(),
+    })
+}
+
+/// Returns a config.txt for a kernel loaded at `kernel_address`.
+///
+/// <https://www.raspberrypi.com/documentation/computers/config_txt.html>
+fn config(kernel_address: u64) -> String {
+    format!(
+        "\
+# written by `cargo xtask build --board rpi4`, for a Raspberry Pi 4 with at least 2GiB of RAM
+arm_64bit=1
+kernel=kernel8.img
+kernel_address={kernel_address:#x}
+device_tree_address={DEVICE_TREE_ADDRESS:#x}
+enable_gic=1
+# the console is the PL011 (UART0) on GPIO 14 and 15, which would otherwise be given to Bluetooth
+enable_uart=1
+dtoverlay=disable-bt
+"
+    )
+}
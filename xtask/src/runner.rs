@@ -1,9 +1,13 @@
 use std::ffi::OsStr;
+use std::io::{self, BufRead, BufReader, IsTerminal, Write};
 use std::os::unix::process::CommandExt;
 use std::path::Path;
-use std::process::Command;
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
 
-use color_eyre::eyre::eyre;
+use color_eyre::eyre::{bail, eyre};
 use color_eyre::Result;
 use owo_colors::OwoColorize;
 
@@ -13,6 +17,12 @@ pub struct Runner {
     binaries: Binaries,
 }
 
+/// What a command run by [`Runner::run_parallel`] printed.
+pub struct Output {
+    pub stdout: String,
+    pub stderr: String,
+}
+
 impl Runner {
     pub fn new(binaries: Binaries) -> Self {
         Self { binaries }
@@ -22,6 +32,10 @@ impl Runner {
         eprintln!("{}", format!("🧾 running step `{name}`").bold());
     }
 
+    pub fn note(&self, message: &str) {
+        eprintln!("⭐ {message}");
+    }
+
     pub fn run(&self, command: impl IntoCommand) -> Result<()> {
         let mut command = command.into_command(&self.binaries)?;
 
@@ -31,6 +45,117 @@ impl Runner {
         Ok(())
     }
 
+    /// Runs independent commands at the same time, each as a step with the given name.
+    ///
+    /// Each command's output is captured, then printed in a section under its step once it
+    /// finishes, so the output of different commands never interleaves. Fails once every command
+    /// has finished, if any of them failed.
+    ///
+    /// Returns the output of each command, in the order given.
+    pub fn run_parallel<C: IntoCommand>(&self, steps: Vec<(&str, C)>) -> Result<Vec<Output>> {
+        let mut commands = vec![];
+        for (name, command) in steps {
+            let mut command = command.into_command(&self.binaries)?;
+            self.print_subprocess(&format!("starting step `{name}`:"), &command)?;
+            // Cargo only uses colour when writing to a terminal, but its output is relayed to one.
+            if io::stderr().is_terminal() {
+                command.env("CARGO_TERM_COLOR", "always");
+            }
+            commands.push((name, command));
+        }
+
+        let len = commands.len();
+        let (sender, receiver) = mpsc::channel();
+        thread::scope(|scope| {
+            for (index, (name, mut command)) in commands.into_iter().enumerate() {
+                let sender = sender.clone();
+                scope.spawn(move || {
+                    let output = command.stdin(Stdio::null()).output();
+                    // The receiver only goes away if relaying an earlier command's output failed.
+                    let _ = sender.send((index, name, output));
+                });
+            }
+            drop(sender);
+
+            let mut outputs = (0..len).map(|_| None).collect::<Vec<_>>();
+            let mut failed = vec![];
+            for (index, name, output) in receiver {
+                let output = output?;
+
+                self.step(name);
+                // Make echoes each command to stdout before running it, so print that first.
+                io::stdout().write_all(&output.stdout)?;
+                io::stderr().write_all(&output.stderr)?;
+                if !output.status.success() {
+                    failed.push(name);
+                    self.note(&format!("step `{name}` failed with {}", output.status));
+                }
+
+                outputs[index] = Some(Output {
+                    stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+                    stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+                });
+            }
+
+            if !failed.is_empty() {
+                bail!("steps failed: {}", failed.join(", "));
+            }
+
+            Ok(outputs.into_iter().flatten().collect())
+        })
+    }
+
+    /// Runs a command with a time limit, echoing its output while capturing it.
+    ///
+    /// Returns the output, and the exit status, or `None` if the command took too long and was
+    /// killed, along with anything it started (like QEMU, when run by make).
+    pub fn run_with_timeout(
+        &self,
+        command: impl IntoCommand,
+        timeout: Duration,
+    ) -> Result<(String, Option<ExitStatus>)> {
+        let mut command = command.into_command(&self.binaries)?;
+
+        self.print_subprocess("running", &command)?;
+        let mut child = command
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .process_group(0)
+            .spawn()?;
+
+        let stdout = child.stdout.take().unwrap();
+        let reader = thread::spawn(move || -> Result<String> {
+            let mut output = String::new();
+            for line in BufReader::new(stdout).split(b'\n') {
+                let line = String::from_utf8_lossy(&line?).into_owned();
+                println!("{line}");
+                output.push_str(&line);
+                output.push('\n');
+            }
+            Ok(output)
+        });
+
+        let deadline = Instant::now() + timeout;
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break Some(status);
+            }
+            if Instant::now() >= deadline {
+                // make doesn't pass signals on to its children, so kill the whole process group
+                Command::new("kill")
+                    .args(["-KILL", "--"])
+                    .arg(format!("-{}", child.id()))
+                    .status()?;
+                child.wait()?;
+                break None;
+            }
+            thread::sleep(Duration::from_millis(100));
+        };
+        let output = reader.join().expect("output reader not to panic")?;
+
+        Ok((output, status))
+    }
+
     pub fn exec(&self, command: impl IntoCommand) -> Result<()> {
         let mut command = command.into_command(&self.binaries)?;
 
@@ -0,0 +1,157 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+use color_eyre::eyre::{bail, eyre, Context};
+use color_eyre::Result;
+use trace_format::{Event, Reason};
+
+/// Number of events in a converted trace, and how many were lost before it was dumped.
+pub struct Counts {
+    pub events: usize,
+    pub dropped: u64,
+    /// Scheduling statistics for each task, if the kernel recorded them (see `schedstats` in
+    /// kernel/src/trace.rs).
+    pub tasks: Vec<TaskStats>,
+}
+
+/// How long a task ran for, and how long it waited to run, according to a trace.
+pub struct TaskStats {
+    pub task: u8,
+    /// Total time the task ran for, in microseconds.
+    pub ran: f64,
+    /// Number of times the task waited to run.
+    pub waits: usize,
+    /// Median time the task waited to run, in microseconds.
+    pub p50: f64,
+    /// 99th percentile of the time the task waited to run, in microseconds.
+    pub p99: f64,
+}
+
+/// Converts a trace buffer dumped from the kernel (see tools/gdb/dump_trace.py) at `input` to
+/// Chrome trace JSON at `output`, which can be opened in Perfetto or chrome://tracing.
+///
+/// Each task gets a track of its own, with a slice for each time it was picked by the scheduler,
+/// which lasts until the next task was picked. The ready queue depth is a counter track. If the
+/// trace has scheduling statistics, they're summarised for each task, and each time a task waited
+/// to run is an instant on its track.
+pub fn convert(input: &Path, output: &Path) -> Result<Counts> {
+    let data = fs::read(input).wrap_err_with(|| format!("failed to read {input:?}"))?;
+    let trace = trace_format::decode(&data).map_err(|error| eyre!("bad trace dump: {error:?}"))?;
+    if trace.frequency == 0 {
+        bail!("trace dump has no counter frequency (was it dumped before the kernel set it?)");
+    }
+    let events = trace
+        .events()
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|error| eyre!("bad trace record: {error:?}"))?;
+
+    // timestamps in Chrome trace JSON are in microseconds, and we start the trace at zero
+    let start = events.first().map_or(0, |&(timestamp, _)| timestamp);
+    let micros = |timestamp: u64| (timestamp - start) as f64 * 1e6 / trace.frequency as f64;
+    let duration = |ticks: u32| ticks as f64 * 1e6 / trace.frequency as f64;
+
+    let mut json =
+        vec![r#"{"name":"process_name","ph":"M","pid":0,"args":{"name":"micropuppy"}}"#.to_owned()];
+    let mut tasks = BTreeSet::new();
+    let mut ran = BTreeMap::<u8, f64>::new();
+    let mut waits = BTreeMap::<u8, Vec<f64>>::new();
+
+    for (i, &(timestamp, event)) in events.iter().enumerate() {
+        let ts = micros(timestamp);
+        match event {
+            Event::TaskPicked {
+                task,
+                reason,
+                ready,
+            } => {
+                tasks.insert(task);
+                let reason = match reason {
+                    Reason::Start => "start",
+                    Reason::Preempted => "preempted",
+                    Reason::Yielded => "yielded",
+                    Reason::Exited => "exited",
+                };
+
+                // the task runs until the next one is picked, which we only know if it's traced
+                let next = events[i + 1..]
+                    .iter()
+                    .find(|(_, event)| matches!(event, Event::TaskPicked { .. }));
+                if let Some(&(next, _)) = next {
+                    let dur = micros(next) - ts;
+                    json.push(format!(
+                        r#"{{"name":"task {task}","cat":"sched","ph":"X","pid":0,"tid":{task},"ts":{ts},"dur":{dur},"args":{{"reason":"{reason}"}}}}"#
+                    ));
+                }
+                json.push(format!(
+                    r#"{{"name":"picked ({reason})","cat":"sched","ph":"i","s":"t","pid":0,"tid":{task},"ts":{ts},"args":{{"ready":{ready}}}}}"#
+                ));
+                json.push(format!(
+                    r#"{{"name":"ready queue","cat":"sched","ph":"C","pid":0,"ts":{ts},"args":{{"ready":{ready}}}}}"#
+                ));
+            }
+            Event::TaskRan { task, ran: ticks } => {
+                *ran.entry(task).or_default() += duration(ticks);
+            }
+            Event::TaskWaited { task, waited } => {
+                let waited = duration(waited);
+                waits.entry(task).or_default().push(waited);
+                json.push(format!(
+                    r#"{{"name":"waited","cat":"sched","ph":"i","s":"t","pid":0,"tid":{task},"ts":{ts},"args":{{"us":{waited}}}}}"#
+                ));
+            }
+            // page allocator events are for replaying with the buddy-alloc CLI
+            Event::PageAllocator { .. }
+            | Event::PagesAllocated { .. }
+            | Event::PagesFreed { .. }
+            | Event::OutOfPages { .. } => {}
+        }
+    }
+    for task in tasks {
+        json.push(format!(
+            r#"{{"name":"thread_name","ph":"M","pid":0,"tid":{task},"args":{{"name":"task {task}"}}}}"#
+        ));
+    }
+
+    let mut result = String::from("{\"traceEvents\":[\n");
+    for (i, event) in json.iter().enumerate() {
+        let separator = if i + 1 < json.len() { "," } else { "" };
+        writeln!(result, "{event}{separator}")?;
+    }
+    result.push_str("]}\n");
+    fs::write(output, result).wrap_err_with(|| format!("failed to write {output:?}"))?;
+
+    let tasks = ran
+        .keys()
+        .chain(waits.keys())
+        .copied()
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .map(|task| {
+            let mut waits = waits.remove(&task).unwrap_or_default();
+            waits.sort_by(f64::total_cmp);
+            TaskStats {
+                task,
+                ran: ran.get(&task).copied().unwrap_or(0.0),
+                waits: waits.len(),
+                p50: percentile(&waits, 50),
+                p99: percentile(&waits, 99),
+            }
+        })
+        .collect();
+
+    Ok(Counts {
+        events: events.len(),
+        dropped: trace.dropped,
+        tasks,
+    })
+}
+
+/// Returns the `p`th percentile of `sorted`, by the nearest-rank method, or zero if it's empty.
+fn percentile(sorted: &[f64], p: usize) -> f64 {
+    // the smallest value that's at least p% of the values
+    let rank = (sorted.len() * p).div_ceil(100);
+
+    sorted.get(rank.saturating_sub(1)).copied().unwrap_or(0.0)
+}